@@ -0,0 +1,244 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Allocation statistics and leak reporting
+//!
+//! The crate doesn't provide a global allocator itself; wrap the application's in [`Tracked`]
+//! to count live and peak usage:
+//!
+//! ```ignore
+//! #[global_allocator]
+//! static ALLOCATOR: uefi::alloc_stats::Tracked<PoolAllocator> = Tracked::new(PoolAllocator);
+//! ```
+//!
+//! Memory the loader leaks stays allocated as loader data after `ExitBootServices()`, which
+//! the kernel may not reclaim until much later. Calling [`report_leaks()`] just before the
+//! final memory map is fetched lists whatever is still live, grouped by size class.
+
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    fmt::{self, Write},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::stdio::try_stderr;
+
+/// Number of size classes; class `n` holds blocks of up to `16 << n` bytes, and the last
+/// class everything larger
+const SIZE_CLASSES: usize = 14;
+
+const SMALLEST_CLASS: usize = 16;
+
+static LIVE_BYTES: AtomicUsize = AtomicUsize::new(0);
+static LIVE_BLOCKS: AtomicUsize = AtomicUsize::new(0);
+static PEAK_BYTES: AtomicUsize = AtomicUsize::new(0);
+static PEAK_BLOCKS: AtomicUsize = AtomicUsize::new(0);
+static TOTAL_ALLOCS: AtomicUsize = AtomicUsize::new(0);
+
+static CLASS_BLOCKS: [AtomicUsize; SIZE_CLASSES] = [const { AtomicUsize::new(0) }; SIZE_CLASSES];
+static CLASS_BYTES: [AtomicUsize; SIZE_CLASSES] = [const { AtomicUsize::new(0) }; SIZE_CLASSES];
+
+/// Global allocator wrapper which keeps [`Stats`]
+///
+/// Counters are global, since there is only ever one global allocator.
+pub struct Tracked<A> {
+    inner: A,
+}
+
+impl<A> Tracked<A> {
+    pub const fn new(inner: A) -> Self {
+        Self { inner }
+    }
+
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for Tracked<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc(layout);
+        if !ptr.is_null() {
+            record_alloc(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            record_alloc(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.inner.dealloc(ptr, layout);
+        record_dealloc(layout.size());
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = self.inner.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            record_realloc(layout.size(), new_size);
+        }
+        new_ptr
+    }
+}
+
+fn size_class(size: usize) -> usize {
+    let class = size
+        .max(1)
+        .div_ceil(SMALLEST_CLASS)
+        .next_power_of_two()
+        .trailing_zeros();
+    (class as usize).min(SIZE_CLASSES - 1)
+}
+
+fn record_alloc(size: usize) {
+    let bytes = LIVE_BYTES.fetch_add(size, Ordering::Relaxed) + size;
+    let blocks = LIVE_BLOCKS.fetch_add(1, Ordering::Relaxed) + 1;
+    PEAK_BYTES.fetch_max(bytes, Ordering::Relaxed);
+    PEAK_BLOCKS.fetch_max(blocks, Ordering::Relaxed);
+    TOTAL_ALLOCS.fetch_add(1, Ordering::Relaxed);
+
+    let class = size_class(size);
+    CLASS_BLOCKS[class].fetch_add(1, Ordering::Relaxed);
+    CLASS_BYTES[class].fetch_add(size, Ordering::Relaxed);
+}
+
+/// Moves a live block to its new size, without counting it as a new allocation
+fn record_realloc(old_size: usize, new_size: usize) {
+    let bytes = if new_size >= old_size {
+        LIVE_BYTES.fetch_add(new_size - old_size, Ordering::Relaxed) + (new_size - old_size)
+    } else {
+        LIVE_BYTES.fetch_sub(old_size - new_size, Ordering::Relaxed) - (old_size - new_size)
+    };
+    PEAK_BYTES.fetch_max(bytes, Ordering::Relaxed);
+
+    let (old_class, new_class) = (size_class(old_size), size_class(new_size));
+    CLASS_BLOCKS[old_class].fetch_sub(1, Ordering::Relaxed);
+    CLASS_BYTES[old_class].fetch_sub(old_size, Ordering::Relaxed);
+    CLASS_BLOCKS[new_class].fetch_add(1, Ordering::Relaxed);
+    CLASS_BYTES[new_class].fetch_add(new_size, Ordering::Relaxed);
+}
+
+fn record_dealloc(size: usize) {
+    LIVE_BYTES.fetch_sub(size, Ordering::Relaxed);
+    LIVE_BLOCKS.fetch_sub(1, Ordering::Relaxed);
+
+    let class = size_class(size);
+    CLASS_BLOCKS[class].fetch_sub(1, Ordering::Relaxed);
+    CLASS_BYTES[class].fetch_sub(size, Ordering::Relaxed);
+}
+
+/// Snapshot of the allocation counters
+///
+/// Sizes are those requested, not counting allocator overhead.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Stats {
+    pub live_bytes:   usize,
+    pub live_blocks:  usize,
+    pub peak_bytes:   usize,
+    pub peak_blocks:  usize,
+    /// Allocations made since startup, including ones since freed
+    ///
+    /// A reallocation resizes a live block and isn't counted.
+    pub total_allocs: usize,
+}
+
+/// Returns the current counters
+///
+/// These are all zero unless the global allocator is wrapped in [`Tracked`].
+pub fn stats() -> Stats {
+    Stats {
+        live_bytes:   LIVE_BYTES.load(Ordering::Relaxed),
+        live_blocks:  LIVE_BLOCKS.load(Ordering::Relaxed),
+        peak_bytes:   PEAK_BYTES.load(Ordering::Relaxed),
+        peak_blocks:  PEAK_BLOCKS.load(Ordering::Relaxed),
+        total_allocs: TOTAL_ALLOCS.load(Ordering::Relaxed),
+    }
+}
+
+/// Live allocations of one size class
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SizeClass {
+    /// Largest block size in the class, or `None` for the last, unbounded class
+    pub max_size: Option<usize>,
+    pub blocks:   usize,
+    pub bytes:    usize,
+}
+
+/// Returns an iterator over the live allocations in each size class, smallest first
+pub fn size_classes() -> impl Iterator<Item = SizeClass> {
+    (0..SIZE_CLASSES).map(|class| SizeClass {
+        max_size: (class < SIZE_CLASSES - 1).then(|| SMALLEST_CLASS << class),
+        blocks:   CLASS_BLOCKS[class].load(Ordering::Relaxed),
+        bytes:    CLASS_BYTES[class].load(Ordering::Relaxed),
+    })
+}
+
+/// Writes the counters and the non-empty size classes to `w`
+pub fn write_report(w: &mut impl Write) -> fmt::Result {
+    let stats = stats();
+    writeln!(
+        w,
+        "{} bytes in {} blocks live (peak {} bytes in {} blocks, {} allocations total)",
+        stats.live_bytes,
+        stats.live_blocks,
+        stats.peak_bytes,
+        stats.peak_blocks,
+        stats.total_allocs,
+    )?;
+    for class in size_classes().filter(|class| class.blocks != 0) {
+        match class.max_size {
+            Some(max_size) => write!(w, "  <= {max_size:>6}")?,
+            None => write!(w, "  >  {:>6}", SMALLEST_CLASS << (SIZE_CLASSES - 2))?,
+        }
+        writeln!(w, ": {} blocks, {} bytes", class.blocks, class.bytes)?;
+    }
+    Ok(())
+}
+
+/// Reports live allocations on stderr, if there are any
+///
+/// Call this just before fetching the memory map for `ExitBootServices()`: printing may
+/// allocate in firmware and invalidate a map key obtained earlier. Returns `true` if anything
+/// was still allocated.
+pub fn report_leaks() -> bool {
+    if LIVE_BLOCKS.load(Ordering::Relaxed) == 0 {
+        return false;
+    }
+    if let Some(mut stderr) = try_stderr() {
+        let _ = writeln!(stderr, "leaked allocations:");
+        let _ = write_report(&mut stderr);
+    }
+    true
+}
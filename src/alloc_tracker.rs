@@ -0,0 +1,363 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Tracking of firmware allocations by memory type
+//!
+//! Memory allocated through [`BootServices::allocate_pages()`] and
+//! [`BootServices::allocate_pool()`] and never freed stays reserved after `ExitBootServices()`
+//! if it has a runtime or OS memory type, and is easily mistaken for firmware bloat. Once
+//! [enabled](enable), every such call is recorded with its memory type, size and a caller
+//! tag, and [`report()`] summarizes them per memory type:
+//!
+//! ```ignore
+//! alloc_tracker::enable();
+//! let _tag = alloc_tracker::tag("kernel");
+//! let kernel = bs.allocate_pages(AllocPagesType::Any, KERNEL_TYPE, pages)?;
+//! drop(_tag);
+//! // ...
+//! alloc_tracker::report(&mut ConsoleWriter::new(stdout))?;
+//! ```
+//!
+//! Allocations made without a [`tag()`] in effect are tagged with the source location of the
+//! call. Only calls made through this crate are seen; firmware's own allocations show up in
+//! the memory map instead, see [`MemoryMap::diff()`](crate::table::MemoryMap::diff).
+//!
+//! The tracker is a debugging aid and is only built with the `alloc_tracker` feature, as its
+//! table takes up several kilobytes. Freeing part of a page allocation trims its record.
+//!
+//! [`BootServices::allocate_pages()`]: crate::table::BootServices::allocate_pages
+//! [`BootServices::allocate_pool()`]: crate::table::BootServices::allocate_pool
+
+use core::{
+    fmt,
+    panic::Location,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use crate::{sync::TryLock, table::MemoryType, PhysicalAddr};
+
+/// Number of live allocations which can be tracked at once
+pub const CAPACITY: usize = 256;
+
+/// Number of distinct memory types with totals kept
+pub const MAX_TYPES: usize = 16;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+/// Allocations which could not be tracked, because the table was full or in use
+static UNTRACKED: AtomicUsize = AtomicUsize::new(0);
+static TAG: TryLock<Option<&'static str>> = TryLock::new(None);
+
+/// Who made an allocation
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Tag {
+    /// The innermost [`tag()`] in effect
+    Name(&'static str),
+    /// The call site, if no tag was in effect
+    Caller(&'static Location<'static>),
+}
+
+impl fmt::Display for Tag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Name(name) => f.write_str(name),
+            Self::Caller(location) => write!(f, "{}:{}", location.file(), location.line()),
+        }
+    }
+}
+
+/// A live allocation
+#[derive(Clone, Copy, Debug)]
+pub struct Allocation {
+    pub memory_type: MemoryType,
+    pub addr:        PhysicalAddr,
+    /// Size in bytes, rounded up to whole pages for page allocations
+    pub size:        usize,
+    pub pages:       bool,
+    pub tag:         Tag,
+}
+
+/// Allocations of one memory type
+#[derive(Clone, Copy, Debug)]
+pub struct TypeSummary {
+    pub memory_type:     MemoryType,
+    /// Allocations made since tracking was enabled, including ones since freed
+    pub calls:           usize,
+    pub allocated_bytes: usize,
+    pub live:            usize,
+    pub live_bytes:      usize,
+}
+
+impl TypeSummary {
+    const fn new(memory_type: MemoryType) -> Self {
+        Self {
+            memory_type,
+            calls: 0,
+            allocated_bytes: 0,
+            live: 0,
+            live_bytes: 0,
+        }
+    }
+}
+
+struct Tracker {
+    live:  [Option<Allocation>; CAPACITY],
+    types: [Option<TypeSummary>; MAX_TYPES],
+}
+
+impl Tracker {
+    fn summary(&mut self, memory_type: MemoryType) -> Option<&mut TypeSummary> {
+        let index = self.types.iter().position(|slot| match slot {
+            Some(summary) => summary.memory_type == memory_type,
+            None => true,
+        })?;
+        Some(self.types[index].get_or_insert(TypeSummary::new(memory_type)))
+    }
+}
+
+static TRACKER: TryLock<Tracker> = TryLock::new(Tracker {
+    live:  [None; CAPACITY],
+    types: [None; MAX_TYPES],
+});
+
+/// Starts recording allocations
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Stops recording allocations; what was recorded so far is kept
+///
+/// Recorded allocations freed afterwards are still forgotten, so they aren't reported as live.
+pub fn disable() {
+    ENABLED.store(false, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Tags allocations with `name` until the returned guard is dropped
+///
+/// Guards nest; dropping one restores the tag in effect when it was created.
+pub fn tag(name: &'static str) -> TagGuard {
+    let previous = TAG.try_with(|tag| tag.replace(name)).flatten();
+    TagGuard { previous }
+}
+
+pub struct TagGuard {
+    previous: Option<&'static str>,
+}
+
+impl Drop for TagGuard {
+    fn drop(&mut self) {
+        TAG.try_with(|tag| *tag = self.previous);
+    }
+}
+
+/// Records a successful allocation
+#[track_caller]
+pub(crate) fn record(memory_type: MemoryType, addr: PhysicalAddr, size: usize, pages: bool) {
+    if !is_enabled() {
+        return;
+    }
+    let tag = match TAG.try_with(|tag| *tag).flatten() {
+        Some(name) => Tag::Name(name),
+        None => Tag::Caller(Location::caller()),
+    };
+    let recorded = TRACKER.try_with(|tracker| {
+        if let Some(summary) = tracker.summary(memory_type) {
+            summary.calls += 1;
+            summary.allocated_bytes += size;
+        }
+        let slot = tracker.live.iter_mut().find(|slot| slot.is_none())?;
+        *slot = Some(Allocation {
+            memory_type,
+            addr,
+            size,
+            pages,
+            tag,
+        });
+        Some(())
+    });
+    if recorded.flatten().is_none() {
+        UNTRACKED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Forgets a freed pool allocation
+pub(crate) fn release_pool(addr: PhysicalAddr) {
+    TRACKER.try_with(|tracker| {
+        let slot = tracker.live.iter_mut().find(|slot| {
+            slot.is_some_and(|allocation| !allocation.pages && allocation.addr == addr)
+        });
+        if let Some(slot) = slot {
+            *slot = None;
+        }
+    });
+}
+
+/// Forgets freed pages, trimming page allocations of which only a part was freed
+pub(crate) fn release_pages(addr: PhysicalAddr, size: usize) {
+    let end = addr.saturating_add(size as u64);
+    TRACKER.try_with(|tracker| {
+        for index in 0..CAPACITY {
+            let Some(allocation) = tracker.live[index] else {
+                continue;
+            };
+            let allocation_end = allocation.addr + allocation.size as u64;
+            if !allocation.pages || allocation.addr >= end || allocation_end <= addr {
+                continue;
+            }
+            let below = (allocation.addr < addr).then(|| Allocation {
+                size: (addr - allocation.addr) as usize,
+                ..allocation
+            });
+            let above = (allocation_end > end).then(|| Allocation {
+                addr: end,
+                size: (allocation_end - end) as usize,
+                ..allocation
+            });
+            tracker.live[index] = below.or(above);
+            if let (Some(_), Some(above)) = (below, above) {
+                // Freeing from the middle splits the allocation in two.
+                match tracker.live.iter_mut().find(|slot| slot.is_none()) {
+                    Some(slot) => *slot = Some(above),
+                    None => {
+                        UNTRACKED.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            }
+        }
+    });
+}
+
+/// Copies the live allocations into `buf` and returns how many were copied
+pub fn snapshot(buf: &mut [Allocation]) -> usize {
+    TRACKER
+        .try_with(|tracker| {
+            buf.iter_mut()
+                .zip(tracker.live.iter().flatten())
+                .map(|(slot, allocation)| *slot = *allocation)
+                .count()
+        })
+        .unwrap_or(0)
+}
+
+/// Copies the totals for each memory type seen into `buf` and returns how many were copied
+pub fn summary(buf: &mut [TypeSummary]) -> usize {
+    TRACKER
+        .try_with(|tracker| {
+            let mut types = tracker.types;
+            for allocation in tracker.live.iter().flatten() {
+                let summary = types
+                    .iter_mut()
+                    .flatten()
+                    .find(|summary| summary.memory_type == allocation.memory_type);
+                if let Some(summary) = summary {
+                    summary.live += 1;
+                    summary.live_bytes += allocation.size;
+                }
+            }
+            buf.iter_mut()
+                .zip(types.iter().flatten())
+                .map(|(slot, summary)| *slot = *summary)
+                .count()
+        })
+        .unwrap_or(0)
+}
+
+/// Returns the number of allocations which were not tracked
+///
+/// If this isn't zero, the live counts in [`summary()`] may be too low.
+pub fn untracked() -> usize {
+    UNTRACKED.load(Ordering::Relaxed)
+}
+
+/// Discards everything recorded
+pub fn clear() {
+    TRACKER.try_with(|tracker| {
+        tracker.live = [None; CAPACITY];
+        tracker.types = [None; MAX_TYPES];
+    });
+    UNTRACKED.store(0, Ordering::Relaxed);
+}
+
+/// Writes the totals for each memory type, followed by the live allocations of that type
+/// grouped by tag
+///
+/// Call this just before fetching the final memory map; printing may allocate in firmware.
+pub fn report(out: &mut impl fmt::Write) -> fmt::Result {
+    let mut types = [TypeSummary::new(MemoryType(0)); MAX_TYPES];
+    let count = summary(&mut types);
+    if count == 0 {
+        return writeln!(out, "alloc_tracker: no allocations recorded");
+    }
+    let mut live: [Option<Allocation>; CAPACITY] = [None; CAPACITY];
+    TRACKER.try_with(|tracker| live = tracker.live);
+
+    writeln!(
+        out,
+        "{:<24} {:>6} {:>12} {:>6} {:>12}",
+        "memory type", "calls", "allocated", "live", "live bytes"
+    )?;
+    for summary in &types[..count] {
+        match summary.memory_type.name() {
+            Some(name) => write!(out, "{name:<24}")?,
+            None => write!(out, "{:<#24x}", summary.memory_type.0)?,
+        }
+        writeln!(
+            out,
+            " {:>6} {:>12} {:>6} {:>12}",
+            summary.calls, summary.allocated_bytes, summary.live, summary.live_bytes
+        )?;
+
+        // Group by tag, listing each at its first allocation.
+        let of_type = live
+            .iter()
+            .flatten()
+            .filter(|allocation| allocation.memory_type == summary.memory_type);
+        for (i, first) in of_type.clone().enumerate() {
+            if of_type.clone().take(i).any(|other| other.tag == first.tag) {
+                continue;
+            }
+            let (blocks, bytes) = of_type
+                .clone()
+                .filter(|other| other.tag == first.tag)
+                .fold((0, 0), |(blocks, bytes), other| {
+                    (blocks + 1, bytes + other.size)
+                });
+            writeln!(out, "{:44} {blocks:>6} {bytes:>12}  {}", "", first.tag)?;
+        }
+    }
+    let untracked = untracked();
+    if untracked != 0 {
+        writeln!(out, "{untracked} allocations were not tracked")?;
+    }
+    Ok(())
+}
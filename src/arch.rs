@@ -0,0 +1,345 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Architecture-specific register access and stack walking
+//!
+//! This is mostly intended for diagnostics, e.g. the [panic screen](crate::graphics::panic).
+
+use core::{arch::asm, fmt, mem::align_of};
+
+use crate::{boot_services_active, proto::loaded_image::loaded_image};
+
+/// Snapshot of the registers most useful for post-mortem debugging
+///
+/// Which registers are captured depends on the architecture; see [`Registers::NAMES`].
+#[derive(Clone, Copy, Debug)]
+pub struct Registers {
+    values: [usize; COUNT],
+}
+
+impl Registers {
+    pub const NAMES: [&'static str; COUNT] = NAMES;
+
+    /// Captures the registers at the call site
+    ///
+    /// The program counter, stack pointer and frame pointer are those of the caller, since this
+    /// is always inlined.
+    #[inline(always)]
+    pub fn capture() -> Self {
+        Self {
+            values: unsafe { capture() },
+        }
+    }
+
+    pub fn stack_pointer(&self) -> usize {
+        self.values[SP]
+    }
+
+    pub fn frame_pointer(&self) -> usize {
+        self.values[FP]
+    }
+
+    pub fn program_counter(&self) -> usize {
+        self.values[PC]
+    }
+
+    /// Returns an iterator over the registers' names and values
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, usize)> + '_ {
+        NAMES.iter().copied().zip(self.values.iter().copied())
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+const COUNT: usize = 8;
+#[cfg(target_arch = "x86_64")]
+const NAMES: [&str; COUNT] = ["rip", "rsp", "rbp", "rflags", "cr0", "cr2", "cr3", "cr4"];
+
+#[cfg(target_arch = "x86")]
+const COUNT: usize = 8;
+#[cfg(target_arch = "x86")]
+const NAMES: [&str; COUNT] = ["eip", "esp", "ebp", "eflags", "cr0", "cr2", "cr3", "cr4"];
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[inline(always)]
+unsafe fn capture() -> [usize; COUNT] {
+    let (pc, sp, fp, flags, cr0, cr2, cr3, cr4): (
+        usize,
+        usize,
+        usize,
+        usize,
+        usize,
+        usize,
+        usize,
+        usize,
+    );
+
+    #[cfg(target_arch = "x86_64")]
+    asm!(
+        "lea {pc}, [rip]",
+        "mov {sp}, rsp",
+        "mov {fp}, rbp",
+        pc = out(reg) pc,
+        sp = out(reg) sp,
+        fp = out(reg) fp,
+        options(nomem, nostack, preserves_flags),
+    );
+    #[cfg(target_arch = "x86")]
+    asm!(
+        "call 2f",
+        "2: pop {pc}",
+        "mov {sp}, esp",
+        "mov {fp}, ebp",
+        pc = out(reg) pc,
+        sp = out(reg) sp,
+        fp = out(reg) fp,
+        options(preserves_flags),
+    );
+    #[cfg(target_arch = "x86_64")]
+    asm!("pushfq", "pop {}", out(reg) flags, options(preserves_flags));
+    #[cfg(target_arch = "x86")]
+    asm!("pushfd", "pop {}", out(reg) flags, options(preserves_flags));
+
+    asm!("mov {}, cr0", out(reg) cr0, options(nomem, nostack, preserves_flags));
+    asm!("mov {}, cr2", out(reg) cr2, options(nomem, nostack, preserves_flags));
+    asm!("mov {}, cr3", out(reg) cr3, options(nomem, nostack, preserves_flags));
+    asm!("mov {}, cr4", out(reg) cr4, options(nomem, nostack, preserves_flags));
+
+    [pc, sp, fp, flags, cr0, cr2, cr3, cr4]
+}
+
+#[cfg(target_arch = "aarch64")]
+const COUNT: usize = 6;
+#[cfg(target_arch = "aarch64")]
+const NAMES: [&str; COUNT] = ["pc", "sp", "x29", "x30", "CurrentEL", "DAIF"];
+
+#[cfg(target_arch = "aarch64")]
+#[inline(always)]
+unsafe fn capture() -> [usize; COUNT] {
+    let (pc, sp, fp, lr, el, daif): (usize, usize, usize, usize, usize, usize);
+    asm!(
+        "adr {pc}, .",
+        "mov {sp}, sp",
+        "mov {fp}, x29",
+        "mov {lr}, x30",
+        "mrs {el}, CurrentEL",
+        "mrs {daif}, DAIF",
+        pc = out(reg) pc,
+        sp = out(reg) sp,
+        fp = out(reg) fp,
+        lr = out(reg) lr,
+        el = out(reg) el,
+        daif = out(reg) daif,
+        options(nomem, nostack, preserves_flags),
+    );
+    [pc, sp, fp, lr, el, daif]
+}
+
+#[cfg(target_arch = "arm")]
+const COUNT: usize = 6;
+#[cfg(target_arch = "arm")]
+const NAMES: [&str; COUNT] = ["pc", "sp", "r11", "lr", "r7", "cpsr"];
+
+#[cfg(target_arch = "arm")]
+#[inline(always)]
+unsafe fn capture() -> [usize; COUNT] {
+    let (pc, sp, r11, lr, r7, cpsr): (usize, usize, usize, usize, usize, usize);
+    asm!(
+        "adr {pc}, .",
+        "mov {sp}, sp",
+        "mov {r11}, r11",
+        "mov {lr}, lr",
+        "mov {r7}, r7",
+        "mrs {cpsr}, cpsr",
+        pc = out(reg) pc,
+        sp = out(reg) sp,
+        r11 = out(reg) r11,
+        lr = out(reg) lr,
+        r7 = out(reg) r7,
+        cpsr = out(reg) cpsr,
+        options(nomem, nostack, preserves_flags),
+    );
+    [pc, sp, r11, lr, r7, cpsr]
+}
+
+#[cfg(target_arch = "riscv64")]
+const COUNT: usize = 6;
+#[cfg(target_arch = "riscv64")]
+const NAMES: [&str; COUNT] = ["pc", "sp", "s0", "ra", "sstatus", "satp"];
+
+#[cfg(target_arch = "riscv64")]
+#[inline(always)]
+unsafe fn capture() -> [usize; COUNT] {
+    let (pc, sp, fp, ra, sstatus, satp): (usize, usize, usize, usize, usize, usize);
+    asm!(
+        "auipc {pc}, 0",
+        "mv {sp}, sp",
+        "mv {fp}, s0",
+        "mv {ra}, ra",
+        "csrr {sstatus}, sstatus",
+        "csrr {satp}, satp",
+        pc = out(reg) pc,
+        sp = out(reg) sp,
+        fp = out(reg) fp,
+        ra = out(reg) ra,
+        sstatus = out(reg) sstatus,
+        satp = out(reg) satp,
+        options(nomem, nostack, preserves_flags),
+    );
+    [pc, sp, fp, ra, sstatus, satp]
+}
+
+const PC: usize = 0;
+const SP: usize = 1;
+const FP: usize = 2;
+
+/// Most return addresses recorded by a [`Backtrace`]
+pub const MAX_FRAMES: usize = 32;
+
+/// Largest distance between two frames before the chain is considered corrupt
+const MAX_FRAME_SIZE: usize = 1024 * 1024;
+
+/// Return addresses found by walking the frame pointer chain
+///
+/// This only works if the image is built with frame pointers (`-C force-frame-pointers=yes`),
+/// and only on x86, x86_64, AArch64 and RISC-V. The walk stops at the first frame pointer
+/// which is null, misaligned, or doesn't point further up the stack, which is usually where
+/// firmware code without frame pointers begins.
+///
+/// Addresses are also resolved against the base of the running image, so they can be
+/// symbolized offline with e.g. `addr2line` after adding the image base from the PE header.
+/// Return addresses point after the call instruction; subtract one to find the call itself.
+#[derive(Clone, Copy, Debug)]
+pub struct Backtrace {
+    frames: [usize; MAX_FRAMES],
+    len:    usize,
+    image:  Option<(usize, usize)>,
+}
+
+impl Backtrace {
+    /// Walks the stack from the call site
+    #[inline(always)]
+    pub fn capture() -> Self {
+        unsafe { Self::from_frame_pointer(Registers::capture().frame_pointer()) }
+    }
+
+    /// Walks the stack starting at the frame `fp` points to
+    ///
+    /// The image base is looked up through the Loaded Image Protocol if boot services are
+    /// still active.
+    ///
+    /// # Safety
+    ///
+    /// `fp` must be null or a valid frame pointer. Frames further up the chain are only
+    /// sanity-checked, so a corrupted stack may still cause a fault.
+    pub unsafe fn from_frame_pointer(mut fp: usize) -> Self {
+        let mut frames = [0; MAX_FRAMES];
+        let mut len = 0;
+
+        while len < MAX_FRAMES && fp != 0 && fp.is_multiple_of(align_of::<usize>()) {
+            let Some((next, return_addr)) = unwind(fp) else {
+                break;
+            };
+            if return_addr == 0 {
+                break;
+            }
+            frames[len] = return_addr;
+            len += 1;
+
+            // The stack grows down, so callers' frames are always at higher addresses.
+            if next <= fp || next - fp > MAX_FRAME_SIZE {
+                break;
+            }
+            fp = next;
+        }
+
+        let image = boot_services_active()
+            .then(loaded_image)
+            .and_then(|image| image.ok())
+            .map(|image| (image.image_base as usize, image.image_size as usize));
+
+        Self { frames, len, image }
+    }
+
+    /// Returns the recorded return addresses, innermost first
+    pub fn frames(&self) -> &[usize] {
+        &self.frames[..self.len]
+    }
+
+    /// Returns the address the running image was loaded at, if it could be determined
+    pub fn image_base(&self) -> Option<usize> {
+        self.image.map(|(base, _)| base)
+    }
+
+    /// Returns the offset of each return address from the image base
+    ///
+    /// Addresses outside the image, or all of them if the image base is unknown, are `None`.
+    pub fn offsets(&self) -> impl Iterator<Item = Option<usize>> + '_ {
+        self.frames().iter().map(|&addr| {
+            let (base, size) = self.image?;
+            addr.checked_sub(base).filter(|&offset| offset < size)
+        })
+    }
+}
+
+impl fmt::Display for Backtrace {
+    /// Formats one frame per line, e.g. `#0 000000003e8a1234 image+1234`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let digits = 2 * core::mem::size_of::<usize>();
+        for (i, (addr, offset)) in self.frames().iter().zip(self.offsets()).enumerate() {
+            write!(f, "#{i:<2} {addr:0digits$x}")?;
+            match offset {
+                Some(offset) => writeln!(f, " image+{offset:x}")?,
+                None => writeln!(f)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads the caller's frame pointer and the return address from the frame at `fp`
+#[cfg(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64"))]
+unsafe fn unwind(fp: usize) -> Option<(usize, usize)> {
+    // The saved frame pointer is at `fp`, followed by the return address.
+    let frame = fp as *const usize;
+    Some((frame.read_volatile(), frame.add(1).read_volatile()))
+}
+
+#[cfg(target_arch = "riscv64")]
+unsafe fn unwind(fp: usize) -> Option<(usize, usize)> {
+    // `s0` points just past the saved return address and frame pointer.
+    let frame = fp as *const usize;
+    Some((frame.sub(2).read_volatile(), frame.sub(1).read_volatile()))
+}
+
+/// ARM and Thumb code lay frames out differently, and neither records which is in use.
+#[cfg(target_arch = "arm")]
+unsafe fn unwind(_fp: usize) -> Option<(usize, usize)> {
+    None
+}
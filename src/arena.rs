@@ -0,0 +1,152 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Bump allocation for transient boot-time data
+//!
+//! A [`BumpArena`] hands out memory from a single page allocation and frees it all at once,
+//! which suits short-lived parsing work (device path copies, memory map snapshots) that would
+//! otherwise fragment pool memory.
+
+use core::{
+    alloc::Layout,
+    cell::Cell,
+    mem::{align_of, size_of},
+    ptr::{self, NonNull},
+    slice,
+};
+
+use crate::{
+    boot_services, boot_services_active, default_memory_type, table::AllocPagesType, Result,
+    Status, PAGE_SIZE,
+};
+
+/// Page-backed bump allocator
+///
+/// The pages are of the [default memory type](crate::default_memory_type) and are freed when
+/// the arena is dropped, so nothing allocated from it survives the arena. Destructors of
+/// values placed in the arena are never run.
+///
+/// Drop the arena before calling `ExitBootServices()`; if boot services have already been
+/// exited, its pages are simply left in the memory map.
+pub struct BumpArena {
+    base:   NonNull<u8>,
+    pages:  usize,
+    offset: Cell<usize>,
+}
+
+impl BumpArena {
+    /// Allocates an arena of at least `size` bytes
+    pub fn new(size: usize) -> Result<Self> {
+        let pages = size.div_ceil(PAGE_SIZE).max(1);
+        let addr =
+            boot_services().allocate_pages(AllocPagesType::Any, default_memory_type(), pages)?;
+        Ok(Self {
+            base: NonNull::new(addr as *mut u8).ok_or(Status::OUT_OF_RESOURCES)?,
+            pages,
+            offset: Cell::new(0),
+        })
+    }
+
+    /// Returns the size of the arena in bytes
+    pub fn capacity(&self) -> usize {
+        self.pages * PAGE_SIZE
+    }
+
+    /// Returns the number of bytes allocated so far, including alignment padding
+    pub fn used(&self) -> usize {
+        self.offset.get()
+    }
+
+    /// Allocates uninitialized memory for `layout`
+    ///
+    /// Fails with `OUT_OF_RESOURCES` if the arena is full.
+    pub fn alloc_layout(&self, layout: Layout) -> Result<NonNull<u8>> {
+        let base = self.base.as_ptr() as usize;
+        let start = (base + self.offset.get())
+            .checked_next_multiple_of(layout.align())
+            .ok_or(Status::OUT_OF_RESOURCES)?;
+        let end = start
+            .checked_add(layout.size())
+            .filter(|&end| end <= base + self.capacity())
+            .ok_or(Status::OUT_OF_RESOURCES)?;
+        self.offset.set(end - base);
+        Ok(unsafe { NonNull::new_unchecked(self.base.as_ptr().add(start - base)) })
+    }
+
+    /// Moves `value` into the arena
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc<T>(&self, value: T) -> Result<&mut T> {
+        let ptr = self.alloc_layout(Layout::new::<T>())?.cast::<T>();
+        unsafe {
+            ptr.as_ptr().write(value);
+            Ok(&mut *ptr.as_ptr())
+        }
+    }
+
+    /// Copies `items` into the arena
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_slice<T: Copy>(&self, items: &[T]) -> Result<&mut [T]> {
+        let layout = Layout::array::<T>(items.len()).map_err(|_| Status::OUT_OF_RESOURCES)?;
+        let ptr = self.alloc_layout(layout)?.cast::<T>();
+        unsafe {
+            ptr::copy_nonoverlapping(items.as_ptr(), ptr.as_ptr(), items.len());
+            Ok(slice::from_raw_parts_mut(ptr.as_ptr(), items.len()))
+        }
+    }
+
+    /// Allocates `len` zeroed bytes aligned for `u64`, e.g. for a memory map buffer
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_zeroed(&self, len: usize) -> Result<&mut [u8]> {
+        let layout = Layout::from_size_align(len, align_of::<u64>().max(size_of::<usize>()))
+            .map_err(|_| Status::OUT_OF_RESOURCES)?;
+        let ptr = self.alloc_layout(layout)?;
+        unsafe {
+            ptr.as_ptr().write_bytes(0, len);
+            Ok(slice::from_raw_parts_mut(ptr.as_ptr(), len))
+        }
+    }
+
+    /// Frees everything allocated from the arena
+    ///
+    /// Taking `&mut self` guarantees nothing borrowed from the arena is still alive.
+    pub fn reset(&mut self) {
+        self.offset.set(0);
+    }
+}
+
+impl Drop for BumpArena {
+    fn drop(&mut self) {
+        if boot_services_active() {
+            unsafe {
+                let _ = boot_services().free_pages(self.base.as_ptr() as u64, self.pages);
+            }
+        }
+    }
+}
@@ -0,0 +1,115 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Audible cues
+//!
+//! Boot menus can beep to tell users who can't see the screen that the menu is waiting, or
+//! that a key did something. Firmware rarely offers sound output, so every function here
+//! quietly does nothing if there is no [speaker](SpeakerIo), returning `false`.
+//!
+//! The Speaker Interface Protocol is the only source used: HII defines no sound output, and
+//! driving the PC speaker's ports directly would bypass whatever the firmware's driver does.
+
+use core::time::Duration;
+
+use crate::{
+    boot_services, boot_services_active,
+    proto::{speaker::SpeakerIo, Proto},
+};
+
+/// A cue with a distinct sound
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Cue {
+    /// The menu is shown and waiting for input
+    Ready,
+    /// The selection moved
+    Select,
+    /// An entry was chosen
+    Confirm,
+    /// A key did nothing, or something failed
+    Error,
+}
+
+impl Cue {
+    /// Returns the tone, number of beeps, beep duration and interval
+    const fn pattern(self) -> (u16, usize, Duration, Duration) {
+        match self {
+            Self::Ready => (880, 2, Duration::from_millis(80), Duration::from_millis(60)),
+            Self::Select => (1320, 1, Duration::from_millis(25), Duration::ZERO),
+            Self::Confirm => (1760, 1, Duration::from_millis(120), Duration::ZERO),
+            Self::Error => (
+                220,
+                3,
+                Duration::from_millis(100),
+                Duration::from_millis(60),
+            ),
+        }
+    }
+}
+
+fn speaker() -> Option<Proto<SpeakerIo>> {
+    if !boot_services_active() {
+        return None;
+    }
+    boot_services().first_protocol::<SpeakerIo>().ok()
+}
+
+/// Returns whether the firmware can make sounds
+pub fn is_available() -> bool {
+    speaker().is_some()
+}
+
+/// Beeps at `frequency` hertz for `duration`, returning whether it did
+///
+/// This blocks until the beep has finished.
+pub fn beep(frequency: u16, duration: Duration) -> bool {
+    beeps(frequency, 1, duration, Duration::ZERO)
+}
+
+/// Beeps `count` times at `frequency` hertz, returning whether it did
+///
+/// Each beep lasts `duration`, with `interval` of silence in between. This blocks until the
+/// last beep has finished.
+pub fn beeps(frequency: u16, count: usize, duration: Duration, interval: Duration) -> bool {
+    let Some(speaker) = speaker() else {
+        return false;
+    };
+    let micros = |d: Duration| usize::try_from(d.as_micros()).unwrap_or(usize::MAX);
+    speaker.set_beep_tone(frequency).is_ok()
+        && speaker
+            .generate_beep(count, micros(duration), micros(interval))
+            .is_ok()
+}
+
+/// Plays `cue`, returning whether it did
+pub fn cue(cue: Cue) -> bool {
+    let (frequency, count, duration, interval) = cue.pattern();
+    beeps(frequency, count, duration, interval)
+}
@@ -0,0 +1,173 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Loader log handed over to the OS
+//!
+//! Once [initialized](init), the boot log keeps the most recent output written to
+//! [`BootLog`] in a ring buffer and publishes it in the configuration table under
+//! [`BOOT_LOG_GUID`], so the kernel can retrieve the loader's log after boot.
+//!
+//! The table entry points to a [`BootLogHeader`], which is immediately followed by
+//! `capacity` bytes of data. If `written` is at most `capacity`, the log is the first
+//! `written` bytes; otherwise it starts at offset `written % capacity` and wraps around.
+//!
+//! The buffer is allocated as the [default memory type](crate::default_memory_type); set an
+//! OS memory type first if the kernel might reclaim loader memory before reading the log.
+
+use core::{fmt, mem::size_of, ptr};
+
+use crate::{
+    boot_services, default_memory_type, guid, sync::TryLock, table::AllocPagesType, Guid,
+    PhysicalAddr, Result, Status, PAGE_SIZE,
+};
+
+/// Configuration table entry pointing to the [`BootLogHeader`]
+pub const BOOT_LOG_GUID: Guid = guid!(
+    0x9213ec93, 0xb6d2, 0x4ab8,
+    {0x95,0x49,0xcb,0x36,0xb4,0xe6,0xf0,0x7f}
+);
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct BootLogHeader {
+    /// [`BootLogHeader::SIGNATURE`]
+    pub signature:   [u8; 8],
+    pub version:     u32,
+    /// Size of this header, which the data follows
+    pub header_size: u32,
+    /// Size of the data area, in bytes
+    pub capacity:    u64,
+    /// Number of bytes ever written, including those since overwritten
+    pub written:     u64,
+}
+
+impl BootLogHeader {
+    pub const SIGNATURE: [u8; 8] = *b"BOOTLOG\0";
+    pub const VERSION: u32 = 1;
+}
+
+/// Address of the header, or zero before [`init()`]
+static LOG: TryLock<usize> = TryLock::new(0);
+
+/// Allocates a log of at least `size` bytes and publishes it in the configuration table
+///
+/// Fails with `ALREADY_STARTED` if the log has already been initialized.
+pub fn init(size: usize) -> Result<()> {
+    let bs = boot_services();
+    let total = size
+        .checked_add(size_of::<BootLogHeader>())
+        .ok_or(Status::INVALID_PARAMETER)?;
+    let pages = total.div_ceil(PAGE_SIZE);
+
+    LOG.try_with(|log| {
+        if *log != 0 {
+            return Err(Status::ALREADY_STARTED);
+        }
+        let addr = bs.allocate_pages(AllocPagesType::Any, default_memory_type(), pages)?;
+        let header = addr as *mut BootLogHeader;
+        unsafe {
+            header.write(BootLogHeader {
+                signature:   BootLogHeader::SIGNATURE,
+                version:     BootLogHeader::VERSION,
+                header_size: size_of::<BootLogHeader>() as u32,
+                capacity:    (pages * PAGE_SIZE - size_of::<BootLogHeader>()) as u64,
+                written:     0,
+            });
+            if let Err(status) = bs.install_configuration_table(&BOOT_LOG_GUID, header.cast()) {
+                let _ = bs.free_pages(addr, pages);
+                return Err(status);
+            }
+        }
+        *log = addr as usize;
+        Ok(())
+    })
+    .unwrap_or(Err(Status::NOT_READY))
+}
+
+/// Returns the address and total size of the log, including the header
+///
+/// This is useful to pass the log to kernels through the boot protocol instead of the
+/// configuration table.
+pub fn location() -> Option<(PhysicalAddr, usize)> {
+    LOG.try_with(|&mut log| {
+        if log == 0 {
+            return None;
+        }
+        let header = unsafe { &*(log as *const BootLogHeader) };
+        let size = header.header_size as usize + header.capacity as usize;
+        Some((log as PhysicalAddr, size))
+    })
+    .flatten()
+}
+
+/// Appends `bytes` to the log
+///
+/// Output is dropped if the log isn't initialized, or if this interrupts another write from
+/// an event callback.
+pub fn write_bytes(bytes: &[u8]) {
+    LOG.try_with(|&mut log| {
+        if log == 0 {
+            return;
+        }
+        unsafe {
+            let header = log as *mut BootLogHeader;
+            let data = header.cast::<u8>().add((*header).header_size as usize);
+            let capacity = (*header).capacity as usize;
+            let written = (*header).written;
+
+            // Only the last `capacity` bytes can survive anyway.
+            let bytes = &bytes[bytes.len().saturating_sub(capacity)..];
+            let head = (written % capacity as u64) as usize;
+            let first = bytes.len().min(capacity - head);
+            ptr::copy_nonoverlapping(bytes.as_ptr(), data.add(head), first);
+            ptr::copy_nonoverlapping(bytes[first..].as_ptr(), data, bytes.len() - first);
+
+            // Volatile, since the kernel is the one reading it.
+            ptr::addr_of_mut!((*header).written).write_volatile(written + bytes.len() as u64);
+        }
+    });
+}
+
+/// Writes formatted output to the boot log, followed by a newline
+pub fn log(args: fmt::Arguments) {
+    let _ = fmt::Write::write_fmt(&mut BootLog, args);
+    write_bytes(b"\n");
+}
+
+/// [`fmt::Write`] sink for the boot log, e.g. for a [`MultiWriter`](crate::output::MultiWriter)
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BootLog;
+
+impl fmt::Write for BootLog {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        write_bytes(s.as_bytes());
+        Ok(())
+    }
+}
@@ -0,0 +1,255 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Command line parsing
+//!
+//! Images started by the boot manager get their command line as UCS-2 load options, while the
+//! shell also passes it through [`ShellParameters`](crate::proto::shell::ShellParameters).
+//! [`CmdLine`] splits a command line into arguments following the shell's quoting rules:
+//!
+//! - arguments are separated by spaces or tabs;
+//! - double quotes group characters, including spaces, into one argument;
+//! - `^` escapes the character after it, e.g. `^"` for a literal quote.
+//!
+//! Each argument is either a flag like `quiet` or a `key=value` pair like `root=/dev/sda2`.
+//! Parsing does not allocate; arguments borrow from the command line and are unquoted lazily.
+
+use core::fmt;
+
+use crate::{Result, Status};
+
+/// A command line, split into arguments on demand
+#[derive(Clone, Copy, Debug)]
+pub struct CmdLine<'a> {
+    text: &'a str,
+}
+
+impl<'a> CmdLine<'a> {
+    pub const fn new(text: &'a str) -> Self {
+        Self { text }
+    }
+
+    /// Returns an iterator over the raw arguments
+    ///
+    /// When the command line comes from the shell, the first one is the program name.
+    pub fn words(&self) -> Words<'a> {
+        Words { rest: self.text }
+    }
+
+    /// Returns an iterator over the arguments as flags and `key=value` pairs
+    pub fn args(&self) -> impl Iterator<Item = Arg<'a>> {
+        self.words().map(Arg::parse)
+    }
+
+    /// Returns the value of `key`
+    ///
+    /// If the key is given more than once, the last value wins.
+    pub fn get(&self, key: &str) -> Option<Word<'a>> {
+        self.args()
+            .filter_map(|arg| match arg {
+                Arg::Pair(k, value) if k == key => Some(value),
+                _ => None,
+            })
+            .last()
+    }
+
+    /// Returns whether `flag` is given, as a flag and not a `key=value` pair
+    pub fn has_flag(&self, flag: &str) -> bool {
+        self.args()
+            .any(|arg| matches!(arg, Arg::Flag(f) if f == flag))
+    }
+}
+
+/// Iterator over the raw arguments of a [`CmdLine`]
+#[derive(Clone, Debug)]
+pub struct Words<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Iterator for Words<'a> {
+    type Item = Word<'a>;
+
+    fn next(&mut self) -> Option<Word<'a>> {
+        let text = self.rest.trim_start_matches([' ', '\t']);
+        if text.is_empty() {
+            self.rest = text;
+            return None;
+        }
+        let mut quoted = false;
+        let mut escaped = false;
+        let end = text
+            .char_indices()
+            .find(|&(_, c)| {
+                match (escaped, c) {
+                    (true, _) => escaped = false,
+                    (false, '^') => escaped = true,
+                    (false, '"') => quoted = !quoted,
+                    (false, ' ' | '\t') if !quoted => return true,
+                    _ => {}
+                }
+                false
+            })
+            .map_or(text.len(), |(i, _)| i);
+        self.rest = &text[end..];
+        Some(Word(&text[..end]))
+    }
+}
+
+/// A single argument, as written on the command line
+///
+/// Comparisons with `str` and formatting use the unquoted text.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Word<'a>(pub &'a str);
+
+impl<'a> Word<'a> {
+    /// Returns the argument with quotes and escapes removed
+    pub fn chars(&self) -> impl Iterator<Item = char> + 'a {
+        let mut escaped = false;
+        self.0.chars().filter(move |&c| match (escaped, c) {
+            (true, _) => {
+                escaped = false;
+                true
+            }
+            (false, '^') => {
+                escaped = true;
+                false
+            }
+            (false, '"') => false,
+            _ => true,
+        })
+    }
+
+    /// Returns the argument as a `str` if it contains no quotes or escapes
+    pub fn as_plain(&self) -> Option<&'a str> {
+        (!self.0.contains(['"', '^'])).then_some(self.0)
+    }
+
+    /// Writes the unquoted argument into `buf`
+    ///
+    /// Fails with `BUFFER_TOO_SMALL` if it does not fit.
+    pub fn unquote_into<'b>(&self, buf: &'b mut [u8]) -> Result<&'b str> {
+        let mut len = 0;
+        for c in self.chars() {
+            let dest = buf
+                .get_mut(len..len + c.len_utf8())
+                .ok_or(Status::BUFFER_TOO_SMALL)?;
+            len += c.encode_utf8(dest).len();
+        }
+        Ok(core::str::from_utf8(&buf[..len]).unwrap())
+    }
+
+    /// Parses the unquoted argument as a decimal, or with a `0x` prefix hexadecimal, number
+    pub fn as_u64(&self) -> Option<u64> {
+        let mut buf = [0; 24];
+        let s = self.unquote_into(&mut buf).ok()?;
+        match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+            Some(hex) => u64::from_str_radix(hex, 16).ok(),
+            None => s.parse().ok(),
+        }
+    }
+}
+
+impl PartialEq<str> for Word<'_> {
+    fn eq(&self, other: &str) -> bool {
+        self.chars().eq(other.chars())
+    }
+}
+
+impl PartialEq<&str> for Word<'_> {
+    fn eq(&self, other: &&str) -> bool {
+        *self == **other
+    }
+}
+
+impl fmt::Display for Word<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.chars().try_for_each(|c| fmt::Write::write_char(f, c))
+    }
+}
+
+/// A parsed argument
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Arg<'a> {
+    Flag(Word<'a>),
+    /// `key=value`, split at the first `=` outside quotes
+    Pair(Word<'a>, Word<'a>),
+}
+
+impl<'a> Arg<'a> {
+    pub fn parse(word: Word<'a>) -> Self {
+        let mut quoted = false;
+        let mut escaped = false;
+        let split = word.0.char_indices().find(|&(_, c)| {
+            match (escaped, c) {
+                (true, _) => escaped = false,
+                (false, '^') => escaped = true,
+                (false, '"') => quoted = !quoted,
+                (false, '=') if !quoted => return true,
+                _ => {}
+            }
+            false
+        });
+        match split {
+            Some((i, _)) => Self::Pair(Word(&word.0[..i]), Word(&word.0[i + 1..])),
+            None => Self::Flag(word),
+        }
+    }
+}
+
+/// Decodes UCS-2 load options into `buf` as UTF-8
+///
+/// Decoding stops at the first NUL; an odd trailing byte is ignored. Fails with
+/// `BUFFER_TOO_SMALL` if the result does not fit.
+pub fn decode_load_options<'b>(options: &[u8], buf: &'b mut [u8]) -> Result<&'b str> {
+    let mut len = 0;
+    for c in decode(options) {
+        let dest = buf
+            .get_mut(len..len + c.len_utf8())
+            .ok_or(Status::BUFFER_TOO_SMALL)?;
+        len += c.encode_utf8(dest).len();
+    }
+    Ok(core::str::from_utf8(&buf[..len]).unwrap())
+}
+
+/// Returns the running image's load options, decoded from UCS-2
+#[cfg(feature = "alloc")]
+pub fn load_options() -> Result<alloc::string::String> {
+    let image = crate::proto::loaded_image::loaded_image()?;
+    Ok(decode(image.load_options()).collect())
+}
+
+/// Decodes unaligned UCS-2, stopping at the first NUL and replacing invalid code units
+fn decode(options: &[u8]) -> impl Iterator<Item = char> + '_ {
+    let units = options
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .take_while(|&c| c != 0);
+    char::decode_utf16(units).map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+}
@@ -0,0 +1,423 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Configuration files
+//!
+//! [`load()`] reads a configuration file from the directory the running image was loaded from,
+//! or from a list of fallback paths on the same volume. [`Config`] parses the common subset of
+//! INI and TOML that bootloader configurations use, without allocating:
+//!
+//! ```text
+//! # Comments start with `#` or `;`
+//! timeout = 5
+//!
+//! [entry.linux]
+//! kernel  = "\EFI\linux\vmlinuz"
+//! cmdline = "root=/dev/sda2 quiet"
+//! modules = ["initrd.img", "microcode.img"]
+//! ```
+//!
+//! Keys before the first section header belong to the section `""`. Quoted strings have no
+//! escape sequences, so Windows-style paths can be written as is.
+
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+use core::fmt;
+
+#[cfg(feature = "alloc")]
+use crate::{
+    boot_services,
+    proto::{
+        device_path::DeviceType,
+        loaded_image::loaded_image,
+        media::file::{File, FileAttribute, FileMode, SimpleFileSystem},
+    },
+    ucs2::{self, CStr16},
+    Result, Status,
+};
+
+/// Loads the file `name` from the running image's directory, or the first of `fallbacks` that
+/// exists
+///
+/// Fallbacks are paths from the root of the volume the image was loaded from, such as
+/// `\EFI\bolt\bolt.conf`. Fails with `NOT_FOUND` if none of the files exist.
+#[cfg(feature = "alloc")]
+pub fn load(name: &str, fallbacks: &[&str]) -> Result<Vec<u8>> {
+    let image = loaded_image()?;
+    let device = image.device_handle.ok_or(Status::NOT_FOUND)?;
+    let mut root = boot_services()
+        .protocol_for_handle::<SimpleFileSystem>(device)?
+        .open_volume()?;
+
+    let mut path = image.file_path().map(image_directory).unwrap_or_default();
+    path.push(b'\\' as u16);
+    path.extend(ucs2::encode_lossy(name));
+    match read(&mut root, path) {
+        Err(Status::NOT_FOUND) => {}
+        result => return result,
+    }
+
+    for fallback in fallbacks {
+        match read(&mut root, ucs2::encode_lossy(fallback).collect()) {
+            Err(Status::NOT_FOUND) => {}
+            result => return result,
+        }
+    }
+    Err(Status::NOT_FOUND)
+}
+
+/// Returns the directory part of an image's file path, without a trailing `\`
+#[cfg(feature = "alloc")]
+fn image_directory(path: &crate::proto::DevicePath) -> Vec<u16> {
+    let mut units = Vec::new();
+    for node in path.nodes() {
+        if (node.kind, node.sub_kind) != (DeviceType::MEDIA, 0x04) {
+            continue;
+        }
+        // The path is UCS-2, but the node gives no alignment guarantee.
+        let node_units = node
+            .data
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .take_while(|&c| c != 0);
+        // A path may be split across several nodes, with or without separators between them.
+        if units.last().is_some_and(|&c| c != b'\\' as u16) {
+            units.push(b'\\' as u16);
+        }
+        units.extend(node_units);
+    }
+    let dir_len = units.iter().rposition(|&c| c == b'\\' as u16).unwrap_or(0);
+    units.truncate(dir_len);
+    units
+}
+
+#[cfg(feature = "alloc")]
+fn read(root: &mut File, mut path: Vec<u16>) -> Result<Vec<u8>> {
+    path.push(0);
+    let path = CStr16::from_slice_with_nul(&path).map_err(|_| Status::INVALID_PARAMETER)?;
+    let mut file = root.open(path, FileMode::READ, FileAttribute::empty())?;
+    let size = usize::try_from(file.size()?).map_err(|_| Status::BAD_BUFFER_SIZE)?;
+    let mut data = alloc::vec![0; size];
+    file.read_exact(&mut data)?;
+    Ok(data)
+}
+
+/// A parsed configuration file
+#[derive(Clone, Copy, Debug)]
+pub struct Config<'a> {
+    text: &'a str,
+}
+
+impl<'a> Config<'a> {
+    /// Parses `text`, failing on the first malformed line
+    pub fn new(text: &'a str) -> core::result::Result<Self, ParseError> {
+        let config = Self {
+            text: text.strip_prefix('\u{feff}').unwrap_or(text),
+        };
+        config.entries().try_for_each(|entry| entry.map(drop))?;
+        Ok(config)
+    }
+
+    /// Parses a configuration file as loaded by [`load()`], which must be UTF-8
+    pub fn from_bytes(data: &'a [u8]) -> core::result::Result<Self, ParseError> {
+        let text = core::str::from_utf8(data).map_err(|error| ParseError {
+            line: line_of(&data[..error.valid_up_to()]),
+            kind: ParseErrorKind::InvalidUtf8,
+        })?;
+        Self::new(text)
+    }
+
+    /// Returns an iterator over all entries, in file order
+    pub fn entries(&self) -> Entries<'a> {
+        Entries {
+            lines:   self.text.lines().enumerate(),
+            section: "",
+        }
+    }
+
+    /// Returns an iterator over the entries of `section`
+    pub fn section(&self, section: &'a str) -> impl Iterator<Item = Entry<'a>> {
+        self.entries()
+            .filter_map(core::result::Result::ok)
+            .filter(move |entry| entry.section == section)
+    }
+
+    /// Returns an iterator over the names of all sections, in file order
+    ///
+    /// A section which is declared more than once is returned each time.
+    pub fn sections(&self) -> impl Iterator<Item = &'a str> {
+        self.text
+            .lines()
+            .filter_map(|line| section_header(line.trim())?.ok())
+    }
+
+    /// Returns the value of `key` in `section`
+    ///
+    /// If the key is given more than once, the last value wins.
+    pub fn get(&self, section: &str, key: &str) -> Option<Value<'a>> {
+        self.entries()
+            .filter_map(core::result::Result::ok)
+            .filter(|entry| entry.section == section && entry.key == key)
+            .last()
+            .map(|entry| entry.value)
+    }
+}
+
+/// Iterator over the entries of a [`Config`]
+#[derive(Clone, Debug)]
+pub struct Entries<'a> {
+    lines:   core::iter::Enumerate<core::str::Lines<'a>>,
+    section: &'a str,
+}
+
+impl<'a> Iterator for Entries<'a> {
+    type Item = core::result::Result<Entry<'a>, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for (index, line) in self.lines.by_ref() {
+            let line = line.trim();
+            let error = |kind| {
+                Some(Err(ParseError {
+                    line: index + 1,
+                    kind,
+                }))
+            };
+            if line.is_empty() || line.starts_with(['#', ';']) {
+                continue;
+            }
+            if let Some(header) = section_header(line) {
+                match header {
+                    Ok(name) => self.section = name,
+                    Err(kind) => return error(kind),
+                }
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                return error(ParseErrorKind::MissingEquals);
+            };
+            let key = key.trim();
+            if key.is_empty() {
+                return error(ParseErrorKind::EmptyKey);
+            }
+            let value = value.trim();
+            let value = match value.chars().next() {
+                Some(quote @ ('"' | '\'')) => match value[1..].find(quote) {
+                    Some(end) => &value[..end + 2],
+                    None => return error(ParseErrorKind::UnterminatedString),
+                },
+                Some('[') => match value.rfind(']') {
+                    Some(end) => &value[..=end],
+                    None => return error(ParseErrorKind::UnterminatedList),
+                },
+                _ => strip_comment(value),
+            };
+            return Some(Ok(Entry {
+                section: self.section,
+                key,
+                value: Value(value),
+                line: index + 1,
+            }));
+        }
+        None
+    }
+}
+
+/// Parses a trimmed `[section]` line, returning `None` if it is not a header
+fn section_header(line: &str) -> Option<core::result::Result<&str, ParseErrorKind>> {
+    let header = line.strip_prefix('[')?;
+    Some(
+        strip_comment(header)
+            .strip_suffix(']')
+            .map(str::trim)
+            .ok_or(ParseErrorKind::UnterminatedSection),
+    )
+}
+
+/// Strips a trailing comment, which must be preceded by whitespace
+fn strip_comment(s: &str) -> &str {
+    let end = s
+        .match_indices(['#', ';'])
+        .find(|&(i, _)| s[..i].ends_with(char::is_whitespace))
+        .map_or(s.len(), |(i, _)| i);
+    s[..end].trim_end()
+}
+
+fn line_of(data: &[u8]) -> usize {
+    data.iter().filter(|&&b| b == b'\n').count() + 1
+}
+
+/// A `key = value` line
+#[derive(Clone, Copy, Debug)]
+pub struct Entry<'a> {
+    pub section: &'a str,
+    pub key:     &'a str,
+    pub value:   Value<'a>,
+    /// Line number, starting at 1
+    pub line:    usize,
+}
+
+/// The value of an [`Entry`], with surrounding whitespace and comments removed
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Value<'a>(pub &'a str);
+
+impl<'a> Value<'a> {
+    /// Returns the value as a string, removing quotes if it is quoted
+    pub fn as_str(&self) -> &'a str {
+        let s = self.0;
+        match s.as_bytes() {
+            [b'"', .., b'"'] | [b'\'', .., b'\''] => &s[1..s.len() - 1],
+            _ => s,
+        }
+    }
+
+    /// Parses `true`/`false`, `yes`/`no`, `on`/`off` or `1`/`0`, ignoring ASCII case
+    pub fn as_bool(&self) -> Option<bool> {
+        const TRUE: [&str; 4] = ["true", "yes", "on", "1"];
+        const FALSE: [&str; 4] = ["false", "no", "off", "0"];
+        let s = self.as_str();
+        if TRUE.iter().any(|t| t.eq_ignore_ascii_case(s)) {
+            Some(true)
+        } else if FALSE.iter().any(|f| f.eq_ignore_ascii_case(s)) {
+            Some(false)
+        } else {
+            None
+        }
+    }
+
+    /// Parses a decimal, or with a `0x` prefix hexadecimal, integer
+    ///
+    /// `_` may be used as a digit separator, as in TOML.
+    pub fn as_int(&self) -> Option<i64> {
+        let s = self.as_str();
+        let (negative, s) = match s.strip_prefix('-') {
+            Some(s) => (true, s),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let (radix, digits) = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+            Some(digits) => (16, digits),
+            None => (10, s),
+        };
+        if digits.is_empty() || digits.starts_with('_') {
+            return None;
+        }
+        let mut value = 0i64;
+        for c in digits.chars().filter(|&c| c != '_') {
+            let digit = c.to_digit(radix)? as i64;
+            value = value.checked_mul(radix as i64)?.checked_add(digit)?;
+        }
+        Some(if negative { -value } else { value })
+    }
+
+    /// Returns an iterator over the items of a `[a, b, c]` list
+    ///
+    /// A value which is not a list is returned as a single item. Lists cannot be nested.
+    pub fn items(&self) -> impl Iterator<Item = Value<'a>> {
+        let s = self.0;
+        let (mut rest, single) = match s.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+            Some(items) => (items, false),
+            None => (s, true),
+        };
+        let mut done = false;
+        core::iter::from_fn(move || loop {
+            if done {
+                return None;
+            }
+            if single {
+                done = true;
+                return Some(Value(rest));
+            }
+            let mut quote = None;
+            let end = rest
+                .char_indices()
+                .find(|&(_, c)| match (quote, c) {
+                    (None, '"' | '\'') => {
+                        quote = Some(c);
+                        false
+                    }
+                    (Some(q), c) if q == c => {
+                        quote = None;
+                        false
+                    }
+                    (None, ',') => true,
+                    _ => false,
+                })
+                .map(|(i, _)| i);
+            let item = rest[..end.unwrap_or(rest.len())].trim();
+            match end {
+                Some(end) => rest = &rest[end + 1..],
+                None => done = true,
+            }
+            // Skips empty items, which allows a trailing comma.
+            if !item.is_empty() {
+                return Some(Value(item));
+            }
+        })
+    }
+}
+
+impl fmt::Display for Value<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Error returned for a malformed configuration file
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ParseError {
+    /// Line number, starting at 1
+    pub line: usize,
+    pub kind: ParseErrorKind,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ParseErrorKind {
+    InvalidUtf8,
+    /// A `[section]` header without the closing `]`
+    UnterminatedSection,
+    /// A line which is neither a section header nor a `key = value` pair
+    MissingEquals,
+    EmptyKey,
+    UnterminatedString,
+    UnterminatedList,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self.kind {
+            ParseErrorKind::InvalidUtf8 => "invalid UTF-8",
+            ParseErrorKind::UnterminatedSection => "expected `]`",
+            ParseErrorKind::MissingEquals => "expected `key = value`",
+            ParseErrorKind::EmptyKey => "empty key",
+            ParseErrorKind::UnterminatedString => "unterminated string",
+            ParseErrorKind::UnterminatedList => "expected `]`",
+        };
+        write!(f, "line {}: {message}", self.line)
+    }
+}
@@ -0,0 +1,309 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Debugger support
+//!
+//! Hardware debuggers and the EDK2 debugging scripts for GDB find the images in memory through
+//! the debug image info table, which the firmware keeps in the configuration table under
+//! [`TableGuid::DEBUG_IMAGE_INFO`]. Each entry points to an image's Loaded Image Protocol,
+//! giving its base and size; the debugger then reads the [PDB path](crate::pe::PeImage::pdb_path)
+//! from the image's headers to find its symbols.
+//!
+//! The firmware registers every image started through `LoadImage()`. Images placed in memory
+//! by the loader itself have to be [registered](register_image) by hand.
+//!
+//! Debuggers without access to the table, such as GDB attached to QEMU's gdbstub, need to be
+//! told where the image is. [`report_image()`] prints the running image's [layout](ImageLayout)
+//! along with the `add-symbol-file` command that loads its symbols.
+
+use core::{
+    fmt,
+    mem::size_of,
+    ptr::{self, NonNull},
+    slice, str,
+};
+
+use crate::{
+    boot_services, default_memory_type, image_handle,
+    pe::PeImage,
+    proto::loaded_image::{loaded_image, LoadedImage},
+    system_table,
+    table::TableGuid,
+    Handle, Result, Status, PAGE_SIZE,
+};
+
+/// `EFI_DEBUG_IMAGE_INFO_TABLE_HEADER`
+#[repr(C)]
+#[derive(Debug)]
+pub struct DebugImageInfoTableHeader {
+    /// [`UPDATE_IN_PROGRESS`] while the table is being changed; [`TABLE_MODIFIED`] once it
+    /// has been changed, until a debugger clears it
+    pub update_status: u32,
+    /// Number of entries in use
+    pub table_size:    u32,
+    /// Array of entries, which may have null entries in between those in use
+    pub table:         *mut Option<NonNull<DebugImageInfoNormal>>,
+}
+
+pub const UPDATE_IN_PROGRESS: u32 = 0x01;
+pub const TABLE_MODIFIED: u32 = 0x02;
+
+/// `EFI_DEBUG_IMAGE_INFO_NORMAL`
+#[repr(C)]
+#[derive(Debug)]
+pub struct DebugImageInfoNormal {
+    /// Always [`IMAGE_INFO_TYPE_NORMAL`]
+    pub image_info_type: u32,
+    pub loaded_image:    *mut LoadedImage,
+    pub image_handle:    Handle,
+}
+
+pub const IMAGE_INFO_TYPE_NORMAL: u32 = 0x01;
+
+/// Returns the debug image info table
+///
+/// Fails with `NOT_FOUND` if the firmware doesn't provide one.
+pub fn table() -> Result<&'static mut DebugImageInfoTableHeader> {
+    system_table()
+        .config_table()
+        .get_table(TableGuid::DEBUG_IMAGE_INFO)
+        .and_then(|header| unsafe { header.cast::<DebugImageInfoTableHeader>().as_mut() })
+        .ok_or(Status::NOT_FOUND)
+}
+
+/// Returns the slots of the table up to and including the last one in use
+fn slots(
+    header: &DebugImageInfoTableHeader,
+) -> &'static mut [Option<NonNull<DebugImageInfoNormal>>] {
+    if header.table.is_null() {
+        return &mut [];
+    }
+    let mut remaining = header.table_size;
+    let mut len = 0;
+    while remaining > 0 {
+        if unsafe { (*header.table.add(len)).is_some() } {
+            remaining -= 1;
+        }
+        len += 1;
+    }
+    unsafe { slice::from_raw_parts_mut(header.table, len) }
+}
+
+/// Returns an iterator over the registered images
+pub fn images() -> Result<impl Iterator<Item = &'static DebugImageInfoNormal>> {
+    let slots = slots(table()?);
+    Ok(slots
+        .iter()
+        .flatten()
+        .map(|entry| unsafe { entry.as_ref() }))
+}
+
+/// Returns whether the image `handle` is registered
+pub fn is_registered(handle: Handle) -> Result<bool> {
+    Ok(images()?.any(|image| image.image_handle == handle))
+}
+
+/// Adds the image `handle` to the table
+///
+/// A free slot is reused if there is one; otherwise the table is moved to a larger allocation.
+/// The old allocation is left alone, since it belongs to the firmware.
+///
+/// # Safety
+///
+/// `loaded_image` must describe the image, and stay valid until it is
+/// [unregistered](unregister_image).
+pub unsafe fn register_image(handle: Handle, loaded_image: *mut LoadedImage) -> Result<()> {
+    let header = table()?;
+    let bs = boot_services();
+    let slots = slots(header);
+    let free_slot = slots.iter().position(Option::is_none);
+
+    // Grow by a page's worth of entries, as EDK2 does.
+    let per_page = PAGE_SIZE / size_of::<usize>();
+    let capacity = (slots.len() / per_page + 1) * per_page;
+    let new_table = match free_slot {
+        Some(_) => None,
+        None => Some(
+            bs.allocate_pool(default_memory_type(), capacity * size_of::<usize>())?
+                .cast::<Option<NonNull<DebugImageInfoNormal>>>(),
+        ),
+    };
+    let entry = match bs.allocate_pool(default_memory_type(), size_of::<DebugImageInfoNormal>()) {
+        Ok(entry) => entry.cast::<DebugImageInfoNormal>(),
+        Err(status) => {
+            if let Some(table) = new_table {
+                let _ = bs.free_pool(table.cast());
+            }
+            return Err(status);
+        }
+    };
+    entry.write(DebugImageInfoNormal {
+        image_info_type: IMAGE_INFO_TYPE_NORMAL,
+        loaded_image,
+        image_handle: handle,
+    });
+    let entry = NonNull::new(entry);
+
+    begin_update(header);
+    match (free_slot, new_table) {
+        (Some(index), _) => slots[index] = entry,
+        (None, Some(table)) => {
+            table.copy_from_nonoverlapping(slots.as_ptr(), slots.len());
+            table.add(slots.len()).write(entry);
+            for i in slots.len() + 1..capacity {
+                table.add(i).write(None);
+            }
+            header.table = table;
+        }
+        (None, None) => unreachable!(),
+    }
+    header.table_size += 1;
+    end_update(header);
+    Ok(())
+}
+
+/// Removes the image `handle` from the table
+///
+/// Fails with `NOT_FOUND` if it isn't registered.
+pub fn unregister_image(handle: Handle) -> Result<()> {
+    let header = table()?;
+    let slots = slots(header);
+    let slot = slots
+        .iter_mut()
+        .find(|slot| slot.is_some_and(|entry| unsafe { entry.as_ref() }.image_handle == handle))
+        .ok_or(Status::NOT_FOUND)?;
+
+    begin_update(header);
+    let entry = slot.take().unwrap();
+    header.table_size -= 1;
+    end_update(header);
+    unsafe { boot_services().free_pool(entry.as_ptr().cast()) }
+}
+
+/// Adds the running image to the table, unless the firmware already did
+pub fn register_running_image() -> Result<()> {
+    let handle = image_handle().handle();
+    if is_registered(handle)? {
+        return Ok(());
+    }
+    unsafe { register_image(handle, loaded_image()?.as_ptr()) }
+}
+
+fn begin_update(header: &mut DebugImageInfoTableHeader) {
+    let status = ptr::addr_of_mut!(header.update_status);
+    unsafe { status.write_volatile(status.read_volatile() | UPDATE_IN_PROGRESS) };
+}
+
+fn end_update(header: &mut DebugImageInfoTableHeader) {
+    let status = ptr::addr_of_mut!(header.update_status);
+    unsafe {
+        status.write_volatile((status.read_volatile() | TABLE_MODIFIED) & !UPDATE_IN_PROGRESS)
+    };
+}
+
+/// Where an image was loaded, for loading its symbols into a debugger
+#[derive(Clone, Copy, Debug)]
+pub struct ImageLayout {
+    /// Address the image was loaded at
+    pub base:   u64,
+    pub size:   u64,
+    /// Difference between the load address and the address the image was linked at, which
+    /// is what GDB's `add-symbol-file -o` takes
+    pub offset: u64,
+    image:      PeImage<'static>,
+}
+
+impl ImageLayout {
+    /// Returns the layout of the running image
+    pub fn running() -> Result<Self> {
+        let loaded_image = loaded_image()?;
+        let base = loaded_image.image_base as u64;
+        let size = loaded_image.image_size;
+        let data =
+            unsafe { slice::from_raw_parts(loaded_image.image_base.cast::<u8>(), size as usize) };
+        let image = PeImage::parse_loaded(data)?;
+        Ok(Self {
+            base,
+            size,
+            offset: base.wrapping_sub(image.image_base),
+            image,
+        })
+    }
+
+    /// Returns an iterator over the sections' names and load addresses
+    pub fn sections(&self) -> impl Iterator<Item = (&'static str, u64)> + '_ {
+        self.image.sections().map(|section| {
+            let name = str::from_utf8(section.name()).unwrap_or("?");
+            (name, self.base + u64::from(section.virtual_address()))
+        })
+    }
+
+    /// Returns the path of the image's symbols, as recorded by the linker
+    pub fn pdb_path(&self) -> Option<&'static str> {
+        self.image.pdb_path()
+    }
+}
+
+/// Writes the running image's layout and the GDB command loading its symbols to `out`
+///
+/// The command names the `.efi` file next to the image's PDB, which is where rustc puts both,
+/// and relocates all of its sections by the image's [offset](ImageLayout::offset):
+///
+/// ```text
+/// image 0x3e5a8000-0x3e5ea000 (linked at 0x140000000)
+///   .text    0x3e5a9000
+///   .rdata   0x3e5d4000
+///   .data    0x3e5e6000
+///   .reloc   0x3e5e9000
+/// add-symbol-file /src/bolt/target/x86_64-unknown-uefi/debug/deps/bolt-5f0e3c2a.efi -o 0xfffffffefe5a8000
+/// ```
+pub fn report_image(out: &mut impl fmt::Write) -> Result<()> {
+    let layout = ImageLayout::running()?;
+    let fmt_err = |_| Status::DEVICE_ERROR;
+    writeln!(
+        out,
+        "image {:#x}-{:#x} (linked at {:#x})",
+        layout.base,
+        layout.base + layout.size,
+        layout.image.image_base,
+    )
+    .map_err(fmt_err)?;
+    for (name, address) in layout.sections() {
+        writeln!(out, "  {name:<8} {address:#x}").map_err(fmt_err)?;
+    }
+    match layout.pdb_path() {
+        Some(path) => {
+            let stem = path.rsplit_once('.').map_or(path, |(stem, _)| stem);
+            writeln!(out, "add-symbol-file {stem}.efi -o {:#x}", layout.offset)
+        }
+        None => writeln!(out, "add-symbol-file <image> -o {:#x}", layout.offset),
+    }
+    .map_err(fmt_err)
+}
@@ -0,0 +1,136 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! gzip (RFC 1952) decompression
+
+use crate::{
+    crc32,
+    inflate::{inflate, inflate_size},
+    Result, Status,
+};
+
+const MAGIC: [u8; 2] = [0x1f, 0x8b];
+const METHOD_DEFLATE: u8 = 8;
+
+const FHCRC: u8 = 1 << 1;
+const FEXTRA: u8 = 1 << 2;
+const FNAME: u8 = 1 << 3;
+const FCOMMENT: u8 = 1 << 4;
+const FRESERVED: u8 = 0xe0;
+
+/// Returns the size of the member header at the start of `src`
+fn header_size(src: &[u8]) -> Result<usize> {
+    let [m0, m1, method, flags, ..] = *src else {
+        return Err(Status::INVALID_PARAMETER);
+    };
+    if [m0, m1] != MAGIC || method != METHOD_DEFLATE || flags & FRESERVED != 0 {
+        return Err(Status::INVALID_PARAMETER);
+    }
+
+    // Skip the modification time, extra flags and OS.
+    let mut size = 10;
+    if flags & FEXTRA != 0 {
+        let len = src.get(size..size + 2).ok_or(Status::INVALID_PARAMETER)?;
+        size += 2 + u16::from_le_bytes([len[0], len[1]]) as usize;
+    }
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            let rest = src.get(size..).ok_or(Status::INVALID_PARAMETER)?;
+            size += rest
+                .iter()
+                .position(|&b| b == 0)
+                .ok_or(Status::INVALID_PARAMETER)?
+                + 1;
+        }
+    }
+    if flags & FHCRC != 0 {
+        size += 2;
+    }
+    if size > src.len() {
+        return Err(Status::INVALID_PARAMETER);
+    }
+    Ok(size)
+}
+
+/// Returns the decompressed size recorded in the trailers
+///
+/// Each member is decoded, without storing the output, to find its trailer, so padding after
+/// the last member is ignored as [`decompress()`] ignores it. The sizes of concatenated
+/// members are added up. Each is only recorded modulo 2^32.
+pub fn decompressed_size(src: &[u8]) -> Option<usize> {
+    let (mut offset, mut total) = (0, 0usize);
+    loop {
+        let member = &src[offset..];
+        let header_size = header_size(member).ok()?;
+        let (_, consumed) = inflate_size(&member[header_size..]).ok()?;
+
+        let trailer_offset = header_size + consumed;
+        let size = member.get(trailer_offset + 4..trailer_offset + 8)?;
+        total = total.checked_add(u32::from_le_bytes(size.try_into().unwrap()) as usize)?;
+
+        offset += trailer_offset + 8;
+        if !src[offset..].starts_with(&MAGIC) {
+            return Some(total);
+        }
+    }
+}
+
+/// Decompresses a gzip stream into `dst`, verifying each member's CRC and size
+///
+/// Concatenated members are decoded one after another. Data following the last member, such
+/// as padding, is ignored. Returns the number of bytes written.
+pub fn decompress(src: &[u8], dst: &mut [u8]) -> Result<usize> {
+    let (mut offset, mut written) = (0, 0);
+    loop {
+        let member = &src[offset..];
+        let header_size = header_size(member)?;
+        let out = &mut dst[written..];
+        let (len, consumed) = inflate(&member[header_size..], out)?;
+
+        let trailer_offset = header_size + consumed;
+        let trailer = member
+            .get(trailer_offset..trailer_offset + 8)
+            .ok_or(Status::INVALID_PARAMETER)?;
+        let crc = u32::from_le_bytes(trailer[..4].try_into().unwrap());
+        let size = u32::from_le_bytes(trailer[4..].try_into().unwrap());
+        if size != len as u32 {
+            return Err(Status::INVALID_PARAMETER);
+        }
+        if crc != crc32::checksum(&out[..len]) {
+            return Err(Status::CRC_ERROR);
+        }
+
+        offset += trailer_offset + 8;
+        written += len;
+        if !src[offset..].starts_with(&MAGIC) {
+            return Ok(written);
+        }
+    }
+}
@@ -0,0 +1,132 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Decompression of kernels and other payloads
+//!
+//! [`decompress()`] picks a decoder based on the data's magic number. gzip and Zstandard are
+//! decoded in software if the `gzip` and `zstd` features are enabled. Anything else is passed
+//! to the firmware's [Decompress Protocol](crate::proto::decompress), which handles the
+//! EFI 1.1 format.
+//!
+//! All decoders write into a caller-provided buffer; use [`decompressed_size()`] to size it
+//! when the format records the size.
+
+use crate::{
+    boot_services, default_memory_type,
+    proto::decompress::{Decompress, DecompressInfo},
+    Result, Status,
+};
+
+#[cfg(feature = "gzip")]
+pub mod gzip;
+#[cfg(feature = "zstd")]
+pub mod zstd;
+
+/// A compression format known to [`decompress()`]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Format {
+    Gzip,
+    Zstd,
+    /// The EFI 1.1 format, which has no magic number
+    Efi,
+}
+
+impl Format {
+    /// Detects the format of `src` from its magic number
+    pub fn detect(src: &[u8]) -> Self {
+        match src {
+            [0x1f, 0x8b, ..] => Self::Gzip,
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Self::Zstd,
+            _ => Self::Efi,
+        }
+    }
+}
+
+/// Returns the size of the decompressed data, if the format records it
+///
+/// Fails with `UNSUPPORTED` if the decoder for the format isn't enabled, or with `NOT_FOUND`
+/// if the size isn't recorded. For gzip, the size of each member is only known modulo 2^32.
+pub fn decompressed_size(src: &[u8]) -> Result<usize> {
+    match Format::detect(src) {
+        #[cfg(feature = "gzip")]
+        Format::Gzip => gzip::decompressed_size(src).ok_or(Status::INVALID_PARAMETER),
+        #[cfg(feature = "zstd")]
+        Format::Zstd => {
+            let size = zstd::content_size(src).ok_or(Status::NOT_FOUND)?;
+            usize::try_from(size).map_err(|_| Status::BUFFER_TOO_SMALL)
+        }
+        Format::Efi => Ok(efi_info(src)?.destination_size),
+        #[allow(unreachable_patterns)]
+        _ => Err(Status::UNSUPPORTED),
+    }
+}
+
+/// Decompresses `src` into `dst`, returning the number of bytes written
+///
+/// Fails with `UNSUPPORTED` if the decoder for the format isn't enabled, `BUFFER_TOO_SMALL`
+/// if `dst` is too small, `CRC_ERROR` if a checksum doesn't match and `INVALID_PARAMETER`
+/// if the data is malformed.
+pub fn decompress(src: &[u8], dst: &mut [u8]) -> Result<usize> {
+    match Format::detect(src) {
+        #[cfg(feature = "gzip")]
+        Format::Gzip => gzip::decompress(src, dst),
+        #[cfg(feature = "zstd")]
+        Format::Zstd => zstd::decompress(src, dst),
+        Format::Efi => efi_decompress(src, dst),
+        #[allow(unreachable_patterns)]
+        _ => Err(Status::UNSUPPORTED),
+    }
+}
+
+fn efi_info(src: &[u8]) -> Result<DecompressInfo> {
+    boot_services()
+        .first_protocol::<Decompress>()
+        .map_err(|_| Status::UNSUPPORTED)?
+        .get_info(src)
+}
+
+fn efi_decompress(src: &[u8], dst: &mut [u8]) -> Result<usize> {
+    let bs = boot_services();
+    let mut decompress = bs
+        .first_protocol::<Decompress>()
+        .map_err(|_| Status::UNSUPPORTED)?;
+    let info = decompress.get_info(src)?;
+    let dst = dst
+        .get_mut(..info.destination_size)
+        .ok_or(Status::BUFFER_TOO_SMALL)?;
+
+    let scratch_size = info.scratch_size.max(1);
+    let scratch = bs.allocate_pool(default_memory_type(), scratch_size)?;
+    let result = decompress.decompress(src, dst, unsafe {
+        core::slice::from_raw_parts_mut(scratch, scratch_size)
+    });
+    let _ = unsafe { bs.free_pool(scratch) };
+    result.map(|()| info.destination_size)
+}
@@ -0,0 +1,931 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Zstandard (RFC 8878) decompression
+//!
+//! Like the DEFLATE decoder, this favours size over speed and decompresses into a
+//! caller-provided buffer without allocating. Decoded literals are staged at the end of the
+//! output buffer until the block's sequences copy them into place; the block's output only
+//! overwrites them if it doesn't fit anyway. Dictionaries are not supported.
+
+use crate::{Result, Status};
+
+const MAGIC: u32 = 0xfd2fb528;
+/// Magic numbers `0x184d2a50` to `0x184d2a5f` start skippable frames
+const SKIPPABLE_MAGIC: u32 = 0x184d2a50;
+const MAX_BLOCK_SIZE: usize = 128 * 1024;
+
+const MAX_HUFFMAN_BITS: u32 = 11;
+const MAX_FSE_SYMBOLS: usize = 64;
+
+const LL_MAX_LOG: u32 = 9;
+const ML_MAX_LOG: u32 = 9;
+const OF_MAX_LOG: u32 = 8;
+const OF_MAX_CODE: u8 = 31;
+
+const LL_BASE: [u32; 36] = [
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 18, 20, 22, 24, 28, 32, 40, 48, 64,
+    128, 256, 512, 1024, 2048, 4096, 8192, 16384, 32768, 65536,
+];
+const LL_BITS: [u8; 36] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 3, 3, 4, 6, 7, 8, 9, 10, 11,
+    12, 13, 14, 15, 16,
+];
+const ML_BASE: [u32; 53] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27,
+    28, 29, 30, 31, 32, 33, 34, 35, 37, 39, 41, 43, 47, 51, 59, 67, 83, 99, 131, 259, 515, 1027,
+    2051, 4099, 8195, 16387, 32771, 65539,
+];
+const ML_BITS: [u8; 53] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    1, 1, 1, 1, 2, 2, 3, 3, 4, 4, 5, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16,
+];
+
+/// Predefined distributions, used in `Predefined_Mode`
+const LL_DEFAULT: (u32, [i16; 36]) = (6, [
+    4, 3, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 3, 2, 1, 1, 1, 1, 1,
+    -1, -1, -1, -1,
+]);
+const ML_DEFAULT: (u32, [i16; 53]) = (6, [
+    1, 4, 3, 2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1, -1, -1,
+]);
+const OF_DEFAULT: (u32, [i16; 29]) = (5, [
+    1, 1, 1, 1, 1, 1, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1,
+]);
+
+fn read_le(src: &[u8], offset: usize, len: usize) -> Result<u64> {
+    let bytes = src
+        .get(offset..offset + len)
+        .ok_or(Status::INVALID_PARAMETER)?;
+    Ok(bytes
+        .iter()
+        .rev()
+        .fold(0, |value, &byte| value << 8 | byte as u64))
+}
+
+/// Forward bit reader, used for FSE table descriptions
+struct Forward<'a> {
+    src: &'a [u8],
+    bit: usize,
+}
+
+impl Forward<'_> {
+    /// Returns the next `n` (at most 16) bits without consuming them, padding with zeros
+    fn peek(&self, n: u32) -> u32 {
+        let mut value = 0;
+        for i in (0..n as usize).rev() {
+            let bit = self.bit + i;
+            let byte = self.src.get(bit / 8).copied().unwrap_or(0);
+            value = value << 1 | (byte >> (bit % 8)) as u32 & 1;
+        }
+        value
+    }
+
+    fn skip(&mut self, n: u32) -> Result<()> {
+        self.bit += n as usize;
+        if self.bit > self.src.len() * 8 {
+            return Err(Status::INVALID_PARAMETER);
+        }
+        Ok(())
+    }
+
+    fn bits(&mut self, n: u32) -> Result<u32> {
+        let value = self.peek(n);
+        self.skip(n)?;
+        Ok(value)
+    }
+}
+
+/// Backward bit reader, used for Huffman and FSE bitstreams
+///
+/// Streams are read from the end, starting below the highest set bit of the last byte.
+/// Reads past the start of the stream return zeros and leave [`remaining`](Self::remaining)
+/// negative.
+struct Backward<'a> {
+    src:       &'a [u8],
+    remaining: isize,
+}
+
+impl<'a> Backward<'a> {
+    fn new(src: &'a [u8]) -> Result<Self> {
+        let last = *src.last().ok_or(Status::INVALID_PARAMETER)?;
+        if last == 0 {
+            return Err(Status::INVALID_PARAMETER);
+        }
+        let padding = last.leading_zeros() as usize + 1;
+        Ok(Self {
+            src,
+            remaining: (src.len() * 8 - padding) as isize,
+        })
+    }
+
+    /// Returns the next `n` (at most 32) bits without consuming them
+    fn peek(&self, n: u32) -> u64 {
+        let (high, low) = (self.remaining, self.remaining - n as isize);
+        if n == 0 || high <= 0 {
+            return 0;
+        }
+        let start = low.max(0) as usize;
+        let (first, last) = (start / 8, (high as usize).div_ceil(8));
+        let bytes = self.src[first..last]
+            .iter()
+            .rev()
+            .fold(0u64, |value, &byte| value << 8 | byte as u64);
+        let count = high as usize - start;
+        let value = (bytes >> (start % 8)) & ((1 << count) - 1);
+        value << (start as isize - low)
+    }
+
+    fn consume(&mut self, n: u32) {
+        self.remaining -= n as isize;
+    }
+
+    fn bits(&mut self, n: u32) -> u64 {
+        let value = self.peek(n);
+        self.consume(n);
+        value
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct FseEntry {
+    symbol:   u8,
+    bits:     u8,
+    baseline: u16,
+}
+
+struct Fse<const N: usize> {
+    log:   u32,
+    table: [FseEntry; N],
+}
+
+impl<const N: usize> Fse<N> {
+    fn new() -> Self {
+        Self {
+            log:   0,
+            table: [FseEntry::default(); N],
+        }
+    }
+
+    /// Builds a table for a single symbol, used in `RLE_Mode`
+    fn rle(&mut self, symbol: u8) {
+        self.log = 0;
+        self.table[0] = FseEntry {
+            symbol,
+            bits: 0,
+            baseline: 0,
+        };
+    }
+
+    /// Reads a table description, returning the number of bytes consumed
+    fn read(&mut self, src: &[u8], max_log: u32, max_symbol: u8) -> Result<usize> {
+        let mut bits = Forward { src, bit: 0 };
+        let log = bits.bits(4)? + 5;
+        if log > max_log {
+            return Err(Status::INVALID_PARAMETER);
+        }
+
+        let mut probs = [0i16; MAX_FSE_SYMBOLS];
+        let mut remaining = 1i32 << log;
+        let mut symbol = 0;
+        while remaining > 0 {
+            if symbol > max_symbol as usize {
+                return Err(Status::INVALID_PARAMETER);
+            }
+            // Values up to `remaining + 1` are encoded in `n` or `n - 1` bits, with the
+            // shorter encoding for the smallest ones.
+            let n = 32 - (remaining as u32 + 1).leading_zeros();
+            let lower_mask = (1 << (n - 1)) - 1;
+            let threshold = (1 << n) - 1 - (remaining as u32 + 1);
+            let mut value = bits.peek(n);
+            if value & lower_mask < threshold {
+                bits.skip(n - 1)?;
+                value &= lower_mask;
+            } else {
+                bits.skip(n)?;
+                if value > lower_mask {
+                    value -= threshold;
+                }
+            }
+
+            let prob = value as i16 - 1;
+            remaining -= prob.unsigned_abs() as i32;
+            probs[symbol] = prob;
+            symbol += 1;
+
+            if prob == 0 {
+                loop {
+                    let repeat = bits.bits(2)? as usize;
+                    symbol += repeat;
+                    if repeat != 3 {
+                        break;
+                    }
+                }
+            }
+        }
+        if remaining != 0 || symbol > max_symbol as usize + 1 {
+            return Err(Status::INVALID_PARAMETER);
+        }
+
+        self.build(log, &probs[..symbol])?;
+        Ok(bits.bit.div_ceil(8))
+    }
+
+    /// Builds the decoding table for a normalized distribution
+    fn build(&mut self, log: u32, probs: &[i16]) -> Result<()> {
+        let size = 1 << log;
+        if size > N {
+            return Err(Status::INVALID_PARAMETER);
+        }
+        self.log = log;
+
+        // "Less than one" probabilities take a single cell each at the end of the table.
+        let mut next = [0u16; MAX_FSE_SYMBOLS];
+        let mut high = size;
+        for (symbol, &prob) in probs.iter().enumerate() {
+            if prob == -1 {
+                high = high.checked_sub(1).ok_or(Status::INVALID_PARAMETER)?;
+                self.table[high].symbol = symbol as u8;
+                next[symbol] = 1;
+            }
+        }
+
+        let (step, mask) = ((size >> 1) + (size >> 3) + 3, size - 1);
+        let mut pos = 0;
+        for (symbol, &prob) in probs.iter().enumerate() {
+            if prob <= 0 {
+                continue;
+            }
+            next[symbol] = prob as u16;
+            for _ in 0..prob {
+                self.table[pos].symbol = symbol as u8;
+                pos = (pos + step) & mask;
+                while pos >= high {
+                    pos = (pos + step) & mask;
+                }
+            }
+        }
+        if pos != 0 {
+            return Err(Status::INVALID_PARAMETER);
+        }
+
+        for entry in &mut self.table[..size] {
+            let state = next[entry.symbol as usize];
+            next[entry.symbol as usize] += 1;
+            let bits = log - (31 - (state as u32).leading_zeros());
+            entry.bits = bits as u8;
+            entry.baseline = ((state as usize) << bits).wrapping_sub(size) as u16;
+        }
+        Ok(())
+    }
+
+    fn init(&self, bits: &mut Backward) -> usize {
+        bits.bits(self.log) as usize
+    }
+
+    fn symbol(&self, state: usize) -> u8 {
+        self.table[state].symbol
+    }
+
+    fn update(&self, state: &mut usize, bits: &mut Backward) {
+        let entry = self.table[*state];
+        *state = entry.baseline as usize + bits.bits(entry.bits as u32) as usize;
+    }
+}
+
+struct Huffman {
+    max_bits: u32,
+    symbols:  [u8; 1 << MAX_HUFFMAN_BITS],
+    bits:     [u8; 1 << MAX_HUFFMAN_BITS],
+}
+
+impl Huffman {
+    fn new() -> Self {
+        Self {
+            max_bits: 0,
+            symbols:  [0; 1 << MAX_HUFFMAN_BITS],
+            bits:     [0; 1 << MAX_HUFFMAN_BITS],
+        }
+    }
+
+    /// Reads a tree description, returning the number of bytes consumed
+    fn read(&mut self, src: &[u8]) -> Result<usize> {
+        let header = *src.first().ok_or(Status::INVALID_PARAMETER)? as usize;
+        let mut weights = [0u8; 256];
+        let (count, consumed) = if header < 128 {
+            // FSE-compressed weights
+            let data = src.get(1..1 + header).ok_or(Status::INVALID_PARAMETER)?;
+            let mut fse = Fse::<64>::new();
+            let table_size = fse.read(data, 6, 15)?;
+            let mut bits = Backward::new(&data[table_size..])?;
+            let mut states = [fse.init(&mut bits), fse.init(&mut bits)];
+            let mut count = 0;
+            // States alternate until the stream runs out; the other state then holds the
+            // final weight.
+            'decode: loop {
+                for i in 0..2 {
+                    if count >= 254 {
+                        return Err(Status::INVALID_PARAMETER);
+                    }
+                    weights[count] = fse.symbol(states[i]);
+                    count += 1;
+                    fse.update(&mut states[i], &mut bits);
+                    if bits.remaining < 0 {
+                        weights[count] = fse.symbol(states[1 - i]);
+                        count += 1;
+                        break 'decode;
+                    }
+                }
+            }
+            (count, 1 + header)
+        } else {
+            // Direct representation, two weights per byte
+            let count = header - 127;
+            let data = src
+                .get(1..1 + count.div_ceil(2))
+                .ok_or(Status::INVALID_PARAMETER)?;
+            for (i, weight) in weights[..count].iter_mut().enumerate() {
+                *weight = data[i / 2] >> (if i % 2 == 0 { 4 } else { 0 }) & 0xf;
+            }
+            (count, 1 + data.len())
+        };
+        self.build(&mut weights[..count + 1])?;
+        Ok(consumed)
+    }
+
+    /// Builds the decoding table from symbol weights
+    ///
+    /// The last weight is implied by the others and filled in here.
+    fn build(&mut self, weights: &mut [u8]) -> Result<()> {
+        let (last, weights_known) = weights.split_last_mut().unwrap();
+        let mut total = 0u32;
+        for &weight in weights_known.iter() {
+            if weight > MAX_HUFFMAN_BITS as u8 {
+                return Err(Status::INVALID_PARAMETER);
+            }
+            if weight > 0 {
+                total += 1 << (weight - 1);
+            }
+        }
+        if total == 0 {
+            return Err(Status::INVALID_PARAMETER);
+        }
+        let max_bits = 32 - total.leading_zeros();
+        if max_bits > MAX_HUFFMAN_BITS {
+            return Err(Status::INVALID_PARAMETER);
+        }
+        let left = (1 << max_bits) - total;
+        if !left.is_power_of_two() {
+            return Err(Status::INVALID_PARAMETER);
+        }
+        *last = left.trailing_zeros() as u8 + 1;
+        self.max_bits = max_bits;
+
+        // Codes are assigned in order of increasing length, then symbol.
+        let bits_of = |weight: u8| max_bits + 1 - weight as u32;
+        let mut rank_count = [0usize; MAX_HUFFMAN_BITS as usize + 2];
+        for &weight in weights.iter().filter(|&&weight| weight > 0) {
+            rank_count[bits_of(weight) as usize] += 1;
+        }
+        let mut rank_start = [0usize; MAX_HUFFMAN_BITS as usize + 2];
+        for bits in (1..=max_bits as usize).rev() {
+            rank_start[bits - 1] =
+                rank_start[bits] + (rank_count[bits] << (max_bits as usize - bits));
+        }
+        for (symbol, &weight) in weights.iter().enumerate() {
+            if weight == 0 {
+                continue;
+            }
+            let bits = bits_of(weight) as usize;
+            let (start, len) = (rank_start[bits], 1 << (max_bits as usize - bits));
+            self.symbols[start..start + len].fill(symbol as u8);
+            self.bits[start..start + len].fill(bits as u8);
+            rank_start[bits] += len;
+        }
+        Ok(())
+    }
+
+    fn decode_stream(&self, src: &[u8], dst: &mut [u8]) -> Result<()> {
+        let mut bits = Backward::new(src)?;
+        for byte in dst {
+            let index = bits.peek(self.max_bits) as usize;
+            *byte = self.symbols[index];
+            bits.consume(self.bits[index] as u32);
+        }
+        if bits.remaining != 0 {
+            return Err(Status::INVALID_PARAMETER);
+        }
+        Ok(())
+    }
+}
+
+/// Decoding state carried from block to block within a frame
+struct Decoder {
+    huffman:     Option<Huffman>,
+    ll:          Option<Fse<512>>,
+    of:          Option<Fse<256>>,
+    ml:          Option<Fse<512>>,
+    rep_offsets: [usize; 3],
+}
+
+impl Decoder {
+    fn new() -> Self {
+        Self {
+            huffman:     None,
+            ll:          None,
+            of:          None,
+            ml:          None,
+            rep_offsets: [1, 4, 8],
+        }
+    }
+
+    /// Decodes a compressed block to `dst[pos..]`, returning the new position
+    fn block(&mut self, src: &[u8], dst: &mut [u8], pos: usize) -> Result<usize> {
+        let (literals, consumed) = self.literals(src, dst, pos)?;
+        self.sequences(&src[consumed..], dst, pos, literals)
+    }
+
+    /// Decodes the literals section to the end of `dst`
+    ///
+    /// Returns the number of literals and the size of the section.
+    fn literals(&mut self, src: &[u8], dst: &mut [u8], pos: usize) -> Result<(usize, usize)> {
+        let header = *src.first().ok_or(Status::INVALID_PARAMETER)?;
+        let (kind, size_format) = (header & 3, header >> 2 & 3);
+
+        if kind < 2 {
+            let (header_size, len) = match size_format {
+                0 | 2 => (1, header as usize >> 3),
+                1 => (2, read_le(src, 0, 2)? as usize >> 4),
+                _ => (3, read_le(src, 0, 3)? as usize >> 4),
+            };
+            let out = Self::literal_buffer(dst, pos, len)?;
+            if kind == 0 {
+                let data = src
+                    .get(header_size..header_size + len)
+                    .ok_or(Status::INVALID_PARAMETER)?;
+                out.copy_from_slice(data);
+                return Ok((len, header_size + len));
+            }
+            out.fill(*src.get(header_size).ok_or(Status::INVALID_PARAMETER)?);
+            return Ok((len, header_size + 1));
+        }
+
+        let (header_size, field_bits, streams) = match size_format {
+            0 => (3, 10, 1),
+            1 => (3, 10, 4),
+            2 => (4, 14, 4),
+            _ => (5, 18, 4),
+        };
+        let fields = read_le(src, 0, header_size)? >> 4;
+        let mask = (1 << field_bits) - 1;
+        let (len, compressed_len) = (
+            (fields & mask) as usize,
+            (fields >> field_bits & mask) as usize,
+        );
+        let mut data = src
+            .get(header_size..header_size + compressed_len)
+            .ok_or(Status::INVALID_PARAMETER)?;
+
+        if kind == 2 {
+            let huffman = self.huffman.get_or_insert_with(Huffman::new);
+            let tree_size = huffman.read(data)?;
+            data = &data[tree_size..];
+        }
+        let huffman = self.huffman.as_ref().ok_or(Status::INVALID_PARAMETER)?;
+        let out = Self::literal_buffer(dst, pos, len)?;
+
+        if streams == 1 {
+            huffman.decode_stream(data, out)?;
+        } else {
+            let jump = data.get(..6).ok_or(Status::INVALID_PARAMETER)?;
+            let sizes = [0, 2, 4].map(|i| u16::from_le_bytes([jump[i], jump[i + 1]]) as usize);
+            let mut data = &data[6..];
+            let segment = len.div_ceil(4);
+            if segment * 3 > len {
+                return Err(Status::INVALID_PARAMETER);
+            }
+            let (mut out, last_out) = out.split_at_mut(segment * 3);
+            for size in sizes {
+                let (stream, rest) = data
+                    .split_at_checked(size)
+                    .ok_or(Status::INVALID_PARAMETER)?;
+                let (chunk, rest_out) = out.split_at_mut(segment);
+                huffman.decode_stream(stream, chunk)?;
+                (data, out) = (rest, rest_out);
+            }
+            huffman.decode_stream(data, last_out)?;
+        }
+        Ok((len, header_size + compressed_len))
+    }
+
+    /// Returns the space for `len` literals at the end of `dst`
+    fn literal_buffer(dst: &mut [u8], pos: usize, len: usize) -> Result<&mut [u8]> {
+        if len > MAX_BLOCK_SIZE || len > dst.len() - pos {
+            return Err(Status::BUFFER_TOO_SMALL);
+        }
+        let start = dst.len() - len;
+        Ok(&mut dst[start..])
+    }
+
+    /// Decodes and executes the sequences section, returning the new output position
+    fn sequences(
+        &mut self,
+        src: &[u8],
+        dst: &mut [u8],
+        mut pos: usize,
+        literals: usize,
+    ) -> Result<usize> {
+        let mut literal = dst.len() - literals;
+        let byte = |i: usize| src.get(i).copied().ok_or(Status::INVALID_PARAMETER);
+
+        let (count, mut offset) = match byte(0)? as usize {
+            0 => (0, 1),
+            n @ 1..=127 => (n, 1),
+            n @ 128..=254 => ((n - 128) << 8 | byte(1)? as usize, 2),
+            _ => (byte(1)? as usize | (byte(2)? as usize) << 8 | 0x7f00, 3),
+        };
+
+        if count > 0 {
+            let modes = byte(offset)?;
+            offset += 1;
+            if modes & 3 != 0 {
+                return Err(Status::INVALID_PARAMETER);
+            }
+            offset += Self::table(
+                &mut self.ll,
+                modes >> 6,
+                &src[offset..],
+                LL_MAX_LOG,
+                35,
+                &LL_DEFAULT,
+            )?;
+            offset += Self::table(
+                &mut self.of,
+                modes >> 4 & 3,
+                &src[offset..],
+                OF_MAX_LOG,
+                OF_MAX_CODE,
+                &OF_DEFAULT,
+            )?;
+            offset += Self::table(
+                &mut self.ml,
+                modes >> 2 & 3,
+                &src[offset..],
+                ML_MAX_LOG,
+                52,
+                &ML_DEFAULT,
+            )?;
+            let (ll, of, ml) = (
+                self.ll.as_ref().unwrap(),
+                self.of.as_ref().unwrap(),
+                self.ml.as_ref().unwrap(),
+            );
+
+            let mut bits = Backward::new(&src[offset..])?;
+            let mut ll_state = ll.init(&mut bits);
+            let mut of_state = of.init(&mut bits);
+            let mut ml_state = ml.init(&mut bits);
+
+            for i in 0..count {
+                let (ll_code, of_code, ml_code) = (
+                    ll.symbol(ll_state) as usize,
+                    of.symbol(of_state),
+                    ml.symbol(ml_state) as usize,
+                );
+                if ll_code >= LL_BASE.len() || ml_code >= ML_BASE.len() || of_code > OF_MAX_CODE {
+                    return Err(Status::INVALID_PARAMETER);
+                }
+                let offset_value = (1 << of_code) + bits.bits(of_code as u32) as usize;
+                let match_len =
+                    ML_BASE[ml_code] as usize + bits.bits(ML_BITS[ml_code] as u32) as usize;
+                let literal_len =
+                    LL_BASE[ll_code] as usize + bits.bits(LL_BITS[ll_code] as u32) as usize;
+                if i + 1 < count {
+                    ll.update(&mut ll_state, &mut bits);
+                    ml.update(&mut ml_state, &mut bits);
+                    of.update(&mut of_state, &mut bits);
+                }
+
+                let distance = Self::offset(&mut self.rep_offsets, offset_value, literal_len)?;
+
+                if literal_len > dst.len() - literal {
+                    return Err(Status::INVALID_PARAMETER);
+                }
+                if literal_len > dst.len() - pos {
+                    return Err(Status::BUFFER_TOO_SMALL);
+                }
+                dst.copy_within(literal..literal + literal_len, pos);
+                literal += literal_len;
+                pos += literal_len;
+
+                if distance > pos {
+                    return Err(Status::INVALID_PARAMETER);
+                }
+                if match_len > dst.len() - pos {
+                    return Err(Status::BUFFER_TOO_SMALL);
+                }
+                if distance >= match_len {
+                    dst.copy_within(pos - distance..pos - distance + match_len, pos);
+                } else {
+                    // The match overlaps its own output, so copy byte by byte.
+                    for i in pos..pos + match_len {
+                        dst[i] = dst[i - distance];
+                    }
+                }
+                pos += match_len;
+            }
+            if bits.remaining != 0 {
+                return Err(Status::INVALID_PARAMETER);
+            }
+        }
+
+        // The remaining literals follow the last sequence.
+        let rest = dst.len() - literal;
+        if rest > dst.len() - pos {
+            return Err(Status::BUFFER_TOO_SMALL);
+        }
+        dst.copy_within(literal.., pos);
+        Ok(pos + rest)
+    }
+
+    /// Sets up a sequence decoding table, returning the size of its description
+    fn table<const N: usize, const M: usize>(
+        fse: &mut Option<Fse<N>>,
+        mode: u8,
+        src: &[u8],
+        max_log: u32,
+        max_symbol: u8,
+        default: &(u32, [i16; M]),
+    ) -> Result<usize> {
+        match mode {
+            0 => {
+                fse.get_or_insert_with(Fse::new)
+                    .build(default.0, &default.1)?;
+                Ok(0)
+            }
+            1 => {
+                let symbol = *src.first().ok_or(Status::INVALID_PARAMETER)?;
+                if symbol > max_symbol {
+                    return Err(Status::INVALID_PARAMETER);
+                }
+                fse.get_or_insert_with(Fse::new).rle(symbol);
+                Ok(1)
+            }
+            2 => fse
+                .get_or_insert_with(Fse::new)
+                .read(src, max_log, max_symbol),
+            _ => match fse {
+                Some(_) => Ok(0),
+                None => Err(Status::INVALID_PARAMETER),
+            },
+        }
+    }
+
+    /// Resolves an offset value to a match distance, updating the repeat offsets
+    fn offset(reps: &mut [usize; 3], value: usize, literal_len: usize) -> Result<usize> {
+        if value > 3 {
+            *reps = [value - 3, reps[0], reps[1]];
+            return Ok(reps[0]);
+        }
+        // Without literals, the repeat offsets are shifted by one.
+        let index = value - 1 + (literal_len == 0) as usize;
+        let distance = match index {
+            0 => return Ok(reps[0]),
+            3 => reps[0]
+                .checked_sub(1)
+                .filter(|&d| d != 0)
+                .ok_or(Status::INVALID_PARAMETER)?,
+            _ => reps[index],
+        };
+        if index == 1 {
+            *reps = [distance, reps[0], reps[2]];
+        } else {
+            *reps = [distance, reps[0], reps[1]];
+        }
+        Ok(distance)
+    }
+}
+
+/// Parsed frame header
+struct FrameHeader {
+    size:         usize,
+    content_size: Option<u64>,
+    checksum:     bool,
+}
+
+fn frame_header(src: &[u8]) -> Result<FrameHeader> {
+    if read_le(src, 0, 4)? as u32 != MAGIC {
+        return Err(Status::INVALID_PARAMETER);
+    }
+    let descriptor = *src.get(4).ok_or(Status::INVALID_PARAMETER)?;
+    let single_segment = descriptor & 0x20 != 0;
+    if descriptor & 0x08 != 0 {
+        return Err(Status::INVALID_PARAMETER);
+    }
+
+    let mut size = 5 + !single_segment as usize;
+    let dict_id_size = [0, 1, 2, 4][descriptor as usize & 3];
+    if read_le(src, size, dict_id_size)? != 0 {
+        return Err(Status::UNSUPPORTED);
+    }
+    size += dict_id_size;
+
+    let content_size_size = match descriptor >> 6 {
+        0 => single_segment as usize,
+        1 => 2,
+        2 => 4,
+        _ => 8,
+    };
+    let content_size = match content_size_size {
+        0 => None,
+        2 => Some(read_le(src, size, 2)? + 256),
+        n => Some(read_le(src, size, n)?),
+    };
+    size += content_size_size;
+
+    Ok(FrameHeader {
+        size,
+        content_size,
+        checksum: descriptor & 0x04 != 0,
+    })
+}
+
+/// Decodes one frame to `dst`, returning the bytes consumed and written
+fn frame(src: &[u8], dst: &mut [u8]) -> Result<(usize, usize)> {
+    let header = frame_header(src)?;
+    let mut offset = header.size;
+    let mut pos = 0;
+    let mut decoder = Decoder::new();
+    loop {
+        let block_header = read_le(src, offset, 3)? as usize;
+        offset += 3;
+        let (last, kind, size) = (
+            block_header & 1 != 0,
+            block_header >> 1 & 3,
+            block_header >> 3,
+        );
+        if size > MAX_BLOCK_SIZE {
+            return Err(Status::INVALID_PARAMETER);
+        }
+        match kind {
+            // Raw
+            0 => {
+                let data = src
+                    .get(offset..offset + size)
+                    .ok_or(Status::INVALID_PARAMETER)?;
+                dst.get_mut(pos..pos + size)
+                    .ok_or(Status::BUFFER_TOO_SMALL)?
+                    .copy_from_slice(data);
+                offset += size;
+                pos += size;
+            }
+            // RLE, where the size is that of the output
+            1 => {
+                let byte = *src.get(offset).ok_or(Status::INVALID_PARAMETER)?;
+                dst.get_mut(pos..pos + size)
+                    .ok_or(Status::BUFFER_TOO_SMALL)?
+                    .fill(byte);
+                offset += 1;
+                pos += size;
+            }
+            2 => {
+                let data = src
+                    .get(offset..offset + size)
+                    .ok_or(Status::INVALID_PARAMETER)?;
+                pos = decoder.block(data, dst, pos)?;
+                offset += size;
+            }
+            _ => return Err(Status::INVALID_PARAMETER),
+        }
+        if last {
+            break;
+        }
+    }
+
+    if header.content_size.is_some_and(|size| size != pos as u64) {
+        return Err(Status::INVALID_PARAMETER);
+    }
+    if header.checksum {
+        let checksum = read_le(src, offset, 4)? as u32;
+        if checksum != xxh64(&dst[..pos]) as u32 {
+            return Err(Status::CRC_ERROR);
+        }
+        offset += 4;
+    }
+    Ok((offset, pos))
+}
+
+/// Returns the decompressed size recorded in the first frame's header, if any
+pub fn content_size(src: &[u8]) -> Option<u64> {
+    frame_header(src).ok()?.content_size
+}
+
+/// Decompresses a Zstandard stream into `dst`, verifying frame checksums
+///
+/// Concatenated frames are decoded one after another, and skippable frames are ignored.
+/// Returns the number of bytes written.
+pub fn decompress(src: &[u8], dst: &mut [u8]) -> Result<usize> {
+    let (mut offset, mut written) = (0, 0);
+    while offset < src.len() {
+        let magic = read_le(src, offset, 4)? as u32;
+        if magic & !0xf == SKIPPABLE_MAGIC {
+            offset += 8 + read_le(src, offset + 4, 4)? as usize;
+            continue;
+        }
+        let (consumed, len) = frame(&src[offset..], &mut dst[written..])?;
+        offset += consumed;
+        written += len;
+    }
+    if offset > src.len() {
+        return Err(Status::INVALID_PARAMETER);
+    }
+    Ok(written)
+}
+
+/// XXH64 with a seed of zero, whose low 32 bits are the frame checksum
+fn xxh64(data: &[u8]) -> u64 {
+    const P1: u64 = 0x9e3779b185ebca87;
+    const P2: u64 = 0xc2b2ae3d27d4eb4f;
+    const P3: u64 = 0x165667b19e3779f9;
+    const P4: u64 = 0x85ebca77c2b2ae63;
+    const P5: u64 = 0x27d4eb2f165667c5;
+
+    fn round(acc: u64, lane: u64) -> u64 {
+        acc.wrapping_add(lane.wrapping_mul(P2))
+            .rotate_left(31)
+            .wrapping_mul(P1)
+    }
+    fn merge(hash: u64, acc: u64) -> u64 {
+        (hash ^ round(0, acc)).wrapping_mul(P1).wrapping_add(P4)
+    }
+    let lane = |bytes: &[u8]| u64::from_le_bytes(bytes.try_into().unwrap());
+
+    let mut stripes = data.chunks_exact(32);
+    let mut hash = if data.len() >= 32 {
+        let mut acc = [P1.wrapping_add(P2), P2, 0, P1.wrapping_neg()];
+        for stripe in &mut stripes {
+            for (acc, lane_bytes) in acc.iter_mut().zip(stripe.chunks_exact(8)) {
+                *acc = round(*acc, lane(lane_bytes));
+            }
+        }
+        let hash = acc[0]
+            .rotate_left(1)
+            .wrapping_add(acc[1].rotate_left(7))
+            .wrapping_add(acc[2].rotate_left(12))
+            .wrapping_add(acc[3].rotate_left(18));
+        acc.iter().fold(hash, |hash, &acc| merge(hash, acc))
+    } else {
+        P5
+    };
+    hash = hash.wrapping_add(data.len() as u64);
+
+    let mut rest = stripes.remainder();
+    while let Some((bytes, tail)) = rest.split_first_chunk::<8>() {
+        hash ^= round(0, u64::from_le_bytes(*bytes));
+        hash = hash.rotate_left(27).wrapping_mul(P1).wrapping_add(P4);
+        rest = tail;
+    }
+    if let Some((bytes, tail)) = rest.split_first_chunk::<4>() {
+        hash ^= (u32::from_le_bytes(*bytes) as u64).wrapping_mul(P1);
+        hash = hash.rotate_left(23).wrapping_mul(P2).wrapping_add(P3);
+        rest = tail;
+    }
+    for &byte in rest {
+        hash ^= (byte as u64).wrapping_mul(P5);
+        hash = hash.rotate_left(11).wrapping_mul(P1);
+    }
+
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(P2);
+    hash ^= hash >> 29;
+    hash = hash.wrapping_mul(P3);
+    hash ^ hash >> 32
+}
@@ -0,0 +1,132 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Buffers for device DMA
+//!
+//! # Cache coherency
+//!
+//! UEFI identity-maps memory, so a [`DmaBuffer`]'s device address is simply its physical
+//! address. On x86 DMA is cache-coherent and nothing more is needed. On AArch64 and RISC-V
+//! firmware usually maps DMA-capable memory as uncached or relies on coherent interconnects,
+//! but neither is guaranteed: a driver for a non-coherent device must clean the data cache
+//! before the device reads the buffer and invalidate it before the CPU reads what the device
+//! wrote. Devices behind an IOMMU, or buses which translate addresses, need a mapping from the
+//! bus's own protocol (e.g. PCI I/O's `Map()`) instead.
+//!
+//! Either way, use volatile or atomic accesses (or a compiler fence) around handing the buffer
+//! to the device, so the compiler doesn't reorder or elide the accesses.
+
+use core::slice;
+
+use crate::{
+    boot_services, boot_services_active, default_memory_type, table::AllocPagesType, PhysicalAddr,
+    Result, Status, PAGE_SIZE,
+};
+
+/// Address limit of devices which can only generate 32-bit addresses
+pub const DMA_32: PhysicalAddr = 1 << 32;
+
+/// Page-aligned, zeroed buffer below a physical address limit
+///
+/// The pages are of the [default memory type](crate::default_memory_type) and are freed when
+/// the buffer is dropped. See the [module documentation](self) for cache coherency.
+pub struct DmaBuffer {
+    addr:  PhysicalAddr,
+    len:   usize,
+    pages: usize,
+}
+
+impl DmaBuffer {
+    /// Allocates a buffer of `len` bytes which is reachable by 32-bit devices
+    pub fn new(len: usize) -> Result<Self> {
+        Self::below(len, DMA_32)
+    }
+
+    /// Allocates a buffer of `len` bytes which ends at or below `limit`
+    ///
+    /// `limit` is the first address the device cannot reach. Fails with `OUT_OF_RESOURCES`
+    /// if no memory below it is free.
+    pub fn below(len: usize, limit: PhysicalAddr) -> Result<Self> {
+        let pages = len.div_ceil(PAGE_SIZE).max(1);
+        let max = limit.checked_sub(1).ok_or(Status::INVALID_PARAMETER)?;
+        let addr = boot_services().allocate_pages(
+            AllocPagesType::Max(max),
+            default_memory_type(),
+            pages,
+        )?;
+        unsafe { (addr as *mut u8).write_bytes(0, pages * PAGE_SIZE) };
+        Ok(Self { addr, len, pages })
+    }
+
+    /// Returns the address the device should be given
+    pub fn device_addr(&self) -> PhysicalAddr {
+        self.addr
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn as_ptr(&self) -> *const u8 {
+        self.addr as *const u8
+    }
+
+    pub fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.addr as *mut u8
+    }
+
+    /// Returns the buffer's contents
+    ///
+    /// The device must not be writing to the buffer while the slice is alive.
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.as_ptr(), self.len) }
+    }
+
+    /// Returns the buffer's contents
+    ///
+    /// The device must not be accessing the buffer while the slice is alive.
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.as_mut_ptr(), self.len) }
+    }
+}
+
+impl Drop for DmaBuffer {
+    fn drop(&mut self) {
+        if boot_services_active() {
+            unsafe {
+                let _ = boot_services().free_pages(self.addr, self.pages);
+            }
+        }
+    }
+}
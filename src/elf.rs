@@ -0,0 +1,426 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! ELF64 kernel loading
+//!
+//! [`ElfFile::load()`] does what every bolt-os style loader needs to do with a kernel image:
+//! allocate pages for its `PT_LOAD` segments, copy them in, zero the BSS, and (for
+//! position-independent kernels) apply the `RELATIVE` relocations from `.rela.dyn`.
+//!
+//! Executables (`ET_EXEC`) are loaded at the physical addresses in their program headers.
+//! Shared objects (`ET_DYN`) are loaded wherever the firmware has room for them.
+
+use core::{mem::size_of, ptr};
+
+use crate::{
+    boot_services, default_memory_type, le,
+    table::{AllocPagesType, MemoryDescriptor, MemoryType},
+    PhysicalAddr, Result, Status,
+};
+
+/// Maximum number of `PT_LOAD` segments [`ElfFile::load()`] supports
+pub const MAX_SEGMENTS: usize = 16;
+
+pub const ET_EXEC: u16 = 2;
+pub const ET_DYN: u16 = 3;
+
+pub const EM_X86_64: u16 = 62;
+pub const EM_AARCH64: u16 = 183;
+pub const EM_RISCV: u16 = 243;
+pub const EM_LOONGARCH: u16 = 258;
+
+/// The ELF machine type of the running firmware
+pub const EM_NATIVE: u16 = if cfg!(target_arch = "x86_64") {
+    EM_X86_64
+} else if cfg!(target_arch = "aarch64") {
+    EM_AARCH64
+} else if cfg!(target_arch = "riscv64") {
+    EM_RISCV
+} else if cfg!(target_arch = "loongarch64") {
+    EM_LOONGARCH
+} else {
+    0
+};
+
+pub const PT_NULL: u32 = 0;
+pub const PT_LOAD: u32 = 1;
+pub const PT_DYNAMIC: u32 = 2;
+pub const PT_INTERP: u32 = 3;
+pub const PT_NOTE: u32 = 4;
+pub const PT_PHDR: u32 = 6;
+pub const PT_TLS: u32 = 7;
+
+pub const PF_X: u32 = 1;
+pub const PF_W: u32 = 2;
+pub const PF_R: u32 = 4;
+
+const DT_NULL: u64 = 0;
+const DT_RELA: u64 = 7;
+const DT_RELASZ: u64 = 8;
+const DT_RELAENT: u64 = 9;
+
+const EHDR_SIZE: usize = 64;
+const PHDR_SIZE: usize = 56;
+const RELA_SIZE: usize = 24;
+
+/// `R_<arch>_RELATIVE` for the running firmware's architecture
+const R_RELATIVE: u32 = match EM_NATIVE {
+    EM_X86_64 => 8,
+    EM_AARCH64 => 1027,
+    EM_RISCV | EM_LOONGARCH => 3,
+    _ => u32::MAX,
+};
+
+/// A parsed ELF64 file
+#[derive(Clone, Copy, Debug)]
+pub struct ElfFile<'a> {
+    data:        &'a [u8],
+    pub kind:    u16,
+    pub machine: u16,
+    /// Entry point, as linked
+    pub entry:   u64,
+    phoff:       usize,
+    phnum:       usize,
+}
+
+/// An ELF64 program header
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ProgramHeader {
+    pub kind:   u32,
+    pub flags:  u32,
+    pub offset: u64,
+    pub vaddr:  u64,
+    pub paddr:  u64,
+    pub filesz: u64,
+    pub memsz:  u64,
+    pub align:  u64,
+}
+
+impl<'a> ElfFile<'a> {
+    /// Parses the ELF header and bounds-checks the program header table
+    ///
+    /// Fails with `LOAD_ERROR` if `data` is not a little-endian ELF64 file, or with
+    /// `UNSUPPORTED` if it is not an executable or shared object for the running architecture.
+    pub fn parse(data: &'a [u8]) -> Result<Self> {
+        let ident = data.get(..16).ok_or(Status::LOAD_ERROR)?;
+        // ELFCLASS64, ELFDATA2LSB, EV_CURRENT
+        if ident[..4] != *b"\x7fELF" || ident[4] != 2 || ident[5] != 1 || ident[6] != 1 {
+            return Err(Status::LOAD_ERROR);
+        }
+        let u16_at = |at| le::read_u16(data, at).ok_or(Status::LOAD_ERROR);
+        let u64_at = |at| le::read_u64(data, at).ok_or(Status::LOAD_ERROR);
+        let kind = u16_at(16)?;
+        let machine = u16_at(18)?;
+        let entry = u64_at(24)?;
+        let phoff = u64_at(32)? as usize;
+        let phentsize = u16_at(54)? as usize;
+        let phnum = u16_at(56)? as usize;
+
+        if phentsize != PHDR_SIZE
+            || phoff < EHDR_SIZE
+            || phoff
+                .checked_add(phnum * PHDR_SIZE)
+                .is_none_or(|end| end > data.len())
+        {
+            return Err(Status::LOAD_ERROR);
+        }
+        if (kind != ET_EXEC && kind != ET_DYN) || machine != EM_NATIVE {
+            return Err(Status::UNSUPPORTED);
+        }
+
+        Ok(Self {
+            data,
+            kind,
+            machine,
+            entry,
+            phoff,
+            phnum,
+        })
+    }
+
+    /// Returns an iterator over the program headers
+    pub fn program_headers(&self) -> impl Iterator<Item = ProgramHeader> + 'a {
+        let (data, phoff) = (self.data, self.phoff);
+        (0..self.phnum).map(move |i| {
+            let offset = phoff + i * PHDR_SIZE;
+            // The table was bounds-checked by `parse()`.
+            let u32_at = |at| le::read_u32(data, offset + at).unwrap_or_default();
+            let u64_at = |at| le::read_u64(data, offset + at).unwrap_or_default();
+            ProgramHeader {
+                kind:   u32_at(0),
+                flags:  u32_at(4),
+                offset: u64_at(8),
+                vaddr:  u64_at(16),
+                paddr:  u64_at(24),
+                filesz: u64_at(32),
+                memsz:  u64_at(40),
+                align:  u64_at(48),
+            }
+        })
+    }
+
+    /// Loads the `PT_LOAD` segments into newly allocated pages and returns the entry point
+    ///
+    /// Executable segments are allocated as `LOADER_CODE`, everything else as `LOADER_DATA`.
+    /// On failure, any pages already allocated are freed again.
+    pub fn load(&self) -> Result<LoadedElf> {
+        self.load_as(MemoryType::LOADER_CODE, MemoryType::LOADER_DATA)
+    }
+
+    /// Like [`load()`](Self::load), but allocates executable segments as `code` and everything
+    /// else as `data`
+    ///
+    /// Passing an OS memory type (see [`MemoryType::os()`]) keeps the kernel identifiable in
+    /// the memory map after boot. Fails with `INVALID_PARAMETER` if either type can't be
+    /// allocated.
+    pub fn load_as(&self, code: MemoryType, data: MemoryType) -> Result<LoadedElf> {
+        if !code.is_allocatable() || !data.is_allocatable() {
+            return Err(Status::INVALID_PARAMETER);
+        }
+        let mut segments = [ProgramHeader::default(); MAX_SEGMENTS];
+        let mut count = 0;
+        for ph in self
+            .program_headers()
+            .filter(|ph| ph.kind == PT_LOAD && ph.memsz > 0)
+        {
+            let in_file = ph.offset.checked_add(ph.filesz);
+            if ph.filesz > ph.memsz || in_file.is_none_or(|end| end > self.data.len() as u64) {
+                return Err(Status::LOAD_ERROR);
+            }
+            *segments.get_mut(count).ok_or(Status::OUT_OF_RESOURCES)? = ph;
+            count += 1;
+        }
+        let segments = &mut segments[..count];
+        if segments.is_empty() {
+            return Err(Status::LOAD_ERROR);
+        }
+        // Loading relies on ascending addresses; overlapping segments are malformed.
+        segments.sort_unstable_by_key(|ph| self.placement(ph));
+        for pair in segments.windows(2) {
+            let end = self.placement(&pair[0]).checked_add(pair[0].memsz);
+            if end.is_none_or(|end| self.placement(&pair[1]) < end) {
+                return Err(Status::LOAD_ERROR);
+            }
+        }
+        let segments = &*segments;
+
+        let bias = match self.kind {
+            ET_DYN => self.reserve(segments)?,
+            _ => 0,
+        };
+        let mut loaded = LoadedElf {
+            bias,
+            entry: self.entry.wrapping_add(bias),
+            allocations: [(0, 0); MAX_SEGMENTS],
+            count: 0,
+        };
+        match self.load_segments(segments, (code, data), &mut loaded) {
+            Ok(()) => Ok(loaded),
+            Err(status) => {
+                let _ = unsafe { loaded.free() };
+                Err(status)
+            }
+        }
+    }
+
+    /// Where a segment is placed in memory before the bias is applied
+    fn placement(&self, ph: &ProgramHeader) -> u64 {
+        match self.kind {
+            ET_DYN => ph.vaddr,
+            _ => ph.paddr,
+        }
+    }
+
+    /// Finds a free range large enough for a position-independent image and returns the bias
+    ///
+    /// The range is freed again so that the segments can be allocated individually with the
+    /// right memory types; boot services are single-threaded, so nothing else can claim it.
+    fn reserve(&self, segments: &[ProgramHeader]) -> Result<u64> {
+        let low = segments.iter().map(|ph| ph.vaddr).min().unwrap_or(0)
+            & !(MemoryDescriptor::PAGE_SIZE - 1);
+        let high = segments
+            .iter()
+            .map(|ph| ph.vaddr.saturating_add(ph.memsz))
+            .max()
+            .unwrap_or(0);
+        let pages = high
+            .saturating_sub(low)
+            .div_ceil(MemoryDescriptor::PAGE_SIZE) as usize;
+        let bs = boot_services();
+        let base = bs.allocate_pages(AllocPagesType::Any, default_memory_type(), pages)?;
+        unsafe { bs.free_pages(base, pages)? };
+        Ok(base.wrapping_sub(low))
+    }
+
+    fn load_segments(
+        &self,
+        segments: &[ProgramHeader],
+        (code, data): (MemoryType, MemoryType),
+        loaded: &mut LoadedElf,
+    ) -> Result<()> {
+        let bs = boot_services();
+        let mut allocated_to = 0;
+        for ph in segments {
+            let start = self.placement(ph).wrapping_add(loaded.bias);
+            let end = start.checked_add(ph.memsz).ok_or(Status::LOAD_ERROR)?;
+
+            // Segments may share a page; it keeps the memory type of the first one.
+            let first_page = (start & !(MemoryDescriptor::PAGE_SIZE - 1)).max(allocated_to);
+            let last_page = end.div_ceil(MemoryDescriptor::PAGE_SIZE) * MemoryDescriptor::PAGE_SIZE;
+            if first_page < last_page {
+                let pages = ((last_page - first_page) / MemoryDescriptor::PAGE_SIZE) as usize;
+                let memory_type = match ph.flags & PF_X {
+                    0 => data,
+                    _ => code,
+                };
+                let addr =
+                    bs.allocate_pages(AllocPagesType::Addr(first_page), memory_type, pages)?;
+                loaded.allocations[loaded.count] = (addr, pages);
+                loaded.count += 1;
+                unsafe {
+                    ptr::write_bytes(
+                        addr as *mut u8,
+                        0,
+                        pages * MemoryDescriptor::PAGE_SIZE as usize,
+                    )
+                };
+                allocated_to = last_page;
+            }
+
+            let file = &self.data[ph.offset as usize..][..ph.filesz as usize];
+            unsafe { ptr::copy_nonoverlapping(file.as_ptr(), start as *mut u8, file.len()) };
+        }
+
+        if self.kind == ET_DYN {
+            self.relocate(segments, loaded.bias)?;
+        }
+        Ok(())
+    }
+
+    /// Applies the `RELATIVE` relocations of a position-independent image
+    ///
+    /// Any other relocation type fails with `UNSUPPORTED`; a kernel linked with `-pie` and
+    /// `-z notext` should not need them.
+    fn relocate(&self, segments: &[ProgramHeader], bias: u64) -> Result<()> {
+        let Some(dynamic) = self.program_headers().find(|ph| ph.kind == PT_DYNAMIC) else {
+            return Ok(());
+        };
+        let (mut rela, mut rela_size, mut rela_ent) = (None, 0, RELA_SIZE as u64);
+        let entries = self
+            .data
+            .get(dynamic.offset as usize..)
+            .and_then(|d| d.get(..dynamic.filesz as usize))
+            .ok_or(Status::LOAD_ERROR)?;
+        for entry in entries.chunks_exact(16) {
+            let u64_at = |at| le::read_u64(entry, at).ok_or(Status::LOAD_ERROR);
+            let (tag, value) = (u64_at(0)?, u64_at(8)?);
+            match tag {
+                DT_NULL => break,
+                DT_RELA => rela = Some(value),
+                DT_RELASZ => rela_size = value,
+                DT_RELAENT => rela_ent = value,
+                _ => {}
+            }
+        }
+        let Some(rela) = rela else {
+            return Ok(());
+        };
+        if rela_ent != RELA_SIZE as u64 {
+            return Err(Status::LOAD_ERROR);
+        }
+
+        // The table is part of a loaded segment; read it from the file.
+        let offset = segments
+            .iter()
+            .find(|ph| {
+                ph.vaddr
+                    .checked_add(ph.filesz)
+                    .is_some_and(|end| (ph.vaddr..end).contains(&rela))
+            })
+            .map(|ph| (ph.offset + (rela - ph.vaddr)) as usize)
+            .ok_or(Status::LOAD_ERROR)?;
+        let table = self
+            .data
+            .get(offset..)
+            .and_then(|d| d.get(..rela_size as usize))
+            .ok_or(Status::LOAD_ERROR)?;
+
+        for entry in table.chunks_exact(RELA_SIZE) {
+            let u64_at = |at| le::read_u64(entry, at).ok_or(Status::LOAD_ERROR);
+            let (target, info, addend) = (u64_at(0)?, u64_at(8)?, u64_at(16)?);
+            match info as u32 {
+                0 => continue,
+                R_RELATIVE => {}
+                _ => return Err(Status::UNSUPPORTED),
+            }
+            let in_image = segments.iter().any(|ph| {
+                let end = ph.vaddr.checked_add(ph.memsz);
+                target >= ph.vaddr
+                    && end.is_some_and(|end| target.saturating_add(size_of::<u64>() as u64) <= end)
+            });
+            if !in_image {
+                return Err(Status::LOAD_ERROR);
+            }
+            let value = bias.wrapping_add(addend);
+            unsafe { ptr::write_unaligned(bias.wrapping_add(target) as *mut u64, value) };
+        }
+        Ok(())
+    }
+}
+
+/// An image loaded by [`ElfFile::load()`]
+#[derive(Debug)]
+pub struct LoadedElf {
+    /// Difference between the load address and the link address (zero for `ET_EXEC`)
+    pub bias:    u64,
+    /// The relocated entry point
+    pub entry:   u64,
+    allocations: [(PhysicalAddr, usize); MAX_SEGMENTS],
+    count:       usize,
+}
+
+impl LoadedElf {
+    /// Returns the page ranges allocated for the image, as `(address, pages)`
+    pub fn allocations(&self) -> &[(PhysicalAddr, usize)] {
+        &self.allocations[..self.count]
+    }
+
+    /// Frees the pages allocated for the image
+    ///
+    /// # Safety
+    ///
+    /// Nothing may still be using the loaded image.
+    pub unsafe fn free(self) -> Result<()> {
+        let bs = boot_services();
+        self.allocations()
+            .iter()
+            .try_for_each(|&(addr, pages)| bs.free_pages(addr, pages))
+    }
+}
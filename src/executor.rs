@@ -0,0 +1,391 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Single-threaded async executor
+//!
+//! [`block_on()`] polls a future until it completes. While the future is pending, the CPU is
+//! parked in `WaitForEvent()` on the events it is waiting for, so a loader can keep several
+//! device requests in flight (combined with [`join()`]) and decompress data as it arrives.
+//!
+//! Futures wait for events through [`poll_event()`]. [`Sleep`] wraps a timer event and
+//! [`Completion`] drives a request made with a completion token, such as those of the
+//! Block I/O 2, Disk I/O 2 and HTTP protocols.
+//!
+//! Futures which wake themselves instead (see [`yield_now()`]) are polled again right away.
+//! A future which is pending without waiting on an event or waking itself is polled in a loop.
+
+use core::{
+    future::Future,
+    marker::PhantomPinned,
+    pin::{pin, Pin},
+    ptr,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
+    time::Duration,
+};
+
+use crate::{
+    boot_services, boot_services_active,
+    sync::TryLock,
+    table::{EventType, TimerDelay},
+    Event, Result, Status, Tpl,
+};
+
+/// The most events [`block_on()`] waits on at once
+///
+/// If more are pending, the future is polled in a loop instead.
+pub const MAX_EVENTS: usize = 16;
+
+struct WaitSet {
+    events:   [Event; MAX_EVENTS],
+    len:      usize,
+    overflow: bool,
+    /// The event which ended the last wait
+    ///
+    /// `WaitForEvent()` clears the signaled state, so it is recorded here for
+    /// [`poll_event()`].
+    signaled: Option<Event>,
+}
+
+// Events are only used on the firmware's single thread.
+unsafe impl Send for WaitSet {}
+
+static WAIT_SET: TryLock<WaitSet> = TryLock::new(WaitSet {
+    events:   [Event(ptr::null_mut()); MAX_EVENTS],
+    len:      0,
+    overflow: false,
+    signaled: None,
+});
+static RUNNING: AtomicBool = AtomicBool::new(false);
+static WOKEN: AtomicBool = AtomicBool::new(false);
+
+static WAKER_VTABLE: RawWakerVTable =
+    RawWakerVTable::new(|_| raw_waker(), |_| wake(), |_| wake(), |_| {});
+
+fn raw_waker() -> RawWaker {
+    RawWaker::new(ptr::null(), &WAKER_VTABLE)
+}
+
+fn wake() {
+    WOKEN.store(true, Ordering::Relaxed);
+}
+
+/// Runs `future` to completion
+///
+/// Fails with `ALREADY_STARTED` if called from a future polled by `block_on()`, or with the
+/// status of `WaitForEvent()`, which requires `TPL_APPLICATION`.
+pub fn block_on<F: Future>(future: F) -> Result<F::Output> {
+    if RUNNING.swap(true, Ordering::Acquire) {
+        return Err(Status::ALREADY_STARTED);
+    }
+    let result = run(pin!(future));
+    WAIT_SET.try_with(|set| {
+        set.len = 0;
+        set.signaled = None;
+    });
+    RUNNING.store(false, Ordering::Release);
+    result
+}
+
+fn run<F: Future>(mut future: Pin<&mut F>) -> Result<F::Output> {
+    let waker = unsafe { Waker::from_raw(raw_waker()) };
+    let mut cx = Context::from_waker(&waker);
+    let mut events = [Event(ptr::null_mut()); MAX_EVENTS];
+    loop {
+        WOKEN.store(false, Ordering::Relaxed);
+        WAIT_SET.try_with(|set| {
+            set.len = 0;
+            set.overflow = false;
+        });
+
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return Ok(output);
+        }
+
+        let len = WAIT_SET
+            .try_with(|set| {
+                set.signaled = None;
+                if set.overflow {
+                    return 0;
+                }
+                events[..set.len].copy_from_slice(&set.events[..set.len]);
+                set.len
+            })
+            .unwrap_or(0);
+        if len == 0 || WOKEN.load(Ordering::Relaxed) {
+            continue;
+        }
+
+        let index = boot_services().wait_for_event(&events[..len])?;
+        WAIT_SET.try_with(|set| set.signaled = Some(events[index]));
+    }
+}
+
+/// Takes the signal recorded for `event` by the executor, or checks the event itself
+fn take_signal(event: Event) -> Result<bool> {
+    let recorded = WAIT_SET
+        .try_with(|set| {
+            set.signaled
+                .take_if(|signaled| *signaled == event)
+                .is_some()
+        })
+        .unwrap_or(false);
+    if recorded {
+        return Ok(true);
+    }
+    boot_services().check_event(event)
+}
+
+/// Returns whether `event` is signaled, or has [`block_on()`] wait for it if not
+///
+/// The signaled state is reset, as with `CheckEvent()`. Events with a notification function
+/// of type `EVT_NOTIFY_SIGNAL` cannot be waited on.
+pub fn poll_event(cx: &mut Context<'_>, event: Event) -> Poll<Result<()>> {
+    match take_signal(event) {
+        Ok(true) => return Poll::Ready(Ok(())),
+        Ok(false) => {}
+        Err(status) => return Poll::Ready(Err(status)),
+    }
+    let registered = WAIT_SET.try_with(|set| {
+        if set.events[..set.len].contains(&event) {
+            return;
+        }
+        match set.events.get_mut(set.len) {
+            Some(slot) => {
+                *slot = event;
+                set.len += 1;
+            }
+            None => set.overflow = true,
+        }
+    });
+    // Fall back to polling in a loop.
+    if registered.is_none() {
+        cx.waker().wake_by_ref();
+    }
+    Poll::Pending
+}
+
+/// Yields to the executor once
+///
+/// Long-running computations can call this between steps to let [`block_on()`] check on
+/// other futures without parking.
+pub async fn yield_now() {
+    let mut yielded = false;
+    core::future::poll_fn(|cx| {
+        if yielded {
+            return Poll::Ready(());
+        }
+        yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    })
+    .await
+}
+
+/// Polls two futures concurrently, returning both outputs once both are done
+pub async fn join<A: Future, B: Future>(a: A, b: B) -> (A::Output, B::Output) {
+    let (mut a, mut b) = (pin!(a), pin!(b));
+    let (mut out_a, mut out_b) = (None, None);
+    core::future::poll_fn(|cx| {
+        if out_a.is_none() {
+            if let Poll::Ready(out) = a.as_mut().poll(cx) {
+                out_a = Some(out);
+            }
+        }
+        if out_b.is_none() {
+            if let Poll::Ready(out) = b.as_mut().poll(cx) {
+                out_b = Some(out);
+            }
+        }
+        match (out_a.is_some(), out_b.is_some()) {
+            (true, true) => Poll::Ready((out_a.take().unwrap(), out_b.take().unwrap())),
+            _ => Poll::Pending,
+        }
+    })
+    .await
+}
+
+/// Timer-backed future which completes after a delay
+///
+/// The timer is cancelled when this is dropped.
+#[derive(Debug)]
+pub struct Sleep {
+    event: Event,
+}
+
+impl Sleep {
+    pub fn new(duration: Duration) -> Result<Self> {
+        let bs = boot_services();
+        let event = bs.create_timer_event()?;
+        // `SetTimer()` takes units of 100ns.
+        let ticks = u64::try_from(duration.as_nanos().div_ceil(100)).unwrap_or(u64::MAX);
+        if let Err(status) = bs.set_timer(event, TimerDelay::Relative, ticks) {
+            let _ = bs.close_event(event);
+            return Err(status);
+        }
+        Ok(Self { event })
+    }
+}
+
+impl Future for Sleep {
+    type Output = Result<()>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        poll_event(cx, self.event)
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if boot_services_active() {
+            let _ = boot_services().close_event(self.event);
+        }
+    }
+}
+
+/// Returns a future which completes after `duration`
+pub fn sleep(duration: Duration) -> Result<Sleep> {
+    Sleep::new(duration)
+}
+
+/// Completion token of an asynchronous protocol request
+///
+/// # Safety
+///
+/// The type must be a `#[repr(C)]` token which starts with the `EFI_EVENT` signaled on
+/// completion, followed by the `EFI_STATUS` of the request.
+pub unsafe trait Token {
+    /// Creates a token which signals `event` on completion
+    fn new(event: Event) -> Self;
+
+    fn event(&self) -> Event;
+
+    fn status(&self) -> Status;
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum CompletionState {
+    Idle,
+    Pending,
+    Done,
+}
+
+/// A request made with a completion [`Token`]
+///
+/// The completion owns the token and the event it signals. [`submit()`](Self::submit) the
+/// request once the completion is pinned, then await it for the request's status. If it is
+/// dropped while the request is still in flight, dropping blocks until the request is done,
+/// since the firmware still writes to the token and the request's buffers.
+#[derive(Debug)]
+pub struct Completion<T: Token> {
+    token:   T,
+    state:   CompletionState,
+    _pinned: PhantomPinned,
+}
+
+impl<T: Token> Completion<T> {
+    pub fn new() -> Result<Self> {
+        let event = unsafe {
+            boot_services().create_event(
+                EventType::empty(),
+                Tpl::CALLBACK,
+                None,
+                ptr::null_mut(),
+            )?
+        };
+        Ok(Self {
+            token:   T::new(event),
+            state:   CompletionState::Idle,
+            _pinned: PhantomPinned,
+        })
+    }
+
+    pub fn token(&self) -> &T {
+        &self.token
+    }
+
+    /// Submits the request by calling `f` with a pointer to the token
+    ///
+    /// `f` is expected to return the status of the protocol function it called. A completion
+    /// can only be submitted once.
+    ///
+    /// # Safety
+    ///
+    /// If `f` returns success, the firmware must signal the token's event once it is done
+    /// with the request. Everything else the request points to must outlive the completion.
+    pub unsafe fn submit(self: Pin<&mut Self>, f: impl FnOnce(*mut T) -> Status) -> Result<()> {
+        let this = unsafe { self.get_unchecked_mut() };
+        if this.state != CompletionState::Idle {
+            return Err(Status::ALREADY_STARTED);
+        }
+        f(&mut this.token).to_result(())?;
+        this.state = CompletionState::Pending;
+        Ok(())
+    }
+}
+
+impl<T: Token> Future for Completion<T> {
+    type Output = Result<()>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = unsafe { self.get_unchecked_mut() };
+        match this.state {
+            CompletionState::Idle => Poll::Ready(Err(Status::NOT_STARTED)),
+            CompletionState::Pending => match poll_event(cx, this.token.event()) {
+                Poll::Ready(Ok(())) => {
+                    this.state = CompletionState::Done;
+                    Poll::Ready(this.token.status().to_result(()))
+                }
+                poll => poll,
+            },
+            CompletionState::Done => Poll::Ready(this.token.status().to_result(())),
+        }
+    }
+}
+
+impl<T: Token> Drop for Completion<T> {
+    fn drop(&mut self) {
+        if !boot_services_active() {
+            return;
+        }
+        let bs = boot_services();
+        let event = self.token.event();
+        if self.state == CompletionState::Pending {
+            // Stop once the request is done, or if the event is unusable and there is
+            // nothing left to wait on.
+            while let Ok(false) = take_signal(event) {
+                if bs.wait_for_event(&[event]).is_ok() {
+                    break;
+                }
+            }
+        }
+        let _ = bs.close_event(event);
+    }
+}
@@ -0,0 +1,630 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Flattened device trees
+//!
+//! Loaders booting a device tree kernel typically take the firmware's [tree](current) from
+//! the configuration table, copy it into a larger buffer and [edit](Fdt) it there, and
+//! [install](install) the result back in its place so the kernel's EFI stub finds it.
+
+use core::{mem::size_of, slice};
+
+use crate::{
+    boot_services, system_table,
+    table::{AllocPagesType, MemoryMap, MemoryType, TableGuid},
+    PhysicalAddr, Result, Status, PAGE_SIZE,
+};
+
+/// Header at the start of every flattened device tree
+///
+/// All fields are big-endian.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct FdtHeader {
+    pub magic:             u32,
+    pub total_size:        u32,
+    pub off_dt_struct:     u32,
+    pub off_dt_strings:    u32,
+    pub off_mem_rsvmap:    u32,
+    pub version:           u32,
+    pub last_comp_version: u32,
+    pub boot_cpuid_phys:   u32,
+    pub size_dt_strings:   u32,
+    pub size_dt_struct:    u32,
+}
+
+impl FdtHeader {
+    pub const MAGIC: u32 = 0xd00dfeed;
+
+    /// Reads the header at the start of `fdt`
+    ///
+    /// Fails with `LOAD_ERROR` if `fdt` doesn't start with a device tree header, or with
+    /// `BUFFER_TOO_SMALL` if it is shorter than the size the header gives.
+    pub fn parse(fdt: &[u8]) -> Result<Self> {
+        let header = Self::read(fdt)?;
+        if fdt.len() < header.total_size as usize {
+            return Err(Status::BUFFER_TOO_SMALL);
+        }
+        Ok(header)
+    }
+
+    /// Reads the header without checking that the tree fits in `fdt`
+    fn read(fdt: &[u8]) -> Result<Self> {
+        let header = fdt.get(..size_of::<Self>()).ok_or(Status::LOAD_ERROR)?;
+        let field = |i: usize| u32::from_be_bytes(header[4 * i..4 * i + 4].try_into().unwrap());
+        let header = Self {
+            magic:             field(0),
+            total_size:        field(1),
+            off_dt_struct:     field(2),
+            off_dt_strings:    field(3),
+            off_mem_rsvmap:    field(4),
+            version:           field(5),
+            last_comp_version: field(6),
+            boot_cpuid_phys:   field(7),
+            size_dt_strings:   field(8),
+            size_dt_struct:    field(9),
+        };
+        if header.magic != Self::MAGIC || (header.total_size as usize) < size_of::<Self>() {
+            return Err(Status::LOAD_ERROR);
+        }
+        Ok(header)
+    }
+}
+
+/// Returns the device tree installed in the configuration table
+///
+/// Fails with `NOT_FOUND` if there is none, or with `LOAD_ERROR` if the entry doesn't point to
+/// a device tree.
+pub fn current() -> Result<&'static [u8]> {
+    let fdt = system_table()
+        .config_table()
+        .get_table(TableGuid::DEVICE_TREE)
+        .filter(|fdt| !fdt.is_null())
+        .ok_or(Status::NOT_FOUND)?
+        .cast::<u8>();
+    let header = FdtHeader::read(unsafe { slice::from_raw_parts(fdt, size_of::<FdtHeader>()) })?;
+    Ok(unsafe { slice::from_raw_parts(fdt, header.total_size as usize) })
+}
+
+/// Copies `fdt` into newly allocated pages and installs it as the device tree
+///
+/// Any device tree already in the configuration table is replaced; memory holding the previous
+/// tree is not freed, as it usually belongs to the firmware. The copy is allocated as
+/// `memory_type`, which should be memory the kernel won't reuse before it has read the tree:
+/// [`MemoryType::ACPI_RECLAIM`], as EBBR requires, or [`MemoryType::RESERVED`] to keep it
+/// for good.
+///
+/// Only the `total_size` bytes given by the header are copied, so `fdt` may be a larger
+/// buffer the tree was edited in. Returns the address of the copy.
+pub fn install(fdt: &[u8], memory_type: MemoryType) -> Result<PhysicalAddr> {
+    let size = FdtHeader::parse(fdt)?.total_size as usize;
+    let pages = size.div_ceil(PAGE_SIZE);
+
+    let bs = boot_services();
+    let addr = bs.allocate_pages(AllocPagesType::Any, memory_type, pages)?;
+    let copy = addr as *mut u8;
+    unsafe {
+        copy.copy_from_nonoverlapping(fdt.as_ptr(), size);
+        copy.add(size).write_bytes(0, pages * PAGE_SIZE - size);
+        if let Err(status) = bs.install_configuration_table(&TableGuid::DEVICE_TREE.0, copy.cast())
+        {
+            let _ = bs.free_pages(addr, pages);
+            return Err(status);
+        }
+    }
+    Ok(addr)
+}
+
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+const FDT_END: u32 = 9;
+
+// Byte offsets of the header fields updated while editing
+const TOTAL_SIZE: usize = 4;
+const OFF_DT_STRUCT: usize = 8;
+const OFF_DT_STRINGS: usize = 12;
+const SIZE_DT_STRINGS: usize = 32;
+const SIZE_DT_STRUCT: usize = 36;
+
+/// Offset of a node's `FDT_BEGIN_NODE` token in the tree
+///
+/// Edits move the nodes after the edited spot, so a `Node` is only valid until the tree is next
+/// changed; look it up again afterwards. Calls given a stale `Node` which no longer points at
+/// the start of a node fail with `LOAD_ERROR`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Node(usize);
+
+/// A device tree being edited in place
+///
+/// The tree grows into the rest of the buffer as properties and nodes are added, failing with
+/// `BUFFER_TOO_SMALL` once it is full. Only trees laid out the way `dtc` writes them are
+/// supported: the memory reservation block, then the structure block, then the strings block.
+/// Malformed trees fail with `LOAD_ERROR`.
+pub struct Fdt<'a> {
+    buf: &'a mut [u8],
+}
+
+impl<'a> Fdt<'a> {
+    /// Edits the tree at the start of `buf`
+    ///
+    /// Fails with `UNSUPPORTED` for trees older than version 17 or laid out differently.
+    pub fn new(buf: &'a mut [u8]) -> Result<Self> {
+        let header = FdtHeader::parse(buf)?;
+        if header.version < 17 || header.last_comp_version > 17 {
+            return Err(Status::UNSUPPORTED);
+        }
+        let (rsvmap, structs, strings) = (
+            header.off_mem_rsvmap as usize,
+            header.off_dt_struct as usize,
+            header.off_dt_strings as usize,
+        );
+        let struct_end = structs.checked_add(header.size_dt_struct as usize);
+        let strings_end = strings.checked_add(header.size_dt_strings as usize);
+        if !(rsvmap < structs && struct_end.is_some_and(|end| end <= strings)) {
+            return Err(Status::UNSUPPORTED);
+        }
+        if structs % 4 != 0 || !strings_end.is_some_and(|end| end <= header.total_size as usize) {
+            return Err(Status::LOAD_ERROR);
+        }
+        Ok(Self { buf })
+    }
+
+    /// Copies the tree `fdt` into `buf` to edit it there
+    pub fn copy_from(fdt: &[u8], buf: &'a mut [u8]) -> Result<Self> {
+        let size = FdtHeader::parse(fdt)?.total_size as usize;
+        buf.get_mut(..size)
+            .ok_or(Status::BUFFER_TOO_SMALL)?
+            .copy_from_slice(&fdt[..size]);
+        Self::new(buf)
+    }
+
+    /// Returns the tree, ready to be [installed](install)
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.header(TOTAL_SIZE)]
+    }
+
+    /// Sets the kernel command line, `/chosen/bootargs`
+    pub fn set_bootargs(&mut self, bootargs: &str) -> Result<()> {
+        if bootargs.contains('\0') {
+            return Err(Status::INVALID_PARAMETER);
+        }
+        let chosen = self.node_or_create("/chosen")?;
+        self.set_property_with(chosen, "bootargs", bootargs.len() + 1, |value| {
+            value[..bootargs.len()].copy_from_slice(bootargs.as_bytes());
+            value[bootargs.len()] = 0;
+        })
+    }
+
+    /// Records where the initrd was loaded, `/chosen/linux,initrd-{start,end}`
+    ///
+    /// `end` is the address just past the initrd.
+    pub fn set_initrd(&mut self, start: PhysicalAddr, end: PhysicalAddr) -> Result<()> {
+        let chosen = self.node_or_create("/chosen")?;
+        self.set_property(chosen, "linux,initrd-start", &start.to_be_bytes())?;
+        self.set_property(chosen, "linux,initrd-end", &end.to_be_bytes())
+    }
+
+    /// Records the system table and the final memory map, `/chosen/linux,uefi-*`
+    ///
+    /// This is how the Linux EFI stub tells the kernel about UEFI, so a kernel entered directly
+    /// after `ExitBootServices()` needs it to find the firmware's tables. Nothing is allocated,
+    /// so this may be called while exiting boot services.
+    pub fn set_uefi_params(&mut self, map: &MemoryMap) -> Result<()> {
+        let chosen = self.node_or_create("/chosen")?;
+        let (info, descriptors) = (map.info(), map.as_bytes());
+        let system_table = system_table() as *const _ as u64;
+        let version = u64::from(info.descriptor_version);
+        // Names, values and widths of the properties.
+        let properties = [
+            ("linux,uefi-system-table", system_table, 8),
+            ("linux,uefi-mmap-start", descriptors.as_ptr() as u64, 8),
+            ("linux,uefi-mmap-size", descriptors.len() as u64, 4),
+            ("linux,uefi-mmap-desc-size", info.descriptor_size as u64, 4),
+            ("linux,uefi-mmap-desc-ver", version, 4),
+        ];
+        for (name, value, width) in properties {
+            self.set_property(chosen, name, &value.to_be_bytes()[8 - width..])?;
+        }
+        Ok(())
+    }
+
+    /// Adds a `/reserved-memory` node keeping the OS away from `size` bytes at `base`
+    ///
+    /// The node is named `name@base`. With `no_map`, the OS won't map the region at all.
+    pub fn add_reserved_memory(
+        &mut self,
+        name: &str,
+        base: PhysicalAddr,
+        size: u64,
+        no_map: bool,
+    ) -> Result<()> {
+        let root = self.root()?;
+        let address_cells = self.u32_property(root, "#address-cells")?.unwrap_or(2);
+        let size_cells = self.u32_property(root, "#size-cells")?.unwrap_or(1);
+
+        let reserved = match self.find("/reserved-memory")? {
+            Some(node) => node,
+            None => {
+                let node = self.add_subnode(root, "reserved-memory")?;
+                self.set_property(node, "#address-cells", &address_cells.to_be_bytes())?;
+                self.set_property(node, "#size-cells", &size_cells.to_be_bytes())?;
+                self.set_property(node, "ranges", &[])?;
+                node
+            }
+        };
+        let address_cells = self
+            .u32_property(reserved, "#address-cells")?
+            .unwrap_or(address_cells);
+        let size_cells = self
+            .u32_property(reserved, "#size-cells")?
+            .unwrap_or(size_cells);
+
+        let mut reg = [0; 16];
+        let address_len = encode_cells(&mut reg, base, address_cells)?;
+        let size_len = encode_cells(&mut reg[address_len..], size, size_cells)?;
+
+        let mut name_buf = [0; 64];
+        let node = self.add_subnode(reserved, unit_name(&mut name_buf, name, base)?)?;
+        self.set_property(node, "reg", &reg[..address_len + size_len])?;
+        if no_map {
+            self.set_property(node, "no-map", &[])?;
+        }
+        Ok(())
+    }
+
+    /// Returns the root node
+    pub fn root(&self) -> Result<Node> {
+        let mut offset = self.header(OFF_DT_STRUCT);
+        loop {
+            match self.token(offset)? {
+                (FDT_NOP, next) => offset = next,
+                (FDT_BEGIN_NODE, _) => return Ok(Node(offset)),
+                _ => return Err(Status::LOAD_ERROR),
+            }
+        }
+    }
+
+    /// Finds the node at the absolute `path`
+    ///
+    /// Path components without a unit address match nodes with any unit address.
+    pub fn find(&self, path: &str) -> Result<Option<Node>> {
+        let mut node = self.root()?;
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            match self.subnode(node, name)? {
+                Some(subnode) => node = subnode,
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(node))
+    }
+
+    /// Finds the node at the absolute `path`, creating it and its missing parents
+    pub fn node_or_create(&mut self, path: &str) -> Result<Node> {
+        let mut node = self.root()?;
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            node = match self.subnode(node, name)? {
+                Some(subnode) => subnode,
+                None => self.add_subnode(node, name)?,
+            };
+        }
+        Ok(node)
+    }
+
+    /// Finds the child of `node` called `name`
+    pub fn subnode(&self, node: Node, name: &str) -> Result<Option<Node>> {
+        let end = self.node_end(node)?;
+        let mut offset = self.token(node.0)?.1;
+        while offset < end {
+            let (token, next) = self.token(offset)?;
+            if token == FDT_BEGIN_NODE {
+                let node_name = self.node_name(Node(offset))?;
+                let base_name = node_name.split(|&b| b == b'@').next().unwrap_or_default();
+                if node_name == name.as_bytes()
+                    || (!name.contains('@') && base_name == name.as_bytes())
+                {
+                    return Ok(Some(Node(offset)));
+                }
+                offset = self.node_end(Node(offset))?;
+            } else {
+                offset = next;
+            }
+        }
+        Ok(None)
+    }
+
+    /// Appends a child called `name` to `node`
+    pub fn add_subnode(&mut self, node: Node, name: &str) -> Result<Node> {
+        if name.is_empty() || name.contains(['\0', '/']) {
+            return Err(Status::INVALID_PARAMETER);
+        }
+        // Insert just before the parent's `FDT_END_NODE`.
+        let offset = self.node_end(node)? - 4;
+        let size = 4 + align4(name.len() + 1) + 4;
+        self.splice_struct(offset, 0, size)?;
+
+        self.set_u32(offset, FDT_BEGIN_NODE);
+        let name_at = offset + 4;
+        self.buf[name_at..name_at + name.len()].copy_from_slice(name.as_bytes());
+        self.buf[name_at + name.len()..offset + size - 4].fill(0);
+        self.set_u32(offset + size - 4, FDT_END_NODE);
+        Ok(Node(offset))
+    }
+
+    /// Sets the property `name` of `node` to `value`, adding it if necessary
+    pub fn set_property(&mut self, node: Node, name: &str, value: &[u8]) -> Result<()> {
+        self.set_property_with(node, name, value.len(), |buf| buf.copy_from_slice(value))
+    }
+
+    /// Sets the property `name` of `node` to a `len`-byte value filled in by `f`
+    fn set_property_with(
+        &mut self,
+        node: Node,
+        name: &str,
+        len: usize,
+        f: impl FnOnce(&mut [u8]),
+    ) -> Result<()> {
+        let len32 = u32::try_from(len).map_err(|_| Status::INVALID_PARAMETER)?;
+        let value_at = match self.property(node, name)? {
+            Some(prop) => {
+                let old_len = self.u32_at(prop + 4)? as usize;
+                self.splice_struct(prop + 12, align4(old_len), align4(len))?;
+                self.set_u32(prop + 4, len32);
+                prop + 12
+            }
+            None => {
+                let name_offset = self.string_offset(name)?;
+                let prop = self.properties_end(node)?;
+                self.splice_struct(prop, 0, 12 + align4(len))?;
+                self.set_u32(prop, FDT_PROP);
+                self.set_u32(prop + 4, len32);
+                self.set_u32(prop + 8, name_offset);
+                prop + 12
+            }
+        };
+        f(&mut self.buf[value_at..value_at + len]);
+        self.buf[value_at + len..value_at + align4(len)].fill(0);
+        Ok(())
+    }
+
+    /// Returns the offset of the `FDT_PROP` token of property `name` of `node`
+    fn property(&self, node: Node, name: &str) -> Result<Option<usize>> {
+        let mut offset = self.token(node.0)?.1;
+        loop {
+            match self.token(offset)? {
+                (FDT_PROP, next) => {
+                    if self.string(self.u32_at(offset + 8)?)? == name.as_bytes() {
+                        return Ok(Some(offset));
+                    }
+                    offset = next;
+                }
+                (FDT_NOP, next) => offset = next,
+                // Properties precede subnodes.
+                _ => return Ok(None),
+            }
+        }
+    }
+
+    /// Returns the offset just past the last property of `node`
+    fn properties_end(&self, node: Node) -> Result<usize> {
+        let mut offset = self.token(node.0)?.1;
+        loop {
+            match self.token(offset)? {
+                (FDT_PROP | FDT_NOP, next) => offset = next,
+                _ => return Ok(offset),
+            }
+        }
+    }
+
+    /// Returns the value of a 32-bit property
+    fn u32_property(&self, node: Node, name: &str) -> Result<Option<u32>> {
+        let Some(prop) = self.property(node, name)? else {
+            return Ok(None);
+        };
+        if self.u32_at(prop + 4)? != 4 {
+            return Err(Status::LOAD_ERROR);
+        }
+        self.u32_at(prop + 12).map(Some)
+    }
+
+    /// Returns the name of `node`, including its unit address
+    fn node_name(&self, node: Node) -> Result<&[u8]> {
+        let name = self
+            .buf
+            .get(node.0 + 4..self.struct_end())
+            .ok_or(Status::LOAD_ERROR)?;
+        let len = name
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or(Status::LOAD_ERROR)?;
+        Ok(&name[..len])
+    }
+
+    /// Returns the offset just past the `FDT_END_NODE` token closing `node`
+    fn node_end(&self, node: Node) -> Result<usize> {
+        if self.token(node.0)?.0 != FDT_BEGIN_NODE {
+            return Err(Status::LOAD_ERROR);
+        }
+        let mut depth = 0usize;
+        let mut offset = node.0;
+        loop {
+            let (token, next) = self.token(offset)?;
+            match token {
+                FDT_BEGIN_NODE => depth += 1,
+                FDT_END_NODE => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(next);
+                    }
+                }
+                FDT_END => return Err(Status::LOAD_ERROR),
+                _ => {}
+            }
+            offset = next;
+        }
+    }
+
+    /// Reads the token at `offset`, returning it and the offset of the next token
+    fn token(&self, offset: usize) -> Result<(u32, usize)> {
+        let token = self.u32_at(offset)?;
+        let next = match token {
+            FDT_BEGIN_NODE => {
+                let name = self
+                    .buf
+                    .get(offset + 4..self.struct_end())
+                    .ok_or(Status::LOAD_ERROR)?;
+                let len = name
+                    .iter()
+                    .position(|&b| b == 0)
+                    .ok_or(Status::LOAD_ERROR)?;
+                offset + 4 + align4(len + 1)
+            }
+            FDT_PROP => offset + 12 + align4(self.u32_at(offset + 4)? as usize),
+            FDT_END_NODE | FDT_NOP | FDT_END => offset + 4,
+            _ => return Err(Status::LOAD_ERROR),
+        };
+        if next > self.struct_end() {
+            return Err(Status::LOAD_ERROR);
+        }
+        Ok((token, next))
+    }
+
+    /// Returns the offset of `name` in the strings block, appending it if necessary
+    fn string_offset(&mut self, name: &str) -> Result<u32> {
+        let strings = self.header(OFF_DT_STRINGS);
+        let size = self.header(SIZE_DT_STRINGS);
+        let block = &self.buf[strings..strings + size];
+        let found = block
+            .windows(name.len() + 1)
+            .position(|s| &s[..name.len()] == name.as_bytes() && s[name.len()] == 0);
+        if let Some(offset) = found {
+            return Ok(offset as u32);
+        }
+
+        let at = strings + size;
+        self.splice(at, 0, name.len() + 1)?;
+        self.buf[at..at + name.len()].copy_from_slice(name.as_bytes());
+        self.buf[at + name.len()] = 0;
+        self.set_header(SIZE_DT_STRINGS, size + name.len() + 1);
+        Ok(size as u32)
+    }
+
+    /// Returns the null-terminated string at `offset` in the strings block
+    fn string(&self, offset: u32) -> Result<&[u8]> {
+        let strings = self.header(OFF_DT_STRINGS);
+        let block = &self.buf[strings..strings + self.header(SIZE_DT_STRINGS)];
+        let string = block.get(offset as usize..).ok_or(Status::LOAD_ERROR)?;
+        let len = string
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or(Status::LOAD_ERROR)?;
+        Ok(&string[..len])
+    }
+
+    fn struct_end(&self) -> usize {
+        self.header(OFF_DT_STRUCT) + self.header(SIZE_DT_STRUCT)
+    }
+
+    /// Resizes `old` bytes at `offset` in the structure block to `new` bytes
+    fn splice_struct(&mut self, offset: usize, old: usize, new: usize) -> Result<()> {
+        self.splice(offset, old, new)?;
+        let size = self.header(SIZE_DT_STRUCT) + new - old;
+        self.set_header(SIZE_DT_STRUCT, size);
+        // The strings block follows the structure block.
+        let strings = self.header(OFF_DT_STRINGS) + new - old;
+        self.set_header(OFF_DT_STRINGS, strings);
+        Ok(())
+    }
+
+    /// Resizes `old` bytes at `offset` to `new` bytes, moving the rest of the tree
+    ///
+    /// The contents of the resized range are unspecified afterwards.
+    fn splice(&mut self, offset: usize, old: usize, new: usize) -> Result<()> {
+        let total = self.header(TOTAL_SIZE);
+        let new_total = total - old + new;
+        if new_total > self.buf.len() || new_total > u32::MAX as usize {
+            return Err(Status::BUFFER_TOO_SMALL);
+        }
+        self.buf.copy_within(offset + old..total, offset + new);
+        self.set_header(TOTAL_SIZE, new_total);
+        Ok(())
+    }
+
+    fn header(&self, field: usize) -> usize {
+        u32::from_be_bytes(self.buf[field..field + 4].try_into().unwrap()) as usize
+    }
+
+    fn set_header(&mut self, field: usize, value: usize) {
+        self.set_u32(field, value as u32);
+    }
+
+    fn u32_at(&self, offset: usize) -> Result<u32> {
+        let bytes = self.buf.get(offset..offset + 4).ok_or(Status::LOAD_ERROR)?;
+        Ok(u32::from_be_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn set_u32(&mut self, offset: usize, value: u32) {
+        self.buf[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+    }
+}
+
+const fn align4(n: usize) -> usize {
+    (n + 3) & !3
+}
+
+/// Writes `value` as `cells` big-endian 32-bit cells, returning the number of bytes written
+fn encode_cells(buf: &mut [u8], value: u64, cells: u32) -> Result<usize> {
+    match cells {
+        1 => {
+            let value = u32::try_from(value).map_err(|_| Status::INVALID_PARAMETER)?;
+            buf[..4].copy_from_slice(&value.to_be_bytes());
+            Ok(4)
+        }
+        2 => {
+            buf[..8].copy_from_slice(&value.to_be_bytes());
+            Ok(8)
+        }
+        _ => Err(Status::UNSUPPORTED),
+    }
+}
+
+/// Formats the node name `name@address` into `buf`
+fn unit_name<'b>(buf: &'b mut [u8], name: &str, address: u64) -> Result<&'b str> {
+    let digits = (64 - address.leading_zeros()).div_ceil(4).max(1) as usize;
+    let len = name.len() + 1 + digits;
+    let buf = buf.get_mut(..len).ok_or(Status::INVALID_PARAMETER)?;
+    buf[..name.len()].copy_from_slice(name.as_bytes());
+    buf[name.len()] = b'@';
+    for (i, digit) in buf[name.len() + 1..].iter_mut().rev().enumerate() {
+        *digit = b"0123456789abcdef"[(address >> (4 * i)) as usize & 0xf];
+    }
+    core::str::from_utf8(buf).map_err(|_| Status::INVALID_PARAMETER)
+}
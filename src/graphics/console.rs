@@ -0,0 +1,271 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Text console drawn on the graphics device
+//!
+//! [`GfxConsole`] renders text with the [built-in font](super::font) or a [PSF font](Psf),
+//! for firmware whose text console is missing, slow, or doesn't follow GOP mode changes.
+
+use core::fmt;
+
+use super::{font, psf::Psf, Gfx};
+use crate::{
+    proto::console::gop::{Blt, BltPixel},
+    Result, Status,
+};
+
+/// Largest supported font scale
+pub const MAX_SCALE: usize = 4;
+
+/// Pixels in a scaled glyph; enough for a 32x32 font at scale 2
+const MAX_GLYPH: usize = 64 * 64;
+
+const TAB_WIDTH: usize = 8;
+
+/// What happens when text reaches the bottom of the screen
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Overflow {
+    /// Scroll everything up by one line
+    Scroll,
+    /// Drop the text, keeping what is already on screen
+    Discard,
+}
+
+/// Font a [`GfxConsole`] draws with
+#[derive(Clone, Copy)]
+pub enum Font {
+    /// The [built-in 8x8 font](font), covering ASCII only
+    Builtin,
+    Psf(Psf<'static>),
+}
+
+impl Font {
+    /// Returns the size of a glyph in pixels, as `(width, height)`
+    pub fn size(&self) -> (usize, usize) {
+        match self {
+            Self::Builtin => (font::WIDTH, font::HEIGHT),
+            Self::Psf(psf) => psf.size(),
+        }
+    }
+
+    fn render(&self, c: char, scale: usize, fg: BltPixel, bg: BltPixel, buffer: &mut [BltPixel]) {
+        match self {
+            Self::Builtin => font::render(c, scale, fg, bg, buffer),
+            Self::Psf(psf) => psf.render(c, scale, fg, bg, buffer),
+        }
+    }
+}
+
+/// Text console covering the screen, with a one character margin on each side
+pub struct GfxConsole {
+    gfx:      Gfx,
+    columns:  usize,
+    rows:     usize,
+    column:   usize,
+    row:      usize,
+    scale:    usize,
+    font:     Font,
+    fg:       BltPixel,
+    bg:       BltPixel,
+    overflow: Overflow,
+    glyph:    [BltPixel; MAX_GLYPH],
+}
+
+impl GfxConsole {
+    /// Creates a console for the current mode, drawing white on black
+    ///
+    /// `scale` is clamped to `1..=MAX_SCALE`. The screen is not cleared; call
+    /// [`clear()`](Self::clear) first if needed.
+    pub fn new(mut gfx: Gfx, scale: usize) -> Result<Self> {
+        let (width, height) = gfx.resolution()?;
+        let scale = scale.clamp(1, MAX_SCALE);
+        Ok(Self {
+            gfx,
+            columns: (width / (font::WIDTH * scale)).saturating_sub(2),
+            rows: (height / (font::HEIGHT * scale)).saturating_sub(2),
+            column: 0,
+            row: 0,
+            scale,
+            font: Font::Builtin,
+            fg: BltPixel::new(0xff, 0xff, 0xff),
+            bg: BltPixel::new(0x00, 0x00, 0x00),
+            overflow: Overflow::Scroll,
+            glyph: [BltPixel::default(); MAX_GLYPH],
+        })
+    }
+
+    /// Returns the size of the console in characters, as `(columns, rows)`
+    pub fn size(&self) -> (usize, usize) {
+        (self.columns, self.rows)
+    }
+
+    /// Returns the cursor position, as `(column, row)`
+    pub fn cursor(&self) -> (usize, usize) {
+        (self.column, self.row)
+    }
+
+    /// Moves the cursor, clamping it to the console
+    pub fn set_cursor(&mut self, column: usize, row: usize) {
+        self.column = column.min(self.columns);
+        self.row = row.min(self.rows);
+    }
+
+    /// Switches to another font, recalculating the size of the console
+    ///
+    /// `scale` is clamped to `1..=MAX_SCALE`, then lowered until a scaled glyph fits the
+    /// console's glyph buffer. Fails with `UNSUPPORTED` if the font's glyphs are too large
+    /// even unscaled. Text already on screen is left as is, and the cursor is clamped to
+    /// the new size.
+    pub fn set_font(&mut self, font: Font, scale: usize) -> Result<()> {
+        let (glyph_width, glyph_height) = font.size();
+        let mut scale = scale.clamp(1, MAX_SCALE);
+        while scale > 0 && glyph_width * glyph_height * scale * scale > MAX_GLYPH {
+            scale -= 1;
+        }
+        if scale == 0 {
+            return Err(Status::UNSUPPORTED);
+        }
+        let (width, height) = self.gfx.resolution()?;
+        self.font = font;
+        self.scale = scale;
+        self.columns = (width / (glyph_width * scale)).saturating_sub(2);
+        self.rows = (height / (glyph_height * scale)).saturating_sub(2);
+        self.set_cursor(self.column, self.row);
+        Ok(())
+    }
+
+    pub fn set_colors(&mut self, fg: BltPixel, bg: BltPixel) {
+        self.fg = fg;
+        self.bg = bg;
+    }
+
+    pub fn set_overflow(&mut self, overflow: Overflow) {
+        self.overflow = overflow;
+    }
+
+    pub fn gfx(&mut self) -> &mut Gfx {
+        &mut self.gfx
+    }
+
+    pub fn into_gfx(self) -> Gfx {
+        self.gfx
+    }
+
+    /// Fills the whole screen with the background color and moves the cursor home
+    pub fn clear(&mut self) -> Result<()> {
+        let (width, height) = self.gfx.resolution()?;
+        self.gfx.fill(self.bg, 0, 0, width, height)?;
+        self.column = 0;
+        self.row = 0;
+        Ok(())
+    }
+
+    /// Writes one character, interpreting `\n`, `\r` and `\t`
+    pub fn put(&mut self, c: char) -> Result<()> {
+        match c {
+            '\n' => return self.newline(),
+            '\r' => {
+                self.column = 0;
+                return Ok(());
+            }
+            '\t' => {
+                let next = (self.column / TAB_WIDTH + 1) * TAB_WIDTH;
+                while self.column < next.min(self.columns) {
+                    self.put(' ')?;
+                }
+                return Ok(());
+            }
+            _ => {}
+        }
+        if self.column == self.columns {
+            self.newline()?;
+        }
+        if self.row >= self.rows {
+            return Ok(());
+        }
+
+        let (width, height) = self.cell_size();
+        self.font
+            .render(c, self.scale, self.fg, self.bg, &mut self.glyph);
+        self.gfx.write_buffer(
+            &self.glyph,
+            width,
+            (self.column + 1) * width,
+            (self.row + 1) * height,
+            width,
+            height,
+        )?;
+        self.column += 1;
+        Ok(())
+    }
+
+    fn cell_size(&self) -> (usize, usize) {
+        let (width, height) = self.font.size();
+        (width * self.scale, height * self.scale)
+    }
+
+    fn newline(&mut self) -> Result<()> {
+        self.column = 0;
+        if self.row + 1 < self.rows || self.overflow == Overflow::Discard {
+            self.row += 1;
+            return Ok(());
+        }
+        self.scroll()
+    }
+
+    /// Moves every line up by one and clears the last
+    fn scroll(&mut self) -> Result<()> {
+        let (width, height) = self.cell_size();
+        if self.rows > 1 {
+            self.gfx.copy(
+                (width, 2 * height),
+                (width, height),
+                self.columns * width,
+                (self.rows - 1) * height,
+            )?;
+        }
+        self.gfx.fill(
+            self.bg,
+            width,
+            self.rows * height,
+            self.columns * width,
+            height,
+        )
+    }
+}
+
+impl fmt::Write for GfxConsole {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            self.put(c).map_err(|_| fmt::Error)?;
+        }
+        Ok(())
+    }
+}
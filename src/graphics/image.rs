@@ -0,0 +1,356 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Image decoding and blitting, for boot splashes
+//!
+//! Uncompressed (and bitfield) BMP files are always supported; PNG support is behind the
+//! `png` feature. Images decode to [`BltPixel`] buffers, which [`blit()`] draws centered or
+//! scaled on any [`Gfx`].
+
+#[cfg(feature = "alloc")]
+use alloc::{vec, vec::Vec};
+
+use super::Gfx;
+use crate::{
+    le,
+    proto::console::gop::{Blt, BltPixel, PixelBitmask},
+    Result, Status,
+};
+
+/// Borrowed image, stored row by row from the top left
+#[derive(Clone, Copy, Debug)]
+pub struct ImageRef<'a> {
+    pub width:  usize,
+    pub height: usize,
+    pub pixels: &'a [BltPixel],
+}
+
+impl<'a> ImageRef<'a> {
+    /// Returns `None` if `pixels` is smaller than `width * height`
+    pub fn new(width: usize, height: usize, pixels: &'a [BltPixel]) -> Option<Self> {
+        let len = width.checked_mul(height)?;
+        (pixels.len() >= len).then_some(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> BltPixel {
+        self.pixels[y * self.width + x]
+    }
+}
+
+/// Owned image, stored row by row from the top left
+#[cfg(feature = "alloc")]
+#[derive(Clone, Debug)]
+pub struct Image {
+    pub width:  usize,
+    pub height: usize,
+    pub pixels: Vec<BltPixel>,
+}
+
+#[cfg(feature = "alloc")]
+impl Image {
+    /// Decodes a BMP or (with the `png` feature) PNG file
+    ///
+    /// Transparent PNG pixels are blended over `background`.
+    pub fn decode(data: &[u8], background: BltPixel) -> Result<Self> {
+        #[cfg(feature = "png")]
+        if data.starts_with(&super::png::SIGNATURE) {
+            return super::png::decode(data, background);
+        }
+        let _ = background;
+        let bmp = Bmp::parse(data)?;
+        let mut pixels = vec![BltPixel::default(); bmp.width() * bmp.height()];
+        bmp.decode_into(&mut pixels)?;
+        Ok(Self {
+            width: bmp.width(),
+            height: bmp.height(),
+            pixels,
+        })
+    }
+
+    pub fn as_ref(&self) -> ImageRef<'_> {
+        ImageRef {
+            width:  self.width,
+            height: self.height,
+            pixels: &self.pixels,
+        }
+    }
+}
+
+const BI_RGB: u32 = 0;
+const BI_BITFIELDS: u32 = 3;
+const BI_ALPHABITFIELDS: u32 = 6;
+
+/// Parsed BMP file
+///
+/// Supports 1, 4 and 8-bit paletted images, and 16, 24 and 32-bit RGB images including
+/// `BI_BITFIELDS`. Run-length encoded and embedded JPEG/PNG images are not supported.
+pub struct Bmp<'a> {
+    data:       &'a [u8],
+    width:      usize,
+    height:     usize,
+    top_down:   bool,
+    bpp:        u16,
+    pixels:     usize,
+    palette:    usize,
+    /// Size of each palette entry (3 for `BITMAPCOREHEADER`, otherwise 4)
+    entry_size: usize,
+    colors:     usize,
+    masks:      PixelBitmask,
+}
+
+impl<'a> Bmp<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self> {
+        if !data.starts_with(b"BM") {
+            return Err(Status::UNSUPPORTED);
+        }
+        let u16_at = |at| le::read_u16(data, at).ok_or(Status::INVALID_PARAMETER);
+        let u32_at = |at| le::read_u32(data, at).ok_or(Status::INVALID_PARAMETER);
+        let pixels = u32_at(10)? as usize;
+        let header_size = u32_at(14)? as usize;
+
+        let (width, height, bpp, compression, colors, entry_size);
+        if header_size == 12 {
+            width = u16_at(18)? as i32;
+            height = u16_at(20)? as i16 as i32;
+            bpp = u16_at(24)?;
+            compression = BI_RGB;
+            colors = 0;
+            entry_size = 3;
+        } else if header_size >= 40 {
+            width = u32_at(18)? as i32;
+            height = u32_at(22)? as i32;
+            bpp = u16_at(28)?;
+            compression = u32_at(30)?;
+            colors = u32_at(46)? as usize;
+            entry_size = 4;
+        } else {
+            return Err(Status::UNSUPPORTED);
+        }
+
+        if width <= 0 || height == 0 || height == i32::MIN {
+            return Err(Status::INVALID_PARAMETER);
+        }
+
+        let masks = match (compression, bpp) {
+            (BI_RGB, 16) => [0x7c00, 0x03e0, 0x001f],
+            (BI_RGB, 24 | 32) => [0xff0000, 0x00ff00, 0x0000ff],
+            (BI_RGB, 1 | 4 | 8) => [0; 3],
+            (BI_BITFIELDS | BI_ALPHABITFIELDS, 16 | 32) => {
+                // The masks are part of V2+ headers, or follow a plain info header.
+                let at = 14 + 40;
+                [u32_at(at)?, u32_at(at + 4)?, u32_at(at + 8)?]
+            }
+            _ => return Err(Status::UNSUPPORTED),
+        };
+
+        let mut palette = 14 + header_size;
+        if header_size == 40 {
+            palette += match compression {
+                BI_BITFIELDS => 12,
+                BI_ALPHABITFIELDS => 16,
+                _ => 0,
+            };
+        }
+        let colors = match (bpp, colors) {
+            (1 | 4 | 8, 0) => 1 << bpp,
+            (1 | 4 | 8, n) => n.min(1 << bpp),
+            _ => 0,
+        };
+        if data.len() < palette + colors * entry_size {
+            return Err(Status::INVALID_PARAMETER);
+        }
+
+        let bmp = Self {
+            data,
+            width: width as usize,
+            height: height.unsigned_abs() as usize,
+            top_down: height < 0,
+            bpp,
+            pixels,
+            palette,
+            entry_size,
+            colors,
+            masks: PixelBitmask {
+                red:      masks[0],
+                green:    masks[1],
+                blue:     masks[2],
+                reserved: 0,
+            },
+        };
+        let end = bmp
+            .stride()?
+            .checked_mul(bmp.height)
+            .and_then(|len| len.checked_add(pixels));
+        match end {
+            Some(end) if end <= data.len() => Ok(bmp),
+            _ => Err(Status::INVALID_PARAMETER),
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Size of each row of pixel data, padded to 4 bytes
+    fn stride(&self) -> Result<usize> {
+        self.width
+            .checked_mul(self.bpp as usize)
+            .map(|bits| bits.div_ceil(32) * 4)
+            .ok_or(Status::INVALID_PARAMETER)
+    }
+
+    fn palette_entry(&self, index: usize) -> BltPixel {
+        if index >= self.colors {
+            return BltPixel::default();
+        }
+        let at = self.palette + index * self.entry_size;
+        BltPixel {
+            blue:     self.data[at],
+            green:    self.data[at + 1],
+            red:      self.data[at + 2],
+            reserved: 0,
+        }
+    }
+
+    /// Decodes the image into `pixels`, row by row from the top left
+    ///
+    /// `pixels` must hold at least `width * height` pixels.
+    pub fn decode_into(&self, pixels: &mut [BltPixel]) -> Result<()> {
+        if pixels.len() < self.width * self.height {
+            return Err(Status::BUFFER_TOO_SMALL);
+        }
+        let stride = self.stride()?;
+        for (y, out) in pixels
+            .chunks_exact_mut(self.width)
+            .take(self.height)
+            .enumerate()
+        {
+            let row = if self.top_down {
+                y
+            } else {
+                self.height - 1 - y
+            };
+            let row = &self.data[self.pixels + row * stride..][..stride];
+            for (x, out) in out.iter_mut().enumerate() {
+                *out = match self.bpp {
+                    1 | 4 | 8 => {
+                        let bits = self.bpp as usize;
+                        let byte = row[x * bits / 8];
+                        let shift = 8 - bits - (x * bits % 8);
+                        self.palette_entry((byte >> shift) as usize & ((1 << bits) - 1))
+                    }
+                    _ => {
+                        let at = x * self.bpp as usize / 8;
+                        let value = match self.bpp {
+                            16 => u16::from_le_bytes([row[at], row[at + 1]]) as u32,
+                            24 => u32::from_le_bytes([row[at], row[at + 1], row[at + 2], 0]),
+                            _ => u32::from_le_bytes(row[at..at + 4].try_into().unwrap()),
+                        };
+                        self.masks.unpack(value)
+                    }
+                };
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Where [`blit()`] draws an image
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Placement {
+    /// Centered at its natural size, shrunk to fit if it is larger than the screen
+    Center,
+    /// Centered and scaled as large as possible, preserving the aspect ratio
+    Fit,
+    /// Scaled to cover the entire screen
+    Stretch,
+    /// At its natural size, with the top left at the given position
+    At(usize, usize),
+}
+
+/// Draws `image`, returning the rectangle `(x, y, width, height)` it occupies
+///
+/// Scaling uses nearest-neighbour sampling, drawing one row segment at a time from the stack.
+pub fn blit(
+    gfx: &mut Gfx,
+    image: ImageRef,
+    placement: Placement,
+) -> Result<(usize, usize, usize, usize)> {
+    let (screen_w, screen_h) = gfx.resolution()?;
+    let (iw, ih) = (image.width, image.height);
+    if iw == 0 || ih == 0 {
+        return Ok((0, 0, 0, 0));
+    }
+
+    let fit = || {
+        // Compare the aspect ratios to find the limiting dimension.
+        if iw * screen_h <= ih * screen_w {
+            ((iw * screen_h / ih).max(1), screen_h)
+        } else {
+            (screen_w, (ih * screen_w / iw).max(1))
+        }
+    };
+    let (w, h) = match placement {
+        Placement::Center if iw <= screen_w && ih <= screen_h => (iw, ih),
+        Placement::Center | Placement::Fit => fit(),
+        Placement::Stretch => (screen_w, screen_h),
+        Placement::At(..) => (iw, ih),
+    };
+    let (x, y) = match placement {
+        Placement::At(x, y) => (x, y),
+        _ => ((screen_w - w) / 2, (screen_h - h) / 2),
+    };
+
+    if (w, h) == (iw, ih) {
+        gfx.write_buffer(image.pixels, iw, x, y, w, h)?;
+        return Ok((x, y, w, h));
+    }
+
+    let mut segment = [BltPixel::default(); 256];
+    for dy in 0..h {
+        let sy = dy * ih / h;
+        for start in (0..w).step_by(segment.len()) {
+            let len = (w - start).min(segment.len());
+            for (i, out) in segment[..len].iter_mut().enumerate() {
+                *out = image.pixel((start + i) * iw / w, sy);
+            }
+            gfx.write_buffer(&segment[..len], len, x + start, y + dy, len, 1)?;
+        }
+    }
+    Ok((x, y, w, h))
+}
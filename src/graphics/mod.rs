@@ -0,0 +1,169 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Graphics output independent of the underlying protocol
+//!
+//! [`Gfx`] prefers the [Graphics Output Protocol](GraphicsOutput), but falls back to
+//! [UGA Draw](UgaDraw) on firmware which predates it (notably older Macs).
+
+pub mod console;
+pub mod font;
+pub mod image;
+pub mod panic;
+#[cfg(feature = "png")]
+pub mod png;
+pub mod psf;
+pub mod scrollback;
+pub mod surface;
+
+use crate::{
+    boot_services,
+    proto::{
+        console::{
+            console_control::{ConsoleControl, ScreenMode},
+            gop::{Blt, BltOperation, BltPixel, GraphicsOutput, PixelBitmask, PixelFormat},
+            uga::UgaDraw,
+        },
+        Proto,
+    },
+    PhysicalAddr, Result, Status,
+};
+
+/// Switches legacy firmware's console to graphics mode
+///
+/// Firmware without the [Console Control Protocol](ConsoleControl) is always considered to be
+/// in graphics mode, so this only fails if switching is supported and fails.
+pub fn enable_graphics_mode() -> Result<()> {
+    match boot_services().first_protocol::<ConsoleControl>() {
+        Ok(mut control) => match control.get_mode()?.mode {
+            ScreenMode::GRAPHICS => Ok(()),
+            _ => control.set_mode(ScreenMode::GRAPHICS),
+        },
+        Err(Status::NOT_FOUND) => Ok(()),
+        Err(status) => Err(status),
+    }
+}
+
+/// Linear framebuffer of the current mode
+#[derive(Clone, Copy, Debug)]
+pub struct Framebuffer {
+    pub addr:    PhysicalAddr,
+    pub size:    usize,
+    pub width:   usize,
+    pub height:  usize,
+    /// Pixels per scanline
+    pub stride:  usize,
+    pub format:  PixelFormat,
+    /// Only meaningful if `format` is [`PixelFormat::BITMASK`]
+    pub bitmask: PixelBitmask,
+}
+
+pub enum Gfx {
+    Gop(Proto<GraphicsOutput>),
+    Uga(Proto<UgaDraw>),
+}
+
+impl Gfx {
+    /// Locates a graphics protocol, preferring GOP over UGA
+    pub fn locate() -> Result<Self> {
+        let bs = boot_services();
+        match bs.first_protocol::<GraphicsOutput>() {
+            Ok(gop) => Ok(Gfx::Gop(gop)),
+            Err(gop_status) => bs
+                .first_protocol::<UgaDraw>()
+                .map(Gfx::Uga)
+                .map_err(|_| gop_status),
+        }
+    }
+
+    /// Returns the width and height of the current mode, in pixels
+    pub fn resolution(&mut self) -> Result<(usize, usize)> {
+        match self {
+            Gfx::Gop(gop) => {
+                let info = gop.mode().info();
+                Ok((
+                    info.horizontal_resolution as usize,
+                    info.vertical_resolution as usize,
+                ))
+            }
+            Gfx::Uga(uga) => {
+                let mode = uga.get_mode()?;
+                Ok((
+                    mode.horizontal_resolution as usize,
+                    mode.vertical_resolution as usize,
+                ))
+            }
+        }
+    }
+
+    /// Returns the linear framebuffer, if the protocol exposes one
+    ///
+    /// UGA never does, and GOP doesn't in [`PixelFormat::BLT_ONLY`] modes; use the blt
+    /// methods instead.
+    pub fn framebuffer(&self) -> Option<Framebuffer> {
+        let Gfx::Gop(gop) = self else { return None };
+        let mode = gop.mode();
+        let info = mode.info();
+        if info.pixel_format == PixelFormat::BLT_ONLY || mode.framebuffer_size == 0 {
+            return None;
+        }
+        Some(Framebuffer {
+            addr:    mode.framebuffer_addr,
+            size:    mode.framebuffer_size,
+            width:   info.horizontal_resolution as usize,
+            height:  info.vertical_resolution as usize,
+            stride:  info.pixels_per_scanline as usize,
+            format:  info.pixel_format,
+            bitmask: info.pixel_info,
+        })
+    }
+
+    pub fn is_gop(&self) -> bool {
+        matches!(self, Gfx::Gop(_))
+    }
+}
+
+impl Blt for Gfx {
+    unsafe fn blt(
+        &mut self,
+        buffer: *mut BltPixel,
+        operation: BltOperation,
+        source: (usize, usize),
+        destination: (usize, usize),
+        width: usize,
+        height: usize,
+        delta: usize,
+    ) -> Result<()> {
+        match self {
+            Gfx::Gop(gop) => gop.blt(buffer, operation, source, destination, width, height, delta),
+            Gfx::Uga(uga) => uga.blt(buffer, operation, source, destination, width, height, delta),
+        }
+    }
+}
@@ -0,0 +1,191 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Full-screen panic reports
+//!
+//! Draws the panic message, its location, a register snapshot, a backtrace and the top of the
+//! stack using the [built-in font](super::font), for debugging on machines without a serial
+//! port. Nothing here allocates, so it can be called from a panic handler even if the
+//! allocator is what panicked.
+//!
+//! Only boot services are used for drawing; call this before `ExitBootServices()`.
+
+use core::{
+    fmt::{self, Write},
+    mem::size_of,
+    panic::PanicInfo,
+    ptr,
+};
+
+use super::{
+    console::{GfxConsole, Overflow},
+    enable_graphics_mode, Gfx,
+};
+use crate::{
+    arch::{Backtrace, Registers},
+    boot_services_active,
+    proto::{
+        console::gop::{BltPixel, GraphicsOutput},
+        Proto,
+    },
+    Result, Status,
+};
+
+/// Modes tried in order, falling back to the current mode if none are available
+const PREFERRED_MODES: [(u32, u32); 3] = [(1024, 768), (800, 600), (640, 480)];
+
+/// Appearance of the panic screen
+#[derive(Clone, Copy, Debug)]
+pub struct PanicScreen {
+    pub background:  BltPixel,
+    pub foreground:  BltPixel,
+    /// Font scale, up to [`MAX_SCALE`](super::console::MAX_SCALE); `None` picks one based on the
+    /// screen width
+    pub scale:       Option<usize>,
+    /// Number of words to dump from the top of the stack
+    pub stack_words: usize,
+}
+
+impl Default for PanicScreen {
+    fn default() -> Self {
+        Self {
+            background:  BltPixel::new(0x00, 0x00, 0xaa),
+            foreground:  BltPixel::new(0xff, 0xff, 0xff),
+            scale:       None,
+            stack_words: 32,
+        }
+    }
+}
+
+impl PanicScreen {
+    /// Switches to a known graphics mode and draws the report
+    ///
+    /// Registers are captured on entry, so they describe this function's caller rather than
+    /// the site of the panic; the stack dump covers both. Fails with `UNSUPPORTED` if boot
+    /// services have been exited.
+    pub fn show(&self, info: &PanicInfo) -> Result<()> {
+        let registers = Registers::capture();
+        let backtrace = Backtrace::capture();
+
+        if !boot_services_active() {
+            return Err(Status::UNSUPPORTED);
+        }
+        enable_graphics_mode()?;
+        let mut gfx = Gfx::locate()?;
+        if let Gfx::Gop(gop) = &mut gfx {
+            set_known_mode(gop);
+        }
+
+        let (width, _) = gfx.resolution()?;
+        let mut screen = GfxConsole::new(gfx, self.scale.unwrap_or(width / 640))?;
+        screen.set_colors(self.foreground, self.background);
+        // Keep the message in view rather than scrolling it away with the stack dump.
+        screen.set_overflow(Overflow::Discard);
+        screen.clear()?;
+
+        // Drawing errors are ignored from here on; a partial report beats none.
+        let _ = self.report(&mut screen, info, &registers, &backtrace);
+        Ok(())
+    }
+
+    fn report(
+        &self,
+        screen: &mut GfxConsole,
+        info: &PanicInfo,
+        registers: &Registers,
+        backtrace: &Backtrace,
+    ) -> fmt::Result {
+        let digits = 2 * size_of::<usize>();
+
+        writeln!(screen, "*** PANIC ***")?;
+        writeln!(screen)?;
+        writeln!(screen, "{}", info.message())?;
+        if let Some(location) = info.location() {
+            writeln!(
+                screen,
+                "at {}:{}:{}",
+                location.file(),
+                location.line(),
+                location.column()
+            )?;
+        }
+        writeln!(screen)?;
+
+        writeln!(screen, "Registers:")?;
+        for (name, value) in registers.iter() {
+            writeln!(screen, "  {name:>9} = {value:0digits$x}")?;
+        }
+        writeln!(screen)?;
+
+        if !backtrace.frames().is_empty() {
+            writeln!(screen, "Backtrace:")?;
+            write!(screen, "{backtrace}")?;
+            writeln!(screen)?;
+        }
+
+        let sp = registers.stack_pointer();
+        writeln!(screen, "Stack:")?;
+        // Each line is `  address: word word ...`.
+        let per_line = (screen.size().0.saturating_sub(digits + 4) / (digits + 1)).max(1);
+        for line in 0..self.stack_words.div_ceil(per_line) {
+            let first = line * per_line;
+            let address = sp + first * size_of::<usize>();
+            write!(screen, "  {address:0digits$x}:")?;
+            for i in first..(first + per_line).min(self.stack_words) {
+                // The stack grows down, so everything above the stack pointer is live.
+                let word = unsafe { ptr::read_volatile((sp as *const usize).add(i)) };
+                write!(screen, " {word:0digits$x}")?;
+            }
+            writeln!(screen)?;
+        }
+        Ok(())
+    }
+}
+
+/// Shows the panic screen with the default appearance
+pub fn show(info: &PanicInfo) -> Result<()> {
+    PanicScreen::default().show(info)
+}
+
+/// Switches to the first available mode in [`PREFERRED_MODES`]
+fn set_known_mode(gop: &mut Proto<GraphicsOutput>) {
+    for (width, height) in PREFERRED_MODES {
+        let mode = gop.all_modes().find_map(|(mode, info)| {
+            let info = info.ok()?;
+            (info.horizontal_resolution == width && info.vertical_resolution == height)
+                .then_some(mode)
+        });
+        match mode {
+            Some(mode) if mode == gop.mode().mode => return,
+            Some(mode) if gop.set_mode(mode).is_ok() => return,
+            _ => {}
+        }
+    }
+}
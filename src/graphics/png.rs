@@ -0,0 +1,268 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! PNG decoding
+//!
+//! Supports every color type and bit depth of non-interlaced images. Transparency is blended
+//! over a background color, since the firmware has no notion of alpha.
+
+use alloc::{vec, vec::Vec};
+
+use super::image::Image;
+use crate::{crc32, inflate, proto::console::gop::BltPixel, Result, Status};
+
+pub(crate) const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+const GRAY: u8 = 0;
+const RGB: u8 = 2;
+const INDEXED: u8 = 3;
+const GRAY_ALPHA: u8 = 4;
+const RGBA: u8 = 6;
+
+/// Largest width, height and chunk length the specification allows
+const MAX_U31: usize = 0x7fff_ffff;
+/// Largest decompressed data or decoded pixel buffer accepted, to bound the allocations made
+/// from the header
+const MAX_BUFFER_LEN: usize = 256 << 20;
+
+fn be_u32(data: &[u8]) -> u32 {
+    u32::from_be_bytes(data[..4].try_into().unwrap())
+}
+
+/// Decodes a PNG file, blending transparent pixels over `background`
+pub fn decode(data: &[u8], background: BltPixel) -> Result<Image> {
+    let mut data = data.strip_prefix(&SIGNATURE).ok_or(Status::UNSUPPORTED)?;
+
+    let mut header = None;
+    let mut palette: &[u8] = &[];
+    let mut transparency: &[u8] = &[];
+    let mut compressed = Vec::new();
+    loop {
+        if data.len() < 12 {
+            return Err(Status::INVALID_PARAMETER);
+        }
+        let len = be_u32(data) as usize;
+        if len > MAX_U31 {
+            return Err(Status::INVALID_PARAMETER);
+        }
+        let chunk = data.get(4..8 + len).ok_or(Status::INVALID_PARAMETER)?;
+        let crc = data
+            .get(8 + len..12 + len)
+            .ok_or(Status::INVALID_PARAMETER)?;
+        if crc32::software(chunk) != be_u32(crc) {
+            return Err(Status::CRC_ERROR);
+        }
+        let (kind, body) = chunk.split_at(4);
+        data = &data[12 + len..];
+
+        match kind {
+            b"IHDR" if body.len() == 13 => header = Some(body),
+            b"PLTE" => palette = body,
+            b"tRNS" => transparency = body,
+            b"IDAT" => compressed.extend_from_slice(body),
+            b"IEND" => break,
+            // Ancillary chunks can be skipped; critical ones cannot.
+            _ if kind[0] & 0x20 == 0 => return Err(Status::UNSUPPORTED),
+            _ => {}
+        }
+    }
+
+    let header = header.ok_or(Status::INVALID_PARAMETER)?;
+    let width = be_u32(header) as usize;
+    let height = be_u32(&header[4..]) as usize;
+    if !(1..=MAX_U31).contains(&width) || !(1..=MAX_U31).contains(&height) {
+        return Err(Status::INVALID_PARAMETER);
+    }
+    let (depth, color) = (header[8], header[9]);
+    if header[10] != 0 || header[11] != 0 {
+        return Err(Status::UNSUPPORTED);
+    }
+    if header[12] != 0 {
+        // Adam7 interlacing
+        return Err(Status::UNSUPPORTED);
+    }
+
+    let channels = match (color, depth) {
+        (GRAY, 1 | 2 | 4 | 8 | 16) => 1,
+        (RGB, 8 | 16) => 3,
+        (INDEXED, 1 | 2 | 4 | 8) => 1,
+        (GRAY_ALPHA, 8 | 16) => 2,
+        (RGBA, 8 | 16) => 4,
+        _ => return Err(Status::UNSUPPORTED),
+    };
+    let bits_per_pixel = channels * depth as usize;
+    let stride = width
+        .checked_mul(bits_per_pixel)
+        .ok_or(Status::OUT_OF_RESOURCES)?
+        .div_ceil(8);
+    let bpp = bits_per_pixel.div_ceil(8);
+    let pixel_count = width
+        .checked_mul(height)
+        .filter(|&n| n <= MAX_BUFFER_LEN / size_of::<BltPixel>())
+        .ok_or(Status::OUT_OF_RESOURCES)?;
+
+    let raw_len = stride
+        .checked_add(1)
+        .and_then(|n| n.checked_mul(height))
+        .filter(|&n| n <= MAX_BUFFER_LEN)
+        .ok_or(Status::OUT_OF_RESOURCES)?;
+    let mut raw = vec![0u8; raw_len];
+    if inflate::zlib_decompress(&compressed, &mut raw)? != raw_len {
+        return Err(Status::INVALID_PARAMETER);
+    }
+    drop(compressed);
+
+    unfilter(&mut raw, stride, bpp)?;
+
+    let sample = |row: &[u8], index: usize| -> u16 {
+        match depth {
+            16 => u16::from_be_bytes([row[index * 2], row[index * 2 + 1]]),
+            8 => row[index] as u16,
+            _ => {
+                let bits = depth as usize;
+                let shift = 8 - bits - (index * bits % 8);
+                (row[index * bits / 8] >> shift) as u16 & ((1 << bits) - 1)
+            }
+        }
+    };
+    // Scales a sample to 8 bits
+    let max = (1u32 << depth) - 1;
+    let scale = |value: u16| (value as u32 * 255 / max) as u8;
+    // Transparent color for gray and RGB images
+    let key = |i: usize| {
+        transparency
+            .get(i * 2..i * 2 + 2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]))
+    };
+
+    let mut pixels = Vec::with_capacity(pixel_count);
+    for row in raw.chunks_exact(stride + 1) {
+        let row = &row[1..];
+        for x in 0..width {
+            let i = x * channels;
+            let (r, g, b, a) = match color {
+                GRAY => {
+                    let v = sample(row, i);
+                    let a = if key(0) == Some(v) { 0 } else { 255 };
+                    (scale(v), scale(v), scale(v), a)
+                }
+                RGB => {
+                    let (r, g, b) = (sample(row, i), sample(row, i + 1), sample(row, i + 2));
+                    let transparent = (key(0), key(1), key(2)) == (Some(r), Some(g), Some(b));
+                    (
+                        scale(r),
+                        scale(g),
+                        scale(b),
+                        if transparent { 0 } else { 255 },
+                    )
+                }
+                INDEXED => {
+                    let index = sample(row, i) as usize;
+                    let entry = palette
+                        .get(index * 3..index * 3 + 3)
+                        .ok_or(Status::INVALID_PARAMETER)?;
+                    let a = transparency.get(index).copied().unwrap_or(255);
+                    (entry[0], entry[1], entry[2], a)
+                }
+                GRAY_ALPHA => {
+                    let v = scale(sample(row, i));
+                    (v, v, v, scale(sample(row, i + 1)))
+                }
+                _ => (
+                    scale(sample(row, i)),
+                    scale(sample(row, i + 1)),
+                    scale(sample(row, i + 2)),
+                    scale(sample(row, i + 3)),
+                ),
+            };
+            let blend = |fg: u8, bg: u8| {
+                ((fg as u32 * a as u32 + bg as u32 * (255 - a as u32)) / 255) as u8
+            };
+            pixels.push(BltPixel {
+                blue:     blend(b, background.blue),
+                green:    blend(g, background.green),
+                red:      blend(r, background.red),
+                reserved: 0,
+            });
+        }
+    }
+
+    Ok(Image {
+        width,
+        height,
+        pixels,
+    })
+}
+
+/// Reverses the per-row filters, in place
+///
+/// Each row of `raw` is a filter type byte followed by `stride` bytes.
+fn unfilter(raw: &mut [u8], stride: usize, bpp: usize) -> Result<()> {
+    let mut prev_start = None;
+    for start in (0..raw.len()).step_by(stride + 1) {
+        let filter = raw[start];
+        let (before, rest) = raw.split_at_mut(start + 1);
+        let row = &mut rest[..stride];
+        let prev = prev_start.map(|p: usize| &before[p + 1..p + 1 + stride]);
+        let up = |x: usize| prev.map_or(0, |prev| prev[x]);
+
+        for x in 0..stride {
+            let a = if x >= bpp { row[x - bpp] } else { 0 };
+            let b = up(x);
+            let c = if x >= bpp { up(x - bpp) } else { 0 };
+            row[x] = row[x].wrapping_add(match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => paeth(a, b, c),
+                _ => return Err(Status::INVALID_PARAMETER),
+            });
+        }
+        prev_start = Some(start);
+    }
+    Ok(())
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
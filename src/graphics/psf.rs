@@ -0,0 +1,269 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! PC Screen Font (PSF) bitmap fonts
+//!
+//! [`Psf`] reads the PSF1 and PSF2 fonts used by the Linux console, including their Unicode
+//! tables, so a [`GfxConsole`](super::console::GfxConsole) can draw more than ASCII. Fonts
+//! without a Unicode table map each code point to the glyph with the same index. Fonts from
+//! `/usr/share/kbd/consolefonts` are usually gzipped and must be decompressed first.
+
+use core::ops::ControlFlow;
+
+use crate::{proto::console::gop::BltPixel, Result, Status};
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF1_MODE_512: u8 = 0x01;
+const PSF1_MODE_HAS_TABLE: u8 = 0x02;
+const PSF1_MODE_HAS_SEQ: u8 = 0x04;
+const PSF1_SEPARATOR: u16 = 0xffff;
+const PSF1_START_SEQ: u16 = 0xfffe;
+
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xb5, 0x4a, 0x86];
+const PSF2_HEADER_SIZE: usize = 32;
+const PSF2_HAS_UNICODE_TABLE: u32 = 0x01;
+const PSF2_SEPARATOR: u8 = 0xff;
+const PSF2_START_SEQ: u8 = 0xfe;
+
+#[derive(Clone, Copy)]
+enum UnicodeTable<'a> {
+    None,
+    /// Little-endian UCS-2 code points
+    Psf1(&'a [u8]),
+    /// UTF-8 strings
+    Psf2(&'a [u8]),
+}
+
+/// A parsed PSF1 or PSF2 font
+#[derive(Clone, Copy)]
+pub struct Psf<'a> {
+    glyphs:          &'a [u8],
+    count:           usize,
+    width:           usize,
+    height:          usize,
+    bytes_per_glyph: usize,
+    unicode:         UnicodeTable<'a>,
+}
+
+impl<'a> Psf<'a> {
+    /// Parses a font file
+    ///
+    /// Fails with `UNSUPPORTED` if `data` is not a PSF font, or with `INVALID_PARAMETER` if
+    /// it is truncated or its header is inconsistent.
+    pub fn parse(data: &'a [u8]) -> Result<Self> {
+        let (glyphs_offset, count, width, height, bytes_per_glyph, has_table) =
+            if data.starts_with(&PSF1_MAGIC) {
+                let &[_, _, mode, height, ..] = data else {
+                    return Err(Status::INVALID_PARAMETER);
+                };
+                let count = if mode & PSF1_MODE_512 != 0 { 512 } else { 256 };
+                let has_table = mode & (PSF1_MODE_HAS_TABLE | PSF1_MODE_HAS_SEQ) != 0;
+                (4, count, 8, height as usize, height as usize, has_table)
+            } else if data.starts_with(&PSF2_MAGIC) {
+                let field = |index: usize| -> Result<usize> {
+                    data.get(4 * index..4 * index + 4)
+                        .map(|b| u32::from_le_bytes(b.try_into().unwrap()) as usize)
+                        .ok_or(Status::INVALID_PARAMETER)
+                };
+                let header_size = field(2)?;
+                let flags = field(3)? as u32;
+                let (count, bytes_per_glyph) = (field(4)?, field(5)?);
+                let (height, width) = (field(6)?, field(7)?);
+                if header_size < PSF2_HEADER_SIZE
+                    || width == 0
+                    || bytes_per_glyph != height * width.div_ceil(8)
+                {
+                    return Err(Status::INVALID_PARAMETER);
+                }
+                let has_table = flags & PSF2_HAS_UNICODE_TABLE != 0;
+                (
+                    header_size,
+                    count,
+                    width,
+                    height,
+                    bytes_per_glyph,
+                    has_table,
+                )
+            } else {
+                return Err(Status::UNSUPPORTED);
+            };
+
+        if count == 0 || height == 0 {
+            return Err(Status::INVALID_PARAMETER);
+        }
+        let glyphs_end = count
+            .checked_mul(bytes_per_glyph)
+            .and_then(|size| size.checked_add(glyphs_offset))
+            .filter(|&end| end <= data.len())
+            .ok_or(Status::INVALID_PARAMETER)?;
+        let table = &data[glyphs_end..];
+        let unicode = match (has_table, data.starts_with(&PSF1_MAGIC)) {
+            (false, _) => UnicodeTable::None,
+            (true, true) => UnicodeTable::Psf1(table),
+            (true, _) => UnicodeTable::Psf2(table),
+        };
+
+        Ok(Self {
+            glyphs: &data[glyphs_offset..glyphs_end],
+            count,
+            width,
+            height,
+            bytes_per_glyph,
+            unicode,
+        })
+    }
+
+    /// Returns the size of a glyph in pixels, as `(width, height)`
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    /// Returns the number of glyphs in the font
+    pub fn glyph_count(&self) -> usize {
+        self.count
+    }
+
+    /// Returns the index of the glyph for `c`, if the font covers it
+    pub fn glyph_index(&self, c: char) -> Option<usize> {
+        // Fonts keep ASCII near the start of the table, so searching it is quick for most text.
+        match self.unicode {
+            UnicodeTable::None => Some(c as usize).filter(|&index| index < self.count),
+            _ => match self.scan(|index, mapped| match mapped == c {
+                true => ControlFlow::Break(index),
+                false => ControlFlow::Continue(()),
+            }) {
+                ControlFlow::Break(index) => Some(index),
+                ControlFlow::Continue(()) => None,
+            },
+        }
+    }
+
+    /// Returns the bitmap of the glyph for `c`, if the font covers it
+    ///
+    /// Each row is `width.div_ceil(8)` bytes, with the most significant bit being the leftmost
+    /// pixel.
+    pub fn glyph(&self, c: char) -> Option<&'a [u8]> {
+        let index = self.glyph_index(c)?;
+        let start = index * self.bytes_per_glyph;
+        Some(&self.glyphs[start..start + self.bytes_per_glyph])
+    }
+
+    /// Rasterizes `c` into `buffer`, scaling each font pixel to a `scale` by `scale` square
+    ///
+    /// Rows of `buffer` are `width * scale` pixels apart. Characters the font doesn't cover are
+    /// drawn as `?`, or as the first glyph if it has none.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `buffer` holds fewer than `width * height * scale * scale` pixels.
+    pub fn render(
+        &self,
+        c: char,
+        scale: usize,
+        fg: BltPixel,
+        bg: BltPixel,
+        buffer: &mut [BltPixel],
+    ) {
+        let bitmap = self
+            .glyph(c)
+            .or_else(|| self.glyph('?'))
+            .unwrap_or(&self.glyphs[..self.bytes_per_glyph]);
+        let row_bytes = self.width.div_ceil(8);
+        let stride = self.width * scale;
+        let buffer = &mut buffer[..stride * self.height * scale];
+
+        for (y, row) in buffer.chunks_exact_mut(stride).enumerate() {
+            let bits = &bitmap[y / scale * row_bytes..][..row_bytes];
+            for (x, pixel) in row.iter_mut().enumerate() {
+                let x = x / scale;
+                *pixel = if bits[x / 8] & (0x80 >> (x % 8)) != 0 {
+                    fg
+                } else {
+                    bg
+                };
+            }
+        }
+    }
+
+    /// Calls `f` with each glyph index and single character mapped to it, until it breaks
+    ///
+    /// Multi-character sequences, which map combining characters onto precomposed glyphs,
+    /// are skipped.
+    fn scan<B>(&self, mut f: impl FnMut(usize, char) -> ControlFlow<B>) -> ControlFlow<B> {
+        match self.unicode {
+            UnicodeTable::None => {}
+            UnicodeTable::Psf1(table) => {
+                let mut units = table
+                    .chunks_exact(2)
+                    .map(|b| u16::from_le_bytes([b[0], b[1]]));
+                for index in 0..self.count {
+                    let mut in_sequence = false;
+                    for unit in units.by_ref() {
+                        match unit {
+                            PSF1_SEPARATOR => break,
+                            PSF1_START_SEQ => in_sequence = true,
+                            _ if in_sequence => {}
+                            _ => {
+                                if let Some(c) = char::from_u32(unit.into()) {
+                                    f(index, c)?;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            UnicodeTable::Psf2(table) => {
+                let entries = table.split(|&b| b == PSF2_SEPARATOR);
+                for (index, entry) in entries.take(self.count).enumerate() {
+                    let singles = entry.split(|&b| b == PSF2_START_SEQ).next().unwrap();
+                    // Skip entries which aren't UTF-8 rather than rejecting the whole font.
+                    for c in core::str::from_utf8(singles).unwrap_or_default().chars() {
+                        f(index, c)?;
+                    }
+                }
+            }
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl Psf<'static> {
+    /// Loads and parses a font file from the running image's directory, or the first of
+    /// `fallbacks` that exists
+    ///
+    /// Paths are looked up as by [`config::load()`](crate::config::load). The file is leaked,
+    /// as the font is usually kept until the console is done with.
+    pub fn load(name: &str, fallbacks: &[&str]) -> Result<Self> {
+        let data = crate::config::load(name, fallbacks)?;
+        Psf::parse(&data)?;
+        Psf::parse(alloc::vec::Vec::leak(data))
+    }
+}
@@ -0,0 +1,220 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Scrollback for the graphics console
+//!
+//! [`Scrollback`] keeps the last `N` bytes written to a [`GfxConsole`], so boot logs longer
+//! than the screen can be paged through before handing off:
+//!
+//! ```ignore
+//! let mut screen = Scrollback::<{ 64 * 1024 }>::new(GfxConsole::new(Gfx::locate()?, 1)?);
+//! writeln!(screen, "loading kernel")?;
+//! // ...
+//! if let InputEvent::Key(key) = events.next_event()? {
+//!     if key.key.scancode == InputKey::SCAN_PAGE_UP {
+//!         screen.review(events.keyboard().unwrap())?;
+//!     }
+//! }
+//! ```
+
+use core::fmt;
+
+use super::console::GfxConsole;
+use crate::{
+    boot_services,
+    output::RingBuffer,
+    proto::console::{text_input::InputKey, text_input_ex::SimpleTextInputEx},
+    Result, Status,
+};
+
+const TAB_WIDTH: usize = 8;
+
+/// A [`GfxConsole`] which remembers the last `N` bytes written to it
+pub struct Scrollback<const N: usize> {
+    console: GfxConsole,
+    history: RingBuffer<N>,
+}
+
+impl<const N: usize> Scrollback<N> {
+    /// Wraps a console; what is already on screen is not part of the history
+    pub fn new(console: GfxConsole) -> Self {
+        Self {
+            console,
+            history: RingBuffer::new(),
+        }
+    }
+
+    pub fn console(&mut self) -> &mut GfxConsole {
+        &mut self.console
+    }
+
+    pub fn into_console(self) -> GfxConsole {
+        self.console
+    }
+
+    /// Lets the user page through the history until they press Esc, Enter or `q`
+    ///
+    /// Starts one page above the bottom, as if Page Up had been pressed. Page Up and Page Down
+    /// scroll by a page, the arrow keys by a line, and Home and End jump to either end. On
+    /// return the bottom of the history is redrawn with the cursor after the last character,
+    /// so output can continue where it left off.
+    pub fn review(&mut self, keyboard: &mut SimpleTextInputEx) -> Result<()> {
+        let (_, rows) = self.console.size();
+        let page = rows.saturating_sub(1).max(1);
+        let bottom = self.lines().saturating_sub(rows);
+        let mut top = bottom.saturating_sub(page);
+        self.draw(top)?;
+
+        loop {
+            boot_services().wait_for_event(&[keyboard.wait_for_key_ex()])?;
+            let key = match keyboard.read_keystroke_ex() {
+                Ok(key) => key.key,
+                Err(Status::NOT_READY) => continue,
+                Err(status) => return Err(status),
+            };
+            let new_top = match (key.scancode, key.char()) {
+                (InputKey::SCAN_ESC, _) | (_, Some('\r' | '\n' | 'q')) => break,
+                (InputKey::SCAN_PAGE_UP, _) => top.saturating_sub(page),
+                (InputKey::SCAN_PAGE_DOWN, _) => (top + page).min(bottom),
+                (InputKey::SCAN_UP, _) => top.saturating_sub(1),
+                (InputKey::SCAN_DOWN, _) => (top + 1).min(bottom),
+                (InputKey::SCAN_HOME, _) => 0,
+                (InputKey::SCAN_END, _) => bottom,
+                _ => continue,
+            };
+            if new_top != top {
+                top = new_top;
+                self.draw(top)?;
+            }
+        }
+        self.draw(bottom)
+    }
+
+    /// Returns the number of lines the history takes up on screen
+    fn lines(&self) -> usize {
+        let mut lines = 0;
+        self.layout(|line, _, _| {
+            lines = line + 1;
+            true
+        });
+        lines.max(1)
+    }
+
+    /// Clears the screen and draws the history from line `top` onwards
+    fn draw(&mut self, top: usize) -> Result<()> {
+        self.console.clear()?;
+        let (_, rows) = self.console.size();
+        let mut cursor = (0, 0);
+        let mut result = Ok(());
+        let Self { console, history } = self;
+        layout(history, console.size().0, |line, column, c| {
+            let Some(row) = line.checked_sub(top).filter(|&row| row < rows) else {
+                return line < top;
+            };
+            cursor = (column, row);
+            if let Some(c) = c {
+                console.set_cursor(column, row);
+                result = console.put(c);
+                cursor.0 += 1;
+            }
+            result.is_ok()
+        });
+        result?;
+        self.console.set_cursor(cursor.0, cursor.1);
+        Ok(())
+    }
+
+    fn layout(&self, f: impl FnMut(usize, usize, Option<char>) -> bool) {
+        layout(&self.history, self.console.size().0, f)
+    }
+}
+
+/// Wraps the history to `columns`, calling `f` with the line, column and character of each
+/// visible character until it returns `false`
+///
+/// `f` is also called with no character for the position after each line break, so the end
+/// of the text is always reported.
+fn layout<const N: usize>(
+    history: &RingBuffer<N>,
+    columns: usize,
+    mut f: impl FnMut(usize, usize, Option<char>) -> bool,
+) {
+    let (first, second) = history.as_slices();
+    let mut chars = [first, second]
+        .into_iter()
+        .flat_map(|bytes| bytes.utf8_chunks())
+        .flat_map(|chunk| {
+            // A character split by the ring buffer's wrap point is replaced as well.
+            let invalid = (!chunk.invalid().is_empty()).then_some(char::REPLACEMENT_CHARACTER);
+            chunk.valid().chars().chain(invalid)
+        })
+        .peekable();
+    if history.has_wrapped() {
+        // The oldest line is likely missing its start.
+        while chars.next_if(|&c| c != '\n').is_some() {}
+        chars.next();
+    }
+
+    let columns = columns.max(1);
+    let (mut line, mut column) = (0, 0);
+    if !f(line, column, None) {
+        return;
+    }
+    for c in chars {
+        match c {
+            '\n' => {
+                line += 1;
+                column = 0;
+                if !f(line, column, None) {
+                    return;
+                }
+            }
+            '\r' => column = 0,
+            '\t' => column = ((column / TAB_WIDTH + 1) * TAB_WIDTH).min(columns),
+            c => {
+                if column == columns {
+                    line += 1;
+                    column = 0;
+                }
+                if !f(line, column, Some(c)) {
+                    return;
+                }
+                column += 1;
+            }
+        }
+    }
+}
+
+impl<const N: usize> fmt::Write for Scrollback<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.history.write_bytes(s.as_bytes());
+        self.console.write_str(s)
+    }
+}
@@ -0,0 +1,303 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Double-buffered drawing
+//!
+//! A [`Surface`] is drawn to in memory, then [flushed](Surface::flush) to the screen with one
+//! block transfer per dirty rectangle. This avoids both tearing and slow per-pixel writes to
+//! the (uncached) framebuffer.
+
+use core::{mem::size_of, ptr::NonNull, slice};
+
+use super::{image::ImageRef, Gfx};
+use crate::{
+    boot_services, default_memory_type,
+    proto::console::gop::{Blt, BltPixel},
+    table::AllocPagesType,
+    Result, Status, PAGE_SIZE,
+};
+
+/// Most dirty rectangles tracked before they start being merged
+const MAX_DIRTY: usize = 16;
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Rect {
+    pub x:      usize,
+    pub y:      usize,
+    pub width:  usize,
+    pub height: usize,
+}
+
+impl Rect {
+    pub const fn new(x: usize, y: usize, width: usize, height: usize) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    pub const fn area(&self) -> usize {
+        self.width * self.height
+    }
+
+    const fn right(&self) -> usize {
+        self.x.saturating_add(self.width)
+    }
+
+    const fn bottom(&self) -> usize {
+        self.y.saturating_add(self.height)
+    }
+
+    /// Returns the smallest rectangle containing both rectangles
+    pub fn union(&self, other: &Rect) -> Rect {
+        if self.is_empty() {
+            return *other;
+        }
+        if other.is_empty() {
+            return *self;
+        }
+        let (x, y) = (self.x.min(other.x), self.y.min(other.y));
+        Rect::new(
+            x,
+            y,
+            self.right().max(other.right()) - x,
+            self.bottom().max(other.bottom()) - y,
+        )
+    }
+
+    /// Returns the overlap of the two rectangles, which may be empty
+    pub fn intersection(&self, other: &Rect) -> Rect {
+        let (x, y) = (self.x.max(other.x), self.y.max(other.y));
+        let right = self.right().min(other.right());
+        let bottom = self.bottom().min(other.bottom());
+        Rect::new(x, y, right.saturating_sub(x), bottom.saturating_sub(y))
+    }
+
+    pub fn contains(&self, other: &Rect) -> bool {
+        other.x >= self.x
+            && other.y >= self.y
+            && other.right() <= self.right()
+            && other.bottom() <= self.bottom()
+    }
+
+    /// Returns `true` if the rectangles overlap or share an edge
+    fn touches(&self, other: &Rect) -> bool {
+        self.x <= other.right()
+            && other.x <= self.right()
+            && self.y <= other.bottom()
+            && other.y <= self.bottom()
+    }
+}
+
+/// Back buffer in pages of the [default memory type](crate::default_memory_type)
+pub struct Surface {
+    width:  usize,
+    height: usize,
+    pixels: NonNull<BltPixel>,
+    pages:  usize,
+    dirty:  [Rect; MAX_DIRTY],
+    ndirty: usize,
+}
+
+impl Surface {
+    /// Allocates a `width` by `height` surface, cleared to black
+    pub fn new(width: usize, height: usize) -> Result<Self> {
+        let bytes = width
+            .checked_mul(height)
+            .and_then(|n| n.checked_mul(size_of::<BltPixel>()))
+            .ok_or(Status::OUT_OF_RESOURCES)?;
+        let pages = bytes.div_ceil(PAGE_SIZE).max(1);
+        let addr =
+            boot_services().allocate_pages(AllocPagesType::Any, default_memory_type(), pages)?;
+        let pixels = NonNull::new(addr as *mut BltPixel).ok_or(Status::OUT_OF_RESOURCES)?;
+        unsafe { pixels.as_ptr().write_bytes(0, width * height) };
+        Ok(Self {
+            width,
+            height,
+            pixels,
+            pages,
+            dirty: [Rect::default(); MAX_DIRTY],
+            ndirty: 0,
+        })
+    }
+
+    /// Allocates a surface the size of the current graphics mode
+    pub fn for_screen(gfx: &mut Gfx) -> Result<Self> {
+        let (width, height) = gfx.resolution()?;
+        Self::new(width, height)
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn bounds(&self) -> Rect {
+        Rect::new(0, 0, self.width, self.height)
+    }
+
+    pub fn pixels(&self) -> &[BltPixel] {
+        unsafe { slice::from_raw_parts(self.pixels.as_ptr(), self.width * self.height) }
+    }
+
+    /// Returns the pixels for direct drawing
+    ///
+    /// Changes are not tracked; use [`mark_dirty()`](Self::mark_dirty) afterwards.
+    pub fn pixels_mut(&mut self) -> &mut [BltPixel] {
+        unsafe { slice::from_raw_parts_mut(self.pixels.as_ptr(), self.width * self.height) }
+    }
+
+    /// Records that `rect` must be redrawn on the next flush
+    pub fn mark_dirty(&mut self, rect: Rect) {
+        let mut rect = rect.intersection(&self.bounds());
+        if rect.is_empty() {
+            return;
+        }
+
+        // Absorb any rectangles this one touches, repeating since the union may grow to
+        // touch others.
+        let mut i = 0;
+        while i < self.ndirty {
+            if self.dirty[i].contains(&rect) {
+                return;
+            }
+            if self.dirty[i].touches(&rect) {
+                rect = rect.union(&self.dirty[i]);
+                self.ndirty -= 1;
+                self.dirty[i] = self.dirty[self.ndirty];
+                i = 0;
+            } else {
+                i += 1;
+            }
+        }
+
+        if self.ndirty == MAX_DIRTY {
+            // Merge with whichever rectangle grows the least.
+            let (index, _) = self.dirty[..self.ndirty]
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, dirty)| dirty.union(&rect).area() - dirty.area())
+                .unwrap();
+            self.dirty[index] = self.dirty[index].union(&rect);
+            return;
+        }
+        self.dirty[self.ndirty] = rect;
+        self.ndirty += 1;
+    }
+
+    pub fn mark_all_dirty(&mut self) {
+        self.dirty[0] = self.bounds();
+        self.ndirty = 1;
+    }
+
+    pub fn dirty_rects(&self) -> &[Rect] {
+        &self.dirty[..self.ndirty]
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: BltPixel) {
+        if x < self.width && y < self.height {
+            let width = self.width;
+            self.pixels_mut()[y * width + x] = color;
+            self.mark_dirty(Rect::new(x, y, 1, 1));
+        }
+    }
+
+    pub fn fill_rect(&mut self, rect: Rect, color: BltPixel) {
+        let rect = rect.intersection(&self.bounds());
+        if rect.is_empty() {
+            return;
+        }
+        let width = self.width;
+        let pixels = self.pixels_mut();
+        for y in rect.y..rect.bottom() {
+            pixels[y * width + rect.x..][..rect.width].fill(color);
+        }
+        self.mark_dirty(rect);
+    }
+
+    pub fn clear(&mut self, color: BltPixel) {
+        self.fill_rect(self.bounds(), color);
+    }
+
+    /// Copies `image` to `(x, y)`, clipping it to the surface
+    pub fn draw_image(&mut self, image: ImageRef, x: usize, y: usize) {
+        let rect = Rect::new(x, y, image.width, image.height).intersection(&self.bounds());
+        if rect.is_empty() {
+            return;
+        }
+        let width = self.width;
+        let pixels = self.pixels_mut();
+        for row in 0..rect.height {
+            let src = &image.pixels[row * image.width..][..rect.width];
+            pixels[(rect.y + row) * width + rect.x..][..rect.width].copy_from_slice(src);
+        }
+        self.mark_dirty(rect);
+    }
+
+    /// Copies the dirty rectangles to the screen at `(x, y)`, and clears them
+    pub fn flush_at(&mut self, gfx: &mut Gfx, x: usize, y: usize) -> Result<()> {
+        for i in 0..self.ndirty {
+            let rect = self.dirty[i];
+            let start = rect.y * self.width + rect.x;
+            gfx.write_buffer(
+                &self.pixels()[start..],
+                self.width,
+                x + rect.x,
+                y + rect.y,
+                rect.width,
+                rect.height,
+            )?;
+        }
+        self.ndirty = 0;
+        Ok(())
+    }
+
+    /// Copies the dirty rectangles to the top left of the screen, and clears them
+    pub fn flush(&mut self, gfx: &mut Gfx) -> Result<()> {
+        self.flush_at(gfx, 0, 0)
+    }
+}
+
+impl Drop for Surface {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = boot_services().free_pages(self.pixels.as_ptr() as u64, self.pages);
+        }
+    }
+}
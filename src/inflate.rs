@@ -0,0 +1,366 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! DEFLATE (RFC 1951) and zlib (RFC 1950) decompression
+//!
+//! A small canonical-Huffman decoder in the style of zlib's `puff`: it favours size over
+//! speed, and decompresses into a caller-provided buffer without allocating.
+
+use crate::{Result, Status};
+
+const MAX_BITS: usize = 15;
+const MAX_LIT_CODES: usize = 288;
+const MAX_DIST_CODES: usize = 30;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// Order in which code length code lengths are stored
+const CLEN_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+struct Bits<'a> {
+    src:   &'a [u8],
+    pos:   usize,
+    buf:   u32,
+    count: u32,
+}
+
+impl<'a> Bits<'a> {
+    fn new(src: &'a [u8]) -> Self {
+        Self {
+            src,
+            pos: 0,
+            buf: 0,
+            count: 0,
+        }
+    }
+
+    /// Reads `n` (at most 16) bits, least significant first
+    fn bits(&mut self, n: u32) -> Result<u32> {
+        while self.count < n {
+            let byte = *self.src.get(self.pos).ok_or(Status::INVALID_PARAMETER)?;
+            self.pos += 1;
+            self.buf |= (byte as u32) << self.count;
+            self.count += 8;
+        }
+        let value = self.buf & ((1 << n) - 1);
+        self.buf >>= n;
+        self.count -= n;
+        Ok(value)
+    }
+
+    /// Discards the bits remaining in the current byte
+    fn align(&mut self) {
+        self.buf = 0;
+        self.count = 0;
+    }
+}
+
+struct Huffman<const N: usize> {
+    /// Number of codes of each length
+    counts:  [u16; MAX_BITS + 1],
+    /// Symbols ordered by code
+    symbols: [u16; N],
+}
+
+impl<const N: usize> Huffman<N> {
+    fn new(lengths: &[u8]) -> Result<Self> {
+        let mut h = Self {
+            counts:  [0; MAX_BITS + 1],
+            symbols: [0; N],
+        };
+        for &len in lengths {
+            h.counts[len as usize] += 1;
+        }
+
+        // Reject over-subscribed sets; incomplete ones are allowed (e.g. a single distance code).
+        let mut left = 1i32;
+        for len in 1..=MAX_BITS {
+            left = (left << 1) - h.counts[len] as i32;
+            if left < 0 {
+                return Err(Status::INVALID_PARAMETER);
+            }
+        }
+
+        let mut offsets = [0u16; MAX_BITS + 1];
+        for len in 1..MAX_BITS {
+            offsets[len + 1] = offsets[len] + h.counts[len];
+        }
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                h.symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        Ok(h)
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Result<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..=MAX_BITS {
+            code |= bits.bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - count < first {
+                return Ok(self.symbols[(index + (code - first)) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(Status::INVALID_PARAMETER)
+    }
+}
+
+/// Where decoded bytes go; with no buffer they are only counted, as `puff` does
+struct Output<'a> {
+    dst: Option<&'a mut [u8]>,
+    pos: usize,
+}
+
+impl Output<'_> {
+    fn push(&mut self, byte: u8) -> Result<()> {
+        if let Some(dst) = &mut self.dst {
+            *dst.get_mut(self.pos).ok_or(Status::BUFFER_TOO_SMALL)? = byte;
+        }
+        self.pos += 1;
+        Ok(())
+    }
+}
+
+fn stored(bits: &mut Bits, out: &mut Output) -> Result<()> {
+    bits.align();
+    let header = bits
+        .src
+        .get(bits.pos..bits.pos + 4)
+        .ok_or(Status::INVALID_PARAMETER)?;
+    let len = u16::from_le_bytes([header[0], header[1]]);
+    let nlen = u16::from_le_bytes([header[2], header[3]]);
+    if len != !nlen {
+        return Err(Status::INVALID_PARAMETER);
+    }
+    bits.pos += 4;
+
+    let len = len as usize;
+    let data = bits
+        .src
+        .get(bits.pos..bits.pos + len)
+        .ok_or(Status::INVALID_PARAMETER)?;
+    if let Some(dst) = &mut out.dst {
+        dst.get_mut(out.pos..out.pos + len)
+            .ok_or(Status::BUFFER_TOO_SMALL)?
+            .copy_from_slice(data);
+    }
+    bits.pos += len;
+    out.pos += len;
+    Ok(())
+}
+
+fn codes(
+    bits: &mut Bits,
+    out: &mut Output,
+    lit: &Huffman<MAX_LIT_CODES>,
+    dist: &Huffman<MAX_DIST_CODES>,
+) -> Result<()> {
+    loop {
+        let symbol = lit.decode(bits)? as usize;
+        match symbol {
+            0..=255 => out.push(symbol as u8)?,
+            256 => return Ok(()),
+            _ => {
+                let symbol = symbol - 257;
+                if symbol >= LENGTH_BASE.len() {
+                    return Err(Status::INVALID_PARAMETER);
+                }
+                let len =
+                    LENGTH_BASE[symbol] as usize + bits.bits(LENGTH_EXTRA[symbol] as u32)? as usize;
+
+                let symbol = dist.decode(bits)? as usize;
+                if symbol >= DIST_BASE.len() {
+                    return Err(Status::INVALID_PARAMETER);
+                }
+                let distance =
+                    DIST_BASE[symbol] as usize + bits.bits(DIST_EXTRA[symbol] as u32)? as usize;
+                if distance > out.pos {
+                    return Err(Status::INVALID_PARAMETER);
+                }
+                let Some(dst) = &mut out.dst else {
+                    out.pos += len;
+                    continue;
+                };
+                if out.pos + len > dst.len() {
+                    return Err(Status::BUFFER_TOO_SMALL);
+                }
+                // The source may overlap the output, so copy byte by byte.
+                for _ in 0..len {
+                    dst[out.pos] = dst[out.pos - distance];
+                    out.pos += 1;
+                }
+            }
+        }
+    }
+}
+
+fn fixed(bits: &mut Bits, out: &mut Output) -> Result<()> {
+    let mut lengths = [0u8; MAX_LIT_CODES];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    let lit = Huffman::new(&lengths)?;
+    let dist = Huffman::new(&[5; MAX_DIST_CODES])?;
+    codes(bits, out, &lit, &dist)
+}
+
+fn dynamic(bits: &mut Bits, out: &mut Output) -> Result<()> {
+    let nlen = bits.bits(5)? as usize + 257;
+    let ndist = bits.bits(5)? as usize + 1;
+    let ncode = bits.bits(4)? as usize + 4;
+    if nlen > MAX_LIT_CODES || ndist > MAX_DIST_CODES {
+        return Err(Status::INVALID_PARAMETER);
+    }
+
+    let mut lengths = [0u8; MAX_LIT_CODES + MAX_DIST_CODES];
+    for &index in &CLEN_ORDER[..ncode] {
+        lengths[index] = bits.bits(3)? as u8;
+    }
+    let clen = Huffman::<19>::new(&lengths[..19])?;
+
+    let mut index = 0;
+    while index < nlen + ndist {
+        let symbol = clen.decode(bits)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                let prev = *lengths[..index].last().ok_or(Status::INVALID_PARAMETER)?;
+                (prev, 3 + bits.bits(2)? as usize)
+            }
+            17 => (0, 3 + bits.bits(3)? as usize),
+            _ => (0, 11 + bits.bits(7)? as usize),
+        };
+        if index + repeat > nlen + ndist {
+            return Err(Status::INVALID_PARAMETER);
+        }
+        lengths[index..index + repeat].fill(value);
+        index += repeat;
+    }
+    if index < nlen + ndist || lengths[256] == 0 {
+        return Err(Status::INVALID_PARAMETER);
+    }
+
+    let lit = Huffman::new(&lengths[..nlen])?;
+    let dist = Huffman::new(&lengths[nlen..nlen + ndist])?;
+    codes(bits, out, &lit, &dist)
+}
+
+/// Decompresses a raw DEFLATE stream into `dst`
+///
+/// Returns the number of bytes written and the number of bytes of `src` consumed.
+pub fn inflate(src: &[u8], dst: &mut [u8]) -> Result<(usize, usize)> {
+    decode_stream(src, Some(dst))
+}
+
+/// Decodes a raw DEFLATE stream without storing the output
+///
+/// Returns the decompressed size and the number of bytes of `src` consumed.
+#[cfg(feature = "gzip")]
+pub fn inflate_size(src: &[u8]) -> Result<(usize, usize)> {
+    decode_stream(src, None)
+}
+
+fn decode_stream(src: &[u8], dst: Option<&mut [u8]>) -> Result<(usize, usize)> {
+    let mut bits = Bits::new(src);
+    let mut out = Output { dst, pos: 0 };
+    loop {
+        let last = bits.bits(1)? == 1;
+        match bits.bits(2)? {
+            0 => stored(&mut bits, &mut out)?,
+            1 => fixed(&mut bits, &mut out)?,
+            2 => dynamic(&mut bits, &mut out)?,
+            _ => return Err(Status::INVALID_PARAMETER),
+        }
+        if last {
+            return Ok((out.pos, bits.pos));
+        }
+    }
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    // 5552 is the most bytes which can be summed before `b` can overflow.
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    b << 16 | a
+}
+
+/// Decompresses a zlib stream into `dst`, verifying its checksum
+///
+/// Returns the number of bytes written.
+pub fn zlib_decompress(src: &[u8], dst: &mut [u8]) -> Result<usize> {
+    let [cmf, flg, ref data @ ..] = *src else {
+        return Err(Status::INVALID_PARAMETER);
+    };
+    if cmf & 0x0f != 8 || !(cmf as u16 * 256 + flg as u16).is_multiple_of(31) {
+        return Err(Status::INVALID_PARAMETER);
+    }
+    if flg & 0x20 != 0 {
+        // Preset dictionaries aren't used by any format we read.
+        return Err(Status::UNSUPPORTED);
+    }
+
+    let (written, consumed) = inflate(data, dst)?;
+    let checksum = data
+        .get(consumed..consumed + 4)
+        .ok_or(Status::INVALID_PARAMETER)?;
+    if u32::from_be_bytes(checksum.try_into().unwrap()) != adler32(&dst[..written]) {
+        return Err(Status::CRC_ERROR);
+    }
+    Ok(written)
+}
@@ -0,0 +1,171 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Unified keyboard and pointer input
+//!
+//! [`EventStream`] waits on both the console's keyboard and a pointer device, so interactive
+//! pre-boot UIs can handle both from a single loop.
+
+use core::{ptr, time::Duration};
+
+use crate::{
+    boot_services,
+    proto::{
+        console::{
+            pointer::{PointerState, SimplePointer},
+            text_input_ex::{KeyData, SimpleTextInputEx},
+        },
+        Proto,
+    },
+    system_table,
+    table::TimerDelay,
+    Event, Result, Status,
+};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum InputEvent {
+    Key(KeyData),
+    Pointer(PointerState),
+}
+
+pub struct EventStream {
+    keyboard: Option<Proto<SimpleTextInputEx>>,
+    pointer:  Option<Proto<SimplePointer>>,
+}
+
+impl EventStream {
+    pub fn new(
+        keyboard: Option<Proto<SimpleTextInputEx>>,
+        pointer: Option<Proto<SimplePointer>>,
+    ) -> Self {
+        Self { keyboard, pointer }
+    }
+
+    /// Uses the console input device's keyboard and the first pointer device found
+    ///
+    /// Returns `NOT_FOUND` if there is neither.
+    pub fn locate() -> Result<Self> {
+        let bs = boot_services();
+        let keyboard = bs
+            .protocol_for_handle::<SimpleTextInputEx>(system_table().stdin_handle)
+            .or_else(|_| bs.first_protocol::<SimpleTextInputEx>())
+            .ok();
+        let pointer = bs.first_protocol::<SimplePointer>().ok();
+        if keyboard.is_none() && pointer.is_none() {
+            return Err(Status::NOT_FOUND);
+        }
+        Ok(Self::new(keyboard, pointer))
+    }
+
+    pub fn keyboard(&mut self) -> Option<&mut SimpleTextInputEx> {
+        self.keyboard.as_deref_mut()
+    }
+
+    pub fn pointer(&mut self) -> Option<&mut SimplePointer> {
+        self.pointer.as_deref_mut()
+    }
+
+    /// Returns the next pending event without waiting
+    pub fn poll(&mut self) -> Result<Option<InputEvent>> {
+        if let Some(keyboard) = &mut self.keyboard {
+            match keyboard.read_keystroke_ex() {
+                Ok(key) => return Ok(Some(InputEvent::Key(key))),
+                Err(Status::NOT_READY) => {}
+                Err(status) => return Err(status),
+            }
+        }
+        if let Some(pointer) = &mut self.pointer {
+            match pointer.get_state() {
+                Ok(state) => return Ok(Some(InputEvent::Pointer(state))),
+                Err(Status::NOT_READY) => {}
+                Err(status) => return Err(status),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Waits for the next event
+    pub fn next_event(&mut self) -> Result<InputEvent> {
+        loop {
+            if let Some(event) = self.wait(None)? {
+                return Ok(event);
+            }
+        }
+    }
+
+    /// Waits up to `timeout` for the next event
+    pub fn next_timeout(&mut self, timeout: Duration) -> Result<Option<InputEvent>> {
+        let bs = boot_services();
+        let timer = bs.create_timer_event()?;
+        let ticks = u64::try_from(timeout.as_nanos() / 100).unwrap_or(u64::MAX);
+        let result = bs
+            .set_timer(timer, TimerDelay::Relative, ticks)
+            .and_then(|()| self.wait(Some(timer)));
+        let _ = bs.close_event(timer);
+        result
+    }
+
+    /// Waits for an event, or for `timer` to be signaled
+    fn wait(&mut self, timer: Option<Event>) -> Result<Option<InputEvent>> {
+        let sources = [
+            self.keyboard.as_ref().map(|k| k.wait_for_key_ex()),
+            self.pointer.as_ref().map(|p| p.wait_for_input()),
+            timer,
+        ];
+        let mut events = [Event(ptr::null_mut()); 3];
+        let mut len = 0;
+        for event in sources.into_iter().flatten() {
+            events[len] = event;
+            len += 1;
+        }
+        if len == 0 {
+            return Err(Status::NOT_READY);
+        }
+
+        loop {
+            if let Some(event) = self.poll()? {
+                return Ok(Some(event));
+            }
+            let index = boot_services().wait_for_event(&events[..len])?;
+            if timer.is_some() && index == len - 1 {
+                return self.poll();
+            }
+        }
+    }
+}
+
+impl Iterator for EventStream {
+    type Item = Result<InputEvent>;
+
+    /// Waits for the next event; never returns `None`
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.next_event())
+    }
+}
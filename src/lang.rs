@@ -0,0 +1,94 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Language codes
+//!
+//! Firmware names languages with RFC 4646 codes such as `en-US`: `PlatformLang` holds one,
+//! while `PlatformLangCodes`, HII string packages and
+//! [`UnicodeCollation::supported_languages()`](crate::proto::unicode_collation::UnicodeCollation::supported_languages)
+//! hold semicolon-separated lists of them. [`best_language()`] picks the entry of such a
+//! list closest to the user's preferences, so boot menus which ship translations can follow
+//! the platform's language; [`Global::language()`](crate::vars::Global::language) does so for
+//! `PlatformLang`.
+
+/// Iterates over the codes in a semicolon-separated list, skipping empty entries
+pub fn codes(list: &str) -> impl Iterator<Item = &str> {
+    list.split(';')
+        .map(|code| code.trim_matches(|c: char| c == '\0' || c.is_ascii_whitespace()))
+        .filter(|code| !code.is_empty())
+}
+
+/// Returns whether `tag` falls within the language `range`
+///
+/// Comparison is case-insensitive, and a range matches any tag it is a prefix of at a subtag
+/// boundary, so `en` matches `en-US` but not `eng`. The range `*` matches every tag.
+pub fn matches(range: &str, tag: &str) -> bool {
+    if range == "*" {
+        return true;
+    }
+    match tag.get(..range.len()) {
+        Some(prefix) if prefix.eq_ignore_ascii_case(range) => {
+            matches!(tag.as_bytes().get(range.len()), None | Some(b'-'))
+        }
+        _ => false,
+    }
+}
+
+/// Selects the supported language which best fits the preferences, in order
+///
+/// Follows the lookup scheme of RFC 4647: each preference is compared against every code in
+/// `supported`, then shortened by a subtag and compared again, so `de-CH-1996` falls back to
+/// `de-CH` and then `de`. Earlier preferences take priority over closer matches of later
+/// ones. Returns `None` if nothing fits, in which case callers usually take the first
+/// supported language.
+pub fn best_language<'s>(supported: &'s str, preferred: &[&str]) -> Option<&'s str> {
+    preferred.iter().find_map(|&lang| {
+        let mut range = lang.trim();
+        while !range.is_empty() {
+            if let Some(code) = codes(supported).find(|code| code.eq_ignore_ascii_case(range)) {
+                return Some(code);
+            }
+            range = truncate(range);
+        }
+        None
+    })
+}
+
+/// Removes the last subtag of a range, along with a singleton such as `x` left before it
+fn truncate(range: &str) -> &str {
+    let Some((rest, _)) = range.rsplit_once('-') else {
+        return "";
+    };
+    match rest.rsplit_once('-') {
+        Some((head, singleton)) if singleton.len() == 1 => head,
+        None if rest.len() == 1 => "",
+        _ => rest,
+    }
+}
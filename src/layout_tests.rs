@@ -0,0 +1,287 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Compile-time layout assertions
+//!
+//! Every structure shared with the firmware has its size (and the offsets of any fields
+//! whose position depends on the target's data model) checked here, so ABI drift breaks
+//! the build instead of a boot.
+//!
+//! The expected values are taken from the EDK2 headers, for the ILP32 (IA32, ARM) and LP64
+//! (X64, AArch64, RISC-V 64) data models. Both models give 64-bit integers 8-byte alignment.
+
+use core::mem::{align_of, offset_of, size_of};
+
+use crate::{
+    proto::{
+        arch::{mp_services::*, riscv::*},
+        bluetooth::*,
+        console::{
+            console_control::*, gop::*, pointer::*, serial::*, text_input::*, text_input_ex::*,
+            text_output::*, uga::*,
+        },
+        decompress::*,
+        device_path::*,
+        driver_override::*,
+        firmware_volume::*,
+        hash2::*,
+        loaded_image::*,
+        media::{
+            block_io::*, block_io2::*, disk_io::*, disk_io2::*, file::*, load_file::*,
+            partition::GptEntry,
+        },
+        memory_attribute::*,
+        mm::*,
+        network::{http::*, rest::*, supplicant::*, wifi::*},
+        shell::*,
+        speaker::*,
+        timestamp::*,
+        unicode_collation::*,
+        variable_policy::*,
+        Proto,
+    },
+    table::*,
+    Guid, Handle, Status,
+};
+
+/// Selects the expected value for the target's pointer width
+const fn w(ilp32: usize, lp64: usize) -> usize {
+    if cfg!(target_pointer_width = "64") {
+        lp64
+    } else {
+        ilp32
+    }
+}
+
+macro_rules! assert_layout {
+    ($ty:ty, size = $size:expr $(, $field:ident @ $offset:expr)* $(,)?) => {
+        const _: () = {
+            assert!(
+                size_of::<$ty>() == $size,
+                concat!("size of `", stringify!($ty), "` does not match the UEFI ABI"),
+            );
+            $(
+                assert!(
+                    offset_of!($ty, $field) == $offset,
+                    concat!(
+                        "offset of `", stringify!($ty), "::", stringify!($field),
+                        "` does not match the UEFI ABI",
+                    ),
+                );
+            )*
+        };
+    };
+}
+
+const _: () = assert!(align_of::<Guid>() == 4);
+assert_layout!(Guid, size = 16, b @ 4, c @ 6, d @ 8);
+
+assert_layout!(Status, size = w(4, 8));
+assert_layout!(Handle, size = w(4, 8));
+assert_layout!(Proto<BlockIo>, size = w(4, 8));
+assert_layout!(Option<Proto<BlockIo>>, size = w(4, 8));
+
+assert_layout!(TableHeader, size = 24);
+
+assert_layout!(
+    SystemTable,
+    size = w(72, 120),
+    firmware_vendor @ 24,
+    firmware_revision @ w(28, 32),
+    stdin_handle @ w(32, 40),
+    stdout_handle @ w(40, 56),
+    stderr_handle @ w(48, 72),
+    runtime_services @ w(56, 88),
+    boot_services @ w(60, 96),
+    config_table_entries @ w(64, 104),
+    config_table @ w(68, 112),
+);
+
+// 44 function pointers (including the reserved slot) following the header
+assert_layout!(BootServices, size = w(200, 376), header @ 0);
+assert_layout!(RawBootServices, size = w(200, 376), header @ 0, create_event_ex @ w(196, 368));
+
+assert_layout!(ConfigurationEntry, size = w(20, 24), vendor_table @ 16);
+
+assert_layout!(RuntimeServices, size = w(80, 136), get_time @ 24, query_variable_info @ w(76, 128));
+assert_layout!(TimeCapabilities, size = 12, sets_to_zero @ 8);
+assert_layout!(CapsuleHeader, size = 28);
+
+assert_layout!(RuntimeProperties, size = 8, runtime_services_supported @ 4);
+
+assert_layout!(
+    MemoryDescriptor,
+    size = 40,
+    phys @ 8,
+    virt @ 16,
+    num_pages @ 24,
+    attribute @ 32,
+);
+
+assert_layout!(OpenProtocolInformationEntry, size = w(16, 24), attributes @ w(8, 16));
+
+assert_layout!(SimpleTextInput, size = w(12, 24));
+assert_layout!(InputKey, size = 4, codepoint @ 2);
+
+assert_layout!(SimpleTextInputEx, size = w(24, 48), wait_for_key_ex @ w(8, 16));
+assert_layout!(KeyState, size = 8, toggle_state @ 4);
+assert_layout!(KeyData, size = 12, state @ 4);
+
+assert_layout!(SimplePointer, size = w(16, 32), wait_for_input @ w(8, 16));
+assert_layout!(PointerState, size = 16, left_button @ 12, right_button @ 13);
+assert_layout!(PointerMode, size = 32, left_button @ 24);
+
+assert_layout!(SerialIo, size = w(36, 72), mode @ w(28, 56), device_type_guid @ w(32, 64));
+assert_layout!(SerialIoMode, size = 32, baud_rate @ 8, stop_bits @ 28);
+
+assert_layout!(SimpleTextOutput, size = w(40, 80));
+assert_layout!(SimpleTextOutputMode, size = 24, cursor_visible @ 20);
+
+assert_layout!(GraphicsOutput, size = w(16, 32));
+assert_layout!(PixelBitmask, size = 16);
+assert_layout!(ModeInfo, size = 36, pixel_format @ 12, pixel_info @ 16, pixels_per_scanline @ 32);
+assert_layout!(BltPixel, size = 4, red @ 2);
+assert_layout!(UgaDraw, size = w(12, 24));
+
+assert_layout!(ConsoleControl, size = w(12, 24));
+assert_layout!(ScreenMode, size = 4);
+
+assert_layout!(
+    LoadedImage,
+    size = w(64, 96),
+    load_options_size @ w(24, 48),
+    image_size @ w(40, 72),
+    image_code_type @ w(48, 80),
+);
+
+const _: () = assert!(align_of::<DevicePath>() == 1);
+assert_layout!(DevicePath, size = 4, sub_kind @ 1, length @ 2);
+
+assert_layout!(EdidDiscovered, size = w(8, 16));
+assert_layout!(EdidActive, size = w(8, 16));
+assert_layout!(EdidOverride, size = w(4, 8));
+
+assert_layout!(
+    Mode,
+    size = w(32, 40),
+    info_size @ w(12, 16),
+    framebuffer_addr @ w(16, 24),
+    framebuffer_size @ w(24, 32),
+);
+
+assert_layout!(Decompress, size = w(8, 16));
+assert_layout!(Hash2, size = w(20, 40));
+assert_layout!(Hash2Output, size = 64);
+assert_layout!(BlockIo, size = w(32, 48), revision @ 0);
+assert_layout!(BlockIo2, size = w(20, 40));
+assert_layout!(BlockIo2Token, size = w(8, 16));
+assert_layout!(DiskIo, size = w(16, 24), revision @ 0);
+assert_layout!(DiskIo2, size = w(24, 40), revision @ 0);
+assert_layout!(DiskIo2Token, size = w(8, 16));
+
+assert_layout!(
+    BlockIoMedia,
+    size = 48,
+    block_size @ 12,
+    last_block @ 24,
+    lowest_aligned_lba @ 32,
+    optimal_transfer_length_granularity @ 44,
+);
+
+assert_layout!(GptEntry, size = 128, starting_lba @ 32, attributes @ 48);
+
+assert_layout!(SimpleFileSystem, size = 16);
+assert_layout!(FileSystemInfo, size = 40, volume_size @ 16, block_size @ 32);
+assert_layout!(FileProtocol, size = w(64, 120));
+assert_layout!(FileIoToken, size = w(16, 32));
+assert_layout!(FileInfo, size = 80, create_time @ 24, attribute @ 72);
+assert_layout!(Time, size = 16, nanosecond @ 8, time_zone @ 12);
+
+assert_layout!(LoadFile2, size = w(4, 8));
+
+assert_layout!(MemoryAttributeProtocol, size = w(12, 24));
+
+assert_layout!(BluetoothAddress, size = 6);
+assert_layout!(BluetoothLeAddress, size = 7);
+assert_layout!(ScanCallbackInfo, size = 258);
+assert_layout!(BluetoothConfig, size = w(44, 88));
+assert_layout!(LeScanParameter, size = 12, scan_interval @ 6);
+assert_layout!(LeConnectParameter, size = 12);
+assert_layout!(LeScanCallbackInfo, size = w(24, 32));
+assert_layout!(BluetoothLeConfig, size = w(48, 96));
+assert_layout!(MmCommunication2, size = w(4, 8));
+
+assert_layout!(HttpRequestData, size = w(8, 16));
+assert_layout!(HttpHeader, size = w(8, 16));
+assert_layout!(HttpMessage, size = w(20, 40));
+assert_layout!(RestEx, size = w(24, 48));
+assert_layout!(RestExToken, size = w(12, 24));
+assert_layout!(Http, size = w(24, 48));
+assert_layout!(HttpToken, size = w(12, 24));
+assert_layout!(HttpConfigData, size = w(16, 24), access_point @ w(12, 16));
+assert_layout!(Httpv4AccessPoint, size = 12, local_port @ 10);
+assert_layout!(Httpv6AccessPoint, size = 18, local_port @ 16);
+assert_layout!(Supplicant, size = w(16, 32));
+assert_layout!(FragmentData, size = w(8, 16));
+assert_layout!(WirelessMacConnection2, size = w(12, 24));
+assert_layout!(Ssid, size = 33);
+assert_layout!(
+    Network,
+    size = w(48, 56),
+    akm_suite @ w(40, 40),
+    cipher_suite @ w(44, 48),
+);
+assert_layout!(NetworkDescription, size = w(52, 64), quality @ w(48, 56));
+assert_layout!(ConnectNetworkToken, size = w(16, 32));
+
+assert_layout!(PlatformDriverOverride, size = w(12, 24));
+assert_layout!(BusSpecificDriverOverride, size = w(4, 8));
+
+assert_layout!(
+    FirmwareVolume2,
+    size = w(40, 80),
+    key_size @ w(24, 48),
+    parent_handle @ w(28, 56),
+);
+
+assert_layout!(RiscvBoot, size = 16, revision @ 0);
+assert_layout!(MpServices, size = w(28, 56));
+assert_layout!(ProcessorInformation, size = 24, status @ 8, location @ 12);
+
+assert_layout!(ShellParameters, size = w(20, 40), stdin @ w(8, 16));
+assert_layout!(ShellDynamicCommand, size = w(12, 24));
+assert_layout!(SpeakerIo, size = w(8, 16), set_beep_tone @ 0, generate_beep @ w(4, 8));
+
+assert_layout!(Timestamp, size = w(8, 16));
+assert_layout!(TimestampProperties, size = 16);
+
+assert_layout!(UnicodeCollation, size = w(28, 56));
+
+assert_layout!(VariablePolicy, size = w(32, 48));
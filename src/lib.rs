@@ -41,16 +41,84 @@ extern crate alloc;
 #[cfg(feature = "limine")]
 extern crate limine;
 
+#[cfg(feature = "alloc")]
+pub mod alloc_stats;
+#[cfg(feature = "alloc_tracker")]
+pub mod alloc_tracker;
+pub mod arch;
+pub mod arena;
+pub mod audio;
+pub mod bootlog;
+pub mod cmdline;
+pub mod config;
+pub mod crc32;
+pub mod debug;
+pub mod decompress;
+pub mod dma;
+#[cfg(feature = "elf")]
+pub mod elf;
+pub mod executor;
+pub mod fdt;
+pub mod graphics;
+pub mod input;
+pub mod lang;
+#[cfg(feature = "linux")]
+pub mod linux;
+#[cfg(feature = "mock")]
+pub mod mock;
+#[cfg(feature = "multiboot2")]
+pub mod multiboot2;
+pub mod output;
+pub mod pe;
+pub mod perf;
+pub mod progress;
 pub mod proto;
+pub mod quirks;
+pub mod sha256;
+pub mod smbios;
+pub mod stdio;
 pub mod table;
+pub mod time;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod ucs2;
+pub mod vars;
+pub mod verify;
+pub mod warning;
+
+#[cfg(any(feature = "png", feature = "gzip"))]
+mod inflate;
+mod le;
+mod sync;
+mod trace;
+
+mod layout_tests;
+
+#[cfg(not(any(
+    target_arch = "x86",
+    target_arch = "x86_64",
+    target_arch = "arm",
+    target_arch = "aarch64",
+    target_arch = "riscv64",
+)))]
+compile_error!("unsupported target architecture");
 
-use core::{ffi::c_void, ptr::{NonNull, self}, sync::atomic::{AtomicPtr, Ordering}};
+use core::{ffi::c_void, ptr::{NonNull, self}, sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, Ordering}};
 
-use table::{SystemTable, BootServices};
+use table::{SystemTable, BootServices, Capabilities, MemoryType, RuntimeServices};
+
+pub use stdio::{stderr, stdout};
 
 pub type Result<T> = core::result::Result<T, Status>;
 
-#[repr(C, align(8))]
+/// Size of the pages allocated by [`BootServices::allocate_pages()`], as a `usize`
+pub(crate) const PAGE_SIZE: usize = table::MemoryDescriptor::PAGE_SIZE as usize;
+
+/// 128-bit globally unique identifier
+///
+/// Firmware (and the EDK2 headers) only give this structure 32-bit alignment, which is what
+/// makes e.g. the configuration table 20 bytes per entry on IA32.
+#[repr(C)]
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub struct Guid {
     pub a: u32,
@@ -59,6 +127,40 @@ pub struct Guid {
     pub d: [u8; 8],
 }
 
+impl Guid {
+    /// Converts the mixed-endian representation used in memory and on disk
+    pub const fn from_bytes(b: [u8; 16]) -> Self {
+        Self {
+            a: u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            b: u16::from_le_bytes([b[4], b[5]]),
+            c: u16::from_le_bytes([b[6], b[7]]),
+            d: [b[8], b[9], b[10], b[11], b[12], b[13], b[14], b[15]],
+        }
+    }
+
+    pub const fn to_bytes(&self) -> [u8; 16] {
+        let [a0, a1, a2, a3] = self.a.to_le_bytes();
+        let [b0, b1] = self.b.to_le_bytes();
+        let [c0, c1] = self.c.to_le_bytes();
+        let d = self.d;
+        [
+            a0, a1, a2, a3, b0, b1, c0, c1, d[0], d[1], d[2], d[3], d[4], d[5], d[6], d[7],
+        ]
+    }
+}
+
+impl core::fmt::Display for Guid {
+    /// Formats the GUID in the registry format, e.g. `8BE4DF61-93CA-11D2-AA0D-00E098032B8C`
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let d = self.d;
+        write!(
+            f,
+            "{:08X}-{:04X}-{:04X}-{:02X}{:02X}-{:02X}{:02X}{:02X}{:02X}{:02X}{:02X}",
+            self.a, self.b, self.c, d[0], d[1], d[2], d[3], d[4], d[5], d[6], d[7]
+        )
+    }
+}
+
 pub macro guid(
     $a:expr,
     $b:expr,
@@ -152,6 +254,8 @@ impl Status {
     pub const SUCCESS: Self = Self(0);
 
     const HIGH_BIT: usize = 1 << (usize::BITS - 1);
+    /// Set, together with the high bit for errors, in codes reserved for OEMs
+    const OEM_BIT: usize = 1 << (usize::BITS - 2);
 
     pub const fn new_error(value: usize) -> Self {
         Self(Self::HIGH_BIT | value)
@@ -161,6 +265,45 @@ impl Status {
         Self(value)
     }
 
+    /// Returns an error code from the range reserved for OEMs
+    pub const fn new_oem_error(value: usize) -> Self {
+        Self(Self::HIGH_BIT | Self::OEM_BIT | value)
+    }
+
+    /// Returns a warning code from the range reserved for OEMs
+    pub const fn new_oem_warn(value: usize) -> Self {
+        Self(Self::OEM_BIT | value)
+    }
+
+    /// Wraps a status returned by firmware, without any interpretation
+    pub const fn from_raw(raw: usize) -> Self {
+        Self(raw)
+    }
+
+    pub const fn as_raw(self) -> usize {
+        self.0
+    }
+
+    pub const fn is_error(self) -> bool {
+        self.0 & Self::HIGH_BIT != 0
+    }
+
+    pub const fn is_warning(self) -> bool {
+        self.0 != 0 && !self.is_error()
+    }
+
+    pub const fn category(self) -> StatusCategory {
+        if self.0 == 0 {
+            StatusCategory::Success
+        } else if self.0 & Self::OEM_BIT != 0 {
+            StatusCategory::Oem
+        } else if self.is_error() {
+            StatusCategory::Error
+        } else {
+            StatusCategory::Warning
+        }
+    }
+
     #[inline(always)]
     pub fn to_result<T>(self, ok: T) -> Result<T> {
         if self == Self::SUCCESS {
@@ -171,13 +314,66 @@ impl Status {
     }
 }
 
+/// Range a [`Status`] code belongs to
+///
+/// OEM codes may be errors or warnings; [`Status::is_error()`] tells them apart.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum StatusCategory {
+    Success,
+    Warning,
+    Error,
+    Oem,
+}
+
 #[repr(transparent)]
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub struct Handle(NonNull<c_void>);
 
+impl Handle {
+    pub fn as_ptr(&self) -> *mut c_void {
+        self.0.as_ptr()
+    }
+}
+
+macro_rules! handle_kinds {
+    ($($(#[$attr:meta])* $name:ident;)*) => {$(
+        $(#[$attr])*
+        ///
+        /// The kind is not checked; it only keeps handles from being mixed up in signatures.
+        #[repr(transparent)]
+        #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+        pub struct $name(Handle);
+
+        impl $name {
+            pub const fn new(handle: Handle) -> Self {
+                Self(handle)
+            }
+
+            pub const fn handle(self) -> Handle {
+                self.0
+            }
+        }
+
+        impl From<$name> for Handle {
+            fn from(handle: $name) -> Self {
+                handle.0
+            }
+        }
+    )*};
+}
+
+handle_kinds! {
+    /// Handle of a loaded image, with the Loaded Image Protocol installed
+    ImageHandle;
+    /// Handle of a controller, usually with a Device Path Protocol installed
+    DeviceHandle;
+    /// Image handle of a driver, with the Driver Binding Protocol installed
+    DriverHandle;
+}
+
 /// Handle to an event structure
 #[repr(transparent)]
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub struct Event(*mut c_void);
 
 /// Logical Block Address
@@ -185,6 +381,7 @@ pub type Lba = u64;
 
 /// Task Priority Level
 #[repr(transparent)]
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
 pub struct Tpl(usize);
 
 impl Tpl {
@@ -199,9 +396,14 @@ pub type VirtualAddr = u64;
 
 static SYSTEM_TABLE: AtomicPtr<SystemTable> = AtomicPtr::new(ptr::null_mut());
 static IMAGE_HANDLE: AtomicPtr<c_void> = AtomicPtr::new(ptr::null_mut());
+static BOOT_SERVICES_EXITED: AtomicBool = AtomicBool::new(false);
+static DEFAULT_MEMORY_TYPE: AtomicU32 = AtomicU32::new(MemoryType::LOADER_DATA.0);
+static CAPABILITIES: AtomicU32 = AtomicU32::new(0);
 
 pub unsafe fn bootstrap(image: Handle, system_table: &'static SystemTable) {
     IMAGE_HANDLE.store(image.0.as_ptr(), Ordering::Release);
+    BOOT_SERVICES_EXITED.store(false, Ordering::Release);
+    CAPABILITIES.store(Capabilities::of(system_table).bits(), Ordering::Release);
     SYSTEM_TABLE.store(system_table as *const _ as *mut _, Ordering::Release);
 }
 
@@ -213,14 +415,50 @@ pub fn system_table() -> &'static SystemTable {
     unsafe { &*ptr }
 }
 
-pub fn image_handle() -> Handle {
+pub fn image_handle() -> ImageHandle {
     let ptr = IMAGE_HANDLE.load(Ordering::Acquire);
     if ptr.is_null() {
         panic!("`uefi::bootstrap()` has not been called");
     }
-    Handle(NonNull::new(ptr).unwrap())
+    ImageHandle(Handle(NonNull::new(ptr).unwrap()))
 }
 
 pub fn boot_services() -> &'static BootServices {
     system_table().boot_services()
 }
+
+pub fn runtime_services() -> &'static RuntimeServices {
+    system_table().runtime_services()
+}
+
+/// Returns the optional services provided by the firmware
+///
+/// Wrappers for these services fail with `UNSUPPORTED` where they are missing. Before
+/// [`bootstrap()`], no optional services are reported.
+pub fn capabilities() -> Capabilities {
+    Capabilities::from_bits_truncate(CAPABILITIES.load(Ordering::Acquire))
+}
+
+/// Returns `true` if the crate has been bootstrapped and boot services have not been exited
+pub fn boot_services_active() -> bool {
+    !SYSTEM_TABLE.load(Ordering::Acquire).is_null() && !BOOT_SERVICES_EXITED.load(Ordering::Acquire)
+}
+
+/// Sets the memory type of allocations the crate makes on its own behalf
+///
+/// This covers e.g. [`Surface`](graphics::surface::Surface) back buffers and exit data, and
+/// defaults to `LOADER_DATA`. Memory from the global allocator, like the buffer returned by
+/// [`BootServices::handles_by_protocol()`], is typed by the allocator instead. Fails with
+/// `INVALID_PARAMETER` if firmware would refuse to allocate `memory_type`.
+pub fn set_default_memory_type(memory_type: MemoryType) -> Result<()> {
+    if !memory_type.is_allocatable() {
+        return Err(Status::INVALID_PARAMETER);
+    }
+    DEFAULT_MEMORY_TYPE.store(memory_type.0, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Returns the memory type set by [`set_default_memory_type()`]
+pub fn default_memory_type() -> MemoryType {
+    MemoryType(DEFAULT_MEMORY_TYPE.load(Ordering::Relaxed))
+}
@@ -0,0 +1,288 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! arm64 and RISC-V `Image` kernels
+//!
+//! Both architectures' kernels start with a 64 byte header giving the offset from a 2 MiB
+//! aligned base at which the image must be placed, and how much memory it needs there. The
+//! kernel is entered with the MMU off and the device tree's address in a register; the
+//! command line and initrd are passed in the tree's `/chosen` node.
+//!
+//! ```ignore
+//! let kernel = Image::parse(&image)?.load()?;
+//! let mut fdt = Fdt::copy_from(fdt::current()?, fdt_buffer)?;
+//! fdt.set_bootargs("console=ttyAMA0 root=/dev/vda1")?;
+//! fdt.set_initrd(initrd, initrd + initrd_len)?;
+//! unsafe { kernel.boot(fdt, map_buffer) }
+//! ```
+
+#[cfg(any(target_arch = "aarch64", target_arch = "riscv64"))]
+use core::convert::Infallible;
+use core::ptr;
+
+use super::{allocate_aligned, PAGE_SIZE};
+#[cfg(any(target_arch = "aarch64", target_arch = "riscv64"))]
+use crate::{boot_services, fdt::Fdt};
+use crate::{le, table::AllocPagesType, PhysicalAddr, Result, Status};
+
+const HEADER_SIZE: usize = 64;
+
+/// Alignment of the base `text_offset` is relative to
+pub const BASE_ALIGN: u64 = 2 * 1024 * 1024;
+
+const ARM64_MAGIC: &[u8; 4] = b"ARM\x64";
+const RISCV_MAGIC: &[u8; 4] = b"RSC\x05";
+
+/// arm64 header flag: the kernel is big-endian
+pub const ARM64_BIG_ENDIAN: u64 = 1 << 0;
+
+/// Architecture an `Image` was built for
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Arch {
+    Aarch64,
+    Riscv64,
+}
+
+impl Arch {
+    /// The architecture of the running firmware, if it boots `Image` kernels
+    pub const NATIVE: Option<Self> = if cfg!(target_arch = "aarch64") {
+        Some(Self::Aarch64)
+    } else if cfg!(target_arch = "riscv64") {
+        Some(Self::Riscv64)
+    } else {
+        None
+    };
+}
+
+/// A parsed `Image`
+#[derive(Clone, Copy, Debug)]
+pub struct Image<'a> {
+    data:            &'a [u8],
+    pub arch:        Arch,
+    /// Offset of the image from a [`BASE_ALIGN`]ed base address
+    pub text_offset: u64,
+    /// Memory the image needs, including its BSS
+    pub image_size:  u64,
+    pub flags:       u64,
+}
+
+impl<'a> Image<'a> {
+    /// Parses the header
+    ///
+    /// Fails with `LOAD_ERROR` if `data` is not an `Image`, or with `UNSUPPORTED` if it is for
+    /// another architecture, big-endian, or older than Linux 3.17 and so missing its size.
+    pub fn parse(data: &'a [u8]) -> Result<Self> {
+        let header = data.get(..HEADER_SIZE).ok_or(Status::LOAD_ERROR)?;
+        let arch = if header[56..60] == *ARM64_MAGIC {
+            Arch::Aarch64
+        } else if header[56..60] == *RISCV_MAGIC {
+            Arch::Riscv64
+        } else {
+            return Err(Status::LOAD_ERROR);
+        };
+        // `header` spans the whole header, so none of these can be out of bounds.
+        let u64_at = |at| le::read_u64(header, at).unwrap_or_default();
+        let text_offset = u64_at(8);
+        let image_size = u64_at(16);
+        let flags = u64_at(24);
+        if !text_offset.is_multiple_of(PAGE_SIZE as u64) {
+            return Err(Status::LOAD_ERROR);
+        }
+        let big_endian = arch == Arch::Aarch64 && flags & ARM64_BIG_ENDIAN != 0;
+        if Some(arch) != Arch::NATIVE || big_endian || image_size == 0 {
+            return Err(Status::UNSUPPORTED);
+        }
+
+        Ok(Self {
+            data,
+            arch,
+            text_offset,
+            image_size: image_size.max(data.len() as u64),
+            flags,
+        })
+    }
+
+    /// Returns the RISC-V header version, as `major << 16 | minor`
+    pub fn riscv_version(&self) -> Option<u32> {
+        if self.arch != Arch::Riscv64 {
+            return None;
+        }
+        le::read_u32(self.data, 32)
+    }
+
+    /// Copies the image to suitably aligned, newly allocated pages and zeroes its BSS
+    ///
+    /// The pages are allocated as the [default memory type](crate::default_memory_type()).
+    pub fn load(&self) -> Result<LoadedKernel> {
+        let pages = (self.image_size as usize).div_ceil(PAGE_SIZE);
+        let addr = allocate_aligned(AllocPagesType::Any, pages, BASE_ALIGN, self.text_offset)?;
+        unsafe {
+            let dst = addr as *mut u8;
+            ptr::copy_nonoverlapping(self.data.as_ptr(), dst, self.data.len());
+            ptr::write_bytes(
+                dst.add(self.data.len()),
+                0,
+                pages * PAGE_SIZE - self.data.len(),
+            );
+        }
+        Ok(LoadedKernel {
+            addr,
+            pages,
+            arch: self.arch,
+        })
+    }
+}
+
+/// An `Image` loaded by [`Image::load()`]
+#[derive(Debug)]
+pub struct LoadedKernel {
+    addr:     PhysicalAddr,
+    pages:    usize,
+    pub arch: Arch,
+}
+
+impl LoadedKernel {
+    /// Returns the address of the image, which is also its entry point
+    pub fn addr(&self) -> PhysicalAddr {
+        self.addr
+    }
+
+    /// Frees the image
+    ///
+    /// # Safety
+    ///
+    /// Nothing may still refer to it.
+    pub unsafe fn free(self) -> Result<()> {
+        crate::boot_services().free_pages(self.addr, self.pages)
+    }
+}
+
+#[cfg(any(target_arch = "aarch64", target_arch = "riscv64"))]
+impl LoadedKernel {
+    /// Exits boot services and enters the kernel with `fdt`'s address in the right register
+    ///
+    /// The final memory map is read into `map_buffer` and recorded in the tree with
+    /// [`Fdt::set_uefi_params()`]; both buffers are handed to the kernel as they are, so the
+    /// tree must have room for the new properties. Fails with `BUFFER_TOO_SMALL` otherwise,
+    /// or if the map doesn't fit. On RISC-V, fails if the boot hart's ID can't be found.
+    ///
+    /// # Safety
+    ///
+    /// The kernel image must be trustworthy; everything else in memory is handed over to it.
+    pub unsafe fn boot(
+        self,
+        mut fdt: Fdt<'static>,
+        map_buffer: &'static mut [u8],
+    ) -> Result<Infallible> {
+        #[cfg(target_arch = "riscv64")]
+        let hartid = crate::proto::arch::riscv::boot_hartid()?;
+        #[cfg_attr(target_arch = "riscv64", allow(unused_variables))]
+        let map =
+            boot_services().exit_with_memory_map(map_buffer, |map| fdt.set_uefi_params(map))?;
+        let fdt = fdt.as_bytes();
+
+        #[cfg(target_arch = "aarch64")]
+        {
+            // The kernel starts with the caches off, so it must find everything in memory.
+            clean_dcache(self.addr, self.pages * PAGE_SIZE);
+            for bytes in [fdt, map.as_bytes()] {
+                clean_dcache(bytes.as_ptr() as u64, bytes.len());
+            }
+            enter(self.addr, fdt.as_ptr() as u64)
+        }
+        #[cfg(target_arch = "riscv64")]
+        enter(self.addr, hartid, fdt.as_ptr() as u64)
+    }
+}
+
+/// Cleans and invalidates the data cache lines holding `len` bytes at `addr`
+#[cfg(target_arch = "aarch64")]
+unsafe fn clean_dcache(addr: u64, len: usize) {
+    let ctr: u64;
+    core::arch::asm!("mrs {}, ctr_el0", out(reg) ctr, options(nomem, nostack));
+    let line = 4 << ((ctr >> 16) & 0xf);
+    let mut at = addr & !(line - 1);
+    while at < addr + len as u64 {
+        core::arch::asm!("dc civac, {}", in(reg) at, options(nostack));
+        at += line;
+    }
+    core::arch::asm!("dsb sy", options(nostack));
+}
+
+/// Turns the MMU and caches off at the current exception level and jumps to the kernel
+///
+/// Runs from the firmware's identity mapping, so execution continues in place.
+#[cfg(target_arch = "aarch64")]
+unsafe fn enter(entry: u64, fdt: u64) -> ! {
+    core::arch::asm!(
+        "msr daifset, #0xf",
+        "mrs x8, CurrentEL",
+        "cmp x8, #8",
+        "b.ne 1f",
+        "mrs x8, sctlr_el2",
+        "bic x8, x8, #1",
+        "bic x8, x8, #4",
+        "msr sctlr_el2, x8",
+        "b 2f",
+        "1:",
+        "mrs x8, sctlr_el1",
+        "bic x8, x8, #1",
+        "bic x8, x8, #4",
+        "msr sctlr_el1, x8",
+        "2:",
+        "isb",
+        "ic iallu",
+        "dsb sy",
+        "isb",
+        "mov x1, xzr",
+        "mov x2, xzr",
+        "mov x3, xzr",
+        "br x9",
+        in("x0") fdt,
+        in("x9") entry,
+        options(noreturn),
+    )
+}
+
+/// Masks interrupts, turns paging off and jumps to the kernel
+#[cfg(target_arch = "riscv64")]
+unsafe fn enter(entry: u64, hartid: usize, fdt: u64) -> ! {
+    core::arch::asm!(
+        "csrw sie, zero",
+        "csrw satp, zero",
+        "sfence.vma",
+        "fence.i",
+        "jr t0",
+        in("a0") hartid,
+        in("a1") fdt,
+        in("t0") entry,
+        options(noreturn),
+    )
+}
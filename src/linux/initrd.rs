@@ -0,0 +1,338 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Initial ramdisks
+//!
+//! [`Initrd::load()`] reads one or more files, typically CPU microcode followed by the
+//! initramfs proper, into a single physically contiguous region. Each part is padded to a
+//! multiple of 4 bytes, which is what the kernel expects between concatenated `cpio`
+//! archives. The result is passed by address, with
+//! [`BootParams::set_initrd()`](super::x86::BootParams::set_initrd) or
+//! [`Fdt::set_initrd()`](crate::fdt::Fdt::set_initrd).
+//!
+//! Kernels entered through their EFI stub can instead fetch the initrd themselves, from the
+//! Load File 2 Protocol [`register()`] installs. This serves a list of parts from wherever they
+//! are in memory, without copying them together first.
+
+use core::{ffi::c_void, ptr, slice};
+
+use super::PAGE_SIZE;
+use crate::{
+    boot_services, default_memory_type, guid,
+    progress::{Progress, ProgressFn},
+    proto::{
+        device_path::{DevicePath, DeviceType, END_ENTIRE},
+        media::{
+            file::{File, ScatterRange},
+            load_file::LoadFile2,
+        },
+    },
+    sync::TryLock,
+    table::AllocPagesType,
+    Guid, Handle, PhysicalAddr, Result, Status,
+};
+
+/// Vendor media device path node GUID the Linux EFI stub looks for
+pub const LINUX_INITRD_MEDIA_GUID: Guid = guid!(
+    0x5568e427,0x68fc,0x4f3d,
+    {0xac,0x74,0xca,0x55,0x52,0x31,0xcc,0x68}
+);
+
+/// Maximum number of parts [`register()`] accepts
+pub const MAX_PARTS: usize = 8;
+
+const PART_ALIGN: usize = 4;
+
+/// An initrd loaded into contiguous pages
+#[derive(Debug)]
+pub struct Initrd {
+    addr:  PhysicalAddr,
+    size:  usize,
+    pages: usize,
+}
+
+impl Initrd {
+    /// Reads `files` one after another into pages ending at or below `max_addr`
+    ///
+    /// For x86 kernels, `max_addr` is
+    /// [`BootParams::initrd_addr_max()`](super::x86::BootParams::initrd_addr_max); `Image`
+    /// kernels accept an initrd anywhere, so take [`PhysicalAddr::MAX`]. `progress` counts
+    /// bytes across all files.
+    pub fn load(
+        files: &mut [File],
+        max_addr: PhysicalAddr,
+        mut progress: impl Progress,
+    ) -> Result<Self> {
+        let mut sizes = [0; MAX_PARTS];
+        if files.len() > MAX_PARTS {
+            return Err(Status::INVALID_PARAMETER);
+        }
+        for (file, size) in files.iter_mut().zip(&mut sizes) {
+            *size = usize::try_from(file.size()?).map_err(|_| Status::BAD_BUFFER_SIZE)?;
+        }
+        let initrd = Self::allocate(&sizes[..files.len()], max_addr)?;
+
+        let total = initrd.size as u64;
+        let mut read = || {
+            let mut offset = 0;
+            for (file, &len) in files.iter_mut().zip(&sizes) {
+                let range = ScatterRange {
+                    file_offset: 0,
+                    addr: initrd.addr + offset as u64,
+                    len,
+                };
+                let base = offset as u64;
+                let progress = ProgressFn(|done, _| progress.update(base + done, total));
+                unsafe { file.read_scatter(&[range], progress)? };
+                offset += len.next_multiple_of(PART_ALIGN);
+            }
+            Ok(())
+        };
+        let result = read();
+        progress.finish(result);
+        match result {
+            Ok(()) => Ok(initrd),
+            Err(status) => {
+                let _ = unsafe { initrd.free() };
+                Err(status)
+            }
+        }
+    }
+
+    /// Copies `parts` one after another into pages ending at or below `max_addr`
+    ///
+    /// See [`load()`](Self::load) for the limit.
+    pub fn from_parts(parts: &[&[u8]], max_addr: PhysicalAddr) -> Result<Self> {
+        if parts.len() > MAX_PARTS {
+            return Err(Status::INVALID_PARAMETER);
+        }
+        let mut sizes = [0; MAX_PARTS];
+        for (part, size) in parts.iter().zip(&mut sizes) {
+            *size = part.len();
+        }
+        let initrd = Self::allocate(&sizes[..parts.len()], max_addr)?;
+        let mut at = initrd.addr as *mut u8;
+        for part in parts {
+            unsafe {
+                ptr::copy_nonoverlapping(part.as_ptr(), at, part.len());
+                at = at.add(part.len().next_multiple_of(PART_ALIGN));
+            }
+        }
+        Ok(initrd)
+    }
+
+    /// Allocates zeroed pages for parts of `sizes` bytes
+    fn allocate(sizes: &[usize], max_addr: PhysicalAddr) -> Result<Self> {
+        if sizes.is_empty() {
+            return Err(Status::INVALID_PARAMETER);
+        }
+        let size = sizes
+            .iter()
+            .try_fold(0usize, |total, len| {
+                total.checked_add(len.checked_next_multiple_of(PART_ALIGN)?)
+            })
+            .ok_or(Status::BAD_BUFFER_SIZE)?;
+        let pages = size.div_ceil(PAGE_SIZE).max(1);
+        let placement = match max_addr {
+            PhysicalAddr::MAX => AllocPagesType::Any,
+            max => AllocPagesType::Max(max),
+        };
+        let addr = boot_services().allocate_pages(placement, default_memory_type(), pages)?;
+        unsafe { ptr::write_bytes(addr as *mut u8, 0, pages * PAGE_SIZE) };
+        Ok(Self { addr, size, pages })
+    }
+
+    pub fn addr(&self) -> PhysicalAddr {
+        self.addr
+    }
+
+    /// Returns the size, including padding between the parts
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns the address just past the initrd
+    pub fn end(&self) -> PhysicalAddr {
+        self.addr + self.size as u64
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.addr as *const u8, self.size) }
+    }
+
+    /// [Registers](register()) the initrd for the kernel's EFI stub to load
+    ///
+    /// # Safety
+    ///
+    /// The initrd must not be freed before it is unregistered.
+    pub unsafe fn register(&self) -> Result<Handle> {
+        register(&[slice::from_raw_parts(self.addr as *const u8, self.size)])
+    }
+
+    /// Frees the pages holding the initrd
+    ///
+    /// # Safety
+    ///
+    /// Nothing may still refer to them.
+    pub unsafe fn free(self) -> Result<()> {
+        boot_services().free_pages(self.addr, self.pages)
+    }
+}
+
+/// The vendor media node identifying the initrd, and the end of the path
+#[repr(C, packed)]
+struct InitrdMediaPath {
+    vendor: DevicePath,
+    guid:   Guid,
+    end:    DevicePath,
+}
+
+static INITRD_MEDIA_PATH: InitrdMediaPath = InitrdMediaPath {
+    vendor: DevicePath {
+        kind:     DeviceType::MEDIA,
+        sub_kind: 0x03,
+        length:   [20, 0],
+    },
+    guid:   LINUX_INITRD_MEDIA_GUID,
+    end:    DevicePath {
+        kind:     DeviceType::END,
+        sub_kind: END_ENTIRE,
+        length:   [4, 0],
+    },
+};
+
+static LOAD_FILE2: LoadFile2 = LoadFile2 { load_file };
+
+/// Addresses and lengths of registered parts
+struct Parts {
+    list:  [(usize, usize); MAX_PARTS],
+    count: usize,
+}
+
+impl Parts {
+    fn iter(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.list[..self.count].iter().copied()
+    }
+}
+
+static PARTS: TryLock<Option<Parts>> = TryLock::new(None);
+
+/// Installs the Load File 2 Protocol serving `parts` as the initrd, on a new handle with the
+/// device path the Linux EFI stub looks for
+///
+/// Parts are padded the same way [`Initrd::load()`] does. Only one initrd can be registered at
+/// a time; fails with `ALREADY_STARTED` if there already is one, e.g. from a previous boot
+/// attempt that was not [unregistered](unregister).
+pub fn register(parts: &[&'static [u8]]) -> Result<Handle> {
+    if parts.len() > MAX_PARTS {
+        return Err(Status::INVALID_PARAMETER);
+    }
+    PARTS
+        .try_with(|registered| {
+            if registered.is_some() {
+                return Err(Status::ALREADY_STARTED);
+            }
+            let mut list = [(0, 0); MAX_PARTS];
+            for (part, entry) in parts.iter().zip(&mut list) {
+                *entry = (part.as_ptr() as usize, part.len());
+            }
+            *registered = Some(Parts {
+                list,
+                count: parts.len(),
+            });
+            Ok(())
+        })
+        .ok_or(Status::NOT_READY)??;
+
+    let (bs, path) = (boot_services(), &INITRD_MEDIA_PATH.vendor);
+    let installed = unsafe {
+        bs.install_protocol_interface(None, path)
+            .and_then(|handle| {
+                bs.install_protocol_interface(Some(handle), &LOAD_FILE2)
+                    .inspect_err(|_| {
+                        let _ = bs.uninstall_protocol_interface(handle, path);
+                    })
+            })
+    };
+    if installed.is_err() {
+        PARTS.try_with(|registered| *registered = None);
+    }
+    installed
+}
+
+/// Removes the protocols [`register()`] installed on `handle`
+pub fn unregister(handle: Handle) -> Result<()> {
+    let bs = boot_services();
+    unsafe {
+        bs.uninstall_protocol_interface(handle, &LOAD_FILE2)?;
+        bs.uninstall_protocol_interface(handle, &INITRD_MEDIA_PATH.vendor)?;
+    }
+    PARTS.try_with(|registered| *registered = None);
+    Ok(())
+}
+
+extern "efiapi" fn load_file(
+    _this: *mut LoadFile2,
+    file_path: *const DevicePath,
+    boot_policy: bool,
+    buffer_size: *mut usize,
+    buffer: *mut c_void,
+) -> Status {
+    // Load File 2 is never used for boot options.
+    if boot_policy {
+        return Status::UNSUPPORTED;
+    }
+    if file_path.is_null() || buffer_size.is_null() {
+        return Status::INVALID_PARAMETER;
+    }
+    let copy = |registered: &mut Option<Parts>| {
+        let Some(parts) = registered else {
+            return Status::NOT_FOUND;
+        };
+        let size: usize = parts
+            .iter()
+            .map(|(_, len)| len.next_multiple_of(PART_ALIGN))
+            .sum();
+        let available = unsafe { buffer_size.replace(size) };
+        if buffer.is_null() || available < size {
+            return Status::BUFFER_TOO_SMALL;
+        }
+        unsafe {
+            ptr::write_bytes(buffer.cast::<u8>(), 0, size);
+            let mut at = buffer.cast::<u8>();
+            for (addr, len) in parts.iter() {
+                ptr::copy_nonoverlapping(addr as *const u8, at, len);
+                at = at.add(len.next_multiple_of(PART_ALIGN));
+            }
+        }
+        Status::SUCCESS
+    };
+    PARTS.try_with(copy).unwrap_or(Status::NOT_READY)
+}
@@ -0,0 +1,84 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Booting Linux kernels directly
+//!
+//! [`x86`] loads an x86 `bzImage` and fills in its zero page. The kernel can then be
+//! entered through its 64-bit entry point after `ExitBootServices()`, or through the EFI
+//! handover protocol, in which case its EFI stub exits boot services itself.
+//!
+//! [`image`] loads an arm64 or RISC-V `Image` and enters it after `ExitBootServices()`, with
+//! the device tree describing everything else.
+
+pub mod image;
+pub mod initrd;
+pub mod x86;
+
+use crate::{
+    boot_services, default_memory_type, table::AllocPagesType, PhysicalAddr, Result, PAGE_SIZE,
+};
+
+/// Allocates `pages` pages starting `offset` bytes past a multiple of `align`
+///
+/// Enough is allocated to find such a start, and the rest given back; failing to give it back
+/// only wastes memory.
+fn allocate_aligned(
+    limit: AllocPagesType,
+    pages: usize,
+    align: u64,
+    offset: u64,
+) -> Result<PhysicalAddr> {
+    let bs = boot_services();
+    let slack = (align as usize / PAGE_SIZE).max(1) - 1;
+    let base = bs.allocate_pages(limit, default_memory_type(), pages + slack)?;
+    let start = base + (offset % align + align - base % align) % align;
+    let head = (start - base) as usize / PAGE_SIZE;
+    unsafe {
+        if head > 0 {
+            let _ = bs.free_pages(base, head);
+        }
+        if slack > head {
+            let _ = bs.free_pages(start + (pages * PAGE_SIZE) as u64, slack - head);
+        }
+    }
+    Ok(start)
+}
+
+fn write_u16(data: &mut [u8], offset: usize, value: u16) {
+    data[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn write_u32(data: &mut [u8], offset: usize, value: u32) {
+    data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn write_u64(data: &mut [u8], offset: usize, value: u64) {
+    data[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+}
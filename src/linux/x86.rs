@@ -0,0 +1,498 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! x86 `bzImage` kernels, per the Linux x86 boot protocol
+//!
+//! ```ignore
+//! let kernel = BzImage::parse(&image)?;
+//! let mut params = kernel.load()?;
+//! params.set_cmdline("console=ttyS0 root=/dev/vda1")?;
+//! params.set_initrd(initrd, initrd_len)?;
+//! params.set_framebuffer(&gfx.framebuffer().unwrap())?;
+//! unsafe { params.boot(map_buffer) }
+//! ```
+//!
+//! Only kernels speaking protocol 2.12 or later are supported, which covers everything
+//! since Linux 3.8.
+
+use core::{convert::Infallible, ptr};
+
+use super::{allocate_aligned, write_u16, write_u32, write_u64, PAGE_SIZE};
+use crate::{
+    boot_services, default_memory_type,
+    graphics::Framebuffer,
+    le,
+    proto::console::gop::PixelFormat,
+    system_table,
+    table::{AllocPagesType, Format, MemoryMap, TableGuid},
+    PhysicalAddr, Result, Status,
+};
+
+/// Oldest boot protocol version supported, 2.12
+pub const MIN_VERSION: u16 = 0x020c;
+
+/// Number of E820 entries the zero page has room for
+pub const E820_MAX_ENTRIES: usize = 128;
+
+// Setup header fields, at the same offsets in the image and the zero page
+const SETUP_SECTS: usize = 0x1f1;
+const BOOT_FLAG: usize = 0x1fe;
+const JUMP: usize = 0x200;
+const HEADER: usize = 0x202;
+const VERSION: usize = 0x206;
+const TYPE_OF_LOADER: usize = 0x210;
+const LOADFLAGS: usize = 0x211;
+const CODE32_START: usize = 0x214;
+const RAMDISK_IMAGE: usize = 0x218;
+const RAMDISK_SIZE: usize = 0x21c;
+const CMD_LINE_PTR: usize = 0x228;
+const INITRD_ADDR_MAX: usize = 0x22c;
+const KERNEL_ALIGNMENT: usize = 0x230;
+const RELOCATABLE_KERNEL: usize = 0x234;
+const XLOADFLAGS: usize = 0x236;
+const CMDLINE_SIZE: usize = 0x238;
+const PREF_ADDRESS: usize = 0x258;
+const INIT_SIZE: usize = 0x260;
+const HANDOVER_OFFSET: usize = 0x264;
+/// The setup header may not extend past this
+const HEADER_END_MAX: usize = 0x290;
+
+// Other zero page fields
+const ACPI_RSDP_ADDR: usize = 0x070;
+const EXT_RAMDISK_IMAGE: usize = 0x0c0;
+const EXT_RAMDISK_SIZE: usize = 0x0c4;
+const EXT_CMD_LINE_PTR: usize = 0x0c8;
+const EFI_INFO: usize = 0x1c0;
+const E820_ENTRIES: usize = 0x1e8;
+const E820_TABLE: usize = 0x2d0;
+const E820_ENTRY_SIZE: usize = 20;
+
+pub const LOADED_HIGH: u8 = 1 << 0;
+
+pub const XLF_KERNEL_64: u16 = 1 << 0;
+pub const XLF_CAN_BE_LOADED_ABOVE_4G: u16 = 1 << 1;
+pub const XLF_EFI_HANDOVER_32: u16 = 1 << 2;
+pub const XLF_EFI_HANDOVER_64: u16 = 1 << 3;
+
+/// `type_of_loader` for boot loaders without an assigned ID
+const LOADER_UNDEFINED: u8 = 0xff;
+
+const VIDEO_TYPE_EFI: u8 = 0x70;
+const VIDEO_CAPABILITY_64BIT_BASE: u32 = 1 << 1;
+
+const HEADER_MAGIC: &[u8; 4] = b"HdrS";
+const EFI64_LOADER_SIGNATURE: &[u8; 4] = b"EL64";
+
+/// A parsed `bzImage`
+#[derive(Clone, Copy, Debug)]
+pub struct BzImage<'a> {
+    /// The setup header, starting at offset `0x1f1`
+    header:              &'a [u8],
+    /// The protected-mode kernel
+    kernel:              &'a [u8],
+    pub version:         u16,
+    pub xloadflags:      u16,
+    pub relocatable:     bool,
+    pub alignment:       u64,
+    pub pref_address:    u64,
+    /// Memory the kernel needs in place before it can relocate itself, at least its size
+    pub init_size:       u64,
+    pub handover_offset: u32,
+    pub initrd_addr_max: u32,
+    /// Maximum length of the command line, excluding the terminating NUL
+    pub cmdline_size:    u32,
+}
+
+impl<'a> BzImage<'a> {
+    /// Parses the setup header
+    ///
+    /// Fails with `LOAD_ERROR` if `data` is not a `bzImage`, or with `UNSUPPORTED` if the
+    /// kernel is too old or can be entered neither in 64-bit mode nor through the 64-bit
+    /// EFI handover protocol.
+    pub fn parse(data: &'a [u8]) -> Result<Self> {
+        if data.len() < HEADER_END_MAX
+            || le::read_u16(data, BOOT_FLAG) != Some(0xaa55)
+            || data[HEADER..HEADER + 4] != *HEADER_MAGIC
+        {
+            return Err(Status::LOAD_ERROR);
+        }
+        // The whole header was bounds-checked above.
+        let u16_at = |at| le::read_u16(data, at).unwrap_or_default();
+        let u32_at = |at| le::read_u32(data, at).unwrap_or_default();
+        let u64_at = |at| le::read_u64(data, at).unwrap_or_default();
+        let version = u16_at(VERSION);
+        let xloadflags = u16_at(XLOADFLAGS);
+        if version < MIN_VERSION
+            || data[LOADFLAGS] & LOADED_HIGH == 0
+            || xloadflags & (XLF_KERNEL_64 | XLF_EFI_HANDOVER_64) == 0
+        {
+            return Err(Status::UNSUPPORTED);
+        }
+
+        // The second byte of the jump at 0x200 skips over the rest of the header.
+        let header_end = HEADER + usize::from(data[JUMP + 1]);
+        let setup_sects = match data[SETUP_SECTS] {
+            0 => 4,
+            sects => usize::from(sects),
+        };
+        let kernel = data
+            .get((setup_sects + 1) * 512..)
+            .filter(|kernel| !kernel.is_empty())
+            .ok_or(Status::LOAD_ERROR)?;
+        let alignment = u64::from(u32_at(KERNEL_ALIGNMENT));
+        if header_end > HEADER_END_MAX || !alignment.is_power_of_two() {
+            return Err(Status::LOAD_ERROR);
+        }
+
+        Ok(Self {
+            header: &data[SETUP_SECTS..header_end],
+            kernel,
+            version,
+            xloadflags,
+            relocatable: data[RELOCATABLE_KERNEL] != 0,
+            alignment: alignment.max(PAGE_SIZE as u64),
+            pref_address: u64_at(PREF_ADDRESS),
+            init_size: u64::from(u32_at(INIT_SIZE)).max(kernel.len() as u64),
+            handover_offset: u32_at(HANDOVER_OFFSET),
+            initrd_addr_max: u32_at(INITRD_ADDR_MAX),
+            cmdline_size: u32_at(CMDLINE_SIZE),
+        })
+    }
+
+    /// Loads the protected-mode kernel and prepares its zero page
+    ///
+    /// The kernel is placed at its preferred address if that is free, otherwise anywhere
+    /// suitably aligned if it is relocatable. It and the zero page are allocated as the
+    /// [default memory type](crate::default_memory_type()).
+    pub fn load(&self) -> Result<BootParams> {
+        let bs = boot_services();
+        let kernel_pages = (self.init_size as usize).div_ceil(PAGE_SIZE);
+        let kernel = self.allocate_kernel(kernel_pages)?;
+        let params = match bs.allocate_pages(AllocPagesType::Any, default_memory_type(), 1) {
+            Ok(params) => params,
+            Err(status) => {
+                let _ = unsafe { bs.free_pages(kernel, kernel_pages) };
+                return Err(status);
+            }
+        };
+
+        unsafe {
+            let dst = kernel as *mut u8;
+            ptr::copy_nonoverlapping(self.kernel.as_ptr(), dst, self.kernel.len());
+            let bss = kernel_pages * PAGE_SIZE - self.kernel.len();
+            ptr::write_bytes(dst.add(self.kernel.len()), 0, bss);
+            ptr::write_bytes(params as *mut u8, 0, PAGE_SIZE);
+        }
+        let mut params = BootParams {
+            params,
+            kernel,
+            kernel_pages,
+            cmdline: None,
+            xloadflags: self.xloadflags,
+            handover_offset: self.handover_offset,
+            initrd_addr_max: self.initrd_addr_max,
+            cmdline_size: self.cmdline_size,
+        };
+        let page = params.as_bytes_mut();
+        page[SETUP_SECTS..][..self.header.len()].copy_from_slice(self.header);
+        page[TYPE_OF_LOADER] = LOADER_UNDEFINED;
+        write_u32(page, CODE32_START, kernel as u32);
+        Ok(params)
+    }
+
+    fn allocate_kernel(&self, pages: usize) -> Result<PhysicalAddr> {
+        let preferred = boot_services().allocate_pages(
+            AllocPagesType::Addr(self.pref_address),
+            default_memory_type(),
+            pages,
+        );
+        if preferred.is_ok() || !self.relocatable {
+            return preferred;
+        }
+        let limit = match self.xloadflags & XLF_CAN_BE_LOADED_ABOVE_4G {
+            0 => AllocPagesType::Max(u32::MAX.into()),
+            _ => AllocPagesType::Any,
+        };
+        allocate_aligned(limit, pages, self.alignment, 0)
+    }
+}
+
+/// A loaded kernel and its zero page (`struct boot_params`)
+#[derive(Debug)]
+pub struct BootParams {
+    params:          PhysicalAddr,
+    kernel:          PhysicalAddr,
+    kernel_pages:    usize,
+    cmdline:         Option<(PhysicalAddr, usize)>,
+    xloadflags:      u16,
+    handover_offset: u32,
+    initrd_addr_max: u32,
+    cmdline_size:    u32,
+}
+
+impl BootParams {
+    /// Returns the address of the zero page
+    pub fn addr(&self) -> PhysicalAddr {
+        self.params
+    }
+
+    /// Returns where the protected-mode kernel was loaded
+    pub fn kernel(&self) -> PhysicalAddr {
+        self.kernel
+    }
+
+    /// Returns the zero page, for fields not covered by the setters
+    pub fn as_bytes_mut(&mut self) -> &mut [u8; PAGE_SIZE] {
+        unsafe { &mut *(self.params as *mut [u8; PAGE_SIZE]) }
+    }
+
+    /// Returns the highest address the initrd may occupy
+    pub fn initrd_addr_max(&self) -> PhysicalAddr {
+        match self.xloadflags & XLF_CAN_BE_LOADED_ABOVE_4G {
+            0 => self.initrd_addr_max.into(),
+            _ => PhysicalAddr::MAX,
+        }
+    }
+
+    /// Copies the command line and points the kernel at it
+    ///
+    /// Fails with `INVALID_PARAMETER` if it is longer than the kernel accepts.
+    pub fn set_cmdline(&mut self, cmdline: &str) -> Result<()> {
+        if cmdline.len() > self.cmdline_size as usize {
+            return Err(Status::INVALID_PARAMETER);
+        }
+        let bs = boot_services();
+        let pages = (cmdline.len() + 1).div_ceil(PAGE_SIZE);
+        let addr = bs.allocate_pages(AllocPagesType::Any, default_memory_type(), pages)?;
+        unsafe {
+            ptr::copy_nonoverlapping(cmdline.as_ptr(), addr as *mut u8, cmdline.len());
+            *(addr as *mut u8).add(cmdline.len()) = 0;
+        }
+        if let Some((old, old_pages)) = self.cmdline.replace((addr, pages)) {
+            let _ = unsafe { bs.free_pages(old, old_pages) };
+        }
+        let page = self.as_bytes_mut();
+        write_u32(page, CMD_LINE_PTR, addr as u32);
+        write_u32(page, EXT_CMD_LINE_PTR, (addr >> 32) as u32);
+        Ok(())
+    }
+
+    /// Points the kernel at an initrd already in memory
+    ///
+    /// Fails with `INVALID_PARAMETER` if it ends above
+    /// [`initrd_addr_max()`](Self::initrd_addr_max).
+    pub fn set_initrd(&mut self, addr: PhysicalAddr, size: usize) -> Result<()> {
+        let last = addr
+            .checked_add(size as u64)
+            .ok_or(Status::INVALID_PARAMETER)?;
+        if size > 0 && last - 1 > self.initrd_addr_max() {
+            return Err(Status::INVALID_PARAMETER);
+        }
+        let page = self.as_bytes_mut();
+        write_u32(page, RAMDISK_IMAGE, addr as u32);
+        write_u32(page, EXT_RAMDISK_IMAGE, (addr >> 32) as u32);
+        write_u32(page, RAMDISK_SIZE, size as u32);
+        write_u32(page, EXT_RAMDISK_SIZE, (size as u64 >> 32) as u32);
+        Ok(())
+    }
+
+    /// Passes the ACPI RSDP from the configuration table, preferring ACPI 2.0
+    ///
+    /// Kernels older than protocol 2.14 ignore this and find it through the EFI system table.
+    /// Fails with `NOT_FOUND` if the firmware provides no ACPI tables.
+    pub fn set_acpi_rsdp(&mut self) -> Result<()> {
+        let config = system_table().config_table();
+        let rsdp = config
+            .get_table(TableGuid::ACPI_20)
+            .or_else(|| config.get_table(TableGuid::ACPI))
+            .ok_or(Status::NOT_FOUND)?;
+        write_u64(self.as_bytes_mut(), ACPI_RSDP_ADDR, rsdp as u64);
+        Ok(())
+    }
+
+    /// Describes the framebuffer in the zero page's `screen_info`
+    ///
+    /// Fails with `UNSUPPORTED` for framebuffers without a linear pixel format.
+    pub fn set_framebuffer(&mut self, fb: &Framebuffer) -> Result<()> {
+        // Positions and sizes of red, green, blue and reserved.
+        let (bpp, fields) = match fb.format {
+            PixelFormat::RGBA8 => (32, [0, 8, 8, 8, 16, 8, 24, 8]),
+            PixelFormat::BGRA8 => (32, [16, 8, 8, 8, 0, 8, 24, 8]),
+            PixelFormat::BITMASK => {
+                let field = |mask: u32| [mask.trailing_zeros() as u8, mask.count_ones() as u8];
+                let mask = &fb.bitmask;
+                let [red, green, blue, reserved] =
+                    [mask.red, mask.green, mask.blue, mask.reserved].map(field);
+                let fields = [
+                    red[0],
+                    red[1],
+                    green[0],
+                    green[1],
+                    blue[0],
+                    blue[1],
+                    reserved[0],
+                    reserved[1],
+                ];
+                (mask.bits_per_pixel() as u16, fields)
+            }
+            _ => return Err(Status::UNSUPPORTED),
+        };
+        let page = self.as_bytes_mut();
+        page[0x0f] = VIDEO_TYPE_EFI;
+        write_u16(page, 0x12, fb.width as u16);
+        write_u16(page, 0x14, fb.height as u16);
+        write_u16(page, 0x16, bpp);
+        write_u32(page, 0x18, fb.addr as u32);
+        write_u32(page, 0x1c, fb.size as u32);
+        let line_length = fb.stride * usize::from(bpp).div_ceil(8);
+        write_u16(page, 0x24, line_length as u16);
+        // Sizes come first in `screen_info`.
+        for (i, pair) in fields.chunks_exact(2).enumerate() {
+            page[0x26 + 2 * i] = pair[1];
+            page[0x27 + 2 * i] = pair[0];
+        }
+        write_u32(page, 0x36, VIDEO_CAPABILITY_64BIT_BASE);
+        write_u32(page, 0x3a, (fb.addr >> 32) as u32);
+        Ok(())
+    }
+
+    /// Records the final memory map as the E820 table and in `efi_info`
+    fn set_memory_map(&mut self, map: &MemoryMap) -> Result<()> {
+        let page = self.as_bytes_mut();
+        let table = &mut page[E820_TABLE..][..E820_MAX_ENTRIES * E820_ENTRY_SIZE];
+        let size = map.write_packed(table, Format::E820)?;
+        page[E820_ENTRIES] = (size / E820_ENTRY_SIZE) as u8;
+
+        let info = map.info();
+        let buffer = map.as_bytes().as_ptr();
+        let system_table = system_table() as *const _ as u64;
+        let efi_info = &mut page[EFI_INFO..];
+        efi_info[0..4].copy_from_slice(EFI64_LOADER_SIGNATURE);
+        write_u32(efi_info, 4, system_table as u32);
+        write_u32(efi_info, 8, info.descriptor_size as u32);
+        write_u32(efi_info, 12, info.descriptor_version);
+        write_u32(efi_info, 16, buffer as u32);
+        write_u32(efi_info, 20, map.as_bytes().len() as u32);
+        write_u32(efi_info, 24, (system_table >> 32) as u32);
+        write_u32(efi_info, 28, (buffer as u64 >> 32) as u32);
+        Ok(())
+    }
+
+    /// Frees the kernel, the zero page and the command line
+    ///
+    /// # Safety
+    ///
+    /// Nothing may still refer to them.
+    pub unsafe fn free(self) -> Result<()> {
+        let bs = boot_services();
+        if let Some((cmdline, pages)) = self.cmdline {
+            bs.free_pages(cmdline, pages)?;
+        }
+        bs.free_pages(self.params, 1)?;
+        bs.free_pages(self.kernel, self.kernel_pages)
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+impl BootParams {
+    /// Exits boot services and enters the kernel through its 64-bit entry point
+    ///
+    /// The final memory map is read into `map_buffer`, which the kernel keeps using, so it
+    /// must have room for the map as it will be when `ExitBootServices()` is called. Fails
+    /// with `UNSUPPORTED` if the kernel has no 64-bit entry point, and with
+    /// `BUFFER_TOO_SMALL` if the map doesn't fit in the buffer or the E820 table.
+    ///
+    /// # Safety
+    ///
+    /// The kernel image must be trustworthy; everything else in memory is handed over to it.
+    pub unsafe fn boot(mut self, map_buffer: &'static mut [u8]) -> Result<Infallible> {
+        if self.xloadflags & XLF_KERNEL_64 == 0 {
+            return Err(Status::UNSUPPORTED);
+        }
+        boot_services().exit_with_memory_map(map_buffer, |map| self.set_memory_map(map))?;
+        enter_64(self.kernel + 0x200, self.params)
+    }
+
+    /// Enters the kernel's EFI stub through the 64-bit EFI handover protocol
+    ///
+    /// The stub reads the rest of the boot information itself, and exits boot services. Fails
+    /// with `UNSUPPORTED` if the kernel doesn't support the handover protocol.
+    ///
+    /// # Safety
+    ///
+    /// The kernel image must be trustworthy.
+    pub unsafe fn handover(self) -> Result<Infallible> {
+        if self.xloadflags & XLF_EFI_HANDOVER_64 == 0 {
+            return Err(Status::UNSUPPORTED);
+        }
+        type Handover =
+            extern "sysv64" fn(*mut core::ffi::c_void, *const crate::table::SystemTable, u64) -> !;
+        let entry = self.kernel + u64::from(self.handover_offset) + 0x200;
+        let entry = core::mem::transmute::<usize, Handover>(entry as usize);
+        core::arch::asm!("cli");
+        entry(
+            crate::image_handle().handle().as_ptr(),
+            system_table(),
+            self.params,
+        )
+    }
+}
+
+/// Loads a flat GDT and jumps to the 64-bit entry point, as the boot protocol requires
+#[cfg(target_arch = "x86_64")]
+unsafe fn enter_64(entry: u64, params: PhysicalAddr) -> ! {
+    #[repr(C, packed)]
+    struct Gdtr {
+        limit: u16,
+        base:  u64,
+    }
+
+    // `__BOOT_CS` is 0x10, `__BOOT_DS` is 0x18.
+    static GDT: [u64; 4] = [0, 0, 0x00af_9a00_0000_ffff, 0x00cf_9200_0000_ffff];
+    let gdtr = Gdtr {
+        limit: (size_of_val(&GDT) - 1) as u16,
+        base:  GDT.as_ptr() as u64,
+    };
+    core::arch::asm!(
+        "cli",
+        "lgdt [rdx]",
+        "mov eax, 0x18",
+        "mov ds, eax",
+        "mov es, eax",
+        "mov ss, eax",
+        "push 0x10",
+        "push rcx",
+        "retfq",
+        in("rdx") &gdtr,
+        in("rcx") entry,
+        in("rsi") params,
+        options(noreturn),
+    )
+}
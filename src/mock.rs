@@ -0,0 +1,1335 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Host-side mock of the UEFI tables
+//!
+//! [`MockSystemTable::install()`] builds a system table, boot services table, and console
+//! whose function pointers are implemented in Rust, and registers it with [`bootstrap()`],
+//! so code which goes through [`boot_services()`](crate::boot_services) can be unit tested
+//! on the host.
+//!
+//! Only one mock can be installed at a time; `install()` blocks until any other instance
+//! (e.g. one belonging to a concurrently running test) has been dropped.
+//!
+//! Timers run on a virtual clock. Waiting on a timer event advances the clock to its trigger
+//! time instead of sleeping, and `stall()` simply advances the clock. Waiting on a set of
+//! events which can never be signaled fails with `NOT_READY` instead of blocking forever.
+//!
+//! Services which cannot be meaningfully emulated (image loading, device paths, driver
+//! binding, ...) return `UNSUPPORTED`.
+//!
+//! The mock builds on x86_64 and aarch64 hosts, where the variadic services are stubbed out in
+//! assembly.
+
+extern crate std;
+
+use core::{
+    ffi::c_void,
+    mem::{self, size_of},
+    ptr::{self, NonNull},
+    sync::atomic::Ordering,
+};
+use std::{
+    alloc::{self, Layout},
+    boxed::Box,
+    collections::{BTreeMap, VecDeque},
+    string::String,
+    sync::{Mutex, MutexGuard, PoisonError},
+    vec::Vec,
+};
+
+use crate::{
+    bootstrap,
+    proto::{
+        console::{
+            text_input::SimpleTextInput,
+            text_output::{SimpleTextOutput, SimpleTextOutputMode},
+        },
+        DevicePath, Proto, Protocol,
+    },
+    table::*,
+    Event, Guid, Handle, PhysicalAddr, Status, Tpl, IMAGE_HANDLE, PAGE_SIZE, SYSTEM_TABLE,
+};
+
+/// Event ID reserved for [`SimpleTextInput`]'s `wait_for_key` event
+const WAIT_FOR_KEY: usize = 1;
+
+/// Serializes installations of the mock
+static INSTALLED: Mutex<()> = Mutex::new(());
+static STATE: Mutex<Option<State>> = Mutex::new(None);
+
+struct State {
+    system_table:     *mut SystemTable,
+    stderr:           *mut SimpleTextOutput,
+    tpl:              usize,
+    /// Virtual time, in 100ns units
+    now:              u64,
+    monotonic_count:  u64,
+    watchdog_timeout: usize,
+    exited:           bool,
+    allocations:      BTreeMap<usize, (Layout, MemoryType)>,
+    memory_map:       Vec<MemoryDescriptor>,
+    map_key:          usize,
+    next_handle:      usize,
+    protocols:        Vec<ProtocolEntry>,
+    events:           BTreeMap<usize, EventState>,
+    next_event:       usize,
+    /// Protocol notifications, keyed by index + 1
+    registrations:    Vec<Registration>,
+    config_table:     Vec<ConfigurationEntry>,
+    stdout_text:      String,
+    stderr_text:      String,
+    keys:             VecDeque<crate::proto::console::text_input::InputKey>,
+}
+
+// SAFETY: The raw pointers are only ever dereferenced by the thread holding `INSTALLED`.
+unsafe impl Send for State {}
+
+struct ProtocolEntry {
+    handle:    Handle,
+    guid:      Box<Guid>,
+    interface: *mut c_void,
+}
+
+struct Registration {
+    guid:    Guid,
+    event:   usize,
+    /// Handles installed since the last `LocateHandle(ByRegisterNotify)`
+    pending: VecDeque<Handle>,
+}
+
+struct EventState {
+    kind:     EventType,
+    signaled: bool,
+    notify:   Option<(EventNotifyFn, *mut c_void)>,
+    deadline: Option<u64>,
+    period:   u64,
+}
+
+type Notify = (EventNotifyFn, Event, *mut c_void);
+
+fn with_state<R>(f: impl FnOnce(&mut State) -> R) -> R {
+    let mut state = STATE.lock().unwrap_or_else(PoisonError::into_inner);
+    f(state.as_mut().expect("no mock system table is installed"))
+}
+
+fn run_notifies(notifies: Vec<Notify>) {
+    for (notify_fn, event, ctx) in notifies {
+        notify_fn(event, ctx);
+    }
+}
+
+impl State {
+    fn new_handle(&mut self) -> Handle {
+        self.next_handle += 1;
+        Handle(NonNull::new((self.next_handle * 16) as *mut c_void).unwrap())
+    }
+
+    fn find_protocol(&self, handle: Handle, guid: &Guid) -> Option<usize> {
+        self.protocols
+            .iter()
+            .position(|entry| entry.handle.0 == handle.0 && *entry.guid == *guid)
+    }
+
+    /// Queues `handle` for the notifications registered for `guid` and signals their events
+    fn notify_install(&mut self, handle: Handle, guid: &Guid, notifies: &mut Vec<Notify>) {
+        let mut events = Vec::new();
+        for registration in &mut self.registrations {
+            if registration.guid == *guid && self.events.contains_key(&registration.event) {
+                registration.pending.push_back(handle);
+                events.push(registration.event);
+            }
+        }
+        for id in events {
+            self.signal(id, notifies);
+        }
+    }
+
+    fn handles(&self, search_type: LocateSearchType, guid: *mut Guid) -> Option<Vec<Handle>> {
+        let mut handles = Vec::<Handle>::new();
+        for entry in &self.protocols {
+            let matches = match search_type {
+                LocateSearchType::AllHandles => true,
+                LocateSearchType::ByProtocol => unsafe { *entry.guid == *guid },
+                LocateSearchType::ByRegisterNotify => return None,
+            };
+            if matches && !handles.iter().any(|h| h.0 == entry.handle.0) {
+                handles.push(entry.handle);
+            }
+        }
+        Some(handles)
+    }
+
+    fn allocate(&mut self, layout: Layout, memory_type: MemoryType) -> Option<*mut u8> {
+        let ptr = unsafe { alloc::alloc_zeroed(layout) };
+        if ptr.is_null() {
+            return None;
+        }
+        self.allocations.insert(ptr as usize, (layout, memory_type));
+        self.map_key += 1;
+        Some(ptr)
+    }
+
+    fn free(&mut self, addr: usize, expected_size: Option<usize>) -> Status {
+        match self.allocations.get(&addr) {
+            None => Status::NOT_FOUND,
+            Some((layout, _)) if expected_size.is_some_and(|size| size != layout.size()) => {
+                Status::INVALID_PARAMETER
+            }
+            Some(&(layout, _)) => {
+                self.allocations.remove(&addr);
+                self.map_key += 1;
+                unsafe { alloc::dealloc(addr as *mut u8, layout) };
+                Status::SUCCESS
+            }
+        }
+    }
+
+    /// Allocates a pool buffer holding a copy of `items`
+    fn pool_copy<T>(&mut self, items: &[T]) -> Option<*mut T> {
+        let layout = Layout::array::<T>(items.len().max(1))
+            .ok()?
+            .align_to(8)
+            .ok()?;
+        let ptr = self
+            .allocate(layout, MemoryType::BOOT_SERVICES_DATA)?
+            .cast::<T>();
+        unsafe { ptr::copy_nonoverlapping(items.as_ptr(), ptr, items.len()) };
+        Some(ptr)
+    }
+
+    fn is_signaled(&self, id: usize) -> Option<bool> {
+        if id == WAIT_FOR_KEY {
+            return Some(!self.keys.is_empty());
+        }
+        self.events.get(&id).map(|event| event.signaled)
+    }
+
+    fn signal(&mut self, id: usize, notifies: &mut Vec<Notify>) {
+        if let Some(event) = self.events.get_mut(&id) {
+            if event.kind.contains(EventType::NOTIFY_SIGNAL) {
+                if let Some((notify_fn, ctx)) = event.notify {
+                    notifies.push((notify_fn, Event(id as *mut c_void), ctx));
+                }
+            } else {
+                event.signaled = true;
+            }
+        }
+    }
+
+    /// Advances the virtual clock, firing any timers which expire
+    fn advance(&mut self, until: u64) -> Vec<Notify> {
+        let mut notifies = Vec::new();
+        loop {
+            let next = self
+                .events
+                .iter()
+                .filter_map(|(&id, event)| Some((event.deadline?, id)))
+                .filter(|&(deadline, _)| deadline <= until)
+                .min();
+            let Some((deadline, id)) = next else { break };
+
+            self.now = self.now.max(deadline);
+            let event = self.events.get_mut(&id).unwrap();
+            event.deadline = match event.period {
+                0 => None,
+                period => Some(deadline + period),
+            };
+            self.signal(id, &mut notifies);
+        }
+        self.now = self.now.max(until);
+        notifies
+    }
+
+    fn next_deadline(&self) -> Option<u64> {
+        self.events
+            .values()
+            .filter_map(|event| event.deadline)
+            .min()
+    }
+
+    fn update_config_table(&mut self) {
+        unsafe {
+            (*self.system_table).config_table = self.config_table.as_mut_ptr().cast();
+            (*self.system_table).config_table_entries = self.config_table.len();
+        }
+    }
+}
+
+/*
+ * Boot Services
+ */
+
+extern "efiapi" fn raise_tpl(new: Tpl) -> Tpl {
+    with_state(|state| Tpl(mem::replace(&mut state.tpl, new.0)))
+}
+
+extern "efiapi" fn restore_tpl(old: Tpl) {
+    with_state(|state| state.tpl = old.0);
+}
+
+extern "efiapi" fn allocate_pages(
+    alloc_type: AllocType,
+    memory_type: MemoryType,
+    pages: usize,
+    memory: *mut PhysicalAddr,
+) -> Status {
+    let Some(size) = pages.checked_mul(PAGE_SIZE).filter(|&size| size > 0) else {
+        return Status::INVALID_PARAMETER;
+    };
+    let Ok(layout) = Layout::from_size_align(size, PAGE_SIZE) else {
+        return Status::INVALID_PARAMETER;
+    };
+
+    with_state(|state| {
+        if alloc_type == AllocType::Address {
+            // Host memory cannot be placed at a caller-chosen address.
+            return Status::NOT_FOUND;
+        }
+        let Some(ptr) = state.allocate(layout, memory_type) else {
+            return Status::OUT_OF_RESOURCES;
+        };
+        let addr = ptr as PhysicalAddr;
+        if alloc_type == AllocType::MaxAddress && addr + (size as u64 - 1) > unsafe { *memory } {
+            state.free(ptr as usize, None);
+            return Status::NOT_FOUND;
+        }
+        unsafe { *memory = addr };
+        Status::SUCCESS
+    })
+}
+
+extern "efiapi" fn free_pages(memory: PhysicalAddr, pages: usize) -> Status {
+    with_state(|state| state.free(memory as usize, Some(pages * PAGE_SIZE)))
+}
+
+extern "efiapi" fn get_memory_map(
+    memory_map_size: *mut usize,
+    memory_map: *mut MemoryDescriptor,
+    map_key: *mut usize,
+    descriptor_size: *mut usize,
+    descriptor_version: *mut u32,
+) -> Status {
+    with_state(|state| unsafe {
+        let needed = state.memory_map.len() * size_of::<MemoryDescriptor>();
+        let available = mem::replace(&mut *memory_map_size, needed);
+        *descriptor_size = size_of::<MemoryDescriptor>();
+        *descriptor_version = 1;
+        *map_key = state.map_key;
+        if available < needed {
+            Status::BUFFER_TOO_SMALL
+        } else if memory_map.is_null() {
+            Status::INVALID_PARAMETER
+        } else {
+            let src = state.memory_map.as_ptr();
+            ptr::copy_nonoverlapping(src, memory_map, state.memory_map.len());
+            Status::SUCCESS
+        }
+    })
+}
+
+extern "efiapi" fn allocate_pool(
+    pool_type: MemoryType,
+    size: usize,
+    buffer: *mut *mut c_void,
+) -> Status {
+    let Ok(layout) = Layout::from_size_align(size.max(1), 8) else {
+        return Status::INVALID_PARAMETER;
+    };
+    with_state(|state| match state.allocate(layout, pool_type) {
+        Some(ptr) => {
+            unsafe { *buffer = ptr.cast() };
+            Status::SUCCESS
+        }
+        None => Status::OUT_OF_RESOURCES,
+    })
+}
+
+extern "efiapi" fn free_pool(buffer: *mut c_void) -> Status {
+    with_state(|state| match state.free(buffer as usize, None) {
+        Status::NOT_FOUND => Status::INVALID_PARAMETER,
+        status => status,
+    })
+}
+
+extern "efiapi" fn create_event(
+    kind: EventType,
+    _notify_tpl: Tpl,
+    notify_fn: Option<EventNotifyFn>,
+    notify_ctx: *mut c_void,
+    event: *mut Event,
+) -> Status {
+    with_state(|state| {
+        state.next_event += 1;
+        let id = state.next_event;
+        state.events.insert(id, EventState {
+            kind,
+            signaled: false,
+            notify: notify_fn.map(|f| (f, notify_ctx)),
+            deadline: None,
+            period: 0,
+        });
+        unsafe { event.write(Event(id as *mut c_void)) };
+        Status::SUCCESS
+    })
+}
+
+extern "efiapi" fn set_timer(event: Event, kind: TimerDelay, trigger_time: u64) -> Status {
+    with_state(|state| {
+        let now = state.now;
+        match state.events.get_mut(&(event.0 as usize)) {
+            Some(event) if event.kind.contains(EventType::TIMER) => {
+                (event.deadline, event.period) = match kind {
+                    TimerDelay::Cancel => (None, 0),
+                    TimerDelay::Relative => (Some(now + trigger_time), 0),
+                    TimerDelay::Periodic => (Some(now + trigger_time), trigger_time),
+                };
+                Status::SUCCESS
+            }
+            _ => Status::INVALID_PARAMETER,
+        }
+    })
+}
+
+extern "efiapi" fn wait_for_event(
+    num_events: usize,
+    events: *mut Event,
+    index: *mut usize,
+) -> Status {
+    if num_events == 0 {
+        return Status::INVALID_PARAMETER;
+    }
+    let events = unsafe { core::slice::from_raw_parts(events, num_events) };
+
+    loop {
+        let notifies = with_state(|state| {
+            for (i, event) in events.iter().enumerate() {
+                let id = event.0 as usize;
+                match state.is_signaled(id) {
+                    None => return Err(Status::INVALID_PARAMETER),
+                    Some(true) => {
+                        if let Some(event) = state.events.get_mut(&id) {
+                            event.signaled = false;
+                        }
+                        unsafe { *index = i };
+                        return Err(Status::SUCCESS);
+                    }
+                    Some(false) => {}
+                }
+            }
+            match state.next_deadline() {
+                Some(deadline) => Ok(state.advance(deadline)),
+                None => Err(Status::NOT_READY),
+            }
+        });
+        match notifies {
+            Ok(notifies) => run_notifies(notifies),
+            Err(status) => return status,
+        }
+    }
+}
+
+extern "efiapi" fn signal_event(event: Event) -> Status {
+    let mut notifies = Vec::new();
+    let status = with_state(|state| {
+        let id = event.0 as usize;
+        if !state.events.contains_key(&id) {
+            return Status::INVALID_PARAMETER;
+        }
+        state.signal(id, &mut notifies);
+        Status::SUCCESS
+    });
+    run_notifies(notifies);
+    status
+}
+
+extern "efiapi" fn close_event(event: Event) -> Status {
+    with_state(|state| match state.events.remove(&(event.0 as usize)) {
+        Some(_) => Status::SUCCESS,
+        None => Status::INVALID_PARAMETER,
+    })
+}
+
+extern "efiapi" fn check_event(event: Event) -> Status {
+    with_state(|state| {
+        let id = event.0 as usize;
+        if let Some(event) = state.events.get_mut(&id) {
+            if event.kind.contains(EventType::NOTIFY_SIGNAL) {
+                return Status::INVALID_PARAMETER;
+            }
+            if mem::take(&mut event.signaled) {
+                return Status::SUCCESS;
+            }
+            return Status::NOT_READY;
+        }
+        match state.is_signaled(id) {
+            Some(true) => Status::SUCCESS,
+            Some(false) => Status::NOT_READY,
+            None => Status::INVALID_PARAMETER,
+        }
+    })
+}
+
+extern "efiapi" fn install_protocol_interface(
+    handle: *mut Handle,
+    protocol: *mut Guid,
+    _interface_type: InterfaceType,
+    interface: *mut c_void,
+) -> Status {
+    let mut notifies = Vec::new();
+    let status = with_state(|state| unsafe {
+        let guid = *protocol;
+        let handle = match *handle.cast::<Option<Handle>>() {
+            Some(handle) if state.find_protocol(handle, &guid).is_some() => {
+                return Status::INVALID_PARAMETER;
+            }
+            Some(handle) => handle,
+            None => {
+                let new = state.new_handle();
+                handle.write(new);
+                new
+            }
+        };
+        state.protocols.push(ProtocolEntry {
+            handle,
+            guid: Box::new(guid),
+            interface,
+        });
+        state.notify_install(handle, &guid, &mut notifies);
+        Status::SUCCESS
+    });
+    run_notifies(notifies);
+    status
+}
+
+extern "efiapi" fn reinstall_protocol_interface(
+    handle: Handle,
+    protocol: *mut Guid,
+    old_interface: *mut c_void,
+    new_interface: *mut c_void,
+) -> Status {
+    let mut notifies = Vec::new();
+    let status = with_state(|state| {
+        let guid = unsafe { *protocol };
+        match state.find_protocol(handle, &guid) {
+            Some(i) if state.protocols[i].interface == old_interface => {
+                state.protocols[i].interface = new_interface;
+                state.notify_install(handle, &guid, &mut notifies);
+                Status::SUCCESS
+            }
+            _ => Status::NOT_FOUND,
+        }
+    });
+    run_notifies(notifies);
+    status
+}
+
+extern "efiapi" fn uninstall_protocol_interface(
+    handle: Handle,
+    protocol: *mut Guid,
+    interface: *mut c_void,
+) -> Status {
+    with_state(
+        |state| match state.find_protocol(handle, unsafe { &*protocol }) {
+            Some(i) if state.protocols[i].interface == interface => {
+                state.protocols.remove(i);
+                Status::SUCCESS
+            }
+            _ => Status::NOT_FOUND,
+        },
+    )
+}
+
+extern "efiapi" fn handle_protocol(
+    handle: Handle,
+    protocol: *mut Guid,
+    interface: *mut *mut c_void,
+) -> Status {
+    with_state(
+        |state| match state.find_protocol(handle, unsafe { &*protocol }) {
+            Some(i) => {
+                unsafe { *interface = state.protocols[i].interface };
+                Status::SUCCESS
+            }
+            None => Status::UNSUPPORTED,
+        },
+    )
+}
+
+extern "efiapi" fn locate_handle(
+    search_type: LocateSearchType,
+    protocol: *mut Guid,
+    search_key: *mut c_void,
+    buffer_size: *mut usize,
+    buffer: *mut Handle,
+) -> Status {
+    with_state(|state| {
+        if matches!(search_type, LocateSearchType::ByRegisterNotify) {
+            let Some(registration) = (search_key as usize)
+                .checked_sub(1)
+                .and_then(|i| state.registrations.get_mut(i))
+            else {
+                return Status::INVALID_PARAMETER;
+            };
+            let Some(&handle) = registration.pending.front() else {
+                return Status::NOT_FOUND;
+            };
+            unsafe {
+                if mem::replace(&mut *buffer_size, size_of::<Handle>()) < size_of::<Handle>() {
+                    return Status::BUFFER_TOO_SMALL;
+                }
+                buffer.write(handle);
+            }
+            registration.pending.pop_front();
+            return Status::SUCCESS;
+        }
+        let Some(handles) = state.handles(search_type, protocol) else {
+            return Status::UNSUPPORTED;
+        };
+        if handles.is_empty() {
+            return Status::NOT_FOUND;
+        }
+        unsafe {
+            let needed = handles.len() * size_of::<Handle>();
+            if mem::replace(&mut *buffer_size, needed) < needed {
+                return Status::BUFFER_TOO_SMALL;
+            }
+            ptr::copy_nonoverlapping(handles.as_ptr(), buffer, handles.len());
+        }
+        Status::SUCCESS
+    })
+}
+
+extern "efiapi" fn install_configuration_table(guid: *mut Guid, table: *mut c_void) -> Status {
+    with_state(|state| {
+        let guid = TableGuid(unsafe { *guid });
+        let existing = state
+            .config_table
+            .iter()
+            .position(|e| e.vendor_guid == guid);
+        match (existing, table.is_null()) {
+            (Some(i), true) => _ = state.config_table.remove(i),
+            (Some(i), false) => state.config_table[i].vendor_table = table,
+            (None, true) => return Status::NOT_FOUND,
+            (None, false) => state.config_table.push(ConfigurationEntry {
+                vendor_guid:  guid,
+                vendor_table: table,
+            }),
+        }
+        state.update_config_table();
+        Status::SUCCESS
+    })
+}
+
+extern "efiapi" fn exit_boot_services(_image_handle: Handle, map_key: usize) -> Status {
+    with_state(|state| {
+        if map_key != state.map_key {
+            return Status::INVALID_PARAMETER;
+        }
+        state.exited = true;
+        Status::SUCCESS
+    })
+}
+
+extern "efiapi" fn get_next_monotonic_count(count: *mut u64) -> Status {
+    with_state(|state| {
+        state.monotonic_count += 1;
+        unsafe { *count = state.monotonic_count };
+        Status::SUCCESS
+    })
+}
+
+extern "efiapi" fn stall(microseconds: usize) -> Status {
+    let notifies = with_state(|state| state.advance(state.now + microseconds as u64 * 10));
+    run_notifies(notifies);
+    Status::SUCCESS
+}
+
+extern "efiapi" fn set_watchdog_timer(
+    timeout: usize,
+    _watchdog_code: u64,
+    _data_size: usize,
+    _watchdog_data: *mut u16,
+) -> Status {
+    with_state(|state| state.watchdog_timeout = timeout);
+    Status::SUCCESS
+}
+
+extern "efiapi" fn open_protocol(
+    handle: Handle,
+    protocol: *mut Guid,
+    interface: *mut *mut c_void,
+    _agent_handle: Handle,
+    _controller_handle: Handle,
+    attributes: OpenProtocolAttributes,
+) -> Status {
+    with_state(
+        |state| match state.find_protocol(handle, unsafe { &*protocol }) {
+            Some(_) if attributes.contains(OpenProtocolAttributes::TEST_PROTOCOL) => {
+                Status::SUCCESS
+            }
+            Some(i) => {
+                unsafe { *interface = state.protocols[i].interface };
+                Status::SUCCESS
+            }
+            None => Status::UNSUPPORTED,
+        },
+    )
+}
+
+extern "efiapi" fn close_protocol(
+    handle: Handle,
+    protocol: *mut Guid,
+    _agent_handle: Handle,
+    _controller_handle: Handle,
+) -> Status {
+    with_state(
+        |state| match state.find_protocol(handle, unsafe { &*protocol }) {
+            Some(_) => Status::SUCCESS,
+            None => Status::NOT_FOUND,
+        },
+    )
+}
+
+extern "efiapi" fn protocols_per_handle(
+    handle: Handle,
+    protocol_buffer: *mut *mut *mut Guid,
+    protocol_buffer_count: *mut usize,
+) -> Status {
+    with_state(|state| {
+        let guids = state
+            .protocols
+            .iter_mut()
+            .filter(|entry| entry.handle.0 == handle.0)
+            .map(|entry| &mut *entry.guid as *mut Guid)
+            .collect::<Vec<_>>();
+        if guids.is_empty() {
+            return Status::INVALID_PARAMETER;
+        }
+        match state.pool_copy(&guids) {
+            Some(buffer) => unsafe {
+                *protocol_buffer = buffer;
+                *protocol_buffer_count = guids.len();
+                Status::SUCCESS
+            },
+            None => Status::OUT_OF_RESOURCES,
+        }
+    })
+}
+
+extern "efiapi" fn locate_handle_buffer(
+    search_type: LocateSearchType,
+    protocol: *mut Guid,
+    _search_key: *mut c_void,
+    num_handles: *mut usize,
+    buffer: *mut *mut Handle,
+) -> Status {
+    with_state(|state| {
+        let Some(handles) = state.handles(search_type, protocol) else {
+            return Status::UNSUPPORTED;
+        };
+        if handles.is_empty() {
+            return Status::NOT_FOUND;
+        }
+        match state.pool_copy(&handles) {
+            Some(ptr) => unsafe {
+                *buffer = ptr;
+                *num_handles = handles.len();
+                Status::SUCCESS
+            },
+            None => Status::OUT_OF_RESOURCES,
+        }
+    })
+}
+
+extern "efiapi" fn locate_protocol(
+    protocol: *mut Guid,
+    _registration: *mut c_void,
+    interface: *mut *mut c_void,
+) -> Status {
+    with_state(|state| {
+        let guid = unsafe { *protocol };
+        match state.protocols.iter().find(|entry| *entry.guid == guid) {
+            Some(entry) => {
+                unsafe { *interface = entry.interface };
+                Status::SUCCESS
+            }
+            None => Status::NOT_FOUND,
+        }
+    })
+}
+
+extern "efiapi" fn copy_mem(dest: *mut c_void, src: *mut c_void, length: usize) {
+    unsafe { ptr::copy(src.cast::<u8>(), dest.cast::<u8>(), length) };
+}
+
+extern "efiapi" fn set_mem(buffer: *mut c_void, size: usize, value: u8) {
+    unsafe { ptr::write_bytes(buffer.cast::<u8>(), value, size) };
+}
+
+extern "efiapi" fn register_protocol_notify(
+    protocol: *mut Guid,
+    event: Event,
+    registration: *mut *mut c_void,
+) -> Status {
+    with_state(|state| {
+        if !state.events.contains_key(&(event.0 as usize)) {
+            return Status::INVALID_PARAMETER;
+        }
+        state.registrations.push(Registration {
+            guid:    unsafe { *protocol },
+            event:   event.0 as usize,
+            pending: VecDeque::new(),
+        });
+        unsafe { *registration = state.registrations.len() as *mut c_void };
+        Status::SUCCESS
+    })
+}
+
+extern "efiapi" fn locate_device_path(
+    _: *mut Guid,
+    _: *mut Proto<DevicePath>,
+    _: *mut Handle,
+) -> Status {
+    Status::UNSUPPORTED
+}
+
+extern "efiapi" fn load_image(
+    _: bool,
+    _: Handle,
+    _: Option<Proto<DevicePath>>,
+    _: *mut c_void,
+    _: usize,
+    _: *mut Handle,
+) -> Status {
+    Status::UNSUPPORTED
+}
+
+extern "efiapi" fn start_image(_: Handle, _: *mut usize, _: *mut *mut u16) -> Status {
+    Status::UNSUPPORTED
+}
+
+extern "efiapi" fn exit(_: Handle, _: Status, _: usize, _: *mut u16) -> Status {
+    Status::UNSUPPORTED
+}
+
+extern "efiapi" fn unload_image(_: Handle) -> Status {
+    Status::UNSUPPORTED
+}
+
+extern "efiapi" fn connect_controller(
+    _: Handle,
+    _: *mut Option<Handle>,
+    _: Option<Proto<DevicePath>>,
+    _: bool,
+) -> Status {
+    Status::UNSUPPORTED
+}
+
+extern "efiapi" fn disconnect_controller(
+    _: Handle,
+    _: Option<Handle>,
+    _: Option<Handle>,
+) -> Status {
+    Status::UNSUPPORTED
+}
+
+extern "efiapi" fn open_protocol_information(
+    _: Handle,
+    _: *mut Guid,
+    _: *mut *mut OpenProtocolInformationEntry,
+    _: *mut usize,
+) -> Status {
+    Status::UNSUPPORTED
+}
+
+// Stands in for the variadic services, which can't be defined in Rust with the `efiapi` ABI.
+// It returns `UNSUPPORTED` without reading any arguments.
+#[cfg(not(target_vendor = "apple"))]
+macro_rules! multiple_protocol_interfaces_symbol {
+    () => {
+        "uefi_mock_multiple_protocol_interfaces"
+    };
+}
+// Mach-O prefixes C symbols with an underscore.
+#[cfg(target_vendor = "apple")]
+macro_rules! multiple_protocol_interfaces_symbol {
+    () => {
+        "_uefi_mock_multiple_protocol_interfaces"
+    };
+}
+#[cfg(target_arch = "x86_64")]
+core::arch::global_asm!(
+    concat!(".globl ", multiple_protocol_interfaces_symbol!()),
+    concat!(multiple_protocol_interfaces_symbol!(), ":"),
+    "    mov rax, {status}",
+    "    ret",
+    status = const Status::UNSUPPORTED.0,
+);
+#[cfg(target_arch = "aarch64")]
+core::arch::global_asm!(
+    concat!(".globl ", multiple_protocol_interfaces_symbol!()),
+    concat!(multiple_protocol_interfaces_symbol!(), ":"),
+    "    ldr x0, ={status}",
+    "    ret",
+    status = const Status::UNSUPPORTED.0,
+);
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+compile_error!("the `mock` feature supports x86_64 and aarch64 hosts only");
+
+unsafe extern "efiapi" {
+    #[link_name = "uefi_mock_multiple_protocol_interfaces"]
+    safe fn multiple_protocol_interfaces(handle: *mut Handle, ...) -> Status;
+}
+
+extern "efiapi" fn calculate_crc32(data: *mut c_void, data_size: usize, crc32: *mut u32) -> Status {
+    if data.is_null() || crc32.is_null() || data_size == 0 {
+        return Status::INVALID_PARAMETER;
+    }
+    let data = unsafe { core::slice::from_raw_parts(data.cast::<u8>(), data_size) };
+    unsafe { *crc32 = crate::crc32::software(data) };
+    Status::SUCCESS
+}
+
+extern "efiapi" fn create_event_ex(
+    _: EventType,
+    _: Tpl,
+    _: Option<EventNotifyFn>,
+    _: *const c_void,
+    _: *const Guid,
+    _: *mut Event,
+) -> Status {
+    Status::UNSUPPORTED
+}
+
+/*
+ * Console
+ */
+
+extern "efiapi" fn input_reset(_this: *mut SimpleTextInput, _: bool) -> Status {
+    Status::SUCCESS
+}
+
+extern "efiapi" fn read_keystroke(
+    _this: *mut SimpleTextInput,
+    key: *mut crate::proto::console::text_input::InputKey,
+) -> Status {
+    with_state(|state| match state.keys.pop_front() {
+        Some(next) => {
+            unsafe { *key = next };
+            Status::SUCCESS
+        }
+        None => Status::NOT_READY,
+    })
+}
+
+extern "efiapi" fn output_reset(this: *mut SimpleTextOutput, _: bool) -> Status {
+    output_clear_screen(this)
+}
+
+extern "efiapi" fn output_string(this: *mut SimpleTextOutput, string: *mut u16) -> Status {
+    let mut len = 0;
+    while unsafe { *string.add(len) } != 0 {
+        len += 1;
+    }
+    let units = unsafe { core::slice::from_raw_parts(string, len) };
+    let text = char::decode_utf16(units.iter().copied())
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect::<String>();
+
+    with_state(|state| match this == state.stderr {
+        true => state.stderr_text.push_str(&text),
+        false => state.stdout_text.push_str(&text),
+    });
+    Status::SUCCESS
+}
+
+extern "efiapi" fn test_string(_this: *mut SimpleTextOutput, _string: *mut u16) -> Status {
+    Status::SUCCESS
+}
+
+extern "efiapi" fn output_query_mode(
+    _this: *mut SimpleTextOutput,
+    mode: usize,
+    cols: *mut usize,
+    rows: *mut usize,
+) -> Status {
+    if mode != 0 {
+        return Status::UNSUPPORTED;
+    }
+    unsafe {
+        *cols = 80;
+        *rows = 25;
+    }
+    Status::SUCCESS
+}
+
+extern "efiapi" fn output_set_mode(_this: *mut SimpleTextOutput, mode: usize) -> Status {
+    match mode {
+        0 => Status::SUCCESS,
+        _ => Status::UNSUPPORTED,
+    }
+}
+
+extern "efiapi" fn output_set_attribute(this: *mut SimpleTextOutput, attribute: usize) -> Status {
+    unsafe { (*(*this).mode).attribute = attribute as i32 };
+    Status::SUCCESS
+}
+
+extern "efiapi" fn output_clear_screen(this: *mut SimpleTextOutput) -> Status {
+    output_set_cursor_position(this, 0, 0)
+}
+
+extern "efiapi" fn output_set_cursor_position(
+    this: *mut SimpleTextOutput,
+    column: usize,
+    row: usize,
+) -> Status {
+    if column >= 80 || row >= 25 {
+        return Status::UNSUPPORTED;
+    }
+    unsafe {
+        (*(*this).mode).cursor_column = column as i32;
+        (*(*this).mode).cursor_row = row as i32;
+    }
+    Status::SUCCESS
+}
+
+extern "efiapi" fn output_enable_cursor(this: *mut SimpleTextOutput, visible: bool) -> Status {
+    unsafe { (*(*this).mode).cursor_visible = visible };
+    Status::SUCCESS
+}
+
+fn new_output() -> NonNull<SimpleTextOutput> {
+    let mode = Box::into_raw(Box::new(SimpleTextOutputMode {
+        max_mode:       1,
+        mode:           0,
+        attribute:      0x07,
+        cursor_column:  0,
+        cursor_row:     0,
+        cursor_visible: true,
+    }));
+    NonNull::from(Box::leak(Box::new(SimpleTextOutput {
+        reset: output_reset,
+        output_string,
+        test_string,
+        query_mode: output_query_mode,
+        set_mode: output_set_mode,
+        set_attribute: output_set_attribute,
+        clear_screen: output_clear_screen,
+        set_cursor_position: output_set_cursor_position,
+        enable_cursor: output_enable_cursor,
+        mode,
+    })))
+}
+
+static FIRMWARE_VENDOR: [u16; 5] = [b'M' as u16, b'o' as u16, b'c' as u16, b'k' as u16, 0];
+
+/// A mocked system table, installed as the crate's global system table
+///
+/// Dropping this value uninstalls the mock and frees any memory still allocated through it.
+pub struct MockSystemTable {
+    system_table:  NonNull<SystemTable>,
+    boot_services: MockBootServices,
+    stdin:         NonNull<SimpleTextInput>,
+    stdout:        NonNull<SimpleTextOutput>,
+    stderr:        NonNull<SimpleTextOutput>,
+    _installed:    MutexGuard<'static, ()>,
+}
+
+/// The mocked boot services table
+///
+/// Provides access to the state behind the mocked services.
+pub struct MockBootServices {
+    table: NonNull<BootServices>,
+}
+
+impl MockSystemTable {
+    /// Revision reported in the table headers (2.70)
+    pub const REVISION: u32 = (2 << 16) | 70;
+
+    /// Builds the mocked tables and installs them with [`bootstrap()`]
+    pub fn install() -> MockSystemTable {
+        let installed = INSTALLED.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let boot_services = NonNull::from(Box::leak(Box::new(BootServices {
+            header: TableHeader {
+                signature:   0x56524553544f4f42,
+                revision:    Self::REVISION,
+                header_size: size_of::<BootServices>() as u32,
+                checksum:    0,
+                reserved:    0,
+            },
+            raise_tpl,
+            restore_tpl,
+            allocate_pages,
+            free_pages,
+            get_memory_map,
+            allocate_pool,
+            free_pool,
+            create_event,
+            set_timer,
+            wait_for_event,
+            signal_event,
+            close_event,
+            check_event,
+            install_protocol_interface,
+            reinstall_protocol_interface,
+            uninstall_protocol_interface,
+            handle_protocol,
+            reserved: ptr::null_mut(),
+            register_protocol_notify,
+            locate_handle,
+            locate_device_path,
+            install_configuration_table,
+            load_image,
+            start_image,
+            exit,
+            unload_image,
+            exit_boot_services,
+            get_next_monotonic_count,
+            stall,
+            set_watchdog_timer,
+            connect_controller,
+            disconnect_controller,
+            open_protocol,
+            close_protocol,
+            open_protocol_information,
+            protocols_per_handle,
+            locate_handle_buffer,
+            locate_protocol,
+            install_multiple_protocol_interfaces: multiple_protocol_interfaces,
+            uninstall_multiple_protocol_interfaces: multiple_protocol_interfaces,
+            calculate_crc32,
+            copy_mem,
+            set_mem,
+            create_event_ex,
+        })));
+
+        let stdin = NonNull::from(Box::leak(Box::new(SimpleTextInput {
+            reset: input_reset,
+            read_keystroke,
+            wait_for_key: Event(WAIT_FOR_KEY as *mut c_void),
+        })));
+        let stdout = new_output();
+        let stderr = new_output();
+
+        let mut state = State {
+            system_table:     ptr::null_mut(),
+            stderr:           stderr.as_ptr(),
+            tpl:              Tpl::APPLICATION.0,
+            now:              0,
+            monotonic_count:  0,
+            watchdog_timeout: 5 * 60,
+            exited:           false,
+            allocations:      BTreeMap::new(),
+            memory_map:       std::vec![MemoryDescriptor {
+                kind:      MemoryType::CONVENTIONAL_MEMORY,
+                phys:      0x100000,
+                virt:      0,
+                num_pages: 0x1000,
+                attribute: MemoryAttribute::WB,
+            }],
+            map_key:          1,
+            next_handle:      0,
+            protocols:        Vec::new(),
+            events:           BTreeMap::new(),
+            next_event:       WAIT_FOR_KEY,
+            registrations:    Vec::new(),
+            config_table:     Vec::new(),
+            stdout_text:      String::new(),
+            stderr_text:      String::new(),
+            keys:             VecDeque::new(),
+        };
+
+        let image_handle = state.new_handle();
+        let mut console_handle = |guid: Guid, interface: *mut c_void| {
+            let handle = state.new_handle();
+            state.protocols.push(ProtocolEntry {
+                handle,
+                guid: Box::new(guid),
+                interface,
+            });
+            handle
+        };
+        let stdin_handle = console_handle(SimpleTextInput::GUID, stdin.as_ptr().cast());
+        let stdout_handle = console_handle(SimpleTextOutput::GUID, stdout.as_ptr().cast());
+        let stderr_handle = console_handle(SimpleTextOutput::GUID, stderr.as_ptr().cast());
+
+        let system_table = NonNull::from(Box::leak(Box::new(SystemTable {
+            header: TableHeader {
+                signature:   0x5453595320494249,
+                revision:    Self::REVISION,
+                header_size: size_of::<SystemTable>() as u32,
+                checksum:    0,
+                reserved:    0,
+            },
+            firmware_vendor: FIRMWARE_VENDOR.as_ptr().cast_mut(),
+            firmware_revision: 0,
+            stdin_handle,
+            stdin: unsafe { Proto::new(stdin) },
+            stdout_handle,
+            stdout: unsafe { Proto::new(stdout) },
+            stderr_handle,
+            stderr: unsafe { Proto::new(stderr) },
+            runtime_services: ptr::null_mut(),
+            boot_services: boot_services.as_ptr(),
+            config_table_entries: 0,
+            config_table: ptr::null_mut(),
+        })));
+
+        state.system_table = system_table.as_ptr();
+        state.update_config_table();
+        *STATE.lock().unwrap_or_else(PoisonError::into_inner) = Some(state);
+
+        unsafe { bootstrap(image_handle, system_table.as_ref()) };
+
+        MockSystemTable {
+            system_table,
+            boot_services: MockBootServices {
+                table: boot_services,
+            },
+            stdin,
+            stdout,
+            stderr,
+            _installed: installed,
+        }
+    }
+
+    pub fn system_table(&self) -> &SystemTable {
+        unsafe { self.system_table.as_ref() }
+    }
+
+    pub fn boot_services(&self) -> &MockBootServices {
+        &self.boot_services
+    }
+
+    /// Queues a keystroke to be returned by `stdin`
+    pub fn push_key(&self, key: crate::proto::console::text_input::InputKey) {
+        with_state(|state| state.keys.push_back(key));
+    }
+
+    /// Queues keystrokes for each character of `s`
+    pub fn push_str(&self, s: &str) {
+        for codepoint in s.encode_utf16() {
+            self.push_key(crate::proto::console::text_input::InputKey {
+                scancode: 0,
+                codepoint,
+            });
+        }
+    }
+
+    /// Returns and clears everything written to `stdout`
+    pub fn take_stdout(&self) -> String {
+        with_state(|state| mem::take(&mut state.stdout_text))
+    }
+
+    /// Returns and clears everything written to `stderr`
+    pub fn take_stderr(&self) -> String {
+        with_state(|state| mem::take(&mut state.stderr_text))
+    }
+}
+
+impl Drop for MockSystemTable {
+    fn drop(&mut self) {
+        SYSTEM_TABLE.store(ptr::null_mut(), Ordering::Release);
+        IMAGE_HANDLE.store(ptr::null_mut(), Ordering::Release);
+
+        let state = STATE.lock().unwrap_or_else(PoisonError::into_inner).take();
+        if let Some(state) = state {
+            for (addr, (layout, _)) in state.allocations {
+                unsafe { alloc::dealloc(addr as *mut u8, layout) };
+            }
+        }
+
+        unsafe {
+            drop(Box::from_raw((*self.stdout.as_ptr()).mode));
+            drop(Box::from_raw((*self.stderr.as_ptr()).mode));
+            drop(Box::from_raw(self.stdout.as_ptr()));
+            drop(Box::from_raw(self.stderr.as_ptr()));
+            drop(Box::from_raw(self.stdin.as_ptr()));
+            drop(Box::from_raw(self.boot_services.table.as_ptr()));
+            drop(Box::from_raw(self.system_table.as_ptr()));
+        }
+    }
+}
+
+impl MockBootServices {
+    pub fn table(&self) -> &BootServices {
+        unsafe { self.table.as_ref() }
+    }
+
+    /// Replaces the memory map returned by `get_memory_map()`
+    ///
+    /// The initial map describes 16MiB of conventional memory at 1MiB.
+    pub fn set_memory_map(&self, map: Vec<MemoryDescriptor>) {
+        with_state(|state| {
+            state.memory_map = map;
+            state.map_key += 1;
+        });
+    }
+
+    /// Installs `interface` on `handle`, or a new handle if `None`
+    pub fn install_protocol<P: Protocol>(
+        &self,
+        handle: Option<Handle>,
+        interface: *mut P,
+    ) -> Handle {
+        with_state(|state| {
+            let handle = handle.unwrap_or_else(|| state.new_handle());
+            state.protocols.push(ProtocolEntry {
+                handle,
+                guid: Box::new(P::GUID),
+                interface: interface.cast(),
+            });
+            handle
+        })
+    }
+
+    /// Returns the number of page and pool allocations which have not been freed
+    pub fn outstanding_allocations(&self) -> usize {
+        with_state(|state| state.allocations.len())
+    }
+
+    /// Returns the virtual time elapsed since installation, in microseconds
+    pub fn elapsed_micros(&self) -> u64 {
+        with_state(|state| state.now / 10)
+    }
+
+    /// Returns the last timeout passed to `set_watchdog_timer()`, in seconds
+    pub fn watchdog_timeout(&self) -> usize {
+        with_state(|state| state.watchdog_timeout)
+    }
+
+    /// Returns `true` once `exit_boot_services()` has succeeded
+    pub fn exited_boot_services(&self) -> bool {
+        with_state(|state| state.exited)
+    }
+}
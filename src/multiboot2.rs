@@ -0,0 +1,402 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Multiboot2 boot information
+//!
+//! [`InfoBuilder`] assembles the boot information structure a Multiboot2 kernel receives,
+//! from data gathered through UEFI, into a caller-provided buffer:
+//!
+//! ```ignore
+//! let mut info = InfoBuilder::new(&mut buf)?;
+//! info.cmdline("console=ttyS0")?;
+//! info.module(initrd, initrd + initrd_len, "initrd")?;
+//! info.framebuffer(&gfx.framebuffer().unwrap())?;
+//! info.acpi()?;
+//! info.efi()?;
+//! info.memory_map(&map)?;
+//! let len = info.finish()?;
+//! ```
+//!
+//! [`Header::find()`] locates the Multiboot2 header of a kernel image, whose tags say which
+//! of these the kernel wants and where it expects to be entered.
+
+use core::mem::size_of;
+
+use crate::{
+    graphics::Framebuffer,
+    image_handle,
+    proto::console::gop::PixelFormat,
+    system_table,
+    table::{Format, MemoryMap, TableGuid},
+    PhysicalAddr, Result, Status,
+};
+
+/// Value a Multiboot2 kernel finds in `eax` when entered
+pub const BOOTLOADER_MAGIC: u32 = 0x36d7_6289;
+
+/// Magic which starts a Multiboot2 header
+pub const HEADER_MAGIC: u32 = 0xe852_50d6;
+
+/// How far into the image the header may start
+const HEADER_SEARCH_LEN: usize = 32768;
+
+const TAG_ALIGN: usize = 8;
+
+// Boot information tag types
+pub const TAG_END: u32 = 0;
+pub const TAG_CMDLINE: u32 = 1;
+pub const TAG_BOOTLOADER_NAME: u32 = 2;
+pub const TAG_MODULE: u32 = 3;
+pub const TAG_MMAP: u32 = 6;
+pub const TAG_FRAMEBUFFER: u32 = 8;
+pub const TAG_EFI32: u32 = 11;
+pub const TAG_EFI64: u32 = 12;
+pub const TAG_ACPI_OLD: u32 = 14;
+pub const TAG_ACPI_NEW: u32 = 15;
+pub const TAG_EFI_MMAP: u32 = 17;
+pub const TAG_EFI_BS: u32 = 18;
+pub const TAG_EFI32_IH: u32 = 19;
+pub const TAG_EFI64_IH: u32 = 20;
+
+const FRAMEBUFFER_TYPE_RGB: u8 = 1;
+
+/// Builds a boot information structure in a buffer
+///
+/// Each method appends one tag, failing with `BUFFER_TOO_SMALL` if it doesn't fit, in which
+/// case nothing is appended. Tags appear in the order they are added.
+pub struct InfoBuilder<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> InfoBuilder<'a> {
+    /// Starts a structure at the beginning of `buf`
+    ///
+    /// Fails with `INVALID_PARAMETER` if `buf` is not 8 byte aligned, as the kernel requires.
+    pub fn new(buf: &'a mut [u8]) -> Result<Self> {
+        if !(buf.as_ptr() as usize).is_multiple_of(TAG_ALIGN) {
+            return Err(Status::INVALID_PARAMETER);
+        }
+        if buf.len() < 8 {
+            return Err(Status::BUFFER_TOO_SMALL);
+        }
+        // `total_size` is filled in by `finish()`; the other field is reserved.
+        buf[..8].fill(0);
+        Ok(Self { buf, len: 8 })
+    }
+
+    /// Adds the kernel command line
+    pub fn cmdline(&mut self, cmdline: &str) -> Result<()> {
+        self.string_tag(TAG_CMDLINE, cmdline)
+    }
+
+    /// Adds the name of the boot loader
+    pub fn bootloader_name(&mut self, name: &str) -> Result<()> {
+        self.string_tag(TAG_BOOTLOADER_NAME, name)
+    }
+
+    /// Adds a module loaded at `start..end`, described by `string`
+    ///
+    /// Fails with `INVALID_PARAMETER` if the module isn't below 4 GiB, which the format
+    /// can't express.
+    pub fn module(&mut self, start: PhysicalAddr, end: PhysicalAddr, string: &str) -> Result<()> {
+        let (Ok(start), Ok(end)) = (u32::try_from(start), u32::try_from(end)) else {
+            return Err(Status::INVALID_PARAMETER);
+        };
+        let payload = self.tag(TAG_MODULE, 8 + string.len() + 1)?;
+        payload[0..4].copy_from_slice(&start.to_le_bytes());
+        payload[4..8].copy_from_slice(&end.to_le_bytes());
+        payload[8..8 + string.len()].copy_from_slice(string.as_bytes());
+        Ok(())
+    }
+
+    /// Adds the memory map, in both the generic and the EFI form
+    ///
+    /// The generic map counts memory the loader allocated as available; see
+    /// [`MemoryMap::write_packed()`].
+    pub fn memory_map(&mut self, map: &MemoryMap) -> Result<()> {
+        let start = self.len;
+        let size = map.packed_size(Format::Multiboot2);
+        let buf = self
+            .buf
+            .get_mut(start..start + size)
+            .ok_or(Status::BUFFER_TOO_SMALL)?;
+        let size = map.write_packed(buf, Format::Multiboot2)?;
+        self.len = (start + size).next_multiple_of(TAG_ALIGN);
+        if let Err(status) = self.efi_memory_map(map) {
+            self.len = start;
+            return Err(status);
+        }
+        Ok(())
+    }
+
+    fn efi_memory_map(&mut self, map: &MemoryMap) -> Result<()> {
+        let info = map.info();
+        let descriptor_size = info
+            .descriptor_size
+            .max(size_of::<crate::table::MemoryDescriptor>());
+        let payload = self.tag(TAG_EFI_MMAP, 8 + map.len() * descriptor_size)?;
+        payload[0..4].copy_from_slice(&(descriptor_size as u32).to_le_bytes());
+        payload[4..8].copy_from_slice(&info.descriptor_version.to_le_bytes());
+        for (desc, entry) in map
+            .iter()
+            .zip(payload[8..].chunks_exact_mut(descriptor_size))
+        {
+            // Firmware descriptors may be larger than ours; the extra bytes stay zero.
+            unsafe { core::ptr::write_unaligned(entry.as_mut_ptr().cast(), desc) };
+        }
+        Ok(())
+    }
+
+    /// Adds a framebuffer tag describing a direct color framebuffer
+    ///
+    /// Fails with `UNSUPPORTED` for [`PixelFormat::BLT_ONLY`].
+    pub fn framebuffer(&mut self, fb: &Framebuffer) -> Result<()> {
+        // Field positions and sizes of red, green and blue.
+        let (bpp, colors) = match fb.format {
+            PixelFormat::RGBA8 => (32, [0, 8, 8, 8, 16, 8]),
+            PixelFormat::BGRA8 => (32, [16, 8, 8, 8, 0, 8]),
+            PixelFormat::BITMASK => {
+                let field = |mask: u32| [mask.trailing_zeros() as u8, mask.count_ones() as u8];
+                let [red, green, blue] = [
+                    field(fb.bitmask.red),
+                    field(fb.bitmask.green),
+                    field(fb.bitmask.blue),
+                ];
+                let colors = [red[0], red[1], green[0], green[1], blue[0], blue[1]];
+                (fb.bitmask.bits_per_pixel() as u8, colors)
+            }
+            _ => return Err(Status::UNSUPPORTED),
+        };
+        let pitch = fb.stride * usize::from(bpp).div_ceil(8);
+        let payload = self.tag(TAG_FRAMEBUFFER, 24 + colors.len())?;
+        payload[0..8].copy_from_slice(&fb.addr.to_le_bytes());
+        payload[8..12].copy_from_slice(&(pitch as u32).to_le_bytes());
+        payload[12..16].copy_from_slice(&(fb.width as u32).to_le_bytes());
+        payload[16..20].copy_from_slice(&(fb.height as u32).to_le_bytes());
+        payload[20] = bpp;
+        payload[21] = FRAMEBUFFER_TYPE_RGB;
+        payload[24..].copy_from_slice(&colors);
+        Ok(())
+    }
+
+    /// Adds a copy of the ACPI RSDP from the configuration table, preferring ACPI 2.0
+    ///
+    /// An RSDP which isn't a valid ACPI 2.0 XSDP is copied as an ACPI 1.0 RSDP. Fails with
+    /// `NOT_FOUND` if the firmware provides no ACPI tables.
+    pub fn acpi(&mut self) -> Result<()> {
+        let config = system_table().config_table();
+        let rsdp = match config.get_table(TableGuid::ACPI_20) {
+            Some(rsdp) => rsdp.cast::<u8>(),
+            None => config
+                .get_table(TableGuid::ACPI)
+                .ok_or(Status::NOT_FOUND)?
+                .cast::<u8>(),
+        };
+        // From revision 2 on, the RSDP is an XSDP which extends the 20-byte ACPI 1.0 RSDP and
+        // records its own length.
+        let revision = unsafe { rsdp.add(15).read() };
+        let xsdp_len = match revision {
+            2.. => unsafe { rsdp.add(20).cast::<u32>().read_unaligned() as usize },
+            _ => 0,
+        };
+        let (kind, len) = match xsdp_len {
+            36.. => (TAG_ACPI_NEW, xsdp_len),
+            _ => (TAG_ACPI_OLD, 20),
+        };
+        let rsdp = unsafe { core::slice::from_raw_parts(rsdp, len) };
+        self.tag(kind, len)?.copy_from_slice(rsdp);
+        Ok(())
+    }
+
+    /// Adds the system table and image handle, for kernels entered with boot services
+    /// still active
+    ///
+    /// Also adds the tag which tells the kernel that boot services have not been exited, so
+    /// call [`exited_boot_services()`](Self::exited_boot_services) instead of this if they
+    /// will be by the time the kernel runs.
+    pub fn efi(&mut self) -> Result<()> {
+        let start = self.len;
+        let result = self
+            .efi_pointers()
+            .and_then(|()| self.tag(TAG_EFI_BS, 0).map(drop));
+        if result.is_err() {
+            self.len = start;
+        }
+        result
+    }
+
+    /// Adds the system table pointer, for kernels entered after `ExitBootServices()`
+    pub fn exited_boot_services(&mut self) -> Result<()> {
+        let start = self.len;
+        let system_table = system_table() as *const _ as u64;
+        let result = match size_of::<usize>() {
+            8 => self.u64_tag(TAG_EFI64, system_table),
+            _ => self.u32_tag(TAG_EFI32, system_table as u32),
+        };
+        if result.is_err() {
+            self.len = start;
+        }
+        result
+    }
+
+    fn efi_pointers(&mut self) -> Result<()> {
+        self.exited_boot_services()?;
+        let image = image_handle().handle().as_ptr() as u64;
+        match size_of::<usize>() {
+            8 => self.u64_tag(TAG_EFI64_IH, image),
+            _ => self.u32_tag(TAG_EFI32_IH, image as u32),
+        }
+    }
+
+    /// Adds the end tag and fills in the total size, returning it
+    pub fn finish(mut self) -> Result<usize> {
+        self.tag(TAG_END, 0)?;
+        let total_size = self.len as u32;
+        self.buf[..4].copy_from_slice(&total_size.to_le_bytes());
+        Ok(self.len)
+    }
+
+    fn string_tag(&mut self, kind: u32, string: &str) -> Result<()> {
+        let payload = self.tag(kind, string.len() + 1)?;
+        payload[..string.len()].copy_from_slice(string.as_bytes());
+        Ok(())
+    }
+
+    fn u32_tag(&mut self, kind: u32, value: u32) -> Result<()> {
+        self.tag(kind, 4)?.copy_from_slice(&value.to_le_bytes());
+        Ok(())
+    }
+
+    fn u64_tag(&mut self, kind: u32, value: u64) -> Result<()> {
+        self.tag(kind, 8)?.copy_from_slice(&value.to_le_bytes());
+        Ok(())
+    }
+
+    /// Appends a tag header and returns its zeroed payload
+    fn tag(&mut self, kind: u32, payload_len: usize) -> Result<&mut [u8]> {
+        let start = self.len;
+        let size = 8 + payload_len;
+        let end = start + size;
+        let tag = self
+            .buf
+            .get_mut(start..end.next_multiple_of(TAG_ALIGN))
+            .ok_or(Status::BUFFER_TOO_SMALL)?;
+        tag.fill(0);
+        tag[0..4].copy_from_slice(&kind.to_le_bytes());
+        tag[4..8].copy_from_slice(&(size as u32).to_le_bytes());
+        self.len = end.next_multiple_of(TAG_ALIGN);
+        Ok(&mut self.buf[start + 8..end])
+    }
+}
+
+/// A kernel image's Multiboot2 header
+#[derive(Clone, Copy, Debug)]
+pub struct Header<'a> {
+    /// Offset of the header in the image
+    pub offset:       usize,
+    /// 0 for 32-bit protected mode i386, 4 for 32-bit MIPS
+    pub architecture: u32,
+    tags:             &'a [u8],
+}
+
+impl<'a> Header<'a> {
+    /// Searches the first 32 KiB of `image` for a header with a valid checksum
+    pub fn find(image: &'a [u8]) -> Option<Self> {
+        let search = &image[..image.len().min(HEADER_SEARCH_LEN)];
+        (0..=search.len().saturating_sub(16))
+            .step_by(TAG_ALIGN)
+            .find_map(|offset| Self::parse(image, offset))
+    }
+
+    fn parse(image: &'a [u8], offset: usize) -> Option<Self> {
+        let field = |index: usize| {
+            let bytes = image.get(offset + 4 * index..offset + 4 * index + 4)?;
+            Some(u32::from_le_bytes(bytes.try_into().unwrap()))
+        };
+        if field(0)? != HEADER_MAGIC {
+            return None;
+        }
+        let (architecture, length, checksum) = (field(1)?, field(2)?, field(3)?);
+        let sum = HEADER_MAGIC
+            .wrapping_add(architecture)
+            .wrapping_add(length)
+            .wrapping_add(checksum);
+        if sum != 0 || (length as usize) < 16 {
+            return None;
+        }
+        let header = image.get(offset..offset + length as usize)?;
+        Some(Self {
+            offset,
+            architecture,
+            tags: &header[16..],
+        })
+    }
+
+    /// Returns an iterator over the header's tags, as `(type, flags, data)`
+    ///
+    /// Tags with the optional flag (bit 0) set may be ignored; others must be honored or the
+    /// kernel refused.
+    pub fn tags(&self) -> impl Iterator<Item = (u16, u16, &'a [u8])> {
+        let mut rest = self.tags;
+        core::iter::from_fn(move || {
+            let header = rest.get(..8)?;
+            let kind = u16::from_le_bytes([header[0], header[1]]);
+            let flags = u16::from_le_bytes([header[2], header[3]]);
+            let size = u32::from_le_bytes(header[4..8].try_into().unwrap()) as usize;
+            if kind == 0 || size < 8 {
+                return None;
+            }
+            let data = rest.get(8..size)?;
+            rest = rest
+                .get(size.next_multiple_of(TAG_ALIGN)..)
+                .unwrap_or_default();
+            Some((kind, flags, data))
+        })
+    }
+
+    /// Returns the type numbers of the information the kernel requests, from the information
+    /// request tag (type 1)
+    pub fn requested(&self) -> impl Iterator<Item = u32> + 'a {
+        let request = self.tags().find(|&(kind, ..)| kind == 1);
+        request
+            .map(|(_, _, data)| data)
+            .unwrap_or_default()
+            .chunks_exact(4)
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    /// Returns the 64-bit EFI entry point (tag 9), for kernels entered with boot services
+    /// active
+    pub fn efi64_entry(&self) -> Option<u32> {
+        let (_, _, data) = self.tags().find(|&(kind, ..)| kind == 9)?;
+        Some(u32::from_le_bytes(data.get(..4)?.try_into().unwrap()))
+    }
+}
@@ -0,0 +1,185 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Sending output to several devices at once
+//!
+//! [`MultiWriter`] fans [`fmt::Write`] output out to any number of sinks, e.g. the locked
+//! [console](crate::stdio), a [serial port](crate::proto::console::serial::SerialIo), the
+//! [graphics console](crate::graphics::console::GfxConsole) and a [`RingBuffer`]:
+//!
+//! ```ignore
+//! let mut log = RingBuffer::<4096>::new();
+//! let mut stdout = uefi::stdout();
+//! let mut out = MultiWriter::<4>::new();
+//! out.add(&mut stdout).ok();
+//! out.add(&mut *serial).ok();
+//! out.add(&mut log).ok();
+//! writeln!(out, "loading kernel")?;
+//! ```
+
+use core::fmt;
+
+/// Writes everything to each of up to `N` sinks
+///
+/// A sink which fails doesn't stop the others from receiving the output; the write as a
+/// whole fails if any sink did.
+pub struct MultiWriter<'a, const N: usize> {
+    sinks: [Option<&'a mut dyn fmt::Write>; N],
+}
+
+impl<'a, const N: usize> MultiWriter<'a, N> {
+    pub const fn new() -> Self {
+        Self {
+            sinks: [const { None }; N],
+        }
+    }
+
+    /// Adds a sink, or returns it again if all `N` slots are in use
+    pub fn add(&mut self, sink: &'a mut dyn fmt::Write) -> Result<(), &'a mut dyn fmt::Write> {
+        match self.sinks.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => {
+                *slot = Some(sink);
+                Ok(())
+            }
+            None => Err(sink),
+        }
+    }
+
+    /// Removes all sinks
+    pub fn clear(&mut self) {
+        self.sinks = [const { None }; N];
+    }
+
+    pub fn len(&self) -> usize {
+        self.sinks.iter().filter(|slot| slot.is_some()).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<const N: usize> Default for MultiWriter<'_, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> fmt::Write for MultiWriter<'_, N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut result = Ok(());
+        for sink in self.sinks.iter_mut().flatten() {
+            if sink.write_str(s).is_err() {
+                result = Err(fmt::Error);
+            }
+        }
+        result
+    }
+}
+
+/// Fixed-size buffer keeping the last `N` bytes written to it
+///
+/// Writes never fail; once the buffer is full, the oldest output is overwritten.
+pub struct RingBuffer<const N: usize> {
+    buf:   [u8; N],
+    /// Index the next byte is written to
+    head:  usize,
+    len:   usize,
+    /// Total number of bytes ever written
+    total: u64,
+}
+
+impl<const N: usize> RingBuffer<N> {
+    pub const fn new() -> Self {
+        Self {
+            buf:   [0; N],
+            head:  0,
+            len:   0,
+            total: 0,
+        }
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.total += bytes.len() as u64;
+        if N == 0 {
+            return;
+        }
+        // Only the last `N` bytes can survive anyway.
+        let bytes = &bytes[bytes.len().saturating_sub(N)..];
+        let first = bytes.len().min(N - self.head);
+        self.buf[self.head..self.head + first].copy_from_slice(&bytes[..first]);
+        self.buf[..bytes.len() - first].copy_from_slice(&bytes[first..]);
+        self.head = (self.head + bytes.len()) % N;
+        self.len = (self.len + bytes.len()).min(N);
+    }
+
+    /// Returns the contents, oldest first, as two slices
+    pub fn as_slices(&self) -> (&[u8], &[u8]) {
+        let start = (self.head + N - self.len) % N.max(1);
+        if start + self.len <= N {
+            (&self.buf[start..start + self.len], &[])
+        } else {
+            (&self.buf[start..], &self.buf[..self.head])
+        }
+    }
+
+    /// Returns the number of bytes held
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns `true` if output has been overwritten
+    pub fn has_wrapped(&self) -> bool {
+        self.total > self.len as u64
+    }
+
+    pub fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+        self.total = 0;
+    }
+}
+
+impl<const N: usize> Default for RingBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> fmt::Write for RingBuffer<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_bytes(s.as_bytes());
+        Ok(())
+    }
+}
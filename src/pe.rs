@@ -0,0 +1,348 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! PE/COFF header inspection
+//!
+//! `LoadImage()` rejects anything it can't run with a bare `LOAD_ERROR` or `UNSUPPORTED`.
+//! [`PeImage`] reads the headers of an image buffer so a loader can tell the user *why* a file
+//! won't boot (wrong architecture, not an application, truncated) before handing it over.
+
+use core::{fmt, str};
+
+use crate::{le, Result, Status};
+
+/// Target machine of an image, from the COFF file header
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Machine(pub u16);
+
+impl Machine {
+    pub const I386: Self = Self(0x014c);
+    pub const ARM_THUMB: Self = Self(0x01c2);
+    pub const IA64: Self = Self(0x0200);
+    pub const EBC: Self = Self(0x0ebc);
+    pub const RISCV32: Self = Self(0x5032);
+    pub const RISCV64: Self = Self(0x5064);
+    pub const LOONGARCH64: Self = Self(0x6264);
+    pub const X64: Self = Self(0x8664);
+    pub const AARCH64: Self = Self(0xaa64);
+
+    /// The machine type of images the running firmware executes natively
+    pub const NATIVE: Self = if cfg!(target_arch = "x86_64") {
+        Self::X64
+    } else if cfg!(target_arch = "x86") {
+        Self::I386
+    } else if cfg!(target_arch = "aarch64") {
+        Self::AARCH64
+    } else if cfg!(target_arch = "arm") {
+        Self::ARM_THUMB
+    } else if cfg!(target_arch = "riscv64") {
+        Self::RISCV64
+    } else if cfg!(target_arch = "riscv32") {
+        Self::RISCV32
+    } else if cfg!(target_arch = "loongarch64") {
+        Self::LOONGARCH64
+    } else {
+        Self(0)
+    };
+}
+
+impl fmt::Display for Machine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match *self {
+            Self::I386 => "IA32",
+            Self::ARM_THUMB => "ARM",
+            Self::IA64 => "IA64",
+            Self::EBC => "EBC",
+            Self::RISCV32 => "RISCV32",
+            Self::RISCV64 => "RISCV64",
+            Self::LOONGARCH64 => "LOONGARCH64",
+            Self::X64 => "X64",
+            Self::AARCH64 => "AARCH64",
+            Self(other) => return write!(f, "{other:#06x}"),
+        };
+        f.write_str(name)
+    }
+}
+
+/// Subsystem of an image, from the optional header
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Subsystem(pub u16);
+
+impl Subsystem {
+    pub const EFI_APPLICATION: Self = Self(10);
+    pub const EFI_BOOT_SERVICE_DRIVER: Self = Self(11);
+    pub const EFI_RUNTIME_DRIVER: Self = Self(12);
+    pub const EFI_ROM: Self = Self(13);
+}
+
+const DOS_MAGIC: &[u8; 2] = b"MZ";
+const PE_MAGIC: &[u8; 4] = b"PE\0\0";
+const PE32_MAGIC: u16 = 0x10b;
+const PE32_PLUS_MAGIC: u16 = 0x20b;
+const COFF_HEADER_SIZE: usize = 20;
+const SECTION_HEADER_SIZE: usize = 40;
+const DATA_DIRECTORY_DEBUG: usize = 6;
+const DEBUG_ENTRY_SIZE: usize = 28;
+const DEBUG_TYPE_CODEVIEW: u32 = 2;
+
+/// The parsed headers of a PE/COFF image
+#[derive(Clone, Copy, Debug)]
+pub struct PeImage<'a> {
+    data:              &'a [u8],
+    pub machine:       Machine,
+    pub subsystem:     Subsystem,
+    /// Whether the optional header is PE32+ (64-bit) rather than PE32
+    pub pe32_plus:     bool,
+    /// Relative virtual address of the entry point
+    pub entry_point:   u32,
+    pub image_base:    u64,
+    pub size_of_image: u32,
+    section_table:     usize,
+    num_sections:      usize,
+    data_directories:  usize,
+    num_directories:   usize,
+    /// Whether sections are at their virtual addresses rather than their file offsets
+    loaded:            bool,
+}
+
+impl<'a> PeImage<'a> {
+    /// Parses the DOS, COFF and optional headers and bounds-checks the section table
+    ///
+    /// Fails with `LOAD_ERROR` if the buffer is not a well-formed PE/COFF image.
+    pub fn parse(data: &'a [u8]) -> Result<Self> {
+        if data.get(..2) != Some(DOS_MAGIC) {
+            return Err(Status::LOAD_ERROR);
+        }
+        let u16_at = |at| le::read_u16(data, at).ok_or(Status::LOAD_ERROR);
+        let u32_at = |at| le::read_u32(data, at).ok_or(Status::LOAD_ERROR);
+        let u64_at = |at| le::read_u64(data, at).ok_or(Status::LOAD_ERROR);
+        let pe = u32_at(0x3c)? as usize;
+        if data.get(pe..pe + 4) != Some(PE_MAGIC) {
+            return Err(Status::LOAD_ERROR);
+        }
+        let coff = pe + 4;
+        let machine = Machine(u16_at(coff)?);
+        let num_sections = u16_at(coff + 2)? as usize;
+        let optional_size = u16_at(coff + 16)? as usize;
+
+        let optional = coff + COFF_HEADER_SIZE;
+        let pe32_plus = match u16_at(optional)? {
+            PE32_MAGIC => false,
+            PE32_PLUS_MAGIC => true,
+            _ => return Err(Status::LOAD_ERROR),
+        };
+        let entry_point = u32_at(optional + 16)?;
+        let image_base = match pe32_plus {
+            true => u64_at(optional + 24)?,
+            false => u32_at(optional + 28)? as u64,
+        };
+        let size_of_image = u32_at(optional + 56)?;
+        let subsystem = Subsystem(u16_at(optional + 68)?);
+        let (num_directories, data_directories) = match pe32_plus {
+            true => (u32_at(optional + 108)?, optional + 112),
+            false => (u32_at(optional + 92)?, optional + 96),
+        };
+
+        let section_table = optional + optional_size;
+        let table_end = section_table + num_sections * SECTION_HEADER_SIZE;
+        if table_end > data.len() {
+            return Err(Status::LOAD_ERROR);
+        }
+
+        Ok(Self {
+            data,
+            machine,
+            subsystem,
+            pe32_plus,
+            entry_point,
+            image_base,
+            size_of_image,
+            section_table,
+            num_sections,
+            data_directories,
+            num_directories: num_directories as usize,
+            loaded: false,
+        })
+    }
+
+    /// Parses an image which has been loaded into memory, such as the running image
+    ///
+    /// `data` covers the whole image, with each section at its virtual address.
+    pub fn parse_loaded(data: &'a [u8]) -> Result<Self> {
+        Ok(Self {
+            loaded: true,
+            ..Self::parse(data)?
+        })
+    }
+
+    /// Checks that the image is a UEFI application the firmware can run natively
+    ///
+    /// Fails with `UNSUPPORTED` if the machine type or subsystem don't match.
+    pub fn check_efi_application(&self) -> Result<()> {
+        if self.machine != Machine::NATIVE || self.subsystem != Subsystem::EFI_APPLICATION {
+            return Err(Status::UNSUPPORTED);
+        }
+        Ok(())
+    }
+
+    /// Returns an iterator over the section table
+    pub fn sections(&self) -> impl Iterator<Item = Section<'a>> + 'a {
+        let table = &self.data[self.section_table..];
+        table
+            .chunks_exact(SECTION_HEADER_SIZE)
+            .take(self.num_sections)
+            .map(|header| Section { header })
+    }
+
+    /// Returns the section with the given name, e.g. `.sbat`
+    pub fn section(&self, name: &str) -> Option<Section<'a>> {
+        self.sections().find(|s| s.name() == name.as_bytes())
+    }
+
+    /// Returns the relative virtual address and size of data directory `index`
+    pub fn data_directory(&self, index: usize) -> Option<(u32, u32)> {
+        if index >= self.num_directories {
+            return None;
+        }
+        let entry = self.data_directories + 8 * index;
+        Some((
+            le::read_u32(self.data, entry)?,
+            le::read_u32(self.data, entry + 4)?,
+        ))
+    }
+
+    /// Returns `len` bytes at relative virtual address `rva`
+    pub fn rva_data(&self, rva: u32, len: u32) -> Option<&'a [u8]> {
+        let offset = match self.loaded {
+            true => rva,
+            false => {
+                let section = self.sections().find(|s| {
+                    (s.virtual_address()..s.virtual_address().saturating_add(s.raw_size()))
+                        .contains(&rva)
+                })?;
+                (rva - section.virtual_address()).checked_add(section.raw_offset())?
+            }
+        };
+        let offset = offset as usize;
+        self.data.get(offset..offset.checked_add(len as usize)?)
+    }
+
+    /// Returns the path of the debug symbols recorded by the linker, if any
+    ///
+    /// This is the PDB path from the image's CodeView debug entry, which debuggers use to find
+    /// the symbols for an image they see in memory.
+    pub fn pdb_path(&self) -> Option<&'a str> {
+        let (rva, size) = self.data_directory(DATA_DIRECTORY_DEBUG)?;
+        let directory = self.rva_data(rva, size)?;
+        let entry = directory
+            .chunks_exact(DEBUG_ENTRY_SIZE)
+            .find(|entry| le::read_u32(entry, 12) == Some(DEBUG_TYPE_CODEVIEW))?;
+        let size = le::read_u32(entry, 16)?;
+        let codeview = match self.loaded {
+            true => self.rva_data(le::read_u32(entry, 20)?, size)?,
+            false => {
+                let offset = le::read_u32(entry, 24)? as usize;
+                self.data.get(offset..offset.checked_add(size as usize)?)?
+            }
+        };
+        let path = match codeview.get(..4)? {
+            b"RSDS" => codeview.get(24..)?,
+            b"NB10" => codeview.get(16..)?,
+            _ => return None,
+        };
+        let len = path.iter().position(|&b| b == 0)?;
+        str::from_utf8(&path[..len]).ok()
+    }
+}
+
+/// An entry of the section table
+#[derive(Clone, Copy)]
+pub struct Section<'a> {
+    header: &'a [u8],
+}
+
+impl<'a> Section<'a> {
+    /// Returns the name, without NUL padding
+    ///
+    /// Names longer than 8 bytes are stored in the COFF string table, which images don't
+    /// normally have; those are returned as-is (`/4`).
+    pub fn name(&self) -> &'a [u8] {
+        let name = &self.header[..8];
+        let len = name.iter().position(|&b| b == 0).unwrap_or(8);
+        &name[..len]
+    }
+
+    pub fn virtual_size(&self) -> u32 {
+        self.field(8)
+    }
+
+    pub fn virtual_address(&self) -> u32 {
+        self.field(12)
+    }
+
+    pub fn raw_size(&self) -> u32 {
+        self.field(16)
+    }
+
+    /// Returns the file offset of the section's data
+    pub fn raw_offset(&self) -> u32 {
+        self.field(20)
+    }
+
+    pub fn characteristics(&self) -> u32 {
+        self.field(36)
+    }
+
+    /// Returns the section's data within `image`, or `None` if it lies outside the buffer
+    pub fn data(&self, image: &PeImage<'a>) -> Option<&'a [u8]> {
+        let start = self.raw_offset() as usize;
+        image.data.get(start..start + self.raw_size() as usize)
+    }
+
+    fn field(&self, offset: usize) -> u32 {
+        let bytes = &self.header[offset..offset + 4];
+        u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+    }
+}
+
+impl fmt::Debug for Section<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Section")
+            .field("name", &str::from_utf8(self.name()).unwrap_or("?"))
+            .field("virtual_address", &self.virtual_address())
+            .field("virtual_size", &self.virtual_size())
+            .field("raw_offset", &self.raw_offset())
+            .field("raw_size", &self.raw_size())
+            .field("characteristics", &self.characteristics())
+            .finish()
+    }
+}
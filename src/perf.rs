@@ -0,0 +1,251 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Boot phase profiling
+//!
+//! Named spans are recorded with [`Instant`] timestamps into a fixed ring buffer, so that
+//! profiling works without an allocator and after boot services have been exited (given an
+//! architectural counter). The oldest spans are overwritten once the buffer is full.
+//!
+//! ```ignore
+//! let _span = perf::span("load kernel");
+//! // ...
+//! drop(_span);
+//! perf::report(&mut ConsoleWriter::new(stdout))?;
+//! ```
+
+use core::fmt;
+
+use crate::{sync::TryLock, time::Instant, Guid, Result, Status};
+
+/// Number of spans kept in the ring buffer
+pub const CAPACITY: usize = 64;
+
+/// A recorded span
+#[derive(Clone, Copy, Debug)]
+pub struct Span {
+    pub name:  &'static str,
+    pub start: Instant,
+    /// `None` while the span is still running
+    pub end:   Option<Instant>,
+    id:        u64,
+}
+
+impl Span {
+    /// Returns the span's duration, or the time elapsed so far if it is still running
+    pub fn duration(&self) -> core::time::Duration {
+        self.end
+            .unwrap_or_else(Instant::now)
+            .duration_since(self.start)
+    }
+}
+
+const EMPTY: Span = Span {
+    name:  "",
+    start: Instant::from_ticks(0),
+    end:   None,
+    id:    0,
+};
+
+struct Ring {
+    spans:   [Option<Span>; CAPACITY],
+    next:    usize,
+    next_id: u64,
+}
+
+static RING: TryLock<Ring> = TryLock::new(Ring {
+    spans:   [None; CAPACITY],
+    next:    0,
+    next_id: 1,
+});
+
+/// Identifies a running span for [`stop()`]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SpanId(u64);
+
+/// Starts a span
+///
+/// If the buffer is in use by interrupted code, the span is not recorded and stopping it does
+/// nothing.
+pub fn start(name: &'static str) -> SpanId {
+    let start = Instant::now();
+    RING.try_with(|ring| {
+        let id = ring.next_id;
+        ring.next_id += 1;
+        ring.spans[ring.next] = Some(Span {
+            name,
+            start,
+            end: None,
+            id,
+        });
+        ring.next = (ring.next + 1) % CAPACITY;
+        SpanId(id)
+    })
+    .unwrap_or(SpanId(0))
+}
+
+/// Stops a span started by [`start()`]
+///
+/// Does nothing if the span has already been overwritten.
+pub fn stop(id: SpanId) {
+    let end = Instant::now();
+    RING.try_with(|ring| {
+        let span = ring.spans.iter_mut().flatten().find(|span| span.id == id.0);
+        if let Some(span) = span {
+            span.end.get_or_insert(end);
+        }
+    });
+}
+
+/// Records a span which has already finished
+pub fn record(name: &'static str, start: Instant, end: Instant) {
+    let id = self::start(name);
+    RING.try_with(|ring| {
+        let span = ring.spans.iter_mut().flatten().find(|span| span.id == id.0);
+        if let Some(span) = span {
+            span.start = start;
+            span.end = Some(end);
+        }
+    });
+}
+
+/// Starts a span which is stopped when the returned guard is dropped
+pub fn span(name: &'static str) -> SpanGuard {
+    SpanGuard(start(name))
+}
+
+pub struct SpanGuard(SpanId);
+
+impl SpanGuard {
+    pub fn id(&self) -> SpanId {
+        self.0
+    }
+}
+
+impl Drop for SpanGuard {
+    fn drop(&mut self) {
+        stop(self.0);
+    }
+}
+
+/// Copies the recorded spans, oldest first, into `buf` and returns how many were copied
+pub fn snapshot(buf: &mut [Span]) -> usize {
+    RING.try_with(|ring| {
+        let (newer, older) = ring.spans.split_at(ring.next);
+        let spans = older.iter().chain(newer).flatten();
+        buf.iter_mut()
+            .zip(spans)
+            .map(|(slot, span)| *slot = *span)
+            .count()
+    })
+    .unwrap_or(0)
+}
+
+/// Discards all recorded spans
+pub fn clear() {
+    RING.try_with(|ring| ring.spans = [None; CAPACITY]);
+}
+
+/// Writes a table of the recorded spans
+///
+/// Start times are relative to the earliest span; spans which are still running are marked
+/// with `+`.
+pub fn report(out: &mut impl fmt::Write) -> fmt::Result {
+    let mut spans = [EMPTY; CAPACITY];
+    let count = snapshot(&mut spans);
+    let spans = &spans[..count];
+    let Some(origin) = spans.iter().map(|span| span.start).min() else {
+        return writeln!(out, "perf: no spans recorded");
+    };
+
+    writeln!(
+        out,
+        "{:<32} {:>12} {:>14}",
+        "span", "start (ms)", "duration (us)"
+    )?;
+    for span in spans {
+        let start = span.start.duration_since(origin);
+        let running = if span.end.is_none() { "+" } else { "" };
+        writeln!(
+            out,
+            "{:<32} {:>8}.{:03} {:>13}{running:1}",
+            span.name,
+            start.as_millis(),
+            start.subsec_micros() % 1000,
+            span.duration().as_micros(),
+        )?;
+    }
+    Ok(())
+}
+
+/// `FPDT_DYNAMIC_STRING_EVENT_RECORD`, as used by EDK2's performance infrastructure
+const FPDT_DYNAMIC_STRING_EVENT: u16 = 0x1011;
+const FPDT_RECORD_REVISION: u8 = 1;
+const FPDT_RECORD_HEADER_SIZE: usize = 34;
+const FPDT_NAME_LENGTH: usize = 24;
+/// `PERF_INMODULE_START_ID` and `PERF_INMODULE_END_ID`
+const PERF_START_ID: u16 = 0x40;
+const PERF_END_ID: u16 = 0x41;
+
+/// Serializes the finished spans as FPDT dynamic string event records
+///
+/// Each span becomes a start and an end record tagged with `module`, with timestamps in
+/// nanoseconds and names truncated to 23 bytes. Returns the number of bytes written, or fails
+/// with `BUFFER_TOO_SMALL` if `buf` can't hold every record.
+pub fn write_fpdt(module: &Guid, buf: &mut [u8]) -> Result<usize> {
+    let mut spans = [EMPTY; CAPACITY];
+    let count = snapshot(&mut spans);
+    let origin = Instant::from_ticks(0);
+
+    let mut offset = 0;
+    for span in &spans[..count] {
+        let Some(end) = span.end else { continue };
+        for (progress_id, at) in [(PERF_START_ID, span.start), (PERF_END_ID, end)] {
+            let name = &span.name.as_bytes()[..span.name.len().min(FPDT_NAME_LENGTH - 1)];
+            let len = FPDT_RECORD_HEADER_SIZE + FPDT_NAME_LENGTH;
+            let record = buf
+                .get_mut(offset..offset + len)
+                .ok_or(Status::BUFFER_TOO_SMALL)?;
+            let timestamp = at.duration_since(origin).as_nanos() as u64;
+
+            record.fill(0);
+            record[0..2].copy_from_slice(&FPDT_DYNAMIC_STRING_EVENT.to_le_bytes());
+            record[2] = len as u8;
+            record[3] = FPDT_RECORD_REVISION;
+            record[4..6].copy_from_slice(&progress_id.to_le_bytes());
+            // ApicID (4 bytes) is left zero.
+            record[10..18].copy_from_slice(&timestamp.to_le_bytes());
+            record[18..34].copy_from_slice(&module.to_bytes());
+            record[34..34 + name.len()].copy_from_slice(name);
+            offset += len;
+        }
+    }
+    Ok(offset)
+}
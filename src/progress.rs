@@ -0,0 +1,131 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Progress reporting for long-running operations
+//!
+//! Helpers that may take a while (reading a kernel from a slow disk, for instance) accept an
+//! `impl Progress` so that front-ends can draw a progress bar. Pass [`NoProgress`] to opt out,
+//! or a [`ConsoleSpinner`] for a simple one-line indicator.
+
+use core::fmt::Write;
+
+use crate::{
+    proto::console::text_output::{ConsoleWriter, SimpleTextOutput},
+    Result,
+};
+
+pub trait Progress {
+    /// Reports that `done` out of `total` bytes (or other units) have been processed
+    ///
+    /// `total` is zero if it is not known in advance.
+    fn update(&mut self, done: u64, total: u64);
+
+    /// Reports that the operation has completed
+    fn finish(&mut self, result: Result<()>) {
+        let _ = result;
+    }
+}
+
+impl<P: Progress + ?Sized> Progress for &mut P {
+    fn update(&mut self, done: u64, total: u64) {
+        (**self).update(done, total);
+    }
+
+    fn finish(&mut self, result: Result<()>) {
+        (**self).finish(result);
+    }
+}
+
+/// Ignores all progress updates
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoProgress;
+
+impl Progress for NoProgress {
+    fn update(&mut self, _done: u64, _total: u64) {}
+}
+
+/// Adapts a closure taking `(done, total)`
+pub struct ProgressFn<F>(pub F);
+
+impl<F: FnMut(u64, u64)> Progress for ProgressFn<F> {
+    fn update(&mut self, done: u64, total: u64) {
+        (self.0)(done, total);
+    }
+}
+
+/// Draws `label [/] 42%` on the current line of a console, redrawing in place
+pub struct ConsoleSpinner<'a> {
+    out:          &'a mut SimpleTextOutput,
+    label:        &'a str,
+    frame:        usize,
+    last_percent: Option<u64>,
+}
+
+impl<'a> ConsoleSpinner<'a> {
+    const FRAMES: [char; 4] = ['|', '/', '-', '\\'];
+
+    pub fn new(out: &'a mut SimpleTextOutput, label: &'a str) -> Self {
+        Self {
+            out,
+            label,
+            frame: 0,
+            last_percent: None,
+        }
+    }
+
+    fn draw(&mut self, args: core::fmt::Arguments) {
+        let _ = write!(ConsoleWriter::new(self.out), "\r{} {}", self.label, args);
+    }
+}
+
+impl Progress for ConsoleSpinner<'_> {
+    fn update(&mut self, done: u64, total: u64) {
+        let frame = Self::FRAMES[self.frame % Self::FRAMES.len()];
+        self.frame += 1;
+        if total == 0 {
+            self.draw(format_args!("[{frame}] {} KiB", done / 1024));
+            return;
+        }
+        let percent = done.min(total) * 100 / total;
+        // Console output is slow; skip redraws that would only move the spinner.
+        if self.last_percent == Some(percent) && !self.frame.is_multiple_of(8) {
+            return;
+        }
+        self.last_percent = Some(percent);
+        self.draw(format_args!("[{frame}] {percent:3}%"));
+    }
+
+    fn finish(&mut self, result: Result<()>) {
+        match result {
+            Ok(()) => self.draw(format_args!("done      \n")),
+            Err(status) => self.draw(format_args!("failed: {status:?}\n")),
+        }
+    }
+}
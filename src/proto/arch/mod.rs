@@ -0,0 +1,93 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Processor discovery across architectures
+//!
+//! [`boot_cpu_id()`] returns the hardware ID of the processor the firmware booted on, which is
+//! what a kernel expects to be told: the hart ID on RISC-V, the APIC ID on x86, and the
+//! affinity fields of `MPIDR` on Arm.
+
+pub mod mp_services;
+pub mod riscv;
+
+use crate::Result;
+
+/// Returns the hardware ID of the boot processor
+///
+/// On RISC-V this uses the [RISC-V Boot Protocol](riscv::RiscvBoot). Elsewhere the [MP
+/// Services Protocol](mp_services::MpServices) is asked for the calling processor's ID, since
+/// the loader runs on the boot processor; without it, the ID is read from the processor itself.
+pub fn boot_cpu_id() -> Result<u64> {
+    #[cfg(target_arch = "riscv64")]
+    {
+        riscv::boot_hartid().map(|id| id as u64)
+    }
+    #[cfg(not(target_arch = "riscv64"))]
+    {
+        mp_services_cpu_id().or_else(|_| Ok(current_cpu_id()))
+    }
+}
+
+#[cfg(not(target_arch = "riscv64"))]
+fn mp_services_cpu_id() -> Result<u64> {
+    let mp = crate::boot_services().first_protocol::<mp_services::MpServices>()?;
+    Ok(mp.processor_info(mp.who_am_i()?)?.processor_id)
+}
+
+/// Reads the initial APIC ID
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn current_cpu_id() -> u64 {
+    #[cfg(target_arch = "x86")]
+    use core::arch::x86::__cpuid;
+    #[cfg(target_arch = "x86_64")]
+    use core::arch::x86_64::__cpuid;
+
+    u64::from(__cpuid(1).ebx >> 24)
+}
+
+/// Reads the affinity fields of `MPIDR_EL1`
+#[cfg(target_arch = "aarch64")]
+fn current_cpu_id() -> u64 {
+    let mpidr: u64;
+    unsafe {
+        core::arch::asm!("mrs {}, mpidr_el1", out(reg) mpidr, options(nomem, nostack, preserves_flags))
+    };
+    mpidr & 0xff_00ff_ffff
+}
+
+/// Reads the affinity fields of `MPIDR`
+#[cfg(target_arch = "arm")]
+fn current_cpu_id() -> u64 {
+    let mpidr: u32;
+    unsafe {
+        core::arch::asm!("mrc p15, 0, {}, c0, c0, 5", out(reg) mpidr, options(nomem, nostack, preserves_flags))
+    };
+    u64::from(mpidr & 0xff_ffff)
+}
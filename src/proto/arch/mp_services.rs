@@ -0,0 +1,163 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! MP Services Protocol
+//!
+//! Defined by the Platform Initialization specification, and provided by most x86 and Arm
+//! firmware to enumerate and control the application processors.
+
+use core::ffi::c_void;
+
+use crate::{
+    guid,
+    proto::{Proto, Protocol},
+    Event, Guid, Result, Status,
+};
+
+pub type ApProcedure = extern "efiapi" fn(argument: *mut c_void);
+
+pub type GetNumberOfProcessorsFn = extern "efiapi" fn(
+    this: *mut MpServices,
+    number_of_processors: *mut usize,
+    number_of_enabled_processors: *mut usize,
+) -> Status;
+
+pub type GetProcessorInfoFn = extern "efiapi" fn(
+    this: *mut MpServices,
+    processor_number: usize,
+    processor_info: *mut ProcessorInformation,
+) -> Status;
+
+pub type StartupAllApsFn = extern "efiapi" fn(
+    this: *mut MpServices,
+    procedure: ApProcedure,
+    single_thread: bool,
+    wait_event: Event,
+    timeout_us: usize,
+    argument: *mut c_void,
+    failed_cpu_list: *mut *mut usize,
+) -> Status;
+
+pub type StartupThisApFn = extern "efiapi" fn(
+    this: *mut MpServices,
+    procedure: ApProcedure,
+    processor_number: usize,
+    wait_event: Event,
+    timeout_us: usize,
+    argument: *mut c_void,
+    finished: *mut bool,
+) -> Status;
+
+pub type SwitchBspFn = extern "efiapi" fn(
+    this: *mut MpServices,
+    processor_number: usize,
+    enable_old_bsp: bool,
+) -> Status;
+
+pub type EnableDisableApFn = extern "efiapi" fn(
+    this: *mut MpServices,
+    processor_number: usize,
+    enable_ap: bool,
+    health_flag: *mut u32,
+) -> Status;
+
+pub type WhoAmIFn =
+    extern "efiapi" fn(this: *mut MpServices, processor_number: *mut usize) -> Status;
+
+#[repr(C)]
+pub struct MpServices {
+    get_number_of_processors: GetNumberOfProcessorsFn,
+    get_processor_info:       GetProcessorInfoFn,
+    startup_all_aps:          StartupAllApsFn,
+    startup_this_ap:          StartupThisApFn,
+    switch_bsp:               SwitchBspFn,
+    enable_disable_ap:        EnableDisableApFn,
+    who_am_i:                 WhoAmIFn,
+}
+
+impl Protocol for MpServices {
+    const GUID: Guid = guid!(
+        0x3fdda605,0xa76e,0x4f46,
+        {0xad,0x29,0x12,0xf4,0x53,0x1b,0x3d,0x08}
+    );
+}
+
+/// Where a processor sits in the system's topology
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CpuPhysicalLocation {
+    pub package: u32,
+    pub core:    u32,
+    pub thread:  u32,
+}
+
+bitflags::bitflags! {
+    #[repr(transparent)]
+    #[derive(Default)]
+    pub struct ProcessorStatus : u32 {
+        /// The processor is the bootstrap processor
+        const BSP     = 1 << 0;
+        const ENABLED = 1 << 1;
+        /// The processor passed its self-test
+        const HEALTHY = 1 << 2;
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ProcessorInformation {
+    /// The processor's hardware ID: the APIC ID on x86, or the affinity fields of `MPIDR` on
+    /// Arm
+    pub processor_id: u64,
+    pub status:       ProcessorStatus,
+    pub location:     CpuPhysicalLocation,
+}
+
+impl Proto<MpServices> {
+    /// Returns the number of processors, and how many of them are enabled
+    pub fn number_of_processors(&self) -> Result<(usize, usize)> {
+        let (mut total, mut enabled) = (0, 0);
+        (self.get_number_of_processors)(self.as_ptr(), &mut total, &mut enabled)
+            .to_result((total, enabled))
+    }
+
+    /// Returns information about processor `number`, in the range given by
+    /// [`number_of_processors()`](Self::number_of_processors)
+    pub fn processor_info(&self, number: usize) -> Result<ProcessorInformation> {
+        let mut info = ProcessorInformation::default();
+        (self.get_processor_info)(self.as_ptr(), number, &mut info).to_result(info)
+    }
+
+    /// Returns the number of the calling processor
+    pub fn who_am_i(&self) -> Result<usize> {
+        let mut number = 0;
+        (self.who_am_i)(self.as_ptr(), &mut number).to_result(number)
+    }
+}
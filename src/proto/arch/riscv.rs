@@ -0,0 +1,94 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+use crate::{
+    boot_services, guid,
+    proto::{Proto, Protocol},
+    Guid, Result, Status,
+};
+
+/// RISC-V Boot Protocol
+///
+/// Later revisions of the protocol may append functions after `get_boot_hartid`. They are to
+/// be declared here together with the revision introducing them, and only called after
+/// checking [`supports()`](Proto::supports), as an older implementation's structure ends
+/// before them.
+#[repr(C)]
+pub struct RiscvBoot {
+    pub revision:    u64,
+    get_boot_hartid: GetBootHartidFn,
+}
+
+impl Protocol for RiscvBoot {
+    const GUID: Guid = guid!(
+        0xccd15fec,0x6f73,0x4eec,
+        {0x83,0x95,0x3e,0x69,0xe4,0xb9,0x40,0xbf}
+    );
+}
+
+pub type GetBootHartidFn =
+    extern "efiapi" fn(this: *mut RiscvBoot, boot_hartid: *mut usize) -> Status;
+
+impl RiscvBoot {
+    /// First revision of the protocol, which introduced `GetBootHartId`
+    pub const REVISION_1_0: u64 = Self::revision(1, 0);
+    /// Latest revision of the protocol known to this crate
+    pub const LATEST_REVISION: u64 = Self::REVISION_1_0;
+
+    /// Encodes a revision the way the `revision` field holds it
+    pub const fn revision(major: u16, minor: u16) -> u64 {
+        (major as u64) << 16 | minor as u64
+    }
+}
+
+impl Proto<RiscvBoot> {
+    /// Returns whether the protocol implements `revision`, and so has its functions
+    pub fn supports(&self, revision: u64) -> bool {
+        self.revision >= revision
+    }
+
+    /// Returns the ID of the hart the firmware booted on
+    ///
+    /// Fails with `UNSUPPORTED` if the protocol predates `GetBootHartId`.
+    pub fn get_boot_hartid(&self) -> Result<usize> {
+        if !self.supports(RiscvBoot::REVISION_1_0) {
+            return Err(Status::UNSUPPORTED);
+        }
+        let mut hartid = 0;
+        (self.get_boot_hartid)(self.as_ptr(), &mut hartid).to_result(hartid)
+    }
+}
+
+/// Locates the RISC-V Boot Protocol and returns the boot hart's ID
+pub fn boot_hartid() -> Result<usize> {
+    boot_services()
+        .first_protocol::<RiscvBoot>()?
+        .get_boot_hartid()
+}
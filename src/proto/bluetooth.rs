@@ -0,0 +1,505 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Bluetooth configuration protocols
+//!
+//! [`BluetoothConfig`] manages a BR/EDR (classic) host controller and [`BluetoothLeConfig`] a
+//! Low Energy one: scanning for devices, connecting to them and reading their properties.
+//! Pairing is handled through callbacks registered with the protocol; the platform normally
+//! provides those, so these bindings only expose them as raw function pointers.
+
+use core::ffi::c_void;
+
+use crate::{
+    guid,
+    proto::{Proto, Protocol},
+    Guid, Result, Status,
+};
+
+/// `BLUETOOTH_ADDRESS`
+#[repr(C, packed)]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct BluetoothAddress {
+    /// The address, least significant byte first
+    pub address: [u8; 6],
+}
+
+impl core::fmt::Display for BluetoothAddress {
+    /// Formats the address most significant byte first, e.g. `00:1A:7D:DA:71:13`
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let [a, b, c, d, e, g] = self.address;
+        write!(f, "{g:02X}:{e:02X}:{d:02X}:{c:02X}:{b:02X}:{a:02X}")
+    }
+}
+
+/// `BLUETOOTH_LE_ADDRESS`
+#[repr(C, packed)]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct BluetoothLeAddress {
+    pub address: BluetoothAddress,
+    /// `0` for a public and `1` for a random device address
+    pub kind:    u8,
+}
+
+/// `EFI_BLUETOOTH_CONFIG_DATA_TYPE`, shared by both protocols
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct BluetoothDataType(pub u32);
+
+impl BluetoothDataType {
+    /// The UTF-8 device name, null-terminated
+    pub const DEVICE_NAME: Self = Self(0);
+    pub const CLASS_OF_DEVICE: Self = Self(1);
+    pub const REMOTE_DEVICE_STATE: Self = Self(2);
+    pub const SDP_INFO: Self = Self(3);
+    pub const BD_ADDR: Self = Self(4);
+    pub const DISCOVERABLE_STATE: Self = Self(5);
+    pub const CONTROLLER_STORED_PAIRED_DEVICE_LIST: Self = Self(6);
+    pub const AVAILABLE_DEVICE_LIST: Self = Self(7);
+    pub const RANDOM_ADDRESS: Self = Self(8);
+    pub const RSSI: Self = Self(9);
+    pub const ADVERTISEMENT_DATA: Self = Self(10);
+    pub const KEY_TYPE: Self = Self(11);
+    pub const ENCRYPTION_KEY_SIZE: Self = Self(12);
+}
+
+/// Maximum length of a remote device's name, including the terminator
+pub const MAX_NAME_SIZE: usize = 248;
+
+/// `EFI_BLUETOOTH_SCAN_CALLBACK_INFORMATION`
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct ScanCallbackInfo {
+    pub address:             BluetoothAddress,
+    pub remote_device_state: u8,
+    /// `BLUETOOTH_CLASS_OF_DEVICE`, a packed 24-bit field
+    pub class_of_device:     [u8; 3],
+    remote_device_name:      [u8; MAX_NAME_SIZE],
+}
+
+impl ScanCallbackInfo {
+    /// Returns the device's name, up to the first null
+    pub fn name(&self) -> &[u8] {
+        let len = self.remote_device_name.iter().position(|&b| b == 0);
+        &self.remote_device_name[..len.unwrap_or(MAX_NAME_SIZE)]
+    }
+}
+
+impl core::fmt::Debug for ScanCallbackInfo {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ScanCallbackInfo")
+            .field("address", &self.address)
+            .field("remote_device_state", &self.remote_device_state)
+            .field("class_of_device", &self.class_of_device)
+            .field("name", &self.name().escape_ascii())
+            .finish()
+    }
+}
+
+pub type ScanCallbackFn = extern "efiapi" fn(
+    this: *mut BluetoothConfig,
+    context: *mut c_void,
+    callback_info: *const ScanCallbackInfo,
+) -> Status;
+
+pub type InitFn = extern "efiapi" fn(this: *mut BluetoothConfig) -> Status;
+
+pub type ScanFn = extern "efiapi" fn(
+    this: *mut BluetoothConfig,
+    rescan: bool,
+    scan_type: u8,
+    callback: ScanCallbackFn,
+    context: *mut c_void,
+) -> Status;
+
+pub type ConnectFn =
+    extern "efiapi" fn(this: *mut BluetoothConfig, bd_addr: *const BluetoothAddress) -> Status;
+
+pub type DisconnectFn = extern "efiapi" fn(
+    this: *mut BluetoothConfig,
+    bd_addr: *const BluetoothAddress,
+    reason: u8,
+) -> Status;
+
+pub type GetDataFn = extern "efiapi" fn(
+    this: *mut BluetoothConfig,
+    data_type: BluetoothDataType,
+    data_size: *mut usize,
+    data: *mut c_void,
+) -> Status;
+
+pub type SetDataFn = extern "efiapi" fn(
+    this: *mut BluetoothConfig,
+    data_type: BluetoothDataType,
+    data_size: usize,
+    data: *const c_void,
+) -> Status;
+
+pub type GetRemoteDataFn = extern "efiapi" fn(
+    this: *mut BluetoothConfig,
+    data_type: BluetoothDataType,
+    bd_addr: *const BluetoothAddress,
+    data_size: *mut usize,
+    data: *mut c_void,
+) -> Status;
+
+/// Registers one of the pairing callbacks; the callback's signature depends on the function
+pub type RegisterCallbackFn = extern "efiapi" fn(
+    this: *mut BluetoothConfig,
+    callback: *const c_void,
+    context: *mut c_void,
+) -> Status;
+
+/// `HCI_Disconnect` reason: remote user terminated connection
+pub const DISCONNECT_REMOTE_USER: u8 = 0x13;
+
+/// Bluetooth Configuration Protocol
+#[repr(C)]
+pub struct BluetoothConfig {
+    init:                                        InitFn,
+    scan:                                        ScanFn,
+    connect:                                     ConnectFn,
+    disconnect:                                  DisconnectFn,
+    get_data:                                    GetDataFn,
+    set_data:                                    SetDataFn,
+    get_remote_data:                             GetRemoteDataFn,
+    pub register_pin_callback:                   RegisterCallbackFn,
+    pub register_get_link_key_callback:          RegisterCallbackFn,
+    pub register_set_link_key_callback:          RegisterCallbackFn,
+    pub register_link_connect_complete_callback: RegisterCallbackFn,
+}
+
+impl Protocol for BluetoothConfig {
+    const GUID: Guid = guid!(
+        0x62960cf3,0x40ff,0x4263,
+        {0xa7,0x7c,0xdf,0xde,0xbd,0x19,0x1b,0x4b}
+    );
+}
+
+impl Proto<BluetoothConfig> {
+    /// Initializes the host controller and the stack
+    pub fn init(&mut self) -> Result<()> {
+        (self.init)(self.as_ptr()).to_result(())
+    }
+
+    /// Starts a scan for devices, calling `callback` for each one found
+    ///
+    /// With `rescan` false, the results of the previous scan are reported again.
+    ///
+    /// # Safety
+    ///
+    /// The callback may be invoked after this returns, until the scan completes; `context`
+    /// must remain valid for that long.
+    pub unsafe fn scan(
+        &mut self,
+        rescan: bool,
+        scan_type: u8,
+        callback: ScanCallbackFn,
+        context: *mut c_void,
+    ) -> Result<()> {
+        (self.scan)(self.as_ptr(), rescan, scan_type, callback, context).to_result(())
+    }
+
+    /// Connects to a device found by a scan, pairing with it if needed
+    pub fn connect(&mut self, address: &BluetoothAddress) -> Result<()> {
+        (self.connect)(self.as_ptr(), address).to_result(())
+    }
+
+    /// Disconnects from a device with the given HCI reason code
+    pub fn disconnect(&mut self, address: &BluetoothAddress, reason: u8) -> Result<()> {
+        (self.disconnect)(self.as_ptr(), address, reason).to_result(())
+    }
+
+    /// Reads a property of the local controller into `buf`, returning its size
+    pub fn get_data(&mut self, kind: BluetoothDataType, buf: &mut [u8]) -> Result<usize> {
+        let mut size = buf.len();
+        (self.get_data)(self.as_ptr(), kind, &mut size, buf.as_mut_ptr().cast()).to_result(size)
+    }
+
+    pub fn set_data(&mut self, kind: BluetoothDataType, data: &[u8]) -> Result<()> {
+        (self.set_data)(self.as_ptr(), kind, data.len(), data.as_ptr().cast()).to_result(())
+    }
+
+    /// Reads a property of a remote device into `buf`, returning its size
+    pub fn get_remote_data(
+        &mut self,
+        kind: BluetoothDataType,
+        address: &BluetoothAddress,
+        buf: &mut [u8],
+    ) -> Result<usize> {
+        let mut size = buf.len();
+        (self.get_remote_data)(
+            self.as_ptr(),
+            kind,
+            address,
+            &mut size,
+            buf.as_mut_ptr().cast(),
+        )
+        .to_result(size)
+    }
+}
+
+/// `EFI_BLUETOOTH_LE_CONFIG_SCAN_PARAMETER`
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct LeScanParameter {
+    pub version:                   u32,
+    /// `0` for a passive and `1` for an active scan
+    pub scan_type:                 u8,
+    /// In units of 0.625 ms
+    pub scan_interval:             u16,
+    /// In units of 0.625 ms
+    pub scan_window:               u16,
+    pub scanning_filter_policy:    u8,
+    pub advertisement_flag_filter: u8,
+}
+
+impl Default for LeScanParameter {
+    /// An active scan with the intervals recommended for discovery
+    fn default() -> Self {
+        Self {
+            version:                   1,
+            scan_type:                 1,
+            scan_interval:             0x0060,
+            scan_window:               0x0030,
+            scanning_filter_policy:    0,
+            advertisement_flag_filter: 0,
+        }
+    }
+}
+
+/// `EFI_BLUETOOTH_LE_CONFIG_CONNECT_PARAMETER`
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct LeConnectParameter {
+    pub version:             u32,
+    /// In units of 1.25 ms
+    pub conn_interval_min:   u16,
+    /// In units of 1.25 ms
+    pub conn_interval_max:   u16,
+    pub conn_latency:        u16,
+    /// In units of 10 ms
+    pub supervision_timeout: u16,
+}
+
+/// `EFI_BLUETOOTH_LE_SCAN_CALLBACK_INFORMATION`
+#[repr(C)]
+#[derive(Debug)]
+pub struct LeScanCallbackInfo {
+    pub address:             BluetoothLeAddress,
+    pub direct_address:      BluetoothLeAddress,
+    pub remote_device_state: u8,
+    pub rssi:                i8,
+    advertisement_data_size: usize,
+    advertisement_data:      *const u8,
+}
+
+impl LeScanCallbackInfo {
+    /// Returns the raw advertising data, a sequence of length-type-value structures
+    pub fn advertisement_data(&self) -> &[u8] {
+        if self.advertisement_data.is_null() {
+            return &[];
+        }
+        unsafe {
+            core::slice::from_raw_parts(self.advertisement_data, self.advertisement_data_size)
+        }
+    }
+
+    /// Returns an iterator over the advertising data structures as `(type, data)` pairs
+    pub fn advertisement_structures(&self) -> impl Iterator<Item = (u8, &[u8])> {
+        let mut data = self.advertisement_data();
+        core::iter::from_fn(move || {
+            let (&len, rest) = data.split_first()?;
+            let (&kind, value) = rest.get(..len as usize)?.split_first()?;
+            data = &rest[len as usize..];
+            Some((kind, value))
+        })
+    }
+}
+
+pub type LeScanCallbackFn = extern "efiapi" fn(
+    this: *mut BluetoothLeConfig,
+    context: *mut c_void,
+    callback_info: *const LeScanCallbackInfo,
+) -> Status;
+
+pub type LeInitFn = extern "efiapi" fn(this: *mut BluetoothLeConfig) -> Status;
+
+pub type LeScanFn = extern "efiapi" fn(
+    this: *mut BluetoothLeConfig,
+    rescan: bool,
+    timeout: u32,
+    scan_parameter: *const LeScanParameter,
+    callback: LeScanCallbackFn,
+    context: *mut c_void,
+) -> Status;
+
+pub type LeConnectFn = extern "efiapi" fn(
+    this: *mut BluetoothLeConfig,
+    auto_reconnect: bool,
+    do_bonding: bool,
+    connect_parameter: *const LeConnectParameter,
+    bd_addr: *const BluetoothLeAddress,
+) -> Status;
+
+pub type LeDisconnectFn = extern "efiapi" fn(
+    this: *mut BluetoothLeConfig,
+    bd_addr: *const BluetoothLeAddress,
+    reason: u8,
+) -> Status;
+
+pub type LeGetDataFn = extern "efiapi" fn(
+    this: *mut BluetoothLeConfig,
+    data_type: BluetoothDataType,
+    data_size: *mut usize,
+    data: *mut c_void,
+) -> Status;
+
+pub type LeSetDataFn = extern "efiapi" fn(
+    this: *mut BluetoothLeConfig,
+    data_type: BluetoothDataType,
+    data_size: usize,
+    data: *const c_void,
+) -> Status;
+
+pub type LeGetRemoteDataFn = extern "efiapi" fn(
+    this: *mut BluetoothLeConfig,
+    data_type: BluetoothDataType,
+    bd_addr: *const BluetoothLeAddress,
+    data_size: *mut usize,
+    data: *mut c_void,
+) -> Status;
+
+pub type LeRegisterCallbackFn = extern "efiapi" fn(
+    this: *mut BluetoothLeConfig,
+    callback: *const c_void,
+    context: *mut c_void,
+) -> Status;
+
+pub type LeSendSmpAuthDataFn = extern "efiapi" fn(
+    this: *mut BluetoothLeConfig,
+    bd_addr: *const BluetoothLeAddress,
+    event_data_type: u32,
+    data_size: usize,
+    data: *const c_void,
+) -> Status;
+
+/// Bluetooth Low Energy Configuration Protocol
+#[repr(C)]
+pub struct BluetoothLeConfig {
+    init:                                        LeInitFn,
+    scan:                                        LeScanFn,
+    connect:                                     LeConnectFn,
+    disconnect:                                  LeDisconnectFn,
+    get_data:                                    LeGetDataFn,
+    set_data:                                    LeSetDataFn,
+    get_remote_data:                             LeGetRemoteDataFn,
+    pub register_smp_auth_callback:              LeRegisterCallbackFn,
+    pub send_smp_auth_data:                      LeSendSmpAuthDataFn,
+    pub register_smp_get_data_callback:          LeRegisterCallbackFn,
+    pub register_smp_set_data_callback:          LeRegisterCallbackFn,
+    pub register_link_connect_complete_callback: LeRegisterCallbackFn,
+}
+
+impl Protocol for BluetoothLeConfig {
+    const GUID: Guid = guid!(
+        0x8f76da58,0x1f99,0x4275,
+        {0xa4,0xec,0x47,0x56,0x51,0x5b,0x1c,0xe8}
+    );
+}
+
+impl Proto<BluetoothLeConfig> {
+    pub fn init(&mut self) -> Result<()> {
+        (self.init)(self.as_ptr()).to_result(())
+    }
+
+    /// Scans for advertising devices for `timeout` milliseconds, calling `callback` for each
+    ///
+    /// # Safety
+    ///
+    /// The callback may be invoked after this returns, until the scan times out; `context`
+    /// must remain valid for that long.
+    pub unsafe fn scan(
+        &mut self,
+        rescan: bool,
+        timeout: u32,
+        parameter: &LeScanParameter,
+        callback: LeScanCallbackFn,
+        context: *mut c_void,
+    ) -> Result<()> {
+        (self.scan)(self.as_ptr(), rescan, timeout, parameter, callback, context).to_result(())
+    }
+
+    /// Connects to a device, optionally bonding with it
+    ///
+    /// `parameter` of `None` uses the controller's defaults.
+    pub fn connect(
+        &mut self,
+        address: &BluetoothLeAddress,
+        parameter: Option<&LeConnectParameter>,
+        auto_reconnect: bool,
+        bond: bool,
+    ) -> Result<()> {
+        let parameter = parameter.map_or(core::ptr::null(), |p| p as *const _);
+        (self.connect)(self.as_ptr(), auto_reconnect, bond, parameter, address).to_result(())
+    }
+
+    pub fn disconnect(&mut self, address: &BluetoothLeAddress, reason: u8) -> Result<()> {
+        (self.disconnect)(self.as_ptr(), address, reason).to_result(())
+    }
+
+    /// Reads a property of the local controller into `buf`, returning its size
+    pub fn get_data(&mut self, kind: BluetoothDataType, buf: &mut [u8]) -> Result<usize> {
+        let mut size = buf.len();
+        (self.get_data)(self.as_ptr(), kind, &mut size, buf.as_mut_ptr().cast()).to_result(size)
+    }
+
+    pub fn set_data(&mut self, kind: BluetoothDataType, data: &[u8]) -> Result<()> {
+        (self.set_data)(self.as_ptr(), kind, data.len(), data.as_ptr().cast()).to_result(())
+    }
+
+    /// Reads a property of a remote device into `buf`, returning its size
+    pub fn get_remote_data(
+        &mut self,
+        kind: BluetoothDataType,
+        address: &BluetoothLeAddress,
+        buf: &mut [u8],
+    ) -> Result<usize> {
+        let mut size = buf.len();
+        (self.get_remote_data)(
+            self.as_ptr(),
+            kind,
+            address,
+            &mut size,
+            buf.as_mut_ptr().cast(),
+        )
+        .to_result(size)
+    }
+}
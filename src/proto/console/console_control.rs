@@ -0,0 +1,110 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Console Control Protocol
+//!
+//! Legacy (Intel/Apple) protocol for switching the console between text and graphics mode.
+//! Some older firmware, notably on Macs, leaves the screen in text mode until told
+//! otherwise, so graphics output is overwritten or never shown.
+
+use core::ffi::c_int;
+
+use crate::{guid, proto::Protocol, Guid, Result, Status};
+
+pub type GetModeFn = extern "efiapi" fn(
+    this: *mut ConsoleControl,
+    mode: *mut ScreenMode,
+    gop_uga_exists: *mut bool,
+    stdin_locked: *mut bool,
+) -> Status;
+
+pub type SetModeFn = extern "efiapi" fn(this: *mut ConsoleControl, mode: ScreenMode) -> Status;
+
+pub type LockStdInFn = extern "efiapi" fn(this: *mut ConsoleControl, password: *mut u16) -> Status;
+
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct ScreenMode(pub c_int);
+
+impl ScreenMode {
+    pub const TEXT: Self = Self(0);
+    pub const GRAPHICS: Self = Self(1);
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ConsoleMode {
+    pub mode:           ScreenMode,
+    /// A graphics protocol is available
+    pub gop_uga_exists: bool,
+    pub stdin_locked:   bool,
+}
+
+#[repr(C)]
+pub struct ConsoleControl {
+    get_mode:    GetModeFn,
+    set_mode:    SetModeFn,
+    lock_std_in: LockStdInFn,
+}
+
+impl Protocol for ConsoleControl {
+    const GUID: Guid = guid!(
+        0xf42f7782, 0x012e, 0x4c12,
+        {0x99,0x56,0x49,0xf9,0x43,0x04,0xf7,0x21}
+    );
+}
+
+impl ConsoleControl {
+    pub fn get_mode(&mut self) -> Result<ConsoleMode> {
+        let mut mode = ConsoleMode {
+            mode:           ScreenMode::TEXT,
+            gop_uga_exists: false,
+            stdin_locked:   false,
+        };
+        (self.get_mode)(
+            self,
+            &mut mode.mode,
+            &mut mode.gop_uga_exists,
+            &mut mode.stdin_locked,
+        )
+        .to_result(mode)
+    }
+
+    pub fn set_mode(&mut self, mode: ScreenMode) -> Result<()> {
+        (self.set_mode)(self, mode).to_result(())
+    }
+
+    /// Locks the console input until `password` (NUL-terminated) is entered
+    pub fn lock_std_in(&mut self, password: &[u16]) -> Result<()> {
+        if !password.contains(&0) {
+            return Err(Status::INVALID_PARAMETER);
+        }
+        (self.lock_std_in)(self, password.as_ptr().cast_mut()).to_result(())
+    }
+}
@@ -30,7 +30,13 @@
 
 use core::{ffi::c_int, mem::size_of, ptr};
 
-use crate::{guid, proto::Protocol, Handle, PhysicalAddr, Result, Status};
+use crate::{
+    guid,
+    proto::Protocol,
+    quirks,
+    table::{AllocPagesType, MemoryAttribute, MemoryDescriptor, MemoryMapInfo, MemoryType},
+    Handle, PhysicalAddr, Result, Status,
+};
 
 pub type QueryModeFn = extern "efiapi" fn(
     this: *mut GraphicsOutput,
@@ -80,19 +86,23 @@ impl GraphicsOutput {
     }
 
     /// Requests the information structure for a specific mode.
-    pub fn query_mode(&mut self, mode: u32) -> Result<&'static ModeInfo> {
+    pub fn query_mode(&mut self, mode: u32) -> Result<ModeInfo> {
         let mut ptr = ptr::null();
         let mut size = 0;
         (self.query_mode)(self, mode, &mut size, &mut ptr).to_result(())?;
         assert!(size >= size_of::<ModeInfo>());
-        Ok(unsafe { &*ptr })
+        let info = unsafe { ptr.read() };
+        if quirks::free_query_mode_info() && crate::boot_services_active() {
+            unsafe { crate::boot_services().free_pool(ptr.cast_mut().cast()).ok() };
+        }
+        Ok(info)
     }
 
     pub fn set_mode(&mut self, mode: u32) -> Result<()> {
         (self.set_mode)(self, mode).to_result(())
     }
 
-    pub fn all_modes(&mut self) -> impl Iterator<Item = (u32, Result<&'static ModeInfo>)> + '_ {
+    pub fn all_modes(&mut self) -> impl Iterator<Item = (u32, Result<ModeInfo>)> + '_ {
         let mut current_mode = 0;
         let max_mode = self.mode().max_mode - 1;
 
@@ -118,6 +128,48 @@ pub struct PixelBitmask {
     pub reserved: u32,
 }
 
+/// Scales an 8-bit channel value to the width of `mask`, and shifts it into place
+fn pack_channel(mask: u32, value: u8) -> u32 {
+    if mask == 0 {
+        return 0;
+    }
+    let shift = mask.trailing_zeros();
+    let max = (mask >> shift) as u64;
+    (((value as u64 * max + 127) / 255) as u32) << shift & mask
+}
+
+/// Extracts the channel selected by `mask` from `raw`, scaled to 8 bits
+fn unpack_channel(mask: u32, raw: u32) -> u8 {
+    if mask == 0 {
+        return 0;
+    }
+    let shift = mask.trailing_zeros();
+    let max = (mask >> shift) as u64;
+    (((raw & mask) >> shift) as u64 * 255 / max) as u8
+}
+
+impl PixelBitmask {
+    /// Returns the number of bits used by each pixel, up to the highest set bit of any mask
+    pub const fn bits_per_pixel(&self) -> u32 {
+        32 - (self.red | self.green | self.blue | self.reserved).leading_zeros()
+    }
+
+    pub fn pack(&self, pixel: BltPixel) -> u32 {
+        pack_channel(self.red, pixel.red)
+            | pack_channel(self.green, pixel.green)
+            | pack_channel(self.blue, pixel.blue)
+    }
+
+    pub fn unpack(&self, raw: u32) -> BltPixel {
+        BltPixel {
+            blue:     unpack_channel(self.blue, raw),
+            green:    unpack_channel(self.green, raw),
+            red:      unpack_channel(self.red, raw),
+            reserved: 0,
+        }
+    }
+}
+
 #[repr(transparent)]
 #[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
 pub struct PixelFormat(pub c_int);
@@ -127,6 +179,42 @@ impl PixelFormat {
     pub const BGRA8: Self = Self(1);
     pub const BITMASK: Self = Self(2);
     pub const BLT_ONLY: Self = Self(3);
+
+    /// Converts a pixel to its framebuffer representation
+    ///
+    /// `bitmask` is only used by [`BITMASK`](Self::BITMASK); returns `None` for
+    /// [`BLT_ONLY`](Self::BLT_ONLY), which has no framebuffer.
+    pub fn pack(self, pixel: BltPixel, bitmask: &PixelBitmask) -> Option<u32> {
+        let BltPixel {
+            red, green, blue, ..
+        } = pixel;
+        match self {
+            Self::RGBA8 => Some(u32::from_le_bytes([red, green, blue, 0])),
+            Self::BGRA8 => Some(u32::from_le_bytes([blue, green, red, 0])),
+            Self::BITMASK => Some(bitmask.pack(pixel)),
+            _ => None,
+        }
+    }
+
+    /// Converts a pixel from its framebuffer representation
+    pub fn unpack(self, raw: u32, bitmask: &PixelBitmask) -> Option<BltPixel> {
+        let [a, b, c, _] = raw.to_le_bytes();
+        match self {
+            Self::RGBA8 => Some(BltPixel::new(a, b, c)),
+            Self::BGRA8 => Some(BltPixel::new(c, b, a)),
+            Self::BITMASK => Some(bitmask.unpack(raw)),
+            _ => None,
+        }
+    }
+
+    /// Returns the number of bytes used by each pixel in the framebuffer
+    pub fn bytes_per_pixel(self, bitmask: &PixelBitmask) -> Option<usize> {
+        match self {
+            Self::RGBA8 | Self::BGRA8 => Some(4),
+            Self::BITMASK => Some(bitmask.bits_per_pixel().div_ceil(8) as usize),
+            _ => None,
+        }
+    }
 }
 
 #[repr(C)]
@@ -140,6 +228,18 @@ pub struct ModeInfo {
     pub pixels_per_scanline:   u32,
 }
 
+impl ModeInfo {
+    /// Converts a pixel to this mode's framebuffer representation
+    pub fn pack(&self, pixel: BltPixel) -> Option<u32> {
+        self.pixel_format.pack(pixel, &self.pixel_info)
+    }
+
+    /// Converts a pixel from this mode's framebuffer representation
+    pub fn unpack(&self, raw: u32) -> Option<BltPixel> {
+        self.pixel_format.unpack(raw, &self.pixel_info)
+    }
+}
+
 #[repr(C)]
 pub struct Mode {
     /// Number of modes supported by [`QueryModeFn`] and [`SetModeFn`]
@@ -156,6 +256,101 @@ impl Mode {
     pub const fn info(&self) -> &'static ModeInfo {
         unsafe { &*self.info }
     }
+
+    /// Checks the framebuffer against the memory map and reserves it for the kernel
+    ///
+    /// `map` must be a buffer filled by [`get_memory_map()`] and described by `info`. Parts of
+    /// the framebuffer which firmware reports as conventional memory are allocated as
+    /// `RESERVED`, so the memory map fetched for `ExitBootServices()` no longer offers them as
+    /// usable RAM; that map must be fetched after this call.
+    ///
+    /// [`get_memory_map()`]: crate::table::BootServices::get_memory_map
+    ///
+    /// Fails with `UNSUPPORTED` if the mode has no framebuffer, or with `ACCESS_DENIED` if
+    /// the framebuffer overlaps memory already allocated as boot services or loader memory,
+    /// which the kernel would otherwise reclaim. Nothing stays reserved if this fails.
+    pub fn reserve_framebuffer(
+        &self,
+        map: &[u8],
+        info: &MemoryMapInfo,
+    ) -> Result<FramebufferRegion> {
+        if self.framebuffer_size == 0 || self.info().pixel_format == PixelFormat::BLT_ONLY {
+            return Err(Status::UNSUPPORTED);
+        }
+        let base = self.framebuffer_addr;
+        let end = base.saturating_add(self.framebuffer_size as u64);
+
+        let bs = crate::boot_services();
+        let overlapping = || {
+            info.descriptors(map)
+                .filter(move |desc| desc.phys < end && desc.end_phys() > base)
+        };
+        // The pages of a conventional memory descriptor that the framebuffer covers
+        let covered_pages = |desc: &MemoryDescriptor| {
+            let start = desc.phys.max(base) & !(MemoryDescriptor::PAGE_SIZE - 1);
+            let pages = (desc.end_phys().min(end) - start).div_ceil(MemoryDescriptor::PAGE_SIZE);
+            (start, pages as usize)
+        };
+
+        let mut region = FramebufferRegion {
+            base,
+            size: self.framebuffer_size,
+            kind: FramebufferMemory::Mmio,
+            attribute: MemoryAttribute::all(),
+        };
+        let mut described = false;
+        let mut reserved = 0;
+        let result = overlapping().try_for_each(|desc| {
+            described = true;
+            region.attribute &= desc.attribute;
+            match desc.kind {
+                MemoryType::MMIO | MemoryType::MMIO_PORT_SPACE => {}
+                MemoryType::CONVENTIONAL_MEMORY => {
+                    let (start, pages) = covered_pages(&desc);
+                    bs.allocate_pages(AllocPagesType::Addr(start), MemoryType::RESERVED, pages)?;
+                    reserved += 1;
+                    region.kind = FramebufferMemory::Reserved;
+                }
+                _ if desc.usable_after_ebs() => return Err(Status::ACCESS_DENIED),
+                _ => region.kind = FramebufferMemory::Reserved,
+            }
+            Ok(())
+        });
+        if let Err(status) = result {
+            // Release the pages reserved before the failure.
+            let conventional =
+                overlapping().filter(|desc| desc.kind == MemoryType::CONVENTIONAL_MEMORY);
+            for desc in conventional.take(reserved) {
+                let (start, pages) = covered_pages(&desc);
+                let _ = unsafe { bs.free_pages(start, pages) };
+            }
+            return Err(status);
+        }
+        if !described {
+            region.attribute = MemoryAttribute::empty();
+        }
+        Ok(region)
+    }
+}
+
+/// The framebuffer's place in the memory map handed to the kernel
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FramebufferMemory {
+    /// The framebuffer is device memory, either described as MMIO or not described at all
+    Mmio,
+    /// The framebuffer lies in RAM, which is marked reserved in the memory map
+    Reserved,
+}
+
+/// A framebuffer range checked by [`Mode::reserve_framebuffer()`]
+#[derive(Clone, Copy, Debug)]
+pub struct FramebufferRegion {
+    pub base:      PhysicalAddr,
+    pub size:      usize,
+    pub kind:      FramebufferMemory,
+    /// Caching attributes supported by every descriptor covering the framebuffer, or empty if
+    /// the memory map does not describe it
+    pub attribute: MemoryAttribute,
 }
 
 #[repr(transparent)]
@@ -168,7 +363,173 @@ impl BltOperation {
     pub const VIDEO_TO_VIDEO: Self = Self(3);
 }
 
+/// Block transfers to and from the screen
+///
+/// GOP and [UGA Draw](super::uga::UgaDraw) take the same `Blt()` arguments, so only
+/// [`blt()`](Self::blt) is implemented for each; the rest check their buffers and call it.
+pub trait Blt {
+    /// Performs a raw block transfer
+    ///
+    /// # Safety
+    ///
+    /// `buffer` must be valid for the transfer described by the other arguments; `delta` is
+    /// the size in bytes of one row of the buffer, or zero if the rows are `width` pixels.
+    #[allow(clippy::too_many_arguments)]
+    unsafe fn blt(
+        &mut self,
+        buffer: *mut BltPixel,
+        operation: BltOperation,
+        source: (usize, usize),
+        destination: (usize, usize),
+        width: usize,
+        height: usize,
+        delta: usize,
+    ) -> Result<()>;
+
+    /// Fills a rectangle of the screen with a single color
+    fn fill(
+        &mut self,
+        color: BltPixel,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> Result<()> {
+        let mut color = color;
+        unsafe {
+            self.blt(
+                &mut color,
+                BltOperation::VIDEO_FILL,
+                (0, 0),
+                (x, y),
+                width,
+                height,
+                0,
+            )
+        }
+    }
+
+    /// Copies a `width` by `height` rectangle from the start of `buffer` to the screen
+    ///
+    /// Rows of `buffer` are `stride` pixels apart.
+    fn write_buffer(
+        &mut self,
+        buffer: &[BltPixel],
+        stride: usize,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> Result<()> {
+        let delta = blt_delta(buffer.len(), stride, width, height)?;
+        unsafe {
+            self.blt(
+                buffer.as_ptr().cast_mut(),
+                BltOperation::BUFFER_TO_VIDEO,
+                (0, 0),
+                (x, y),
+                width,
+                height,
+                delta,
+            )
+        }
+    }
+
+    /// Copies a `width` by `height` rectangle of the screen to the start of `buffer`
+    ///
+    /// Rows of `buffer` are `stride` pixels apart.
+    fn read_buffer(
+        &mut self,
+        buffer: &mut [BltPixel],
+        stride: usize,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> Result<()> {
+        let delta = blt_delta(buffer.len(), stride, width, height)?;
+        unsafe {
+            self.blt(
+                buffer.as_mut_ptr(),
+                BltOperation::VIDEO_TO_BLT_BUFFER,
+                (x, y),
+                (0, 0),
+                width,
+                height,
+                delta,
+            )
+        }
+    }
+
+    /// Copies a rectangle of the screen to another position on the screen
+    fn copy(
+        &mut self,
+        source: (usize, usize),
+        destination: (usize, usize),
+        width: usize,
+        height: usize,
+    ) -> Result<()> {
+        unsafe {
+            self.blt(
+                ptr::null_mut(),
+                BltOperation::VIDEO_TO_VIDEO,
+                source,
+                destination,
+                width,
+                height,
+                0,
+            )
+        }
+    }
+}
+
+impl Blt for GraphicsOutput {
+    unsafe fn blt(
+        &mut self,
+        buffer: *mut BltPixel,
+        operation: BltOperation,
+        source: (usize, usize),
+        destination: (usize, usize),
+        width: usize,
+        height: usize,
+        delta: usize,
+    ) -> Result<()> {
+        (self.blt)(
+            self,
+            buffer,
+            operation,
+            source.0,
+            source.1,
+            destination.0,
+            destination.1,
+            width,
+            height,
+            delta,
+        )
+        .to_result(())
+    }
+}
+
+/// Checks that a buffer of `len` pixels holds a `width` by `height` rectangle with rows
+/// `stride` pixels apart, and returns the size of a row in bytes
+fn blt_delta(len: usize, stride: usize, width: usize, height: usize) -> Result<usize> {
+    let delta = stride
+        .checked_mul(size_of::<BltPixel>())
+        .ok_or(Status::BAD_BUFFER_SIZE)?;
+    if width == 0 || height == 0 {
+        return Ok(delta);
+    }
+    let needed = (height - 1)
+        .checked_mul(stride)
+        .and_then(|n| n.checked_add(width));
+    match needed {
+        Some(needed) if width <= stride && needed <= len => Ok(delta),
+        _ => Err(Status::BAD_BUFFER_SIZE),
+    }
+}
+
 #[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
 pub struct BltPixel {
     pub blue:     u8,
     pub green:    u8,
@@ -176,6 +537,43 @@ pub struct BltPixel {
     pub reserved: u8,
 }
 
+impl BltPixel {
+    pub const fn new(red: u8, green: u8, blue: u8) -> Self {
+        Self {
+            blue,
+            green,
+            red,
+            reserved: 0,
+        }
+    }
+
+    /// Returns the color as `0x00RRGGBB`
+    pub const fn to_u32(self) -> u32 {
+        u32::from_le_bytes([self.blue, self.green, self.red, 0])
+    }
+}
+
+impl From<(u8, u8, u8)> for BltPixel {
+    /// Converts a `(red, green, blue)` triple
+    fn from((red, green, blue): (u8, u8, u8)) -> Self {
+        Self::new(red, green, blue)
+    }
+}
+
+impl From<u32> for BltPixel {
+    /// Converts a `0x00RRGGBB` color; the top byte is ignored
+    fn from(rgb: u32) -> Self {
+        let [blue, green, red, _] = rgb.to_le_bytes();
+        Self::new(red, green, blue)
+    }
+}
+
+impl From<BltPixel> for u32 {
+    fn from(pixel: BltPixel) -> Self {
+        pixel.to_u32()
+    }
+}
+
 #[repr(C)]
 pub struct EdidDiscovered {
     edid_size: u32,
@@ -28,6 +28,11 @@
  * SPDX-License-Identifier: BSD-3-Clause
  */
 
+pub mod console_control;
 pub mod gop;
+pub mod pointer;
+pub mod serial;
 pub mod text_input;
+pub mod text_input_ex;
 pub mod text_output;
+pub mod uga;
@@ -0,0 +1,99 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Simple Pointer Protocol
+
+use crate::{guid, proto::Protocol, Event, Guid, Result, Status};
+
+pub type PointerResetFn =
+    extern "efiapi" fn(this: *mut SimplePointer, extended_verification: bool) -> Status;
+
+pub type GetStateFn =
+    extern "efiapi" fn(this: *mut SimplePointer, state: *mut PointerState) -> Status;
+
+/// Movement since the state was last read, and the current button state
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct PointerState {
+    /// Movement in units of [`PointerMode::resolution_x`] counts per millimeter
+    pub relative_movement_x: i32,
+    pub relative_movement_y: i32,
+    pub relative_movement_z: i32,
+    pub left_button:         bool,
+    pub right_button:        bool,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct PointerMode {
+    /// Counts per millimeter, or zero if the axis is not supported
+    pub resolution_x: u64,
+    pub resolution_y: u64,
+    pub resolution_z: u64,
+    pub left_button:  bool,
+    pub right_button: bool,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct SimplePointer {
+    pub(crate) reset:          PointerResetFn,
+    pub(crate) get_state:      GetStateFn,
+    pub(crate) wait_for_input: Event,
+    pub(crate) mode:           *mut PointerMode,
+}
+
+impl Protocol for SimplePointer {
+    const GUID: Guid = guid!(
+        0x31878c87, 0x0b75, 0x11d5,
+        {0x9a,0x4f,0x00,0x90,0x27,0x3f,0xc1,0x4d}
+    );
+}
+
+impl SimplePointer {
+    pub fn reset(&mut self, extended_verification: bool) -> Result<()> {
+        (self.reset)(self, extended_verification).to_result(())
+    }
+
+    /// Reads the pointer's state, or `NOT_READY` if it hasn't changed since the last read
+    pub fn get_state(&mut self) -> Result<PointerState> {
+        let mut state = PointerState::default();
+        (self.get_state)(self, &mut state).to_result(state)
+    }
+
+    /// Returns the event signaled when the pointer's state changes
+    pub fn wait_for_input(&self) -> Event {
+        self.wait_for_input
+    }
+
+    pub fn mode(&self) -> &PointerMode {
+        unsafe { &*self.mode }
+    }
+}
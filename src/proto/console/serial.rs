@@ -0,0 +1,237 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Serial I/O Protocol
+
+use core::{ffi::c_int, fmt};
+
+use bitflags::bitflags;
+
+use crate::{guid, proto::Protocol, Guid, Result, Status};
+
+pub type SerialResetFn = extern "efiapi" fn(this: *mut SerialIo) -> Status;
+
+pub type SetAttributesFn = extern "efiapi" fn(
+    this: *mut SerialIo,
+    baud_rate: u64,
+    receive_fifo_depth: u32,
+    timeout: u32,
+    parity: Parity,
+    data_bits: u8,
+    stop_bits: StopBits,
+) -> Status;
+
+pub type SetControlFn = extern "efiapi" fn(this: *mut SerialIo, control: ControlBits) -> Status;
+
+pub type GetControlFn =
+    extern "efiapi" fn(this: *mut SerialIo, control: *mut ControlBits) -> Status;
+
+pub type SerialWriteFn =
+    extern "efiapi" fn(this: *mut SerialIo, buffer_size: *mut usize, buffer: *const u8) -> Status;
+
+pub type SerialReadFn =
+    extern "efiapi" fn(this: *mut SerialIo, buffer_size: *mut usize, buffer: *mut u8) -> Status;
+
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Parity(pub c_int);
+
+impl Parity {
+    pub const DEFAULT: Self = Self(0);
+    pub const NONE: Self = Self(1);
+    pub const EVEN: Self = Self(2);
+    pub const ODD: Self = Self(3);
+    pub const MARK: Self = Self(4);
+    pub const SPACE: Self = Self(5);
+}
+
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct StopBits(pub c_int);
+
+impl StopBits {
+    pub const DEFAULT: Self = Self(0);
+    pub const ONE: Self = Self(1);
+    pub const ONE_FIVE: Self = Self(2);
+    pub const TWO: Self = Self(3);
+}
+
+bitflags! {
+    #[repr(transparent)]
+    pub struct ControlBits : u32 {
+        const DATA_TERMINAL_READY          = 0x0001;
+        const REQUEST_TO_SEND              = 0x0002;
+        const CLEAR_TO_SEND                = 0x0010;
+        const DATA_SET_READY               = 0x0020;
+        const RING_INDICATE                = 0x0040;
+        const CARRIER_DETECT               = 0x0080;
+        const INPUT_BUFFER_EMPTY           = 0x0100;
+        const OUTPUT_BUFFER_EMPTY          = 0x0200;
+        const HARDWARE_LOOPBACK_ENABLE     = 0x1000;
+        const SOFTWARE_LOOPBACK_ENABLE     = 0x2000;
+        const HARDWARE_FLOW_CONTROL_ENABLE = 0x4000;
+    }
+}
+
+/// Current attributes of the serial device
+///
+/// A value of zero means the device's default is in use.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct SerialIoMode {
+    /// Control bits [`SerialIo::get_control`] can report
+    pub control_mask:       u32,
+    /// Timeout of a read or write of one byte, in microseconds
+    pub timeout:            u32,
+    pub baud_rate:          u64,
+    pub receive_fifo_depth: u32,
+    pub data_bits:          u32,
+    pub parity:             u32,
+    pub stop_bits:          u32,
+}
+
+#[repr(C)]
+pub struct SerialIo {
+    pub revision:                u32,
+    pub(crate) reset:            SerialResetFn,
+    pub(crate) set_attributes:   SetAttributesFn,
+    pub(crate) set_control:      SetControlFn,
+    pub(crate) get_control:      GetControlFn,
+    pub(crate) write:            SerialWriteFn,
+    pub(crate) read:             SerialReadFn,
+    pub(crate) mode:             *const SerialIoMode,
+    // Revision 1.1+
+    pub(crate) device_type_guid: *const Guid,
+}
+
+impl Protocol for SerialIo {
+    const GUID: Guid = guid!(
+        0xbb25cf6f, 0xf1d4, 0x11d2,
+        {0x9a,0x0c,0x00,0x90,0x27,0x3f,0xc1,0xfd}
+    );
+}
+
+impl SerialIo {
+    pub const REVISION: u32 = 0x0001_0000;
+    pub const REVISION_1_1: u32 = 0x0001_0001;
+
+    pub fn reset(&mut self) -> Result<()> {
+        (self.reset)(self).to_result(())
+    }
+
+    /// Sets the device's attributes; zero, or the `DEFAULT` constants, select the default
+    pub fn set_attributes(
+        &mut self,
+        baud_rate: u64,
+        receive_fifo_depth: u32,
+        timeout: u32,
+        parity: Parity,
+        data_bits: u8,
+        stop_bits: StopBits,
+    ) -> Result<()> {
+        (self.set_attributes)(
+            self,
+            baud_rate,
+            receive_fifo_depth,
+            timeout,
+            parity,
+            data_bits,
+            stop_bits,
+        )
+        .to_result(())
+    }
+
+    pub fn set_control(&mut self, control: ControlBits) -> Result<()> {
+        (self.set_control)(self, control).to_result(())
+    }
+
+    pub fn get_control(&mut self) -> Result<ControlBits> {
+        let mut control = ControlBits::empty();
+        (self.get_control)(self, &mut control).to_result(control)
+    }
+
+    /// Writes as much of `buf` as possible, returning the number of bytes written
+    ///
+    /// Fails with `TIMEOUT` if nothing could be written in time.
+    pub fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let mut size = buf.len();
+        match (self.write)(self, &mut size, buf.as_ptr()) {
+            Status::TIMEOUT if size != 0 => Ok(size),
+            status => status.to_result(size),
+        }
+    }
+
+    /// Writes all of `buf`, failing with `TIMEOUT` if the device stops accepting data
+    pub fn write_all(&mut self, mut buf: &[u8]) -> Result<()> {
+        while !buf.is_empty() {
+            match self.write(buf)? {
+                0 => return Err(Status::TIMEOUT),
+                n => buf = &buf[n.min(buf.len())..],
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads into `buf`, returning the number of bytes read
+    ///
+    /// Fails with `TIMEOUT` if no data arrived in time.
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let mut size = buf.len();
+        match (self.read)(self, &mut size, buf.as_mut_ptr()) {
+            Status::TIMEOUT if size != 0 => Ok(size),
+            status => status.to_result(size),
+        }
+    }
+
+    pub fn mode(&self) -> &SerialIoMode {
+        unsafe { &*self.mode }
+    }
+
+    /// Returns the type of device, e.g. a terminal, if the firmware reports it
+    pub fn device_type(&self) -> Option<Guid> {
+        if self.revision < Self::REVISION_1_1 || self.device_type_guid.is_null() {
+            return None;
+        }
+        Some(unsafe { *self.device_type_guid })
+    }
+}
+
+impl fmt::Write for SerialIo {
+    /// Writes `s`, translating `\n` to `\r\n` for terminals
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for (i, line) in s.split('\n').enumerate() {
+            if i != 0 {
+                self.write_all(b"\r\n").map_err(|_| fmt::Error)?;
+            }
+            self.write_all(line.as_bytes()).map_err(|_| fmt::Error)?;
+        }
+        Ok(())
+    }
+}
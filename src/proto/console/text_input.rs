@@ -28,7 +28,9 @@
  * SPDX-License-Identifier: BSD-3-Clause
  */
 
-use crate::{guid, proto::Protocol, Event, Guid, Result, Status};
+use core::time::Duration;
+
+use crate::{boot_services, guid, proto::Protocol, table::TimerDelay, Event, Guid, Result, Status};
 
 pub type InputResetFn =
     extern "efiapi" fn(this: *mut SimpleTextInput, extended_verification: bool) -> Status;
@@ -40,15 +42,50 @@ pub type InputReadKeystrokeFn =
 #[derive(Clone, Copy, Debug, Default, Hash, Eq, PartialEq)]
 pub struct InputKey {
     pub scancode:  u16,
-    pub codepoint: u32,
+    pub codepoint: u16,
+}
+
+impl InputKey {
+    pub const SCAN_NULL: u16 = 0x00;
+    pub const SCAN_UP: u16 = 0x01;
+    pub const SCAN_DOWN: u16 = 0x02;
+    pub const SCAN_RIGHT: u16 = 0x03;
+    pub const SCAN_LEFT: u16 = 0x04;
+    pub const SCAN_HOME: u16 = 0x05;
+    pub const SCAN_END: u16 = 0x06;
+    pub const SCAN_INSERT: u16 = 0x07;
+    pub const SCAN_DELETE: u16 = 0x08;
+    pub const SCAN_PAGE_UP: u16 = 0x09;
+    pub const SCAN_PAGE_DOWN: u16 = 0x0a;
+    pub const SCAN_F1: u16 = 0x0b;
+    pub const SCAN_F2: u16 = 0x0c;
+    pub const SCAN_F3: u16 = 0x0d;
+    pub const SCAN_F4: u16 = 0x0e;
+    pub const SCAN_F5: u16 = 0x0f;
+    pub const SCAN_F6: u16 = 0x10;
+    pub const SCAN_F7: u16 = 0x11;
+    pub const SCAN_F8: u16 = 0x12;
+    pub const SCAN_F9: u16 = 0x13;
+    pub const SCAN_F10: u16 = 0x14;
+    pub const SCAN_F11: u16 = 0x15;
+    pub const SCAN_F12: u16 = 0x16;
+    pub const SCAN_ESC: u16 = 0x17;
+
+    /// Returns the key's character, if it has one
+    pub fn char(&self) -> Option<char> {
+        match self.codepoint {
+            0 => None,
+            c => char::from_u32(c as u32),
+        }
+    }
 }
 
 #[repr(C)]
 #[derive(Debug)]
 pub struct SimpleTextInput {
-    reset:          InputResetFn,
-    read_keystroke: InputReadKeystrokeFn,
-    wait_for_key:   Event,
+    pub(crate) reset:          InputResetFn,
+    pub(crate) read_keystroke: InputReadKeystrokeFn,
+    pub(crate) wait_for_key:   Event,
 }
 
 impl Protocol for SimpleTextInput {
@@ -69,4 +106,40 @@ impl SimpleTextInput {
         let mut key = InputKey::default();
         (self.read_keystroke)(self, &mut key).to_result(key)
     }
+
+    /// Returns the event signaled when a keystroke is available
+    pub fn wait_for_key(&self) -> Event {
+        self.wait_for_key
+    }
+
+    /// Waits up to `timeout` for a keystroke
+    ///
+    /// Returns `None` if the timeout expires first, e.g. for "press any key to enter setup"
+    /// prompts. A zero timeout only checks for a pending keystroke.
+    pub fn read_key_timeout(&mut self, timeout: Duration) -> Result<Option<InputKey>> {
+        let bs = boot_services();
+        let timer = bs.create_timer_event()?;
+        let result = self.read_key_until(timer, timeout);
+        let _ = bs.close_event(timer);
+        result
+    }
+
+    fn read_key_until(&mut self, timer: Event, timeout: Duration) -> Result<Option<InputKey>> {
+        let bs = boot_services();
+        let ticks = u64::try_from(timeout.as_nanos() / 100).unwrap_or(u64::MAX);
+        if ticks > 0 {
+            bs.set_timer(timer, TimerDelay::Relative, ticks)?;
+        }
+        loop {
+            match self.read_keystroke() {
+                Ok(key) => return Ok(Some(key)),
+                Err(Status::NOT_READY) if ticks == 0 => return Ok(None),
+                Err(Status::NOT_READY) => {}
+                Err(status) => return Err(status),
+            }
+            if bs.wait_for_event(&[self.wait_for_key, timer])? == 1 {
+                return Ok(None);
+            }
+        }
+    }
 }
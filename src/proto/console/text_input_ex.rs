@@ -0,0 +1,182 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Simple Text Input Ex Protocol
+//!
+//! Extends [`SimpleTextInput`](super::text_input::SimpleTextInput) with the state of the
+//! shift and toggle keys, and keystroke notifications.
+
+use core::ffi::c_void;
+
+use super::text_input::InputKey;
+use crate::{guid, proto::Protocol, Event, Guid, Result, Status};
+
+pub type InputResetExFn =
+    extern "efiapi" fn(this: *mut SimpleTextInputEx, extended_verification: bool) -> Status;
+
+pub type InputReadKeystrokeExFn =
+    extern "efiapi" fn(this: *mut SimpleTextInputEx, key_data: *mut KeyData) -> Status;
+
+pub type SetStateFn =
+    extern "efiapi" fn(this: *mut SimpleTextInputEx, toggle_state: *mut KeyToggleState) -> Status;
+
+pub type KeyNotifyFn = extern "efiapi" fn(key_data: *mut KeyData) -> Status;
+
+pub type RegisterKeyNotifyFn = extern "efiapi" fn(
+    this: *mut SimpleTextInputEx,
+    key_data: *mut KeyData,
+    notify_fn: KeyNotifyFn,
+    notify_handle: *mut *mut c_void,
+) -> Status;
+
+pub type UnregisterKeyNotifyFn =
+    extern "efiapi" fn(this: *mut SimpleTextInputEx, notify_handle: *mut c_void) -> Status;
+
+bitflags::bitflags! {
+    #[repr(transparent)]
+    #[derive(Default)]
+    pub struct KeyShiftState : u32 {
+        const RIGHT_SHIFT   = 0x00000001;
+        const LEFT_SHIFT    = 0x00000002;
+        const RIGHT_CONTROL = 0x00000004;
+        const LEFT_CONTROL  = 0x00000008;
+        const RIGHT_ALT     = 0x00000010;
+        const LEFT_ALT      = 0x00000020;
+        const RIGHT_LOGO    = 0x00000040;
+        const LEFT_LOGO     = 0x00000080;
+        const MENU_KEY      = 0x00000100;
+        const SYS_REQ       = 0x00000200;
+        /// The other bits are valid
+        const VALID         = 0x80000000;
+
+        const SHIFT   = Self::LEFT_SHIFT.bits | Self::RIGHT_SHIFT.bits;
+        const CONTROL = Self::LEFT_CONTROL.bits | Self::RIGHT_CONTROL.bits;
+        const ALT     = Self::LEFT_ALT.bits | Self::RIGHT_ALT.bits;
+    }
+}
+
+bitflags::bitflags! {
+    #[repr(transparent)]
+    #[derive(Default)]
+    pub struct KeyToggleState : u8 {
+        const SCROLL_LOCK = 0x01;
+        const NUM_LOCK    = 0x02;
+        const CAPS_LOCK   = 0x04;
+        /// Report keys which would otherwise be hidden, e.g. a lone shift key
+        const KEY_STATE_EXPOSED = 0x40;
+        /// The other bits are valid
+        const VALID       = 0x80;
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct KeyState {
+    pub shift_state:  KeyShiftState,
+    pub toggle_state: KeyToggleState,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct KeyData {
+    pub key:   InputKey,
+    pub state: KeyState,
+}
+
+impl KeyData {
+    /// Returns `true` if either control key was held, as far as the firmware reports
+    pub fn control(&self) -> bool {
+        let shift = self.state.shift_state;
+        shift.contains(KeyShiftState::VALID) && shift.intersects(KeyShiftState::CONTROL)
+    }
+
+    /// Returns `true` if either alt key was held, as far as the firmware reports
+    pub fn alt(&self) -> bool {
+        let shift = self.state.shift_state;
+        shift.contains(KeyShiftState::VALID) && shift.intersects(KeyShiftState::ALT)
+    }
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct SimpleTextInputEx {
+    pub(crate) reset:                 InputResetExFn,
+    pub(crate) read_keystroke_ex:     InputReadKeystrokeExFn,
+    pub(crate) wait_for_key_ex:       Event,
+    pub(crate) set_state:             SetStateFn,
+    pub(crate) register_key_notify:   RegisterKeyNotifyFn,
+    pub(crate) unregister_key_notify: UnregisterKeyNotifyFn,
+}
+
+impl Protocol for SimpleTextInputEx {
+    const GUID: Guid = guid!(
+        0xdd9e7534, 0x7762, 0x4698,
+        {0x8c,0x14,0xf5,0x85,0x17,0xa6,0x25,0xaa}
+    );
+}
+
+impl SimpleTextInputEx {
+    /// Reset the input device
+    pub fn reset(&mut self, extended_verification: bool) -> Result<()> {
+        (self.reset)(self, extended_verification).to_result(())
+    }
+
+    /// Read the next keystroke from the input device, along with the modifier state
+    pub fn read_keystroke_ex(&mut self) -> Result<KeyData> {
+        let mut key = KeyData::default();
+        (self.read_keystroke_ex)(self, &mut key).to_result(key)
+    }
+
+    /// Returns the event signaled when a keystroke is available
+    pub fn wait_for_key_ex(&self) -> Event {
+        self.wait_for_key_ex
+    }
+
+    /// Sets the state of the toggle keys (e.g. caps lock)
+    pub fn set_state(&mut self, mut state: KeyToggleState) -> Result<()> {
+        (self.set_state)(self, &mut state).to_result(())
+    }
+
+    /// Registers `notify_fn` to be called when a keystroke matching `key` is entered
+    ///
+    /// Returns a handle to pass to [`unregister_key_notify()`](Self::unregister_key_notify).
+    pub fn register_key_notify(
+        &mut self,
+        mut key: KeyData,
+        notify_fn: KeyNotifyFn,
+    ) -> Result<*mut c_void> {
+        let mut handle = core::ptr::null_mut();
+        (self.register_key_notify)(self, &mut key, notify_fn, &mut handle).to_result(handle)
+    }
+
+    pub fn unregister_key_notify(&mut self, handle: *mut c_void) -> Result<()> {
+        (self.unregister_key_notify)(self, handle).to_result(())
+    }
+}
@@ -30,7 +30,12 @@
 
 use core::fmt;
 
-use crate::{guid, proto::Protocol, Result, Status};
+use crate::{
+    guid,
+    proto::Protocol,
+    ucs2::{self, CStr16},
+    warning, Result, Status,
+};
 
 pub type ResetFn =
     extern "efiapi" fn(this: *mut SimpleTextOutput, extended_verification: bool) -> Status;
@@ -57,6 +62,7 @@ pub type SetCursorPositionFn =
 pub type EnableCursorFn = extern "efiapi" fn(this: *mut SimpleTextOutput, visible: bool) -> Status;
 
 #[repr(C)]
+#[derive(Clone, Copy, Debug)]
 pub struct SimpleTextOutputMode {
     pub max_mode:       i32,
     pub mode:           i32,
@@ -72,19 +78,25 @@ pub struct WindowSize {
     pub cols: usize,
 }
 
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct CursorPosition {
+    pub row: usize,
+    pub col: usize,
+}
+
 #[repr(C)]
 #[derive(Debug)]
 pub struct SimpleTextOutput {
-    reset:               ResetFn,
-    output_string:       StringFn,
-    test_string:         StringFn,
-    query_mode:          QueryModeFn,
-    set_mode:            SetModeFn,
-    set_attribute:       SetAttributeFn,
-    clear_screen:        ClearScreenFn,
-    set_cursor_position: SetCursorPositionFn,
-    enable_cursor:       EnableCursorFn,
-    mode:                *mut SimpleTextOutputMode,
+    pub(crate) reset:               ResetFn,
+    pub(crate) output_string:       StringFn,
+    pub(crate) test_string:         StringFn,
+    pub(crate) query_mode:          QueryModeFn,
+    pub(crate) set_mode:            SetModeFn,
+    pub(crate) set_attribute:       SetAttributeFn,
+    pub(crate) clear_screen:        ClearScreenFn,
+    pub(crate) set_cursor_position: SetCursorPositionFn,
+    pub(crate) enable_cursor:       EnableCursorFn,
+    pub(crate) mode:                *mut SimpleTextOutputMode,
 }
 
 impl Protocol for SimpleTextOutput {
@@ -109,22 +121,59 @@ impl SimpleTextOutput {
         status.to_result(())
     }
 
+    /// Writes a NUL-terminated string to the console
+    ///
+    /// Debug builds panic if `s` is not NUL-terminated; release builds return
+    /// `INVALID_PARAMETER`, as [`output_str_checked()`](Self::output_str_checked) does.
     pub fn output_string(&mut self, s: &[u16]) -> Result<()> {
+        debug_assert!(
+            check_null_terminated(s),
+            "output_string: string must be null terminated"
+        );
+        self.output_str_checked(s)
+    }
+
+    /// Writes a NUL-terminated string to the console, without panicking
+    ///
+    /// Returns `INVALID_PARAMETER` if `s` is not NUL-terminated. Warnings such as
+    /// `WARN_UNKNOWN_GLYPH` are [reported](crate::warning) rather than failing the output.
+    pub fn output_str_checked(&mut self, s: &[u16]) -> Result<()> {
         if !check_null_terminated(s) {
-            panic!("output_string: string must be null terminated");
+            return Err(Status::INVALID_PARAMETER);
         }
         let status = (self.output_string)(self, s.as_ptr().cast_mut());
-        status.to_result(())
+        warning::check(status, "OutputString")
     }
 
+    /// Writes a string to the console
+    ///
+    /// Warnings are [reported](crate::warning) rather than failing the output.
+    pub fn output_cstr16(&mut self, s: &CStr16) -> Result<()> {
+        let status = (self.output_string)(self, s.as_ptr().cast_mut());
+        warning::check(status, "OutputString")
+    }
+
+    /// Checks whether the console can display every character of a NUL-terminated string
+    ///
+    /// Debug builds panic if `s` is not NUL-terminated; release builds return
+    /// `INVALID_PARAMETER`.
     pub fn test_string(&mut self, s: &[u16]) -> Result<()> {
+        debug_assert!(
+            check_null_terminated(s),
+            "test_string: string must be null terminated"
+        );
         if !check_null_terminated(s) {
-            panic!("test_string: string must be null terminated");
+            return Err(Status::INVALID_PARAMETER);
         }
         let status = (self.test_string)(self, s.as_ptr().cast_mut());
         status.to_result(())
     }
 
+    pub fn test_cstr16(&mut self, s: &CStr16) -> Result<()> {
+        let status = (self.test_string)(self, s.as_ptr().cast_mut());
+        status.to_result(())
+    }
+
     pub fn query_mode(&mut self, mode: usize) -> Result<WindowSize> {
         let mut size = WindowSize::default();
         (self.query_mode)(self, mode, &mut size.cols, &mut size.rows).to_result(size)
@@ -145,27 +194,157 @@ impl SimpleTextOutput {
     pub fn enable_cursor(&mut self, visible: bool) -> Result<()> {
         (self.enable_cursor)(self, visible).to_result(())
     }
+
+    pub fn set_attribute(&mut self, attribute: usize) -> Result<()> {
+        (self.set_attribute)(self, attribute).to_result(())
+    }
+
+    /// Returns the console's current mode information
+    pub fn mode(&self) -> &SimpleTextOutputMode {
+        unsafe { &*self.mode }
+    }
+
+    /// Returns the number of the active text mode
+    pub fn current_mode(&self) -> usize {
+        self.mode().mode as usize
+    }
+
+    pub fn cursor_position(&self) -> CursorPosition {
+        let mode = self.mode();
+        CursorPosition {
+            row: mode.cursor_row as usize,
+            col: mode.cursor_column as usize,
+        }
+    }
+
+    /// Returns the size of the active text mode
+    pub fn window_size(&mut self) -> Result<WindowSize> {
+        self.query_mode(self.current_mode())
+    }
+
+    /// Calls `f`, then restores the cursor's position, visibility and the text attribute
+    ///
+    /// Restoring is best-effort, since not every console supports hiding the cursor.
+    pub fn with_saved_cursor<R>(&mut self, f: impl FnOnce(&mut Self) -> R) -> R {
+        let position = self.cursor_position();
+        let SimpleTextOutputMode {
+            attribute,
+            cursor_visible,
+            ..
+        } = *self.mode();
+
+        let result = f(self);
+
+        let _ = self.set_attribute(attribute as usize);
+        let _ = self.set_cursor_position(position.row, position.col);
+        let _ = self.enable_cursor(cursor_visible);
+        result
+    }
+}
+
+impl SimpleTextOutput {
+    /// Converts `s` in stack-sized chunks, translating `\n` to `\r\n` unless it already follows
+    /// a `\r`
+    ///
+    /// Each code unit is passed through `map` before being buffered, and each NUL-terminated
+    /// chunk is passed to `flush`.
+    fn write_chunked(
+        &mut self,
+        s: &str,
+        mut map: impl FnMut(&mut Self, u16) -> u16,
+        mut flush: impl FnMut(&mut Self, &[u16]) -> Result<()>,
+    ) -> Result<()> {
+        let mut buf = [0u16; 128];
+        let (mut len, mut prev) = (0, 0);
+        for unit in ucs2::encode_lossy(s) {
+            // Leave room for a `\r\n` pair and the terminator.
+            if len + 3 > buf.len() {
+                buf[len] = 0;
+                flush(self, &buf[..=len])?;
+                len = 0;
+            }
+            if unit == b'\n' as u16 && prev != b'\r' as u16 {
+                buf[len] = b'\r' as u16;
+                len += 1;
+            }
+            buf[len] = map(self, unit);
+            len += 1;
+            prev = unit;
+        }
+        if len > 0 {
+            buf[len] = 0;
+            flush(self, &buf[..=len])?;
+        }
+        Ok(())
+    }
 }
 
-#[cfg(feature = "alloc")]
 impl fmt::Write for SimpleTextOutput {
+    /// Writes `s` to the console, translating a bare `\n` to `\r\n`
+    ///
+    /// The string is converted in chunks on the stack, so each call makes as few firmware
+    /// calls as possible without allocating.
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        use alloc::vec::Vec;
+        self.write_chunked(s, |_, unit| unit, Self::output_string)
+            .map_err(|_| fmt::Error)
+    }
+}
 
-        let s = s
-            .encode_utf16()
-            .chain(core::iter::once(0))
-            .collect::<Vec<_>>();
-        self.output_string(&s).map_err(|_| fmt::Error)
+/// Console writer which tolerates gaps in the firmware's font
+///
+/// Like the `fmt::Write` impl of [`SimpleTextOutput`], a bare `\n` is translated to `\r\n`. In
+/// addition, non-ASCII characters the firmware reports it cannot render are replaced before
+/// output, and [`WARN_UNKNOWN_GLYPH`](Status::WARN_UNKNOWN_GLYPH) is treated as success, so
+/// logging never fails on an unusual character.
+pub struct ConsoleWriter<'a> {
+    out:         &'a mut SimpleTextOutput,
+    replacement: u16,
+}
+
+impl<'a> ConsoleWriter<'a> {
+    pub fn new(out: &'a mut SimpleTextOutput) -> Self {
+        Self {
+            out,
+            replacement: b'?' as u16,
+        }
+    }
+
+    /// Sets the character substituted for unsupported glyphs (`?` by default)
+    ///
+    /// Characters outside the Basic Multilingual Plane cannot be used and are ignored.
+    pub fn with_replacement(mut self, c: char) -> Self {
+        if let Some(unit) = ucs2::encode_char(c) {
+            self.replacement = unit;
+        }
+        self
+    }
+
+    pub fn inner(&mut self) -> &mut SimpleTextOutput {
+        self.out
+    }
+
+    /// Writes `s`, substituting any characters the console cannot display
+    pub fn write_str_lossy(&mut self, s: &str) -> Result<()> {
+        let replacement = self.replacement;
+        self.out.write_chunked(
+            s,
+            |out, unit| {
+                if unit < 0x80 || out.test_string(&[unit, 0]).is_ok() {
+                    unit
+                } else {
+                    replacement
+                }
+            },
+            |out, chunk| match (out.output_string)(out, chunk.as_ptr().cast_mut()) {
+                Status::SUCCESS | Status::WARN_UNKNOWN_GLYPH => Ok(()),
+                status => Err(status),
+            },
+        )
     }
 }
 
-#[cfg(not(feature = "alloc"))]
-impl fmt::Write for SimpleTextOutput {
+impl fmt::Write for ConsoleWriter<'_> {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        for char in s.encode_utf16() {
-            self.output_string(&[char, 0]).map_err(|_| fmt::Error)?;
-        }
-        Ok(())
+        self.write_str_lossy(s).map_err(|_| fmt::Error)
     }
 }
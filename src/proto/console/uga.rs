@@ -0,0 +1,147 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Universal Graphics Adapter Draw Protocol
+//!
+//! The predecessor of the [Graphics Output Protocol](super::gop), still the only graphics
+//! protocol on some pre-GOP Apple firmware. Unlike GOP, it never exposes the framebuffer;
+//! all drawing goes through [`Blt`].
+
+use super::gop::{Blt, BltOperation, BltPixel};
+use crate::{guid, proto::Protocol, Guid, Result, Status};
+
+/// Identical in layout to [`BltPixel`]
+pub type UgaPixel = BltPixel;
+
+/// Identical in values to [`BltOperation`]
+pub type UgaBltOperation = BltOperation;
+
+pub type GetModeFn = extern "efiapi" fn(
+    this: *mut UgaDraw,
+    horizontal_resolution: *mut u32,
+    vertical_resolution: *mut u32,
+    color_depth: *mut u32,
+    refresh_rate: *mut u32,
+) -> Status;
+
+pub type SetModeFn = extern "efiapi" fn(
+    this: *mut UgaDraw,
+    horizontal_resolution: u32,
+    vertical_resolution: u32,
+    color_depth: u32,
+    refresh_rate: u32,
+) -> Status;
+
+pub type BltFn = extern "efiapi" fn(
+    this: *mut UgaDraw,
+    buffer: *mut UgaPixel,
+    operation: UgaBltOperation,
+    source_x: usize,
+    source_y: usize,
+    destination_x: usize,
+    destination_y: usize,
+    width: usize,
+    height: usize,
+    delta: usize,
+) -> Status;
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct UgaMode {
+    pub horizontal_resolution: u32,
+    pub vertical_resolution:   u32,
+    pub color_depth:           u32,
+    pub refresh_rate:          u32,
+}
+
+#[repr(C)]
+pub struct UgaDraw {
+    get_mode: GetModeFn,
+    set_mode: SetModeFn,
+    blt:      BltFn,
+}
+
+impl Protocol for UgaDraw {
+    const GUID: Guid = guid!(
+        0x982c298b, 0xf4fa, 0x41cb,
+        {0xb8,0x38,0x77,0xaa,0x68,0x8f,0xb8,0x39}
+    );
+}
+
+impl UgaDraw {
+    pub fn get_mode(&mut self) -> Result<UgaMode> {
+        let mut mode = UgaMode::default();
+        (self.get_mode)(
+            self,
+            &mut mode.horizontal_resolution,
+            &mut mode.vertical_resolution,
+            &mut mode.color_depth,
+            &mut mode.refresh_rate,
+        )
+        .to_result(mode)
+    }
+
+    pub fn set_mode(&mut self, mode: UgaMode) -> Result<()> {
+        (self.set_mode)(
+            self,
+            mode.horizontal_resolution,
+            mode.vertical_resolution,
+            mode.color_depth,
+            mode.refresh_rate,
+        )
+        .to_result(())
+    }
+}
+
+impl Blt for UgaDraw {
+    unsafe fn blt(
+        &mut self,
+        buffer: *mut UgaPixel,
+        operation: UgaBltOperation,
+        source: (usize, usize),
+        destination: (usize, usize),
+        width: usize,
+        height: usize,
+        delta: usize,
+    ) -> Result<()> {
+        (self.blt)(
+            self,
+            buffer,
+            operation,
+            source.0,
+            source.1,
+            destination.0,
+            destination.1,
+            width,
+            height,
+            delta,
+        )
+        .to_result(())
+    }
+}
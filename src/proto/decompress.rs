@@ -0,0 +1,144 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Decompress Protocol
+//!
+//! Decompresses data in the EFI 1.1 compression format (also known as "Tiano"), used for
+//! compressed sections in firmware volumes.
+
+use core::ffi::c_void;
+
+use super::{Proto, Protocol};
+use crate::{boot_services, default_memory_type, guid, table::PoolSlice, Guid, Result, Status};
+
+pub type GetInfoFn = extern "efiapi" fn(
+    this: *mut Decompress,
+    source: *const c_void,
+    source_size: u32,
+    destination_size: *mut u32,
+    scratch_size: *mut u32,
+) -> Status;
+
+pub type DecompressFn = extern "efiapi" fn(
+    this: *mut Decompress,
+    source: *const c_void,
+    source_size: u32,
+    destination: *mut c_void,
+    destination_size: u32,
+    scratch: *mut c_void,
+    scratch_size: u32,
+) -> Status;
+
+#[repr(C)]
+pub struct Decompress {
+    get_info:   GetInfoFn,
+    decompress: DecompressFn,
+}
+
+impl Protocol for Decompress {
+    const GUID: Guid = guid!(
+        0xd8117cfe,0x94a6,0x11d4,
+        {0x9a,0x3a,0x00,0x90,0x27,0x3f,0xc1,0x4d}
+    );
+}
+
+/// Buffer sizes needed to decompress a particular input
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct DecompressInfo {
+    /// Size of the decompressed data
+    pub destination_size: usize,
+    /// Size of the scratch buffer used while decompressing
+    pub scratch_size:     usize,
+}
+
+impl Proto<Decompress> {
+    /// Reads the buffer sizes needed to decompress `src` from its header
+    pub fn get_info(&mut self, src: &[u8]) -> Result<DecompressInfo> {
+        let src_size = u32::try_from(src.len()).map_err(|_| Status::INVALID_PARAMETER)?;
+        let (mut destination_size, mut scratch_size) = (0, 0);
+        (self.get_info)(
+            self.as_ptr(),
+            src.as_ptr().cast(),
+            src_size,
+            &mut destination_size,
+            &mut scratch_size,
+        )
+        .to_result(DecompressInfo {
+            destination_size: destination_size as usize,
+            scratch_size:     scratch_size as usize,
+        })
+    }
+
+    /// Decompresses `src` into `dst`
+    ///
+    /// `dst` and `scratch` must be at least as large as reported by
+    /// [`get_info()`](Self::get_info).
+    pub fn decompress(&mut self, src: &[u8], dst: &mut [u8], scratch: &mut [u8]) -> Result<()> {
+        let size = |buf: &[u8]| u32::try_from(buf.len()).map_err(|_| Status::INVALID_PARAMETER);
+        (self.decompress)(
+            self.as_ptr(),
+            src.as_ptr().cast(),
+            size(src)?,
+            dst.as_mut_ptr().cast(),
+            size(dst)?,
+            scratch.as_mut_ptr().cast(),
+            size(scratch)?,
+        )
+        .to_result(())
+    }
+    /// Decompresses `src` into a pool allocation of the size recorded in its header
+    ///
+    /// The scratch buffer is allocated and freed internally.
+    pub fn decompress_to_pool(&mut self, src: &[u8]) -> Result<PoolSlice<u8>> {
+        let info = self.get_info(src)?;
+        let bs = boot_services();
+        let alloc = |size: usize| bs.allocate_pool(default_memory_type(), size.max(1));
+
+        let dst = alloc(info.destination_size)?;
+        let result = alloc(info.scratch_size).and_then(|scratch| {
+            let result = unsafe {
+                self.decompress(
+                    src,
+                    core::slice::from_raw_parts_mut(dst, info.destination_size),
+                    core::slice::from_raw_parts_mut(scratch, info.scratch_size),
+                )
+            };
+            let _ = unsafe { bs.free_pool(scratch) };
+            result
+        });
+        match result {
+            Ok(()) => Ok(unsafe { PoolSlice::from_raw(dst, info.destination_size) }),
+            Err(status) => {
+                let _ = unsafe { bs.free_pool(dst) };
+                Err(status)
+            }
+        }
+    }
+}
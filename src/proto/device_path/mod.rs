@@ -0,0 +1,249 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Device Path Protocol
+//!
+//! A device path is a packed sequence of variable-length nodes, each starting with a 4-byte
+//! header, and terminated by an End Entire node. Paths can be formatted with [`fmt::Display`],
+//! which follows the text representation from the UEFI specification (`PciRoot(0x0)/Pci(0x1,0x0)`).
+
+use core::{fmt, marker::PhantomData, mem::size_of, slice};
+
+use super::Protocol;
+use crate::{guid, le, Guid, Result, Status};
+
+mod text;
+
+pub use text::{register_formatter, FormatNodeFn};
+
+#[repr(C, packed)]
+pub struct DevicePath {
+    pub kind:     DeviceType,
+    pub sub_kind: u8,
+    pub length:   [u8; 2],
+}
+
+impl Protocol for DevicePath {
+    const GUID: Guid = guid!(
+        0x09576e91,0x6d3f,0x11d2,
+        {0x8e,0x39,0x00,0xa0,0xc9,0x69,0x72,0x3b}
+    );
+}
+
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct DeviceType(pub u8);
+
+impl DeviceType {
+    pub const HARDWARE: Self = Self(0x01);
+    pub const ACPI: Self = Self(0x02);
+    pub const MESSAGING: Self = Self(0x03);
+    pub const MEDIA: Self = Self(0x04);
+    pub const BBS: Self = Self(0x05);
+    pub const END: Self = Self(0x7f);
+}
+
+/// Sub-type of an End node which terminates the whole path
+pub const END_ENTIRE: u8 = 0xff;
+/// Sub-type of an End node which separates instances of a multi-instance path
+pub const END_INSTANCE: u8 = 0x01;
+
+const HEADER_SIZE: usize = size_of::<DevicePath>();
+
+impl DevicePath {
+    /// Validates `bytes` as a device path and returns a reference to it
+    ///
+    /// Every node must fit within the buffer and the path must be terminated by an End Entire
+    /// node; trailing bytes after it are ignored.
+    pub fn from_bytes(bytes: &[u8]) -> Result<&DevicePath> {
+        let mut offset = 0;
+        loop {
+            let header = bytes
+                .get(offset..offset + HEADER_SIZE)
+                .ok_or(Status::INVALID_PARAMETER)?;
+            let len = u16::from_le_bytes([header[2], header[3]]) as usize;
+            if len < HEADER_SIZE || offset + len > bytes.len() {
+                return Err(Status::INVALID_PARAMETER);
+            }
+            if header[0] == DeviceType::END.0 && header[1] == END_ENTIRE {
+                return Ok(unsafe { &*bytes.as_ptr().cast() });
+            }
+            offset += len;
+        }
+    }
+
+    /// Returns the length of this node, including its header
+    pub fn node_len(&self) -> usize {
+        u16::from_le_bytes(self.length) as usize
+    }
+
+    /// Returns an iterator over the nodes of the path, excluding the terminating End Entire node
+    pub fn nodes(&self) -> Nodes<'_> {
+        Nodes {
+            ptr:     (self as *const Self).cast(),
+            done:    false,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the size of the whole path in bytes, including the End Entire node
+    pub fn size(&self) -> usize {
+        self.nodes().map(|node| node.len()).sum::<usize>() + HEADER_SIZE
+    }
+
+    /// Returns the whole path as bytes, including the End Entire node
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts((self as *const Self).cast(), self.size()) }
+    }
+}
+
+impl fmt::Display for DevicePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut separator = None;
+        for node in self.nodes() {
+            if node.is_end_instance() {
+                separator = Some(',');
+                continue;
+            }
+            if let Some(c) = separator {
+                fmt::Write::write_char(f, c)?;
+            }
+            write!(f, "{node}")?;
+            separator = Some('/');
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for DevicePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DevicePath({self})")
+    }
+}
+
+/// Iterator over the nodes of a [`DevicePath`]
+pub struct Nodes<'a> {
+    ptr:     *const u8,
+    done:    bool,
+    _marker: PhantomData<&'a DevicePath>,
+}
+
+impl<'a> Iterator for Nodes<'a> {
+    type Item = DevicePathNode<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let header = unsafe { &*self.ptr.cast::<DevicePath>() };
+        let len = header.node_len();
+        // A malformed length would otherwise loop forever or walk backwards.
+        if (header.kind == DeviceType::END && header.sub_kind == END_ENTIRE) || len < HEADER_SIZE {
+            self.done = true;
+            return None;
+        }
+        let data = unsafe { slice::from_raw_parts(self.ptr.add(HEADER_SIZE), len - HEADER_SIZE) };
+        self.ptr = unsafe { self.ptr.add(len) };
+        Some(DevicePathNode {
+            kind: header.kind,
+            sub_kind: header.sub_kind,
+            data,
+        })
+    }
+}
+
+/// A single node of a device path
+#[derive(Clone, Copy, Debug)]
+pub struct DevicePathNode<'a> {
+    pub kind:     DeviceType,
+    pub sub_kind: u8,
+    /// Node-specific data following the header
+    pub data:     &'a [u8],
+}
+
+impl<'a> DevicePathNode<'a> {
+    /// Returns the length of the node, including its header
+    pub fn len(&self) -> usize {
+        HEADER_SIZE + self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn is_end_instance(&self) -> bool {
+        self.kind == DeviceType::END && self.sub_kind == END_INSTANCE
+    }
+
+    /// Returns the vendor GUID if this is a Hardware, Messaging or Media vendor node
+    pub fn vendor_guid(&self) -> Option<Guid> {
+        match (self.kind, self.sub_kind) {
+            (DeviceType::HARDWARE, 0x04)
+            | (DeviceType::MESSAGING, 0x0a)
+            | (DeviceType::MEDIA, 0x03) => self.read_guid(0),
+            _ => None,
+        }
+    }
+
+    /// Returns the vendor-defined data following the GUID of a vendor node
+    pub fn vendor_data(&self) -> Option<&'a [u8]> {
+        self.vendor_guid().map(|_| &self.data[16..])
+    }
+
+    pub fn read_u8(&self, offset: usize) -> Option<u8> {
+        self.data.get(offset).copied()
+    }
+
+    pub fn read_u16(&self, offset: usize) -> Option<u16> {
+        self.read_array(offset).map(u16::from_le_bytes)
+    }
+
+    pub fn read_u32(&self, offset: usize) -> Option<u32> {
+        self.read_array(offset).map(u32::from_le_bytes)
+    }
+
+    pub fn read_u64(&self, offset: usize) -> Option<u64> {
+        self.read_array(offset).map(u64::from_le_bytes)
+    }
+
+    pub fn read_guid(&self, offset: usize) -> Option<Guid> {
+        self.read_array(offset).map(Guid::from_bytes)
+    }
+
+    pub fn read_array<const N: usize>(&self, offset: usize) -> Option<[u8; N]> {
+        le::read_array(self.data, offset)
+    }
+}
+
+impl fmt::Display for DevicePathNode<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        text::format_node(self, f)
+    }
+}
@@ -0,0 +1,436 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Text representation of device path nodes
+//!
+//! The built-in renderers cover the nodes commonly found on PC and virtual platforms, using the
+//! formats from the "Device Path Nodes" table of the UEFI specification. Nodes that cannot be
+//! decoded fall back to the generic `Path(Type,SubType,Data)` form. Custom pretty-printers can be
+//! registered with [`register_formatter`] and take priority over the built-in ones.
+
+use core::fmt;
+
+use super::{DevicePathNode, DeviceType, END_INSTANCE};
+use crate::{guid, sync::TryLock, Guid, Result, Status};
+
+/// Renders a single node, without any separator
+pub type FormatNodeFn = fn(node: &DevicePathNode<'_>, f: &mut fmt::Formatter<'_>) -> fmt::Result;
+
+const MAX_FORMATTERS: usize = 16;
+
+#[derive(Clone, Copy)]
+struct Entry {
+    kind:     DeviceType,
+    sub_kind: u8,
+    vendor:   Option<Guid>,
+    format:   FormatNodeFn,
+}
+
+static REGISTRY: TryLock<[Option<Entry>; MAX_FORMATTERS]> = TryLock::new([None; MAX_FORMATTERS]);
+
+/// Registers a pretty-printer for nodes of the given type and sub-type
+///
+/// If `vendor` is given, the formatter only applies to vendor nodes with that GUID and takes
+/// priority over a formatter registered for the sub-type as a whole. Registering the same key
+/// twice replaces the previous formatter.
+///
+/// Fails with `OUT_OF_RESOURCES` if the registry is full, or `NOT_READY` if it is being accessed
+/// by interrupted code.
+pub fn register_formatter(
+    kind: DeviceType,
+    sub_kind: u8,
+    vendor: Option<Guid>,
+    format: FormatNodeFn,
+) -> Result<()> {
+    let entry = Entry {
+        kind,
+        sub_kind,
+        vendor,
+        format,
+    };
+    REGISTRY
+        .try_with(|entries| {
+            let slot = entries
+                .iter()
+                .position(|e| {
+                    e.is_some_and(|e| {
+                        e.kind == kind && e.sub_kind == sub_kind && e.vendor == vendor
+                    })
+                })
+                .or_else(|| entries.iter().position(Option::is_none))
+                .ok_or(Status::OUT_OF_RESOURCES)?;
+            entries[slot] = Some(entry);
+            Ok(())
+        })
+        .unwrap_or(Err(Status::NOT_READY))
+}
+
+fn lookup(node: &DevicePathNode<'_>) -> Option<FormatNodeFn> {
+    let vendor = node.vendor_guid();
+    REGISTRY
+        .try_with(|entries| {
+            let mut generic = None;
+            for e in entries.iter().flatten() {
+                if e.kind != node.kind || e.sub_kind != node.sub_kind {
+                    continue;
+                }
+                match e.vendor {
+                    None => generic = Some(e.format),
+                    Some(guid) if Some(guid) == vendor => return Some(e.format),
+                    Some(_) => {}
+                }
+            }
+            generic
+        })
+        .flatten()
+}
+
+pub(super) fn format_node(node: &DevicePathNode<'_>, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    // The formatter is copied out so that it may itself format nested paths.
+    if let Some(format) = lookup(node) {
+        return format(node, f);
+    }
+    match builtin(node, f) {
+        Some(result) => result,
+        None => {
+            write!(
+                f,
+                "Path({},{},{})",
+                node.kind.0,
+                node.sub_kind,
+                Hex(node.data)
+            )
+        }
+    }
+}
+
+const PC_ANSI_GUID: Guid = guid!(
+    0xe0c14753,0xf9be,0x11d2,
+    {0x9a,0x0c,0x00,0x90,0x27,0x3f,0xc1,0x4d}
+);
+const VT100_GUID: Guid = guid!(
+    0xdfa66065,0xb419,0x11d3,
+    {0x9a,0x2d,0x00,0x90,0x27,0x3f,0xc1,0x4d}
+);
+const VT100_PLUS_GUID: Guid = guid!(
+    0x7baec70b,0x57e0,0x4c76,
+    {0x8e,0x87,0x2f,0x9e,0x28,0x08,0x83,0x43}
+);
+const VT_UTF8_GUID: Guid = guid!(
+    0xad15a0d6,0x8bec,0x4acf,
+    {0xa0,0x73,0xd0,0x1d,0xe7,0x7e,0x2d,0x88}
+);
+const UART_FLOW_CONTROL_GUID: Guid = guid!(
+    0x37499a9d,0x542f,0x4c89,
+    {0xa0,0x26,0x35,0xda,0x14,0x20,0x94,0xe4}
+);
+
+/// Renders a node in its specification format, or returns `None` if it is unknown or truncated
+fn builtin(n: &DevicePathNode<'_>, f: &mut fmt::Formatter<'_>) -> Option<fmt::Result> {
+    Some(match (n.kind, n.sub_kind) {
+        (DeviceType::END, END_INSTANCE) => f.write_str(","),
+
+        (DeviceType::HARDWARE, 0x01) => {
+            let (function, device) = (n.read_u8(0)?, n.read_u8(1)?);
+            write!(f, "Pci({device:#x},{function:#x})")
+        }
+        (DeviceType::HARDWARE, 0x02) => write!(f, "PcCard({:#x})", n.read_u8(0)?),
+        (DeviceType::HARDWARE, 0x03) => {
+            let (kind, start, end) = (n.read_u32(0)?, n.read_u64(4)?, n.read_u64(12)?);
+            write!(f, "MemoryMapped({kind:#x},{start:#x},{end:#x})")
+        }
+        (DeviceType::HARDWARE, 0x04) => vendor(f, "VenHw", n.vendor_guid()?, &n.data[16..]),
+        (DeviceType::HARDWARE, 0x05) => write!(f, "Ctrl({:#x})", n.read_u32(0)?),
+        (DeviceType::HARDWARE, 0x06) => {
+            let (kind, address) = (n.read_u8(0)?, n.read_u64(1)?);
+            write!(f, "BMC({kind:#x},{address:#x})")
+        }
+
+        (DeviceType::ACPI, 0x01) => {
+            let (hid, uid) = (n.read_u32(0)?, n.read_u32(4)?);
+            match hid {
+                0x0a0341d0 => write!(f, "PciRoot({uid:#x})"),
+                0x0a0841d0 => write!(f, "PcieRoot({uid:#x})"),
+                _ => write!(f, "Acpi({},{uid:#x})", EisaId(hid)),
+            }
+        }
+        (DeviceType::ACPI, 0x03) => write!(f, "AcpiAdr({:#x})", n.read_u32(0)?),
+
+        (DeviceType::MESSAGING, 0x01) => {
+            let (primary, slave, lun) = (n.read_u8(0)?, n.read_u8(1)?, n.read_u16(2)?);
+            let primary = if primary == 0 { "Primary" } else { "Secondary" };
+            let slave = if slave == 0 { "Master" } else { "Slave" };
+            write!(f, "Ata({primary},{slave},{lun:#x})")
+        }
+        (DeviceType::MESSAGING, 0x02) => {
+            write!(f, "Scsi({:#x},{:#x})", n.read_u16(0)?, n.read_u16(2)?)
+        }
+        (DeviceType::MESSAGING, 0x03) => {
+            write!(f, "Fibre({:#x},{:#x})", n.read_u64(4)?, n.read_u64(12)?)
+        }
+        (DeviceType::MESSAGING, 0x05) => {
+            write!(f, "USB({:#x},{:#x})", n.read_u8(0)?, n.read_u8(1)?)
+        }
+        (DeviceType::MESSAGING, 0x09) => write!(f, "I2O({:#x})", n.read_u32(0)?),
+        (DeviceType::MESSAGING, 0x0a) => {
+            let data = &n.data[16..];
+            match n.vendor_guid()? {
+                PC_ANSI_GUID => f.write_str("VenPcAnsi()"),
+                VT100_GUID => f.write_str("VenVt100()"),
+                VT100_PLUS_GUID => f.write_str("VenVt100Plus()"),
+                VT_UTF8_GUID => f.write_str("VenUtf8()"),
+                UART_FLOW_CONTROL_GUID => match n.read_u32(16)? & 0x3 {
+                    0 => f.write_str("UartFlowCtrl(None)"),
+                    1 => f.write_str("UartFlowCtrl(Hardware)"),
+                    2 => f.write_str("UartFlowCtrl(XonXoff)"),
+                    _ => return None,
+                },
+                guid => vendor(f, "VenMsg", guid, data),
+            }
+        }
+        (DeviceType::MESSAGING, 0x0b) => {
+            let (address, if_type) = (n.data.get(..32)?, n.read_u8(32)?);
+            let len = if if_type <= 1 { 6 } else { 32 };
+            write!(f, "MAC({},{if_type:#x})", Hex(&address[..len]))
+        }
+        (DeviceType::MESSAGING, 0x0c) => {
+            let (local, remote): ([u8; 4], [u8; 4]) = (n.read_array(0)?, n.read_array(4)?);
+            let (protocol, is_static) = (n.read_u16(12)?, n.read_u8(14)?);
+            let origin = if is_static != 0 { "Static" } else { "DHCP" };
+            let (remote, local, protocol) = (Ipv4(remote), Ipv4(local), IpProtocol(protocol));
+            write!(f, "IPv4({remote},{protocol},{origin},{local})")
+        }
+        (DeviceType::MESSAGING, 0x0d) => {
+            let (local, remote): ([u8; 16], [u8; 16]) = (n.read_array(0)?, n.read_array(16)?);
+            let (protocol, origin) = (n.read_u16(36)?, n.read_u8(38)?);
+            let origin = match origin {
+                0 => "Static",
+                1 => "StatelessAutoConfigure",
+                _ => "StatefulAutoConfigure",
+            };
+            let (remote, local, protocol) = (Ipv6(remote), Ipv6(local), IpProtocol(protocol));
+            write!(f, "IPv6({remote},{protocol},{origin},{local})")
+        }
+        (DeviceType::MESSAGING, 0x0e) => {
+            let (baud, data_bits) = (n.read_u64(4)?, n.read_u8(12)?);
+            let parity = match n.read_u8(13)? {
+                0 => 'D',
+                1 => 'N',
+                2 => 'E',
+                3 => 'O',
+                4 => 'M',
+                5 => 'S',
+                _ => return None,
+            };
+            let stop_bits = match n.read_u8(14)? {
+                0 => "D",
+                1 => "1",
+                2 => "1.5",
+                3 => "2",
+                _ => return None,
+            };
+            write!(f, "Uart({baud},{data_bits},{parity},{stop_bits})")
+        }
+        (DeviceType::MESSAGING, 0x0f) => {
+            let (vid, pid) = (n.read_u16(0)?, n.read_u16(2)?);
+            let (class, subclass, protocol) = (n.read_u8(4)?, n.read_u8(5)?, n.read_u8(6)?);
+            write!(
+                f,
+                "UsbClass({vid:#x},{pid:#x},{class:#x},{subclass:#x},{protocol:#x})"
+            )
+        }
+        (DeviceType::MESSAGING, 0x11) => write!(f, "Unit({:#x})", n.read_u8(0)?),
+        (DeviceType::MESSAGING, 0x12) => {
+            let (hba, pm, lun) = (n.read_u16(0)?, n.read_u16(2)?, n.read_u16(4)?);
+            write!(f, "Sata({hba:#x},{pm:#x},{lun:#x})")
+        }
+        (DeviceType::MESSAGING, 0x14) => write!(f, "Vlan({})", n.read_u16(0)?),
+        (DeviceType::MESSAGING, 0x17) => {
+            let (nsid, eui): (u32, [u8; 8]) = (n.read_u32(0)?, n.read_array(4)?);
+            write!(f, "NVMe({nsid:#x},{})", Eui64(eui))
+        }
+        (DeviceType::MESSAGING, 0x18) => write!(f, "Uri({})", Ascii(n.data)),
+        (DeviceType::MESSAGING, 0x1a) => write!(f, "SD({:#x})", n.read_u8(0)?),
+        (DeviceType::MESSAGING, 0x1b) => {
+            let address: [u8; 6] = n.read_array(0)?;
+            write!(f, "Bluetooth({})", Hex(&address))
+        }
+        (DeviceType::MESSAGING, 0x1c) => {
+            write!(f, "Wi-Fi({})", Ascii(n.data.get(..32)?))
+        }
+        (DeviceType::MESSAGING, 0x1d) => write!(f, "eMMC({:#x})", n.read_u8(0)?),
+
+        (DeviceType::MEDIA, 0x01) => {
+            let (number, start, size) = (n.read_u32(0)?, n.read_u64(4)?, n.read_u64(12)?);
+            let signature: [u8; 16] = n.read_array(20)?;
+            match n.read_u8(37)? {
+                0x01 => {
+                    let mbr = u32::from_le_bytes([
+                        signature[0],
+                        signature[1],
+                        signature[2],
+                        signature[3],
+                    ]);
+                    write!(f, "HD({number},MBR,{mbr:#010x},{start:#x},{size:#x})")
+                }
+                0x02 => write!(
+                    f,
+                    "HD({number},GPT,{},{start:#x},{size:#x})",
+                    Guid::from_bytes(signature)
+                ),
+                kind => write!(f, "HD({number},{kind},0,{start:#x},{size:#x})"),
+            }
+        }
+        (DeviceType::MEDIA, 0x02) => {
+            let (entry, start, size) = (n.read_u32(0)?, n.read_u64(4)?, n.read_u64(12)?);
+            write!(f, "CDROM({entry:#x},{start:#x},{size:#x})")
+        }
+        (DeviceType::MEDIA, 0x03) => vendor(f, "VenMedia", n.vendor_guid()?, &n.data[16..]),
+        (DeviceType::MEDIA, 0x04) => {
+            // The path is UCS-2, but the node gives no alignment guarantee.
+            let units = n
+                .data
+                .chunks_exact(2)
+                .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                .take_while(|&c| c != 0);
+            char::decode_utf16(units).try_for_each(|c| {
+                fmt::Write::write_char(f, c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            })
+        }
+        (DeviceType::MEDIA, 0x05) => write!(f, "Media({})", n.read_guid(0)?),
+        (DeviceType::MEDIA, 0x06) => write!(f, "FvFile({})", n.read_guid(0)?),
+        (DeviceType::MEDIA, 0x07) => write!(f, "Fv({})", n.read_guid(0)?),
+        (DeviceType::MEDIA, 0x08) => {
+            write!(f, "Offset({:#x},{:#x})", n.read_u64(4)?, n.read_u64(12)?)
+        }
+        (DeviceType::MEDIA, 0x09) => {
+            let (start, end) = (n.read_u64(0)?, n.read_u64(8)?);
+            let (kind, instance) = (n.read_guid(16)?, n.read_u16(32)?);
+            write!(f, "RamDisk({start:#x},{end:#x},{instance},{kind})")
+        }
+
+        (DeviceType::BBS, 0x01) => {
+            let (kind, flags) = (n.read_u16(0)?, n.read_u16(2)?);
+            let description = Ascii(n.data.get(4..)?);
+            write!(f, "BBS({kind:#x},{description},{flags:#x})")
+        }
+
+        _ => return None,
+    })
+}
+
+fn vendor(f: &mut fmt::Formatter<'_>, name: &str, guid: Guid, data: &[u8]) -> fmt::Result {
+    if data.is_empty() {
+        write!(f, "{name}({guid})")
+    } else {
+        write!(f, "{name}({guid},{})", Hex(data))
+    }
+}
+
+/// Bytes as a run of lowercase hex digits
+struct Hex<'a>(&'a [u8]);
+
+impl fmt::Display for Hex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|b| write!(f, "{b:02x}"))
+    }
+}
+
+/// NUL-terminated (or unterminated) ASCII string
+struct Ascii<'a>(&'a [u8]);
+
+impl fmt::Display for Ascii<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0
+            .iter()
+            .take_while(|&&b| b != 0)
+            .try_for_each(|&b| fmt::Write::write_char(f, b as char))
+    }
+}
+
+struct IpProtocol(u16);
+
+impl fmt::Display for IpProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            6 => f.write_str("TCP"),
+            17 => f.write_str("UDP"),
+            protocol => write!(f, "{protocol:#x}"),
+        }
+    }
+}
+
+/// EUI-64, stored least significant byte first
+struct Eui64([u8; 8]);
+
+impl fmt::Display for Eui64 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, b) in self.0.iter().rev().enumerate() {
+            let sep = if i == 0 { "" } else { "-" };
+            write!(f, "{sep}{b:02X}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Compressed EISA identifier, e.g. `PNP0501`
+struct EisaId(u32);
+
+impl fmt::Display for EisaId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let vendor = self.0 as u16;
+        for shift in [10, 5, 0] {
+            let c = b'A' - 1 + ((vendor >> shift) & 0x1f) as u8;
+            fmt::Write::write_char(f, c as char)?;
+        }
+        write!(f, "{:04X}", self.0 >> 16)
+    }
+}
+
+struct Ipv4([u8; 4]);
+
+impl fmt::Display for Ipv4 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d] = self.0;
+        write!(f, "{a}.{b}.{c}.{d}")
+    }
+}
+
+struct Ipv6([u8; 16]);
+
+impl fmt::Display for Ipv6 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, pair) in self.0.chunks_exact(2).enumerate() {
+            let sep = if i == 0 { "" } else { ":" };
+            write!(f, "{sep}{:x}", u16::from_be_bytes([pair[0], pair[1]]))?;
+        }
+        Ok(())
+    }
+}
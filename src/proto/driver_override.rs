@@ -0,0 +1,165 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Driver override protocols
+//!
+//! When connecting a controller, `ConnectController()` tries drivers in priority order:
+//! those returned by the [`PlatformDriverOverride`] protocol first, then the drivers named by
+//! the controller's [`BusSpecificDriverOverride`] protocol, then all other drivers.
+
+use crate::{
+    guid,
+    proto::{DevicePath, Proto, Protocol},
+    DeviceHandle, DriverHandle, Guid, Handle, Result, Status,
+};
+
+pub type PlatformGetDriverFn = extern "efiapi" fn(
+    this: *mut PlatformDriverOverride,
+    controller_handle: Handle,
+    driver_image_handle: *mut Option<Handle>,
+) -> Status;
+
+pub type PlatformGetDriverPathFn = extern "efiapi" fn(
+    this: *mut PlatformDriverOverride,
+    controller_handle: Handle,
+    driver_image_path: *mut *const DevicePath,
+) -> Status;
+
+pub type PlatformDriverLoadedFn = extern "efiapi" fn(
+    this: *mut PlatformDriverOverride,
+    controller_handle: Handle,
+    driver_image_path: *const DevicePath,
+    driver_image_handle: Handle,
+) -> Status;
+
+/// Platform Driver Override Protocol
+///
+/// Installed at most once, by the platform, to override the drivers chosen for any
+/// controller.
+#[repr(C)]
+pub struct PlatformDriverOverride {
+    get_driver:      PlatformGetDriverFn,
+    get_driver_path: PlatformGetDriverPathFn,
+    driver_loaded:   PlatformDriverLoadedFn,
+}
+
+impl Protocol for PlatformDriverOverride {
+    const GUID: Guid = guid!(
+        0x6b30c738,0xa391,0x11d4,
+        {0x9a,0x3b,0x00,0x90,0x27,0x3f,0xc1,0x4d}
+    );
+}
+
+impl Proto<PlatformDriverOverride> {
+    /// Returns the override driver following `previous`, or the first if `previous` is `None`
+    ///
+    /// Fails with `NOT_FOUND` once the list is exhausted or if `controller` has no overrides.
+    pub fn get_driver(
+        &mut self,
+        controller: DeviceHandle,
+        previous: Option<DriverHandle>,
+    ) -> Result<DriverHandle> {
+        let mut driver = previous.map(DriverHandle::handle);
+        (self.get_driver)(self.as_ptr(), controller.handle(), &mut driver).to_result(())?;
+        driver.map(DriverHandle::new).ok_or(Status::NOT_FOUND)
+    }
+
+    /// Returns an iterator over the override drivers for `controller`, highest priority first
+    pub fn drivers(&mut self, controller: DeviceHandle) -> impl Iterator<Item = DriverHandle> + '_ {
+        let mut previous = None;
+        core::iter::from_fn(move || {
+            previous = Some(self.get_driver(controller, previous).ok()?);
+            previous
+        })
+    }
+
+    /// Returns the device path of an override driver which has not been loaded yet
+    ///
+    /// The caller is expected to load the image and report it with
+    /// [`driver_loaded()`](Self::driver_loaded). Fails with `NOT_FOUND` once every driver
+    /// path has been returned.
+    pub fn get_driver_path(&mut self, controller: DeviceHandle) -> Result<&'static DevicePath> {
+        let mut path = core::ptr::null();
+        (self.get_driver_path)(self.as_ptr(), controller.handle(), &mut path).to_result(())?;
+        unsafe { path.as_ref() }.ok_or(Status::NOT_FOUND)
+    }
+
+    /// Associates an image loaded from a path returned by
+    /// [`get_driver_path()`](Self::get_driver_path) with `controller`
+    pub fn driver_loaded(
+        &mut self,
+        controller: DeviceHandle,
+        path: &DevicePath,
+        image: DriverHandle,
+    ) -> Result<()> {
+        (self.driver_loaded)(self.as_ptr(), controller.handle(), path, image.handle()).to_result(())
+    }
+}
+
+pub type BusGetDriverFn = extern "efiapi" fn(
+    this: *mut BusSpecificDriverOverride,
+    driver_image_handle: *mut Option<Handle>,
+) -> Status;
+
+/// Bus Specific Driver Override Protocol
+///
+/// Installed by bus drivers on child controllers, e.g. by the PCI bus driver for devices with
+/// an option ROM containing a driver.
+#[repr(C)]
+pub struct BusSpecificDriverOverride {
+    get_driver: BusGetDriverFn,
+}
+
+impl Protocol for BusSpecificDriverOverride {
+    const GUID: Guid = guid!(
+        0x3bc1b285,0x8a15,0x4a82,
+        {0xaa,0xbf,0x4d,0x7d,0x13,0xfb,0x32,0x65}
+    );
+}
+
+impl Proto<BusSpecificDriverOverride> {
+    /// Returns the override driver following `previous`, or the first if `previous` is `None`
+    ///
+    /// Fails with `NOT_FOUND` once the list is exhausted.
+    pub fn get_driver(&mut self, previous: Option<DriverHandle>) -> Result<DriverHandle> {
+        let mut driver = previous.map(DriverHandle::handle);
+        (self.get_driver)(self.as_ptr(), &mut driver).to_result(())?;
+        driver.map(DriverHandle::new).ok_or(Status::NOT_FOUND)
+    }
+
+    /// Returns an iterator over the override drivers, highest priority first
+    pub fn drivers(&mut self) -> impl Iterator<Item = DriverHandle> + '_ {
+        let mut previous = None;
+        core::iter::from_fn(move || {
+            previous = Some(self.get_driver(previous).ok()?);
+            previous
+        })
+    }
+}
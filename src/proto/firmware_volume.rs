@@ -0,0 +1,450 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Firmware Volume 2 Protocol (PI)
+//!
+//! Provides file-level access to the platform's firmware volumes, which hold the drivers,
+//! applications and data files making up the firmware in the Firmware File System format.
+//! Files are identified by GUID and consist of typed sections.
+
+use core::{ffi::c_void, ptr};
+
+use crate::{
+    boot_services, default_memory_type, guid,
+    proto::{decompress::Decompress, Proto, Protocol},
+    table::PoolSlice,
+    ucs2::CStr16,
+    Guid, Handle, Result, Status,
+};
+
+pub type GetVolumeAttributesFn =
+    extern "efiapi" fn(this: *mut FirmwareVolume2, fv_attributes: *mut u64) -> Status;
+
+pub type SetVolumeAttributesFn =
+    extern "efiapi" fn(this: *mut FirmwareVolume2, fv_attributes: *mut u64) -> Status;
+
+pub type ReadFileFn = extern "efiapi" fn(
+    this: *mut FirmwareVolume2,
+    name_guid: *const Guid,
+    buffer: *mut *mut c_void,
+    buffer_size: *mut usize,
+    found_type: *mut FvFileType,
+    file_attributes: *mut FvFileAttributes,
+    authentication_status: *mut u32,
+) -> Status;
+
+pub type ReadSectionFn = extern "efiapi" fn(
+    this: *mut FirmwareVolume2,
+    name_guid: *const Guid,
+    section_type: SectionType,
+    section_instance: usize,
+    buffer: *mut *mut c_void,
+    buffer_size: *mut usize,
+    authentication_status: *mut u32,
+) -> Status;
+
+pub type WriteFileFn = extern "efiapi" fn(
+    this: *mut FirmwareVolume2,
+    number_of_files: u32,
+    write_policy: u32,
+    file_data: *mut c_void,
+) -> Status;
+
+pub type GetNextFileFn = extern "efiapi" fn(
+    this: *mut FirmwareVolume2,
+    key: *mut c_void,
+    file_type: *mut FvFileType,
+    name_guid: *mut Guid,
+    attributes: *mut FvFileAttributes,
+    size: *mut usize,
+) -> Status;
+
+pub type GetInfoFn = extern "efiapi" fn(
+    this: *mut FirmwareVolume2,
+    information_type: *const Guid,
+    buffer_size: *mut usize,
+    buffer: *mut c_void,
+) -> Status;
+
+pub type SetInfoFn = extern "efiapi" fn(
+    this: *mut FirmwareVolume2,
+    information_type: *const Guid,
+    buffer_size: usize,
+    buffer: *const c_void,
+) -> Status;
+
+/// Firmware Volume 2 Protocol
+#[repr(C)]
+pub struct FirmwareVolume2 {
+    get_volume_attributes: GetVolumeAttributesFn,
+    set_volume_attributes: SetVolumeAttributesFn,
+    read_file:             ReadFileFn,
+    read_section:          ReadSectionFn,
+    write_file:            WriteFileFn,
+    get_next_file:         GetNextFileFn,
+    /// Size in bytes of the search key used by `GetNextFile()`
+    pub key_size:          u32,
+    pub parent_handle:     Option<Handle>,
+    get_info:              GetInfoFn,
+    set_info:              SetInfoFn,
+}
+
+impl Protocol for FirmwareVolume2 {
+    const GUID: Guid = guid!(
+        0x220e73b6,0x6bdb,0x4413,
+        {0x84,0x05,0xb9,0x74,0xb1,0x08,0x61,0x9a}
+    );
+}
+
+/// `EFI_FV_FILETYPE`
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct FvFileType(pub u8);
+
+impl FvFileType {
+    /// Matches any file type when searching
+    pub const ALL: Self = Self(0x00);
+    pub const RAW: Self = Self(0x01);
+    pub const FREEFORM: Self = Self(0x02);
+    pub const SECURITY_CORE: Self = Self(0x03);
+    pub const PEI_CORE: Self = Self(0x04);
+    pub const DXE_CORE: Self = Self(0x05);
+    pub const PEIM: Self = Self(0x06);
+    pub const DRIVER: Self = Self(0x07);
+    pub const COMBINED_PEIM_DRIVER: Self = Self(0x08);
+    pub const APPLICATION: Self = Self(0x09);
+    pub const MM: Self = Self(0x0a);
+    pub const FIRMWARE_VOLUME_IMAGE: Self = Self(0x0b);
+    pub const COMBINED_MM_DXE: Self = Self(0x0c);
+    pub const MM_CORE: Self = Self(0x0d);
+    pub const MM_STANDALONE: Self = Self(0x0e);
+    pub const MM_CORE_STANDALONE: Self = Self(0x0f);
+}
+
+/// `EFI_SECTION_TYPE`
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SectionType(pub u8);
+
+impl SectionType {
+    pub const ALL: Self = Self(0x00);
+    pub const COMPRESSION: Self = Self(0x01);
+    pub const GUID_DEFINED: Self = Self(0x02);
+    pub const DISPOSABLE: Self = Self(0x03);
+    pub const PE32: Self = Self(0x10);
+    pub const PIC: Self = Self(0x11);
+    pub const TE: Self = Self(0x12);
+    pub const DXE_DEPEX: Self = Self(0x13);
+    pub const VERSION: Self = Self(0x14);
+    /// The file's name, as a null-terminated UCS-2 string
+    pub const USER_INTERFACE: Self = Self(0x15);
+    pub const COMPATIBILITY16: Self = Self(0x16);
+    pub const FIRMWARE_VOLUME_IMAGE: Self = Self(0x17);
+    pub const FREEFORM_SUBTYPE_GUID: Self = Self(0x18);
+    pub const RAW: Self = Self(0x19);
+    pub const PEI_DEPEX: Self = Self(0x1b);
+    pub const MM_DEPEX: Self = Self(0x1c);
+}
+
+bitflags::bitflags! {
+    /// `EFI_FV_FILE_ATTRIBUTES`
+    #[repr(transparent)]
+    pub struct FvFileAttributes : u32 {
+        const ALIGNMENT     = 0x0000001f;
+        const FIXED         = 0x00000100;
+        const MEMORY_MAPPED = 0x00000200;
+    }
+}
+
+impl FvFileAttributes {
+    /// Returns the required alignment of the file's data in bytes
+    pub fn alignment(&self) -> u64 {
+        1 << (self.bits() & Self::ALIGNMENT.bits())
+    }
+}
+
+/// A file's metadata, as returned by [`Proto::<FirmwareVolume2>::file_info()`]
+#[derive(Clone, Copy, Debug)]
+pub struct FvFileInfo {
+    pub name:       Guid,
+    pub kind:       FvFileType,
+    pub attributes: FvFileAttributes,
+    pub size:       usize,
+}
+
+/// A file read with [`Proto::<FirmwareVolume2>::read_file()`]
+#[derive(Debug)]
+pub struct FvFile {
+    pub kind:                  FvFileType,
+    pub attributes:            FvFileAttributes,
+    /// `EFI_AUTH_STATUS_*` bits describing whether the file's contents were authenticated
+    pub authentication_status: u32,
+    pub data:                  PoolSlice<u8>,
+}
+
+impl FvFile {
+    /// Returns an iterator over the file's sections
+    pub fn sections(&self) -> impl Iterator<Item = Section<'_>> {
+        sections(&self.data)
+    }
+}
+
+/// A section of a file, as returned by [`sections()`]
+#[derive(Clone, Copy, Debug)]
+pub struct Section<'a> {
+    pub kind: SectionType,
+    /// The section's contents, following its header
+    pub data: &'a [u8],
+}
+
+/// Size of a section in an `EFI_COMMON_SECTION_HEADER` which indicates the extended header
+const EXTENDED_SECTION_SIZE: usize = 0xffffff;
+
+/// Returns an iterator over the sections in `data`
+///
+/// `data` is the contents of a file read with [`Proto::<FirmwareVolume2>::read_file()`], or
+/// of an encapsulation section. Iteration stops at the first malformed section header.
+pub fn sections(data: &[u8]) -> impl Iterator<Item = Section<'_>> {
+    let mut offset = 0;
+    core::iter::from_fn(move || {
+        let header = data.get(offset..offset + 4)?;
+        let size = u32::from_le_bytes([header[0], header[1], header[2], 0]) as usize;
+        let kind = SectionType(header[3]);
+        let (header_size, size) = match size {
+            EXTENDED_SECTION_SIZE => {
+                let extended = data.get(offset + 4..offset + 8)?;
+                (8, u32::from_le_bytes(extended.try_into().unwrap()) as usize)
+            }
+            size => (4, size),
+        };
+        let section = data.get(offset + header_size..offset.checked_add(size)?)?;
+        // Sections are aligned to 4 bytes.
+        offset = (offset + size).next_multiple_of(4);
+        Some(Section {
+            kind,
+            data: section,
+        })
+    })
+}
+
+/// `EFI_COMPRESSION_SECTION` compression type
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct CompressionType(pub u8);
+
+impl CompressionType {
+    pub const NOT_COMPRESSED: Self = Self(0x00);
+    /// The EFI 1.1 compression format
+    pub const STANDARD: Self = Self(0x01);
+}
+
+/// The contents of a compression section
+#[derive(Clone, Copy, Debug)]
+pub struct CompressionSection<'a> {
+    pub uncompressed_length: u32,
+    pub compression_type:    CompressionType,
+    /// The compressed list of encapsulated sections
+    pub data:                &'a [u8],
+}
+
+impl<'a> Section<'a> {
+    /// Returns the contents of a compression section, or `None` if this is another kind of
+    /// section
+    pub fn compression(&self) -> Option<CompressionSection<'a>> {
+        if self.kind != SectionType::COMPRESSION {
+            return None;
+        }
+        let (header, data) = self.data.split_first_chunk::<5>()?;
+        Some(CompressionSection {
+            uncompressed_length: u32::from_le_bytes(header[..4].try_into().unwrap()),
+            compression_type: CompressionType(header[4]),
+            data,
+        })
+    }
+}
+
+impl CompressionSection<'_> {
+    /// Unpacks the encapsulated sections, which can then be iterated with [`sections()`]
+    ///
+    /// Standard compression is undone with the firmware's Decompress Protocol. Fails with
+    /// `UNSUPPORTED` for other compression types, and with `VOLUME_CORRUPTED` if the
+    /// result's length doesn't match the section header.
+    pub fn decompress(&self) -> Result<PoolSlice<u8>> {
+        let len = self.uncompressed_length as usize;
+        let data = match self.compression_type {
+            CompressionType::NOT_COMPRESSED => {
+                let data = self.data.get(..len).ok_or(Status::VOLUME_CORRUPTED)?;
+                let buffer = boot_services().allocate_pool(default_memory_type(), len.max(1))?;
+                unsafe {
+                    ptr::copy_nonoverlapping(data.as_ptr(), buffer, len);
+                    PoolSlice::from_raw(buffer, len)
+                }
+            }
+            CompressionType::STANDARD => boot_services()
+                .first_protocol::<Decompress>()?
+                .decompress_to_pool(self.data)?,
+            _ => return Err(Status::UNSUPPORTED),
+        };
+        if data.len() != len {
+            return Err(Status::VOLUME_CORRUPTED);
+        }
+        Ok(data)
+    }
+}
+
+/// The largest search key supported by [`Proto::<FirmwareVolume2>::files()`]
+const MAX_KEY_SIZE: usize = 64;
+
+impl Proto<FirmwareVolume2> {
+    /// Returns the volume's `EFI_FV_ATTRIBUTES`
+    pub fn volume_attributes(&mut self) -> Result<u64> {
+        let mut attributes = 0;
+        (self.get_volume_attributes)(self.as_ptr(), &mut attributes).to_result(attributes)
+    }
+
+    /// Returns a file's type, attributes and size without reading it
+    ///
+    /// Fails with `NOT_FOUND` if the volume has no file named `name`.
+    pub fn file_info(&mut self, name: &Guid) -> Result<FvFileInfo> {
+        let mut info = FvFileInfo {
+            name:       *name,
+            kind:       FvFileType::ALL,
+            attributes: FvFileAttributes::empty(),
+            size:       0,
+        };
+        let mut authentication_status = 0;
+        (self.read_file)(
+            self.as_ptr(),
+            name,
+            ptr::null_mut(),
+            &mut info.size,
+            &mut info.kind,
+            &mut info.attributes,
+            &mut authentication_status,
+        )
+        .to_result(info)
+    }
+
+    /// Reads a whole file, including its section headers, into a buffer allocated by firmware
+    pub fn read_file(&mut self, name: &Guid) -> Result<FvFile> {
+        let mut buffer = ptr::null_mut();
+        let mut size = 0;
+        let mut kind = FvFileType::ALL;
+        let mut attributes = FvFileAttributes::empty();
+        let mut authentication_status = 0;
+        (self.read_file)(
+            self.as_ptr(),
+            name,
+            &mut buffer,
+            &mut size,
+            &mut kind,
+            &mut attributes,
+            &mut authentication_status,
+        )
+        .to_result(())?;
+        Ok(FvFile {
+            kind,
+            attributes,
+            authentication_status,
+            data: unsafe { PoolSlice::from_raw(buffer.cast(), size) },
+        })
+    }
+
+    /// Reads the data of the `instance`th section of type `kind` from a file
+    ///
+    /// Encapsulation sections (compressed or GUID-defined) are searched transparently. Fails
+    /// with `NOT_FOUND` if the file or section does not exist.
+    pub fn read_section(
+        &mut self,
+        name: &Guid,
+        kind: SectionType,
+        instance: usize,
+    ) -> Result<PoolSlice<u8>> {
+        let mut buffer = ptr::null_mut();
+        let mut size = 0;
+        let mut authentication_status = 0;
+        (self.read_section)(
+            self.as_ptr(),
+            name,
+            kind,
+            instance,
+            &mut buffer,
+            &mut size,
+            &mut authentication_status,
+        )
+        .to_result(())?;
+        Ok(unsafe { PoolSlice::from_raw(buffer.cast(), size) })
+    }
+
+    /// Returns a file's name from its user interface section
+    ///
+    /// Fails with `VOLUME_CORRUPTED` if the section is not a null-terminated string.
+    pub fn user_interface_name(&mut self, name: &Guid) -> Result<PoolSlice<u16>> {
+        let section = self.read_section(name, SectionType::USER_INTERFACE, 0)?;
+        if section.len() % 2 != 0 || section.as_ptr().align_offset(2) != 0 {
+            return Err(Status::VOLUME_CORRUPTED);
+        }
+        let units = section.len() / 2;
+        let ptr = core::mem::ManuallyDrop::new(section).as_ptr().cast_mut();
+        let name = unsafe { PoolSlice::from_raw(ptr.cast::<u16>(), units) };
+        CStr16::from_slice_until_nul(&name).map_err(|_| Status::VOLUME_CORRUPTED)?;
+        Ok(name)
+    }
+
+    /// Returns an iterator over the files of type `kind` in the volume
+    ///
+    /// [`FvFileType::ALL`] matches every file. Iteration ends early if firmware reports an
+    /// error, or immediately if the volume's key is larger than supported.
+    pub fn files(&mut self, kind: FvFileType) -> impl Iterator<Item = FvFileInfo> + '_ {
+        let mut key = [0u64; MAX_KEY_SIZE / 8];
+        let mut done = self.key_size as usize > MAX_KEY_SIZE;
+        core::iter::from_fn(move || {
+            if done {
+                return None;
+            }
+            let mut info = FvFileInfo {
+                name: Guid::from_bytes([0; 16]),
+                kind,
+                attributes: FvFileAttributes::empty(),
+                size: 0,
+            };
+            let status = (self.get_next_file)(
+                self.as_ptr(),
+                key.as_mut_ptr().cast(),
+                &mut info.kind,
+                &mut info.name,
+                &mut info.attributes,
+                &mut info.size,
+            );
+            done = status != Status::SUCCESS;
+            (!done).then_some(info)
+        })
+    }
+}
@@ -0,0 +1,144 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Hash 2 Protocol
+//!
+//! Computes digests with the firmware's hash implementations. Instances are created through
+//! the [`ServiceBinding`](crate::proto::service_binding::ServiceBinding) installed by the
+//! hash driver.
+
+use core::ffi::c_void;
+
+use super::{service_binding::ServiceProtocol, Proto, Protocol};
+use crate::{guid, Guid, Result, Status};
+
+pub const HASH_ALGORITHM_SHA1: Guid = guid!(
+    0x2ae9d80f,0x3fb2,0x4095,
+    {0xb7,0xb1,0xe9,0x31,0x57,0xb9,0x46,0xb6}
+);
+pub const HASH_ALGORITHM_SHA256: Guid = guid!(
+    0x51aa59de,0xfdf2,0x4ea3,
+    {0xbc,0x63,0x87,0x5f,0xb7,0x84,0x2e,0xe9}
+);
+pub const HASH_ALGORITHM_SHA384: Guid = guid!(
+    0xefa96432,0xde33,0x4dd2,
+    {0xae,0xe6,0x32,0x8c,0x33,0xdf,0x77,0x7a}
+);
+pub const HASH_ALGORITHM_SHA512: Guid = guid!(
+    0xcaa4381e,0x750c,0x4770,
+    {0xb8,0x70,0x7a,0x23,0xb4,0xe4,0x21,0x30}
+);
+
+/// `EFI_HASH2_OUTPUT`, large enough for any supported digest
+///
+/// Digests smaller than 64 bytes are stored at the start.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Hash2Output(pub [u8; 64]);
+
+pub type GetHashSizeFn = extern "efiapi" fn(
+    this: *mut Hash2,
+    hash_algorithm: *const Guid,
+    hash_size: *mut usize,
+) -> Status;
+
+pub type HashFn = extern "efiapi" fn(
+    this: *mut Hash2,
+    hash_algorithm: *const Guid,
+    message: *const c_void,
+    message_size: usize,
+    hash: *mut Hash2Output,
+) -> Status;
+
+pub type HashInitFn = extern "efiapi" fn(this: *mut Hash2, hash_algorithm: *const Guid) -> Status;
+
+pub type HashUpdateFn =
+    extern "efiapi" fn(this: *mut Hash2, message: *const c_void, message_size: usize) -> Status;
+
+pub type HashFinalFn = extern "efiapi" fn(this: *mut Hash2, hash: *mut Hash2Output) -> Status;
+
+#[repr(C)]
+pub struct Hash2 {
+    get_hash_size: GetHashSizeFn,
+    hash:          HashFn,
+    hash_init:     HashInitFn,
+    hash_update:   HashUpdateFn,
+    hash_final:    HashFinalFn,
+}
+
+impl Protocol for Hash2 {
+    const GUID: Guid = guid!(
+        0x55b1d734,0xc5e1,0x49db,
+        {0x96,0x47,0xb1,0x6a,0xfb,0x0e,0x30,0x5b}
+    );
+}
+
+impl ServiceProtocol for Hash2 {
+    const SERVICE_BINDING_GUID: Guid = guid!(
+        0xda836f8d,0x217f,0x4ca0,
+        {0x99,0xc2,0x1c,0xa4,0xe1,0x60,0x77,0xea}
+    );
+}
+
+impl Proto<Hash2> {
+    /// Returns the size of `algorithm`'s digests, failing with `UNSUPPORTED` if the firmware
+    /// doesn't implement it
+    pub fn hash_size(&mut self, algorithm: &Guid) -> Result<usize> {
+        let mut size = 0;
+        (self.get_hash_size)(self.as_ptr(), algorithm, &mut size).to_result(size)
+    }
+
+    /// Hashes `data` in one go
+    pub fn hash(&mut self, algorithm: &Guid, data: &[u8]) -> Result<Hash2Output> {
+        let mut output = Hash2Output([0; 64]);
+        (self.hash)(
+            self.as_ptr(),
+            algorithm,
+            data.as_ptr().cast(),
+            data.len(),
+            &mut output,
+        )
+        .to_result(output)
+    }
+
+    /// Starts an incremental hash, aborting any which is in progress
+    pub fn hash_init(&mut self, algorithm: &Guid) -> Result<()> {
+        (self.hash_init)(self.as_ptr(), algorithm).to_result(())
+    }
+
+    pub fn hash_update(&mut self, data: &[u8]) -> Result<()> {
+        (self.hash_update)(self.as_ptr(), data.as_ptr().cast(), data.len()).to_result(())
+    }
+
+    pub fn hash_final(&mut self) -> Result<Hash2Output> {
+        let mut output = Hash2Output([0; 64]);
+        (self.hash_final)(self.as_ptr(), &mut output).to_result(output)
+    }
+}
@@ -0,0 +1,141 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Loaded Image and Loaded Image Device Path Protocols
+
+use core::{ffi::c_void, ops::Deref, slice};
+
+use super::{
+    device_path::{DevicePath, DeviceType},
+    Proto, Protocol,
+};
+use crate::{
+    boot_services, guid, image_handle,
+    table::{MemoryType, SystemTable},
+    Guid, Handle, Result, Status,
+};
+
+pub type UnloadFn = extern "efiapi" fn(image_handle: Handle) -> Status;
+
+#[repr(C)]
+pub struct LoadedImage {
+    pub revision:          u32,
+    /// The image that loaded this one, or `None` if it was loaded by the firmware
+    pub parent_handle:     Option<Handle>,
+    pub system_table:      *mut SystemTable,
+    /// The device the image was loaded from, or `None` if it was loaded from a buffer
+    pub device_handle:     Option<Handle>,
+    file_path:             *mut DevicePath,
+    reserved:              *mut c_void,
+    pub load_options_size: u32,
+    load_options:          *mut c_void,
+    pub image_base:        *mut c_void,
+    pub image_size:        u64,
+    pub image_code_type:   MemoryType,
+    pub image_data_type:   MemoryType,
+    unload:                Option<UnloadFn>,
+}
+
+impl Protocol for LoadedImage {
+    const GUID: Guid = guid!(
+        0x5b1b31a1,0x9562,0x11d2,
+        {0x8e,0x3f,0x00,0xa0,0xc9,0x69,0x72,0x3b}
+    );
+}
+
+impl Proto<LoadedImage> {
+    /// Returns the path of the image file, relative to the device it was loaded from
+    ///
+    /// Some firmware only records the last few nodes here; see [`boot_device_path()`] for the
+    /// full path.
+    pub fn file_path(&self) -> Option<&DevicePath> {
+        unsafe { self.file_path.as_ref() }
+    }
+
+    /// Returns the image's load options, typically a UCS-2 command line
+    pub fn load_options(&self) -> &[u8] {
+        if self.load_options.is_null() {
+            return &[];
+        }
+        unsafe { slice::from_raw_parts(self.load_options.cast(), self.load_options_size as usize) }
+    }
+
+    /// Returns the image as it was loaded into memory
+    pub fn image(&self) -> &[u8] {
+        if self.image_base.is_null() {
+            return &[];
+        }
+        unsafe { slice::from_raw_parts(self.image_base.cast(), self.image_size as usize) }
+    }
+}
+
+/// The full device path of a loaded image, installed on the image's handle
+///
+/// Unlike [`LoadedImage`]'s `file_path`, this includes the path to the device.
+#[repr(transparent)]
+pub struct LoadedImageDevicePath(DevicePath);
+
+impl Protocol for LoadedImageDevicePath {
+    const GUID: Guid = guid!(
+        0xbc62157e,0x3e33,0x4fec,
+        {0x99,0x20,0x2d,0x3b,0x36,0xd7,0x50,0xdf}
+    );
+}
+
+impl Deref for LoadedImageDevicePath {
+    type Target = DevicePath;
+
+    fn deref(&self) -> &DevicePath {
+        &self.0
+    }
+}
+
+/// Returns the Loaded Image Protocol of the running image
+pub fn loaded_image() -> Result<Proto<LoadedImage>> {
+    boot_services().protocol_for_handle(image_handle().handle())
+}
+
+/// Returns the full device path of the running image, including the image file
+///
+/// This is the image's Loaded Image Device Path if the firmware installed one (UEFI 2.1+).
+/// Otherwise the Loaded Image Protocol's `file_path` is used, if it starts at the device
+/// rather than being relative to it. Fails with `NOT_FOUND` if neither is available, e.g.
+/// when the image was loaded from a buffer.
+pub fn boot_device_path() -> Result<&'static DevicePath> {
+    let bs = boot_services();
+    if let Ok(path) = bs.protocol_for_handle::<LoadedImageDevicePath>(image_handle().handle()) {
+        return Ok(unsafe { &(*path.as_ptr()).0 });
+    }
+    let path = unsafe { loaded_image()?.file_path.as_ref() }.ok_or(Status::NOT_FOUND)?;
+    match path.nodes().next() {
+        Some(node) if node.kind != DeviceType::MEDIA => Ok(path),
+        _ => Err(Status::NOT_FOUND),
+    }
+}
@@ -30,6 +30,7 @@
 
 use core::ffi::c_void;
 
+use super::retry::RetryPolicy;
 use crate::{
     guid,
     proto::{Proto, Protocol},
@@ -93,6 +94,26 @@ impl Proto<BlockIo> {
         .to_result(())
     }
 
+    /// Reads like [`read_blocks()`](Self::read_blocks), retrying transient errors per `policy`
+    ///
+    /// After `MEDIA_CHANGED`, the read is retried with the media ID the device now reports,
+    /// which is stored in `media_id`; data read earlier may be from the previous media.
+    pub fn read_blocks_retry(
+        &mut self,
+        policy: &RetryPolicy,
+        media_id: &mut u32,
+        lba: Lba,
+        buf: &mut [u8],
+    ) -> Result<()> {
+        policy.run(|| {
+            let result = self.read_blocks(*media_id, lba, buf);
+            if result == Err(Status::MEDIA_CHANGED) {
+                *media_id = self.media().media_id;
+            }
+            result
+        })
+    }
+
     pub fn write_blocks(&mut self, media_id: u32, lba: Lba, buf: &mut [u8]) -> Result<()> {
         (self.write_blocks)(
             self.as_ptr(),
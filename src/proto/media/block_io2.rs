@@ -0,0 +1,186 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Block I/O 2 Protocol
+//!
+//! Asynchronous counterpart of the [Block I/O Protocol](super::block_io). Requests complete
+//! through a [`Completion`], so they are driven by an executor such as
+//! [`block_on()`](crate::executor::block_on).
+
+use core::{ffi::c_void, pin::pin};
+
+use super::block_io::BlockIoMedia;
+use crate::{
+    executor::{Completion, Token},
+    guid,
+    proto::{Proto, Protocol},
+    Event, Guid, Lba, Result, Status,
+};
+
+pub type ResetFn = extern "efiapi" fn(this: *mut BlockIo2, extended_verification: bool) -> Status;
+
+pub type ReadBlocksExFn = extern "efiapi" fn(
+    this: *mut BlockIo2,
+    media_id: u32,
+    lba: Lba,
+    token: *mut BlockIo2Token,
+    buffer_size: usize,
+    buffer: *mut c_void,
+) -> Status;
+
+pub type WriteBlocksExFn = extern "efiapi" fn(
+    this: *mut BlockIo2,
+    media_id: u32,
+    lba: Lba,
+    token: *mut BlockIo2Token,
+    buffer_size: usize,
+    buffer: *mut c_void,
+) -> Status;
+
+pub type FlushBlocksExFn =
+    extern "efiapi" fn(this: *mut BlockIo2, token: *mut BlockIo2Token) -> Status;
+
+/// `EFI_BLOCK_IO2_TOKEN`
+#[repr(C)]
+#[derive(Debug)]
+pub struct BlockIo2Token {
+    pub event:              Event,
+    pub transaction_status: Status,
+}
+
+unsafe impl Token for BlockIo2Token {
+    fn new(event: Event) -> Self {
+        Self {
+            event,
+            transaction_status: Status::SUCCESS,
+        }
+    }
+
+    fn event(&self) -> Event {
+        self.event
+    }
+
+    fn status(&self) -> Status {
+        self.transaction_status
+    }
+}
+
+#[repr(C)]
+pub struct BlockIo2 {
+    media:           *mut BlockIoMedia,
+    reset:           ResetFn,
+    read_blocks_ex:  ReadBlocksExFn,
+    write_blocks_ex: WriteBlocksExFn,
+    flush_blocks_ex: FlushBlocksExFn,
+}
+
+impl Protocol for BlockIo2 {
+    const GUID: Guid = guid!(
+        0xa77b2472,0xe282,0x4e9f,
+        {0xa2,0x45,0xc2,0xc0,0xe2,0x7b,0xbc,0xc1}
+    );
+}
+
+impl Proto<BlockIo2> {
+    pub fn media(&self) -> &BlockIoMedia {
+        unsafe { &*self.media }
+    }
+
+    /// Resets the device, aborting all outstanding requests
+    pub fn reset(&mut self, extended_verification: bool) -> Result<()> {
+        (self.reset)(self.as_ptr(), extended_verification).to_result(())
+    }
+
+    /// Reads blocks starting at `lba` into `buf`
+    ///
+    /// # Safety
+    ///
+    /// The returned future must not be leaked, e.g. with [`mem::forget()`](core::mem::forget),
+    /// once it has been polled: the firmware uses `buf` until the request completes, and only
+    /// dropping the future waits for that.
+    pub async unsafe fn read_blocks(
+        &mut self,
+        media_id: u32,
+        lba: Lba,
+        buf: &mut [u8],
+    ) -> Result<()> {
+        let (this, read_blocks_ex) = (self.as_ptr(), self.read_blocks_ex);
+        let mut completion = pin!(Completion::new()?);
+        unsafe {
+            completion.as_mut().submit(|token| {
+                read_blocks_ex(
+                    this,
+                    media_id,
+                    lba,
+                    token,
+                    buf.len(),
+                    buf.as_mut_ptr().cast(),
+                )
+            })?;
+        }
+        completion.await
+    }
+
+    /// Writes `buf` to blocks starting at `lba`
+    ///
+    /// # Safety
+    ///
+    /// The returned future must not be leaked, e.g. with [`mem::forget()`](core::mem::forget),
+    /// once it has been polled: the firmware uses `buf` until the request completes, and only
+    /// dropping the future waits for that.
+    pub async unsafe fn write_blocks(&mut self, media_id: u32, lba: Lba, buf: &[u8]) -> Result<()> {
+        let (this, write_blocks_ex) = (self.as_ptr(), self.write_blocks_ex);
+        let mut completion = pin!(Completion::new()?);
+        unsafe {
+            completion.as_mut().submit(|token| {
+                write_blocks_ex(
+                    this,
+                    media_id,
+                    lba,
+                    token,
+                    buf.len(),
+                    buf.as_ptr().cast_mut().cast(),
+                )
+            })?;
+        }
+        completion.await
+    }
+
+    pub async fn flush_blocks(&mut self) -> Result<()> {
+        let (this, flush_blocks_ex) = (self.as_ptr(), self.flush_blocks_ex);
+        let mut completion = pin!(Completion::new()?);
+        unsafe {
+            completion
+                .as_mut()
+                .submit(|token| flush_blocks_ex(this, token))?
+        };
+        completion.await
+    }
+}
@@ -0,0 +1,114 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Disk I/O Protocol
+//!
+//! Synchronous byte-granular access to a block device, layered by the firmware on top of
+//! [`BlockIo`](super::block_io::BlockIo).
+
+use core::ffi::c_void;
+
+use super::retry::RetryPolicy;
+use crate::{
+    guid,
+    proto::{Proto, Protocol},
+    Guid, Result, Status,
+};
+
+pub type ReadDiskFn = extern "efiapi" fn(
+    this: *mut DiskIo,
+    media_id: u32,
+    offset: u64,
+    buffer_size: usize,
+    buffer: *mut c_void,
+) -> Status;
+
+pub type WriteDiskFn = extern "efiapi" fn(
+    this: *mut DiskIo,
+    media_id: u32,
+    offset: u64,
+    buffer_size: usize,
+    buffer: *const c_void,
+) -> Status;
+
+#[repr(C)]
+pub struct DiskIo {
+    pub revision: u64,
+    read_disk:    ReadDiskFn,
+    write_disk:   WriteDiskFn,
+}
+
+impl Protocol for DiskIo {
+    const GUID: Guid = guid!(
+        0xce345171,0xba0b,0x11d2,
+        {0x8e,0x4f,0x00,0xa0,0xc9,0x69,0x72,0x3b}
+    );
+}
+
+impl Proto<DiskIo> {
+    pub fn read_disk(&mut self, media_id: u32, offset: u64, buf: &mut [u8]) -> Result<()> {
+        (self.read_disk)(
+            self.as_ptr(),
+            media_id,
+            offset,
+            buf.len(),
+            buf.as_mut_ptr().cast(),
+        )
+        .to_result(())
+    }
+
+    /// Reads like [`read_disk()`](Self::read_disk), retrying transient errors per `policy`
+    ///
+    /// `MEDIA_CHANGED` is returned right away, since the new media ID has to come from the
+    /// device's [`BlockIo`](super::block_io::BlockIo).
+    pub fn read_disk_retry(
+        &mut self,
+        policy: &RetryPolicy,
+        media_id: u32,
+        offset: u64,
+        buf: &mut [u8],
+    ) -> Result<()> {
+        policy.run_if(
+            || self.read_disk(media_id, offset, buf),
+            |status| status != Status::MEDIA_CHANGED && RetryPolicy::is_transient(status),
+        )
+    }
+
+    pub fn write_disk(&mut self, media_id: u32, offset: u64, buf: &[u8]) -> Result<()> {
+        (self.write_disk)(
+            self.as_ptr(),
+            media_id,
+            offset,
+            buf.len(),
+            buf.as_ptr().cast(),
+        )
+        .to_result(())
+    }
+}
@@ -0,0 +1,187 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Disk I/O 2 Protocol
+//!
+//! Asynchronous byte-granular access to a block device. Requests complete through a
+//! [`Completion`], so they are driven by an executor such as
+//! [`block_on()`](crate::executor::block_on).
+
+use core::{ffi::c_void, pin::pin};
+
+use crate::{
+    executor::{Completion, Token},
+    guid,
+    proto::{Proto, Protocol},
+    Event, Guid, Result, Status,
+};
+
+pub type CancelFn = extern "efiapi" fn(this: *mut DiskIo2) -> Status;
+
+pub type ReadDiskExFn = extern "efiapi" fn(
+    this: *mut DiskIo2,
+    media_id: u32,
+    offset: u64,
+    token: *mut DiskIo2Token,
+    buffer_size: usize,
+    buffer: *mut c_void,
+) -> Status;
+
+pub type WriteDiskExFn = extern "efiapi" fn(
+    this: *mut DiskIo2,
+    media_id: u32,
+    offset: u64,
+    token: *mut DiskIo2Token,
+    buffer_size: usize,
+    buffer: *mut c_void,
+) -> Status;
+
+pub type FlushDiskExFn = extern "efiapi" fn(this: *mut DiskIo2, token: *mut DiskIo2Token) -> Status;
+
+/// `EFI_DISK_IO2_TOKEN`
+#[repr(C)]
+#[derive(Debug)]
+pub struct DiskIo2Token {
+    pub event:              Event,
+    pub transaction_status: Status,
+}
+
+unsafe impl Token for DiskIo2Token {
+    fn new(event: Event) -> Self {
+        Self {
+            event,
+            transaction_status: Status::SUCCESS,
+        }
+    }
+
+    fn event(&self) -> Event {
+        self.event
+    }
+
+    fn status(&self) -> Status {
+        self.transaction_status
+    }
+}
+
+#[repr(C)]
+pub struct DiskIo2 {
+    pub revision:  u64,
+    cancel:        CancelFn,
+    read_disk_ex:  ReadDiskExFn,
+    write_disk_ex: WriteDiskExFn,
+    flush_disk_ex: FlushDiskExFn,
+}
+
+impl Protocol for DiskIo2 {
+    const GUID: Guid = guid!(
+        0x151c8eae,0x7f2c,0x472c,
+        {0x9e,0x54,0x98,0x28,0x19,0x4f,0x6a,0x88}
+    );
+}
+
+impl Proto<DiskIo2> {
+    /// Aborts all outstanding requests
+    ///
+    /// Their tokens complete with `ABORTED`.
+    pub fn cancel(&mut self) -> Result<()> {
+        (self.cancel)(self.as_ptr()).to_result(())
+    }
+
+    /// Reads from `offset` on the disk into `buf`
+    ///
+    /// # Safety
+    ///
+    /// The returned future must not be leaked, e.g. with [`mem::forget()`](core::mem::forget),
+    /// once it has been polled: the firmware uses `buf` until the request completes, and only
+    /// dropping the future waits for that.
+    pub async unsafe fn read_disk(
+        &mut self,
+        media_id: u32,
+        offset: u64,
+        buf: &mut [u8],
+    ) -> Result<()> {
+        let (this, read_disk_ex) = (self.as_ptr(), self.read_disk_ex);
+        let mut completion = pin!(Completion::new()?);
+        unsafe {
+            completion.as_mut().submit(|token| {
+                read_disk_ex(
+                    this,
+                    media_id,
+                    offset,
+                    token,
+                    buf.len(),
+                    buf.as_mut_ptr().cast(),
+                )
+            })?;
+        }
+        completion.await
+    }
+
+    /// Writes `buf` at `offset` on the disk
+    ///
+    /// # Safety
+    ///
+    /// The returned future must not be leaked, e.g. with [`mem::forget()`](core::mem::forget),
+    /// once it has been polled: the firmware uses `buf` until the request completes, and only
+    /// dropping the future waits for that.
+    pub async unsafe fn write_disk(
+        &mut self,
+        media_id: u32,
+        offset: u64,
+        buf: &[u8],
+    ) -> Result<()> {
+        let (this, write_disk_ex) = (self.as_ptr(), self.write_disk_ex);
+        let mut completion = pin!(Completion::new()?);
+        unsafe {
+            completion.as_mut().submit(|token| {
+                write_disk_ex(
+                    this,
+                    media_id,
+                    offset,
+                    token,
+                    buf.len(),
+                    buf.as_ptr().cast_mut().cast(),
+                )
+            })?;
+        }
+        completion.await
+    }
+
+    pub async fn flush_disk(&mut self) -> Result<()> {
+        let (this, flush_disk_ex) = (self.as_ptr(), self.flush_disk_ex);
+        let mut completion = pin!(Completion::new()?);
+        unsafe {
+            completion
+                .as_mut()
+                .submit(|token| flush_disk_ex(this, token))?
+        };
+        completion.await
+    }
+}
@@ -0,0 +1,791 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Simple File System and File Protocols
+
+#[cfg(feature = "alloc")]
+use alloc::{boxed::Box, vec, vec::Vec};
+#[cfg(feature = "alloc")]
+use core::fmt;
+use core::{
+    ffi::c_void,
+    mem::size_of,
+    ops::{Deref, DerefMut},
+    ptr::NonNull,
+    slice,
+};
+
+#[cfg(feature = "alloc")]
+use crate::proto::unicode_collation::{wildcard_match, UnicodeCollation};
+use crate::{
+    boot_services, guid,
+    progress::Progress,
+    proto::{Proto, Protocol},
+    table::{AllocPagesType, MemoryType, Time},
+    ucs2::CStr16,
+    warning, Event, Guid, PhysicalAddr, Result, Status,
+};
+
+pub type OpenVolumeFn =
+    extern "efiapi" fn(this: *mut SimpleFileSystem, root: *mut *mut FileProtocol) -> Status;
+
+#[repr(C)]
+pub struct SimpleFileSystem {
+    pub revision: u64,
+    open_volume:  OpenVolumeFn,
+}
+
+impl Protocol for SimpleFileSystem {
+    const GUID: Guid = guid!(
+        0x964e5b22,0x6459,0x11d2,
+        {0x8e,0x39,0x00,0xa0,0xc9,0x69,0x72,0x3b}
+    );
+}
+
+impl Proto<SimpleFileSystem> {
+    /// Opens the root directory of the volume
+    pub fn open_volume(&mut self) -> Result<File> {
+        let mut root = core::ptr::null_mut();
+        (self.open_volume)(self.as_ptr(), &mut root).to_result(())?;
+        File::from_raw(root).ok_or(Status::DEVICE_ERROR)
+    }
+
+    /// Reads the volume's size, free space, block size and label into `buf`
+    ///
+    /// This is [`File::fs_info()`] on the root directory. Installers should check
+    /// `free_space` before writing; the label is what menus usually show for a volume.
+    pub fn volume_info<'b>(&mut self, buf: &'b mut [u8]) -> Result<&'b FileSystemInfo> {
+        self.open_volume()?.fs_info(buf)
+    }
+
+    /// Reads the volume's label into `buf`
+    pub fn volume_label<'b>(&mut self, buf: &'b mut [u16]) -> Result<&'b CStr16> {
+        self.open_volume()?.volume_label(buf)
+    }
+}
+
+pub type OpenFn = extern "efiapi" fn(
+    this: *mut FileProtocol,
+    new_handle: *mut *mut FileProtocol,
+    file_name: *const u16,
+    open_mode: FileMode,
+    attributes: FileAttribute,
+) -> Status;
+pub type CloseFn = extern "efiapi" fn(this: *mut FileProtocol) -> Status;
+pub type DeleteFn = extern "efiapi" fn(this: *mut FileProtocol) -> Status;
+pub type ReadFn = extern "efiapi" fn(
+    this: *mut FileProtocol,
+    buffer_size: *mut usize,
+    buffer: *mut c_void,
+) -> Status;
+pub type WriteFn = extern "efiapi" fn(
+    this: *mut FileProtocol,
+    buffer_size: *mut usize,
+    buffer: *const c_void,
+) -> Status;
+pub type GetPositionFn = extern "efiapi" fn(this: *mut FileProtocol, position: *mut u64) -> Status;
+pub type SetPositionFn = extern "efiapi" fn(this: *mut FileProtocol, position: u64) -> Status;
+pub type GetInfoFn = extern "efiapi" fn(
+    this: *mut FileProtocol,
+    information_type: *const Guid,
+    buffer_size: *mut usize,
+    buffer: *mut c_void,
+) -> Status;
+pub type SetInfoFn = extern "efiapi" fn(
+    this: *mut FileProtocol,
+    information_type: *const Guid,
+    buffer_size: usize,
+    buffer: *const c_void,
+) -> Status;
+pub type FlushFn = extern "efiapi" fn(this: *mut FileProtocol) -> Status;
+pub type OpenExFn = extern "efiapi" fn(
+    this: *mut FileProtocol,
+    new_handle: *mut *mut FileProtocol,
+    file_name: *const u16,
+    open_mode: FileMode,
+    attributes: FileAttribute,
+    token: *mut FileIoToken,
+) -> Status;
+pub type ReadExFn = extern "efiapi" fn(this: *mut FileProtocol, token: *mut FileIoToken) -> Status;
+pub type WriteExFn = extern "efiapi" fn(this: *mut FileProtocol, token: *mut FileIoToken) -> Status;
+pub type FlushExFn = extern "efiapi" fn(this: *mut FileProtocol, token: *mut FileIoToken) -> Status;
+
+/// `EFI_FILE_PROTOCOL`
+///
+/// This is not installed on a handle; instances are returned by
+/// [`open_volume()`](Proto::<SimpleFileSystem>::open_volume) and [`File::open()`]. The `*_ex`
+/// functions are only present if `revision` is at least [`FileProtocol::REVISION_2`].
+#[repr(C)]
+pub struct FileProtocol {
+    pub revision: u64,
+    open:         OpenFn,
+    close:        CloseFn,
+    delete:       DeleteFn,
+    read:         ReadFn,
+    write:        WriteFn,
+    get_position: GetPositionFn,
+    set_position: SetPositionFn,
+    get_info:     GetInfoFn,
+    set_info:     SetInfoFn,
+    flush:        FlushFn,
+    open_ex:      OpenExFn,
+    read_ex:      ReadExFn,
+    write_ex:     WriteExFn,
+    flush_ex:     FlushExFn,
+}
+
+impl FileProtocol {
+    pub const REVISION_1: u64 = 0x00010000;
+    pub const REVISION_2: u64 = 0x00020000;
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct FileIoToken {
+    pub event:       Event,
+    pub status:      Status,
+    pub buffer_size: usize,
+    pub buffer:      *mut c_void,
+}
+
+bitflags::bitflags! {
+    #[repr(transparent)]
+    pub struct FileMode : u64 {
+        const READ   = 0x0000000000000001;
+        const WRITE  = 0x0000000000000002;
+        const CREATE = 0x8000000000000000;
+        const READ_WRITE = Self::READ.bits | Self::WRITE.bits;
+        /// Opens the file for reading and writing, creating it if it does not exist
+        const CREATE_READ_WRITE = Self::READ_WRITE.bits | Self::CREATE.bits;
+    }
+}
+
+bitflags::bitflags! {
+    #[repr(transparent)]
+    #[derive(Default)]
+    pub struct FileAttribute : u64 {
+        const READ_ONLY = 0x01;
+        const HIDDEN    = 0x02;
+        const SYSTEM    = 0x04;
+        const RESERVED  = 0x08;
+        const DIRECTORY = 0x10;
+        const ARCHIVE   = 0x20;
+    }
+}
+
+/// `EFI_FILE_SYSTEM_INFO`, followed by the NUL-terminated volume label
+#[repr(C)]
+#[derive(Debug)]
+pub struct FileSystemInfo {
+    /// Size of the structure, including the volume label
+    pub size:        u64,
+    pub read_only:   bool,
+    /// Size of the volume in bytes
+    pub volume_size: u64,
+    /// Free space on the volume in bytes
+    pub free_space:  u64,
+    pub block_size:  u32,
+    volume_label:    [u16; 0],
+}
+
+impl FileSystemInfo {
+    pub const GUID: Guid = guid!(
+        0x09576e93,0x6d3f,0x11d2,
+        {0x8e,0x39,0x00,0xa0,0xc9,0x69,0x72,0x3b}
+    );
+
+    pub fn volume_label(&self) -> &CStr16 {
+        unsafe { CStr16::from_ptr(self.volume_label.as_ptr()) }
+    }
+}
+
+/// `EFI_FILE_SYSTEM_VOLUME_LABEL`, the NUL-terminated volume label alone
+pub struct FileSystemVolumeLabel;
+
+impl FileSystemVolumeLabel {
+    pub const GUID: Guid = guid!(
+        0xdb47d7d3,0xfe81,0x11d3,
+        {0x9a,0x35,0x00,0x90,0x27,0x3f,0xc1,0x4d}
+    );
+}
+
+/// `EFI_FILE_INFO`, followed by the NUL-terminated file name
+#[repr(C)]
+#[derive(Debug)]
+pub struct FileInfo {
+    /// Size of the structure, including the file name
+    pub size:              u64,
+    pub file_size:         u64,
+    pub physical_size:     u64,
+    pub create_time:       Time,
+    pub last_access_time:  Time,
+    pub modification_time: Time,
+    pub attribute:         FileAttribute,
+    file_name:             [u16; 0],
+}
+
+impl FileInfo {
+    pub const GUID: Guid = guid!(
+        0x09576e92,0x6d3f,0x11d2,
+        {0x8e,0x39,0x00,0xa0,0xc9,0x69,0x72,0x3b}
+    );
+
+    pub fn file_name(&self) -> &CStr16 {
+        unsafe { CStr16::from_ptr(self.file_name.as_ptr()) }
+    }
+
+    pub fn is_directory(&self) -> bool {
+        self.attribute.contains(FileAttribute::DIRECTORY)
+    }
+}
+
+/// An open file or directory, closed when dropped
+#[derive(Debug)]
+pub struct File {
+    ptr: NonNull<FileProtocol>,
+}
+
+impl File {
+    /// Takes ownership of a file handle returned by the firmware
+    pub fn from_raw(ptr: *mut FileProtocol) -> Option<Self> {
+        NonNull::new(ptr).map(|ptr| Self { ptr })
+    }
+
+    pub fn as_ptr(&self) -> *mut FileProtocol {
+        self.ptr.as_ptr()
+    }
+
+    fn protocol(&self) -> &FileProtocol {
+        unsafe { self.ptr.as_ref() }
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.protocol().revision
+    }
+
+    /// Opens a file relative to this directory
+    ///
+    /// Path components are separated by `\`; a leading `\` starts at the root of the volume.
+    pub fn open(
+        &mut self,
+        name: &CStr16,
+        mode: FileMode,
+        attributes: FileAttribute,
+    ) -> Result<File> {
+        let mut new = core::ptr::null_mut();
+        (self.protocol().open)(self.as_ptr(), &mut new, name.as_ptr(), mode, attributes)
+            .to_result(())?;
+        File::from_raw(new).ok_or(Status::DEVICE_ERROR)
+    }
+
+    /// Reads from the current position, returning the number of bytes read
+    ///
+    /// Zero bytes are returned at the end of the file. Warnings are [reported](crate::warning)
+    /// rather than failing the read.
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let mut size = buf.len();
+        let status = (self.protocol().read)(self.as_ptr(), &mut size, buf.as_mut_ptr().cast());
+        warning::check(status, "File.Read")?;
+        Ok(size)
+    }
+
+    /// Fills `buf` completely, failing with `END_OF_FILE` if the file is too short
+    pub fn read_exact(&mut self, mut buf: &mut [u8]) -> Result<()> {
+        while !buf.is_empty() {
+            match self.read(buf)? {
+                0 => return Err(Status::END_OF_FILE),
+                n => buf = &mut buf[n..],
+            }
+        }
+        Ok(())
+    }
+
+    pub fn position(&mut self) -> Result<u64> {
+        let mut position = 0;
+        (self.protocol().get_position)(self.as_ptr(), &mut position).to_result(position)
+    }
+
+    pub fn set_position(&mut self, position: u64) -> Result<()> {
+        (self.protocol().set_position)(self.as_ptr(), position).to_result(())
+    }
+
+    /// Returns the size of the file, without moving the current position
+    pub fn size(&mut self) -> Result<u64> {
+        let position = self.position()?;
+        // Seeking to `u64::MAX` moves to the end of the file.
+        self.set_position(u64::MAX)?;
+        let size = self.position();
+        self.set_position(position)?;
+        size
+    }
+
+    /// Queries information of type `kind` into `buf`, returning the number of bytes written
+    ///
+    /// Fails with `BUFFER_TOO_SMALL` if `buf` is too small; the required size is not reported.
+    pub fn get_info(&mut self, kind: &Guid, buf: &mut [u8]) -> Result<usize> {
+        let mut size = buf.len();
+        (self.protocol().get_info)(self.as_ptr(), kind, &mut size, buf.as_mut_ptr().cast())
+            .to_result(size)
+    }
+
+    /// Reads the file's [`FileInfo`] into `buf`
+    ///
+    /// `buf` does not need to be aligned; the returned reference points into it.
+    pub fn info<'b>(&mut self, buf: &'b mut [u8]) -> Result<&'b FileInfo> {
+        let offset = buf.as_ptr().align_offset(core::mem::align_of::<FileInfo>());
+        let buf = buf.get_mut(offset..).ok_or(Status::BUFFER_TOO_SMALL)?;
+        let len = self.get_info(&FileInfo::GUID, buf)?;
+        if len < size_of::<FileInfo>() + 2 {
+            return Err(Status::VOLUME_CORRUPTED);
+        }
+        Ok(unsafe { &*buf.as_ptr().cast::<FileInfo>() })
+    }
+
+    /// Reads the [`FileSystemInfo`] of the volume containing the file into `buf`
+    ///
+    /// `buf` does not need to be aligned; the returned reference points into it.
+    pub fn fs_info<'b>(&mut self, buf: &'b mut [u8]) -> Result<&'b FileSystemInfo> {
+        let offset = buf
+            .as_ptr()
+            .align_offset(core::mem::align_of::<FileSystemInfo>());
+        let buf = buf.get_mut(offset..).ok_or(Status::BUFFER_TOO_SMALL)?;
+        let len = self.get_info(&FileSystemInfo::GUID, buf)?;
+        if len < core::mem::offset_of!(FileSystemInfo, volume_label) + 2 {
+            return Err(Status::VOLUME_CORRUPTED);
+        }
+        Ok(unsafe { &*buf.as_ptr().cast::<FileSystemInfo>() })
+    }
+
+    /// Reads the label of the volume containing the file into `buf`
+    pub fn volume_label<'b>(&mut self, buf: &'b mut [u16]) -> Result<&'b CStr16> {
+        let bytes = unsafe {
+            slice::from_raw_parts_mut(buf.as_mut_ptr().cast::<u8>(), core::mem::size_of_val(buf))
+        };
+        let len = self.get_info(&FileSystemVolumeLabel::GUID, bytes)?;
+        CStr16::from_slice_until_nul(&buf[..len / 2]).map_err(|_| Status::VOLUME_CORRUPTED)
+    }
+}
+
+/// Writing
+impl File {
+    /// Writes at the current position, returning the number of bytes written
+    ///
+    /// Writing past the end of the file extends it. Warnings are [reported](crate::warning)
+    /// rather than failing the write.
+    pub fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let mut size = buf.len();
+        let status = (self.protocol().write)(self.as_ptr(), &mut size, buf.as_ptr().cast());
+        warning::check(status, "File.Write")?;
+        Ok(size)
+    }
+
+    /// Writes all of `buf`, failing with `VOLUME_FULL` if the file system stops accepting data
+    pub fn write_all(&mut self, mut buf: &[u8]) -> Result<()> {
+        while !buf.is_empty() {
+            match self.write(buf)? {
+                0 => return Err(Status::VOLUME_FULL),
+                n => buf = &buf[n..],
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes any cached data of the file to the device
+    ///
+    /// Warnings are [reported](crate::warning) rather than failing the flush.
+    pub fn flush(&mut self) -> Result<()> {
+        warning::check((self.protocol().flush)(self.as_ptr()), "File.Flush")
+    }
+
+    /// Closes the file, returning any error
+    ///
+    /// Dropping the file closes it too, but can only [report](crate::warning) warnings such
+    /// as `WARN_WRITE_FAILURE`; errors are lost.
+    pub fn close(self) -> Result<()> {
+        let this = core::mem::ManuallyDrop::new(self);
+        warning::check((this.protocol().close)(this.as_ptr()), "File.Close")
+    }
+
+    /// Deletes the file and closes it
+    ///
+    /// Fails with `WARN_DELETE_FAILURE` if the file could not be deleted; it is closed either way.
+    pub fn delete(self) -> Result<()> {
+        let this = core::mem::ManuallyDrop::new(self);
+        (this.protocol().delete)(this.as_ptr()).to_result(())
+    }
+
+    /// Sets information of type `kind` from `data`
+    pub fn set_info(&mut self, kind: &Guid, data: &[u8]) -> Result<()> {
+        (self.protocol().set_info)(self.as_ptr(), kind, data.len(), data.as_ptr().cast())
+            .to_result(())
+    }
+
+    /// Reads the file's [`FileInfo`], lets `f` modify it and writes it back
+    fn update_info(&mut self, f: impl FnOnce(&mut FileInfo)) -> Result<()> {
+        // Enough for a `FileInfo` with the longest name FAT allows.
+        let mut buf = [0u64; 80];
+        let bytes = unsafe {
+            slice::from_raw_parts_mut(buf.as_mut_ptr().cast::<u8>(), core::mem::size_of_val(&buf))
+        };
+        let len = self.get_info(&FileInfo::GUID, bytes)?;
+        if len < size_of::<FileInfo>() + 2 {
+            return Err(Status::VOLUME_CORRUPTED);
+        }
+        f(unsafe { &mut *bytes.as_mut_ptr().cast::<FileInfo>() });
+        self.set_info(&FileInfo::GUID, &bytes[..len])
+    }
+
+    /// Truncates or extends the file to `len` bytes
+    pub fn set_len(&mut self, len: u64) -> Result<()> {
+        self.update_info(|info| info.file_size = len)
+    }
+
+    /// Sets the file's timestamps, leaving those which are `None` unchanged
+    pub fn set_times(&mut self, times: FileTimes) -> Result<()> {
+        self.update_info(|info| {
+            // The firmware ignores fields which are zero.
+            info.create_time = times.create.unwrap_or_default();
+            info.last_access_time = times.last_access.unwrap_or_default();
+            info.modification_time = times.modification.unwrap_or_default();
+        })
+    }
+
+    /// Sets the file's attributes
+    ///
+    /// [`FileAttribute::DIRECTORY`] cannot be changed and is ignored in `attributes`.
+    pub fn set_attributes(&mut self, attributes: FileAttribute) -> Result<()> {
+        self.update_info(|info| {
+            let directory = info.attribute & FileAttribute::DIRECTORY;
+            info.attribute = (attributes - FileAttribute::DIRECTORY) | directory;
+        })
+    }
+
+    /// Opens a file relative to this directory for writing, creating or truncating it
+    pub fn create(&mut self, name: &CStr16) -> Result<File> {
+        let mut file = self.open(name, FileMode::CREATE_READ_WRITE, FileAttribute::empty())?;
+        file.set_len(0)?;
+        Ok(file)
+    }
+
+    /// Opens a directory relative to this one, creating it if it does not exist
+    ///
+    /// Fails with `ACCESS_DENIED` if `name` exists but is not a directory.
+    pub fn create_dir(&mut self, name: &CStr16) -> Result<File> {
+        let mut dir = self.open(name, FileMode::CREATE_READ_WRITE, FileAttribute::DIRECTORY)?;
+        if !dir.info(&mut [0; 648])?.is_directory() {
+            return Err(Status::ACCESS_DENIED);
+        }
+        Ok(dir)
+    }
+
+    /// Creates a directory and all of its missing parents, returning the innermost one
+    ///
+    /// The path is relative to this directory unless it starts with `\`. Components may be at
+    /// most 255 characters long.
+    pub fn create_dir_all(&mut self, path: &CStr16) -> Result<File> {
+        let path = path.as_slice();
+        let absolute = path.first() == Some(&SEPARATOR);
+        let mut components = path.split(|&c| c == SEPARATOR).filter(|c| !c.is_empty());
+        let mut name = [0; 257];
+
+        let first = components.next().ok_or(Status::INVALID_PARAMETER)?;
+        let mut dir = self.create_dir(component_name(&mut name, first, absolute)?)?;
+        for component in components {
+            dir = dir.create_dir(component_name(&mut name, component, false)?)?;
+        }
+        Ok(dir)
+    }
+}
+
+const SEPARATOR: u16 = b'\\' as u16;
+
+/// Copies a path component into `buf` as a null-terminated name
+fn component_name<'b>(buf: &'b mut [u16], component: &[u16], absolute: bool) -> Result<&'b CStr16> {
+    let start = absolute as usize;
+    let end = start + component.len();
+    if end >= buf.len() {
+        return Err(Status::INVALID_PARAMETER);
+    }
+    buf[0] = SEPARATOR;
+    buf[start..end].copy_from_slice(component);
+    buf[end] = 0;
+    CStr16::from_slice_with_nul(&buf[..=end]).map_err(|_| Status::INVALID_PARAMETER)
+}
+
+/// An open directory
+///
+/// Reading a directory returns one [`FileInfo`] per entry; [`Directory::entries()`] wraps that
+/// in an iterator.
+#[derive(Debug)]
+pub struct Directory(File);
+
+impl Directory {
+    /// Wraps `file`, failing with `INVALID_PARAMETER` if it is not a directory
+    pub fn new(mut file: File) -> Result<Self> {
+        if !file.info(&mut [0; 648])?.is_directory() {
+            return Err(Status::INVALID_PARAMETER);
+        }
+        Ok(Self(file))
+    }
+
+    pub fn into_file(self) -> File {
+        self.0
+    }
+}
+
+impl File {
+    /// Opens a directory relative to this one for reading
+    pub fn open_dir(&mut self, name: &CStr16) -> Result<Directory> {
+        Directory::new(self.open(name, FileMode::READ, FileAttribute::empty())?)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl Directory {
+    /// Returns an iterator over the directory's entries, starting from the first
+    ///
+    /// The `.` and `..` entries are skipped.
+    pub fn entries(&mut self) -> Result<Entries<'_>> {
+        self.0.set_position(0)?;
+        Ok(Entries {
+            dir:  &mut self.0,
+            buf:  vec![0; 80],
+            done: false,
+        })
+    }
+
+    /// Returns an iterator over the entries whose names match `pattern`
+    ///
+    /// Matching uses the firmware's [`UnicodeCollation`] if available, which supports `*`, `?`
+    /// and character sets, and otherwise [`wildcard_match()`]. Errors are always passed through.
+    pub fn matching<'a>(
+        &'a mut self,
+        pattern: &'a CStr16,
+    ) -> Result<impl Iterator<Item = Result<DirEntry>> + 'a> {
+        let mut collation = boot_services().first_protocol::<UnicodeCollation>().ok();
+        Ok(self
+            .entries()?
+            .filter(move |entry| match (entry, &mut collation) {
+                (Err(_), _) => true,
+                (Ok(entry), Some(collation)) => collation.metai_match(entry.file_name(), pattern),
+                (Ok(entry), None) => {
+                    wildcard_match(entry.file_name().as_slice(), pattern.as_slice())
+                }
+            }))
+    }
+}
+
+impl Deref for Directory {
+    type Target = File;
+
+    fn deref(&self) -> &File {
+        &self.0
+    }
+}
+
+impl DerefMut for Directory {
+    fn deref_mut(&mut self) -> &mut File {
+        &mut self.0
+    }
+}
+
+/// Iterator over the entries of a [`Directory`]
+#[cfg(feature = "alloc")]
+pub struct Entries<'a> {
+    dir:  &'a mut File,
+    // `u64` for the alignment of `FileInfo`
+    buf:  Vec<u64>,
+    done: bool,
+}
+
+#[cfg(feature = "alloc")]
+impl Iterator for Entries<'_> {
+    type Item = Result<DirEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        const DOT: u16 = b'.' as u16;
+        while !self.done {
+            let mut size = self.buf.len() * 8;
+            let status = (self.dir.protocol().read)(
+                self.dir.as_ptr(),
+                &mut size,
+                self.buf.as_mut_ptr().cast(),
+            );
+            if status == Status::BUFFER_TOO_SMALL {
+                self.buf
+                    .resize(crate::quirks::buffer_size(size).div_ceil(8), 0);
+                continue;
+            }
+            if let Err(status) = status.to_result(()) {
+                self.done = true;
+                return Some(Err(status));
+            }
+            // An empty read marks the end of the directory.
+            if size == 0 {
+                self.done = true;
+                return None;
+            }
+            if size < size_of::<FileInfo>() + 2 {
+                self.done = true;
+                return Some(Err(Status::VOLUME_CORRUPTED));
+            }
+            let entry = DirEntry {
+                data: self.buf[..size.div_ceil(8)].into(),
+            };
+            if !matches!(entry.file_name().as_slice(), [DOT] | [DOT, DOT]) {
+                return Some(Ok(entry));
+            }
+        }
+        None
+    }
+}
+
+/// A directory entry returned by [`Entries`]
+#[cfg(feature = "alloc")]
+pub struct DirEntry {
+    data: Box<[u64]>,
+}
+
+#[cfg(feature = "alloc")]
+impl Deref for DirEntry {
+    type Target = FileInfo;
+
+    fn deref(&self) -> &FileInfo {
+        unsafe { &*self.data.as_ptr().cast() }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl fmt::Debug for DirEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DirEntry")
+            .field("name", &self.file_name())
+            .field("file_size", &self.file_size)
+            .field("attribute", &self.attribute)
+            .field("modification_time", &self.modification_time)
+            .finish()
+    }
+}
+
+/// Timestamps to set with [`File::set_times()`]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FileTimes {
+    pub create:       Option<Time>,
+    pub last_access:  Option<Time>,
+    pub modification: Option<Time>,
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        let _ = warning::check((self.protocol().close)(self.as_ptr()), "File.Close");
+    }
+}
+
+/// Loading into physical memory
+impl File {
+    /// Amount of data read per call into the firmware
+    ///
+    /// Large reads are split up so that progress can be reported; some file system drivers are
+    /// also unreliable with very large requests.
+    pub const READ_CHUNK: usize = 1024 * 1024;
+
+    /// Reads ranges of the file straight into physical memory
+    ///
+    /// `progress` is updated after every chunk with the number of bytes read so far and the
+    /// total for all ranges, and finished once all ranges are read or reading fails. Fails with
+    /// `END_OF_FILE` if a range extends past the end of the file.
+    ///
+    /// # Safety
+    ///
+    /// Every range must be valid for writes and not be in use by anything else.
+    pub unsafe fn read_scatter(
+        &mut self,
+        ranges: &[ScatterRange],
+        mut progress: impl Progress,
+    ) -> Result<()> {
+        let total = ranges.iter().map(|r| r.len as u64).sum();
+        let mut done = 0;
+        let mut read = || {
+            for range in ranges {
+                self.set_position(range.file_offset)?;
+                let dest = slice::from_raw_parts_mut(range.addr as *mut u8, range.len);
+                for chunk in dest.chunks_mut(Self::READ_CHUNK) {
+                    self.read_exact(chunk)?;
+                    done += chunk.len() as u64;
+                    progress.update(done, total);
+                }
+            }
+            Ok(())
+        };
+        let result = read();
+        progress.finish(result);
+        result
+    }
+
+    /// Allocates pages and reads `len` bytes starting at `file_offset` into them
+    ///
+    /// Use [`AllocPagesType::Addr`] for images with a fixed load address and
+    /// [`AllocPagesType::Max`] for ones that must stay below a limit. Any remainder of the
+    /// last page is zeroed. The pages are freed again if reading fails.
+    pub fn read_to_pages(
+        &mut self,
+        file_offset: u64,
+        len: usize,
+        placement: AllocPagesType,
+        memory_type: MemoryType,
+        progress: impl Progress,
+    ) -> Result<PhysicalAddr> {
+        let bs = boot_services();
+        let pages = len.div_ceil(4096).max(1);
+        let addr = bs.allocate_pages(placement, memory_type, pages)?;
+        let range = ScatterRange {
+            file_offset,
+            addr,
+            len,
+        };
+        unsafe {
+            if let Err(status) = self.read_scatter(&[range], progress) {
+                let _ = bs.free_pages(addr, pages);
+                return Err(status);
+            }
+            let tail = (addr as *mut u8).add(len);
+            core::ptr::write_bytes(tail, 0, pages * 4096 - len);
+        }
+        Ok(addr)
+    }
+}
+
+/// A range of a file to be read into physical memory by [`File::read_scatter()`]
+#[derive(Clone, Copy, Debug)]
+pub struct ScatterRange {
+    pub file_offset: u64,
+    pub addr:        PhysicalAddr,
+    pub len:         usize,
+}
@@ -0,0 +1,85 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Load File 2 Protocol
+//!
+//! Loads a file other than a boot option from a device which has no file system, such as an
+//! initrd provided by the boot loader to the Linux EFI stub.
+
+use core::{ffi::c_void, ptr};
+
+use super::super::{device_path::DevicePath, Proto, Protocol};
+use crate::{guid, Guid, Result, Status};
+
+pub type LoadFileFn = extern "efiapi" fn(
+    this: *mut LoadFile2,
+    file_path: *const DevicePath,
+    boot_policy: bool,
+    buffer_size: *mut usize,
+    buffer: *mut c_void,
+) -> Status;
+
+#[repr(C)]
+pub struct LoadFile2 {
+    pub(crate) load_file: LoadFileFn,
+}
+
+impl Protocol for LoadFile2 {
+    const GUID: Guid = guid!(
+        0x4006c0c1,0xfcb3,0x403e,
+        {0x99,0x6d,0x4a,0x6c,0x87,0x24,0xe0,0x6d}
+    );
+}
+
+impl Proto<LoadFile2> {
+    /// Returns the size of the file at `path`, relative to the protocol's device
+    pub fn file_size(&self, path: &DevicePath) -> Result<usize> {
+        let mut size = 0;
+        match (self.load_file)(self.as_ptr(), path, false, &mut size, ptr::null_mut()) {
+            Status::BUFFER_TOO_SMALL | Status::SUCCESS => Ok(size),
+            status => Err(status),
+        }
+    }
+
+    /// Reads the file at `path` into `buf`, returning its size
+    ///
+    /// Fails with `BUFFER_TOO_SMALL` if it doesn't fit.
+    pub fn load_file(&self, path: &DevicePath, buf: &mut [u8]) -> Result<usize> {
+        let mut size = buf.len();
+        (self.load_file)(
+            self.as_ptr(),
+            path,
+            false,
+            &mut size,
+            buf.as_mut_ptr().cast(),
+        )
+        .to_result(size)
+    }
+}
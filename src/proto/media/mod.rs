@@ -29,3 +29,11 @@
  */
 
 pub mod block_io;
+pub mod block_io2;
+pub mod disk_io;
+pub mod disk_io2;
+pub mod file;
+pub mod load_file;
+pub mod partition;
+pub mod removable;
+pub mod retry;
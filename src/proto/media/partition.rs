@@ -0,0 +1,261 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Partitions
+//!
+//! Firmware exposes each partition it recognizes as a child handle of the disk, with a device
+//! path ending in a Hard Drive media node. [`locate_partition()`] walks that path back to the
+//! partition's and the disk's Block I/O handles, which is what an installer needs to find, say,
+//! the ESP the running image was booted from.
+
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+#[cfg(feature = "alloc")]
+use core::mem::size_of;
+
+use crate::{
+    guid,
+    proto::device_path::{DevicePath, DevicePathNode, DeviceType},
+    Guid, Lba,
+};
+#[cfg(feature = "alloc")]
+use crate::{
+    proto::{device_path::END_ENTIRE, media::block_io::BlockIo, Proto},
+    table::boot::BootServices,
+    Handle, Result, Status,
+};
+
+/// Signature of the disk a partition belongs to
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PartitionSignature {
+    None,
+    /// The 32-bit MBR disk signature
+    Mbr(u32),
+    /// The partition's unique GUID from its GPT entry
+    Gpt(Guid),
+}
+
+/// A Hard Drive media device path node
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct HardDriveNode {
+    /// The partition's index in the partition table, starting at 1
+    pub partition_number: u32,
+    pub start:            Lba,
+    /// Size of the partition in logical blocks
+    pub size:             u64,
+    pub signature:        PartitionSignature,
+}
+
+impl HardDriveNode {
+    /// Parses `node`, returning `None` if it is not a Hard Drive node
+    pub fn from_node(node: &DevicePathNode) -> Option<Self> {
+        if (node.kind, node.sub_kind) != (DeviceType::MEDIA, 0x01) {
+            return None;
+        }
+        let signature: [u8; 16] = node.read_array(20)?;
+        let signature = match node.read_u8(37)? {
+            0x01 => PartitionSignature::Mbr(u32::from_le_bytes(signature[..4].try_into().ok()?)),
+            0x02 => PartitionSignature::Gpt(Guid::from_bytes(signature)),
+            _ => PartitionSignature::None,
+        };
+        Some(Self {
+            partition_number: node.read_u32(0)?,
+            start: node.read_u64(4)?,
+            size: node.read_u64(12)?,
+            signature,
+        })
+    }
+
+    /// Finds the last Hard Drive node of `path`
+    ///
+    /// Also returns the size in bytes of the prefix of `path` before the node, which is the
+    /// device path of the disk.
+    pub fn find(path: &DevicePath) -> Option<(Self, usize)> {
+        let mut offset = 0;
+        let mut found = None;
+        for node in path.nodes() {
+            if let Some(hd) = Self::from_node(&node) {
+                found = Some((hd, offset));
+            }
+            offset += node.len();
+        }
+        found
+    }
+}
+
+/// `EFI_PARTITION_ENTRY`
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct GptEntry {
+    pub partition_type: Guid,
+    pub unique_guid:    Guid,
+    pub starting_lba:   Lba,
+    /// The last LBA of the partition, inclusive
+    pub ending_lba:     Lba,
+    pub attributes:     u64,
+    name:               [u16; 36],
+}
+
+impl GptEntry {
+    /// The EFI System Partition type GUID
+    pub const ESP: Guid = guid!(
+        0xc12a7328,0xf81f,0x11d2,
+        {0xba,0x4b,0x00,0xa0,0xc9,0x3e,0xc9,0x3b}
+    );
+
+    /// Returns the partition's name, up to the first null
+    pub fn name(&self) -> &[u16] {
+        let len = self.name.iter().position(|&c| c == 0);
+        &self.name[..len.unwrap_or(self.name.len())]
+    }
+
+    pub fn is_esp(&self) -> bool {
+        self.partition_type == Self::ESP
+    }
+}
+
+/// Where a partition handle sits on its disk
+#[cfg(feature = "alloc")]
+#[derive(Clone, Copy, Debug)]
+pub struct PartitionLocation {
+    /// The partition's Block I/O handle
+    pub partition:  Handle,
+    /// The Block I/O handle of the whole disk
+    pub disk:       Handle,
+    pub hard_drive: HardDriveNode,
+}
+
+/// Maps `handle`, e.g. one with the Simple File System Protocol, back to its partition and disk
+///
+/// Fails with `NOT_FOUND` if the handle's device path has no Hard Drive node or the firmware
+/// has no Block I/O handle for the partition or the disk.
+#[cfg(feature = "alloc")]
+pub fn locate_partition(bs: &BootServices, handle: Handle) -> Result<PartitionLocation> {
+    let path = bs.protocol_for_handle::<DevicePath>(handle)?;
+    let (hard_drive, prefix_len) = HardDriveNode::find(&path).ok_or(Status::NOT_FOUND)?;
+
+    let (partition, _) = bs.locate_device_path::<BlockIo>(&path)?;
+
+    let mut disk_path = Vec::with_capacity(prefix_len + size_of::<DevicePath>());
+    disk_path.extend_from_slice(&path.as_bytes()[..prefix_len]);
+    disk_path.extend_from_slice(&[DeviceType::END.0, END_ENTIRE, 4, 0]);
+    let disk_path = DevicePath::from_bytes(&disk_path)?;
+    let (disk, rest) = bs.locate_device_path::<BlockIo>(disk_path)?;
+    // A partial match is some controller above the disk, not the disk itself.
+    if rest.nodes().next().is_some() || disk == partition {
+        return Err(Status::NOT_FOUND);
+    }
+
+    Ok(PartitionLocation {
+        partition,
+        disk,
+        hard_drive,
+    })
+}
+
+/// Locates the partition the running image was loaded from
+#[cfg(feature = "alloc")]
+pub fn boot_partition(bs: &BootServices) -> Result<PartitionLocation> {
+    let image = crate::proto::loaded_image::loaded_image()?;
+    locate_partition(bs, image.device_handle.ok_or(Status::NOT_FOUND)?)
+}
+
+#[cfg(feature = "alloc")]
+impl PartitionLocation {
+    /// Reads the partition's entry from the disk's GPT
+    ///
+    /// Fails with `UNSUPPORTED` if the partition is not on a GPT disk, and `VOLUME_CORRUPTED`
+    /// if the table does not describe the partition the firmware reported.
+    pub fn gpt_entry(&self, bs: &BootServices) -> Result<GptEntry> {
+        let PartitionSignature::Gpt(unique_guid) = self.hard_drive.signature else {
+            return Err(Status::UNSUPPORTED);
+        };
+        let mut disk = bs.protocol_for_handle::<BlockIo>(self.disk)?;
+        let mut buf = Vec::new();
+
+        let header = read(&mut disk, 1, 92, &mut buf)?;
+        if &header[..8] != b"EFI PART" {
+            return Err(Status::VOLUME_CORRUPTED);
+        }
+        let entries_lba = u64::from_le_bytes(header[72..80].try_into().unwrap());
+        let num_entries = u32::from_le_bytes(header[80..84].try_into().unwrap());
+        let entry_size = u32::from_le_bytes(header[84..88].try_into().unwrap()) as usize;
+        let index = self.hard_drive.partition_number.wrapping_sub(1);
+        if index >= num_entries || entry_size < size_of::<GptEntry>() {
+            return Err(Status::VOLUME_CORRUPTED);
+        }
+
+        let block_size = disk.media().block_size as usize;
+        let offset = (index as usize)
+            .checked_mul(entry_size)
+            .ok_or(Status::VOLUME_CORRUPTED)?;
+        let lba = entries_lba
+            .checked_add((offset / block_size) as u64)
+            .ok_or(Status::VOLUME_CORRUPTED)?;
+        let offset = offset % block_size;
+        let data = read(&mut disk, lba, offset + size_of::<GptEntry>(), &mut buf)?;
+        let entry = unsafe {
+            data.as_ptr()
+                .add(offset)
+                .cast::<GptEntry>()
+                .read_unaligned()
+        };
+
+        if entry.unique_guid != unique_guid || entry.starting_lba != self.hard_drive.start {
+            return Err(Status::VOLUME_CORRUPTED);
+        }
+        Ok(entry)
+    }
+}
+
+/// Reads at least `len` bytes starting at `lba`, honoring the device's buffer alignment
+///
+/// Fails with `DEVICE_ERROR` if the device reports a block size of zero.
+#[cfg(feature = "alloc")]
+fn read<'b>(
+    disk: &mut Proto<BlockIo>,
+    lba: Lba,
+    len: usize,
+    buf: &'b mut Vec<u8>,
+) -> Result<&'b [u8]> {
+    let media = disk.media();
+    let (media_id, block_size) = (media.media_id, media.block_size as usize);
+    if block_size == 0 {
+        return Err(Status::DEVICE_ERROR);
+    }
+    let align = media.io_align.max(1) as usize;
+    let len = len.div_ceil(block_size) * block_size;
+    buf.clear();
+    buf.resize(len + align - 1, 0);
+    let start = buf.as_ptr().align_offset(align);
+    let data = &mut buf[start..start + len];
+    disk.read_blocks(media_id, lba, data)?;
+    Ok(data)
+}
@@ -0,0 +1,331 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Following removable media
+//!
+//! Block I/O interfaces don't outlive the media they were opened for: drivers reinstall the
+//! protocol when a card is swapped, and a USB stick gets a new handle each time it is plugged
+//! in. [`BlockIoWatch`] reports the handles Block I/O appears on, e.g. so a boot menu can offer
+//! a stick inserted after it was shown, and [`BlockDevice`] follows a device across such
+//! changes by its device path.
+//!
+//! [`probe_removable_boot_paths()`] finds the removable media firmware would boot from by
+//! default, for boot managers to offer them alongside their own entries.
+
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+use core::ptr;
+
+use super::block_io::BlockIo;
+#[cfg(feature = "alloc")]
+use super::{
+    block_io::BlockIoMedia,
+    file::{FileAttribute, FileMode, SimpleFileSystem},
+};
+use crate::{
+    boot_services,
+    table::{EventType, ProtocolRegistration},
+    ucs2::{cstr16, CStr16},
+    Event, Handle, Result, Tpl,
+};
+#[cfg(feature = "alloc")]
+use crate::{
+    proto::{
+        device_path::{DeviceType, END_ENTIRE},
+        DevicePath, Proto,
+    },
+    Lba, Status,
+};
+
+/// The file firmware boots from removable media which have no boot option of their own
+#[cfg(target_arch = "x86_64")]
+pub const DEFAULT_BOOT_FILE: &CStr16 = cstr16!("\\EFI\\BOOT\\BOOTX64.EFI");
+/// The file firmware boots from removable media which have no boot option of their own
+#[cfg(target_arch = "x86")]
+pub const DEFAULT_BOOT_FILE: &CStr16 = cstr16!("\\EFI\\BOOT\\BOOTIA32.EFI");
+/// The file firmware boots from removable media which have no boot option of their own
+#[cfg(target_arch = "aarch64")]
+pub const DEFAULT_BOOT_FILE: &CStr16 = cstr16!("\\EFI\\BOOT\\BOOTAA64.EFI");
+/// The file firmware boots from removable media which have no boot option of their own
+#[cfg(target_arch = "arm")]
+pub const DEFAULT_BOOT_FILE: &CStr16 = cstr16!("\\EFI\\BOOT\\BOOTARM.EFI");
+/// The file firmware boots from removable media which have no boot option of their own
+#[cfg(target_arch = "riscv64")]
+pub const DEFAULT_BOOT_FILE: &CStr16 = cstr16!("\\EFI\\BOOT\\BOOTRISCV64.EFI");
+
+/// Notification of Block I/O being installed or reinstalled on any handle
+///
+/// Only changes after the watch was created are reported.
+#[derive(Debug)]
+pub struct BlockIoWatch {
+    event:        Event,
+    registration: ProtocolRegistration,
+}
+
+impl BlockIoWatch {
+    pub fn new() -> Result<Self> {
+        let bs = boot_services();
+        let event =
+            unsafe { bs.create_event(EventType::empty(), Tpl::CALLBACK, None, ptr::null_mut())? };
+        match bs.register_protocol_notify::<BlockIo>(event) {
+            Ok(registration) => Ok(Self {
+                event,
+                registration,
+            }),
+            Err(status) => {
+                let _ = bs.close_event(event);
+                Err(status)
+            }
+        }
+    }
+
+    /// Returns the event signaled on each change, to wait on alongside other input
+    pub fn event(&self) -> Event {
+        self.event
+    }
+
+    /// Returns whether there was a change since the last call
+    pub fn changed(&self) -> bool {
+        boot_services().check_event(self.event).unwrap_or(false)
+    }
+
+    /// Returns the next handle Block I/O was installed or reinstalled on
+    pub fn next_handle(&mut self) -> Option<Handle> {
+        boot_services()
+            .locate_notified_handle(self.registration)
+            .ok()
+            .flatten()
+    }
+}
+
+impl Drop for BlockIoWatch {
+    fn drop(&mut self) {
+        let _ = boot_services().close_event(self.event);
+    }
+}
+
+/// A Block I/O device which is reopened when its media or handle changes
+///
+/// The device is identified by the device path of the handle it was opened on. Reads check
+/// that the interface is still installed on the handle first, and look the path up again if
+/// it isn't, or if the device reports `MEDIA_CHANGED` or `NO_MEDIA`. Data read before a change
+/// may be from different media; compare [`media_id()`](Self::media_id) to tell.
+#[cfg(feature = "alloc")]
+pub struct BlockDevice {
+    path:     Vec<u8>,
+    handle:   Handle,
+    io:       Proto<BlockIo>,
+    media_id: u32,
+    watch:    BlockIoWatch,
+}
+
+#[cfg(feature = "alloc")]
+impl BlockDevice {
+    pub fn open(handle: Handle) -> Result<Self> {
+        let bs = boot_services();
+        // Created first, so that no reinstallation goes unnoticed.
+        let watch = BlockIoWatch::new()?;
+        let path = bs
+            .protocol_for_handle::<DevicePath>(handle)?
+            .as_bytes()
+            .to_vec();
+        DevicePath::from_bytes(&path)?;
+        let io = bs.protocol_for_handle::<BlockIo>(handle)?;
+        Ok(Self {
+            path,
+            handle,
+            media_id: io.media().media_id,
+            io,
+            watch,
+        })
+    }
+
+    pub fn handle(&self) -> Handle {
+        self.handle
+    }
+
+    pub fn device_path(&self) -> &DevicePath {
+        // Validated in `open()`.
+        unsafe { validated_path(&self.path) }
+    }
+
+    pub fn media(&self) -> &BlockIoMedia {
+        self.io.media()
+    }
+
+    /// Returns the ID of the media reads go to
+    pub fn media_id(&self) -> u32 {
+        self.media_id
+    }
+
+    /// Returns an event signaled whenever the device may have changed
+    pub fn event(&self) -> Event {
+        self.watch.event()
+    }
+
+    /// Looks the device up again by its device path and reopens it
+    ///
+    /// Returns whether the handle or media changed. Fails with `NO_MEDIA` if no handle with
+    /// the device path has Block I/O, e.g. while a USB stick is unplugged.
+    pub fn refresh(&mut self) -> Result<bool> {
+        let bs = boot_services();
+        while self.watch.next_handle().is_some() {}
+        self.watch.changed();
+
+        let (handle, rest) = bs
+            .locate_device_path::<BlockIo>(self.device_path())
+            .map_err(|_| Status::NO_MEDIA)?;
+        // A partial match is some controller above the device.
+        if rest.nodes().next().is_some() {
+            return Err(Status::NO_MEDIA);
+        }
+        let io = bs.protocol_for_handle::<BlockIo>(handle)?;
+        let media_id = io.media().media_id;
+        let changed = handle != self.handle || media_id != self.media_id;
+        (self.handle, self.io, self.media_id) = (handle, io, media_id);
+        Ok(changed)
+    }
+
+    /// Waits until the device has media, e.g. for a USB stick to be plugged back in
+    pub fn wait_for_media(&mut self) -> Result<()> {
+        loop {
+            match self.refresh() {
+                Ok(_) if self.media().media_present => return Ok(()),
+                Ok(_) | Err(Status::NO_MEDIA) => {}
+                Err(status) => return Err(status),
+            }
+            boot_services().wait_for_event(&[self.watch.event()])?;
+        }
+    }
+
+    /// Reads blocks from the current media, reopening the device as needed
+    ///
+    /// A read failing with `MEDIA_CHANGED` or `NO_MEDIA` is retried once after a
+    /// [`refresh()`](Self::refresh).
+    pub fn read_blocks(&mut self, lba: Lba, buf: &mut [u8]) -> Result<()> {
+        if !self.is_current() {
+            self.refresh()?;
+        }
+        match self.io.read_blocks(self.media_id, lba, buf) {
+            Err(Status::MEDIA_CHANGED | Status::NO_MEDIA) => {
+                self.refresh()?;
+                self.io.read_blocks(self.media_id, lba, buf)
+            }
+            result => result,
+        }
+    }
+
+    /// Returns whether the interface is still installed on the handle
+    fn is_current(&self) -> bool {
+        match boot_services().protocol_for_handle::<BlockIo>(self.handle) {
+            Ok(io) => io.as_ptr() == self.io.as_ptr(),
+            Err(_) => false,
+        }
+    }
+}
+
+/// Removable media with a [`DEFAULT_BOOT_FILE`]
+#[cfg(feature = "alloc")]
+#[derive(Clone, Debug)]
+pub struct BootCandidate {
+    /// The handle with the file system
+    pub handle: Handle,
+    path:       Vec<u8>,
+}
+
+#[cfg(feature = "alloc")]
+impl BootCandidate {
+    /// Returns the full path of the boot file, to pass to
+    /// [`load_image_from_path()`](crate::table::BootServices::load_image_from_path)
+    pub fn device_path(&self) -> &DevicePath {
+        // Validated in `probe_removable_boot_paths()`.
+        unsafe { validated_path(&self.path) }
+    }
+}
+
+/// Searches every file system on removable media with media present for [`DEFAULT_BOOT_FILE`]
+///
+/// Candidates are returned in handle database order. File systems which can't be opened, or
+/// whose device path is malformed, are skipped.
+#[cfg(feature = "alloc")]
+pub fn probe_removable_boot_paths() -> Result<Vec<BootCandidate>> {
+    let bs = boot_services();
+    let mut candidates = Vec::new();
+    for &handle in bs.all_handles()?.iter() {
+        let removable = bs
+            .protocol_for_handle::<BlockIo>(handle)
+            .is_ok_and(|io| io.media().removable_media && io.media().media_present);
+        if !removable {
+            continue;
+        }
+        let Ok(mut fs) = bs.protocol_for_handle::<SimpleFileSystem>(handle) else {
+            continue;
+        };
+        let Ok(device) = bs.protocol_for_handle::<DevicePath>(handle) else {
+            continue;
+        };
+        let found = fs.open_volume().and_then(|mut root| {
+            // Enough for a `FileInfo` with the file's name.
+            let mut buf = [0u8; 128];
+            let mut file = root.open(DEFAULT_BOOT_FILE, FileMode::READ, FileAttribute::empty())?;
+            Ok(!file.info(&mut buf)?.is_directory())
+        });
+        if found != Ok(true) {
+            continue;
+        }
+        let path = file_path(&device, DEFAULT_BOOT_FILE);
+        if DevicePath::from_bytes(&path).is_ok() {
+            candidates.push(BootCandidate { handle, path });
+        }
+    }
+    Ok(candidates)
+}
+
+/// Appends a File Path node for `file` to `device`
+#[cfg(feature = "alloc")]
+fn file_path(device: &DevicePath, file: &CStr16) -> Vec<u8> {
+    let bytes = device.as_bytes();
+    let prefix = &bytes[..bytes.len() - 4];
+    let name = file.as_slice_with_nul();
+    let node_len = 4 + name.len() * 2;
+    let mut path = Vec::with_capacity(prefix.len() + node_len + 4);
+    path.extend_from_slice(prefix);
+    path.extend_from_slice(&[DeviceType::MEDIA.0, 0x04]);
+    path.extend_from_slice(&(node_len as u16).to_le_bytes());
+    path.extend(name.iter().flat_map(|unit| unit.to_le_bytes()));
+    path.extend_from_slice(&[DeviceType::END.0, END_ENTIRE, 4, 0]);
+    path
+}
+
+/// Returns the device path in `bytes`, which must have passed [`DevicePath::from_bytes()`]
+#[cfg(feature = "alloc")]
+unsafe fn validated_path(bytes: &[u8]) -> &DevicePath {
+    &*bytes.as_ptr().cast()
+}
@@ -0,0 +1,127 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Retrying transient device errors
+//!
+//! USB and SD media often fail the first reads after power-up or a reset with `NOT_READY` or
+//! `DEVICE_ERROR`, and removable media report `MEDIA_CHANGED` once after being reinserted. A
+//! [`RetryPolicy`] repeats such reads a few times, stalling between attempts with an
+//! exponential backoff. It is used by
+//! [`Proto<BlockIo>::read_blocks_retry()`](crate::proto::Proto::read_blocks_retry) and
+//! [`Proto<DiskIo>::read_disk_retry()`](crate::proto::Proto::read_disk_retry), and can wrap
+//! any other operation with [`run()`](RetryPolicy::run).
+
+use core::time::Duration;
+
+use crate::{boot_services, Result, Status};
+
+/// How often, and how patiently, to retry an operation
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first; 0 is treated as 1
+    pub attempts:  u32,
+    /// Stall before the first retry
+    pub delay:     Duration,
+    /// Factor the stall grows by after each retry
+    pub backoff:   u32,
+    /// Longest stall between two attempts
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// A single attempt
+    pub const NONE: Self = Self::new(1);
+
+    /// Retries up to `attempts` times in total, starting with a 10 ms stall which doubles up to
+    /// 500 ms
+    pub const fn new(attempts: u32) -> Self {
+        Self {
+            attempts,
+            delay: Duration::from_millis(10),
+            backoff: 2,
+            max_delay: Duration::from_millis(500),
+        }
+    }
+
+    pub const fn with_delay(mut self, delay: Duration, max_delay: Duration) -> Self {
+        self.delay = delay;
+        self.max_delay = max_delay;
+        self
+    }
+
+    pub const fn with_backoff(mut self, backoff: u32) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Returns whether a failure with `status` is worth retrying
+    pub fn is_transient(status: Status) -> bool {
+        matches!(
+            status,
+            Status::DEVICE_ERROR | Status::NOT_READY | Status::MEDIA_CHANGED
+        )
+    }
+
+    /// Runs `op` until it succeeds, fails with a non-[transient](Self::is_transient) error or
+    /// runs out of attempts
+    ///
+    /// The last error is returned.
+    pub fn run<T>(&self, op: impl FnMut() -> Result<T>) -> Result<T> {
+        self.run_if(op, Self::is_transient)
+    }
+
+    /// Like [`run()`](Self::run), but retries the errors for which `retry` returns `true`
+    pub fn run_if<T>(
+        &self,
+        mut op: impl FnMut() -> Result<T>,
+        mut retry: impl FnMut(Status) -> bool,
+    ) -> Result<T> {
+        let mut delay = self.delay.min(self.max_delay);
+        let mut attempt = 1;
+        loop {
+            match op() {
+                Err(status) if attempt < self.attempts && retry(status) => {
+                    // A failed stall only makes the retry come sooner.
+                    let _ = boot_services().stall(delay.as_micros() as usize);
+                    delay = delay.saturating_mul(self.backoff).min(self.max_delay);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    /// Three attempts
+    fn default() -> Self {
+        Self::new(3)
+    }
+}
@@ -0,0 +1,123 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+use crate::{
+    guid,
+    proto::{Proto, Protocol},
+    table::MemoryAttribute,
+    Guid, PhysicalAddr, Result, Status,
+};
+
+pub type GetMemoryAttributesFn = extern "efiapi" fn(
+    this: *mut MemoryAttributeProtocol,
+    base_address: PhysicalAddr,
+    length: u64,
+    attributes: *mut MemoryAttribute,
+) -> Status;
+
+pub type SetMemoryAttributesFn = extern "efiapi" fn(
+    this: *mut MemoryAttributeProtocol,
+    base_address: PhysicalAddr,
+    length: u64,
+    attributes: MemoryAttribute,
+) -> Status;
+
+pub type ClearMemoryAttributesFn = extern "efiapi" fn(
+    this: *mut MemoryAttributeProtocol,
+    base_address: PhysicalAddr,
+    length: u64,
+    attributes: MemoryAttribute,
+) -> Status;
+
+/// Memory Attribute Protocol
+///
+/// Allows an image to query and change the paging permissions of memory it owns.
+/// Only the [`RP`], [`XP`], and [`RO`] attributes are accepted by this protocol,
+/// and both `base_address` and `length` must be page aligned.
+///
+/// [`RP`]: MemoryAttribute::RP
+/// [`XP`]: MemoryAttribute::XP
+/// [`RO`]: MemoryAttribute::RO
+#[repr(C)]
+pub struct MemoryAttributeProtocol {
+    get_memory_attributes:   GetMemoryAttributesFn,
+    set_memory_attributes:   SetMemoryAttributesFn,
+    clear_memory_attributes: ClearMemoryAttributesFn,
+}
+
+impl Protocol for MemoryAttributeProtocol {
+    const GUID: Guid = guid!(
+        0xf4560cf6,0x40ec,0x4b4a,
+        {0xa1,0x92,0xbf,0x1d,0x57,0xd0,0xb1,0x89}
+    );
+}
+
+impl MemoryAttributeProtocol {
+    /// Attributes which may be passed to this protocol
+    pub const SUPPORTED: MemoryAttribute = MemoryAttribute::from_bits_truncate(
+        MemoryAttribute::RP.bits() | MemoryAttribute::XP.bits() | MemoryAttribute::RO.bits(),
+    );
+}
+
+impl Proto<MemoryAttributeProtocol> {
+    /// Returns the attributes of the given range
+    ///
+    /// Fails with `NO_MAPPING` if the range is not mapped, or `INVALID_PARAMETER` if the
+    /// attributes are not uniform across the entire range.
+    pub fn get_memory_attributes(
+        &mut self,
+        base: PhysicalAddr,
+        length: u64,
+    ) -> Result<MemoryAttribute> {
+        let mut attributes = MemoryAttribute::empty();
+        (self.get_memory_attributes)(self.as_ptr(), base, length, &mut attributes)
+            .to_result(attributes)
+    }
+
+    /// Sets `attributes` on the given range, leaving other attributes untouched
+    pub fn set_memory_attributes(
+        &mut self,
+        base: PhysicalAddr,
+        length: u64,
+        attributes: MemoryAttribute,
+    ) -> Result<()> {
+        (self.set_memory_attributes)(self.as_ptr(), base, length, attributes).to_result(())
+    }
+
+    /// Clears `attributes` from the given range, leaving other attributes untouched
+    pub fn clear_memory_attributes(
+        &mut self,
+        base: PhysicalAddr,
+        length: u64,
+        attributes: MemoryAttribute,
+    ) -> Result<()> {
+        (self.clear_memory_attributes)(self.as_ptr(), base, length, attributes).to_result(())
+    }
+}
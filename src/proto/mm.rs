@@ -0,0 +1,134 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! MM Communication 2 Protocol (PI)
+//!
+//! Passes messages to handlers running in Management Mode (SMM on x86, a secure partition on
+//! Arm), such as the trusted back-end of the variable services. Each message starts with an
+//! `EFI_MM_COMMUNICATE_HEADER` naming the handler by GUID; [`CommBuffer`] takes care of the
+//! framing.
+
+use core::{ffi::c_void, mem::size_of};
+
+use crate::{
+    guid,
+    proto::{Proto, Protocol},
+    Guid, Result, Status,
+};
+
+pub type CommunicateFn = extern "efiapi" fn(
+    this: *mut MmCommunication2,
+    comm_buffer_physical: *mut c_void,
+    comm_buffer_virtual: *mut c_void,
+    comm_size: *mut usize,
+) -> Status;
+
+/// MM Communication 2 Protocol
+#[repr(C)]
+pub struct MmCommunication2 {
+    communicate: CommunicateFn,
+}
+
+impl Protocol for MmCommunication2 {
+    const GUID: Guid = guid!(
+        0x378daedc,0xf06b,0x4446,
+        {0x83,0x14,0x40,0xab,0x93,0x3c,0x87,0xa3}
+    );
+}
+
+/// Size of `EFI_MM_COMMUNICATE_HEADER`, which precedes every message
+pub const HEADER_SIZE: usize = 16 + size_of::<usize>();
+
+/// A communication buffer with room for the header and a message
+///
+/// Firmware only accepts buffers in memory it has made accessible to MM, usually a region
+/// listed in the `EDKII_PI_SMM_COMMUNICATION_REGION_TABLE` configuration table or runtime
+/// services data; a buffer on the stack will be rejected on most platforms.
+pub struct CommBuffer<'b> {
+    buf: &'b mut [u8],
+}
+
+impl<'b> CommBuffer<'b> {
+    /// Fails with `BAD_BUFFER_SIZE` if `buf` can't hold the header
+    pub fn new(buf: &'b mut [u8]) -> Result<Self> {
+        if buf.len() < HEADER_SIZE {
+            return Err(Status::BAD_BUFFER_SIZE);
+        }
+        Ok(Self { buf })
+    }
+
+    /// Returns the space for the message, after the header
+    pub fn payload_mut(&mut self) -> &mut [u8] {
+        &mut self.buf[HEADER_SIZE..]
+    }
+
+    /// Returns the largest message which fits in the buffer
+    pub fn capacity(&self) -> usize {
+        self.buf.len() - HEADER_SIZE
+    }
+
+    /// Writes the header for a `len` byte message to `handler`
+    fn frame(&mut self, handler: &Guid, len: usize) -> Result<usize> {
+        if len > self.capacity() {
+            return Err(Status::BAD_BUFFER_SIZE);
+        }
+        self.buf[..16].copy_from_slice(&handler.to_bytes());
+        self.buf[16..HEADER_SIZE].copy_from_slice(&len.to_ne_bytes());
+        Ok(HEADER_SIZE + len)
+    }
+
+    /// Returns the message written back by the handler
+    fn response(&self) -> Result<&[u8]> {
+        let len = usize::from_ne_bytes(self.buf[16..HEADER_SIZE].try_into().unwrap());
+        self.buf[HEADER_SIZE..]
+            .get(..len)
+            .ok_or(Status::PROTOCOL_ERROR)
+    }
+}
+
+impl Proto<MmCommunication2> {
+    /// Sends the first `len` bytes of the buffer's payload to `handler` and returns its reply
+    ///
+    /// The reply overwrites the request in place. Fails with `BAD_BUFFER_SIZE` if `len`
+    /// exceeds the buffer's capacity, with `NOT_STARTED` if MM is not available, or with
+    /// `PROTOCOL_ERROR` if the handler claims a reply larger than the buffer.
+    pub fn communicate<'c>(
+        &mut self,
+        handler: &Guid,
+        buffer: &'c mut CommBuffer<'_>,
+        len: usize,
+    ) -> Result<&'c [u8]> {
+        let mut size = buffer.frame(handler, len)?;
+        // Identity mapped before `SetVirtualAddressMap()`.
+        let ptr = buffer.buf.as_mut_ptr().cast();
+        (self.communicate)(self.as_ptr(), ptr, ptr, &mut size).to_result(())?;
+        buffer.response()
+    }
+}
@@ -33,16 +33,125 @@ use core::{
     ptr::NonNull,
 };
 
-use super::{guid, Guid};
+use super::Guid;
 
+pub mod arch;
+pub mod bluetooth;
 pub mod console;
+pub mod decompress;
+pub mod device_path;
+pub mod driver_override;
+pub mod firmware_volume;
+pub mod hash2;
+pub mod loaded_image;
 pub mod media;
-pub mod riscv;
+pub mod memory_attribute;
+pub mod mm;
+pub mod network;
+pub mod service_binding;
+pub mod shell;
+pub mod speaker;
+pub mod timestamp;
+pub mod unicode_collation;
+pub mod variable_policy;
+
+pub use device_path::DevicePath;
+
+/// The RISC-V Boot Protocol, which now lives in [`arch::riscv`]
+#[deprecated(note = "moved to `proto::arch::riscv`")]
+pub mod riscv {
+    pub use super::arch::riscv::*;
+}
 
 pub trait Protocol {
     const GUID: Guid;
 }
 
+/// Returns the name of a protocol known to this crate, for diagnostics
+pub fn protocol_name(guid: &Guid) -> Option<&'static str> {
+    use self::{
+        arch::{mp_services::MpServices, riscv::RiscvBoot},
+        bluetooth::{BluetoothConfig, BluetoothLeConfig},
+        console::{
+            console_control::ConsoleControl,
+            gop::{EdidActive, EdidDiscovered, EdidOverride, GraphicsOutput},
+            pointer::SimplePointer,
+            serial::SerialIo,
+            text_input::SimpleTextInput,
+            text_input_ex::SimpleTextInputEx,
+            text_output::SimpleTextOutput,
+            uga::UgaDraw,
+        },
+        decompress::Decompress,
+        driver_override::{BusSpecificDriverOverride, PlatformDriverOverride},
+        firmware_volume::FirmwareVolume2,
+        hash2::Hash2,
+        loaded_image::{LoadedImage, LoadedImageDevicePath},
+        media::{
+            block_io::BlockIo, block_io2::BlockIo2, disk_io::DiskIo, disk_io2::DiskIo2,
+            file::SimpleFileSystem, load_file::LoadFile2,
+        },
+        memory_attribute::MemoryAttributeProtocol,
+        mm::MmCommunication2,
+        network::{http::Http, rest::RestEx, supplicant::Supplicant, wifi::WirelessMacConnection2},
+        shell::{ShellDynamicCommand, ShellParameters},
+        speaker::SpeakerIo,
+        timestamp::Timestamp,
+        unicode_collation::UnicodeCollation,
+        variable_policy::VariablePolicy,
+    };
+
+    macro_rules! names {
+        ($($proto:ty => $name:literal),* $(,)?) => {
+            $(if *guid == <$proto>::GUID { return Some($name); })*
+        };
+    }
+    names! {
+        MpServices => "MpServices",
+        RiscvBoot => "RiscvBoot",
+        BluetoothConfig => "BluetoothConfig",
+        BluetoothLeConfig => "BluetoothLeConfig",
+        ConsoleControl => "ConsoleControl",
+        EdidActive => "EdidActive",
+        EdidDiscovered => "EdidDiscovered",
+        EdidOverride => "EdidOverride",
+        GraphicsOutput => "GraphicsOutput",
+        SimplePointer => "SimplePointer",
+        SerialIo => "SerialIo",
+        SimpleTextInput => "SimpleTextInput",
+        SimpleTextInputEx => "SimpleTextInputEx",
+        SimpleTextOutput => "SimpleTextOutput",
+        UgaDraw => "UgaDraw",
+        Decompress => "Decompress",
+        DevicePath => "DevicePath",
+        BusSpecificDriverOverride => "BusSpecificDriverOverride",
+        PlatformDriverOverride => "PlatformDriverOverride",
+        FirmwareVolume2 => "FirmwareVolume2",
+        Hash2 => "Hash2",
+        LoadedImage => "LoadedImage",
+        LoadedImageDevicePath => "LoadedImageDevicePath",
+        BlockIo => "BlockIo",
+        BlockIo2 => "BlockIo2",
+        DiskIo => "DiskIo",
+        DiskIo2 => "DiskIo2",
+        SimpleFileSystem => "SimpleFileSystem",
+        LoadFile2 => "LoadFile2",
+        MemoryAttributeProtocol => "MemoryAttribute",
+        MmCommunication2 => "MmCommunication2",
+        Http => "Http",
+        RestEx => "RestEx",
+        Supplicant => "Supplicant",
+        WirelessMacConnection2 => "WirelessMacConnection2",
+        ShellDynamicCommand => "ShellDynamicCommand",
+        ShellParameters => "ShellParameters",
+        SpeakerIo => "SpeakerIo",
+        Timestamp => "Timestamp",
+        UnicodeCollation => "UnicodeCollation",
+        VariablePolicy => "VariablePolicy",
+    }
+    None
+}
+
 #[repr(transparent)]
 #[derive(Debug)]
 pub struct Proto<P: Protocol> {
@@ -50,6 +159,10 @@ pub struct Proto<P: Protocol> {
 }
 
 impl<P: Protocol> Proto<P> {
+    pub(crate) const unsafe fn new(ptr: NonNull<P>) -> Self {
+        Self { ptr }
+    }
+
     pub const fn as_ptr(&self) -> *mut P {
         self.ptr.as_ptr()
     }
@@ -76,10 +189,3 @@ impl<P: Protocol> DerefMut for Proto<P> {
         unsafe { self.ptr.as_mut() }
     }
 }
-
-
-pub struct DevicePath {}
-
-impl Protocol for DevicePath {
-    const GUID: Guid = guid!(0, 0, 0, {0,0,0,0,0,0,0,0});
-}
@@ -0,0 +1,334 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! HTTP Protocol and message structures
+//!
+//! The message structures are shared by the HTTP and REST protocols. Header names and values
+//! are ASCII strings; URLs are UCS-2.
+//!
+//! HTTP requests complete through a [`Completion`], so they are driven by an executor such
+//! as [`block_on()`](crate::executor::block_on).
+
+use core::{
+    ffi::{c_char, c_void, CStr},
+    pin::pin,
+    ptr,
+};
+
+use crate::{
+    executor::{Completion, Token},
+    guid,
+    proto::{service_binding::ServiceProtocol, Proto, Protocol},
+    Event, Guid, Result, Status,
+};
+
+/// `EFI_HTTP_METHOD`
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct HttpMethod(pub u32);
+
+impl HttpMethod {
+    pub const GET: Self = Self(0);
+    pub const POST: Self = Self(1);
+    pub const PATCH: Self = Self(2);
+    pub const OPTIONS: Self = Self(3);
+    pub const CONNECT: Self = Self(4);
+    pub const HEAD: Self = Self(5);
+    pub const PUT: Self = Self(6);
+    pub const DELETE: Self = Self(7);
+    pub const TRACE: Self = Self(8);
+}
+
+/// `EFI_HTTP_STATUS_CODE`, an index into a fixed list of status codes
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct HttpStatusCode(pub u32);
+
+/// The status codes in `EFI_HTTP_STATUS_CODE` order
+const STATUS_CODES: [u16; 43] = [
+    0, 100, 101, 200, 201, 202, 203, 204, 205, 206, 300, 301, 302, 303, 304, 305, 307, 400, 401,
+    402, 403, 404, 405, 406, 407, 408, 409, 410, 411, 412, 413, 414, 415, 416, 417, 500, 501, 502,
+    503, 504, 505, 308, 429,
+];
+
+impl HttpStatusCode {
+    /// A status code without an `EFI_HTTP_STATUS_CODE` value
+    pub const UNSUPPORTED: Self = Self(0);
+
+    /// Returns the numeric status code, or `None` if it is unsupported
+    pub fn code(self) -> Option<u16> {
+        STATUS_CODES
+            .get(self.0 as usize)
+            .copied()
+            .filter(|&code| code != 0)
+    }
+
+    pub fn from_code(code: u16) -> Self {
+        let index = STATUS_CODES.iter().position(|&c| c == code && c != 0);
+        Self(index.unwrap_or(0) as u32)
+    }
+
+    pub fn is_success(self) -> bool {
+        self.code().is_some_and(|code| (200..300).contains(&code))
+    }
+}
+
+/// `EFI_HTTP_REQUEST_DATA`
+#[repr(C)]
+#[derive(Debug)]
+pub struct HttpRequestData {
+    pub method: HttpMethod,
+    pub url:    *const u16,
+}
+
+/// `EFI_HTTP_RESPONSE_DATA`
+#[repr(C)]
+#[derive(Debug)]
+pub struct HttpResponseData {
+    pub status_code: HttpStatusCode,
+}
+
+/// `EFI_HTTP_HEADER`
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct HttpHeader {
+    pub field_name:  *const c_char,
+    pub field_value: *const c_char,
+}
+
+impl HttpHeader {
+    pub fn new(name: &CStr, value: &CStr) -> Self {
+        Self {
+            field_name:  name.as_ptr(),
+            field_value: value.as_ptr(),
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `field_name` must point to a null-terminated string which outlives `'a`.
+    pub unsafe fn name<'a>(&self) -> &'a CStr {
+        CStr::from_ptr(self.field_name)
+    }
+
+    /// # Safety
+    ///
+    /// `field_value` must point to a null-terminated string which outlives `'a`.
+    pub unsafe fn value<'a>(&self) -> &'a CStr {
+        CStr::from_ptr(self.field_value)
+    }
+}
+
+/// `EFI_HTTP_MESSAGE`
+///
+/// `data` points to an [`HttpRequestData`] in requests and an [`HttpResponseData`] in
+/// responses.
+#[repr(C)]
+#[derive(Debug)]
+pub struct HttpMessage {
+    pub data:         *mut c_void,
+    pub header_count: usize,
+    pub headers:      *mut HttpHeader,
+    pub body_length:  usize,
+    pub body:         *mut c_void,
+}
+
+/// `EFI_HTTP_VERSION`
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct HttpVersion(pub u32);
+
+impl HttpVersion {
+    pub const HTTP_1_0: Self = Self(0);
+    pub const HTTP_1_1: Self = Self(1);
+}
+
+/// `EFI_HTTPv4_ACCESS_POINT`
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Httpv4AccessPoint {
+    pub use_default_address: bool,
+    pub local_address:       [u8; 4],
+    pub local_subnet:        [u8; 4],
+    pub local_port:          u16,
+}
+
+/// `EFI_HTTPv6_ACCESS_POINT`
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Httpv6AccessPoint {
+    pub local_address: [u8; 16],
+    pub local_port:    u16,
+}
+
+/// `EFI_HTTP_CONFIG_DATA`
+///
+/// `access_point` points to an [`Httpv6AccessPoint`] if `local_address_is_ipv6` is set, and
+/// to an [`Httpv4AccessPoint`] otherwise.
+#[repr(C)]
+#[derive(Debug)]
+pub struct HttpConfigData {
+    pub http_version:          HttpVersion,
+    pub timeout_ms:            u32,
+    pub local_address_is_ipv6: bool,
+    pub access_point:          *mut c_void,
+}
+
+/// `EFI_HTTP_TOKEN`
+#[repr(C)]
+#[derive(Debug)]
+pub struct HttpToken {
+    pub event:   Event,
+    pub status:  Status,
+    pub message: *mut HttpMessage,
+}
+
+unsafe impl Token for HttpToken {
+    fn new(event: Event) -> Self {
+        Self {
+            event,
+            status: Status::SUCCESS,
+            message: ptr::null_mut(),
+        }
+    }
+
+    fn event(&self) -> Event {
+        self.event
+    }
+
+    fn status(&self) -> Status {
+        self.status
+    }
+}
+
+pub type GetModeDataFn =
+    extern "efiapi" fn(this: *mut Http, http_config_data: *mut HttpConfigData) -> Status;
+
+pub type ConfigureFn =
+    extern "efiapi" fn(this: *mut Http, http_config_data: *const HttpConfigData) -> Status;
+
+pub type RequestFn = extern "efiapi" fn(this: *mut Http, token: *mut HttpToken) -> Status;
+
+pub type CancelFn = extern "efiapi" fn(this: *mut Http, token: *mut HttpToken) -> Status;
+
+pub type ResponseFn = extern "efiapi" fn(this: *mut Http, token: *mut HttpToken) -> Status;
+
+pub type PollFn = extern "efiapi" fn(this: *mut Http) -> Status;
+
+/// HTTP Protocol
+///
+/// Instances are created through the
+/// [`ServiceBinding`](crate::proto::service_binding::ServiceBinding) installed on the
+/// network controller.
+#[repr(C)]
+pub struct Http {
+    get_mode_data: GetModeDataFn,
+    configure:     ConfigureFn,
+    request:       RequestFn,
+    cancel:        CancelFn,
+    response:      ResponseFn,
+    poll:          PollFn,
+}
+
+impl Protocol for Http {
+    const GUID: Guid = guid!(
+        0x7a59b29b,0x910b,0x4171,
+        {0x82,0x42,0xa8,0x5a,0x0d,0xf2,0x5b,0x5b}
+    );
+}
+
+impl ServiceProtocol for Http {
+    const SERVICE_BINDING_GUID: Guid = guid!(
+        0xbdc8e6af,0xd9bc,0x4379,
+        {0xa7,0x2a,0xe0,0xc4,0xe7,0x5d,0xae,0x1c}
+    );
+}
+
+impl Proto<Http> {
+    /// Configures the instance, or resets it if `config` is `None`
+    ///
+    /// The access point is copied, so only needs to live for the duration of the call.
+    pub fn configure(&mut self, config: Option<&HttpConfigData>) -> Result<()> {
+        let config = config.map_or(ptr::null(), ptr::from_ref);
+        (self.configure)(self.as_ptr(), config).to_result(())
+    }
+
+    /// Sends a request
+    ///
+    /// # Safety
+    ///
+    /// `message` must describe a valid request: its data must point to an
+    /// [`HttpRequestData`], and its headers and body must be valid for the advertised lengths.
+    pub async unsafe fn request(&mut self, message: &mut HttpMessage) -> Result<()> {
+        let (this, request) = (self.as_ptr(), self.request);
+        let mut completion = pin!(Completion::<HttpToken>::new()?);
+        unsafe {
+            completion.as_mut().submit(|token| {
+                (*token).message = message;
+                request(this, token)
+            })?;
+        }
+        completion.await
+    }
+
+    /// Receives (part of) a response
+    ///
+    /// The driver fills in `message.data`, the headers and `body_length`, allocating the
+    /// headers from pool memory. Call it again with only a body buffer to receive the rest of
+    /// a large body.
+    ///
+    /// # Safety
+    ///
+    /// `message.data` must be null or point to an [`HttpResponseData`], and `message.body`
+    /// must be valid for writes of `message.body_length` bytes.
+    pub async unsafe fn response(&mut self, message: &mut HttpMessage) -> Result<()> {
+        let (this, response) = (self.as_ptr(), self.response);
+        let mut completion = pin!(Completion::<HttpToken>::new()?);
+        unsafe {
+            completion.as_mut().submit(|token| {
+                (*token).message = message;
+                response(this, token)
+            })?;
+        }
+        completion.await
+    }
+
+    /// Aborts all outstanding requests and responses
+    pub fn cancel_all(&mut self) -> Result<()> {
+        (self.cancel)(self.as_ptr(), ptr::null_mut()).to_result(())
+    }
+
+    /// Moves data between the network device and the driver's queues
+    ///
+    /// Polling speeds up transfers on drivers which otherwise only make progress on a timer.
+    pub fn poll(&mut self) -> Result<()> {
+        (self.poll)(self.as_ptr()).to_result(())
+    }
+}
@@ -28,28 +28,9 @@
  * SPDX-License-Identifier: BSD-3-Clause
  */
 
-use super::Protocol;
-use crate::{guid, Guid, Result, Status};
+//! Network protocols
 
-#[repr(C)]
-pub struct RiscvBoot {
-    pub revision:    u64,
-    get_boot_hartid: GetBootHartidFn,
-}
-
-impl Protocol for RiscvBoot {
-    const GUID: Guid = guid!(
-        0xccd15fec,0x6f73,0x4eec,
-        {0x83,0x95,0x3e,0x69,0xe4,0xb9,0x40,0xbf}
-    );
-}
-
-pub type GetBootHartidFn =
-    extern "efiapi" fn(this: *mut RiscvBoot, boot_hartid: *mut usize) -> Status;
-
-impl RiscvBoot {
-    pub fn get_boot_hartid(&mut self) -> Result<usize> {
-        let mut hartid = 0;
-        (self.get_boot_hartid)(self, &mut hartid).to_result(hartid)
-    }
-}
+pub mod http;
+pub mod rest;
+pub mod supplicant;
+pub mod wifi;
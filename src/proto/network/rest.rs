@@ -0,0 +1,310 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! REST EX Protocol
+//!
+//! Sends HTTP requests to a REST service, typically the platform's Redfish service reached
+//! in-band through the BMC. Instances are created through the
+//! [`ServiceBinding`](crate::proto::service_binding::ServiceBinding) installed on the
+//! network controller. Bodies are passed through untouched, so any JSON library (or none)
+//! can be used on top.
+
+use core::{
+    ffi::{c_void, CStr},
+    ptr, slice,
+};
+
+use super::http::{
+    HttpHeader, HttpMessage, HttpMethod, HttpRequestData, HttpResponseData, HttpStatusCode,
+};
+use crate::{
+    executor::Token,
+    guid,
+    proto::{service_binding::ServiceProtocol, Proto, Protocol},
+    ucs2::CStr16,
+    Event, Guid, Result, Status,
+};
+
+pub type SendReceiveFn = extern "efiapi" fn(
+    this: *mut RestEx,
+    request_message: *mut HttpMessage,
+    response_message: *mut HttpMessage,
+) -> Status;
+
+pub type GetServiceFn =
+    extern "efiapi" fn(this: *mut RestEx, rest_ex_service_info: *mut *mut c_void) -> Status;
+
+pub type GetModeDataFn =
+    extern "efiapi" fn(this: *mut RestEx, http_config_data: *mut c_void) -> Status;
+
+pub type ConfigureFn =
+    extern "efiapi" fn(this: *mut RestEx, rest_ex_config_data: *const c_void) -> Status;
+
+pub type AsyncSendReceiveFn = extern "efiapi" fn(
+    this: *mut RestEx,
+    request_message: *mut HttpMessage,
+    rest_ex_token: *mut RestExToken,
+) -> Status;
+
+pub type EventServiceFn = extern "efiapi" fn(
+    this: *mut RestEx,
+    request_message: *mut HttpMessage,
+    rest_ex_token: *mut RestExToken,
+) -> Status;
+
+/// `EFI_REST_EX_TOKEN`
+#[repr(C)]
+#[derive(Debug)]
+pub struct RestExToken {
+    pub event:            Event,
+    pub status:           Status,
+    pub response_message: *mut HttpMessage,
+}
+
+unsafe impl Token for RestExToken {
+    fn new(event: Event) -> Self {
+        Self {
+            event,
+            status: Status::SUCCESS,
+            response_message: ptr::null_mut(),
+        }
+    }
+
+    fn event(&self) -> Event {
+        self.event
+    }
+
+    fn status(&self) -> Status {
+        self.status
+    }
+}
+
+/// REST EX Protocol
+#[repr(C)]
+pub struct RestEx {
+    send_receive:       SendReceiveFn,
+    get_service:        GetServiceFn,
+    get_mode_data:      GetModeDataFn,
+    configure:          ConfigureFn,
+    async_send_receive: AsyncSendReceiveFn,
+    event_service:      EventServiceFn,
+}
+
+impl Protocol for RestEx {
+    const GUID: Guid = guid!(
+        0x55648b91,0xe7d0,0x40a3,
+        {0xa9,0xb3,0xa8,0x15,0xd7,0xea,0xdf,0x97}
+    );
+}
+
+impl ServiceProtocol for RestEx {
+    const SERVICE_BINDING_GUID: Guid = guid!(
+        0x456bbe01,0x99d0,0x45ea,
+        {0xbb,0x5f,0x16,0xd8,0x4b,0xed,0xc5,0x59}
+    );
+}
+
+/// `EFI_REST_EX_SERVICE_TYPE`
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RestServiceType(pub u32);
+
+impl RestServiceType {
+    pub const UNSPECIFIC: Self = Self(1);
+    pub const REDFISH: Self = Self(2);
+    pub const ODATA: Self = Self(3);
+    pub const VENDOR_SPECIFIC: Self = Self(0xff);
+}
+
+/// `EFI_REST_EX_SERVICE_ACCESS_MODE`
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RestAccessMode(pub u32);
+
+impl RestAccessMode {
+    pub const IN_BAND: Self = Self(1);
+    pub const OUT_OF_BAND: Self = Self(2);
+}
+
+/// The common part of `EFI_REST_EX_SERVICE_INFO`
+#[derive(Clone, Copy, Debug)]
+pub struct RestServiceInfo {
+    /// Major and minor version of the information structure
+    pub version:      (u8, u8),
+    pub service_type: RestServiceType,
+    pub access_mode:  RestAccessMode,
+}
+
+/// The most headers accepted by [`Proto::<RestEx>::send_receive()`]
+pub const MAX_REQUEST_HEADERS: usize = 16;
+
+/// A response received with [`Proto::<RestEx>::send_receive()`]
+///
+/// The status, headers and body are allocated by the driver and freed when this is dropped.
+pub struct RestResponse {
+    message: HttpMessage,
+}
+
+impl RestResponse {
+    pub fn status(&self) -> HttpStatusCode {
+        match self.message.data.cast::<HttpResponseData>() {
+            data if data.is_null() => HttpStatusCode::UNSUPPORTED,
+            data => unsafe { (*data).status_code },
+        }
+    }
+
+    fn raw_headers(&self) -> &[HttpHeader] {
+        if self.message.headers.is_null() {
+            return &[];
+        }
+        unsafe { slice::from_raw_parts(self.message.headers, self.message.header_count) }
+    }
+
+    /// Returns an iterator over the header names and values
+    pub fn headers(&self) -> impl Iterator<Item = (&CStr, &CStr)> {
+        self.raw_headers()
+            .iter()
+            .map(|header| unsafe { (header.name(), header.value()) })
+    }
+
+    /// Returns the value of the first header named `name`, ignoring case
+    pub fn header(&self, name: &str) -> Option<&CStr> {
+        self.headers()
+            .find(|(field, _)| field.to_bytes().eq_ignore_ascii_case(name.as_bytes()))
+            .map(|(_, value)| value)
+    }
+
+    pub fn body(&self) -> &[u8] {
+        if self.message.body.is_null() {
+            return &[];
+        }
+        unsafe { slice::from_raw_parts(self.message.body.cast(), self.message.body_length) }
+    }
+}
+
+impl Drop for RestResponse {
+    fn drop(&mut self) {
+        // Everything is simply leaked if boot services have been exited.
+        if !crate::boot_services_active() {
+            return;
+        }
+        let bs = crate::boot_services();
+        let free = |ptr: *mut u8| {
+            if !ptr.is_null() {
+                let _ = unsafe { bs.free_pool(ptr) };
+            }
+        };
+        for header in self.raw_headers() {
+            free(header.field_name.cast_mut().cast());
+            free(header.field_value.cast_mut().cast());
+        }
+        free(self.message.headers.cast());
+        free(self.message.body.cast());
+        free(self.message.data.cast());
+    }
+}
+
+impl Proto<RestEx> {
+    /// Sends a request to `url` and waits for the response
+    ///
+    /// `headers` are name/value pairs, at most [`MAX_REQUEST_HEADERS`] of them. A non-success
+    /// HTTP status is not an error; check [`RestResponse::status()`].
+    pub fn send_receive(
+        &mut self,
+        method: HttpMethod,
+        url: &CStr16,
+        headers: &[(&CStr, &CStr)],
+        body: &[u8],
+    ) -> Result<RestResponse> {
+        let mut raw_headers = [HttpHeader {
+            field_name:  ptr::null(),
+            field_value: ptr::null(),
+        }; MAX_REQUEST_HEADERS];
+        let raw_headers = raw_headers
+            .get_mut(..headers.len())
+            .ok_or(Status::INVALID_PARAMETER)?;
+        for (raw, (name, value)) in raw_headers.iter_mut().zip(headers) {
+            *raw = HttpHeader::new(name, value);
+        }
+
+        let mut request_data = HttpRequestData {
+            method,
+            url: url.as_ptr(),
+        };
+        let mut request = HttpMessage {
+            data:         ptr::addr_of_mut!(request_data).cast(),
+            header_count: raw_headers.len(),
+            headers:      raw_headers.as_mut_ptr(),
+            body_length:  body.len(),
+            body:         body.as_ptr().cast_mut().cast(),
+        };
+        let mut response = RestResponse {
+            message: HttpMessage {
+                data:         ptr::null_mut(),
+                header_count: 0,
+                headers:      ptr::null_mut(),
+                body_length:  0,
+                body:         ptr::null_mut(),
+            },
+        };
+        (self.send_receive)(self.as_ptr(), &mut request, &mut response.message).to_result(response)
+    }
+
+    /// Returns information about the REST service behind this instance
+    pub fn service_info(&mut self) -> Result<RestServiceInfo> {
+        let mut info = ptr::null_mut();
+        (self.get_service)(self.as_ptr(), &mut info).to_result(())?;
+        if info.is_null() {
+            return Err(Status::NOT_FOUND);
+        }
+        // `EFI_REST_EX_SERVICE_INFO_HEADER` is a `UINT32` length and two version bytes,
+        // padded to 8 bytes, followed by the service type and access mode.
+        let bytes = info.cast::<u8>();
+        let service_info = unsafe {
+            RestServiceInfo {
+                version:      (*bytes.add(4), *bytes.add(5)),
+                service_type: RestServiceType(bytes.add(8).cast::<u32>().read_unaligned()),
+                access_mode:  RestAccessMode(bytes.add(12).cast::<u32>().read_unaligned()),
+            }
+        };
+        let _ = unsafe { crate::boot_services().free_pool(bytes) };
+        Ok(service_info)
+    }
+
+    /// Configures the instance
+    ///
+    /// # Safety
+    ///
+    /// `config` must point to an `EFI_REST_EX_CONFIG_DATA` of the type reported by the
+    /// service, or be null to reset the instance.
+    pub unsafe fn configure(&mut self, config: *const c_void) -> Result<()> {
+        (self.configure)(self.as_ptr(), config).to_result(())
+    }
+}
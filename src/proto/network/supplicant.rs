@@ -0,0 +1,223 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Supplicant Protocol
+//!
+//! Performs the 802.1X/WPA key exchange for a wireless driver. A loader only needs it to hand
+//! over the network's credentials before connecting with
+//! [`WirelessMacConnection2`](super::wifi::WirelessMacConnection2); the driver drives the
+//! exchange itself.
+
+use core::ffi::c_void;
+
+use super::wifi::{Ssid, SuiteSelector};
+use crate::{
+    guid,
+    proto::{service_binding::ServiceProtocol, Proto, Protocol},
+    Guid, Result, Status,
+};
+
+pub type BuildResponsePacketFn = extern "efiapi" fn(
+    this: *mut Supplicant,
+    request_buffer: *const u8,
+    request_buffer_size: usize,
+    buffer: *mut u8,
+    buffer_size: *mut usize,
+) -> Status;
+
+pub type ProcessPacketFn = extern "efiapi" fn(
+    this: *mut Supplicant,
+    fragment_table: *mut *mut FragmentData,
+    fragment_count: *mut u32,
+    crypt_mode: CryptMode,
+) -> Status;
+
+pub type SetDataFn = extern "efiapi" fn(
+    this: *mut Supplicant,
+    data_type: SupplicantDataType,
+    data: *const c_void,
+    data_size: usize,
+) -> Status;
+
+pub type GetDataFn = extern "efiapi" fn(
+    this: *mut Supplicant,
+    data_type: SupplicantDataType,
+    data: *mut u8,
+    data_size: *mut usize,
+) -> Status;
+
+/// `EFI_SUPPLICANT_FRAGMENT_DATA`
+#[repr(C)]
+#[derive(Debug)]
+pub struct FragmentData {
+    pub fragment_length: u32,
+    pub fragment_buffer: *mut c_void,
+}
+
+/// `EFI_SUPPLICANT_CRYPT_MODE`
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct CryptMode(pub u32);
+
+impl CryptMode {
+    pub const ENCRYPT: Self = Self(0);
+    pub const DECRYPT: Self = Self(1);
+}
+
+/// `EFI_SUPPLICANT_DATA_TYPE`
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SupplicantDataType(pub u32);
+
+impl SupplicantDataType {
+    pub const AKM_SUITE: Self = Self(0);
+    pub const GROUP_DATA_CIPHER_SUITE: Self = Self(1);
+    pub const PAIRWISE_CIPHER_SUITE: Self = Self(2);
+    /// The WPA passphrase, a null-terminated ASCII string
+    pub const PSK_PASSWORD: Self = Self(3);
+    pub const TARGET_SSID_NAME: Self = Self(4);
+    pub const STATION_MAC: Self = Self(5);
+    pub const TARGET_SSID_MAC: Self = Self(6);
+    pub const PTK: Self = Self(7);
+    pub const GTK: Self = Self(8);
+    pub const STATE: Self = Self(9);
+    pub const LINK_STATE: Self = Self(10);
+    pub const KEY_REFRESH: Self = Self(11);
+    pub const SUPPORTED_AKM_SUITES: Self = Self(12);
+    pub const SUPPORTED_SOFTWARE_CIPHER_SUITES: Self = Self(13);
+    pub const SUPPORTED_HARDWARE_CIPHER_SUITES: Self = Self(14);
+    pub const IGTK: Self = Self(15);
+    pub const PMK: Self = Self(16);
+}
+
+/// Supplicant Protocol
+#[repr(C)]
+pub struct Supplicant {
+    build_response_packet: BuildResponsePacketFn,
+    process_packet:        ProcessPacketFn,
+    set_data:              SetDataFn,
+    get_data:              GetDataFn,
+}
+
+impl Protocol for Supplicant {
+    const GUID: Guid = guid!(
+        0x54fcc43e,0xaa89,0x4333,
+        {0x9a,0x85,0xcd,0xea,0x24,0x05,0x1e,0x9e}
+    );
+}
+
+impl ServiceProtocol for Supplicant {
+    const SERVICE_BINDING_GUID: Guid = guid!(
+        0x45bcd98e,0x59ad,0x4174,
+        {0x95,0x46,0x34,0x4a,0x07,0x48,0x58,0x98}
+    );
+}
+
+impl Proto<Supplicant> {
+    /// Sets a configuration value; the format of `data` depends on `kind`
+    pub fn set_data(&mut self, kind: SupplicantDataType, data: &[u8]) -> Result<()> {
+        (self.set_data)(self.as_ptr(), kind, data.as_ptr().cast(), data.len()).to_result(())
+    }
+
+    /// Reads a configuration value into `buf`, returning its size
+    pub fn get_data(&mut self, kind: SupplicantDataType, buf: &mut [u8]) -> Result<usize> {
+        let mut size = buf.len();
+        (self.get_data)(self.as_ptr(), kind, buf.as_mut_ptr(), &mut size).to_result(size)
+    }
+
+    /// Configures the credentials for a WPA2-Personal network with CCMP encryption
+    ///
+    /// Fails with `INVALID_PARAMETER` unless `password` is 8 to 63 printable ASCII
+    /// characters.
+    pub fn configure_wpa2_psk(&mut self, ssid: &Ssid, password: &str) -> Result<()> {
+        const MAX_PASSWORD_LEN: usize = 63;
+
+        let valid = password.bytes().all(|b| (0x20..0x7f).contains(&b));
+        if !valid || !(8..=MAX_PASSWORD_LEN).contains(&password.len()) {
+            return Err(Status::INVALID_PARAMETER);
+        }
+        let mut buf = [0; MAX_PASSWORD_LEN + 1];
+        buf[..password.len()].copy_from_slice(password.as_bytes());
+
+        self.set_suite(SupplicantDataType::AKM_SUITE, SuiteSelector::AKM_PSK)?;
+        self.set_suite(
+            SupplicantDataType::PAIRWISE_CIPHER_SUITE,
+            SuiteSelector::CIPHER_CCMP,
+        )?;
+        self.set_suite(
+            SupplicantDataType::GROUP_DATA_CIPHER_SUITE,
+            SuiteSelector::CIPHER_CCMP,
+        )?;
+        let ssid_ptr = (ssid as *const Ssid).cast::<c_void>();
+        (self.set_data)(
+            self.as_ptr(),
+            SupplicantDataType::TARGET_SSID_NAME,
+            ssid_ptr,
+            core::mem::size_of::<Ssid>(),
+        )
+        .to_result(())?;
+        self.set_data(SupplicantDataType::PSK_PASSWORD, &buf[..=password.len()])
+    }
+
+    fn set_suite(&mut self, kind: SupplicantDataType, suite: SuiteSelector) -> Result<()> {
+        let SuiteSelector { oui, suite_type } = suite;
+        self.set_data(kind, &[oui[0], oui[1], oui[2], suite_type])
+    }
+
+    /// Builds the response to an EAPOL key frame
+    ///
+    /// Returns the response's size; zero means no response is needed.
+    pub fn build_response_packet(&mut self, request: &[u8], buf: &mut [u8]) -> Result<usize> {
+        let mut size = buf.len();
+        (self.build_response_packet)(
+            self.as_ptr(),
+            request.as_ptr(),
+            request.len(),
+            buf.as_mut_ptr(),
+            &mut size,
+        )
+        .to_result(size)
+    }
+
+    /// Encrypts or decrypts a frame in place
+    ///
+    /// # Safety
+    ///
+    /// `fragment_table` must point to `*fragment_count` valid fragments. The driver may
+    /// replace the table with one it allocated, which the caller must free.
+    pub unsafe fn process_packet(
+        &mut self,
+        fragment_table: *mut *mut FragmentData,
+        fragment_count: &mut u32,
+        mode: CryptMode,
+    ) -> Result<()> {
+        (self.process_packet)(self.as_ptr(), fragment_table, fragment_count, mode).to_result(())
+    }
+}
@@ -0,0 +1,397 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Wireless MAC Connection II Protocol
+//!
+//! Scans for and connects to 802.11 networks. For WPA2-PSK networks, the credentials are
+//! handed to the [`Supplicant`](super::supplicant::Supplicant) on the same controller before
+//! connecting. The operations are asynchronous in the protocol; the wrappers here wait for
+//! completion.
+
+use core::{ffi::c_void, mem::offset_of, ptr, slice};
+
+use crate::{
+    boot_services, guid,
+    proto::{Proto, Protocol},
+    table::EventType,
+    Event, Guid, Result, Status, Tpl,
+};
+
+pub type GetNetworksFn =
+    extern "efiapi" fn(this: *mut WirelessMacConnection2, token: *mut GetNetworksToken) -> Status;
+
+pub type ConnectNetworkFn = extern "efiapi" fn(
+    this: *mut WirelessMacConnection2,
+    token: *mut ConnectNetworkToken,
+) -> Status;
+
+pub type DisconnectNetworkFn = extern "efiapi" fn(
+    this: *mut WirelessMacConnection2,
+    token: *mut DisconnectNetworkToken,
+) -> Status;
+
+/// Wireless MAC Connection II Protocol
+#[repr(C)]
+pub struct WirelessMacConnection2 {
+    get_networks:       GetNetworksFn,
+    connect_network:    ConnectNetworkFn,
+    disconnect_network: DisconnectNetworkFn,
+}
+
+impl Protocol for WirelessMacConnection2 {
+    const GUID: Guid = guid!(
+        0x1b0fb9bf,0x699d,0x4fdd,
+        {0xa7,0xc3,0x25,0x46,0x68,0x1b,0xf6,0x3b}
+    );
+}
+
+/// `EFI_80211_SSID`
+#[repr(C)]
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub struct Ssid {
+    len:   u8,
+    bytes: [u8; 32],
+}
+
+impl Ssid {
+    /// Fails with `INVALID_PARAMETER` if `ssid` is longer than 32 bytes
+    pub fn new(ssid: &[u8]) -> Result<Self> {
+        let mut bytes = [0; 32];
+        bytes
+            .get_mut(..ssid.len())
+            .ok_or(Status::INVALID_PARAMETER)?
+            .copy_from_slice(ssid);
+        Ok(Self {
+            len: ssid.len() as u8,
+            bytes,
+        })
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..(self.len as usize).min(32)]
+    }
+}
+
+impl core::fmt::Debug for Ssid {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "\"{}\"", self.as_bytes().escape_ascii())
+    }
+}
+
+/// `EFI_80211_SUITE_SELECTOR`, an OUI and a suite type
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SuiteSelector {
+    pub oui:        [u8; 3],
+    pub suite_type: u8,
+}
+
+impl SuiteSelector {
+    const IEEE: [u8; 3] = [0x00, 0x0f, 0xac];
+
+    /// Pre-shared key authentication, as used by WPA2-Personal
+    pub const AKM_PSK: Self = Self::ieee(2);
+    /// CCMP-128 (AES) encryption
+    pub const CIPHER_CCMP: Self = Self::ieee(4);
+
+    pub const fn ieee(suite_type: u8) -> Self {
+        Self {
+            oui: Self::IEEE,
+            suite_type,
+        }
+    }
+}
+
+/// `EFI_80211_AKM_SUITE_SELECTOR` and `EFI_80211_CIPHER_SUITE_SELECTOR`
+#[repr(C)]
+pub struct SuiteList {
+    pub count: u16,
+    list:      [SuiteSelector; 0],
+}
+
+impl SuiteList {
+    pub fn suites(&self) -> &[SuiteSelector] {
+        unsafe { slice::from_raw_parts(self.list.as_ptr(), self.count as usize) }
+    }
+}
+
+/// `EFI_80211_BSS_TYPE`
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct BssType(pub u32);
+
+impl BssType {
+    pub const INFRASTRUCTURE: Self = Self(0);
+    pub const INDEPENDENT: Self = Self(1);
+    pub const MESH: Self = Self(2);
+    pub const ANY: Self = Self(3);
+}
+
+/// `EFI_80211_NETWORK`
+#[repr(C)]
+pub struct Network {
+    pub bss_type:     BssType,
+    pub ssid:         Ssid,
+    pub akm_suite:    *mut SuiteList,
+    pub cipher_suite: *mut SuiteList,
+}
+
+impl Network {
+    /// Returns the supported authentication and key management suites
+    pub fn akm_suites(&self) -> &[SuiteSelector] {
+        unsafe { self.akm_suite.as_ref() }.map_or(&[], SuiteList::suites)
+    }
+
+    pub fn cipher_suites(&self) -> &[SuiteSelector] {
+        unsafe { self.cipher_suite.as_ref() }.map_or(&[], SuiteList::suites)
+    }
+
+    /// Returns `true` if the network accepts WPA2-PSK with CCMP
+    pub fn supports_wpa2_psk(&self) -> bool {
+        self.akm_suites().contains(&SuiteSelector::AKM_PSK)
+            && self.cipher_suites().contains(&SuiteSelector::CIPHER_CCMP)
+    }
+}
+
+impl core::fmt::Debug for Network {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Network")
+            .field("bss_type", &self.bss_type)
+            .field("ssid", &self.ssid)
+            .field("akm_suites", &self.akm_suites())
+            .field("cipher_suites", &self.cipher_suites())
+            .finish()
+    }
+}
+
+/// `EFI_80211_NETWORK_DESCRIPTION`
+#[repr(C)]
+#[derive(Debug)]
+pub struct NetworkDescription {
+    pub network: Network,
+    /// Signal quality, from 0 to 100
+    pub quality: u8,
+}
+
+/// The most SSIDs accepted by [`Proto::<WirelessMacConnection2>::get_networks()`]
+pub const MAX_SCAN_SSIDS: usize = 8;
+
+/// `EFI_80211_GET_NETWORKS_DATA`, sized for [`MAX_SCAN_SSIDS`]
+#[repr(C)]
+struct GetNetworksData {
+    count: u32,
+    ssids: [Ssid; MAX_SCAN_SSIDS],
+}
+
+/// `EFI_80211_GET_NETWORKS_RESULT`
+#[repr(C)]
+struct GetNetworksResult {
+    count:    u8,
+    networks: [NetworkDescription; 0],
+}
+
+/// `EFI_80211_GET_NETWORKS_TOKEN`
+#[repr(C)]
+pub struct GetNetworksToken {
+    pub event:  Event,
+    pub status: Status,
+    data:       *mut c_void,
+    result:     *mut c_void,
+}
+
+/// `EFI_80211_CONNECT_NETWORK_DATA`
+#[repr(C)]
+struct ConnectNetworkData {
+    network:         *const Network,
+    failure_timeout: u32,
+}
+
+/// `EFI_80211_CONNECT_NETWORK_RESULT_CODE`
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ConnectResult(pub u32);
+
+impl ConnectResult {
+    pub const SUCCESS: Self = Self(0);
+    pub const REFUSED: Self = Self(1);
+    pub const FAILED: Self = Self(2);
+    pub const TIMEOUT: Self = Self(3);
+    pub const UNSPECIFIED: Self = Self(4);
+}
+
+/// `EFI_80211_CONNECT_NETWORK_TOKEN`
+#[repr(C)]
+pub struct ConnectNetworkToken {
+    pub event:       Event,
+    pub status:      Status,
+    data:            *mut ConnectNetworkData,
+    pub result_code: ConnectResult,
+}
+
+/// `EFI_80211_DISCONNECT_NETWORK_TOKEN`
+#[repr(C)]
+pub struct DisconnectNetworkToken {
+    pub event:  Event,
+    pub status: Status,
+}
+
+/// The networks found by [`Proto::<WirelessMacConnection2>::get_networks()`]
+///
+/// The results are allocated by the driver and freed when this is dropped.
+pub struct ScanResult {
+    result: *mut GetNetworksResult,
+}
+
+impl ScanResult {
+    pub fn networks(&self) -> &[NetworkDescription] {
+        if self.result.is_null() {
+            return &[];
+        }
+        unsafe {
+            let count = (*self.result).count as usize;
+            let first = self
+                .result
+                .cast::<u8>()
+                .add(offset_of!(GetNetworksResult, networks));
+            slice::from_raw_parts(first.cast(), count)
+        }
+    }
+}
+
+impl Drop for ScanResult {
+    fn drop(&mut self) {
+        if self.result.is_null() || !crate::boot_services_active() {
+            return;
+        }
+        let bs = boot_services();
+        for desc in self.networks() {
+            for list in [desc.network.akm_suite, desc.network.cipher_suite] {
+                if !list.is_null() {
+                    let _ = unsafe { bs.free_pool(list.cast()) };
+                }
+            }
+        }
+        let _ = unsafe { bs.free_pool(self.result.cast()) };
+    }
+}
+
+impl core::fmt::Debug for ScanResult {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_list().entries(self.networks()).finish()
+    }
+}
+
+/// Creates an event, starts an operation signaling it, and waits for the event
+fn wait_for(start: impl FnOnce(Event) -> Status) -> Result<()> {
+    let bs = boot_services();
+    let event =
+        unsafe { bs.create_event(EventType::empty(), Tpl::CALLBACK, None, ptr::null_mut())? };
+    let result = start(event)
+        .to_result(())
+        .and_then(|()| bs.wait_for_event(&[event]).map(drop));
+    let _ = bs.close_event(event);
+    result
+}
+
+impl Proto<WirelessMacConnection2> {
+    /// Scans for networks, including hidden networks named in `ssids`
+    ///
+    /// Fails with `INVALID_PARAMETER` for more than [`MAX_SCAN_SSIDS`] SSIDs, or with
+    /// `NOT_FOUND` if no network was found.
+    pub fn get_networks(&mut self, ssids: &[Ssid]) -> Result<ScanResult> {
+        let mut data = GetNetworksData {
+            count: ssids.len() as u32,
+            ssids: [Ssid::new(&[])?; MAX_SCAN_SSIDS],
+        };
+        data.ssids
+            .get_mut(..ssids.len())
+            .ok_or(Status::INVALID_PARAMETER)?
+            .copy_from_slice(ssids);
+
+        let mut token = GetNetworksToken {
+            event:  Event(ptr::null_mut()),
+            status: Status::SUCCESS,
+            data:   ptr::addr_of_mut!(data).cast(),
+            result: ptr::null_mut(),
+        };
+        let this = self.as_ptr();
+        wait_for(|event| {
+            token.event = event;
+            (self.get_networks)(this, &mut token)
+        })?;
+        let result = ScanResult {
+            result: token.result.cast(),
+        };
+        token.status.to_result(result)
+    }
+
+    /// Connects to `network`, giving up after `timeout` seconds
+    ///
+    /// For protected networks the supplicant must have been configured first. A connection
+    /// refused by the access point fails with `ACCESS_DENIED`, a timeout with `TIMEOUT`.
+    pub fn connect(&mut self, network: &Network, timeout: u32) -> Result<()> {
+        let mut data = ConnectNetworkData {
+            network,
+            failure_timeout: timeout,
+        };
+        let mut token = ConnectNetworkToken {
+            event:       Event(ptr::null_mut()),
+            status:      Status::SUCCESS,
+            data:        &mut data,
+            result_code: ConnectResult::SUCCESS,
+        };
+        let this = self.as_ptr();
+        wait_for(|event| {
+            token.event = event;
+            (self.connect_network)(this, &mut token)
+        })?;
+        token.status.to_result(())?;
+        match token.result_code {
+            ConnectResult::SUCCESS => Ok(()),
+            ConnectResult::REFUSED => Err(Status::ACCESS_DENIED),
+            ConnectResult::TIMEOUT => Err(Status::TIMEOUT),
+            _ => Err(Status::DEVICE_ERROR),
+        }
+    }
+
+    /// Disconnects from the current network
+    pub fn disconnect(&mut self) -> Result<()> {
+        let mut token = DisconnectNetworkToken {
+            event:  Event(ptr::null_mut()),
+            status: Status::SUCCESS,
+        };
+        let this = self.as_ptr();
+        wait_for(|event| {
+            token.event = event;
+            (self.disconnect_network)(this, &mut token)
+        })?;
+        token.status.to_result(())
+    }
+}
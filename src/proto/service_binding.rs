@@ -0,0 +1,80 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Service Binding Protocols
+//!
+//! Network drivers don't install their protocols on controller handles directly; instead,
+//! each child instance is created on demand through a service binding protocol installed on
+//! the controller, and the protocol is then opened on the new child handle.
+
+use core::{ffi::c_void, marker::PhantomData};
+
+use crate::{
+    proto::{Proto, Protocol},
+    Guid, Handle, Result, Status,
+};
+
+pub type CreateChildFn =
+    extern "efiapi" fn(this: *mut c_void, child_handle: *mut Option<Handle>) -> Status;
+
+pub type DestroyChildFn = extern "efiapi" fn(this: *mut c_void, child_handle: Handle) -> Status;
+
+/// A protocol whose instances are created through a [`ServiceBinding`]
+pub trait ServiceProtocol: Protocol {
+    const SERVICE_BINDING_GUID: Guid;
+}
+
+/// The service binding protocol for `P`
+#[repr(C)]
+pub struct ServiceBinding<P: ServiceProtocol> {
+    create_child:  CreateChildFn,
+    destroy_child: DestroyChildFn,
+    _protocol:     PhantomData<P>,
+}
+
+impl<P: ServiceProtocol> Protocol for ServiceBinding<P> {
+    const GUID: Guid = P::SERVICE_BINDING_GUID;
+}
+
+impl<P: ServiceProtocol> Proto<ServiceBinding<P>> {
+    /// Creates a child with a new instance of `P` installed, returning its handle
+    pub fn create_child(&mut self) -> Result<Handle> {
+        let mut child = None;
+        (self.create_child)(self.as_ptr().cast(), &mut child).to_result(())?;
+        child.ok_or(Status::DEVICE_ERROR)
+    }
+
+    /// Destroys a child created by [`create_child()`](Self::create_child)
+    ///
+    /// Fails with `ACCESS_DENIED` while its protocol is still open.
+    pub fn destroy_child(&mut self, child: Handle) -> Result<()> {
+        (self.destroy_child)(self.as_ptr().cast(), child).to_result(())
+    }
+}
@@ -0,0 +1,188 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! UEFI Shell protocols
+
+use core::{
+    ffi::c_void,
+    ptr::{self, NonNull},
+    slice,
+};
+
+use crate::{
+    boot_services, default_memory_type, guid,
+    proto::{Proto, Protocol},
+    table::SystemTable,
+    ucs2::{self, CStr16},
+    Guid, Handle, Result, Status,
+};
+
+/// `EFI_SHELL_PARAMETERS_PROTOCOL`
+///
+/// The shell installs this on the image handle of every application it starts, with the
+/// command line already split into arguments.
+#[repr(C)]
+pub struct ShellParameters {
+    argv:       *const *const u16,
+    argc:       usize,
+    pub stdin:  *mut c_void,
+    pub stdout: *mut c_void,
+    pub stderr: *mut c_void,
+}
+
+impl Protocol for ShellParameters {
+    const GUID: Guid = guid!(
+        0x752f3136,0x4e16,0x4fdc,
+        {0xa2,0x2a,0xe5,0xf4,0x68,0x12,0xf4,0xca}
+    );
+}
+
+impl Proto<ShellParameters> {
+    /// Returns an iterator over the arguments, starting with the program name
+    ///
+    /// The shell has already removed quotes and `^` escapes.
+    pub fn args(&self) -> impl ExactSizeIterator<Item = &CStr16> + '_ {
+        let argv = match self.argv.is_null() {
+            true => &[],
+            false => unsafe { slice::from_raw_parts(self.argv, self.argc) },
+        };
+        argv.iter().map(|&arg| unsafe { CStr16::from_ptr(arg) })
+    }
+}
+
+pub type ShellCommandHandlerFn = extern "efiapi" fn(
+    this: *mut ShellDynamicCommand,
+    system_table: *mut SystemTable,
+    parameters: *mut ShellParameters,
+    shell: *mut c_void,
+) -> Status;
+
+pub type ShellCommandGetHelpFn =
+    extern "efiapi" fn(this: *mut ShellDynamicCommand, language: *const u8) -> *mut u16;
+
+/// `EFI_SHELL_DYNAMIC_COMMAND_PROTOCOL`
+///
+/// The shell adds a command for every instance of this protocol. See [`DynamicCommand`] to
+/// implement one.
+#[repr(C)]
+pub struct ShellDynamicCommand {
+    command_name: *const u16,
+    handler:      ShellCommandHandlerFn,
+    get_help:     ShellCommandGetHelpFn,
+}
+
+impl Protocol for ShellDynamicCommand {
+    const GUID: Guid = guid!(
+        0x3c7200e9,0x005f,0x4ea4,
+        {0x87,0xde,0xa3,0xdf,0xac,0x8a,0x27,0xc3}
+    );
+}
+
+/// A shell command implemented by this image
+///
+/// Once [registered](Self::register), the shell runs `run` with the command line whenever the
+/// command is invoked, and shows `help` for `help <name>`. The image must stay loaded, so this
+/// is meant for drivers, loaded with the shell's `load` command or from `Driver####`.
+///
+/// ```ignore
+/// static HELLO: DynamicCommand = DynamicCommand::new(NAME, hello, "Prints a greeting.\r\n");
+///
+/// fn hello(params: &Proto<ShellParameters>) -> Status {
+///     println!("hello, {} arguments", params.args().len() - 1);
+///     Status::SUCCESS
+/// }
+///
+/// HELLO.register()?;
+/// ```
+#[repr(C)]
+pub struct DynamicCommand {
+    // Must come first, the callbacks cast `this` back to `DynamicCommand`.
+    protocol: ShellDynamicCommand,
+    run:      fn(&Proto<ShellParameters>) -> Status,
+    help:     &'static str,
+}
+
+// The command is only used by the firmware's single thread.
+unsafe impl Sync for DynamicCommand {}
+
+impl DynamicCommand {
+    pub const fn new(
+        name: &'static CStr16,
+        run: fn(&Proto<ShellParameters>) -> Status,
+        help: &'static str,
+    ) -> Self {
+        Self {
+            protocol: ShellDynamicCommand {
+                command_name: name.as_ptr(),
+                handler:      Self::handler,
+                get_help:     Self::get_help,
+            },
+            run,
+            help,
+        }
+    }
+
+    /// Installs the command on a new handle, returning the handle
+    pub fn register(&'static self) -> Result<Handle> {
+        unsafe { boot_services().install_protocol_interface(None, &self.protocol) }
+    }
+
+    /// Removes the command from the shell, e.g. before the driver is unloaded
+    pub fn unregister(&'static self, handle: Handle) -> Result<()> {
+        unsafe { boot_services().uninstall_protocol_interface(handle, &self.protocol) }
+    }
+
+    extern "efiapi" fn handler(
+        this: *mut ShellDynamicCommand,
+        _system_table: *mut SystemTable,
+        parameters: *mut ShellParameters,
+        _shell: *mut c_void,
+    ) -> Status {
+        let command = unsafe { &*this.cast::<Self>() };
+        match NonNull::new(parameters) {
+            Some(parameters) => (command.run)(&unsafe { Proto::new(parameters) }),
+            None => Status::INVALID_PARAMETER,
+        }
+    }
+
+    /// Returns the help text in a pool allocation, which the shell frees
+    ///
+    /// The text isn't translated, so `language` is ignored.
+    extern "efiapi" fn get_help(this: *mut ShellDynamicCommand, _language: *const u8) -> *mut u16 {
+        let command = unsafe { &*this.cast::<Self>() };
+        let len = ucs2::encode_lossy(command.help).count() + 1;
+        let Ok(buf) = boot_services().allocate_pool(default_memory_type(), len * 2) else {
+            return ptr::null_mut();
+        };
+        let buf = unsafe { slice::from_raw_parts_mut(buf.cast::<u16>(), len) };
+        let _ = ucs2::encode_str_lossy_into(command.help, buf);
+        buf.as_mut_ptr()
+    }
+}
@@ -0,0 +1,79 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Speaker Interface Protocol
+//!
+//! Not part of the UEFI specification, but provided by EDK2-based firmware on machines with a
+//! PC speaker or a beep codec.
+
+use super::{Proto, Protocol};
+use crate::{guid, Guid, Result, Status};
+
+pub type SetBeepToneFn = extern "efiapi" fn(this: *mut SpeakerIo, frequency: u16) -> Status;
+
+pub type GenerateBeepFn = extern "efiapi" fn(
+    this: *mut SpeakerIo,
+    number_of_beeps: usize,
+    beep_duration_us: usize,
+    interval_us: usize,
+) -> Status;
+
+/// `EFI_SPEAKER_IF_PROTOCOL`
+#[repr(C)]
+pub struct SpeakerIo {
+    pub(crate) set_beep_tone: SetBeepToneFn,
+    pub(crate) generate_beep: GenerateBeepFn,
+}
+
+impl Protocol for SpeakerIo {
+    const GUID: Guid = guid!(
+        0x400b4476,0x3081,0x11d6,
+        {0x87,0xed,0x00,0x06,0x29,0x45,0xc3,0xb9}
+    );
+}
+
+impl Proto<SpeakerIo> {
+    /// Beeps `count` times, for `duration_us` microseconds each with `interval_us` between
+    ///
+    /// This blocks until the last beep has finished.
+    pub fn generate_beep(
+        &self,
+        count: usize,
+        duration_us: usize,
+        interval_us: usize,
+    ) -> Result<()> {
+        (self.generate_beep)(self.as_ptr(), count, duration_us, interval_us).to_result(())
+    }
+
+    /// Sets the frequency of later beeps, in hertz
+    pub fn set_beep_tone(&self, frequency: u16) -> Result<()> {
+        (self.set_beep_tone)(self.as_ptr(), frequency).to_result(())
+    }
+}
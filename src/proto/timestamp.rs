@@ -0,0 +1,71 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Timestamp Protocol
+
+use super::{Proto, Protocol};
+use crate::{guid, Guid, Result, Status};
+
+pub type GetTimestampFn = extern "efiapi" fn() -> u64;
+
+pub type GetPropertiesFn = extern "efiapi" fn(properties: *mut TimestampProperties) -> Status;
+
+#[repr(C)]
+pub struct Timestamp {
+    pub(crate) get_timestamp:  GetTimestampFn,
+    pub(crate) get_properties: GetPropertiesFn,
+}
+
+impl Protocol for Timestamp {
+    const GUID: Guid = guid!(
+        0xafbfde41,0x2e6e,0x4262,
+        {0xba,0x65,0x62,0xb9,0x23,0x6e,0x54,0x95}
+    );
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TimestampProperties {
+    /// Ticks per second
+    pub frequency: u64,
+    /// Value at which the counter rolls over to zero
+    pub end_value: u64,
+}
+
+impl Proto<Timestamp> {
+    pub fn get_timestamp(&self) -> u64 {
+        (self.get_timestamp)()
+    }
+
+    pub fn get_properties(&self) -> Result<TimestampProperties> {
+        let mut properties = TimestampProperties::default();
+        (self.get_properties)(&mut properties).to_result(properties)
+    }
+}
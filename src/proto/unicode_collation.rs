@@ -0,0 +1,163 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Unicode Collation Protocol
+//!
+//! Case-insensitive comparison and pattern matching in the rules of the platform's language,
+//! as used by the FAT driver and the shell.
+
+use core::{cmp::Ordering, ffi::CStr};
+
+use crate::{
+    guid,
+    proto::{Proto, Protocol},
+    ucs2::CStr16,
+    Guid, Result, Status,
+};
+
+pub type StriCollFn =
+    extern "efiapi" fn(this: *mut UnicodeCollation, s1: *const u16, s2: *const u16) -> isize;
+
+pub type MetaiMatchFn = extern "efiapi" fn(
+    this: *mut UnicodeCollation,
+    string: *const u16,
+    pattern: *const u16,
+) -> bool;
+
+pub type StrLwrFn = extern "efiapi" fn(this: *mut UnicodeCollation, string: *mut u16);
+
+pub type StrUprFn = extern "efiapi" fn(this: *mut UnicodeCollation, string: *mut u16);
+
+pub type FatToStrFn = extern "efiapi" fn(
+    this: *mut UnicodeCollation,
+    fat_size: usize,
+    fat: *const u8,
+    string: *mut u16,
+);
+
+pub type StrToFatFn = extern "efiapi" fn(
+    this: *mut UnicodeCollation,
+    string: *const u16,
+    fat_size: usize,
+    fat: *mut u8,
+) -> bool;
+
+/// `EFI_UNICODE_COLLATION_PROTOCOL2`
+#[repr(C)]
+pub struct UnicodeCollation {
+    stri_coll:           StriCollFn,
+    metai_match:         MetaiMatchFn,
+    str_lwr:             StrLwrFn,
+    str_upr:             StrUprFn,
+    fat_to_str:          FatToStrFn,
+    str_to_fat:          StrToFatFn,
+    /// Semicolon-separated list of RFC 4646 language codes
+    supported_languages: *const u8,
+}
+
+impl Protocol for UnicodeCollation {
+    const GUID: Guid = guid!(
+        0xa4c751fc,0x23ae,0x4c3e,
+        {0x92,0xe9,0x49,0x64,0xcf,0x63,0xf3,0x49}
+    );
+}
+
+impl Proto<UnicodeCollation> {
+    /// Compares two strings case-insensitively
+    pub fn stri_coll(&mut self, a: &CStr16, b: &CStr16) -> Ordering {
+        (self.stri_coll)(self.as_ptr(), a.as_ptr(), b.as_ptr()).cmp(&0)
+    }
+
+    /// Matches `string` case-insensitively against a pattern
+    ///
+    /// Patterns may contain `*`, `?` and character sets like `[a-z]`.
+    pub fn metai_match(&mut self, string: &CStr16, pattern: &CStr16) -> bool {
+        (self.metai_match)(self.as_ptr(), string.as_ptr(), pattern.as_ptr())
+    }
+
+    /// Converts a null-terminated string to lower case in place
+    pub fn str_lwr(&mut self, string: &mut [u16]) -> Result<()> {
+        if !string.contains(&0) {
+            return Err(Status::INVALID_PARAMETER);
+        }
+        (self.str_lwr)(self.as_ptr(), string.as_mut_ptr());
+        Ok(())
+    }
+
+    /// Converts a null-terminated string to upper case in place
+    pub fn str_upr(&mut self, string: &mut [u16]) -> Result<()> {
+        if !string.contains(&0) {
+            return Err(Status::INVALID_PARAMETER);
+        }
+        (self.str_upr)(self.as_ptr(), string.as_mut_ptr());
+        Ok(())
+    }
+
+    pub fn supported_languages(&self) -> Option<&CStr> {
+        (!self.supported_languages.is_null())
+            .then(|| unsafe { CStr::from_ptr(self.supported_languages.cast()) })
+    }
+}
+
+/// Matches `string` against a pattern of `*` and `?` wildcards, ignoring ASCII case
+///
+/// This is a fallback for when no [`UnicodeCollation`] instance is available; unlike
+/// [`metai_match()`](Proto::<UnicodeCollation>::metai_match), it does not support character
+/// sets or case folding outside of ASCII.
+pub fn wildcard_match(string: &[u16], pattern: &[u16]) -> bool {
+    let fold = |c: u16| match c {
+        0x41..=0x5a => c + 0x20,
+        _ => c,
+    };
+    let (mut s, mut p) = (0, 0);
+    // Position of the last `*` in the pattern and the string position it was tried at
+    let mut backtrack = None;
+    while s < string.len() {
+        match pattern.get(p) {
+            Some(&c) if c == b'*' as u16 => {
+                backtrack = Some((p, s));
+                p += 1;
+            }
+            Some(&c) if c == b'?' as u16 || fold(c) == fold(string[s]) => {
+                s += 1;
+                p += 1;
+            }
+            _ => match backtrack {
+                Some((star, tried)) => {
+                    p = star + 1;
+                    s = tried + 1;
+                    backtrack = Some((star, s));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*' as u16)
+}
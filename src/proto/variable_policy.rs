@@ -0,0 +1,230 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Variable Policy Protocol (EDK2)
+//!
+//! Restricts the size and attributes of variables and locks them against further changes,
+//! enforced by the variable driver itself (in MM where available). Once the interface is
+//! locked with [`lock()`](Proto::<VariablePolicy>::lock), no policy can be added or disabled
+//! until the next reset, so hardening tools should register their policies and lock before
+//! handing control to the OS.
+
+use core::mem::size_of_val;
+
+use crate::{
+    guid,
+    proto::{Proto, Protocol},
+    table::VariableAttributes,
+    ucs2::CStr16,
+    Guid, Result, Status,
+};
+
+pub type DisableVariablePolicyFn = extern "efiapi" fn() -> Status;
+
+pub type IsVariablePolicyEnabledFn = extern "efiapi" fn(state: *mut bool) -> Status;
+
+pub type RegisterVariablePolicyFn = extern "efiapi" fn(new_policy: *const u8) -> Status;
+
+pub type DumpVariablePolicyFn = extern "efiapi" fn(policy: *mut u8, size: *mut u32) -> Status;
+
+pub type LockVariablePolicyFn = extern "efiapi" fn() -> Status;
+
+/// Variable Policy Protocol
+///
+/// Unlike most protocols, the functions take no `this` pointer.
+#[repr(C)]
+pub struct VariablePolicy {
+    pub revision:               u64,
+    disable_variable_policy:    DisableVariablePolicyFn,
+    is_variable_policy_enabled: IsVariablePolicyEnabledFn,
+    register_variable_policy:   RegisterVariablePolicyFn,
+    dump_variable_policy:       DumpVariablePolicyFn,
+    lock_variable_policy:       LockVariablePolicyFn,
+}
+
+impl Protocol for VariablePolicy {
+    const GUID: Guid = guid!(
+        0x81d1675c,0x86f6,0x48df,
+        {0xbd,0x95,0x9a,0x6e,0x4f,0x09,0x25,0xc3}
+    );
+}
+
+/// `VARIABLE_POLICY_ENTRY_REVISION`
+const ENTRY_REVISION: u32 = 0x0001_0000;
+/// Size of the fixed part of `VARIABLE_POLICY_ENTRY`
+const ENTRY_HEADER_SIZE: usize = 44;
+/// Size of the fixed part of `VARIABLE_LOCK_ON_VAR_STATE_POLICY`
+const VAR_STATE_HEADER_SIZE: usize = 18;
+
+/// The largest serialized policy accepted by [`Proto::<VariablePolicy>::register()`]
+pub const MAX_POLICY_SIZE: usize = 512;
+
+/// When a variable covered by a policy becomes read-only
+#[derive(Clone, Copy, Debug)]
+pub enum LockPolicy<'a> {
+    /// Never; only the size and attribute restrictions apply
+    None,
+    /// Immediately
+    Now,
+    /// As soon as it has been created
+    OnCreate,
+    /// Once the variable `name` in `namespace` holds the single byte `value`
+    OnVarState {
+        namespace: Guid,
+        name:      &'a CStr16,
+        value:     u8,
+    },
+}
+
+impl LockPolicy<'_> {
+    const fn kind(&self) -> u8 {
+        match self {
+            Self::None => 0,
+            Self::Now => 1,
+            Self::OnCreate => 2,
+            Self::OnVarState { .. } => 3,
+        }
+    }
+}
+
+/// A `VARIABLE_POLICY_ENTRY`
+#[derive(Clone, Copy, Debug)]
+pub struct VariablePolicyEntry<'a> {
+    pub namespace:            Guid,
+    /// The variable the policy applies to, or `None` for every variable in `namespace`
+    pub name:                 Option<&'a CStr16>,
+    pub min_size:             u32,
+    pub max_size:             u32,
+    pub attributes_must_have: VariableAttributes,
+    pub attributes_cant_have: VariableAttributes,
+    pub lock_policy:          LockPolicy<'a>,
+}
+
+impl<'a> VariablePolicyEntry<'a> {
+    /// Creates a policy without size or attribute restrictions
+    pub const fn new(namespace: Guid, name: Option<&'a CStr16>, lock: LockPolicy<'a>) -> Self {
+        Self {
+            namespace,
+            name,
+            min_size: 0,
+            max_size: u32::MAX,
+            attributes_must_have: VariableAttributes::empty(),
+            attributes_cant_have: VariableAttributes::empty(),
+            lock_policy: lock,
+        }
+    }
+
+    /// Serializes the policy into `buf`, returning the number of bytes written
+    ///
+    /// Fails with `BUFFER_TOO_SMALL` if it doesn't fit, or with `INVALID_PARAMETER` if it is
+    /// larger than the 64 KiB the format allows.
+    pub fn write(&self, buf: &mut [u8]) -> Result<usize> {
+        let name_bytes = |name: &CStr16| size_of_val(name.as_slice_with_nul());
+        let lock_size = match self.lock_policy {
+            LockPolicy::OnVarState { name, .. } => VAR_STATE_HEADER_SIZE + name_bytes(name),
+            _ => 0,
+        };
+        let name_offset = ENTRY_HEADER_SIZE + lock_size;
+        let size = name_offset + self.name.map_or(0, name_bytes);
+        let size16 = u16::try_from(size).map_err(|_| Status::INVALID_PARAMETER)?;
+        let buf = buf.get_mut(..size).ok_or(Status::BUFFER_TOO_SMALL)?;
+
+        buf.fill(0);
+        buf[0..4].copy_from_slice(&ENTRY_REVISION.to_le_bytes());
+        buf[4..6].copy_from_slice(&size16.to_le_bytes());
+        buf[6..8].copy_from_slice(&(name_offset as u16).to_le_bytes());
+        buf[8..24].copy_from_slice(&self.namespace.to_bytes());
+        buf[24..28].copy_from_slice(&self.min_size.to_le_bytes());
+        buf[28..32].copy_from_slice(&self.max_size.to_le_bytes());
+        buf[32..36].copy_from_slice(&self.attributes_must_have.bits().to_le_bytes());
+        buf[36..40].copy_from_slice(&self.attributes_cant_have.bits().to_le_bytes());
+        buf[40] = self.lock_policy.kind();
+
+        if let LockPolicy::OnVarState {
+            namespace,
+            name,
+            value,
+        } = self.lock_policy
+        {
+            let lock = &mut buf[ENTRY_HEADER_SIZE..name_offset];
+            lock[..16].copy_from_slice(&namespace.to_bytes());
+            lock[16] = value;
+            write_ucs2(&mut lock[VAR_STATE_HEADER_SIZE..], name);
+        }
+        if let Some(name) = self.name {
+            write_ucs2(&mut buf[name_offset..], name);
+        }
+        Ok(size)
+    }
+}
+
+fn write_ucs2(buf: &mut [u8], s: &CStr16) {
+    for (bytes, unit) in buf.chunks_exact_mut(2).zip(s.as_slice_with_nul()) {
+        bytes.copy_from_slice(&unit.to_le_bytes());
+    }
+}
+
+impl Proto<VariablePolicy> {
+    /// Returns whether policies are being enforced
+    pub fn is_enabled(&mut self) -> Result<bool> {
+        let mut state = false;
+        (self.is_variable_policy_enabled)(&mut state).to_result(state)
+    }
+
+    /// Registers a new policy
+    ///
+    /// Fails with `ALREADY_STARTED` if an identical policy exists, or with `WRITE_PROTECTED`
+    /// if the interface has been locked.
+    pub fn register(&mut self, policy: &VariablePolicyEntry<'_>) -> Result<()> {
+        let mut buf = [0; MAX_POLICY_SIZE];
+        let len = policy.write(&mut buf)?;
+        (self.register_variable_policy)(buf[..len].as_ptr()).to_result(())
+    }
+
+    /// Copies the registered policies, back to back, into `buf` and returns their total size
+    ///
+    /// Fails with `BUFFER_TOO_SMALL` if they don't fit.
+    pub fn dump(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let mut size = u32::try_from(buf.len()).unwrap_or(u32::MAX);
+        (self.dump_variable_policy)(buf.as_mut_ptr(), &mut size).to_result(size as usize)
+    }
+
+    /// Stops enforcing all policies until the next reset
+    ///
+    /// Only possible before the interface is locked; some platforms refuse it entirely.
+    pub fn disable(&mut self) -> Result<()> {
+        (self.disable_variable_policy)().to_result(())
+    }
+
+    /// Locks the interface, so that policies can no longer be registered or disabled
+    pub fn lock(&mut self) -> Result<()> {
+        (self.lock_variable_policy)().to_result(())
+    }
+}
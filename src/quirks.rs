@@ -0,0 +1,137 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Workarounds for firmware bugs
+//!
+//! Some firmware doesn't behave as the specification says, in ways the wrappers in this crate
+//! can work around once they know to. [`Quirks`] are flags toggling those workarounds; they
+//! are all off until [`apply()`] matches the running firmware against a table of [`Quirk`]
+//! entries, or [`set_quirks()`] sets them directly (e.g. from a configuration file).
+//!
+//! The crate ships no entries of its own, as a workaround is only worth its cost on the
+//! machines it was written for.
+
+use core::{
+    ops::RangeInclusive,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use crate::{table::SystemTable, Status};
+
+bitflags::bitflags! {
+    /// Workarounds to enable
+    #[repr(transparent)]
+    pub struct Quirks : u32 {
+        /// Don't trust the sizes reported alongside `BUFFER_TOO_SMALL`
+        ///
+        /// Some firmware reports a size that is still too small on the next call, so buffers
+        /// are allocated with half again as much room (and at least 256 bytes more).
+        const UNTRUSTED_BUFFER_SIZES = 1 << 0;
+        /// Don't free the mode information returned by GOP's `QueryMode()`
+        ///
+        /// Some drivers return a pointer to their own copy of the mode information rather
+        /// than a pool allocation, and freeing it corrupts the pool. Leaking the buffer is the
+        /// safe choice.
+        const LEAK_QUERY_MODE_INFO   = 1 << 1;
+        /// Treat `WARN_RESET_REQUESTED` as success
+        ///
+        /// Some firmware returns this warning from calls which completed normally, such as
+        /// `SetVariable()`, without anything actually waiting on a reset.
+        const IGNORE_RESET_REQUESTED = 1 << 2;
+    }
+}
+
+/// An entry in a table of known firmware bugs
+#[derive(Clone, Debug)]
+pub struct Quirk {
+    /// Prefix of the firmware vendor string, as returned by
+    /// [`SystemTable::firmware_vendor()`]
+    pub vendor:    &'static str,
+    /// Affected vendor-specific firmware revisions
+    pub revisions: RangeInclusive<u32>,
+    pub quirks:    Quirks,
+}
+
+impl Quirk {
+    /// Returns whether the firmware behind `system_table` is affected
+    pub fn matches(&self, system_table: &SystemTable) -> bool {
+        let mut vendor = system_table.firmware_vendor().chars();
+        self.vendor.chars().all(|c| vendor.next() == Some(c))
+            && self.revisions.contains(&system_table.firmware_revision())
+    }
+}
+
+static QUIRKS: AtomicU32 = AtomicU32::new(0);
+
+/// Returns the workarounds for every entry of `table` matching the running firmware
+pub fn detect(system_table: &SystemTable, table: &[Quirk]) -> Quirks {
+    table
+        .iter()
+        .filter(|quirk| quirk.matches(system_table))
+        .fold(Quirks::empty(), |quirks, quirk| quirks | quirk.quirks)
+}
+
+/// Enables the workarounds for every entry of `table` matching the running firmware
+///
+/// Workarounds which are already enabled stay enabled.
+pub fn apply(system_table: &SystemTable, table: &[Quirk]) {
+    QUIRKS.fetch_or(detect(system_table, table).bits(), Ordering::AcqRel);
+}
+
+/// Returns the enabled workarounds
+pub fn quirks() -> Quirks {
+    Quirks::from_bits_truncate(QUIRKS.load(Ordering::Acquire))
+}
+
+/// Replaces the enabled workarounds
+pub fn set_quirks(quirks: Quirks) {
+    QUIRKS.store(quirks.bits(), Ordering::Release);
+}
+
+/// Returns the size to allocate for a buffer the firmware said needs `reported` bytes
+pub(crate) fn buffer_size(reported: usize) -> usize {
+    if !quirks().contains(Quirks::UNTRUSTED_BUFFER_SIZES) {
+        return reported;
+    }
+    reported.saturating_add(usize::max(reported / 2, 256))
+}
+
+/// Returns whether the mode information returned by GOP's `QueryMode()` should be freed
+pub(crate) fn free_query_mode_info() -> bool {
+    !quirks().contains(Quirks::LEAK_QUERY_MODE_INFO)
+}
+
+/// Filters out warnings which the enabled workarounds ignore
+pub(crate) fn filter_status(status: Status) -> Status {
+    if status == Status::WARN_RESET_REQUESTED && quirks().contains(Quirks::IGNORE_RESET_REQUESTED) {
+        return Status::SUCCESS;
+    }
+    status
+}
@@ -0,0 +1,198 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! SHA-256 digests
+//!
+//! The firmware's Hash 2 Protocol is not available everywhere, so this module also provides
+//! a software implementation. [`digest()`] picks whichever is available, like
+//! [`crc32::checksum()`](crate::crc32::checksum).
+
+use crate::{
+    boot_services, boot_services_active,
+    proto::{
+        hash2::{Hash2, HASH_ALGORITHM_SHA256},
+        service_binding::ServiceBinding,
+    },
+    Result,
+};
+
+pub const DIGEST_SIZE: usize = 32;
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+const INITIAL_STATE: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// Incremental software SHA-256
+#[derive(Clone, Debug)]
+pub struct Hasher {
+    state:  [u32; 8],
+    block:  [u8; 64],
+    /// Total bytes hashed
+    length: u64,
+}
+
+impl Hasher {
+    pub const fn new() -> Self {
+        Self {
+            state:  INITIAL_STATE,
+            block:  [0; 64],
+            length: 0,
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        let buffered = (self.length % 64) as usize;
+        self.length += data.len() as u64;
+
+        if buffered > 0 {
+            let take = data.len().min(64 - buffered);
+            self.block[buffered..buffered + take].copy_from_slice(&data[..take]);
+            data = &data[take..];
+            if buffered + take < 64 {
+                return;
+            }
+            compress(&mut self.state, &self.block);
+        }
+
+        let mut blocks = data.chunks_exact(64);
+        for block in &mut blocks {
+            compress(&mut self.state, block.try_into().unwrap());
+        }
+        let rest = blocks.remainder();
+        self.block[..rest.len()].copy_from_slice(rest);
+    }
+
+    pub fn finish(mut self) -> [u8; DIGEST_SIZE] {
+        let bit_length = self.length * 8;
+        // Pad with a one bit, zeros, then the length in bits.
+        self.update(&[0x80]);
+        while self.length % 64 != 56 {
+            self.update(&[0]);
+        }
+        self.update(&bit_length.to_be_bytes());
+
+        let mut digest = [0; DIGEST_SIZE];
+        for (bytes, word) in digest.chunks_exact_mut(4).zip(self.state) {
+            bytes.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+}
+
+impl Default for Hasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn compress(state: &mut [u32; 8], block: &[u8; 64]) {
+    let mut w = [0u32; 64];
+    for (word, bytes) in w.iter_mut().zip(block.chunks_exact(4)) {
+        *word = u32::from_be_bytes(bytes.try_into().unwrap());
+    }
+    for i in 16..64 {
+        let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+        let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+        w[i] = w[i - 16]
+            .wrapping_add(s0)
+            .wrapping_add(w[i - 7])
+            .wrapping_add(s1);
+    }
+
+    let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = *state;
+    for i in 0..64 {
+        let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+        let ch = (e & f) ^ (!e & g);
+        let t1 = h
+            .wrapping_add(s1)
+            .wrapping_add(ch)
+            .wrapping_add(K[i])
+            .wrapping_add(w[i]);
+        let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+        let maj = (a & b) ^ (a & c) ^ (b & c);
+        let t2 = s0.wrapping_add(maj);
+        (h, g, f, e, d, c, b, a) = (g, f, e, d.wrapping_add(t1), c, b, a, t1.wrapping_add(t2));
+    }
+    for (word, value) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+        *word = word.wrapping_add(value);
+    }
+}
+
+/// Calculates the SHA-256 of `data` in software
+pub fn software(data: &[u8]) -> [u8; DIGEST_SIZE] {
+    let mut hasher = Hasher::new();
+    hasher.update(data);
+    hasher.finish()
+}
+
+/// Calculates the SHA-256 of `data` with the firmware's Hash 2 Protocol
+///
+/// Fails with `NOT_FOUND` if the protocol isn't installed, or `UNSUPPORTED` if it doesn't
+/// implement SHA-256.
+pub fn firmware(data: &[u8]) -> Result<[u8; DIGEST_SIZE]> {
+    let bs = boot_services();
+    let output = match bs.first_protocol::<Hash2>() {
+        Ok(mut hash2) => hash2.hash(&HASH_ALGORITHM_SHA256, data)?,
+        Err(_) => {
+            let mut service = bs.first_protocol::<ServiceBinding<Hash2>>()?;
+            let child = service.create_child()?;
+            let output = bs
+                .protocol_for_handle::<Hash2>(child)
+                .and_then(|mut hash2| hash2.hash(&HASH_ALGORITHM_SHA256, data));
+            let _ = service.destroy_child(child);
+            output?
+        }
+    };
+    Ok(output.0[..DIGEST_SIZE].try_into().unwrap())
+}
+
+/// Calculates the SHA-256 of `data`
+///
+/// The firmware's implementation is used if it is available, falling back to [`software()`]
+/// after `ExitBootServices()` or if the firmware can't hash.
+pub fn digest(data: &[u8]) -> [u8; DIGEST_SIZE] {
+    if boot_services_active() {
+        if let Ok(digest) = firmware(data) {
+            return digest;
+        }
+    }
+    software(data)
+}
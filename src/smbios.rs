@@ -0,0 +1,292 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! SMBIOS tables
+//!
+//! [`Smbios::locate()`] finds the structure table through the configuration table, preferring
+//! the SMBIOS 3 entry point. [`platform_id()`] reads the machine's identity from it, which
+//! loaders can use to pick per-machine configuration:
+//!
+//! ```text
+//! [machine:LENOVO/20XW*]
+//! cmdline = "i915.enable_psr=0"
+//! ```
+//!
+//! Given the prefix `machine:`, [`PlatformId::section()`] returns the first such section
+//! whose [pattern](PlatformId::matches) matches.
+
+use core::{fmt, slice};
+
+use crate::{config::Config, system_table, table::TableGuid, Result, Status};
+
+/// The SMBIOS structure table
+#[derive(Clone, Copy, Debug)]
+pub struct Smbios {
+    /// Major and minor version of the specification the table conforms to
+    pub version: (u8, u8),
+    table:       &'static [u8],
+}
+
+impl Smbios {
+    /// Finds the structure table through the configuration table
+    ///
+    /// Fails with `NOT_FOUND` if the firmware provides no SMBIOS tables, or with
+    /// `LOAD_ERROR` if the entry point is malformed.
+    pub fn locate() -> Result<Self> {
+        let config = system_table().config_table();
+        if let Some(entry) = config.get_table(TableGuid::SMBIOS3) {
+            let entry = unsafe { slice::from_raw_parts(entry.cast::<u8>(), 0x18) };
+            if &entry[..5] != b"_SM3_" || !checksum_valid(entry, entry[6]) {
+                return Err(Status::LOAD_ERROR);
+            }
+            let size = u32::from_le_bytes(entry[0x0c..0x10].try_into().unwrap());
+            let addr = u64::from_le_bytes(entry[0x10..0x18].try_into().unwrap());
+            return Ok(Self {
+                version: (entry[7], entry[8]),
+                table:   unsafe { slice::from_raw_parts(addr as *const u8, size as usize) },
+            });
+        }
+        if let Some(entry) = config.get_table(TableGuid::SMBIOS) {
+            let entry = unsafe { slice::from_raw_parts(entry.cast::<u8>(), 0x1f) };
+            if &entry[..4] != b"_SM_" || &entry[0x10..0x15] != b"_DMI_" {
+                return Err(Status::LOAD_ERROR);
+            }
+            if !checksum_valid(entry, entry[5]) || !checksum_valid(&entry[0x10..], 0x0f) {
+                return Err(Status::LOAD_ERROR);
+            }
+            let size = u16::from_le_bytes(entry[0x16..0x18].try_into().unwrap());
+            let addr = u32::from_le_bytes(entry[0x18..0x1c].try_into().unwrap());
+            return Ok(Self {
+                version: (entry[6], entry[7]),
+                table:   unsafe { slice::from_raw_parts(addr as *const u8, size as usize) },
+            });
+        }
+        Err(Status::NOT_FOUND)
+    }
+
+    /// Returns an iterator over the structures in the table
+    pub fn structures(&self) -> Structures<'static> {
+        Structures { table: self.table }
+    }
+
+    /// Returns the first structure of type `kind`
+    pub fn find(&self, kind: u8) -> Option<Structure<'static>> {
+        self.structures().find(|structure| structure.kind == kind)
+    }
+}
+
+/// Checks that the first `len` bytes of `data` sum to zero
+fn checksum_valid(data: &[u8], len: u8) -> bool {
+    data.get(..len as usize)
+        .is_some_and(|data| data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) == 0)
+}
+
+/// An SMBIOS structure
+#[derive(Clone, Copy, Debug)]
+pub struct Structure<'a> {
+    pub kind:   u8,
+    pub handle: u16,
+    /// The formatted area, including the four-byte header
+    pub data:   &'a [u8],
+    strings:    &'a [u8],
+}
+
+impl<'a> Structure<'a> {
+    pub const SYSTEM_INFORMATION: u8 = 1;
+    pub const END_OF_TABLE: u8 = 127;
+
+    /// Returns string number `index`, counting from one
+    ///
+    /// Index zero, which SMBIOS uses for a missing string, returns `None`, as does a string
+    /// which isn't valid UTF-8.
+    pub fn string(&self, index: u8) -> Option<&'a str> {
+        let index = usize::from(index).checked_sub(1)?;
+        let string = self.strings.split(|&b| b == 0).nth(index)?;
+        core::str::from_utf8(string).ok().filter(|s| !s.is_empty())
+    }
+
+    /// Returns the string referred to by the byte at `offset` in the formatted area
+    pub fn string_at(&self, offset: usize) -> Option<&'a str> {
+        self.string(*self.data.get(offset)?)
+    }
+}
+
+/// Iterator over the structures of an [`Smbios`] table
+#[derive(Clone, Debug)]
+pub struct Structures<'a> {
+    table: &'a [u8],
+}
+
+impl<'a> Iterator for Structures<'a> {
+    type Item = Structure<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let table = self.table;
+        let len = usize::from(*table.get(1)?);
+        if len < 4 || table.len() < len {
+            self.table = &[];
+            return None;
+        }
+        // The string set ends with two null bytes, even if it is empty.
+        let Some(end) = table[len..].windows(2).position(|w| w == [0, 0]) else {
+            self.table = &[];
+            return None;
+        };
+        let structure = Structure {
+            kind:    table[0],
+            handle:  u16::from_le_bytes([table[2], table[3]]),
+            data:    &table[..len],
+            strings: &table[len..len + end],
+        };
+        self.table = &table[len + end + 2..];
+        if structure.kind == Structure::END_OF_TABLE {
+            self.table = &[];
+        }
+        Some(structure)
+    }
+}
+
+/// The machine's identity, from the SMBIOS System Information structure
+///
+/// Placeholder strings left in by the firmware vendor, such as "To Be Filled By O.E.M.", are
+/// replaced with empty strings.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct PlatformId<'a> {
+    pub manufacturer: &'a str,
+    pub product:      &'a str,
+    pub version:      &'a str,
+}
+
+/// Returns the machine's identity
+///
+/// Fails with `NOT_FOUND` if there is no SMBIOS System Information structure.
+pub fn platform_id() -> Result<PlatformId<'static>> {
+    let system = Smbios::locate()?
+        .find(Structure::SYSTEM_INFORMATION)
+        .ok_or(Status::NOT_FOUND)?;
+    let field = |offset| {
+        system
+            .string_at(offset)
+            .map(str::trim)
+            .filter(|s| !is_placeholder(s))
+    };
+    Ok(PlatformId {
+        manufacturer: field(0x04).unwrap_or_default(),
+        product:      field(0x05).unwrap_or_default(),
+        version:      field(0x06).unwrap_or_default(),
+    })
+}
+
+/// Returns whether `s` is one of the placeholders commonly left in SMBIOS strings
+fn is_placeholder(s: &str) -> bool {
+    const PLACEHOLDERS: [&str; 7] = [
+        "To Be Filled By O.E.M.",
+        "Default string",
+        "Not Specified",
+        "None",
+        "System manufacturer",
+        "System Product Name",
+        "System Version",
+    ];
+    PLACEHOLDERS.iter().any(|p| p.eq_ignore_ascii_case(s))
+}
+
+impl<'a> PlatformId<'a> {
+    /// Returns whether `pattern` matches the machine
+    ///
+    /// The pattern is `manufacturer/product/version`, where trailing fields may be left out to
+    /// match anything. Each field is a glob, in which `*` matches any number of characters and
+    /// `?` a single one. The comparison ignores case and anything but letters and digits, so
+    /// `dell inc/xps 13*` matches a "Dell Inc." "XPS 13 9310".
+    pub fn matches(&self, pattern: &str) -> bool {
+        let mut fields = pattern.splitn(3, '/');
+        [self.manufacturer, self.product, self.version]
+            .into_iter()
+            .all(|value| match fields.next() {
+                Some(glob) => glob_matches(glob, value),
+                None => true,
+            })
+    }
+
+    /// Returns the first section of `config` named `prefix` followed by a pattern matching the
+    /// machine
+    pub fn section<'c>(&self, config: &Config<'c>, prefix: &str) -> Option<&'c str> {
+        config.sections().find(|section| {
+            section
+                .strip_prefix(prefix)
+                .is_some_and(|pattern| self.matches(pattern))
+        })
+    }
+}
+
+impl fmt::Display for PlatformId<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}/{}", self.manufacturer, self.product, self.version)
+    }
+}
+
+/// Matches `value` against `glob`, both normalized as described in [`PlatformId::matches()`]
+fn glob_matches(glob: &str, value: &str) -> bool {
+    fn normalize<'s>(s: &'s str, keep: &'static [u8]) -> impl Iterator<Item = u8> + Clone + 's {
+        s.bytes()
+            .filter(move |b| b.is_ascii_alphanumeric() || *b >= 0x80 || keep.contains(b))
+            .map(|b| b.to_ascii_lowercase())
+    }
+
+    let mut glob = normalize(glob, b"*?");
+    let mut value = normalize(value, b"");
+    // Where to resume after the last `*`, should the rest fail to match
+    let mut backtrack = None;
+    loop {
+        let (g, v) = (glob.clone().next(), value.clone().next());
+        match (g, v) {
+            (Some(b'*'), _) => {
+                glob.next();
+                backtrack = Some((glob.clone(), value.clone()));
+            }
+            (Some(g), Some(v)) if g == b'?' || g == v => {
+                glob.next();
+                value.next();
+            }
+            (None, None) => return true,
+            _ => {
+                let Some((star_glob, star_value)) = &mut backtrack else {
+                    return false;
+                };
+                // Let the `*` swallow one more character and try again.
+                if star_value.next().is_none() {
+                    return false;
+                }
+                glob = star_glob.clone();
+                value = star_value.clone();
+            }
+        }
+    }
+}
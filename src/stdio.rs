@@ -0,0 +1,191 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Locked access to the console
+//!
+//! The system table's `ConOut` and `StdErr` are shared by everything that prints, including
+//! event callbacks and the panic handler, which may interrupt normal output. [`stdout()`] and
+//! [`stderr()`] return a guard which holds a spinlock at [`Tpl::NOTIFY`]: callbacks cannot run
+//! while it is held, so lines from different sources never interleave.
+//!
+//! A callback which is already running cannot be interrupted by code taking the lock, so
+//! spinning only deadlocks if the holder itself tries to print again, e.g. when formatting a
+//! value panics. Panic handlers should therefore use [`try_stdout()`] or [`try_stderr()`].
+
+use core::{
+    fmt, hint,
+    ops::{Deref, DerefMut},
+    ptr,
+    sync::atomic::{AtomicBool, AtomicPtr, Ordering},
+};
+
+use crate::{
+    boot_services, boot_services_active,
+    proto::console::text_output::{ConsoleWriter, SimpleTextOutput},
+    system_table, Tpl,
+};
+
+static STDOUT_LOCKED: AtomicBool = AtomicBool::new(false);
+static STDERR_LOCKED: AtomicBool = AtomicBool::new(false);
+
+/// Interfaces found by [`reconnect_console()`](crate::table::SystemTable::reconnect_console),
+/// used instead of the system table's if not null
+static STDOUT: AtomicPtr<SimpleTextOutput> = AtomicPtr::new(ptr::null_mut());
+static STDERR: AtomicPtr<SimpleTextOutput> = AtomicPtr::new(ptr::null_mut());
+
+/// Exclusive access to a console output device, released when dropped
+pub struct ConsoleGuard {
+    out:     &'static mut SimpleTextOutput,
+    locked:  &'static AtomicBool,
+    old_tpl: Tpl,
+}
+
+/// Locks the console output device, waiting if it is in use
+///
+/// This must not be called above [`Tpl::NOTIFY`] or after boot services have been exited.
+pub fn stdout() -> ConsoleGuard {
+    lock(&STDOUT_LOCKED, stdout_ptr)
+}
+
+/// Locks the standard error device, waiting if it is in use
+///
+/// This must not be called above [`Tpl::NOTIFY`] or after boot services have been exited.
+pub fn stderr() -> ConsoleGuard {
+    lock(&STDERR_LOCKED, stderr_ptr)
+}
+
+/// Locks the console output device, or returns `None` if it is in use or boot services are
+/// not available
+pub fn try_stdout() -> Option<ConsoleGuard> {
+    try_lock(&STDOUT_LOCKED, stdout_ptr)
+}
+
+/// Locks the standard error device, or returns `None` if it is in use or boot services are
+/// not available
+pub fn try_stderr() -> Option<ConsoleGuard> {
+    try_lock(&STDERR_LOCKED, stderr_ptr)
+}
+
+fn stdout_ptr() -> *mut SimpleTextOutput {
+    let ptr = STDOUT.load(Ordering::Acquire);
+    if ptr.is_null() {
+        system_table().stdout.as_ptr()
+    } else {
+        ptr
+    }
+}
+
+fn stderr_ptr() -> *mut SimpleTextOutput {
+    let ptr = STDERR.load(Ordering::Acquire);
+    if ptr.is_null() {
+        system_table().stderr.as_ptr()
+    } else {
+        ptr
+    }
+}
+
+/// Replaces the interfaces used for stdout and stderr; null restores the system table's
+///
+/// Each is swapped while its lock is held, so output in progress finishes on the old device.
+pub(crate) fn set_consoles(stdout: *mut SimpleTextOutput, stderr: *mut SimpleTextOutput) {
+    for (locked, slot, new) in [
+        (&STDOUT_LOCKED, &STDOUT, stdout),
+        (&STDERR_LOCKED, &STDERR, stderr),
+    ] {
+        let old_tpl = acquire(locked);
+        slot.store(new, Ordering::Release);
+        locked.store(false, Ordering::Release);
+        boot_services().restore_tpl(old_tpl);
+    }
+}
+
+fn acquire(locked: &AtomicBool) -> Tpl {
+    let old_tpl = boot_services().raise_tpl(Tpl::NOTIFY);
+    while locked.swap(true, Ordering::Acquire) {
+        hint::spin_loop();
+    }
+    old_tpl
+}
+
+fn lock(locked: &'static AtomicBool, out: impl FnOnce() -> *mut SimpleTextOutput) -> ConsoleGuard {
+    let old_tpl = acquire(locked);
+    ConsoleGuard {
+        out: unsafe { &mut *out() },
+        locked,
+        old_tpl,
+    }
+}
+
+fn try_lock(
+    locked: &'static AtomicBool,
+    out: impl FnOnce() -> *mut SimpleTextOutput,
+) -> Option<ConsoleGuard> {
+    if !boot_services_active() {
+        return None;
+    }
+    let old_tpl = boot_services().raise_tpl(Tpl::NOTIFY);
+    if locked.swap(true, Ordering::Acquire) {
+        boot_services().restore_tpl(old_tpl);
+        return None;
+    }
+    Some(ConsoleGuard {
+        out: unsafe { &mut *out() },
+        locked,
+        old_tpl,
+    })
+}
+
+impl Deref for ConsoleGuard {
+    type Target = SimpleTextOutput;
+
+    fn deref(&self) -> &SimpleTextOutput {
+        self.out
+    }
+}
+
+impl DerefMut for ConsoleGuard {
+    fn deref_mut(&mut self) -> &mut SimpleTextOutput {
+        self.out
+    }
+}
+
+impl fmt::Write for ConsoleGuard {
+    /// Writes `s` like [`ConsoleWriter`], replacing characters the console cannot display
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        fmt::Write::write_str(&mut ConsoleWriter::new(self.out), s)
+    }
+}
+
+impl Drop for ConsoleGuard {
+    fn drop(&mut self) {
+        self.locked.store(false, Ordering::Release);
+        boot_services().restore_tpl(self.old_tpl);
+    }
+}
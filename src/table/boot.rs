@@ -30,16 +30,30 @@
 
 #[cfg(feature = "alloc")]
 use alloc::boxed::Box;
-use core::{ffi::c_void, mem::size_of, ptr};
+use core::{
+    convert::Infallible,
+    ffi::c_void,
+    fmt,
+    mem::{size_of, size_of_val, MaybeUninit},
+    ops::Deref,
+    ptr::{self, NonNull},
+    slice,
+    sync::atomic::Ordering,
+};
 
-use super::TableHeader;
+use super::{Capabilities, TableHeader};
 use crate::{
+    capabilities,
     proto::{DevicePath, Proto, Protocol},
-    Event, Guid, Handle, PhysicalAddr, Result, Status, Tpl, VirtualAddr,
+    quirks,
+    trace::traced,
+    ucs2::CStr16,
+    DeviceHandle, DriverHandle, Event, Guid, Handle, ImageHandle, PhysicalAddr, Result, Status,
+    Tpl, VirtualAddr,
 };
 
 pub type CreateEventFn = extern "efiapi" fn(
-    kind: u32,
+    kind: EventType,
     notify_tpl: Tpl,
     notify_fn: Option<EventNotifyFn>,
     notify_ctx: *mut c_void,
@@ -49,13 +63,26 @@ pub type CreateEventFn = extern "efiapi" fn(
 pub type EventNotifyFn = extern "efiapi" fn(event: Event, ctx: *mut c_void) -> Status;
 
 pub type CreateEventExFn = extern "efiapi" fn(
-    kind: u32,
+    kind: EventType,
     notify_tpl: Tpl,
     notify_fn: Option<EventNotifyFn>,
-    notify_ctx: *mut c_void,
-    event_group: *mut Guid,
+    notify_ctx: *const c_void,
+    event_group: *const Guid,
+    event: *mut Event,
 ) -> Status;
 
+bitflags::bitflags! {
+    #[repr(transparent)]
+    pub struct EventType : u32 {
+        const TIMER                         = 0x80000000;
+        const RUNTIME                       = 0x40000000;
+        const NOTIFY_WAIT                   = 0x00000100;
+        const NOTIFY_SIGNAL                 = 0x00000200;
+        const SIGNAL_EXIT_BOOT_SERVICES     = 0x00000201;
+        const SIGNAL_VIRTUAL_ADDRESS_CHANGE = 0x60000202;
+    }
+}
+
 pub type CloseEventFn = extern "efiapi" fn(event: Event) -> Status;
 
 pub type SignalEventFn = extern "efiapi" fn(event: Event) -> Status;
@@ -103,6 +130,15 @@ macro_rules! memory_types {
     ($($name:ident = $value:expr),*$(,)?) => {
         impl MemoryType {
             $(pub const $name: Self = Self($value);)*
+
+            /// Returns the name of a type defined by the specification, e.g.
+            /// `CONVENTIONAL_MEMORY`
+            pub const fn name(self) -> Option<&'static str> {
+                match self {
+                    $(Self::$name => Some(stringify!($name)),)*
+                    _ => None,
+                }
+            }
         }
     }
 }
@@ -126,6 +162,64 @@ memory_types! {
     UNACCEPTED              = 15,
 }
 
+impl MemoryType {
+    /// First memory type reserved for OEM use
+    pub const OEM_BASE: u32 = 0x7000_0000;
+    /// First memory type reserved for use by operating system loaders
+    pub const OS_BASE: u32 = 0x8000_0000;
+
+    /// Returns the `n`th OEM memory type, or `None` if `n` is outside the OEM range
+    pub const fn oem(n: u32) -> Option<Self> {
+        if n < Self::OS_BASE - Self::OEM_BASE {
+            Some(Self(Self::OEM_BASE + n))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the `n`th OS memory type, or `None` if `n` is outside the OS range
+    ///
+    /// Allocating the kernel or boot information under an OS type makes it easy to find in
+    /// the memory map after boot.
+    pub const fn os(n: u32) -> Option<Self> {
+        if n <= u32::MAX - Self::OS_BASE {
+            Some(Self(Self::OS_BASE + n))
+        } else {
+            None
+        }
+    }
+
+    pub const fn is_oem(self) -> bool {
+        self.0 >= Self::OEM_BASE && self.0 < Self::OS_BASE
+    }
+
+    pub const fn is_os(self) -> bool {
+        self.0 >= Self::OS_BASE
+    }
+
+    /// Returns `true` if the type may be passed to `AllocatePages()` or `AllocatePool()`
+    ///
+    /// Free memory types and the range between the standard and OEM types are rejected by
+    /// firmware with `INVALID_PARAMETER`.
+    pub const fn is_allocatable(self) -> bool {
+        match self {
+            Self::CONVENTIONAL_MEMORY | Self::PERSISTENT | Self::UNACCEPTED => false,
+            Self(n) => n <= Self::UNACCEPTED.0 || n >= Self::OEM_BASE,
+        }
+    }
+
+    /// Returns `true` for memory that is free or owned by the loader
+    ///
+    /// This covers conventional memory, the loader's own allocations and any OS memory types,
+    /// i.e. everything the loader may hand to the kernel without consulting firmware.
+    pub const fn is_usable_by_loader(self) -> bool {
+        matches!(
+            self,
+            Self::CONVENTIONAL_MEMORY | Self::LOADER_CODE | Self::LOADER_DATA
+        ) || self.is_os()
+    }
+}
+
 #[cfg(feature = "limine")]
 impl From<MemoryType> for limine::MemoryKind {
     fn from(value: MemoryType) -> limine::MemoryKind {
@@ -156,7 +250,6 @@ pub type GetMemoryMapFn = extern "efiapi" fn(
     descriptor_version: *mut u32,
 ) -> Status;
 
-#[repr(C)]
 #[repr(C)]
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct MemoryDescriptor {
@@ -167,6 +260,47 @@ pub struct MemoryDescriptor {
     pub attribute: MemoryAttribute,
 }
 
+impl MemoryDescriptor {
+    /// Memory map entries are always counted in 4 KiB pages, regardless of the architecture
+    pub const PAGE_SIZE: u64 = 4096;
+
+    /// Returns the size of the region in bytes
+    pub const fn byte_len(&self) -> u64 {
+        self.num_pages.saturating_mul(Self::PAGE_SIZE)
+    }
+
+    /// Returns the first physical address past the end of the region
+    pub const fn end_phys(&self) -> PhysicalAddr {
+        self.phys.saturating_add(self.byte_len())
+    }
+
+    /// Returns `true` if the physical address `addr` lies within the region
+    pub const fn contains(&self, addr: PhysicalAddr) -> bool {
+        addr >= self.phys && addr < self.end_phys()
+    }
+
+    /// Returns `true` if the region must be mapped for runtime services
+    pub const fn is_runtime(&self) -> bool {
+        self.attribute.contains(MemoryAttribute::RUNTIME)
+    }
+
+    /// Returns `true` if the OS may use the region as general memory once boot services have
+    /// been exited
+    ///
+    /// Loader allocations are included; anything still needed by the kernel (the kernel image
+    /// itself, boot information) should be allocated under an OS memory type instead.
+    pub const fn usable_after_ebs(&self) -> bool {
+        matches!(
+            self.kind,
+            MemoryType::CONVENTIONAL_MEMORY
+                | MemoryType::LOADER_CODE
+                | MemoryType::LOADER_DATA
+                | MemoryType::BOOT_SERVICES_CODE
+                | MemoryType::BOOT_SERVICES_DATA
+        )
+    }
+}
+
 bitflags::bitflags! {
     #[repr(transparent)]
     pub struct MemoryAttribute : u64 {
@@ -276,28 +410,31 @@ pub type OpenProtocolInformationFn = extern "efiapi" fn(
     handle: Handle,
     protocol: *mut Guid,
     entry_buffer: *mut *mut OpenProtocolInformationEntry,
-    entry_count: usize,
+    entry_count: *mut usize,
 ) -> Status;
 
 #[repr(C)]
+#[derive(Debug)]
 pub struct OpenProtocolInformationEntry {
+    /// The image that opened the protocol
     pub agent_handle:      Handle,
-    pub controller_handle: Handle,
+    /// The controller managed by the agent, if it is a driver
+    pub controller_handle: Option<Handle>,
     pub attributes:        OpenProtocolAttributes,
     pub open_count:        u32,
 }
 
 pub type ConnectControllerFn = extern "efiapi" fn(
     controller_handle: Handle,
-    driver_image_handle: *mut Handle,
-    remaining_device_path: Proto<DevicePath>,
+    driver_image_handle: *mut Option<Handle>,
+    remaining_device_path: Option<Proto<DevicePath>>,
     recursive: bool,
 ) -> Status;
 
 pub type DisconnectControllerFn = extern "efiapi" fn(
     controller_handle: Handle,
-    driver_image_handle: Handle,
-    child_handle: Handle,
+    driver_image_handle: Option<Handle>,
+    child_handle: Option<Handle>,
 ) -> Status;
 
 pub type ProtocolsPerHandleFn = extern "efiapi" fn(
@@ -381,11 +518,28 @@ pub type InstallConfigurationTableFn =
 pub type CalculateCrc32Fn =
     extern "efiapi" fn(data: *mut c_void, data_size: usize, crc32: *mut u32) -> Status;
 
-#[repr(C)]
-#[derive(Debug)]
-pub struct BootServices {
-    pub header: TableHeader,
+macro_rules! boot_services_table {
+    ($($field:ident: $ty:ty,)*) => {
+        #[repr(C)]
+        #[derive(Debug)]
+        pub struct BootServices {
+            pub header: TableHeader,
+            $(pub(crate) $field: $ty,)*
+        }
+
+        /// The boot services table with its function pointers exposed
+        ///
+        /// See [`BootServices::raw()`].
+        #[repr(C)]
+        #[derive(Debug)]
+        pub struct RawBootServices {
+            pub header: TableHeader,
+            $(pub $field: $ty,)*
+        }
+    };
+}
 
+boot_services_table! {
     // Task Priority Services
     raise_tpl:   RaiseTplFn,
     restore_tpl: RestoreTplFn,
@@ -459,17 +613,35 @@ pub struct BootServices {
 
 impl !Sync for BootServices {}
 
+impl BootServices {
+    /// Returns the table's raw function pointers
+    ///
+    /// This is an escape hatch for working around firmware bugs the safe wrappers don't
+    /// accommodate; prefer the wrappers wherever possible.
+    ///
+    /// # Safety
+    ///
+    /// The function pointers can be called without `unsafe`, but take raw pointers and are
+    /// not checked in any way. Each call must satisfy the specification's requirements, and
+    /// must not invalidate anything the crate relies on: e.g. calling `exit_boot_services`
+    /// directly leaves [`boot_services_active()`](crate::boot_services_active) returning
+    /// `true`.
+    pub unsafe fn raw(&self) -> &RawBootServices {
+        &*(self as *const Self).cast::<RawBootServices>()
+    }
+}
+
 /// Task Priority Services
 impl BootServices {
     /// Raises the task's priority level, returning the previous one
     ///
     /// The new priority level must be
     pub fn raise_tpl(&self, tpl: Tpl) -> Tpl {
-        (self.raise_tpl)(tpl)
+        traced!("RaiseTPL", "{:?}", tpl; (self.raise_tpl)(tpl))
     }
 
     pub fn restore_tpl(&self, old: Tpl) {
-        (self.restore_tpl)(old);
+        traced!("RestoreTPL", "{:?}", old; (self.restore_tpl)(old));
     }
 }
 
@@ -487,8 +659,120 @@ pub struct MemoryMapInfo {
     pub map_key:            usize,
 }
 
+impl MemoryMapInfo {
+    /// Returns an iterator over the descriptors in a buffer filled by
+    /// [`BootServices::get_memory_map()`]
+    ///
+    /// Descriptors are copied out, as firmware may use a stride larger than
+    /// [`MemoryDescriptor`] and the buffer need not be aligned.
+    pub fn descriptors<'a>(&self, buffer: &'a [u8]) -> impl Iterator<Item = MemoryDescriptor> + 'a {
+        let stride = self.descriptor_size.max(size_of::<MemoryDescriptor>());
+        let len = self.buffer_size.min(buffer.len());
+        buffer[..len]
+            .chunks_exact(stride)
+            .map(|chunk| unsafe { ptr::read_unaligned(chunk.as_ptr().cast::<MemoryDescriptor>()) })
+    }
+}
+
+/// Result of [`BootServices::start_image()`]
+#[derive(Debug)]
+pub struct StartImageOutcome {
+    /// The status the image exited with, or the error which prevented it from starting
+    pub status:  Status,
+    /// The message the image passed to `Exit()`, if any
+    #[cfg(feature = "alloc")]
+    pub message: Option<crate::ucs2::CString16>,
+}
+
+impl StartImageOutcome {
+    pub fn to_result(&self) -> Result<()> {
+        self.status.to_result(())
+    }
+}
+
+impl fmt::Display for StartImageOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.status)?;
+        #[cfg(feature = "alloc")]
+        if let Some(message) = &self.message {
+            write!(f, ": {message}")?;
+        }
+        Ok(())
+    }
+}
+
+/// An array allocated by the firmware, freed with `FreePool()` when dropped
+pub struct PoolSlice<T> {
+    ptr: *mut T,
+    len: usize,
+}
+
+impl<T> PoolSlice<T> {
+    /// # Safety
+    ///
+    /// `ptr` must be a pool allocation holding `len` initialized elements, or null if `len` is
+    /// zero.
+    pub unsafe fn from_raw(ptr: *mut T, len: usize) -> Self {
+        Self { ptr, len }
+    }
+}
+
+impl<T> Deref for PoolSlice<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        if self.ptr.is_null() {
+            return &[];
+        }
+        unsafe { slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl<T> Drop for PoolSlice<T> {
+    fn drop(&mut self) {
+        // The buffer is simply leaked if boot services have been exited.
+        if !self.ptr.is_null() && crate::boot_services_active() {
+            unsafe {
+                ptr::drop_in_place(ptr::slice_from_raw_parts_mut(self.ptr, self.len));
+                let _ = crate::boot_services().free_pool(self.ptr.cast());
+            }
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for PoolSlice<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+/// The protocols installed on a handle, as returned by [`BootServices::protocols_per_handle()`]
+#[derive(Debug)]
+pub struct ProtocolGuids(PoolSlice<*const Guid>);
+
+impl ProtocolGuids {
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Guid> + '_ {
+        self.0.iter().map(|&guid| unsafe { *guid })
+    }
+}
+
+/// Key returned by [`BootServices::register_protocol_notify()`]
+///
+/// It stays valid until the event it was registered with is closed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ProtocolRegistration(NonNull<c_void>);
+
 /// Memory Services
 impl BootServices {
+    #[track_caller]
     pub fn allocate_pages(
         &self,
         alloc_type: AllocPagesType,
@@ -500,35 +784,65 @@ impl BootServices {
             AllocPagesType::Max(addr) => (AllocType::MaxAddress, addr),
             AllocPagesType::Addr(addr) => (AllocType::Address, addr),
         };
-        let status = (self.allocate_pages)(alloc_type, memory_type, num_pages, &mut memory);
+        let status = traced!(
+            "AllocatePages", "{:?}, {:?}, {}, {:#x}", alloc_type, memory_type, num_pages, memory;
+            (self.allocate_pages)(alloc_type, memory_type, num_pages, &mut memory)
+        );
+        #[cfg(feature = "alloc_tracker")]
+        if status == Status::SUCCESS {
+            let size = num_pages.saturating_mul(MemoryDescriptor::PAGE_SIZE as usize);
+            crate::alloc_tracker::record(memory_type, memory, size, true);
+        }
         status.to_result(memory)
     }
 
     pub unsafe fn free_pages(&self, memory: PhysicalAddr, num_pages: usize) -> Result<()> {
-        (self.free_pages)(memory, num_pages).to_result(())
+        traced!("FreePages", "{:#x}, {}", memory, num_pages; (self.free_pages)(memory, num_pages))
+            .to_result(())?;
+        #[cfg(feature = "alloc_tracker")]
+        crate::alloc_tracker::release_pages(
+            memory,
+            num_pages.saturating_mul(MemoryDescriptor::PAGE_SIZE as usize),
+        );
+        Ok(())
     }
 
+    #[track_caller]
     pub fn allocate_pool(&self, pool_type: MemoryType, size: usize) -> Result<*mut u8> {
         let mut buffer = ptr::null_mut();
-        let status = (self.allocate_pool)(pool_type, size, &mut buffer);
+        let status = traced!(
+            "AllocatePool", "{:?}, {}", pool_type, size;
+            (self.allocate_pool)(pool_type, size, &mut buffer)
+        );
+        #[cfg(feature = "alloc_tracker")]
+        if status == Status::SUCCESS {
+            crate::alloc_tracker::record(pool_type, buffer as PhysicalAddr, size, false);
+        }
         status.to_result(buffer.cast())
     }
 
     pub unsafe fn free_pool(&self, buffer: *mut u8) -> Result<()> {
-        (self.free_pool)(buffer.cast()).to_result(())
+        traced!("FreePool", "{:p}", buffer; (self.free_pool)(buffer.cast())).to_result(())?;
+        #[cfg(feature = "alloc_tracker")]
+        crate::alloc_tracker::release_pool(buffer as PhysicalAddr);
+        Ok(())
     }
 
     pub fn get_memory_map_info(&self) -> Result<MemoryMapInfo> {
         let mut info = MemoryMapInfo::default();
 
-        match (self.get_memory_map)(
+        let status = traced!("GetMemoryMap", "0"; (self.get_memory_map)(
             &mut info.buffer_size,
             ptr::null_mut(),
             &mut info.map_key,
             &mut info.descriptor_size,
             &mut info.descriptor_version,
-        ) {
-            Status::BUFFER_TOO_SMALL => Ok(info),
+        ));
+        match status {
+            Status::BUFFER_TOO_SMALL => {
+                info.buffer_size = quirks::buffer_size(info.buffer_size);
+                Ok(info)
+            }
             status => Err(status),
         }
     }
@@ -540,13 +854,14 @@ impl BootServices {
             ..Default::default()
         };
 
-        match (self.get_memory_map)(
+        let status = traced!("GetMemoryMap", "{}", info.buffer_size; (self.get_memory_map)(
             &mut info.buffer_size,
             buffer.as_mut_ptr().cast(),
             &mut info.map_key,
             &mut info.descriptor_size,
             &mut info.descriptor_version,
-        ) {
+        ));
+        match status {
             Status::SUCCESS => Ok(info),
             status => Err(status),
         }
@@ -554,42 +869,267 @@ impl BootServices {
 }
 
 /// Event and Timer Services
-impl BootServices {}
+impl BootServices {
+    /// Creates an event
+    ///
+    /// # Safety
+    ///
+    /// `notify_fn` is called with `notify_ctx` at `notify_tpl` whenever the event is waited on
+    /// or signaled (depending on `kind`), until the event is closed. `notify_ctx` must remain
+    /// valid for that long, and `notify_fn` must be safe to run at that priority level.
+    pub unsafe fn create_event(
+        &self,
+        kind: EventType,
+        notify_tpl: Tpl,
+        notify_fn: Option<EventNotifyFn>,
+        notify_ctx: *mut c_void,
+    ) -> Result<Event> {
+        let mut event = Event(ptr::null_mut());
+        traced!(
+            "CreateEvent", "{:?}, {:?}, {:?}, {:p}", kind, notify_tpl, notify_fn, notify_ctx;
+            (self.create_event)(kind, notify_tpl, notify_fn, notify_ctx, &mut event)
+        )
+        .to_result(event)
+    }
+
+    /// Creates a timer event without a notification function
+    ///
+    /// The event can be armed with [`set_timer()`](Self::set_timer) and then polled with
+    /// [`check_event()`](Self::check_event) or waited on with
+    /// [`wait_for_event()`](Self::wait_for_event).
+    pub fn create_timer_event(&self) -> Result<Event> {
+        unsafe { self.create_event(EventType::TIMER, Tpl::CALLBACK, None, ptr::null_mut()) }
+    }
+
+    /// Arms (or, with [`TimerDelay::Cancel`], disarms) a timer event
+    ///
+    /// `trigger_time` is in units of 100ns.
+    pub fn set_timer(&self, event: Event, kind: TimerDelay, trigger_time: u64) -> Result<()> {
+        traced!(
+            "SetTimer", "{:?}, {:?}, {}", event, kind, trigger_time;
+            (self.set_timer)(event, kind, trigger_time)
+        )
+        .to_result(())
+    }
+
+    /// Waits for any of `events` to be signaled, returning its index
+    ///
+    /// The signaled event is reset. This may only be called at [`Tpl::APPLICATION`].
+    pub fn wait_for_event(&self, events: &[Event]) -> Result<usize> {
+        let mut index = 0;
+        traced!(
+            "WaitForEvent", "{}, {:p}", events.len(), events.as_ptr();
+            (self.wait_for_event)(events.len(), events.as_ptr().cast_mut(), &mut index)
+        )
+        .to_result(index)
+    }
+
+    pub fn signal_event(&self, event: Event) -> Result<()> {
+        traced!("SignalEvent", "{:?}", event; (self.signal_event)(event)).to_result(())
+    }
+
+    /// Returns whether an event is signaled, resetting it if so
+    pub fn check_event(&self, event: Event) -> Result<bool> {
+        match traced!("CheckEvent", "{:?}", event; (self.check_event)(event)) {
+            Status::SUCCESS => Ok(true),
+            Status::NOT_READY => Ok(false),
+            status => Err(status),
+        }
+    }
+
+    pub fn close_event(&self, event: Event) -> Result<()> {
+        traced!("CloseEvent", "{:?}", event; (self.close_event)(event)).to_result(())
+    }
+}
 
 /// Protocol Handler Services
 impl BootServices {
+    /// Signals `event` whenever `P` is installed or reinstalled on a handle
+    ///
+    /// The handles are then returned one at a time by
+    /// [`locate_notified_handle()`](Self::locate_notified_handle).
+    pub fn register_protocol_notify<P: Protocol>(
+        &self,
+        event: Event,
+    ) -> Result<ProtocolRegistration> {
+        let mut guid = P::GUID;
+        let mut registration = ptr::null_mut();
+        traced!(
+            "RegisterProtocolNotify", "{:?}, {:?}", guid, event;
+            (self.register_protocol_notify)(&mut guid, event, &mut registration)
+        )
+        .to_result(())?;
+        NonNull::new(registration)
+            .map(ProtocolRegistration)
+            .ok_or(Status::DEVICE_ERROR)
+    }
+
+    /// Returns the next handle `P` was installed on since `registration` was made, if any
+    pub fn locate_notified_handle(
+        &self,
+        registration: ProtocolRegistration,
+    ) -> Result<Option<Handle>> {
+        let mut handle = MaybeUninit::<Handle>::uninit();
+        let mut buffer_size = size_of::<Handle>();
+        let status = traced!(
+            "LocateHandle", "ByRegisterNotify, {:p}", registration.0;
+            (self.locate_handle)(
+                LocateSearchType::ByRegisterNotify,
+                ptr::null_mut(),
+                registration.0.as_ptr(),
+                &mut buffer_size,
+                handle.as_mut_ptr(),
+            )
+        );
+        match status {
+            Status::SUCCESS => Ok(Some(unsafe { handle.assume_init() })),
+            Status::NOT_FOUND => Ok(None),
+            status => Err(status),
+        }
+    }
+
     #[cfg(feature = "alloc")]
     pub fn handles_by_protocol<P: Protocol>(&self) -> Result<Box<[Handle]>> {
         let mut guid = P::GUID;
         let mut buffer_size = 0;
 
-        match (self.locate_handle)(
+        let status = traced!("LocateHandle", "ByProtocol, {:?}, 0", guid; (self.locate_handle)(
             LocateSearchType::ByProtocol,
             &mut guid,
             ptr::null_mut(),
             &mut buffer_size,
             ptr::null_mut(),
-        ) {
+        ));
+        match status {
             Status::BUFFER_TOO_SMALL => {}
             Status::NOT_FOUND => panic!("no block devices"),
             Status::SUCCESS => panic!(),
             status => status.to_result(())?,
         }
 
+        buffer_size = quirks::buffer_size(buffer_size);
         buffer_size = (buffer_size + (size_of::<Handle>() - 1)) & !(size_of::<Handle>() - 1);
 
-        let mut buffer = Box::new_uninit_slice(buffer_size / size_of::<Handle>());
+        let mut buffer = Box::<[Handle]>::new_uninit_slice(buffer_size / size_of::<Handle>());
 
-        (self.locate_handle)(
+        traced!("LocateHandle", "ByProtocol, {:?}, {}", guid, buffer_size; (self.locate_handle)(
             LocateSearchType::ByProtocol,
             &mut guid,
             ptr::null_mut(),
             &mut buffer_size,
             buffer.as_mut_ptr().cast(),
+        ))
+        .to_result(())?;
+
+        // The buffer may be larger than needed; only the handles written are initialized.
+        let len = (buffer_size / size_of::<Handle>()).min(buffer.len());
+        Ok(unsafe { slice::from_raw_parts(buffer.as_ptr().cast::<Handle>(), len) }.into())
+    }
+
+    /// Returns the number of handles in the handle database
+    pub fn all_handles_len(&self) -> Result<usize> {
+        let mut buffer_size = 0;
+        let status = traced!("LocateHandle", "AllHandles, 0"; (self.locate_handle)(
+            LocateSearchType::AllHandles,
+            ptr::null_mut(),
+            ptr::null_mut(),
+            &mut buffer_size,
+            ptr::null_mut(),
+        ));
+        match status {
+            Status::BUFFER_TOO_SMALL => Ok(buffer_size / size_of::<Handle>()),
+            status => status.to_result(0),
+        }
+    }
+
+    /// Fills `buf` with every handle in the handle database
+    ///
+    /// Fails with `BUFFER_TOO_SMALL` if `buf` can't hold [`all_handles_len()`] handles.
+    ///
+    /// [`all_handles_len()`]: Self::all_handles_len
+    pub fn all_handles_into<'b>(&self, buf: &'b mut [MaybeUninit<Handle>]) -> Result<&'b [Handle]> {
+        let mut buffer_size = size_of_val(buf);
+        traced!("LocateHandle", "AllHandles, {}", buffer_size; (self.locate_handle)(
+            LocateSearchType::AllHandles,
+            ptr::null_mut(),
+            ptr::null_mut(),
+            &mut buffer_size,
+            buf.as_mut_ptr().cast(),
+        ))
+        .to_result(())?;
+        let len = buffer_size / size_of::<Handle>();
+        Ok(unsafe { slice::from_raw_parts(buf.as_ptr().cast(), len) })
+    }
+
+    /// Returns every handle in the handle database
+    #[cfg(feature = "alloc")]
+    pub fn all_handles(&self) -> Result<Box<[Handle]>> {
+        loop {
+            // Leave some room in case handles are created in between the two calls.
+            let len = self.all_handles_len()? + 8;
+            let mut buffer = Box::new_uninit_slice(len);
+            match self.all_handles_into(&mut buffer) {
+                Ok(handles) => return Ok(handles.into()),
+                Err(Status::BUFFER_TOO_SMALL) => continue,
+                Err(status) => return Err(status),
+            }
+        }
+    }
+
+    /// Returns the GUIDs of the protocols installed on `handle`
+    pub fn protocols_per_handle(&self, handle: Handle) -> Result<ProtocolGuids> {
+        capabilities().require(Capabilities::LIBRARY_SERVICES)?;
+        let mut buffer = ptr::null_mut();
+        let mut count = 0;
+        traced!(
+            "ProtocolsPerHandle", "{:?}", handle;
+            (self.protocols_per_handle)(handle, &mut buffer, &mut count)
         )
         .to_result(())?;
+        Ok(ProtocolGuids(unsafe {
+            PoolSlice::from_raw(buffer.cast(), count)
+        }))
+    }
 
-        Ok(unsafe { buffer.assume_init() })
+    /// Returns the agents that have `protocol` on `handle` open
+    pub fn open_protocol_information(
+        &self,
+        handle: Handle,
+        protocol: &Guid,
+    ) -> Result<PoolSlice<OpenProtocolInformationEntry>> {
+        capabilities().require(Capabilities::OPEN_PROTOCOL)?;
+        let mut guid = *protocol;
+        let mut buffer = ptr::null_mut();
+        let mut count = 0;
+        traced!(
+            "OpenProtocolInformation", "{:?}, {:?}", handle, guid;
+            (self.open_protocol_information)(handle, &mut guid, &mut buffer, &mut count)
+        )
+        .to_result(())?;
+        Ok(unsafe { PoolSlice::from_raw(buffer, count) })
+    }
+
+    /// Writes every handle with its protocols and device path, for diagnostics
+    ///
+    /// Protocols known to this crate are shown by name, others by GUID.
+    #[cfg(feature = "alloc")]
+    pub fn dump_handles(&self, out: &mut dyn fmt::Write) -> Result<()> {
+        let fmt_err = |_| Status::DEVICE_ERROR;
+        for &handle in self.all_handles()?.iter() {
+            write!(out, "{:p}:", handle.as_ptr()).map_err(fmt_err)?;
+            for guid in self.protocols_per_handle(handle)?.iter() {
+                match crate::proto::protocol_name(&guid) {
+                    Some(name) => write!(out, " {name}"),
+                    None => write!(out, " {guid}"),
+                }
+                .map_err(fmt_err)?;
+            }
+            if let Ok(path) = self.protocol_for_handle::<DevicePath>(handle) {
+                write!(out, "\n    {}", *path).map_err(fmt_err)?;
+            }
+            writeln!(out).map_err(fmt_err)?;
+        }
+        Ok(())
     }
 
     pub fn protocol_for_handle<P: Protocol>(&self, handle: Handle) -> Result<Proto<P>> {
@@ -600,17 +1140,95 @@ impl BootServices {
         //     (self.open_protocol)(handle, &mut guid, ptr::addr_of_mut!(proto).cast(), )
         //     todo!()
         // } else {
-        (self.handle_protocol)(handle, &mut guid, ptr::addr_of_mut!(proto).cast()).to_result(())?;
+        traced!(
+            "HandleProtocol", "{:?}, {:?}", handle, guid;
+            (self.handle_protocol)(handle, &mut guid, ptr::addr_of_mut!(proto).cast())
+        )
+        .to_result(())?;
         Ok(proto.unwrap())
         // }
     }
 
+    /// Finds the handle supporting `P` whose device path matches the longest prefix of `path`
+    ///
+    /// Returns the handle and the remainder of `path` after the matched prefix, which is an
+    /// empty path if the handle's device path is `path` itself.
+    pub fn locate_device_path<'p, P: Protocol>(
+        &self,
+        path: &'p DevicePath,
+    ) -> Result<(Handle, &'p DevicePath)> {
+        let mut guid = P::GUID;
+        let mut remaining = path as *const DevicePath;
+        let mut handle = MaybeUninit::<Handle>::uninit();
+        traced!(
+            "LocateDevicePath", "{:?}, {}", guid, path;
+            (self.locate_device_path)(
+                &mut guid,
+                ptr::addr_of_mut!(remaining).cast(),
+                handle.as_mut_ptr(),
+            )
+        )
+        .to_result(())?;
+        Ok(unsafe { (handle.assume_init(), &*remaining) })
+    }
+
+    /// Installs `interface` as protocol `P` on `handle`, or on a new handle if `None`
+    ///
+    /// Returns the handle the protocol was installed on.
+    ///
+    /// # Safety
+    ///
+    /// `interface` must point to a valid `P`, which stays valid and usable by other images until
+    /// it is uninstalled.
+    pub unsafe fn install_protocol_interface<P: Protocol>(
+        &self,
+        handle: Option<Handle>,
+        interface: *const P,
+    ) -> Result<Handle> {
+        let mut guid = P::GUID;
+        let mut handle = handle;
+        traced!(
+            "InstallProtocolInterface", "{:?}, {:?}, {:p}", handle, guid, interface;
+            (self.install_protocol_interface)(
+                ptr::addr_of_mut!(handle).cast(),
+                &mut guid,
+                InterfaceType::Native,
+                interface.cast_mut().cast(),
+            )
+        )
+        .to_result(())?;
+        handle.ok_or(Status::INVALID_PARAMETER)
+    }
+
+    /// Removes protocol `P`, implemented by `interface`, from `handle`
+    ///
+    /// Fails with `ACCESS_DENIED` if the protocol is still in use by a driver.
+    ///
+    /// # Safety
+    ///
+    /// Nothing may use the interface afterwards.
+    pub unsafe fn uninstall_protocol_interface<P: Protocol>(
+        &self,
+        handle: Handle,
+        interface: *const P,
+    ) -> Result<()> {
+        let mut guid = P::GUID;
+        traced!(
+            "UninstallProtocolInterface", "{:?}, {:?}, {:p}", handle, guid, interface;
+            (self.uninstall_protocol_interface)(handle, &mut guid, interface.cast_mut().cast())
+        )
+        .to_result(())
+    }
+
     pub fn first_protocol<P: Protocol>(&self) -> Result<Proto<P>> {
-        if self.header.revision >= (1 << 16) | 10 {
+        if capabilities().contains(Capabilities::LIBRARY_SERVICES) {
             let mut guid = P::GUID;
             let mut proto = Option::<Proto<P>>::None;
-            (self.locate_protocol)(&mut guid, ptr::null_mut(), ptr::addr_of_mut!(proto).cast())
-                .to_result(())?;
+            traced!(
+                "LocateProtocol", "{:?}", guid;
+                (self.locate_protocol)(&mut guid, ptr::null_mut(), ptr::addr_of_mut!(proto).cast())
+            )
+            .to_result(())?;
             Ok(proto.unwrap())
         } else {
             let handles = self.handles_by_protocol::<P>()?;
@@ -621,8 +1239,141 @@ impl BootServices {
 
 /// Image Services
 impl BootServices {
-    pub fn exit_boot_services(&self, image_handle: Handle, map_key: usize) -> Result<()> {
-        (self.exit_boot_services)(image_handle, map_key).to_result(())
+    /// Loads an image from the file at `path`
+    ///
+    /// With `boot_policy`, the path may also name a device with the Load File Protocol, as
+    /// for network boot options.
+    pub fn load_image_from_path(
+        &self,
+        parent: ImageHandle,
+        path: &DevicePath,
+        boot_policy: bool,
+    ) -> Result<ImageHandle> {
+        self.load_image(boot_policy, parent, Some(path), &[])
+    }
+
+    /// Loads an image from memory
+    ///
+    /// `path` is recorded as the image's file path and, under Secure Boot, is what the
+    /// platform's policy is applied to.
+    pub fn load_image_from_buffer(
+        &self,
+        parent: ImageHandle,
+        data: &[u8],
+        path: Option<&DevicePath>,
+    ) -> Result<ImageHandle> {
+        if data.is_empty() {
+            return Err(Status::INVALID_PARAMETER);
+        }
+        self.load_image(false, parent, path, data)
+    }
+
+    fn load_image(
+        &self,
+        boot_policy: bool,
+        parent: ImageHandle,
+        path: Option<&DevicePath>,
+        data: &[u8],
+    ) -> Result<ImageHandle> {
+        let path = path.map(|path| unsafe { Proto::new(NonNull::from(path).cast()) });
+        let source = match data {
+            [] => ptr::null_mut(),
+            data => data.as_ptr().cast_mut().cast(),
+        };
+        let mut handle = None::<ImageHandle>;
+        let status = traced!(
+            "LoadImage", "{}, {:?}, {}", boot_policy, parent, data.len();
+            (self.load_image)(
+                boot_policy,
+                parent.handle(),
+                path,
+                source,
+                data.len(),
+                ptr::addr_of_mut!(handle).cast(),
+            )
+        );
+        // An image which fails verification is still loaded, but can never be started.
+        if status == Status::SECURITY_VIOLATION {
+            if let Some(handle) = handle {
+                let _ = self.unload_image(handle);
+            }
+        }
+        status.to_result(())?;
+        handle.ok_or(Status::LOAD_ERROR)
+    }
+
+    /// Starts a loaded image and waits for it to exit
+    ///
+    /// The image's exit data is copied into the returned message and freed.
+    pub fn start_image(&self, image: ImageHandle) -> StartImageOutcome {
+        let mut exit_data_size = 0;
+        let mut exit_data = ptr::null_mut::<u16>();
+        let status = traced!(
+            "StartImage", "{:?}", image;
+            (self.start_image)(image.handle(), &mut exit_data_size, &mut exit_data)
+        );
+
+        #[cfg(feature = "alloc")]
+        let message = (!exit_data.is_null()).then(|| {
+            // The string may be followed by binary data, or be missing its terminator.
+            let units =
+                unsafe { slice::from_raw_parts(exit_data, exit_data_size / size_of::<u16>()) };
+            crate::ucs2::CString16::from_units_until_nul(units)
+        });
+        if !exit_data.is_null() {
+            let _ = unsafe { self.free_pool(exit_data.cast()) };
+        }
+
+        StartImageOutcome {
+            status,
+            #[cfg(feature = "alloc")]
+            message,
+        }
+    }
+
+    /// Unloads an image which has not been started, or which supports being unloaded
+    pub fn unload_image(&self, image: ImageHandle) -> Result<()> {
+        traced!("UnloadImage", "{:?}", image; (self.unload_image)(image.handle())).to_result(())
+    }
+
+    /// Exits the image `image`, returning `status` and `message` to whoever started it
+    ///
+    /// This only returns if exiting fails.
+    pub fn exit(
+        &self,
+        image: ImageHandle,
+        status: Status,
+        message: Option<&CStr16>,
+    ) -> Result<Infallible> {
+        // Exit data must be pool memory, since the caller of `StartImage()` frees it.
+        let (exit_data, exit_data_size) = match message {
+            Some(message) => {
+                let units = message.as_slice_with_nul();
+                let size = size_of_val(units);
+                let data = self.allocate_pool(crate::default_memory_type(), size)?;
+                unsafe { ptr::copy_nonoverlapping(units.as_ptr(), data.cast(), units.len()) };
+                (data.cast::<u16>(), size)
+            }
+            None => (ptr::null_mut(), 0),
+        };
+        let result = traced!(
+            "Exit", "{:?}, {:?}, {}", image, status, exit_data_size;
+            (self.exit)(image.handle(), status, exit_data_size, exit_data)
+        );
+        if !exit_data.is_null() {
+            let _ = unsafe { self.free_pool(exit_data.cast()) };
+        }
+        Err(result)
+    }
+
+    pub fn exit_boot_services(&self, image_handle: ImageHandle, map_key: usize) -> Result<()> {
+        traced!(
+            "ExitBootServices", "{:?}, {}", image_handle, map_key;
+            (self.exit_boot_services)(image_handle.handle(), map_key)
+        )
+        .to_result(())?;
+        crate::BOOT_SERVICES_EXITED.store(true, Ordering::Release);
+        Ok(())
     }
 }
 
@@ -630,10 +1381,171 @@ impl BootServices {
 impl BootServices {
     pub fn next_monotonic_count(&self) -> Result<u64> {
         let mut count = 0;
-        let status = (self.get_next_monotonic_count)(&mut count);
+        let status = traced!("GetNextMonotonicCount"; (self.get_next_monotonic_count)(&mut count));
         status.to_result(count)
     }
+
+    /// Busy-waits for at least `microseconds`
+    pub fn stall(&self, microseconds: usize) -> Result<()> {
+        traced!("Stall", "{}", microseconds; (self.stall)(microseconds)).to_result(())
+    }
+
+    /// Sets the watchdog timer, or disables it if `timeout` is zero
+    ///
+    /// `timeout` is in seconds. Codes up to `0xffff` are reserved for firmware; `data` is an
+    /// optional null-terminated string logged with the code, followed by binary data.
+    pub fn set_watchdog_timer(&self, timeout: usize, code: u64, data: &[u16]) -> Result<()> {
+        let data_ptr = match data {
+            [] => ptr::null_mut(),
+            data => data.as_ptr().cast_mut(),
+        };
+        let data_size = size_of_val(data);
+        traced!(
+            "SetWatchdogTimer", "{}, {:#x}, {}", timeout, code, data_size;
+            (self.set_watchdog_timer)(timeout, code, data_size, data_ptr)
+        )
+        .to_result(())
+    }
+
+    /// Runs `f` with the watchdog timer disabled, then re-arms it with `restore_timeout` seconds
+    ///
+    /// The timer is also re-armed if `f` unwinds. Firmware arms a 5 minute watchdog before
+    /// starting a boot option, which long network downloads can easily exceed; pass
+    /// [`DEFAULT_WATCHDOG_TIMEOUT`] to restore it.
+    pub fn with_watchdog_disabled<R>(
+        &self,
+        restore_timeout: usize,
+        f: impl FnOnce() -> R,
+    ) -> Result<R> {
+        struct Rearm<'a>(&'a BootServices, usize);
+
+        impl Drop for Rearm<'_> {
+            fn drop(&mut self) {
+                let _ = self.0.set_watchdog_timer(self.1, WATCHDOG_CODE, &[]);
+            }
+        }
+
+        self.set_watchdog_timer(0, WATCHDOG_CODE, &[])?;
+        let _rearm = Rearm(self, restore_timeout);
+        Ok(f())
+    }
+
+    /// Adds or replaces the configuration table entry for `guid`, or removes it if `table` is
+    /// null
+    ///
+    /// # Safety
+    ///
+    /// `table` must point to data in the format consumers of `guid` expect, in memory which
+    /// stays valid for as long as the entry is installed. Tables the OS uses after boot must
+    /// not be in boot services or loader memory it may reclaim first.
+    pub unsafe fn install_configuration_table(
+        &self,
+        guid: &Guid,
+        table: *const c_void,
+    ) -> Result<()> {
+        let mut guid = *guid;
+        traced!(
+            "InstallConfigurationTable", "{:?}, {:p}", guid, table;
+            (self.install_configuration_table)(&mut guid, table.cast_mut())
+        )
+        .to_result(())
+    }
 }
 
+/// The watchdog timeout, in seconds, armed by the boot manager before starting an image
+pub const DEFAULT_WATCHDOG_TIMEOUT: usize = 300;
+
+/// First watchdog code not reserved for firmware
+const WATCHDOG_CODE: u64 = 0x10000;
+
 /// DriverSupport Services
-impl BootServices {}
+impl BootServices {
+    /// Connects drivers to `controller`
+    ///
+    /// `drivers` are tried in order before the drivers firmware would pick itself; without the
+    /// `alloc` feature, at most [`MAX_CONNECT_DRIVERS`] may be given. For bus controllers,
+    /// `remaining_path` limits which child is created, and `recursive` connects drivers to the
+    /// children as well.
+    pub fn connect_controller(
+        &self,
+        controller: DeviceHandle,
+        drivers: &[DriverHandle],
+        remaining_path: Option<&DevicePath>,
+        recursive: bool,
+    ) -> Result<()> {
+        capabilities().require(Capabilities::CONNECT_CONTROLLER)?;
+        // The list of drivers is terminated by a null handle.
+        #[cfg(feature = "alloc")]
+        let mut list = drivers
+            .iter()
+            .map(|driver| Some(driver.handle()))
+            .chain([None])
+            .collect::<alloc::vec::Vec<_>>();
+        #[cfg(not(feature = "alloc"))]
+        let mut list = {
+            if drivers.len() > MAX_CONNECT_DRIVERS {
+                return Err(Status::INVALID_PARAMETER);
+            }
+            let mut list = [None; MAX_CONNECT_DRIVERS + 1];
+            for (entry, driver) in list.iter_mut().zip(drivers) {
+                *entry = Some(driver.handle());
+            }
+            list
+        };
+        let list = match drivers {
+            [] => ptr::null_mut(),
+            _ => list.as_mut_ptr(),
+        };
+        let path = remaining_path.map(|path| unsafe { Proto::new(NonNull::from(path).cast()) });
+        traced!(
+            "ConnectController", "{:?}, {:?}, {}", controller, drivers, recursive;
+            (self.connect_controller)(controller.handle(), list, path, recursive)
+        )
+        .to_result(())
+    }
+
+    /// Disconnects `driver`, or all drivers if `None`, from `controller`
+    ///
+    /// If `child` is given, only that child controller is destroyed.
+    pub fn disconnect_controller(
+        &self,
+        controller: DeviceHandle,
+        driver: Option<DriverHandle>,
+        child: Option<DeviceHandle>,
+    ) -> Result<()> {
+        capabilities().require(Capabilities::CONNECT_CONTROLLER)?;
+        traced!(
+            "DisconnectController", "{:?}, {:?}, {:?}", controller, driver, child;
+            (self.disconnect_controller)(
+                controller.handle(),
+                driver.map(DriverHandle::handle),
+                child.map(DeviceHandle::handle),
+            )
+        )
+        .to_result(())
+    }
+}
+
+/// Most drivers which can be passed to [`BootServices::connect_controller()`] without the
+/// `alloc` feature
+///
+/// The list is then built on the stack. Drivers are only given explicitly to override the
+/// firmware's choice for a controller, which rarely involves more than one or two.
+pub const MAX_CONNECT_DRIVERS: usize = 16;
+
+/// 32-bit CRC Services
+impl BootServices {
+    /// Calculates the CRC32 of `data` using the firmware's implementation
+    ///
+    /// See [`crc32::checksum()`](crate::crc32::checksum) for a version which keeps working
+    /// after boot services have been exited.
+    pub fn calculate_crc32(&self, data: &[u8]) -> Result<u32> {
+        capabilities().require(Capabilities::CALCULATE_CRC32)?;
+        let mut crc = 0;
+        traced!(
+            "CalculateCrc32", "{:p}, {}", data.as_ptr(), data.len();
+            (self.calculate_crc32)(data.as_ptr().cast_mut().cast(), data.len(), &mut crc)
+        )
+        .to_result(crc)
+    }
+}
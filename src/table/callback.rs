@@ -0,0 +1,233 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Event notification callbacks
+//!
+//! Notification functions run at [`Tpl::CALLBACK`] or [`Tpl::NOTIFY`], where only part of the
+//! boot services may be used: waiting for events, loading images and most protocol services
+//! are restricted to lower priority levels. A closure registered with
+//! [`BootServices::create_callback_event()`] is handed a [`CallbackCtx`] which exposes just
+//! the services that are safe at [`Tpl::NOTIFY`], so calling anything else requires reaching
+//! for the global [`boot_services()`](crate::boot_services) on purpose.
+
+#[cfg(feature = "alloc")]
+use alloc::boxed::Box;
+#[cfg(feature = "alloc")]
+use core::{ffi::c_void, ptr};
+
+#[cfg(feature = "alloc")]
+use super::EventType;
+use super::{AllocPagesType, BootServices, MemoryType, TimerDelay};
+#[cfg(feature = "alloc")]
+use crate::Status;
+use crate::{Event, PhysicalAddr, Result, Tpl};
+
+/// The boot services which may be used from an event notification function
+///
+/// Memory allocation, event and timer services, TPL changes and the miscellaneous services
+/// are all allowed up to [`Tpl::NOTIFY`]. Notification functions of
+/// [`EventType::SIGNAL_EXIT_BOOT_SERVICES`](super::EventType::SIGNAL_EXIT_BOOT_SERVICES)
+/// events must not allocate or free memory either.
+pub struct CallbackCtx<'a> {
+    bs:    &'a BootServices,
+    event: Event,
+    tpl:   Tpl,
+}
+
+impl<'a> CallbackCtx<'a> {
+    /// # Safety
+    ///
+    /// The caller must be running at `tpl`, which must not be above [`Tpl::NOTIFY`].
+    pub unsafe fn new(bs: &'a BootServices, event: Event, tpl: Tpl) -> Self {
+        Self { bs, event, tpl }
+    }
+
+    /// Returns the event being notified
+    pub fn event(&self) -> Event {
+        self.event
+    }
+
+    /// Returns the priority level the callback is running at
+    pub fn tpl(&self) -> Tpl {
+        self.tpl
+    }
+
+    pub fn allocate_pages(
+        &self,
+        alloc_type: AllocPagesType,
+        memory_type: MemoryType,
+        num_pages: usize,
+    ) -> Result<PhysicalAddr> {
+        self.bs.allocate_pages(alloc_type, memory_type, num_pages)
+    }
+
+    /// # Safety
+    ///
+    /// See [`BootServices::free_pages()`].
+    pub unsafe fn free_pages(&self, memory: PhysicalAddr, num_pages: usize) -> Result<()> {
+        self.bs.free_pages(memory, num_pages)
+    }
+
+    pub fn allocate_pool(&self, pool_type: MemoryType, size: usize) -> Result<*mut u8> {
+        self.bs.allocate_pool(pool_type, size)
+    }
+
+    /// # Safety
+    ///
+    /// See [`BootServices::free_pool()`].
+    pub unsafe fn free_pool(&self, buffer: *mut u8) -> Result<()> {
+        self.bs.free_pool(buffer)
+    }
+
+    pub fn create_timer_event(&self) -> Result<Event> {
+        self.bs.create_timer_event()
+    }
+
+    pub fn set_timer(&self, event: Event, kind: TimerDelay, trigger_time: u64) -> Result<()> {
+        self.bs.set_timer(event, kind, trigger_time)
+    }
+
+    pub fn signal_event(&self, event: Event) -> Result<()> {
+        self.bs.signal_event(event)
+    }
+
+    pub fn check_event(&self, event: Event) -> Result<bool> {
+        self.bs.check_event(event)
+    }
+
+    pub fn close_event(&self, event: Event) -> Result<()> {
+        self.bs.close_event(event)
+    }
+
+    /// Runs `f` at a higher priority level
+    ///
+    /// `tpl` is clamped so that it is never below the callback's own level.
+    pub fn with_raised_tpl<R>(&self, tpl: Tpl, f: impl FnOnce() -> R) -> R {
+        let old = self.bs.raise_tpl(tpl.max(self.tpl));
+        let result = f();
+        self.bs.restore_tpl(old);
+        result
+    }
+
+    pub fn stall(&self, microseconds: usize) -> Result<()> {
+        self.bs.stall(microseconds)
+    }
+
+    pub fn next_monotonic_count(&self) -> Result<u64> {
+        self.bs.next_monotonic_count()
+    }
+
+    pub fn calculate_crc32(&self, data: &[u8]) -> Result<u32> {
+        self.bs.calculate_crc32(data)
+    }
+}
+
+#[cfg(feature = "alloc")]
+struct CallbackState<F> {
+    bs:  *const BootServices,
+    tpl: Tpl,
+    f:   F,
+}
+
+#[cfg(feature = "alloc")]
+extern "efiapi" fn notify_trampoline<F: FnMut(&CallbackCtx)>(
+    event: Event,
+    ctx: *mut c_void,
+) -> Status {
+    let state = unsafe { &mut *ctx.cast::<CallbackState<F>>() };
+    let ctx = unsafe { CallbackCtx::new(&*state.bs, event, state.tpl) };
+    (state.f)(&ctx);
+    Status::SUCCESS
+}
+
+/// An event with a closure as its notification function, closed when dropped
+#[cfg(feature = "alloc")]
+pub struct CallbackEvent<'a, F> {
+    bs:    &'a BootServices,
+    event: Event,
+    state: *mut CallbackState<F>,
+}
+
+#[cfg(feature = "alloc")]
+impl<F> CallbackEvent<'_, F> {
+    pub fn event(&self) -> Event {
+        self.event
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<F> Drop for CallbackEvent<'_, F> {
+    fn drop(&mut self) {
+        // Once the event is closed the closure can no longer be called.
+        if self.bs.close_event(self.event).is_ok() {
+            drop(unsafe { Box::from_raw(self.state) });
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl BootServices {
+    /// Creates an event which calls `f` at `tpl` whenever it is signaled or, for
+    /// [`EventType::NOTIFY_WAIT`] events, waited on
+    ///
+    /// `tpl` must be [`Tpl::CALLBACK`] or [`Tpl::NOTIFY`], and `kind` must include one of the
+    /// notification types. The closure is freed when the returned event is dropped.
+    pub fn create_callback_event<F>(
+        &self,
+        kind: EventType,
+        tpl: Tpl,
+        f: F,
+    ) -> Result<CallbackEvent<'_, F>>
+    where
+        F: FnMut(&CallbackCtx) + 'static,
+    {
+        if !(Tpl::CALLBACK..=Tpl::NOTIFY).contains(&tpl)
+            || !kind.intersects(EventType::NOTIFY_WAIT | EventType::NOTIFY_SIGNAL)
+        {
+            return Err(Status::INVALID_PARAMETER);
+        }
+        let state = Box::into_raw(Box::new(CallbackState {
+            bs: ptr::from_ref(self),
+            tpl,
+            f,
+        }));
+        match unsafe { self.create_event(kind, tpl, Some(notify_trampoline::<F>), state.cast()) } {
+            Ok(event) => Ok(CallbackEvent {
+                bs: self,
+                event,
+                state,
+            }),
+            Err(status) => {
+                drop(unsafe { Box::from_raw(state) });
+                Err(status)
+            }
+        }
+    }
+}
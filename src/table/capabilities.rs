@@ -0,0 +1,153 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Services available on the running firmware
+//!
+//! The service tables have grown with each revision of the specification, and EFI 1.02
+//! firmware has shorter tables than this crate's definitions: calling a function pointer past
+//! the end of one calls whatever happens to follow the table in memory. [`Capabilities`]
+//! records which of the later services exist, judged by each table's revision and size.
+
+use core::mem::{offset_of, size_of};
+
+use super::{RawBootServices, Revision, RuntimeServices, SystemTable, TableHeader};
+use crate::{Result, Status};
+
+bitflags::bitflags! {
+    /// Optional services provided by the firmware
+    ///
+    /// Computed once by [`bootstrap()`](crate::bootstrap) and returned by
+    /// [`capabilities()`](crate::capabilities).
+    #[repr(transparent)]
+    pub struct Capabilities : u32 {
+        /// `ConnectController()` and `DisconnectController()` (EFI 1.10)
+        const CONNECT_CONTROLLER    = 1 << 0;
+        /// `OpenProtocol()`, `CloseProtocol()` and `OpenProtocolInformation()` (EFI 1.10)
+        const OPEN_PROTOCOL         = 1 << 1;
+        /// `ProtocolsPerHandle()`, `LocateHandleBuffer()`, `LocateProtocol()` and
+        /// `(Un)InstallMultipleProtocolInterfaces()` (EFI 1.10)
+        const LIBRARY_SERVICES      = 1 << 2;
+        /// `CalculateCrc32()` (EFI 1.10)
+        const CALCULATE_CRC32       = 1 << 3;
+        /// `CopyMem()` and `SetMem()` (EFI 1.10)
+        const COPY_MEM              = 1 << 4;
+        /// `CreateEventEx()` (UEFI 2.0)
+        const CREATE_EVENT_EX       = 1 << 5;
+        /// `UpdateCapsule()` and `QueryCapsuleCapabilities()` (UEFI 2.0)
+        const CAPSULE               = 1 << 6;
+        /// `QueryVariableInfo()` (UEFI 2.0)
+        const QUERY_VARIABLE_INFO   = 1 << 7;
+    }
+}
+
+/// Returns whether a table of `revision` includes a field ending at `end`
+fn has_field(header: &TableHeader, revision: Revision, end: usize) -> bool {
+    Revision(header.revision) >= revision && header.header_size as usize >= end
+}
+
+macro_rules! field_end {
+    ($table:ty, $field:ident) => {
+        offset_of!($table, $field) + size_of::<usize>()
+    };
+}
+
+impl Capabilities {
+    /// Determines the services provided by the tables of `system_table`
+    pub fn of(system_table: &SystemTable) -> Self {
+        let mut capabilities = Self::empty();
+
+        let bs = &system_table.boot_services().header;
+        let boot_services = [
+            (
+                Self::CONNECT_CONTROLLER,
+                Revision::EFI_1_10,
+                field_end!(RawBootServices, disconnect_controller),
+            ),
+            (
+                Self::OPEN_PROTOCOL,
+                Revision::EFI_1_10,
+                field_end!(RawBootServices, open_protocol_information),
+            ),
+            (
+                Self::LIBRARY_SERVICES,
+                Revision::EFI_1_10,
+                field_end!(RawBootServices, uninstall_multiple_protocol_interfaces),
+            ),
+            (
+                Self::CALCULATE_CRC32,
+                Revision::EFI_1_10,
+                field_end!(RawBootServices, calculate_crc32),
+            ),
+            (
+                Self::COPY_MEM,
+                Revision::EFI_1_10,
+                field_end!(RawBootServices, set_mem),
+            ),
+            (
+                Self::CREATE_EVENT_EX,
+                Revision::UEFI_2_0,
+                field_end!(RawBootServices, create_event_ex),
+            ),
+        ];
+        for (capability, revision, end) in boot_services {
+            capabilities.set(capability, has_field(bs, revision, end));
+        }
+
+        // Host mocks provide no runtime services table.
+        let Some(rt) = (unsafe { system_table.runtime_services.as_ref() }) else {
+            return capabilities;
+        };
+        let runtime_services = [
+            (
+                Self::CAPSULE,
+                Revision::UEFI_2_0,
+                field_end!(RuntimeServices, query_capsule_capabilities),
+            ),
+            (
+                Self::QUERY_VARIABLE_INFO,
+                Revision::UEFI_2_0,
+                field_end!(RuntimeServices, query_variable_info),
+            ),
+        ];
+        for (capability, revision, end) in runtime_services {
+            capabilities.set(capability, has_field(&rt.header, revision, end));
+        }
+
+        capabilities
+    }
+
+    /// Fails with `UNSUPPORTED` unless all of `required` are provided
+    pub fn require(self, required: Self) -> Result<()> {
+        if !self.contains(required) {
+            return Err(Status::UNSUPPORTED);
+        }
+        Ok(())
+    }
+}
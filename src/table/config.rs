@@ -91,6 +91,8 @@ table_guids! {
     RT_PROPERTIES = guid!(0xeb66918a,0x7eef,0x402a,{0x84,0x2e,0x93,0x1d,0x21,0xc3,0x8a,0xe9});
 
     MEMORY_ATTRIBUTES = guid!(0xdcfa911d,0x26eb,0x469f,{0xa2,0x20,0x38,0xb7,0xdc,0x46,0x12,0x20});
+
+    DEBUG_IMAGE_INFO = guid!(0x49152e77,0x1ada,0x4764,{0xb7,0xa2,0x7a,0xfe,0xfe,0xd9,0x5e,0x8b});
 }
 
 #[repr(C)]
@@ -0,0 +1,523 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Memory map snapshots
+//!
+//! [`MemoryMap`] pairs a buffer filled by [`BootServices::get_memory_map()`] with the
+//! information needed to read it. Snapshots taken at different times can be
+//! [compared](MemoryMap::diff), e.g. to find what firmware allocated between the loader's
+//! first look at the map and the final one before `ExitBootServices()`. The same snapshot
+//! can be [serialized](MemoryMap::write_packed) for kernels expecting different boot
+//! protocols.
+
+use core::fmt;
+
+use super::{BootServices, MemoryDescriptor, MemoryMapInfo, MemoryType};
+use crate::{PhysicalAddr, Result, Status};
+
+/// A memory map held in a caller-provided buffer
+#[derive(Clone, Copy, Debug)]
+pub struct MemoryMap<'a> {
+    info:   MemoryMapInfo,
+    buffer: &'a [u8],
+}
+
+impl<'a> MemoryMap<'a> {
+    /// Wraps a buffer filled by [`BootServices::get_memory_map()`] and described by `info`
+    pub const fn new(info: MemoryMapInfo, buffer: &'a [u8]) -> Self {
+        Self { info, buffer }
+    }
+
+    pub const fn info(&self) -> &MemoryMapInfo {
+        &self.info
+    }
+
+    /// Returns the key to pass to [`BootServices::exit_boot_services()`]
+    pub const fn key(&self) -> usize {
+        self.info.map_key
+    }
+
+    /// Returns the number of descriptors
+    pub fn len(&self) -> usize {
+        let stride = self.info.descriptor_size.max(size_of::<MemoryDescriptor>());
+        self.info.buffer_size.min(self.buffer.len()) / stride
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the descriptors as the firmware wrote them, e.g. to hand the map to a kernel
+    pub fn as_bytes(&self) -> &'a [u8] {
+        &self.buffer[..self.info.buffer_size.min(self.buffer.len())]
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = MemoryDescriptor> + 'a {
+        self.info.descriptors(self.buffer)
+    }
+
+    /// Returns an iterator over the descriptors in order of their physical address
+    pub fn sorted(&self) -> impl Iterator<Item = MemoryDescriptor> + 'a {
+        let map = *self;
+        let key = |(index, desc): &(usize, MemoryDescriptor)| (desc.phys, *index);
+        let next_after = move |last: Option<(PhysicalAddr, usize)>| {
+            map.iter()
+                .enumerate()
+                .filter(|entry| last.is_none_or(|last| key(entry) > last))
+                .min_by_key(key)
+        };
+        core::iter::successors(next_after(None), move |entry| next_after(Some(key(entry))))
+            .map(|(_, desc)| desc)
+    }
+
+    /// Returns the number of bytes [`write_packed()`](Self::write_packed) needs at most
+    pub fn packed_size(&self, format: Format) -> usize {
+        format.header_size() + self.len() * format.entry_size()
+    }
+
+    /// Serializes the map in `format` into `buf`, returning the number of bytes written
+    ///
+    /// Entries are sorted by address and empty ones are dropped. The boot protocol formats
+    /// merge adjacent regions of the same kind, and count memory which is
+    /// [usable after `ExitBootServices()`](MemoryDescriptor::usable_after_ebs), including
+    /// the loader's own allocations, as free; anything the kernel must keep should be
+    /// allocated under an [OS memory type](MemoryType::os) instead. Fails with
+    /// `BUFFER_TOO_SMALL` if the entries don't fit; [`packed_size()`](Self::packed_size)
+    /// bytes are always enough.
+    pub fn write_packed(&self, buf: &mut [u8], format: Format) -> Result<usize> {
+        let header_size = format.header_size();
+        let mut entries = buf
+            .get_mut(header_size..)
+            .ok_or(Status::BUFFER_TOO_SMALL)?
+            .chunks_exact_mut(format.entry_size());
+        let mut count = 0;
+        let mut pending: Option<MemoryDescriptor> = None;
+        for desc in self.sorted().filter(|desc| desc.num_pages != 0) {
+            if let Some(last) = &mut pending {
+                if format.merges(last, &desc) {
+                    last.num_pages += desc.num_pages;
+                    continue;
+                }
+            }
+            if let Some(last) = pending.replace(desc) {
+                let entry = entries.next().ok_or(Status::BUFFER_TOO_SMALL)?;
+                format.write_entry(entry, &last);
+                count += 1;
+            }
+        }
+        if let Some(last) = pending {
+            let entry = entries.next().ok_or(Status::BUFFER_TOO_SMALL)?;
+            format.write_entry(entry, &last);
+            count += 1;
+        }
+
+        let size = header_size + count * format.entry_size();
+        let header = &mut buf[..header_size];
+        match format {
+            Format::Packed => {
+                header[0..4].copy_from_slice(&Format::PACKED_MAGIC);
+                header[4..8].copy_from_slice(&Format::PACKED_VERSION.to_le_bytes());
+                header[8..12].copy_from_slice(&(count as u32).to_le_bytes());
+                header[12..16].copy_from_slice(&(format.entry_size() as u32).to_le_bytes());
+            }
+            Format::Limine | Format::E820 => {}
+            Format::Multiboot2 => {
+                header[0..4].copy_from_slice(&Format::MULTIBOOT2_TAG_MMAP.to_le_bytes());
+                header[4..8].copy_from_slice(&(size as u32).to_le_bytes());
+                header[8..12].copy_from_slice(&(format.entry_size() as u32).to_le_bytes());
+                header[12..16].copy_from_slice(&0u32.to_le_bytes());
+            }
+        }
+        Ok(size)
+    }
+
+    /// Returns the regions which differ between this map and `newer`
+    ///
+    /// Regions are compared by physical address, so a descriptor split by an allocation shows
+    /// up as only the allocated part having changed. Two regions are the same if their type
+    /// and attributes match; virtual addresses are ignored. Adjacent changes of the same kind
+    /// are merged, and changes are reported in address order.
+    pub fn diff<'b>(&self, newer: &MemoryMap<'b>) -> MemoryMapDiff<'a, 'b> {
+        MemoryMapDiff {
+            old:    *self,
+            new:    *newer,
+            cursor: Some(0),
+        }
+    }
+}
+
+impl BootServices {
+    /// Reads the memory map into `buffer`
+    ///
+    /// Fails with `BUFFER_TOO_SMALL` if it doesn't fit; see
+    /// [`get_memory_map_info()`](Self::get_memory_map_info) for the size needed.
+    pub fn memory_map<'a>(&self, buffer: &'a mut [u8]) -> Result<MemoryMap<'a>> {
+        let info = self.get_memory_map(buffer, 0)?;
+        Ok(MemoryMap::new(info, buffer))
+    }
+
+    /// Reads the final memory map into `buffer` and exits boot services
+    ///
+    /// `prepare` sees each map just before `ExitBootServices()` is attempted, to record it in
+    /// the kernel's boot information; it must not allocate or free memory. The attempt is
+    /// retried once if firmware changed the map in between, as the specification allows.
+    pub fn exit_with_memory_map<'a>(
+        &self,
+        buffer: &'a mut [u8],
+        mut prepare: impl FnMut(&MemoryMap) -> Result<()>,
+    ) -> Result<MemoryMap<'a>> {
+        let mut retried = false;
+        loop {
+            let info = self.get_memory_map(buffer, 0)?;
+            prepare(&MemoryMap::new(info, buffer))?;
+            match self.exit_boot_services(crate::image_handle(), info.map_key) {
+                Ok(()) => return Ok(MemoryMap::new(info, buffer)),
+                Err(Status::INVALID_PARAMETER) if !retried => retried = true,
+                Err(status) => return Err(status),
+            }
+        }
+    }
+}
+
+/// Encodings for [`MemoryMap::write_packed()`]
+///
+/// All fields are little-endian.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Format {
+    /// This crate's own format, which keeps every descriptor's type and attributes
+    ///
+    /// A 16 byte header holds the magic `EMAP`, the format version (1), the number of
+    /// entries and the size of an entry (32), each as a `u32`. Each entry holds the `u64`
+    /// start address and number of 4 KiB pages, the `u32` memory type, four reserved bytes
+    /// and the `u64` attributes.
+    Packed,
+    /// Limine memory map entries of `u64` base, length and type, without a header
+    ///
+    /// Limine hands the kernel an array of pointers to these, which the caller must build.
+    Limine,
+    /// A Multiboot2 memory map tag (type 6), including its header
+    ///
+    /// The size excludes the padding to the next 8 byte boundary that must follow the tag in
+    /// the boot information.
+    Multiboot2,
+    /// E820 entries of `u64` base and length and `u32` type, without a header, as found in
+    /// the Linux zero page
+    E820,
+}
+
+impl Format {
+    const PACKED_MAGIC: [u8; 4] = *b"EMAP";
+    const PACKED_VERSION: u32 = 1;
+    const MULTIBOOT2_TAG_MMAP: u32 = 6;
+
+    const fn header_size(self) -> usize {
+        match self {
+            Self::Packed | Self::Multiboot2 => 16,
+            Self::Limine | Self::E820 => 0,
+        }
+    }
+
+    const fn entry_size(self) -> usize {
+        match self {
+            Self::Packed => 32,
+            Self::Limine | Self::Multiboot2 => 24,
+            Self::E820 => 20,
+        }
+    }
+
+    /// Returns the type a region is reported as
+    fn kind(self, desc: &MemoryDescriptor) -> u64 {
+        match self {
+            Self::Packed => desc.kind.0.into(),
+            // The standard `LIMINE_MEMMAP_*` types. These have no type for runtime services
+            // memory, so unlike the conversion to `limine::MemoryKind`, which uses the
+            // `EfiRuntimeCode` and `EfiRuntimeData` extensions, it is reported as reserved.
+            Self::Limine => match desc.kind {
+                MemoryType::CONVENTIONAL_MEMORY => 0,
+                MemoryType::ACPI_RECLAIM => 2,
+                MemoryType::ACPI_NVS => 3,
+                MemoryType::UNACCEPTED => 4,
+                _ if desc.usable_after_ebs() => 5,
+                _ => 1,
+            },
+            // Multiboot2 reuses the E820 types.
+            Self::Multiboot2 | Self::E820 => match desc.kind {
+                _ if desc.usable_after_ebs() => 1,
+                MemoryType::ACPI_RECLAIM => 3,
+                MemoryType::ACPI_NVS => 4,
+                MemoryType::UNUSABLE => 5,
+                _ => 2,
+            },
+        }
+    }
+
+    /// Returns `true` if `next` directly follows `last` and can be reported as part of it
+    fn merges(self, last: &MemoryDescriptor, next: &MemoryDescriptor) -> bool {
+        self != Self::Packed && last.end_phys() == next.phys && self.kind(last) == self.kind(next)
+    }
+
+    fn write_entry(self, entry: &mut [u8], desc: &MemoryDescriptor) {
+        let kind = self.kind(desc);
+        entry[0..8].copy_from_slice(&desc.phys.to_le_bytes());
+        match self {
+            Self::Packed => {
+                entry[8..16].copy_from_slice(&desc.num_pages.to_le_bytes());
+                entry[16..20].copy_from_slice(&(kind as u32).to_le_bytes());
+                entry[20..24].fill(0);
+                entry[24..32].copy_from_slice(&desc.attribute.bits().to_le_bytes());
+            }
+            Self::Limine => {
+                entry[8..16].copy_from_slice(&desc.byte_len().to_le_bytes());
+                entry[16..24].copy_from_slice(&kind.to_le_bytes());
+            }
+            Self::Multiboot2 | Self::E820 => {
+                entry[8..16].copy_from_slice(&desc.byte_len().to_le_bytes());
+                entry[16..20].copy_from_slice(&(kind as u32).to_le_bytes());
+                entry[20..].fill(0);
+            }
+        }
+    }
+}
+
+/// A region which differs between two [`MemoryMap`]s
+///
+/// Descriptors are clipped to the region, which may be smaller than what either map
+/// describes.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum MapChange {
+    /// The region is only described by the newer map
+    Added(MemoryDescriptor),
+    /// The region is only described by the older map
+    Removed(MemoryDescriptor),
+    /// The region's type or attributes changed
+    Changed {
+        old: MemoryDescriptor,
+        new: MemoryDescriptor,
+    },
+}
+
+impl MapChange {
+    /// Returns the region's start address
+    pub fn phys(&self) -> PhysicalAddr {
+        match self {
+            Self::Added(desc) | Self::Removed(desc) | Self::Changed { new: desc, .. } => desc.phys,
+        }
+    }
+
+    /// Returns the region's size in pages
+    pub fn num_pages(&self) -> u64 {
+        match self {
+            Self::Added(desc) | Self::Removed(desc) | Self::Changed { new: desc, .. } => {
+                desc.num_pages
+            }
+        }
+    }
+
+    fn before(&self) -> Option<&MemoryDescriptor> {
+        match self {
+            Self::Removed(old) | Self::Changed { old, .. } => Some(old),
+            Self::Added(_) => None,
+        }
+    }
+
+    fn after(&self) -> Option<&MemoryDescriptor> {
+        match self {
+            Self::Added(new) | Self::Changed { new, .. } => Some(new),
+            Self::Removed(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for MapChange {
+    /// Formats the change as e.g.
+    /// `~ 0x7f000000-0x7f010000 CONVENTIONAL_MEMORY (0xf) -> BOOT_SERVICES_DATA (0xf)`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let start = self.phys();
+        let end = start.saturating_add(self.num_pages() * MemoryDescriptor::PAGE_SIZE);
+        let sign = match self {
+            Self::Added(_) => '+',
+            Self::Removed(_) => '-',
+            Self::Changed { .. } => '~',
+        };
+        write!(f, "{sign} {start:#x}-{end:#x}")?;
+        if let Some(old) = self.before() {
+            write!(f, " ")?;
+            write_region(f, old)?;
+        }
+        if matches!(self, Self::Changed { .. }) {
+            f.write_str(" ->")?;
+        }
+        if let Some(new) = self.after() {
+            write!(f, " ")?;
+            write_region(f, new)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes a descriptor's type and attributes, e.g. `BOOT_SERVICES_DATA (0xf)`
+fn write_region(f: &mut fmt::Formatter<'_>, desc: &MemoryDescriptor) -> fmt::Result {
+    match desc.kind.name() {
+        Some(name) => f.write_str(name)?,
+        None => write!(f, "{:#x}", desc.kind.0)?,
+    }
+    write!(f, " ({:#x})", desc.attribute.bits())
+}
+
+/// Iterator over the differences between two memory maps, returned by [`MemoryMap::diff()`]
+///
+/// Needs no allocation, at the cost of scanning both maps for every region visited.
+pub struct MemoryMapDiff<'a, 'b> {
+    old:    MemoryMap<'a>,
+    new:    MemoryMap<'b>,
+    /// Start of the next region to compare, or `None` once the end of memory is reached
+    cursor: Option<PhysicalAddr>,
+}
+
+impl MemoryMapDiff<'_, '_> {
+    /// Returns the region starting at the cursor over which neither map changes, along with
+    /// the descriptors covering it
+    fn next_region(
+        &mut self,
+    ) -> Option<(
+        PhysicalAddr,
+        PhysicalAddr,
+        Option<MemoryDescriptor>,
+        Option<MemoryDescriptor>,
+    )> {
+        let start = self.cursor?;
+        let (old, old_next) = covering(&self.old, start);
+        let (new, new_next) = covering(&self.new, start);
+        let Some(end) = [old_next, new_next].into_iter().flatten().min() else {
+            // Nothing left in either map.
+            self.cursor = None;
+            return None;
+        };
+        self.cursor = (end != PhysicalAddr::MAX).then_some(end);
+        Some((start, end, old, new))
+    }
+}
+
+impl Iterator for MemoryMapDiff<'_, '_> {
+    type Item = MapChange;
+
+    fn next(&mut self) -> Option<MapChange> {
+        let mut pending: Option<MapChange> = None;
+        loop {
+            let Some((start, end, old, new)) = self.next_region() else {
+                return pending;
+            };
+            let change = match (old, new) {
+                (None, None) => None,
+                (Some(old), Some(new)) if same(&old, &new) => None,
+                (Some(old), Some(new)) => Some(MapChange::Changed {
+                    old: clip(old, start, end),
+                    new: clip(new, start, end),
+                }),
+                (Some(old), None) => Some(MapChange::Removed(clip(old, start, end))),
+                (None, Some(new)) => Some(MapChange::Added(clip(new, start, end))),
+            };
+
+            match (&mut pending, change) {
+                (None, None) => {}
+                (None, Some(change)) => pending = Some(change),
+                (Some(pending), Some(change)) if extends(pending, &change) => {
+                    grow(pending, change.num_pages());
+                }
+                (Some(_), change) => {
+                    // Revisit this region next time, unless it is unchanged.
+                    if change.is_some() {
+                        self.cursor = Some(start);
+                    }
+                    return pending;
+                }
+            }
+        }
+    }
+}
+
+/// Returns the descriptor containing `addr`, and where the map next changes after `addr`
+///
+/// The latter is the end of the containing descriptor, or the start of the next one if
+/// `addr` lies in a hole.
+fn covering(
+    map: &MemoryMap,
+    addr: PhysicalAddr,
+) -> (Option<MemoryDescriptor>, Option<PhysicalAddr>) {
+    let descs = || map.iter().filter(|desc| desc.num_pages != 0);
+    let containing = descs().find(|desc| desc.contains(addr));
+    // Firmware shouldn't report overlapping descriptors, but stop at any that start within
+    // the containing one so no change is missed.
+    let next_start = descs()
+        .map(|desc| desc.phys)
+        .filter(|&phys| phys > addr)
+        .min();
+    let next = match &containing {
+        Some(desc) => Some(next_start.map_or(desc.end_phys(), |start| start.min(desc.end_phys()))),
+        None => next_start,
+    };
+    (containing, next)
+}
+
+fn same(old: &MemoryDescriptor, new: &MemoryDescriptor) -> bool {
+    old.kind == new.kind && old.attribute == new.attribute
+}
+
+/// Narrows a descriptor to the region `start..end`
+fn clip(mut desc: MemoryDescriptor, start: PhysicalAddr, end: PhysicalAddr) -> MemoryDescriptor {
+    if desc.virt != 0 {
+        desc.virt += start - desc.phys;
+    }
+    desc.phys = start;
+    desc.num_pages = (end - start) / MemoryDescriptor::PAGE_SIZE;
+    desc
+}
+
+/// Returns `true` if `change` directly follows `pending` and changes the same thing
+fn extends(pending: &MapChange, change: &MapChange) -> bool {
+    let key = |desc: Option<&MemoryDescriptor>| desc.map(|desc| (desc.kind, desc.attribute));
+    let contiguous = pending
+        .phys()
+        .checked_add(pending.num_pages() * MemoryDescriptor::PAGE_SIZE)
+        == Some(change.phys());
+    contiguous
+        && key(pending.before()) == key(change.before())
+        && key(pending.after()) == key(change.after())
+}
+
+fn grow(change: &mut MapChange, num_pages: u64) {
+    match change {
+        MapChange::Added(desc) | MapChange::Removed(desc) => desc.num_pages += num_pages,
+        MapChange::Changed { old, new } => {
+            old.num_pages += num_pages;
+            new.num_pages += num_pages;
+        }
+    }
+}
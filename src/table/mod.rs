@@ -28,22 +28,36 @@
  * SPDX-License-Identifier: BSD-3-Clause
  */
 
-use core::ffi::c_void;
+use core::{ffi::c_void, fmt, ptr};
 
 use super::{
     proto::{
         console::{text_input::SimpleTextInput, text_output::SimpleTextOutput},
         Proto,
     },
-    Handle,
+    stdio,
+    ucs2::CStr16,
+    Handle, Result,
 };
 
 pub mod boot;
 pub use boot::*;
 
+pub mod callback;
+pub use callback::*;
+
+pub mod capabilities;
+pub use capabilities::*;
+
 pub mod config;
 pub use config::*;
 
+pub mod memory_map;
+pub use memory_map::*;
+
+pub mod runtime;
+pub use runtime::*;
+
 #[repr(C)]
 #[derive(Debug)]
 pub struct TableHeader {
@@ -54,6 +68,34 @@ pub struct TableHeader {
     pub reserved:    u32,
 }
 
+impl TableHeader {
+    /// Checks the table's CRC32 against its `checksum` field
+    ///
+    /// The CRC is calculated in software, so this also works after boot services have been
+    /// exited. A `header_size` too small to cover the header itself is never valid.
+    ///
+    /// # Safety
+    ///
+    /// `header_size` bytes starting at the header must be readable.
+    pub unsafe fn checksum_valid(&self) -> bool {
+        let size = self.header_size as usize;
+        if size < core::mem::size_of::<TableHeader>() {
+            return false;
+        }
+        let bytes = core::slice::from_raw_parts((self as *const Self).cast::<u8>(), size);
+
+        // The checksum is calculated with the `checksum` field set to zero.
+        let checksum_offset = core::mem::offset_of!(TableHeader, checksum);
+        let mut hasher = crate::crc32::Hasher::new();
+        hasher.update(&bytes[..checksum_offset]);
+        hasher.update(&[0; 4]);
+        hasher.update(&bytes[checksum_offset + 4..]);
+        let crc = hasher.finish();
+
+        crc == self.checksum
+    }
+}
+
 #[repr(C)]
 #[derive(Debug)]
 pub struct SystemTable {
@@ -66,20 +108,114 @@ pub struct SystemTable {
     pub stdout:               Proto<SimpleTextOutput>,
     pub stderr_handle:        Handle,
     pub stderr:               Proto<SimpleTextOutput>,
-    pub runtime_services:     *mut (),
+    pub runtime_services:     *mut RuntimeServices,
     pub boot_services:        *mut BootServices,
     pub config_table_entries: usize,
     pub config_table:         *mut c_void,
 }
 
+/// UEFI specification revision, as found in table headers
+///
+/// The minor version holds two decimal digits, so 2.3.1 is `Revision::new(2, 31)` and 2.7 is
+/// `Revision::new(2, 70)`.
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Revision(pub u32);
+
+impl Revision {
+    pub const EFI_1_10: Self = Self::new(1, 10);
+    pub const UEFI_2_0: Self = Self::new(2, 0);
+    pub const UEFI_2_3_1: Self = Self::new(2, 31);
+    pub const UEFI_2_7: Self = Self::new(2, 70);
+    pub const UEFI_2_10: Self = Self::new(2, 100);
+
+    pub const fn new(major: u16, minor: u16) -> Self {
+        Self((major as u32) << 16 | minor as u32)
+    }
+
+    pub const fn major(self) -> u16 {
+        (self.0 >> 16) as u16
+    }
+
+    pub const fn minor(self) -> u16 {
+        self.0 as u16
+    }
+}
+
+impl fmt::Display for Revision {
+    /// Formats the revision the way the specification names it, e.g. `2.3.1` or `2.10`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (major, minor) = (self.major(), self.minor());
+        // EFI 1.x predates the two-digit minor encoding.
+        if major < 2 {
+            return write!(f, "{major}.{minor:02}");
+        }
+        write!(f, "{major}.{}", minor / 10)?;
+        match minor % 10 {
+            0 => Ok(()),
+            patch => write!(f, ".{patch}"),
+        }
+    }
+}
+
 impl SystemTable {
+    /// Returns the firmware vendor's name, e.g. `EDK II` or `American Megatrends`
+    pub fn firmware_vendor(&self) -> &CStr16 {
+        if self.firmware_vendor.is_null() {
+            return unsafe { CStr16::from_slice_with_nul_unchecked(&[0]) };
+        }
+        unsafe { CStr16::from_ptr(self.firmware_vendor) }
+    }
+
+    /// Returns the vendor-specific firmware revision
+    pub fn firmware_revision(&self) -> u32 {
+        self.firmware_revision
+    }
+
+    /// Returns the revision of the specification the firmware conforms to
+    pub fn uefi_revision(&self) -> Revision {
+        Revision(self.header.revision)
+    }
+
     pub fn boot_services(&self) -> &'static BootServices {
         unsafe { &*self.boot_services }
     }
 
+    pub fn runtime_services(&self) -> &'static RuntimeServices {
+        unsafe { &*self.runtime_services }
+    }
+
     pub fn config_table(&self) -> ConfigTable {
         unsafe { ConfigTable::new(self.config_table, self.config_table_entries) }
     }
+
+    /// Looks up the console output interfaces again
+    ///
+    /// Some firmware replaces the console devices when controllers are connected or the GOP
+    /// mode changes, leaving stale interface pointers behind. Afterwards
+    /// [`stdout()`](crate::stdout) and [`stderr()`](crate::stderr) use the interfaces now
+    /// installed on the `ConOut` and `StdErr` handles, falling back to `ConOut` if `StdErr`
+    /// has none. Input is always looked up through `ConIn`'s handle, so needs no refresh.
+    ///
+    /// Fails if the `ConOut` handle no longer has a Simple Text Output Protocol.
+    pub fn reconnect_console(&self) -> Result<()> {
+        let bs = self.boot_services();
+        // Firmware may have updated the handles since the table was last read.
+        let (stdout_handle, stderr_handle) = unsafe {
+            (
+                ptr::read_volatile(&self.stdout_handle),
+                ptr::read_volatile(&self.stderr_handle),
+            )
+        };
+        let stdout = bs
+            .protocol_for_handle::<SimpleTextOutput>(stdout_handle)?
+            .as_ptr();
+        let stderr = bs
+            .protocol_for_handle::<SimpleTextOutput>(stderr_handle)
+            .map_or(stdout, |stderr| stderr.as_ptr());
+        stdio::set_consoles(stdout, stderr);
+        Ok(())
+    }
 }
 
 //
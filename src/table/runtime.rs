@@ -0,0 +1,394 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Runtime Services
+
+use core::{ffi::c_void, fmt, ptr};
+
+use super::{MemoryDescriptor, TableHeader};
+use crate::{quirks, trace::traced, ucs2::CStr16, Guid, PhysicalAddr, Result, Status};
+
+/*
+ * Time Services
+ */
+
+pub type GetTimeFn =
+    extern "efiapi" fn(time: *mut Time, capabilities: *mut TimeCapabilities) -> Status;
+
+pub type SetTimeFn = extern "efiapi" fn(time: *const Time) -> Status;
+
+pub type GetWakeupTimeFn =
+    extern "efiapi" fn(enabled: *mut bool, pending: *mut bool, time: *mut Time) -> Status;
+
+pub type SetWakeupTimeFn = extern "efiapi" fn(enable: bool, time: *const Time) -> Status;
+
+/*
+ * Virtual Memory Services
+ */
+
+pub type SetVirtualAddressMapFn = extern "efiapi" fn(
+    memory_map_size: usize,
+    descriptor_size: usize,
+    descriptor_version: u32,
+    virtual_map: *mut MemoryDescriptor,
+) -> Status;
+
+pub type ConvertPointerFn =
+    extern "efiapi" fn(debug_disposition: usize, address: *mut *mut c_void) -> Status;
+
+/*
+ * Variable Services
+ */
+
+pub type GetVariableFn = extern "efiapi" fn(
+    variable_name: *const u16,
+    vendor_guid: *const Guid,
+    attributes: *mut u32,
+    data_size: *mut usize,
+    data: *mut c_void,
+) -> Status;
+
+pub type GetNextVariableNameFn = extern "efiapi" fn(
+    variable_name_size: *mut usize,
+    variable_name: *mut u16,
+    vendor_guid: *mut Guid,
+) -> Status;
+
+pub type SetVariableFn = extern "efiapi" fn(
+    variable_name: *const u16,
+    vendor_guid: *const Guid,
+    attributes: u32,
+    data_size: usize,
+    data: *const c_void,
+) -> Status;
+
+pub type QueryVariableInfoFn = extern "efiapi" fn(
+    attributes: u32,
+    maximum_variable_storage_size: *mut u64,
+    remaining_variable_storage_size: *mut u64,
+    maximum_variable_size: *mut u64,
+) -> Status;
+
+/*
+ * Misc. Runtime Services
+ */
+
+pub type GetNextHighMonotonicCountFn = extern "efiapi" fn(high_count: *mut u32) -> Status;
+
+pub type ResetSystemFn = extern "efiapi" fn(
+    reset_type: ResetType,
+    reset_status: Status,
+    data_size: usize,
+    reset_data: *const c_void,
+) -> !;
+
+pub type UpdateCapsuleFn = extern "efiapi" fn(
+    capsule_header_array: *const *const CapsuleHeader,
+    capsule_count: usize,
+    scatter_gather_list: PhysicalAddr,
+) -> Status;
+
+pub type QueryCapsuleCapabilitiesFn = extern "efiapi" fn(
+    capsule_header_array: *const *const CapsuleHeader,
+    capsule_count: usize,
+    maximum_capsule_size: *mut u64,
+    reset_type: *mut ResetType,
+) -> Status;
+
+bitflags::bitflags! {
+    #[repr(transparent)]
+    pub struct VariableAttributes : u32 {
+        const NON_VOLATILE                          = 0x00000001;
+        const BOOTSERVICE_ACCESS                    = 0x00000002;
+        const RUNTIME_ACCESS                        = 0x00000004;
+        const HARDWARE_ERROR_RECORD                 = 0x00000008;
+        const AUTHENTICATED_WRITE_ACCESS            = 0x00000010;
+        const TIME_BASED_AUTHENTICATED_WRITE_ACCESS = 0x00000020;
+        const APPEND_WRITE                          = 0x00000040;
+        const ENHANCED_AUTHENTICATED_ACCESS         = 0x00000080;
+    }
+}
+
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ResetType(pub u32);
+
+impl ResetType {
+    pub const COLD: Self = Self(0);
+    pub const WARM: Self = Self(1);
+    pub const SHUTDOWN: Self = Self(2);
+    pub const PLATFORM_SPECIFIC: Self = Self(3);
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct CapsuleHeader {
+    pub capsule_guid:       Guid,
+    pub header_size:        u32,
+    pub flags:              u32,
+    pub capsule_image_size: u32,
+}
+
+#[repr(C)]
+pub struct RuntimeServices {
+    pub header: TableHeader,
+
+    // Time Services
+    pub(crate) get_time:        GetTimeFn,
+    pub(crate) set_time:        SetTimeFn,
+    pub(crate) get_wakeup_time: GetWakeupTimeFn,
+    pub(crate) set_wakeup_time: SetWakeupTimeFn,
+
+    // Virtual Memory Services
+    pub(crate) set_virtual_address_map: SetVirtualAddressMapFn,
+    pub(crate) convert_pointer:         ConvertPointerFn,
+
+    // Variable Services
+    pub(crate) get_variable:           GetVariableFn,
+    pub(crate) get_next_variable_name: GetNextVariableNameFn,
+    pub(crate) set_variable:           SetVariableFn,
+
+    // Misc. Services
+    pub(crate) get_next_high_monotonic_count: GetNextHighMonotonicCountFn,
+    pub(crate) reset_system:                  ResetSystemFn,
+
+    // EFI 2.0+
+
+    // Capsule Services
+    pub(crate) update_capsule:             UpdateCapsuleFn,
+    pub(crate) query_capsule_capabilities: QueryCapsuleCapabilitiesFn,
+
+    // Misc. Services
+    pub(crate) query_variable_info: QueryVariableInfoFn,
+}
+
+/// The state of the wake-up alarm, as returned by [`RuntimeServices::get_wakeup_time()`]
+#[derive(Clone, Copy, Debug)]
+pub struct WakeupTime {
+    /// Whether the alarm is armed
+    pub enabled: bool,
+    /// Whether the alarm has fired but not been acknowledged
+    pub pending: bool,
+    pub time:    Time,
+}
+
+/// Variable Services
+impl RuntimeServices {
+    /// Reads a variable into `buf`, returning its size and attributes
+    ///
+    /// Fails with `NOT_FOUND` if the variable does not exist, or with `BUFFER_TOO_SMALL` if
+    /// `buf` can't hold it; see [`get_variable_size()`](Self::get_variable_size).
+    pub fn get_variable(
+        &self,
+        name: &CStr16,
+        vendor: &Guid,
+        buf: &mut [u8],
+    ) -> Result<(usize, VariableAttributes)> {
+        let mut attributes = 0;
+        let mut size = buf.len();
+        traced!(
+            "GetVariable", "{}, {}, {}", name, vendor, size;
+            (self.get_variable)(
+                name.as_ptr(),
+                vendor,
+                &mut attributes,
+                &mut size,
+                buf.as_mut_ptr().cast(),
+            )
+        )
+        .to_result((size, VariableAttributes::from_bits_truncate(attributes)))
+    }
+
+    /// Returns the size of a buffer which can hold a variable's data
+    ///
+    /// This is the variable's size, unless
+    /// [`UNTRUSTED_BUFFER_SIZES`](crate::quirks::Quirks::UNTRUSTED_BUFFER_SIZES) is enabled, in
+    /// which case it includes the same padding as the crate's own allocations.
+    pub fn get_variable_size(&self, name: &CStr16, vendor: &Guid) -> Result<usize> {
+        let mut size = 0;
+        let status = traced!(
+            "GetVariable", "{}, {}, 0", name, vendor;
+            (self.get_variable)(
+                name.as_ptr(),
+                vendor,
+                ptr::null_mut(),
+                &mut size,
+                ptr::null_mut(),
+            )
+        );
+        match status {
+            Status::SUCCESS => Ok(size),
+            Status::BUFFER_TOO_SMALL => Ok(quirks::buffer_size(size)),
+            status => Err(status),
+        }
+    }
+
+    /// Writes a variable, or deletes it if `data` is empty
+    ///
+    /// Deleting a variable which does not exist fails with `NOT_FOUND`.
+    pub fn set_variable(
+        &self,
+        name: &CStr16,
+        vendor: &Guid,
+        attributes: VariableAttributes,
+        data: &[u8],
+    ) -> Result<()> {
+        let status = traced!(
+            "SetVariable", "{}, {}, {:?}, {}", name, vendor, attributes, data.len();
+            (self.set_variable)(
+                name.as_ptr(),
+                vendor,
+                attributes.bits(),
+                data.len(),
+                data.as_ptr().cast(),
+            )
+        );
+        quirks::filter_status(status).to_result(())
+    }
+}
+
+/// Misc. Runtime Services
+impl RuntimeServices {
+    /// Resets the whole platform
+    ///
+    /// `data` is passed to the firmware along with `status`; for a non-successful status it
+    /// should start with a null-terminated UCS-2 description of the reason.
+    pub fn reset_system(&self, kind: ResetType, status: Status, data: &[u8]) -> ! {
+        // Not `traced!()`, as the call never returns.
+        #[cfg(feature = "trace")]
+        log::trace!(
+            target: "uefi::ffi",
+            "-> ResetSystem({:?}, {:?}, {})",
+            kind,
+            status,
+            data.len()
+        );
+        // An empty slice has a dangling pointer, which firmware may still dereference.
+        let data_ptr = match data {
+            [] => ptr::null(),
+            _ => data.as_ptr().cast(),
+        };
+        (self.reset_system)(kind, status, data.len(), data_ptr)
+    }
+}
+
+/// Time Services
+impl RuntimeServices {
+    /// Returns the current time and the capabilities of the real-time clock
+    pub fn get_time(&self) -> Result<(Time, TimeCapabilities)> {
+        let mut time = Time::default();
+        let mut capabilities = TimeCapabilities::default();
+        traced!("GetTime"; (self.get_time)(&mut time, &mut capabilities))
+            .to_result((time, capabilities))
+    }
+
+    pub fn set_time(&self, time: &Time) -> Result<()> {
+        traced!("SetTime", "{}", time; (self.set_time)(time)).to_result(())
+    }
+
+    /// Reads the wake-up alarm
+    ///
+    /// Fails with `UNSUPPORTED` on platforms without one.
+    pub fn get_wakeup_time(&self) -> Result<WakeupTime> {
+        let (mut enabled, mut pending) = (false, false);
+        let mut time = Time::default();
+        traced!("GetWakeupTime"; (self.get_wakeup_time)(&mut enabled, &mut pending, &mut time))
+            .to_result(WakeupTime {
+                enabled,
+                pending,
+                time,
+            })
+    }
+
+    /// Arms the wake-up alarm for `time`, or disables it if `time` is `None`
+    ///
+    /// Disabling the alarm also clears a pending wake-up. Fails with `UNSUPPORTED` on platforms
+    /// without an alarm.
+    pub fn set_wakeup_time(&self, time: Option<&Time>) -> Result<()> {
+        let time_ptr = time.map_or(ptr::null(), |time| time as *const Time);
+        traced!(
+            "SetWakeupTime", "{}, {:?}", time.is_some(), time;
+            (self.set_wakeup_time)(time.is_some(), time_ptr)
+        )
+        .to_result(())
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TimeCapabilities {
+    /// Resolution of the real-time clock in counts per second
+    pub resolution:   u32,
+    /// Accuracy of the real-time clock in units of 10^-6 ppm (50 ppm is 50,000,000)
+    pub accuracy:     u32,
+    /// Whether `set_time()` clears the time below the clock's resolution
+    pub sets_to_zero: bool,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Time {
+    pub year:       u16,
+    pub month:      u8,
+    pub day:        u8,
+    pub hour:       u8,
+    pub minute:     u8,
+    pub second:     u8,
+    pub pad1:       u8,
+    pub nanosecond: u32,
+    /// Offset from UTC in minutes, or [`Time::UNSPECIFIED_TIMEZONE`]
+    pub time_zone:  i16,
+    pub daylight:   Daylight,
+    pub pad2:       u8,
+}
+
+impl Time {
+    /// The time is local time, with no known relation to UTC
+    pub const UNSPECIFIED_TIMEZONE: i16 = 0x07ff;
+}
+
+impl fmt::Display for Time {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+bitflags::bitflags! {
+    #[repr(transparent)]
+    #[derive(Default)]
+    pub struct Daylight : u8 {
+        const ADJUST_DAYLIGHT = 0x01;
+        const IN_DAYLIGHT     = 0x02;
+    }
+}
@@ -0,0 +1,310 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Monotonic time measurement
+//!
+//! [`Instant`] reads the best counter available, chosen on first use:
+//!
+//! 1. the Timestamp Protocol, if the firmware provides it;
+//! 2. the architectural counter (TSC, `CNTVCT_EL0` or `time`), calibrated against `Stall()`
+//!    where its frequency isn't architecturally defined;
+//! 3. `GetNextMonotonicCount()`, which only orders instants; durations measured with it are
+//!    always zero.
+//!
+//! Only the architectural counter keeps working after boot services have been exited. With the
+//! other sources, [`Instant::now()`] then keeps returning the last instant taken.
+
+use core::{
+    fmt,
+    ops::{Add, AddAssign, Sub, SubAssign},
+    ptr,
+    sync::atomic::{AtomicPtr, AtomicU64, AtomicU8, Ordering},
+    time::Duration,
+};
+
+use crate::{
+    boot_services, boot_services_active,
+    proto::timestamp::{GetTimestampFn, Timestamp},
+};
+
+/// The counter backing [`Instant`]
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ClockSource {
+    Timestamp = 1,
+    Counter,
+    MonotonicCount,
+}
+
+static SOURCE: AtomicU8 = AtomicU8::new(0);
+static FREQUENCY: AtomicU64 = AtomicU64::new(0);
+static END_VALUE: AtomicU64 = AtomicU64::new(u64::MAX);
+static GET_TIMESTAMP: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+static LAST: AtomicU64 = AtomicU64::new(0);
+
+/// Time `Stall()` is given to calibrate the architectural counter
+const CALIBRATION_US: u64 = 10_000;
+
+/// Returns the clock source, selecting one if this is the first use
+pub fn source() -> ClockSource {
+    match SOURCE.load(Ordering::Acquire) {
+        1 => ClockSource::Timestamp,
+        2 => ClockSource::Counter,
+        3 => ClockSource::MonotonicCount,
+        _ => init(),
+    }
+}
+
+/// Returns the frequency of the clock source in ticks per second, or zero if unknown
+pub fn frequency() -> u64 {
+    source();
+    FREQUENCY.load(Ordering::Relaxed)
+}
+
+fn init() -> ClockSource {
+    let (source, frequency) = select();
+    FREQUENCY.store(frequency, Ordering::Relaxed);
+    SOURCE.store(source as u8, Ordering::Release);
+    source
+}
+
+fn select() -> (ClockSource, u64) {
+    if boot_services_active() {
+        if let Ok(timestamp) = boot_services().first_protocol::<Timestamp>() {
+            if let Ok(properties) = timestamp.get_properties() {
+                if properties.frequency != 0 {
+                    END_VALUE.store(properties.end_value, Ordering::Relaxed);
+                    GET_TIMESTAMP.store(timestamp.get_timestamp as *mut (), Ordering::Relaxed);
+                    return (ClockSource::Timestamp, properties.frequency);
+                }
+            }
+        }
+    }
+
+    if let Some(frequency) = counter::frequency().or_else(calibrate) {
+        return (ClockSource::Counter, frequency);
+    }
+
+    (ClockSource::MonotonicCount, 0)
+}
+
+/// Measures the architectural counter's frequency against `Stall()`
+fn calibrate() -> Option<u64> {
+    if !boot_services_active() {
+        return None;
+    }
+    let start = counter::read()?;
+    boot_services().stall(CALIBRATION_US as usize).ok()?;
+    let ticks = counter::read()?.wrapping_sub(start);
+    Some(ticks * (1_000_000 / CALIBRATION_US)).filter(|&frequency| frequency != 0)
+}
+
+fn read_ticks() -> u64 {
+    let ticks = match source() {
+        ClockSource::Counter => counter::read(),
+        _ if !boot_services_active() => None,
+        ClockSource::Timestamp => {
+            let get_timestamp = GET_TIMESTAMP.load(Ordering::Relaxed);
+            let get_timestamp: GetTimestampFn = unsafe { core::mem::transmute(get_timestamp) };
+            Some(get_timestamp())
+        }
+        ClockSource::MonotonicCount => boot_services().next_monotonic_count().ok(),
+    };
+    match ticks {
+        Some(ticks) => {
+            LAST.store(ticks, Ordering::Relaxed);
+            ticks
+        }
+        None => LAST.load(Ordering::Relaxed),
+    }
+}
+
+/// Returns the number of ticks from `earlier` to `later`, allowing for one rollover
+///
+/// The counter counts up to `END_VALUE` and then restarts from zero.
+fn ticks_between(earlier: u64, later: u64) -> u64 {
+    let end = END_VALUE.load(Ordering::Relaxed);
+    if later >= earlier {
+        later - earlier
+    } else {
+        end.wrapping_sub(earlier)
+            .wrapping_add(later)
+            .wrapping_add(1)
+    }
+}
+
+fn ticks_to_duration(ticks: u64) -> Duration {
+    match frequency() {
+        0 => Duration::ZERO,
+        frequency => {
+            let nanos = ticks as u128 * 1_000_000_000 / frequency as u128;
+            Duration::new(
+                (nanos / 1_000_000_000) as u64,
+                (nanos % 1_000_000_000) as u32,
+            )
+        }
+    }
+}
+
+fn duration_to_ticks(duration: Duration) -> u64 {
+    let ticks = duration.as_nanos() * frequency() as u128 / 1_000_000_000;
+    ticks.try_into().unwrap_or(u64::MAX)
+}
+
+/// A point in time, as read from the [clock source](source())
+#[derive(Clone, Copy, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Instant(u64);
+
+impl Instant {
+    pub fn now() -> Self {
+        Self(read_ticks())
+    }
+
+    /// Returns the raw counter value
+    pub fn ticks(&self) -> u64 {
+        self.0
+    }
+
+    /// Creates an instant from a raw counter value
+    pub const fn from_ticks(ticks: u64) -> Self {
+        Self(ticks)
+    }
+
+    /// Returns the time elapsed since `earlier`
+    ///
+    /// If `earlier` has the larger counter value, the counter is taken to have rolled over once
+    /// in between, so the instants must not be swapped.
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        ticks_to_duration(ticks_between(earlier.0, self.0))
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Self::now().duration_since(*self)
+    }
+
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        self.0.checked_add(duration_to_ticks(duration)).map(Self)
+    }
+
+    pub fn checked_sub(&self, duration: Duration) -> Option<Instant> {
+        self.0.checked_sub(duration_to_ticks(duration)).map(Self)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, duration: Duration) -> Instant {
+        Self(self.0.saturating_add(duration_to_ticks(duration)))
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, duration: Duration) {
+        *self = *self + duration;
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, duration: Duration) -> Instant {
+        Self(self.0.saturating_sub(duration_to_ticks(duration)))
+    }
+}
+
+impl SubAssign<Duration> for Instant {
+    fn sub_assign(&mut self, duration: Duration) {
+        *self = *self - duration;
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, earlier: Instant) -> Duration {
+        self.duration_since(earlier)
+    }
+}
+
+impl fmt::Debug for Instant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Instant({})", self.0)
+    }
+}
+
+/// Architectural counters
+mod counter {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    pub fn read() -> Option<u64> {
+        #[cfg(target_arch = "x86")]
+        use core::arch::x86::_rdtsc;
+        #[cfg(target_arch = "x86_64")]
+        use core::arch::x86_64::_rdtsc;
+        Some(unsafe { _rdtsc() })
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    pub fn read() -> Option<u64> {
+        let value: u64;
+        unsafe { core::arch::asm!("mrs {}, cntvct_el0", out(reg) value, options(nomem, nostack)) };
+        Some(value)
+    }
+
+    #[cfg(target_arch = "riscv64")]
+    pub fn read() -> Option<u64> {
+        let value: u64;
+        unsafe { core::arch::asm!("rdtime {}", out(reg) value, options(nomem, nostack)) };
+        Some(value)
+    }
+
+    #[cfg(not(any(
+        target_arch = "x86",
+        target_arch = "x86_64",
+        target_arch = "aarch64",
+        target_arch = "riscv64"
+    )))]
+    pub fn read() -> Option<u64> {
+        None
+    }
+
+    /// Returns the counter frequency if the architecture reports it
+    #[cfg(target_arch = "aarch64")]
+    pub fn frequency() -> Option<u64> {
+        let value: u64;
+        unsafe { core::arch::asm!("mrs {}, cntfrq_el0", out(reg) value, options(nomem, nostack)) };
+        Some(value).filter(|&frequency| frequency != 0)
+    }
+
+    #[cfg(not(target_arch = "aarch64"))]
+    pub fn frequency() -> Option<u64> {
+        None
+    }
+}
@@ -0,0 +1,52 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Tracing of firmware calls
+//!
+//! With the `trace` feature enabled, every boot service invoked through this crate is logged
+//! through the [`log`](https://docs.rs/log) facade, at trace level and with the `uefi::ffi`
+//! target, both before the call (with its interesting arguments) and after it returns (with
+//! the result). Without the feature the macro expands to the bare call.
+
+/// Calls a firmware function, tracing the call with the `trace` feature
+///
+/// ```ignore
+/// let status = traced!("AllocatePool", "{:?}, {}", pool_type, size;
+///     (self.allocate_pool)(pool_type, size, &mut buffer));
+/// ```
+pub(crate) macro traced($name:literal $(, $fmt:literal $(, $arg:expr)*)?; $call:expr) {{
+    #[cfg(feature = "trace")]
+    log::trace!(target: "uefi::ffi", concat!("-> ", $name, "(", $($fmt,)? ")") $($(, $arg)*)?);
+    #[allow(clippy::let_unit_value)]
+    let ret = $call;
+    #[cfg(feature = "trace")]
+    log::trace!(target: "uefi::ffi", concat!("<- ", $name, " = {:?}"), ret);
+    ret
+}}
@@ -0,0 +1,536 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Text-mode widgets for boot menus
+//!
+//! Each widget draws itself at a fixed position on a [`SimpleTextOutput`] and is driven by
+//! [`Key`]s, which can be read from either text input protocol through [`KeyInput`]. The
+//! widgets' `run()` methods implement the usual blocking loop; bootloaders which need to mix
+//! in their own events can call `draw()` and `handle_key()` directly instead.
+
+use core::fmt::{self, Write};
+
+use crate::{
+    boot_services,
+    proto::console::{
+        text_input::{InputKey, SimpleTextInput},
+        text_input_ex::{KeyData, SimpleTextInputEx},
+        text_output::{ConsoleWriter, SimpleTextOutput},
+    },
+    table::TimerDelay,
+    Event, Result, Status,
+};
+
+/// Light gray on black
+pub const NORMAL: usize = 0x07;
+/// Black on light gray
+pub const HIGHLIGHT: usize = 0x70;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Key {
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Delete,
+    Escape,
+    Enter,
+    Backspace,
+    Tab,
+    Char(char),
+    Other(InputKey),
+}
+
+impl From<InputKey> for Key {
+    fn from(key: InputKey) -> Self {
+        match (key.scancode, key.codepoint) {
+            (InputKey::SCAN_UP, _) => Key::Up,
+            (InputKey::SCAN_DOWN, _) => Key::Down,
+            (InputKey::SCAN_LEFT, _) => Key::Left,
+            (InputKey::SCAN_RIGHT, _) => Key::Right,
+            (InputKey::SCAN_HOME, _) => Key::Home,
+            (InputKey::SCAN_END, _) => Key::End,
+            (InputKey::SCAN_PAGE_UP, _) => Key::PageUp,
+            (InputKey::SCAN_PAGE_DOWN, _) => Key::PageDown,
+            (InputKey::SCAN_DELETE, _) => Key::Delete,
+            (InputKey::SCAN_ESC, _) => Key::Escape,
+            (InputKey::SCAN_NULL, 0x0d | 0x0a) => Key::Enter,
+            (InputKey::SCAN_NULL, 0x08) => Key::Backspace,
+            (InputKey::SCAN_NULL, 0x09) => Key::Tab,
+            (InputKey::SCAN_NULL, _) => match key.char() {
+                Some(c) if !c.is_control() => Key::Char(c),
+                _ => Key::Other(key),
+            },
+            _ => Key::Other(key),
+        }
+    }
+}
+
+impl From<KeyData> for Key {
+    fn from(key: KeyData) -> Self {
+        key.key.into()
+    }
+}
+
+/// Source of keystrokes
+pub trait KeyInput {
+    /// Returns the event signaled when a keystroke is available
+    fn key_event(&self) -> Event;
+
+    /// Reads the next keystroke, if one is available
+    fn poll_key(&mut self) -> Result<Option<Key>>;
+}
+
+impl KeyInput for SimpleTextInput {
+    fn key_event(&self) -> Event {
+        self.wait_for_key()
+    }
+
+    fn poll_key(&mut self) -> Result<Option<Key>> {
+        match self.read_keystroke() {
+            Ok(key) => Ok(Some(key.into())),
+            Err(Status::NOT_READY) => Ok(None),
+            Err(status) => Err(status),
+        }
+    }
+}
+
+impl KeyInput for SimpleTextInputEx {
+    fn key_event(&self) -> Event {
+        self.wait_for_key_ex()
+    }
+
+    fn poll_key(&mut self) -> Result<Option<Key>> {
+        match self.read_keystroke_ex() {
+            Ok(key) => Ok(Some(key.into())),
+            Err(Status::NOT_READY) => Ok(None),
+            Err(status) => Err(status),
+        }
+    }
+}
+
+/// Blocks until a key is pressed
+pub fn wait_key(input: &mut impl KeyInput) -> Result<Key> {
+    loop {
+        boot_services().wait_for_event(&[input.key_event()])?;
+        if let Some(key) = input.poll_key()? {
+            return Ok(key);
+        }
+    }
+}
+
+fn put(out: &mut SimpleTextOutput, row: usize, col: usize, args: fmt::Arguments) -> Result<()> {
+    out.set_cursor_position(row, col)?;
+    ConsoleWriter::new(out)
+        .write_fmt(args)
+        .map_err(|_| Status::DEVICE_ERROR)
+}
+
+/// Periodic one-second timer, closed on drop
+struct Ticker(Event);
+
+impl Ticker {
+    fn new() -> Result<Self> {
+        let bs = boot_services();
+        let event = bs.create_timer_event()?;
+        let ticker = Self(event);
+        bs.set_timer(event, TimerDelay::Periodic, 10_000_000)?;
+        Ok(ticker)
+    }
+}
+
+impl Drop for Ticker {
+    fn drop(&mut self) {
+        let _ = boot_services().close_event(self.0);
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MenuAction {
+    /// Nothing was chosen; redraw the menu
+    None,
+    Chosen(usize),
+    Cancelled,
+}
+
+/// Scrolling list of entries, navigated with the arrow keys
+pub struct Menu<'a> {
+    items:    &'a [&'a str],
+    selected: usize,
+    scroll:   usize,
+    row:      usize,
+    col:      usize,
+    width:    usize,
+    height:   usize,
+}
+
+impl<'a> Menu<'a> {
+    /// Creates a menu occupying `height` rows of `width` columns from `(row, col)`
+    pub fn new(items: &'a [&'a str], row: usize, col: usize, width: usize, height: usize) -> Self {
+        Self {
+            items,
+            selected: 0,
+            scroll: 0,
+            row,
+            col,
+            width,
+            height: height.max(1),
+        }
+    }
+
+    pub fn with_selected(mut self, index: usize) -> Self {
+        self.select(index);
+        self
+    }
+
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    pub fn select(&mut self, index: usize) {
+        self.selected = index.min(self.items.len().saturating_sub(1));
+        if self.selected < self.scroll {
+            self.scroll = self.selected;
+        } else if self.selected >= self.scroll + self.height {
+            self.scroll = self.selected + 1 - self.height;
+        }
+    }
+
+    pub fn draw(&self, out: &mut SimpleTextOutput) -> Result<()> {
+        let width = self.width;
+        for i in 0..self.height {
+            let index = self.scroll + i;
+            let item = self.items.get(index).copied().unwrap_or("");
+            let attribute = if index == self.selected && index < self.items.len() {
+                HIGHLIGHT
+            } else {
+                NORMAL
+            };
+            out.set_attribute(attribute)?;
+            put(
+                out,
+                self.row + i,
+                self.col,
+                format_args!("{item:<width$.width$}"),
+            )?;
+        }
+        out.set_attribute(NORMAL)
+    }
+
+    pub fn handle_key(&mut self, key: Key) -> MenuAction {
+        let last = self.items.len().saturating_sub(1);
+        match key {
+            Key::Up => self.select(self.selected.saturating_sub(1)),
+            Key::Down => self.select(self.selected + 1),
+            Key::PageUp => self.select(self.selected.saturating_sub(self.height)),
+            Key::PageDown => self.select(self.selected + self.height),
+            Key::Home => self.select(0),
+            Key::End => self.select(last),
+            Key::Enter if !self.items.is_empty() => return MenuAction::Chosen(self.selected),
+            Key::Escape => return MenuAction::Cancelled,
+            _ => {}
+        }
+        MenuAction::None
+    }
+
+    /// Runs the menu until an entry is chosen or it is cancelled
+    ///
+    /// If a `countdown` is given, the selected entry is chosen automatically when it expires;
+    /// any keystroke cancels it. Returns `None` if the menu was cancelled with escape.
+    pub fn run(
+        &mut self,
+        out: &mut SimpleTextOutput,
+        input: &mut impl KeyInput,
+        mut countdown: Option<&mut Countdown>,
+    ) -> Result<Option<usize>> {
+        let mut ticker = match countdown {
+            Some(ref mut countdown) if countdown.remaining() > 0 => {
+                countdown.draw(out)?;
+                Some(Ticker::new()?)
+            }
+            _ => None,
+        };
+
+        out.enable_cursor(false).ok();
+        self.draw(out)?;
+
+        loop {
+            let index = match &ticker {
+                Some(ticker) => boot_services().wait_for_event(&[input.key_event(), ticker.0])?,
+                None => boot_services().wait_for_event(&[input.key_event()])?,
+            };
+
+            if index == 1 {
+                if let Some(countdown) = countdown.as_deref_mut() {
+                    if countdown.tick() {
+                        return Ok(Some(self.selected));
+                    }
+                    countdown.draw(out)?;
+                }
+                continue;
+            }
+
+            let Some(key) = input.poll_key()? else {
+                continue;
+            };
+            if ticker.take().is_some() {
+                if let Some(countdown) = countdown.as_deref_mut() {
+                    countdown.cancel(out)?;
+                }
+            }
+            match self.handle_key(key) {
+                MenuAction::None => self.draw(out)?,
+                MenuAction::Chosen(index) => return Ok(Some(index)),
+                MenuAction::Cancelled => return Ok(None),
+            }
+        }
+    }
+}
+
+/// Line counting down the seconds until a default action is taken
+pub struct Countdown<'a> {
+    label:     &'a str,
+    remaining: u32,
+    row:       usize,
+    col:       usize,
+    width:     usize,
+}
+
+impl<'a> Countdown<'a> {
+    /// Creates a countdown drawn as `"{label} {remaining}"` on `width` columns from `(row, col)`
+    pub fn new(label: &'a str, seconds: u32, row: usize, col: usize, width: usize) -> Self {
+        Self {
+            label,
+            remaining: seconds,
+            row,
+            col,
+            width,
+        }
+    }
+
+    pub fn remaining(&self) -> u32 {
+        self.remaining
+    }
+
+    /// Counts down one second, returning `true` once the countdown has expired
+    pub fn tick(&mut self) -> bool {
+        self.remaining = self.remaining.saturating_sub(1);
+        self.remaining == 0
+    }
+
+    pub fn draw(&self, out: &mut SimpleTextOutput) -> Result<()> {
+        let mut line = Line::<256>::new();
+        let _ = write!(line, "{} {}", self.label, self.remaining);
+        let (text, width) = (line.as_str(), self.width);
+        put(
+            out,
+            self.row,
+            self.col,
+            format_args!("{text:<width$.width$}"),
+        )
+    }
+
+    /// Stops the countdown and erases its line
+    pub fn cancel(&mut self, out: &mut SimpleTextOutput) -> Result<()> {
+        self.remaining = 0;
+        let width = self.width;
+        put(out, self.row, self.col, format_args!("{:width$}", ""))
+    }
+}
+
+/// Fixed-capacity buffer used to pad formatted text as a whole
+struct Line<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> Line<N> {
+    fn new() -> Self {
+        Self {
+            buf: [0; N],
+            len: 0,
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        // Only whole characters are ever appended.
+        unsafe { core::str::from_utf8_unchecked(&self.buf[..self.len]) }
+    }
+}
+
+impl<const N: usize> Write for Line<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            let len = c.len_utf8();
+            if self.len + len > N {
+                return Err(fmt::Error);
+            }
+            c.encode_utf8(&mut self.buf[self.len..]);
+            self.len += len;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FieldAction {
+    /// The text or cursor may have changed; redraw the field
+    None,
+    Submitted,
+    Cancelled,
+}
+
+/// Single-line text input holding up to `N` bytes of UTF-8
+pub struct TextField<const N: usize> {
+    buf:    [u8; N],
+    len:    usize,
+    cursor: usize,
+    row:    usize,
+    col:    usize,
+    width:  usize,
+}
+
+impl<const N: usize> TextField<N> {
+    /// Creates an empty field displayed on `width` columns from `(row, col)`
+    pub fn new(row: usize, col: usize, width: usize) -> Self {
+        Self {
+            buf: [0; N],
+            len: 0,
+            cursor: 0,
+            row,
+            col,
+            width: width.max(1),
+        }
+    }
+
+    /// Replaces the field's contents, truncating them to fit
+    pub fn with_text(mut self, text: &str) -> Self {
+        self.clear();
+        for c in text.chars() {
+            if !self.insert(c) {
+                break;
+            }
+        }
+        self
+    }
+
+    pub fn text(&self) -> &str {
+        // Only whole characters are ever inserted or removed.
+        unsafe { core::str::from_utf8_unchecked(&self.buf[..self.len]) }
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+        self.cursor = 0;
+    }
+
+    /// Inserts a character at the cursor, returning `false` if the field is full
+    pub fn insert(&mut self, c: char) -> bool {
+        let len = c.len_utf8();
+        if self.len + len > N {
+            return false;
+        }
+        self.buf
+            .copy_within(self.cursor..self.len, self.cursor + len);
+        c.encode_utf8(&mut self.buf[self.cursor..]);
+        self.len += len;
+        self.cursor += len;
+        true
+    }
+
+    fn prev_boundary(&self) -> usize {
+        self.text()[..self.cursor]
+            .char_indices()
+            .next_back()
+            .map_or(0, |(i, _)| i)
+    }
+
+    fn next_boundary(&self) -> usize {
+        self.text()[self.cursor..]
+            .chars()
+            .next()
+            .map_or(self.cursor, |c| self.cursor + c.len_utf8())
+    }
+
+    fn remove(&mut self, start: usize, end: usize) {
+        self.buf.copy_within(end..self.len, start);
+        self.len -= end - start;
+        self.cursor = start;
+    }
+
+    pub fn draw(&self, out: &mut SimpleTextOutput) -> Result<()> {
+        // Scroll so the cursor stays on the last column at most.
+        let before = self.text()[..self.cursor].chars().count();
+        let skip = (before + 1).saturating_sub(self.width);
+        let mut line = Line::<N>::new();
+        for c in self.text().chars().skip(skip).take(self.width) {
+            let _ = line.write_char(c);
+        }
+        let (text, width) = (line.as_str(), self.width);
+        put(out, self.row, self.col, format_args!("{text:<width$}"))?;
+        out.set_cursor_position(self.row, self.col + before - skip)?;
+        out.enable_cursor(true).ok();
+        Ok(())
+    }
+
+    pub fn handle_key(&mut self, key: Key) -> FieldAction {
+        match key {
+            Key::Char(c) => {
+                self.insert(c);
+            }
+            Key::Backspace if self.cursor > 0 => self.remove(self.prev_boundary(), self.cursor),
+            Key::Delete if self.cursor < self.len => self.remove(self.cursor, self.next_boundary()),
+            Key::Left => self.cursor = self.prev_boundary(),
+            Key::Right => self.cursor = self.next_boundary(),
+            Key::Home => self.cursor = 0,
+            Key::End => self.cursor = self.len,
+            Key::Enter => return FieldAction::Submitted,
+            Key::Escape => return FieldAction::Cancelled,
+            _ => {}
+        }
+        FieldAction::None
+    }
+
+    /// Edits the field until enter or escape is pressed, returning `true` for enter
+    pub fn run(&mut self, out: &mut SimpleTextOutput, input: &mut impl KeyInput) -> Result<bool> {
+        loop {
+            self.draw(out)?;
+            match self.handle_key(wait_key(input)?) {
+                FieldAction::None => {}
+                FieldAction::Submitted => return Ok(true),
+                FieldAction::Cancelled => return Ok(false),
+            }
+        }
+    }
+}
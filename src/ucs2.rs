@@ -0,0 +1,427 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! UCS-2 string conversion
+//!
+//! UEFI strings (`CHAR16 *`) are NUL-terminated arrays of UCS-2 code units, i.e. characters of
+//! the Basic Multilingual Plane stored one per `u16`. Surrogates are not valid UCS-2, so
+//! characters outside the BMP cannot be represented at all.
+
+#[cfg(feature = "alloc")]
+use alloc::{string::String, vec::Vec};
+use core::fmt;
+#[cfg(feature = "alloc")]
+use core::ops::Deref;
+
+/// `U+FFFD REPLACEMENT CHARACTER`, substituted by the lossy conversions
+pub const REPLACEMENT: u16 = 0xfffd;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Error {
+    /// The output buffer cannot hold the string and its NUL terminator
+    BufferTooSmall,
+    /// The character lies outside the Basic Multilingual Plane
+    Unrepresentable(char),
+    /// A surrogate code unit was found at the given index
+    Surrogate(usize),
+    /// The string is not NUL-terminated
+    MissingNul,
+    /// A NUL was found before the end of the string, at the given index
+    InteriorNul(usize),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::BufferTooSmall => f.write_str("buffer too small"),
+            Error::Unrepresentable(c) => write!(f, "{c:?} cannot be represented in UCS-2"),
+            Error::Surrogate(index) => write!(f, "surrogate code unit at index {index}"),
+            Error::MissingNul => f.write_str("string is not NUL-terminated"),
+            Error::InteriorNul(index) => write!(f, "interior NUL at index {index}"),
+        }
+    }
+}
+
+pub const fn is_surrogate(unit: u16) -> bool {
+    matches!(unit, 0xd800..=0xdfff)
+}
+
+/// Encodes a single character, if it is representable
+pub const fn encode_char(c: char) -> Option<u16> {
+    if (c as u32) < 0x10000 {
+        Some(c as u16)
+    } else {
+        None
+    }
+}
+
+/// Returns the number of code units needed to encode `s`, not counting the NUL terminator
+pub fn encoded_len(s: &str) -> Result<usize, Error> {
+    s.chars().try_fold(0, |len, c| match encode_char(c) {
+        Some(_) => Ok(len + 1),
+        None => Err(Error::Unrepresentable(c)),
+    })
+}
+
+/// Returns the code units of `s`, replacing unrepresentable characters with [`REPLACEMENT`]
+pub fn encode_lossy(s: &str) -> impl Iterator<Item = u16> + '_ {
+    s.chars().map(|c| encode_char(c).unwrap_or(REPLACEMENT))
+}
+
+/// Encodes `s` into `buf`, followed by a NUL terminator
+///
+/// Returns the number of code units written, not counting the terminator. `buf` is left in
+/// an unspecified state on error.
+pub fn encode_str_into(s: &str, buf: &mut [u16]) -> Result<usize, Error> {
+    let mut len = 0;
+    for c in s.chars() {
+        let unit = encode_char(c).ok_or(Error::Unrepresentable(c))?;
+        *buf.get_mut(len).ok_or(Error::BufferTooSmall)? = unit;
+        len += 1;
+    }
+    *buf.get_mut(len).ok_or(Error::BufferTooSmall)? = 0;
+    Ok(len)
+}
+
+/// Like [`encode_str_into()`], but replaces unrepresentable characters
+pub fn encode_str_lossy_into(s: &str, buf: &mut [u16]) -> Result<usize, Error> {
+    let mut len = 0;
+    for unit in encode_lossy(s) {
+        *buf.get_mut(len).ok_or(Error::BufferTooSmall)? = unit;
+        len += 1;
+    }
+    *buf.get_mut(len).ok_or(Error::BufferTooSmall)? = 0;
+    Ok(len)
+}
+
+/// Encodes `s` into a new NUL-terminated buffer
+#[cfg(feature = "alloc")]
+pub fn encode(s: &str) -> Result<Vec<u16>, Error> {
+    let mut buf = alloc::vec![0; encoded_len(s)? + 1];
+    encode_str_into(s, &mut buf)?;
+    Ok(buf)
+}
+
+/// Returns the length of a string, up to (but not including) the first NUL
+///
+/// If there is no NUL the entire slice is considered part of the string.
+pub fn len(units: &[u16]) -> usize {
+    units.iter().position(|&u| u == 0).unwrap_or(units.len())
+}
+
+/// Returns the length of the NUL-terminated string at `ptr`
+///
+/// # Safety
+///
+/// `ptr` must point to a readable, NUL-terminated array of code units.
+pub unsafe fn strlen(ptr: *const u16) -> usize {
+    let mut len = 0;
+    while *ptr.add(len) != 0 {
+        len += 1;
+    }
+    len
+}
+
+/// Checks that a string contains no surrogates, returning its [length](len())
+pub fn validate(units: &[u16]) -> Result<usize, Error> {
+    let len = len(units);
+    match units[..len].iter().position(|&u| is_surrogate(u)) {
+        Some(index) => Err(Error::Surrogate(index)),
+        None => Ok(len),
+    }
+}
+
+/// Decodes a string, stopping at the first NUL
+pub fn decode(units: &[u16]) -> impl Iterator<Item = Result<char, Error>> + '_ {
+    units[..len(units)]
+        .iter()
+        .enumerate()
+        .map(|(index, &unit)| char::from_u32(unit as u32).ok_or(Error::Surrogate(index)))
+}
+
+/// Decodes a string, stopping at the first NUL and replacing surrogates
+pub fn decode_lossy(units: &[u16]) -> impl Iterator<Item = char> + '_ {
+    decode(units).map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+}
+
+/// Decodes a string into a new `String`, replacing surrogates
+#[cfg(feature = "alloc")]
+pub fn decode_to_string_lossy(units: &[u16]) -> String {
+    decode_lossy(units).collect()
+}
+
+/// Formats a string with [`decode_lossy()`]
+#[derive(Clone, Copy)]
+pub struct DisplayLossy<'a>(pub &'a [u16]);
+
+impl fmt::Display for DisplayLossy<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use fmt::Write;
+
+        for c in decode_lossy(self.0) {
+            f.write_char(c)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for DisplayLossy<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use fmt::Write;
+
+        f.write_char('"')?;
+        for c in decode_lossy(self.0) {
+            for c in c.escape_debug() {
+                f.write_char(c)?;
+            }
+        }
+        f.write_char('"')
+    }
+}
+
+/// Borrowed NUL-terminated UCS-2 string
+///
+/// The terminator is included in the slice, and no other code unit is NUL. The contents are
+/// not otherwise validated, since firmware is free to hand back surrogates.
+#[repr(transparent)]
+#[derive(Eq, PartialEq)]
+pub struct CStr16([u16]);
+
+impl CStr16 {
+    /// Wraps a slice which ends with the string's only NUL
+    pub fn from_slice_with_nul(units: &[u16]) -> Result<&Self, Error> {
+        match units.iter().position(|&u| u == 0) {
+            Some(index) if index + 1 == units.len() => {
+                Ok(unsafe { Self::from_slice_with_nul_unchecked(units) })
+            }
+            Some(index) => Err(Error::InteriorNul(index)),
+            None => Err(Error::MissingNul),
+        }
+    }
+
+    /// Wraps the start of a slice, up to and including the first NUL
+    pub fn from_slice_until_nul(units: &[u16]) -> Result<&Self, Error> {
+        match units.iter().position(|&u| u == 0) {
+            Some(index) => Ok(unsafe { Self::from_slice_with_nul_unchecked(&units[..=index]) }),
+            None => Err(Error::MissingNul),
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `units` must end with a NUL, and contain no other.
+    pub const unsafe fn from_slice_with_nul_unchecked(units: &[u16]) -> &Self {
+        &*(units as *const [u16] as *const Self)
+    }
+
+    /// # Safety
+    ///
+    /// `ptr` must point to a NUL-terminated string which is valid for, and not modified
+    /// during, the lifetime `'a`.
+    pub unsafe fn from_ptr<'a>(ptr: *const u16) -> &'a Self {
+        let len = strlen(ptr);
+        Self::from_slice_with_nul_unchecked(core::slice::from_raw_parts(ptr, len + 1))
+    }
+
+    pub const fn as_ptr(&self) -> *const u16 {
+        self.0.as_ptr()
+    }
+
+    /// Returns the string's code units, without the terminator
+    pub fn as_slice(&self) -> &[u16] {
+        &self.0[..self.0.len() - 1]
+    }
+
+    pub const fn as_slice_with_nul(&self) -> &[u16] {
+        &self.0
+    }
+
+    /// Returns the number of code units, not counting the terminator
+    pub const fn len(&self) -> usize {
+        self.0.len() - 1
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Decodes the string, replacing surrogates
+    pub fn chars(&self) -> impl Iterator<Item = char> + '_ {
+        decode_lossy(self.as_slice())
+    }
+
+    pub fn display(&self) -> DisplayLossy<'_> {
+        DisplayLossy(self.as_slice())
+    }
+}
+
+impl fmt::Debug for CStr16 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.display(), f)
+    }
+}
+
+impl fmt::Display for CStr16 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.display(), f)
+    }
+}
+
+impl AsRef<[u16]> for CStr16 {
+    fn as_ref(&self) -> &[u16] {
+        self.as_slice()
+    }
+}
+
+/// Owned NUL-terminated UCS-2 string
+#[cfg(feature = "alloc")]
+#[derive(Clone, Eq, PartialEq)]
+pub struct CString16(Vec<u16>);
+
+#[cfg(feature = "alloc")]
+impl CString16 {
+    /// Copies `units` up to the first NUL, or all of them if there is none
+    pub fn from_units_until_nul(units: &[u16]) -> Self {
+        let mut buf = units[..len(units)].to_vec();
+        buf.push(0);
+        Self(buf)
+    }
+
+    pub fn as_c_str(&self) -> &CStr16 {
+        unsafe { CStr16::from_slice_with_nul_unchecked(&self.0) }
+    }
+
+    /// Returns the string's code units, including the terminator
+    pub fn into_vec_with_nul(self) -> Vec<u16> {
+        self.0
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl TryFrom<&str> for CString16 {
+    type Error = Error;
+
+    fn try_from(s: &str) -> Result<Self, Error> {
+        let units = encode(s)?;
+        match units.iter().position(|&u| u == 0) {
+            Some(index) if index + 1 < units.len() => Err(Error::InteriorNul(index)),
+            _ => Ok(Self(units)),
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl From<&CStr16> for CString16 {
+    fn from(s: &CStr16) -> Self {
+        Self(s.as_slice_with_nul().to_vec())
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl Deref for CString16 {
+    type Target = CStr16;
+
+    fn deref(&self) -> &CStr16 {
+        self.as_c_str()
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl fmt::Debug for CString16 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_c_str(), f)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl fmt::Display for CString16 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self.as_c_str(), f)
+    }
+}
+
+/// Returns the number of code units needed to encode `s` at compile time
+#[doc(hidden)]
+pub const fn const_encoded_len(s: &str) -> usize {
+    let bytes = s.as_bytes();
+    let mut len = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        // Count every byte which is not a UTF-8 continuation byte.
+        if bytes[i] & 0xc0 != 0x80 {
+            len += 1;
+        }
+        i += 1;
+    }
+    len
+}
+
+/// Encodes `s` at compile time, panicking on characters which cannot be encoded
+#[doc(hidden)]
+pub const fn const_encode<const N: usize>(s: &str) -> [u16; N] {
+    let bytes = s.as_bytes();
+    let mut buf = [0u16; N];
+    let mut len = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i] as u16;
+        let unit = if b < 0x80 {
+            i += 1;
+            b
+        } else if b & 0xe0 == 0xc0 {
+            i += 2;
+            (b & 0x1f) << 6 | (bytes[i - 1] as u16 & 0x3f)
+        } else if b & 0xf0 == 0xe0 {
+            i += 3;
+            (b & 0x0f) << 12 | (bytes[i - 2] as u16 & 0x3f) << 6 | (bytes[i - 1] as u16 & 0x3f)
+        } else {
+            panic!("character cannot be represented in UCS-2");
+        };
+        if unit == 0 {
+            panic!("string contains a NUL");
+        }
+        buf[len] = unit;
+        len += 1;
+    }
+    assert!(len + 1 == N, "buffer size does not match string");
+    buf
+}
+
+/// Creates a `&'static CStr16` from a string literal
+///
+/// ```ignore
+/// stdout.output_cstr16(cstr16!("Hello, world!\r\n"))?;
+/// ```
+pub macro cstr16($s:expr) {{
+    const S: &str = $s;
+    const N: usize = $crate::ucs2::const_encoded_len(S) + 1;
+    const UNITS: [u16; N] = $crate::ucs2::const_encode::<N>(S);
+    unsafe { $crate::ucs2::CStr16::from_slice_with_nul_unchecked(&UNITS) }
+}}
@@ -0,0 +1,511 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! UEFI variables
+//!
+//! [`Global`] provides typed access to the variables defined by the specification under
+//! [`GLOBAL_VARIABLE`], on top of [`RuntimeServices::get_variable()`] and
+//! [`RuntimeServices::set_variable()`].
+//!
+//! [`slots`] keeps the state of A/B boot slots in a variable of the loader's own.
+//! [`recovery`] reports why the loader entered recovery to the OS.
+
+pub mod recovery;
+pub mod signature;
+pub mod slots;
+
+use core::{cell::Cell, convert::Infallible};
+
+use crate::{
+    crc32, guid, lang,
+    proto::console::text_input::InputKey,
+    proto::DevicePath,
+    table::{ResetType, RuntimeServices, VariableAttributes},
+    ucs2::{cstr16, CStr16},
+    Guid, Result, Status,
+};
+
+/// `EFI_GLOBAL_VARIABLE`, the vendor of the variables defined by the specification
+pub const GLOBAL_VARIABLE: Guid = guid!(
+    0x8be4df61,0x93ca,0x11d2,
+    {0xaa,0x0d,0x00,0xe0,0x98,0x03,0x2b,0x8c}
+);
+
+/// Attributes of the non-volatile global variables which may be written by the OS
+const NV_BS_RT: VariableAttributes = VariableAttributes::NON_VOLATILE
+    .union(VariableAttributes::BOOTSERVICE_ACCESS)
+    .union(VariableAttributes::RUNTIME_ACCESS);
+
+/// The longest `PlatformLang` accepted by [`Global::set_platform_lang()`]
+pub const MAX_LANG_LEN: usize = 63;
+
+bitflags::bitflags! {
+    /// Requests to the firmware, in `OsIndications` and `OsIndicationsSupported`
+    #[repr(transparent)]
+    pub struct OsIndications : u64 {
+        const BOOT_TO_FW_UI                   = 0x0000000000000001;
+        const TIMESTAMP_REVOCATION            = 0x0000000000000002;
+        const FILE_CAPSULE_DELIVERY_SUPPORTED = 0x0000000000000004;
+        const FMP_CAPSULE_SUPPORTED           = 0x0000000000000008;
+        const CAPSULE_RESULT_VAR_SUPPORTED    = 0x0000000000000010;
+        const START_OS_RECOVERY               = 0x0000000000000020;
+        const START_PLATFORM_RECOVERY         = 0x0000000000000040;
+        const JSON_CONFIG_DATA_REFRESH        = 0x0000000000000080;
+    }
+}
+
+/// The boot manager's `Timeout` setting
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Timeout {
+    /// Boot the default option after this many seconds; zero boots immediately
+    Seconds(u16),
+    /// Wait for the user to choose a boot option
+    Indefinite,
+}
+
+impl Timeout {
+    const INDEFINITE: u16 = 0xffff;
+
+    pub const fn from_raw(raw: u16) -> Self {
+        match raw {
+            Self::INDEFINITE => Self::Indefinite,
+            seconds => Self::Seconds(seconds),
+        }
+    }
+
+    /// Returns the raw value, or `None` for a timeout of `0xffff` seconds
+    pub const fn to_raw(self) -> Option<u16> {
+        match self {
+            Self::Seconds(Self::INDEFINITE) => None,
+            Self::Seconds(seconds) => Some(seconds),
+            Self::Indefinite => Some(Self::INDEFINITE),
+        }
+    }
+}
+
+/// Typed access to the global variables
+///
+/// Fixed-size variables are cached after the first successful read; the setters update the
+/// cache, so it only goes stale if something else writes the variable behind its back. Use
+/// [`invalidate()`](Self::invalidate) after calling out to other images.
+pub struct Global<'a> {
+    rt:                       &'a RuntimeServices,
+    timeout:                  Cell<Option<Timeout>>,
+    secure_boot:              Cell<Option<bool>>,
+    os_indications:           Cell<Option<OsIndications>>,
+    os_indications_supported: Cell<Option<OsIndications>>,
+}
+
+impl<'a> Global<'a> {
+    pub const fn new(rt: &'a RuntimeServices) -> Self {
+        Self {
+            rt,
+            timeout: Cell::new(None),
+            secure_boot: Cell::new(None),
+            os_indications: Cell::new(None),
+            os_indications_supported: Cell::new(None),
+        }
+    }
+
+    /// Discards all cached values
+    pub fn invalidate(&self) {
+        self.timeout.set(None);
+        self.secure_boot.set(None);
+        self.os_indications.set(None);
+        self.os_indications_supported.set(None);
+    }
+
+    /// Returns the boot manager timeout
+    pub fn timeout(&self) -> Result<Timeout> {
+        cached(&self.timeout, || {
+            self.read::<2>(cstr16!("Timeout"))
+                .map(|raw| Timeout::from_raw(u16::from_le_bytes(raw)))
+        })
+    }
+
+    /// Sets the boot manager timeout
+    ///
+    /// Fails with `INVALID_PARAMETER` for `Seconds(0xffff)`, which would read back as
+    /// [`Timeout::Indefinite`].
+    pub fn set_timeout(&self, timeout: Timeout) -> Result<()> {
+        let raw = timeout.to_raw().ok_or(Status::INVALID_PARAMETER)?;
+        self.write(cstr16!("Timeout"), NV_BS_RT, &raw.to_le_bytes())?;
+        self.timeout.set(Some(timeout));
+        Ok(())
+    }
+
+    /// Returns whether the platform booted with Secure Boot enforced
+    ///
+    /// A missing `SecureBoot` variable means the platform does not support it.
+    pub fn secure_boot(&self) -> Result<bool> {
+        cached(&self.secure_boot, || {
+            match self.read::<1>(cstr16!("SecureBoot")) {
+                Ok([enabled]) => Ok(enabled == 1),
+                Err(Status::NOT_FOUND) => Ok(false),
+                Err(status) => Err(status),
+            }
+        })
+    }
+
+    /// Returns the pending requests to the firmware, which are empty if none were made
+    pub fn os_indications(&self) -> Result<OsIndications> {
+        cached(&self.os_indications, || {
+            match self.read::<8>(cstr16!("OsIndications")) {
+                Ok(raw) => Ok(OsIndications::from_bits_truncate(u64::from_le_bytes(raw))),
+                Err(Status::NOT_FOUND) => Ok(OsIndications::empty()),
+                Err(status) => Err(status),
+            }
+        })
+    }
+
+    /// Sets the requests to the firmware, to be acted upon at the next boot
+    pub fn set_os_indications(&self, indications: OsIndications) -> Result<()> {
+        self.write(
+            cstr16!("OsIndications"),
+            NV_BS_RT,
+            &indications.bits().to_le_bytes(),
+        )?;
+        self.os_indications.set(Some(indications));
+        Ok(())
+    }
+
+    /// Returns the requests supported by the firmware
+    pub fn os_indications_supported(&self) -> Result<OsIndications> {
+        cached(&self.os_indications_supported, || {
+            match self.read::<8>(cstr16!("OsIndicationsSupported")) {
+                Ok(raw) => Ok(OsIndications::from_bits_truncate(u64::from_le_bytes(raw))),
+                Err(Status::NOT_FOUND) => Ok(OsIndications::empty()),
+                Err(status) => Err(status),
+            }
+        })
+    }
+
+    /// Reads the console output devices into `buf`
+    ///
+    /// `ConOut` may contain several device path instances, one for each console.
+    pub fn con_out<'b>(&self, buf: &'b mut [u8]) -> Result<&'b DevicePath> {
+        let (size, _) = self
+            .rt
+            .get_variable(cstr16!("ConOut"), &GLOBAL_VARIABLE, buf)?;
+        DevicePath::from_bytes(&buf[..size])
+    }
+
+    pub fn set_con_out(&self, path: &DevicePath) -> Result<()> {
+        self.write(cstr16!("ConOut"), NV_BS_RT, path.as_bytes())
+    }
+
+    /// Reads the platform language, an RFC 4646 code such as `en-US`, into `buf`
+    ///
+    /// Fails with `VOLUME_CORRUPTED` if the variable is not an ASCII string.
+    pub fn platform_lang<'b>(&self, buf: &'b mut [u8]) -> Result<&'b str> {
+        self.read_ascii(cstr16!("PlatformLang"), buf)
+    }
+
+    /// Reads the languages the firmware supports into `buf`
+    ///
+    /// The result is a semicolon-separated list of RFC 4646 codes, such as `en-US;fr-FR`,
+    /// which [`lang::codes()`] iterates over. Fails with `VOLUME_CORRUPTED` if the variable
+    /// is not an ASCII string.
+    pub fn platform_lang_codes<'b>(&self, buf: &'b mut [u8]) -> Result<&'b str> {
+        self.read_ascii(cstr16!("PlatformLangCodes"), buf)
+    }
+
+    /// Selects the language from `supported` which best fits the platform language
+    ///
+    /// `supported` is a semicolon-separated list of RFC 4646 codes, such as the languages a
+    /// boot menu ships translations for. Falls back to the first code in the list if
+    /// `PlatformLang` cannot be read or nothing in the list fits it, and returns `None` only
+    /// if the list is empty.
+    pub fn language<'s>(&self, supported: &'s str) -> Option<&'s str> {
+        let mut buf = [0; MAX_LANG_LEN + 1];
+        self.platform_lang(&mut buf)
+            .ok()
+            .and_then(|platform| lang::best_language(supported, &[platform]))
+            .or_else(|| lang::codes(supported).next())
+    }
+
+    /// Sets the platform language
+    ///
+    /// Fails with `INVALID_PARAMETER` if `lang` is not ASCII or is longer than
+    /// [`MAX_LANG_LEN`]. Firmware rejects codes missing from `PlatformLangCodes`.
+    pub fn set_platform_lang(&self, lang: &str) -> Result<()> {
+        if !lang.is_ascii() || lang.len() > MAX_LANG_LEN || lang.contains('\0') {
+            return Err(Status::INVALID_PARAMETER);
+        }
+        let mut buf = [0; MAX_LANG_LEN + 1];
+        buf[..lang.len()].copy_from_slice(lang.as_bytes());
+        self.write(cstr16!("PlatformLang"), NV_BS_RT, &buf[..=lang.len()])
+    }
+
+    /// Reads the `EFI_LOAD_OPTION` in `Boot####` into `buf`
+    pub fn boot_option<'b>(&self, number: u16, buf: &'b mut [u8]) -> Result<&'b [u8]> {
+        let mut name = [0; 9];
+        let name = option_variable_name("Boot", number, &mut name)?;
+        let (size, _) = self.rt.get_variable(name, &GLOBAL_VARIABLE, buf)?;
+        Ok(&buf[..size])
+    }
+
+    /// Reads the hot key in `Key####`
+    pub fn key_option(&self, number: u16) -> Result<KeyOption> {
+        let mut name = [0; 8];
+        let name = option_variable_name("Key", number, &mut name)?;
+        let mut buf = [0; KeyOption::MAX_SIZE];
+        match self.rt.get_variable(name, &GLOBAL_VARIABLE, &mut buf) {
+            Ok((size, _)) => KeyOption::parse(&buf[..size]),
+            Err(Status::BUFFER_TOO_SMALL) => Err(Status::VOLUME_CORRUPTED),
+            Err(status) => Err(status),
+        }
+    }
+
+    /// Writes the hot key in `Key####`, or deletes it if `option` is `None`
+    ///
+    /// Firmware only honors hot keys if `EFI_BOOT_OPTION_SUPPORT_KEY` is set in the
+    /// `BootOptionSupport` variable.
+    pub fn set_key_option(&self, number: u16, option: Option<&KeyOption>) -> Result<()> {
+        let mut name = [0; 8];
+        let name = option_variable_name("Key", number, &mut name)?;
+        let mut buf = [0; KeyOption::MAX_SIZE];
+        let len = match option {
+            Some(option) => option.write(&mut buf)?,
+            None => 0,
+        };
+        self.write(name, NV_BS_RT, &buf[..len])
+    }
+
+    /// Reads a fixed-size variable, failing with `VOLUME_CORRUPTED` if the size is wrong
+    fn read<const N: usize>(&self, name: &CStr16) -> Result<[u8; N]> {
+        let mut buf = [0; N];
+        match self.rt.get_variable(name, &GLOBAL_VARIABLE, &mut buf) {
+            Ok((size, _)) if size == N => Ok(buf),
+            Ok(_) | Err(Status::BUFFER_TOO_SMALL) => Err(Status::VOLUME_CORRUPTED),
+            Err(status) => Err(status),
+        }
+    }
+
+    /// Reads a nul-terminated ASCII string, failing with `VOLUME_CORRUPTED` if it is not one
+    fn read_ascii<'b>(&self, name: &CStr16, buf: &'b mut [u8]) -> Result<&'b str> {
+        let (size, _) = self.rt.get_variable(name, &GLOBAL_VARIABLE, buf)?;
+        let string = &buf[..size];
+        let string = string.split(|&b| b == 0).next().unwrap_or_default();
+        if !string.is_ascii() {
+            return Err(Status::VOLUME_CORRUPTED);
+        }
+        core::str::from_utf8(string).map_err(|_| Status::VOLUME_CORRUPTED)
+    }
+
+    fn write(&self, name: &CStr16, attributes: VariableAttributes, data: &[u8]) -> Result<()> {
+        self.rt
+            .set_variable(name, &GLOBAL_VARIABLE, attributes, data)
+    }
+}
+
+bitflags::bitflags! {
+    /// Modifiers which must be held for a [`KeyOption`] to trigger
+    #[repr(transparent)]
+    pub struct KeyModifiers : u32 {
+        const SHIFT   = 0x00000100;
+        const CONTROL = 0x00000200;
+        const ALT     = 0x00000400;
+        const LOGO    = 0x00000800;
+        const MENU    = 0x00001000;
+        const SYS_REQ = 0x00002000;
+    }
+}
+
+/// A firmware hot key bound to a boot option, stored in a `Key####` variable
+///
+/// This is `EFI_KEY_OPTION`: the packed `KeyData`, the CRC32 of the `Boot####` variable it
+/// refers to, the option's number and up to three keys.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct KeyOption {
+    pub revision:        u8,
+    pub modifiers:       KeyModifiers,
+    /// CRC32 of the whole `EFI_LOAD_OPTION` in the `Boot####` variable
+    pub boot_option_crc: u32,
+    /// Number of the `Boot####` variable to start
+    pub boot_option:     u16,
+    keys:                [InputKey; KeyOption::MAX_KEYS],
+    key_count:           u8,
+}
+
+impl KeyOption {
+    pub const MAX_KEYS: usize = 3;
+    /// Size of the fixed part of `EFI_KEY_OPTION`
+    const HEADER_SIZE: usize = 10;
+    pub const MAX_SIZE: usize = Self::HEADER_SIZE + Self::MAX_KEYS * 4;
+
+    /// Creates a hot key for the boot option `number`, whose `Boot####` variable holds
+    /// `load_option`
+    ///
+    /// Fails with `INVALID_PARAMETER` if there are more than [`MAX_KEYS`](Self::MAX_KEYS) keys.
+    pub fn new(
+        number: u16,
+        load_option: &[u8],
+        modifiers: KeyModifiers,
+        keys: &[InputKey],
+    ) -> Result<Self> {
+        if keys.len() > Self::MAX_KEYS {
+            return Err(Status::INVALID_PARAMETER);
+        }
+        let mut option = Self {
+            revision: 0,
+            modifiers,
+            boot_option_crc: crc32::checksum(load_option),
+            boot_option: number,
+            keys: [InputKey::default(); Self::MAX_KEYS],
+            key_count: keys.len() as u8,
+        };
+        option.keys[..keys.len()].copy_from_slice(keys);
+        Ok(option)
+    }
+
+    /// Parses the contents of a `Key####` variable
+    ///
+    /// Fails with `VOLUME_CORRUPTED` if the size does not match the key count.
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let header = data
+            .get(..Self::HEADER_SIZE)
+            .ok_or(Status::VOLUME_CORRUPTED)?;
+        let key_data = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let key_count = (key_data >> 30) as usize;
+        if data.len() != Self::HEADER_SIZE + key_count * 4 {
+            return Err(Status::VOLUME_CORRUPTED);
+        }
+
+        let mut keys = [InputKey::default(); Self::MAX_KEYS];
+        for (key, raw) in keys
+            .iter_mut()
+            .zip(data[Self::HEADER_SIZE..].chunks_exact(4))
+        {
+            key.scancode = u16::from_le_bytes([raw[0], raw[1]]);
+            key.codepoint = u16::from_le_bytes([raw[2], raw[3]]);
+        }
+        Ok(Self {
+            revision: key_data as u8,
+            modifiers: KeyModifiers::from_bits_truncate(key_data),
+            boot_option_crc: u32::from_le_bytes([header[4], header[5], header[6], header[7]]),
+            boot_option: u16::from_le_bytes([header[8], header[9]]),
+            keys,
+            key_count: key_count as u8,
+        })
+    }
+
+    /// Serializes the option into `buf`, returning the number of bytes written
+    pub fn write(&self, buf: &mut [u8]) -> Result<usize> {
+        let len = self.size();
+        let buf = buf.get_mut(..len).ok_or(Status::BUFFER_TOO_SMALL)?;
+        let key_data = self.revision as u32 | self.modifiers.bits() | (self.key_count as u32) << 30;
+        buf[0..4].copy_from_slice(&key_data.to_le_bytes());
+        buf[4..8].copy_from_slice(&self.boot_option_crc.to_le_bytes());
+        buf[8..10].copy_from_slice(&self.boot_option.to_le_bytes());
+        for (raw, key) in buf[Self::HEADER_SIZE..]
+            .chunks_exact_mut(4)
+            .zip(self.keys())
+        {
+            raw[..2].copy_from_slice(&key.scancode.to_le_bytes());
+            raw[2..].copy_from_slice(&key.codepoint.to_le_bytes());
+        }
+        Ok(len)
+    }
+
+    /// Returns the serialized size in bytes
+    pub fn size(&self) -> usize {
+        Self::HEADER_SIZE + self.keys().len() * 4
+    }
+
+    pub fn keys(&self) -> &[InputKey] {
+        &self.keys[..self.key_count as usize]
+    }
+
+    /// Returns `true` if the option still refers to `load_option`, the current contents of
+    /// its `Boot####` variable
+    ///
+    /// Firmware ignores hot keys whose boot option has changed since they were created.
+    pub fn is_valid_for(&self, load_option: &[u8]) -> bool {
+        crc32::checksum(load_option) == self.boot_option_crc
+    }
+
+    /// Returns `true` if `keys` were pressed with exactly the required modifiers held
+    pub fn matches(&self, keys: &[InputKey], modifiers: KeyModifiers) -> bool {
+        self.keys() == keys && self.modifiers == modifiers
+    }
+}
+
+/// Writes the name of an option variable such as `Boot0001` or `Key001A` to `buf`
+///
+/// `prefix` is the ASCII part of the name, e.g. `"Boot"` or `"Key"`; the option number is
+/// appended as 4 uppercase hex digits.
+pub fn option_variable_name<'b>(
+    prefix: &str,
+    number: u16,
+    buf: &'b mut [u16],
+) -> Result<&'b CStr16> {
+    const HEX: &[u8; 16] = b"0123456789ABCDEF";
+
+    let len = prefix.len() + 4;
+    if !prefix.is_ascii() || prefix.contains('\0') {
+        return Err(Status::INVALID_PARAMETER);
+    }
+    let name = buf.get_mut(..=len).ok_or(Status::BUFFER_TOO_SMALL)?;
+    for (unit, &b) in name.iter_mut().zip(prefix.as_bytes()) {
+        *unit = b as u16;
+    }
+    for (i, unit) in name[prefix.len()..len].iter_mut().enumerate() {
+        *unit = HEX[(number >> (12 - 4 * i) & 0xf) as usize] as u16;
+    }
+    name[len] = 0;
+    Ok(unsafe { CStr16::from_slice_with_nul_unchecked(name) })
+}
+
+/// Asks the firmware to enter its setup UI at the next boot, then resets the platform
+///
+/// Fails with `UNSUPPORTED` if the firmware does not advertise `BOOT_TO_FW_UI` in
+/// `OsIndicationsSupported`. Only returns on failure.
+pub fn reboot_to_firmware_setup(rt: &RuntimeServices) -> Result<Infallible> {
+    let global = Global::new(rt);
+    if !global
+        .os_indications_supported()?
+        .contains(OsIndications::BOOT_TO_FW_UI)
+    {
+        return Err(Status::UNSUPPORTED);
+    }
+    let indications = global.os_indications()?;
+    global.set_os_indications(indications | OsIndications::BOOT_TO_FW_UI)?;
+    rt.reset_system(ResetType::COLD, Status::SUCCESS, &[])
+}
+
+fn cached<T: Copy>(cell: &Cell<Option<T>>, read: impl FnOnce() -> Result<T>) -> Result<T> {
+    if let Some(value) = cell.get() {
+        return Ok(value);
+    }
+    let value = read()?;
+    cell.set(Some(value));
+    Ok(value)
+}
@@ -0,0 +1,279 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Recovery boot reason
+//!
+//! Loaders which fall back to a recovery path record why with a [`RecoveryReport`], so the
+//! recovery OS or the next boot stage can tell the user what happened. The report is kept in
+//! the volatile variable [`VARIABLE_NAME`] under [`RECOVERY_GUID`], which the OS can read at
+//! runtime, and can additionally be [published](publish) in the configuration table under the
+//! same GUID for kernels without variable support. Either copy holds:
+//!
+//! | Offset | Size | Contents                                                      |
+//! |--------|------|---------------------------------------------------------------|
+//! | 0      | 4    | Magic, `RCVR`                                                 |
+//! | 4      | 4    | [`RecoveryReason`], little-endian                             |
+//! | 8      | 8    | Raw status of the failure, zero-extended, little-endian       |
+//! | 16     | 4    | Length of the message, little-endian                          |
+//! | 20     | 4    | Reserved, 0                                                   |
+//! | 24     |      | Message, UTF-8 without a terminator                           |
+//!
+//! Being volatile, the variable does not survive a reset; the recovery OS is expected to
+//! [`clear()`] it once the reason has been reported.
+
+use core::fmt;
+
+use crate::{
+    boot_services, guid, system_table,
+    table::{AllocPagesType, MemoryType, RuntimeServices, TableGuid, VariableAttributes},
+    ucs2::{cstr16, CStr16},
+    Guid, PhysicalAddr, Result, Status,
+};
+
+/// Vendor of [`VARIABLE_NAME`], and GUID of the configuration table entry
+pub const RECOVERY_GUID: Guid = guid!(
+    0xf6f5c713, 0xac91, 0x4ec5,
+    {0x85,0xc2,0x60,0xaa,0x15,0xd8,0xc2,0xbc}
+);
+
+/// Name of the variable holding the report
+pub const VARIABLE_NAME: &CStr16 = cstr16!("RecoveryReason");
+
+const MAGIC: &[u8; 4] = b"RCVR";
+const HEADER_SIZE: usize = 24;
+
+/// Volatile, and readable by the OS
+const BS_RT: VariableAttributes =
+    VariableAttributes::BOOTSERVICE_ACCESS.union(VariableAttributes::RUNTIME_ACCESS);
+
+/// Why recovery was entered
+///
+/// Values from `0x8000_0000` up are free for loader-specific reasons.
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct RecoveryReason(pub u32);
+
+impl RecoveryReason {
+    /// The user or the OS asked for recovery
+    pub const REQUESTED: Self = Self(1);
+    /// A watchdog reset the system during the previous boot
+    pub const WATCHDOG: Self = Self(2);
+    /// An image, signature or checksum failed to verify
+    pub const VERIFICATION_FAILED: Self = Self(3);
+    /// No boot entry or slot could be started
+    pub const BOOT_FAILED: Self = Self(4);
+    /// The previous boot crashed
+    pub const CRASH: Self = Self(5);
+
+    pub fn name(self) -> Option<&'static str> {
+        Some(match self {
+            Self::REQUESTED => "requested",
+            Self::WATCHDOG => "watchdog",
+            Self::VERIFICATION_FAILED => "verification failed",
+            Self::BOOT_FAILED => "boot failed",
+            Self::CRASH => "crash",
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Display for RecoveryReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.name() {
+            Some(name) => f.write_str(name),
+            None => write!(f, "{:#x}", self.0),
+        }
+    }
+}
+
+/// A recorded reason, with the failing status and a short description
+#[derive(Clone, Copy, Debug)]
+pub struct RecoveryReport<'a> {
+    pub reason:  RecoveryReason,
+    /// `SUCCESS` if there is no particular status
+    pub status:  Status,
+    pub message: &'a str,
+}
+
+impl<'a> RecoveryReport<'a> {
+    /// The largest encoded report
+    pub const MAX_SIZE: usize = 256;
+    /// The longest message which is stored
+    pub const MAX_MESSAGE_LEN: usize = Self::MAX_SIZE - HEADER_SIZE;
+
+    /// Creates a report, truncating `message` at a character boundary if it is too long
+    pub fn new(reason: RecoveryReason, status: Status, message: &'a str) -> Self {
+        let mut len = message.len().min(Self::MAX_MESSAGE_LEN);
+        while !message.is_char_boundary(len) {
+            len -= 1;
+        }
+        Self {
+            reason,
+            status,
+            message: &message[..len],
+        }
+    }
+
+    /// Decodes a report, failing with `VOLUME_CORRUPTED` if `data` isn't one
+    pub fn parse(data: &'a [u8]) -> Result<Self> {
+        let (header, rest) = data
+            .split_at_checked(HEADER_SIZE)
+            .ok_or(Status::VOLUME_CORRUPTED)?;
+        if header[..4] != *MAGIC {
+            return Err(Status::VOLUME_CORRUPTED);
+        }
+        let reason = u32::from_le_bytes(header[4..8].try_into().unwrap());
+        let status = u64::from_le_bytes(header[8..16].try_into().unwrap());
+        let len = u32::from_le_bytes(header[16..20].try_into().unwrap()) as usize;
+        let message = rest.get(..len).ok_or(Status::VOLUME_CORRUPTED)?;
+        Ok(Self {
+            reason:  RecoveryReason(reason),
+            status:  Status::from_raw(status as usize),
+            message: core::str::from_utf8(message).map_err(|_| Status::VOLUME_CORRUPTED)?,
+        })
+    }
+
+    /// Encodes the report into `buf`, returning the number of bytes written
+    pub fn write(&self, buf: &mut [u8]) -> Result<usize> {
+        let size = self.size();
+        let buf = buf.get_mut(..size).ok_or(Status::BUFFER_TOO_SMALL)?;
+        buf[..4].copy_from_slice(MAGIC);
+        buf[4..8].copy_from_slice(&self.reason.0.to_le_bytes());
+        buf[8..16].copy_from_slice(&(self.status.as_raw() as u64).to_le_bytes());
+        buf[16..20].copy_from_slice(&(self.message.len() as u32).to_le_bytes());
+        buf[20..24].fill(0);
+        buf[HEADER_SIZE..].copy_from_slice(self.message.as_bytes());
+        Ok(size)
+    }
+
+    /// Returns the encoded size
+    pub fn size(&self) -> usize {
+        HEADER_SIZE + self.message.len()
+    }
+}
+
+/// Stores `report` in the recovery variable, replacing any earlier one
+///
+/// Fails with `INVALID_PARAMETER` if the message is longer than
+/// [`RecoveryReport::MAX_MESSAGE_LEN`]; see [`RecoveryReport::new()`].
+pub fn record(rt: &RuntimeServices, report: &RecoveryReport) -> Result<()> {
+    let mut buf = [0; RecoveryReport::MAX_SIZE];
+    let size = report
+        .write(&mut buf)
+        .map_err(|_| Status::INVALID_PARAMETER)?;
+    rt.set_variable(VARIABLE_NAME, &RECOVERY_GUID, BS_RT, &buf[..size])
+}
+
+/// Reads the report from the recovery variable into `buf`, if there is one
+///
+/// A `buf` of [`RecoveryReport::MAX_SIZE`] bytes holds any report written by [`record()`].
+pub fn query<'b>(rt: &RuntimeServices, buf: &'b mut [u8]) -> Result<Option<RecoveryReport<'b>>> {
+    match rt.get_variable(VARIABLE_NAME, &RECOVERY_GUID, buf) {
+        Ok((size, _)) => RecoveryReport::parse(&buf[..size]).map(Some),
+        Err(Status::NOT_FOUND) => Ok(None),
+        Err(status) => Err(status),
+    }
+}
+
+/// Deletes the recovery variable, and the configuration table entry while boot services are
+/// active
+///
+/// Succeeds if there is nothing to clear.
+pub fn clear(rt: &RuntimeServices) -> Result<()> {
+    match rt.set_variable(VARIABLE_NAME, &RECOVERY_GUID, BS_RT, &[]) {
+        Ok(()) | Err(Status::NOT_FOUND) => {}
+        Err(status) => return Err(status),
+    }
+    if crate::boot_services_active() {
+        unpublish()?;
+    }
+    Ok(())
+}
+
+/// Copies `report` into ACPI reclaimable memory and installs it in the configuration table
+///
+/// A previously published report is replaced and its memory freed.
+pub fn publish(report: &RecoveryReport) -> Result<()> {
+    let bs = boot_services();
+    let size = report.size();
+    if size > RecoveryReport::MAX_SIZE {
+        return Err(Status::INVALID_PARAMETER);
+    }
+    let addr = bs.allocate_pages(AllocPagesType::Any, MemoryType::ACPI_RECLAIM, 1)?;
+    let page = unsafe { core::slice::from_raw_parts_mut(addr as *mut u8, size) };
+    report.write(page)?;
+
+    let old = system_table()
+        .config_table()
+        .get_table(TableGuid(RECOVERY_GUID));
+    unsafe {
+        if let Err(status) = bs.install_configuration_table(&RECOVERY_GUID, page.as_ptr().cast()) {
+            let _ = bs.free_pages(addr, 1);
+            return Err(status);
+        }
+        if let Some(old) = old {
+            let _ = bs.free_pages(old as PhysicalAddr, 1);
+        }
+    }
+    Ok(())
+}
+
+/// Returns the report published in the configuration table, if any
+///
+/// This works after boot services have been exited too, as long as the system table is still
+/// mapped.
+pub fn published() -> Option<RecoveryReport<'static>> {
+    let table = system_table()
+        .config_table()
+        .get_table(TableGuid(RECOVERY_GUID))?;
+    let header = unsafe { core::slice::from_raw_parts(table as *const u8, HEADER_SIZE) };
+    let len = u32::from_le_bytes(header[16..20].try_into().unwrap()) as usize;
+    if HEADER_SIZE + len > RecoveryReport::MAX_SIZE {
+        return None;
+    }
+    let data = unsafe { core::slice::from_raw_parts(table as *const u8, HEADER_SIZE + len) };
+    RecoveryReport::parse(data).ok()
+}
+
+/// Removes the configuration table entry and frees its memory
+pub fn unpublish() -> Result<()> {
+    let bs = boot_services();
+    let Some(table) = system_table()
+        .config_table()
+        .get_table(TableGuid(RECOVERY_GUID))
+    else {
+        return Ok(());
+    };
+    unsafe {
+        bs.install_configuration_table(&RECOVERY_GUID, core::ptr::null())?;
+        let _ = bs.free_pages(table as PhysicalAddr, 1);
+    }
+    Ok(())
+}
@@ -0,0 +1,341 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Signature databases
+//!
+//! The Secure Boot variables (`PK`, `KEK`, `db`, `dbx` and their `*Default` counterparts) each
+//! hold a sequence of `EFI_SIGNATURE_LIST`s. Every list has a type, an optional header and an
+//! array of equally sized `EFI_SIGNATURE_DATA` entries, each an owner GUID followed by the
+//! signature itself.
+
+use crate::{guid, Guid, Result, Status};
+
+/// Vendor of `db`, `dbx`, `dbt` and `dbr`
+///
+/// `PK`, `KEK` and the `*Default` variables live under
+/// [`GLOBAL_VARIABLE`](super::GLOBAL_VARIABLE).
+pub const IMAGE_SECURITY_DATABASE: Guid = guid!(
+    0xd719b2cb,0x3d3a,0x4596,
+    {0xa3,0xbc,0xda,0xd0,0x0e,0x67,0x65,0x6f}
+);
+
+/// `EFI_CERT_SHA256_GUID`, a SHA-256 hash of an image
+pub const CERT_SHA256: Guid = guid!(
+    0xc1c41626,0x504c,0x4092,
+    {0xac,0xa9,0x41,0xf9,0x36,0x93,0x43,0x28}
+);
+
+/// `EFI_CERT_X509_GUID`, a DER-encoded X.509 certificate
+pub const CERT_X509: Guid = guid!(
+    0xa5c059a1,0x94e4,0x4aa7,
+    {0x87,0xb5,0xab,0x15,0x5c,0x2b,0xf0,0x72}
+);
+
+/// `EFI_CERT_RSA2048_GUID`, a bare RSA-2048 public key modulus
+pub const CERT_RSA2048: Guid = guid!(
+    0x3c5766e8,0x269c,0x4e34,
+    {0xaa,0x14,0xed,0x77,0x6e,0x85,0xb3,0xb6}
+);
+
+/// `EFI_CERT_X509_SHA256_GUID`, the SHA-256 of a certificate's TBS data plus a revocation time
+pub const CERT_X509_SHA256: Guid = guid!(
+    0x3bd2a492,0x96c0,0x4079,
+    {0xb4,0x20,0xfc,0xf9,0x8e,0xf1,0x03,0xed}
+);
+
+/// Size of the fixed part of `EFI_SIGNATURE_LIST`
+const LIST_HEADER_SIZE: usize = 28;
+/// Size of the owner GUID at the start of each `EFI_SIGNATURE_DATA`
+const OWNER_SIZE: usize = 16;
+const SHA256_SIZE: usize = 32;
+
+/// An `EFI_SIGNATURE_LIST`
+#[derive(Clone, Copy, Debug)]
+pub struct SignatureList<'a> {
+    pub kind:       Guid,
+    /// The type-specific header, empty for every type defined by the specification
+    pub header:     &'a [u8],
+    signature_size: usize,
+    signatures:     &'a [u8],
+}
+
+impl<'a> SignatureList<'a> {
+    /// Parses the list at the start of `data`, returning it and the number of bytes it spans
+    ///
+    /// Fails with `VOLUME_CORRUPTED` if the sizes are inconsistent.
+    pub fn parse(data: &'a [u8]) -> Result<(Self, usize)> {
+        let header = data
+            .get(..LIST_HEADER_SIZE)
+            .ok_or(Status::VOLUME_CORRUPTED)?;
+        let read_u32 = |at: usize| {
+            u32::from_le_bytes([header[at], header[at + 1], header[at + 2], header[at + 3]])
+                as usize
+        };
+        let kind = Guid::from_bytes(header[..16].try_into().unwrap());
+        let (list_size, header_size, signature_size) = (read_u32(16), read_u32(20), read_u32(24));
+
+        let list = data.get(..list_size).ok_or(Status::VOLUME_CORRUPTED)?;
+        let signatures_at = LIST_HEADER_SIZE
+            .checked_add(header_size)
+            .filter(|&at| at <= list_size)
+            .ok_or(Status::VOLUME_CORRUPTED)?;
+        let signatures = &list[signatures_at..];
+        if signature_size <= OWNER_SIZE || signatures.len() % signature_size != 0 {
+            return Err(Status::VOLUME_CORRUPTED);
+        }
+
+        Ok((
+            Self {
+                kind,
+                header: &list[LIST_HEADER_SIZE..signatures_at],
+                signature_size,
+                signatures,
+            },
+            list_size,
+        ))
+    }
+
+    /// Returns the size of each entry's signature data, not counting the owner GUID
+    pub fn signature_size(&self) -> usize {
+        self.signature_size - OWNER_SIZE
+    }
+
+    pub fn len(&self) -> usize {
+        self.signatures.len() / self.signature_size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.signatures.is_empty()
+    }
+
+    /// Returns an iterator over the raw entries
+    pub fn signatures(&self) -> impl Iterator<Item = SignatureData<'a>> + 'a {
+        self.signatures
+            .chunks_exact(self.signature_size)
+            .map(|entry| SignatureData {
+                owner: Guid::from_bytes(entry[..OWNER_SIZE].try_into().unwrap()),
+                data:  &entry[OWNER_SIZE..],
+            })
+    }
+
+    /// Returns an iterator over the entries, typed according to the list's type
+    pub fn entries(&self) -> impl Iterator<Item = Signature<'a>> + 'a {
+        let kind = self.kind;
+        self.signatures()
+            .map(move |SignatureData { owner, data }| match kind {
+                CERT_SHA256 if data.len() == SHA256_SIZE => Signature::Sha256 {
+                    owner,
+                    hash: data.try_into().unwrap(),
+                },
+                CERT_X509 => Signature::X509 { owner, der: data },
+                kind => Signature::Other { kind, owner, data },
+            })
+    }
+}
+
+/// An `EFI_SIGNATURE_DATA` entry
+#[derive(Clone, Copy, Debug)]
+pub struct SignatureData<'a> {
+    pub owner: Guid,
+    pub data:  &'a [u8],
+}
+
+/// A signature database entry
+#[derive(Clone, Copy, Debug)]
+pub enum Signature<'a> {
+    Sha256 {
+        owner: Guid,
+        hash:  &'a [u8; SHA256_SIZE],
+    },
+    /// A DER-encoded certificate
+    X509 { owner: Guid, der: &'a [u8] },
+    Other {
+        kind:  Guid,
+        owner: Guid,
+        data:  &'a [u8],
+    },
+}
+
+/// Returns an iterator over the lists in a signature database
+///
+/// Iteration stops after the first malformed list, which is returned as an error.
+pub fn signature_lists(db: &[u8]) -> SignatureLists<'_> {
+    SignatureLists { data: db }
+}
+
+pub struct SignatureLists<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Iterator for SignatureLists<'a> {
+    type Item = Result<SignatureList<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+        match SignatureList::parse(self.data) {
+            Ok((list, size)) => {
+                self.data = &self.data[size..];
+                Some(Ok(list))
+            }
+            Err(status) => {
+                self.data = &[];
+                Some(Err(status))
+            }
+        }
+    }
+}
+
+/// Returns an iterator over every entry in a signature database
+///
+/// Entries of malformed lists are skipped; use [`signature_lists()`] to detect them.
+pub fn entries(db: &[u8]) -> impl Iterator<Item = Signature<'_>> {
+    signature_lists(db)
+        .flatten()
+        .flat_map(|list| list.entries())
+}
+
+/// Returns an iterator over the DER-encoded X.509 certificates in a signature database
+pub fn x509_certificates(db: &[u8]) -> impl Iterator<Item = (Guid, &[u8])> {
+    entries(db).filter_map(|entry| match entry {
+        Signature::X509 { owner, der } => Some((owner, der)),
+        _ => None,
+    })
+}
+
+/// Returns an iterator over the SHA-256 image hashes in a signature database
+pub fn sha256_hashes(db: &[u8]) -> impl Iterator<Item = (Guid, &[u8; SHA256_SIZE])> {
+    entries(db).filter_map(|entry| match entry {
+        Signature::Sha256 { owner, hash } => Some((owner, hash)),
+        _ => None,
+    })
+}
+
+/// Builds a signature database in a caller-provided buffer
+///
+/// ```ignore
+/// let mut writer = SignatureListWriter::new(&mut buf);
+/// writer.push_x509(&OWNER, cert)?;
+/// writer.push_sha256(&OWNER, &[hash_a, hash_b])?;
+/// rt.set_variable(cstr16!("db"), &IMAGE_SECURITY_DATABASE, attributes, writer.finish())?;
+/// ```
+///
+/// Writing to `db`, `KEK` or `PK` outside of setup mode additionally requires wrapping the
+/// data in an authentication descriptor, which is left to the caller.
+pub struct SignatureListWriter<'b> {
+    buf: &'b mut [u8],
+    len: usize,
+}
+
+impl<'b> SignatureListWriter<'b> {
+    pub fn new(buf: &'b mut [u8]) -> Self {
+        Self { buf, len: 0 }
+    }
+
+    /// Appends a list of `kind` holding `signatures`, which must all be the same size
+    ///
+    /// Fails with `INVALID_PARAMETER` if the signatures are empty or differ in size, or with
+    /// `BUFFER_TOO_SMALL` if they don't fit.
+    pub fn push_list(&mut self, kind: &Guid, owner: &Guid, signatures: &[&[u8]]) -> Result<()> {
+        let data_size = signatures.first().map_or(0, |data| data.len());
+        if signatures.iter().any(|data| data.len() != data_size) {
+            return Err(Status::INVALID_PARAMETER);
+        }
+        let entries = self.reserve_list(kind, data_size, signatures.len())?;
+        for (entry, data) in entries
+            .chunks_exact_mut(OWNER_SIZE + data_size)
+            .zip(signatures)
+        {
+            entry[..OWNER_SIZE].copy_from_slice(&owner.to_bytes());
+            entry[OWNER_SIZE..].copy_from_slice(data);
+        }
+        Ok(())
+    }
+
+    /// Appends a list holding a single DER-encoded certificate
+    ///
+    /// Certificates differ in size, so each needs a list of its own.
+    pub fn push_x509(&mut self, owner: &Guid, der: &[u8]) -> Result<()> {
+        self.push_list(&CERT_X509, owner, &[der])
+    }
+
+    /// Appends a list holding SHA-256 image hashes
+    pub fn push_sha256(&mut self, owner: &Guid, hashes: &[[u8; SHA256_SIZE]]) -> Result<()> {
+        let entries = self.reserve_list(&CERT_SHA256, SHA256_SIZE, hashes.len())?;
+        for (entry, hash) in entries
+            .chunks_exact_mut(OWNER_SIZE + SHA256_SIZE)
+            .zip(hashes)
+        {
+            entry[..OWNER_SIZE].copy_from_slice(&owner.to_bytes());
+            entry[OWNER_SIZE..].copy_from_slice(hash);
+        }
+        Ok(())
+    }
+
+    /// Writes a list header and returns the space for its entries
+    fn reserve_list(&mut self, kind: &Guid, data_size: usize, count: usize) -> Result<&mut [u8]> {
+        if data_size == 0 || count == 0 {
+            return Err(Status::INVALID_PARAMETER);
+        }
+        let signature_size = OWNER_SIZE + data_size;
+        let list_size = count
+            .checked_mul(signature_size)
+            .and_then(|size| size.checked_add(LIST_HEADER_SIZE))
+            .filter(|&size| u32::try_from(size).is_ok())
+            .ok_or(Status::INVALID_PARAMETER)?;
+        let list = self
+            .buf
+            .get_mut(self.len..)
+            .and_then(|buf| buf.get_mut(..list_size))
+            .ok_or(Status::BUFFER_TOO_SMALL)?;
+
+        list[..16].copy_from_slice(&kind.to_bytes());
+        list[16..20].copy_from_slice(&(list_size as u32).to_le_bytes());
+        list[20..24].copy_from_slice(&0u32.to_le_bytes());
+        list[24..28].copy_from_slice(&(signature_size as u32).to_le_bytes());
+        self.len += list_size;
+        Ok(&mut list[LIST_HEADER_SIZE..])
+    }
+
+    /// Returns the number of bytes written so far
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the database written so far
+    pub fn finish(self) -> &'b [u8] {
+        &self.buf[..self.len]
+    }
+}
@@ -0,0 +1,325 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! A/B boot slots
+//!
+//! Systems updated by writing the new version into the inactive of two slots boot it with a
+//! limited number of tries: each boot of a slot not yet marked successful uses one, and once
+//! they run out the slot is marked unbootable and the loader falls back to the other slot.
+//! The OS marks the slot successful once it has come up, which stops the counting.
+//!
+//! [`BootControl`] keeps the state in a non-volatile variable of the loader's choosing,
+//! readable and writable by the OS. The variable holds [`BootSlots::SIZE`] bytes:
+//!
+//! | Offset | Size | Contents                                                    |
+//! |--------|------|-------------------------------------------------------------|
+//! | 0      | 4    | Magic, `ABBC`                                               |
+//! | 4      | 1    | Version, 1                                                  |
+//! | 5      | 1    | Active slot, 0 for A or 1 for B                             |
+//! | 6      | 1    | Tries a newly activated slot gets                           |
+//! | 7      | 1    | Reserved, 0                                                 |
+//! | 8      | 4    | Slot A: tries remaining, successful, unbootable, reserved  |
+//! | 12     | 4    | Slot B, likewise                                            |
+//! | 16     | 4    | CRC32 of the preceding bytes, little-endian                 |
+
+use core::fmt;
+
+use super::NV_BS_RT;
+use crate::{crc32, table::RuntimeServices, ucs2::CStr16, Guid, Result, Status};
+
+const MAGIC: &[u8; 4] = b"ABBC";
+const VERSION: u8 = 1;
+
+/// Tries a slot gets unless the loader asks for a different number
+pub const DEFAULT_TRIES: u8 = 3;
+
+/// One of the two boot slots
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Slot {
+    A,
+    B,
+}
+
+impl Slot {
+    pub const fn other(self) -> Self {
+        match self {
+            Self::A => Self::B,
+            Self::B => Self::A,
+        }
+    }
+
+    pub const fn index(self) -> usize {
+        self as usize
+    }
+
+    /// Returns the suffix partitions of the slot are conventionally named with, `_a` or `_b`
+    pub const fn suffix(self) -> &'static str {
+        match self {
+            Self::A => "_a",
+            Self::B => "_b",
+        }
+    }
+}
+
+impl fmt::Display for Slot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::A => "A",
+            Self::B => "B",
+        })
+    }
+}
+
+/// The boot state of one slot
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct SlotState {
+    pub tries_remaining: u8,
+    /// The OS has come up from this slot, so it is booted without counting tries
+    pub successful:      bool,
+    /// The slot ran out of tries, or was never written
+    pub unbootable:      bool,
+}
+
+/// The state of both slots, as stored in the variable
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct BootSlots {
+    active:    Slot,
+    max_tries: u8,
+    slots:     [SlotState; 2],
+}
+
+impl BootSlots {
+    /// Size of the encoded state
+    pub const SIZE: usize = 20;
+
+    /// Returns the state of a fresh system, booting slot A with `max_tries` tries
+    ///
+    /// Slot B is unbootable until something is installed there and it is
+    /// [activated](Self::set_active).
+    pub const fn new(max_tries: u8) -> Self {
+        let fresh = SlotState {
+            tries_remaining: max_tries,
+            successful:      false,
+            unbootable:      false,
+        };
+        let empty = SlotState {
+            tries_remaining: 0,
+            successful:      false,
+            unbootable:      true,
+        };
+        Self {
+            active: Slot::A,
+            max_tries,
+            slots: [fresh, empty],
+        }
+    }
+
+    /// Decodes the state, failing with `VOLUME_CORRUPTED` if `data` is not valid
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let data: &[u8; Self::SIZE] = data.try_into().map_err(|_| Status::VOLUME_CORRUPTED)?;
+        let crc = u32::from_le_bytes(data[16..20].try_into().unwrap());
+        if data[..4] != *MAGIC || data[4] != VERSION || crc32::checksum(&data[..16]) != crc {
+            return Err(Status::VOLUME_CORRUPTED);
+        }
+        let active = match data[5] {
+            0 => Slot::A,
+            1 => Slot::B,
+            _ => return Err(Status::VOLUME_CORRUPTED),
+        };
+        let slot = |at: usize| SlotState {
+            tries_remaining: data[at],
+            successful:      data[at + 1] != 0,
+            unbootable:      data[at + 2] != 0,
+        };
+        Ok(Self {
+            active,
+            max_tries: data[6],
+            slots: [slot(8), slot(12)],
+        })
+    }
+
+    /// Encodes the state
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut data = [0; Self::SIZE];
+        data[..4].copy_from_slice(MAGIC);
+        data[4] = VERSION;
+        data[5] = self.active.index() as u8;
+        data[6] = self.max_tries;
+        for (state, at) in self.slots.iter().zip([8, 12]) {
+            data[at] = state.tries_remaining;
+            data[at + 1] = state.successful.into();
+            data[at + 2] = state.unbootable.into();
+        }
+        let crc = crc32::checksum(&data[..16]);
+        data[16..].copy_from_slice(&crc.to_le_bytes());
+        data
+    }
+
+    pub fn active(&self) -> Slot {
+        self.active
+    }
+
+    pub fn max_tries(&self) -> u8 {
+        self.max_tries
+    }
+
+    pub fn slot(&self, slot: Slot) -> SlotState {
+        self.slots[slot.index()]
+    }
+
+    /// Picks the slot to boot, using up one of its tries
+    ///
+    /// A successful active slot is always chosen. Otherwise, a try is used if there is one
+    /// left; if not, the slot is marked unbootable and the other one becomes active. Returns
+    /// `None` if neither slot can be booted.
+    pub fn choose(&mut self) -> Option<Slot> {
+        for _ in 0..2 {
+            let state = &mut self.slots[self.active.index()];
+            if !state.unbootable {
+                if state.successful {
+                    return Some(self.active);
+                }
+                if state.tries_remaining > 0 {
+                    state.tries_remaining -= 1;
+                    return Some(self.active);
+                }
+                state.unbootable = true;
+            }
+            self.active = self.active.other();
+        }
+        None
+    }
+
+    /// Marks the active slot as successfully booted
+    pub fn mark_successful(&mut self) {
+        let state = &mut self.slots[self.active.index()];
+        state.successful = true;
+        state.unbootable = false;
+    }
+
+    /// Makes `slot` active with a full set of tries, e.g. after an update was written to it
+    pub fn set_active(&mut self, slot: Slot) {
+        self.active = slot;
+        self.slots[slot.index()] = SlotState {
+            tries_remaining: self.max_tries,
+            successful:      false,
+            unbootable:      false,
+        };
+    }
+
+    /// Marks `slot` unbootable, e.g. before overwriting it
+    pub fn set_unbootable(&mut self, slot: Slot) {
+        self.slots[slot.index()] = SlotState {
+            tries_remaining: 0,
+            successful:      false,
+            unbootable:      true,
+        };
+    }
+}
+
+/// Boot slot state kept in a variable
+pub struct BootControl<'a> {
+    rt:     &'a RuntimeServices,
+    name:   &'a CStr16,
+    vendor: &'a Guid,
+    slots:  BootSlots,
+    /// The state as last read or written
+    stored: BootSlots,
+}
+
+impl<'a> BootControl<'a> {
+    /// Reads the state from the variable `name` of `vendor`
+    ///
+    /// A missing or corrupted variable starts over from [`BootSlots::new()`] with `max_tries`,
+    /// which is ignored otherwise. Nothing is written until the state changes.
+    pub fn load(
+        rt: &'a RuntimeServices,
+        name: &'a CStr16,
+        vendor: &'a Guid,
+        max_tries: u8,
+    ) -> Result<Self> {
+        let mut buf = [0; BootSlots::SIZE];
+        let slots = match rt.get_variable(name, vendor, &mut buf) {
+            Ok((size, _)) => BootSlots::parse(&buf[..size]).ok(),
+            Err(Status::NOT_FOUND | Status::BUFFER_TOO_SMALL) => None,
+            Err(status) => return Err(status),
+        };
+        let slots = slots.unwrap_or(BootSlots::new(max_tries));
+        Ok(Self {
+            rt,
+            name,
+            vendor,
+            slots,
+            stored: slots,
+        })
+    }
+
+    pub fn slots(&self) -> &BootSlots {
+        &self.slots
+    }
+
+    /// Picks the slot to boot with [`BootSlots::choose()`] and saves the new state
+    ///
+    /// Call this once per boot, from the loader's main path, before loading anything from the
+    /// slot. The variable is only written if choosing changed the state, e.g. not when booting
+    /// a slot already marked successful. Fails with `NOT_FOUND` if neither slot can be booted.
+    pub fn begin_boot(&mut self) -> Result<Slot> {
+        let slot = self.slots.choose();
+        self.store()?;
+        slot.ok_or(Status::NOT_FOUND)
+    }
+
+    /// Marks the active slot as successfully booted and saves the state
+    ///
+    /// This is normally done by the OS, but loaders which can tell on their own that a slot
+    /// works may do so too.
+    pub fn mark_successful(&mut self) -> Result<()> {
+        self.slots.mark_successful();
+        self.store()
+    }
+
+    /// Makes `slot` active with a full set of tries and saves the state
+    pub fn set_active(&mut self, slot: Slot) -> Result<()> {
+        self.slots.set_active(slot);
+        self.store()
+    }
+
+    /// Writes the state, unless it is unchanged
+    fn store(&mut self) -> Result<()> {
+        if self.slots == self.stored {
+            return Ok(());
+        }
+        let data = self.slots.to_bytes();
+        self.rt
+            .set_variable(self.name, self.vendor, NV_BS_RT, &data)?;
+        self.stored = self.slots;
+        Ok(())
+    }
+}
@@ -0,0 +1,191 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Verification of loaded files against recorded digests
+//!
+//! A configuration file can record the expected digest of each file it names, in a key with
+//! a `_sha256` or `_crc32` suffix:
+//!
+//! ```text
+//! [entry.linux]
+//! kernel        = "\EFI\linux\vmlinuz"
+//! kernel_sha256 = "5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03"
+//! initrd        = "\EFI\linux\initrd.img"
+//! initrd_crc32  = 0x1c291ca3
+//! ```
+//!
+//! This only detects corruption and accidental mismatches. Unless the configuration file is
+//! itself protected, anyone who can replace a file can replace its digest too; use Secure Boot
+//! where that matters.
+
+use core::fmt;
+
+use crate::{config::Config, crc32, sha256, Status};
+
+/// An expected or actual digest
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Digest {
+    Sha256([u8; sha256::DIGEST_SIZE]),
+    Crc32(u32),
+}
+
+impl Digest {
+    /// Parses a SHA-256 digest written as 64 hex digits
+    pub fn parse_sha256(hex: &str) -> Option<Self> {
+        let hex = hex.as_bytes();
+        if hex.len() != sha256::DIGEST_SIZE * 2 {
+            return None;
+        }
+        let mut digest = [0; sha256::DIGEST_SIZE];
+        for (byte, pair) in digest.iter_mut().zip(hex.chunks_exact(2)) {
+            let digit = |c: u8| (c as char).to_digit(16);
+            *byte = (digit(pair[0])? << 4 | digit(pair[1])?) as u8;
+        }
+        Some(Self::Sha256(digest))
+    }
+
+    /// Reads the digest recorded for the file named by `key` in `section`
+    ///
+    /// `<key>_sha256` takes precedence over `<key>_crc32` if both are present.
+    pub fn from_config(
+        config: &Config,
+        section: &str,
+        key: &str,
+    ) -> core::result::Result<Self, VerifyError> {
+        // Like `Config::get()`, the last value wins.
+        let lookup = |suffix: &str| {
+            config
+                .section(section)
+                .filter(|entry| entry.key.strip_prefix(key) == Some(suffix))
+                .last()
+                .map(|entry| entry.value)
+        };
+
+        if let Some(value) = lookup("_sha256") {
+            return Self::parse_sha256(value.as_str()).ok_or(VerifyError::Malformed);
+        }
+        if let Some(value) = lookup("_crc32") {
+            let crc = value.as_int().and_then(|crc| u32::try_from(crc).ok());
+            return crc.map(Self::Crc32).ok_or(VerifyError::Malformed);
+        }
+        Err(VerifyError::Missing)
+    }
+
+    /// Calculates the digest of `data` with the same algorithm as `self`
+    pub fn calculate(&self, data: &[u8]) -> Self {
+        match self {
+            Self::Sha256(_) => Self::Sha256(sha256::digest(data)),
+            Self::Crc32(_) => Self::Crc32(crc32::checksum(data)),
+        }
+    }
+
+    /// Checks that `data` has this digest
+    pub fn verify(&self, data: &[u8]) -> core::result::Result<(), VerifyError> {
+        let actual = self.calculate(data);
+        if actual != *self {
+            return Err(VerifyError::Mismatch {
+                expected: *self,
+                actual,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn algorithm(&self) -> &'static str {
+        match self {
+            Self::Sha256(_) => "SHA-256",
+            Self::Crc32(_) => "CRC32",
+        }
+    }
+}
+
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Sha256(digest) => digest.iter().try_for_each(|byte| write!(f, "{byte:02x}")),
+            Self::Crc32(crc) => write!(f, "{crc:#010x}"),
+        }
+    }
+}
+
+/// Error returned when a file can't be verified
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum VerifyError {
+    /// No digest is recorded for the file
+    Missing,
+    /// The recorded digest is not a valid digest
+    Malformed,
+    /// The file's digest doesn't match the recorded one
+    Mismatch { expected: Digest, actual: Digest },
+}
+
+impl VerifyError {
+    /// Returns the closest status code, for callers which propagate a [`Status`]
+    pub fn status(&self) -> Status {
+        match self {
+            Self::Missing => Status::NOT_FOUND,
+            Self::Malformed => Status::INVALID_PARAMETER,
+            Self::Mismatch { .. } => Status::SECURITY_VIOLATION,
+        }
+    }
+}
+
+impl From<VerifyError> for Status {
+    fn from(error: VerifyError) -> Self {
+        error.status()
+    }
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing => f.write_str("no digest recorded"),
+            Self::Malformed => f.write_str("malformed digest"),
+            Self::Mismatch { expected, actual } => write!(
+                f,
+                "{} mismatch: expected {expected}, got {actual}",
+                expected.algorithm()
+            ),
+        }
+    }
+}
+
+/// Checks `data` against the digest recorded for `key` in `section`
+///
+/// Loaders which only want to verify files that have a digest recorded can treat
+/// [`VerifyError::Missing`] as success.
+pub fn verify_entry(
+    config: &Config,
+    section: &str,
+    key: &str,
+    data: &[u8],
+) -> core::result::Result<(), VerifyError> {
+    Digest::from_config(config, section, key)?.verify(data)
+}
@@ -0,0 +1,85 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Warnings from firmware calls which otherwise succeeded
+//!
+//! Some calls complete but return a warning status about a degraded condition, e.g.
+//! `WARN_FILE_SYSTEM` when a file system driver finds damage it worked around, or
+//! `WARN_WRITE_FAILURE` when closing a file could not flush its data. The file and console
+//! wrappers treat these as success rather than failing the operation, and pass them to the
+//! handler set with [`set_handler()`] so the loader can log them.
+//!
+//! Warnings raised while the handler runs, e.g. by the console it logs to, are dropped.
+
+use core::fmt;
+
+use crate::{sync::TryLock, Status};
+
+/// A warning status, and the call which returned it
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Warning {
+    pub status:    Status,
+    /// Name of the firmware function, e.g. `"File.Write"`
+    pub operation: &'static str,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {:?}", self.operation, self.status)
+    }
+}
+
+static HANDLER: TryLock<Option<fn(&Warning)>> = TryLock::new(None);
+
+/// Sets the function called with each warning, or stops reporting them
+///
+/// Has no effect if called from the handler itself.
+pub fn set_handler(handler: Option<fn(&Warning)>) {
+    HANDLER.try_with(|h| *h = handler);
+}
+
+/// Passes a warning to the handler
+pub fn report(status: Status, operation: &'static str) {
+    // The lock is held while the handler runs, which drops nested warnings.
+    HANDLER.try_with(|handler| {
+        if let Some(handler) = handler {
+            handler(&Warning { status, operation });
+        }
+    });
+}
+
+/// Converts the status of `operation` into a result, reporting any warning
+pub(crate) fn check(status: Status, operation: &'static str) -> crate::Result<()> {
+    if status.is_warning() {
+        report(status, operation);
+        return Ok(());
+    }
+    status.to_result(())
+}
@@ -0,0 +1,259 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Test application booted under QEMU by `cargo xtask test`
+//!
+//! Results are reported on the console, which the firmware mirrors to the serial port. The
+//! runner prints [`PASSED`] once every test has run; a panic prints [`FAILED`] instead. Both
+//! markers are what the xtask waits for, so the application never needs to shut down.
+
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::vec;
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    fmt::Write,
+    panic::PanicInfo,
+    ptr,
+};
+
+use uefi::{
+    proto::{
+        console::{gop::GraphicsOutput, text_output::SimpleTextOutput},
+        loaded_image::loaded_image,
+        media::{
+            file::{FileAttribute, FileMode, SimpleFileSystem},
+            removable::DEFAULT_BOOT_FILE,
+        },
+        Protocol,
+    },
+    table::{AllocPagesType, MapChange, MemoryDescriptor, MemoryMap, MemoryType, SystemTable},
+    Handle, Status,
+};
+
+const PASSED: &str = "uefi-test-runner: all tests passed";
+const FAILED: &str = "uefi-test-runner: FAILED";
+
+fn stdout() -> &'static mut SimpleTextOutput {
+    unsafe { &mut *uefi::system_table().stdout.as_ptr() }
+}
+
+macro_rules! println {
+    ($($arg:tt)*) => {{
+        let _ = writeln!(stdout(), $($arg)*);
+    }};
+}
+
+struct PoolAllocator;
+
+unsafe impl GlobalAlloc for PoolAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if layout.align() > 8 {
+            return ptr::null_mut();
+        }
+        uefi::boot_services()
+            .allocate_pool(MemoryType::LOADER_DATA, layout.size())
+            .unwrap_or(ptr::null_mut())
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
+        let _ = uefi::boot_services().free_pool(ptr);
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: PoolAllocator = PoolAllocator;
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    println!("{FAILED}: {info}");
+    loop {
+        core::hint::spin_loop();
+    }
+}
+
+#[no_mangle]
+extern "efiapi" fn efi_main(image: Handle, system_table: &'static SystemTable) -> Status {
+    unsafe { uefi::bootstrap(image, system_table) };
+
+    let tests: &[(&str, fn())] = &[
+        ("console", test_console),
+        ("memory::pages", test_pages),
+        ("memory::pool", test_pool),
+        ("memory::map", test_memory_map),
+        ("protocol::handles", test_handles),
+        ("file", test_file),
+        ("gop", test_gop),
+    ];
+
+    for (name, test) in tests {
+        println!("test {name} ...");
+        test();
+        println!("test {name} ... ok");
+    }
+
+    println!("{PASSED}");
+    loop {
+        core::hint::spin_loop();
+    }
+}
+
+fn test_console() {
+    let stdout = stdout();
+    let size = stdout.query_mode(0).expect("mode 0 must be supported");
+    assert!(size.cols >= 80 && size.rows >= 25);
+    stdout.enable_cursor(true).ok();
+    let text = [b'o' as u16, b'k' as u16, b'\r' as u16, b'\n' as u16, 0];
+    stdout.output_string(&text).unwrap();
+}
+
+fn test_pages() {
+    let bs = uefi::boot_services();
+    let addr = bs
+        .allocate_pages(AllocPagesType::Any, MemoryType::LOADER_DATA, 4)
+        .unwrap();
+    assert_eq!(addr % 4096, 0);
+    unsafe {
+        ptr::write_bytes(addr as *mut u8, 0xa5, 4 * 4096);
+        bs.free_pages(addr, 4).unwrap();
+    }
+
+    let below_4g = bs
+        .allocate_pages(AllocPagesType::Max(0xffff_ffff), MemoryType::LOADER_DATA, 1)
+        .unwrap();
+    assert!(below_4g < 0x1_0000_0000);
+    unsafe { bs.free_pages(below_4g, 1).unwrap() };
+}
+
+fn test_pool() {
+    let buffer = vec![0x5au8; 10_000];
+    assert!(buffer.iter().all(|&b| b == 0x5a));
+}
+
+fn test_memory_map() {
+    let bs = uefi::boot_services();
+    let info = bs.get_memory_map_info().unwrap();
+    assert!(info.descriptor_size >= core::mem::size_of::<MemoryDescriptor>());
+
+    let mut buffer = vec![0u8; info.buffer_size + 8 * info.descriptor_size];
+    let info = bs.get_memory_map(&mut buffer, 0).unwrap();
+    let count = info.buffer_size / info.descriptor_size;
+    assert!(count > 0);
+
+    let usable = (0..count)
+        .map(|i| unsafe {
+            &*buffer
+                .as_ptr()
+                .add(i * info.descriptor_size)
+                .cast::<MemoryDescriptor>()
+        })
+        .filter(|desc| desc.kind == MemoryType::CONVENTIONAL_MEMORY)
+        .map(|desc| desc.num_pages)
+        .sum::<u64>();
+    assert!(usable > 0, "no conventional memory in the memory map");
+
+    // An allocation between two snapshots shows up in their diff.
+    let before = buffer.clone();
+    let before = MemoryMap::new(info, &before);
+    let addr = bs
+        .allocate_pages(AllocPagesType::Any, MemoryType::LOADER_CODE, 1)
+        .unwrap();
+    let after = bs.memory_map(&mut buffer).unwrap();
+    let found = before.diff(&after).any(|change| match change {
+        MapChange::Changed { new, .. } | MapChange::Added(new) => {
+            new.kind == MemoryType::LOADER_CODE && new.contains(addr)
+        }
+        MapChange::Removed(_) => false,
+    });
+    unsafe { bs.free_pages(addr, 1).unwrap() };
+    assert!(found, "allocation missing from the memory map diff");
+}
+
+fn test_handles() {
+    let bs = uefi::boot_services();
+    let handles = bs.handles_by_protocol::<SimpleTextOutput>().unwrap();
+    assert!(!handles.is_empty());
+
+    let all = bs.all_handles().unwrap();
+    assert!(handles.iter().all(|handle| all.contains(handle)));
+    let protocols = bs.protocols_per_handle(handles[0]).unwrap();
+    assert!(protocols.iter().any(|guid| guid == SimpleTextOutput::GUID));
+}
+
+fn test_file() {
+    let bs = uefi::boot_services();
+    let device = loaded_image().unwrap().device_handle.unwrap();
+    let mut root = bs
+        .protocol_for_handle::<SimpleFileSystem>(device)
+        .unwrap()
+        .open_volume()
+        .unwrap();
+
+    // The xtask installs the runner as the default boot file of the ESP.
+    let mut file = root
+        .open(DEFAULT_BOOT_FILE, FileMode::READ, FileAttribute::empty())
+        .unwrap();
+    let mut info = [0u8; 256];
+    let (size, is_directory) = {
+        let info = file.info(&mut info).unwrap();
+        (info.file_size, info.is_directory())
+    };
+    assert!(!is_directory);
+    assert_eq!(file.size().unwrap(), size);
+
+    let mut data = vec![0u8; size as usize];
+    file.read_exact(&mut data).unwrap();
+    assert_eq!(&data[..2], b"MZ");
+    assert_eq!(
+        file.read(&mut [0; 16]).unwrap(),
+        0,
+        "read past the size in FileInfo"
+    );
+}
+
+fn test_gop() {
+    let Ok(mut gop) = uefi::boot_services().first_protocol::<GraphicsOutput>() else {
+        println!("no GOP instance found, skipping");
+        return;
+    };
+    let mode = gop.mode();
+    let info = mode.info();
+    assert!(info.horizontal_resolution > 0 && info.vertical_resolution > 0);
+    assert!(
+        mode.framebuffer_size > 0
+            || info.pixel_format == uefi::proto::console::gop::PixelFormat::BLT_ONLY
+    );
+
+    let max_mode = mode.max_mode;
+    assert_eq!(gop.all_modes().count(), max_mode as usize);
+}
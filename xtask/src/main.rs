@@ -0,0 +1,301 @@
+/*
+ * Copyright (c) 2023 xvanc and contributors
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * 3. Neither the name of the copyright holder nor the names of its contributors
+ *    may be used to endorse or promote products derived from this software without
+ *    specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES
+ * OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED.
+ * IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT,
+ * INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT
+ * LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ *
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+
+//! Development tasks
+//!
+//! `cargo xtask test [--arch ARCH] [--firmware PATH] [--image PATH] [--timeout SECS]`
+//!
+//! Builds `uefi-test-runner`, places it at the removable-media boot path of a virtual FAT
+//! drive, boots it under QEMU, and checks the serial output for the runner's pass/fail
+//! markers.
+//!
+//! `rustc` has no RISC-V UEFI target, so for `--arch riscv64` the runner must be built with
+//! an external PE toolchain and passed in with `--image`.
+
+use std::{
+    env, fs,
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+    process::{self, Command, Stdio},
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+
+const PASSED: &str = "uefi-test-runner: all tests passed";
+const FAILED: &str = "uefi-test-runner: FAILED";
+
+#[derive(Clone, Copy, Debug)]
+enum Arch {
+    X86_64,
+    Aarch64,
+    Riscv64,
+}
+
+impl Arch {
+    fn parse(s: &str) -> Option<Arch> {
+        match s {
+            "x86_64" | "x64" => Some(Arch::X86_64),
+            "aarch64" | "arm64" => Some(Arch::Aarch64),
+            "riscv64" => Some(Arch::Riscv64),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Arch::X86_64 => "x86_64",
+            Arch::Aarch64 => "aarch64",
+            Arch::Riscv64 => "riscv64",
+        }
+    }
+
+    fn target(self) -> Option<&'static str> {
+        match self {
+            Arch::X86_64 => Some("x86_64-unknown-uefi"),
+            Arch::Aarch64 => Some("aarch64-unknown-uefi"),
+            Arch::Riscv64 => None,
+        }
+    }
+
+    /// Default boot file name for removable media
+    fn boot_file(self) -> &'static str {
+        match self {
+            Arch::X86_64 => "BOOTX64.EFI",
+            Arch::Aarch64 => "BOOTAA64.EFI",
+            Arch::Riscv64 => "BOOTRISCV64.EFI",
+        }
+    }
+
+    /// Environment variable overriding the firmware search
+    fn firmware_var(self) -> &'static str {
+        match self {
+            Arch::X86_64 => "OVMF_X64",
+            Arch::Aarch64 => "OVMF_AA64",
+            Arch::Riscv64 => "OVMF_RISCV64",
+        }
+    }
+
+    /// Where distributions commonly install the firmware
+    fn firmware_paths(self) -> &'static [&'static str] {
+        match self {
+            Arch::X86_64 => &[
+                "/usr/share/OVMF/OVMF_CODE.fd",
+                "/usr/share/ovmf/OVMF.fd",
+                "/usr/share/edk2/x64/OVMF_CODE.fd",
+                "/usr/share/edk2-ovmf/x64/OVMF_CODE.fd",
+            ],
+            Arch::Aarch64 => &[
+                "/usr/share/qemu-efi-aarch64/QEMU_EFI.fd",
+                "/usr/share/AAVMF/AAVMF_CODE.fd",
+                "/usr/share/edk2/aarch64/QEMU_EFI.fd",
+            ],
+            Arch::Riscv64 => &[
+                "/usr/share/qemu-efi-riscv64/RISCV_VIRT_CODE.fd",
+                "/usr/share/edk2/riscv/RISCV_VIRT_CODE.fd",
+            ],
+        }
+    }
+
+    fn qemu_args(self, firmware: &Path) -> Vec<String> {
+        let firmware = firmware.display();
+        let args: &[&str] = match self {
+            Arch::X86_64 => &["-machine", "q35", "-vga", "std"],
+            Arch::Aarch64 => &["-machine", "virt", "-cpu", "cortex-a72", "-device", "ramfb"],
+            Arch::Riscv64 => &["-machine", "virt", "-device", "ramfb"],
+        };
+        let mut args = args.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        match self {
+            Arch::Aarch64 => args.extend(["-bios".into(), firmware.to_string()]),
+            _ => args.extend([
+                "-drive".into(),
+                format!("if=pflash,format=raw,readonly=on,file={firmware}"),
+            ]),
+        }
+        args
+    }
+}
+
+struct TestArgs {
+    arch:     Arch,
+    firmware: Option<PathBuf>,
+    image:    Option<PathBuf>,
+    timeout:  Duration,
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: cargo xtask test [--arch x86_64|aarch64|riscv64] [--firmware PATH] \
+         [--image PATH] [--timeout SECS]"
+    );
+    process::exit(2);
+}
+
+fn fail(msg: impl std::fmt::Display) -> ! {
+    eprintln!("xtask: {msg}");
+    process::exit(1);
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+    match args.next().as_deref() {
+        Some("test") => test(parse_test_args(args)),
+        _ => usage(),
+    }
+}
+
+fn parse_test_args(mut args: impl Iterator<Item = String>) -> TestArgs {
+    let mut parsed = TestArgs {
+        arch:     Arch::X86_64,
+        firmware: None,
+        image:    None,
+        timeout:  Duration::from_secs(120),
+    };
+    while let Some(arg) = args.next() {
+        let value = args.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "--arch" => parsed.arch = Arch::parse(&value).unwrap_or_else(|| usage()),
+            "--firmware" => parsed.firmware = Some(value.into()),
+            "--image" => parsed.image = Some(value.into()),
+            "--timeout" => {
+                let secs = value.parse().unwrap_or_else(|_| usage());
+                parsed.timeout = Duration::from_secs(secs);
+            }
+            _ => usage(),
+        }
+    }
+    parsed
+}
+
+fn workspace_root() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .parent()
+        .unwrap()
+        .to_owned()
+}
+
+fn build_runner(arch: Arch) -> PathBuf {
+    let Some(target) = arch.target() else {
+        fail(format_args!(
+            "rustc cannot build {} UEFI images; pass a prebuilt runner with --image",
+            arch.name()
+        ));
+    };
+    let root = workspace_root();
+    let cargo = env::var("CARGO").unwrap_or_else(|_| "cargo".into());
+    let status = Command::new(cargo)
+        .current_dir(&root)
+        .args([
+            "build",
+            "--release",
+            "--manifest-path",
+            "uefi-test-runner/Cargo.toml",
+        ])
+        .args(["--target", target, "--target-dir", "target"])
+        .args([
+            "-Zbuild-std=core,alloc",
+            "-Zbuild-std-features=compiler-builtins-mem",
+        ])
+        .status()
+        .unwrap_or_else(|err| fail(format_args!("failed to run cargo: {err}")));
+    if !status.success() {
+        fail("failed to build uefi-test-runner");
+    }
+    root.join("target")
+        .join(target)
+        .join("release/uefi-test-runner.efi")
+}
+
+fn find_firmware(arch: Arch) -> PathBuf {
+    if let Some(path) = env::var_os(arch.firmware_var()) {
+        return path.into();
+    }
+    arch.firmware_paths()
+        .iter()
+        .map(PathBuf::from)
+        .find(|path| path.exists())
+        .unwrap_or_else(|| {
+            fail(format_args!(
+                "no {} firmware found; pass --firmware or set {}",
+                arch.name(),
+                arch.firmware_var()
+            ))
+        })
+}
+
+fn test(args: TestArgs) {
+    let arch = args.arch;
+    let image = args.image.unwrap_or_else(|| build_runner(arch));
+    let firmware = args.firmware.unwrap_or_else(|| find_firmware(arch));
+
+    let esp = workspace_root()
+        .join("target/xtask")
+        .join(arch.name())
+        .join("esp");
+    let boot_dir = esp.join("EFI/BOOT");
+    fs::create_dir_all(&boot_dir).unwrap_or_else(|err| fail(err));
+    fs::copy(&image, boot_dir.join(arch.boot_file()))
+        .unwrap_or_else(|err| fail(format_args!("{}: {err}", image.display())));
+
+    let mut qemu = Command::new(format!("qemu-system-{}", arch.name()))
+        .args(arch.qemu_args(&firmware))
+        .args(["-m", "256M", "-nographic", "-no-reboot", "-net", "none"])
+        .arg("-drive")
+        .arg(format!("format=raw,file=fat:rw:{}", esp.display()))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap_or_else(|err| fail(format_args!("failed to start QEMU: {err}")));
+
+    let (tx, rx) = mpsc::channel();
+    let stdout = qemu.stdout.take().unwrap();
+    thread::spawn(move || {
+        for line in BufReader::new(stdout).lines() {
+            let Ok(line) = line else { break };
+            println!("{line}");
+            if line.contains(PASSED) {
+                let _ = tx.send(true);
+            } else if line.contains(FAILED) {
+                let _ = tx.send(false);
+            }
+        }
+    });
+
+    let result = rx.recv_timeout(args.timeout);
+    let _ = qemu.kill();
+    let _ = qemu.wait();
+
+    match result {
+        Ok(true) => println!("xtask: {} tests passed", arch.name()),
+        Ok(false) => fail(format_args!("{} tests failed", arch.name())),
+        Err(mpsc::RecvTimeoutError::Timeout) => fail("timed out waiting for test results"),
+        Err(mpsc::RecvTimeoutError::Disconnected) => fail("QEMU exited without reporting results"),
+    }
+}